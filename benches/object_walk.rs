@@ -0,0 +1,77 @@
+//! Writes a repository of about 100,000 loose objects, then walks every tree and reads every
+//! blob in it, timing both: `cargo bench --bench object_walk`. Run without `--bench`, as
+//! `cargo test --benches` does, it only checks that the walk works on a small tree.
+
+use codecrafters_git::{Oid, Repository, Tree, TreeEntry, TreeEntryPermission};
+use std::time::{Duration, Instant};
+
+fn main() -> anyhow::Result<()> {
+    // 1,000 directories of 99 files, with their trees and the root: 100,001 objects.
+    let (directories, files) = if std::env::args().any(|arg| arg == "--bench") {
+        (1_000, 99)
+    } else {
+        (10, 9)
+    };
+    let dir = std::env::temp_dir().join(format!("object-walk-{}", std::process::id()));
+    let repo = Repository::init(&dir)?;
+
+    let start = Instant::now();
+    let root = write_objects(&repo, directories, files)?;
+    report("write", directories * (files + 1) + 1, start.elapsed());
+
+    let start = Instant::now();
+    let mut objects = 1;
+    let mut bytes = 0;
+    for entry in repo.tree_walk(root) {
+        let (_, entry) = entry?;
+        if entry.permission != TreeEntryPermission::Directory {
+            bytes += repo.find_blob(Oid::from(entry.hash))?.content().len();
+        }
+        objects += 1;
+    }
+    report("walk", objects, start.elapsed());
+    assert_eq!(objects, directories * (files + 1) + 1);
+    assert!(bytes > 0);
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+/// Writes `directories` trees of `files` small blobs each, and the root tree over them.
+fn write_objects(repo: &Repository, directories: usize, files: usize) -> anyhow::Result<Oid> {
+    let entry = |name: String, permission, oid: Oid| TreeEntry {
+        permission,
+        name,
+        hash: *oid.as_bytes(),
+    };
+    let mut subtrees = vec![];
+    for directory in 0..directories {
+        let mut entries = vec![];
+        for file in 0..files {
+            let content = format!("file {} of directory {}\n", file, directory);
+            let blob = repo.write_blob(content.as_bytes())?;
+            entries.push(entry(
+                format!("file{:03}", file),
+                TreeEntryPermission::RegularFile,
+                blob,
+            ));
+        }
+        let tree = repo.write_tree(&Tree { entries })?;
+        subtrees.push(entry(
+            format!("dir{:04}", directory),
+            TreeEntryPermission::Directory,
+            tree,
+        ));
+    }
+    repo.write_tree(&Tree { entries: subtrees })
+}
+
+fn report(what: &str, objects: usize, elapsed: Duration) {
+    println!(
+        "{:<5} {:>7} objects in {:>8.1} ms, {:>6.2} µs per object",
+        what,
+        objects,
+        elapsed.as_secs_f64() * 1e3,
+        elapsed.as_secs_f64() * 1e6 / objects as f64
+    );
+}
@@ -0,0 +1,363 @@
+//! The tar archives `git archive` writes of a tree: a ustar entry for each directory, file and
+//! symlink in tree order, each directory before what is in it, with a pax header for whatever
+//! does not fit in ustar's fields. The archive of a commit starts with a global pax header
+//! whose comment is the commit id, which `git get-tar-commit-id` reads back.
+//!
+//! Paths with the `export-ignore` attribute are left out, and in the archive of a commit,
+//! files with `export-subst` have each `$Format:<format>$` replaced by the commit's pretty
+//! format, such as `$Format:%H$` by its id. The attributes come from the `.gitattributes` files
+//! of the tree archived, not those of the working tree, and `.git/info/attributes`.
+//!
+//! Nothing in an archive depends on the machine or the moment it is written: entries belong
+//! to `root` (uid and gid 0), modes come from the tree and `tar.umask`, and every entry has the
+//! same modification time, the committer time of the commit unless one is given. The same
+//! tree thus always gives the same bytes, as `git archive` does.
+
+use crate::attributes::{AttributeValue, Attributes};
+use crate::filters::Filters;
+use crate::object_storage::{Commit, GitObject, ObjectStorage, Sha, TreeEntryPermission};
+use crate::pretty;
+use crate::tree_walk::TreeWalk;
+use std::io::Write;
+
+/// tar writes records of 512 bytes, in blocks of 20 records.
+const RECORD_SIZE: usize = 512;
+const BLOCK_SIZE: usize = RECORD_SIZE * 20;
+
+/// The largest size and modification time the ustar fields of 11 octal digits can hold.
+const USTAR_MAX_SIZE: u64 = 0o77777777777;
+const USTAR_MAX_MTIME: i64 = 0o77777777777;
+
+const TYPEFLAG_REG: u8 = b'0';
+const TYPEFLAG_LNK: u8 = b'2';
+const TYPEFLAG_DIR: u8 = b'5';
+const TYPEFLAG_GLOBAL_HEADER: u8 = b'g';
+const TYPEFLAG_EXT_HEADER: u8 = b'x';
+
+/// What goes into an archive besides the tree.
+pub struct ArchiveOptions {
+    /// Put before every path, such as `project-1.0/`.
+    pub prefix: String,
+    /// The modification time of every entry, in seconds since the epoch.
+    pub mtime: i64,
+    /// The commit archived, if it is one, to record in the global header.
+    pub commit: Option<Sha>,
+    /// The permission bits taken away from every entry, `tar.umask`.
+    pub umask: u32,
+}
+
+/// Writes a tar archive of the tree `tree` to `out`.
+pub fn write_tar(tree: &Sha, options: &ArchiveOptions, out: &mut dyn Write) -> anyhow::Result<()> {
+    let mut tar = TarWriter {
+        out,
+        written: 0,
+        mtime: options.mtime,
+        umask: options.umask,
+    };
+    let commit = match &options.commit {
+        Some(sha) => {
+            tar.write_global_header(sha)?;
+            let commit = GitObject::read_commit(sha)?;
+            Some((*sha, commit))
+        }
+        None => None,
+    };
+    // A prefix ending in a slash is a directory of its own.
+    if let Some(dir) = options.prefix.strip_suffix('/') {
+        let dir = dir.trim_end_matches('/');
+        tar.write_entry(tree, &format!("{}/", dir), 0o40777, &[])?;
+    }
+    let mut gitattributes: Vec<(String, Vec<u8>)> =
+        gitattributes_of(tree, "")?.into_iter().collect();
+    let mut walk = TreeWalk::new((*tree).into());
+    while let Some(entry) = walk.next() {
+        let (relative, entry) = entry?;
+        // The walk is done with the trees the path is not in.
+        gitattributes.retain(|(base, _)| relative.starts_with(base.as_str()));
+        let attributes = Attributes::for_tree_path(&relative, &gitattributes)?;
+        if attributes.get("export-ignore") == Some(AttributeValue::Set) {
+            walk.skip_subtree();
+            continue;
+        }
+        let path = format!("{}{}", options.prefix, relative);
+        match entry.permission {
+            TreeEntryPermission::Directory => {
+                tar.write_entry(&entry.hash, &format!("{}/", path), 0o40000, &[])?;
+                gitattributes.extend(gitattributes_of(&entry.hash, &format!("{}/", relative))?);
+            }
+            TreeEntryPermission::SymbolicLink => {
+                let (_, target) = ObjectStorage::read_object(&entry.hash)?;
+                tar.write_entry(&entry.hash, &path, 0o120000, &target)?;
+            }
+            permission => {
+                let mode = match permission {
+                    TreeEntryPermission::Executable => 0o100755,
+                    _ => 0o100644,
+                };
+                let (_, content) = ObjectStorage::read_object(&entry.hash)?;
+                let mut content =
+                    Filters::for_attributes(&attributes).to_work_tree(content, &entry.hash);
+                if let Some((sha, commit)) = &commit
+                    && attributes.get("export-subst") == Some(AttributeValue::Set)
+                {
+                    content = substitute(&content, sha, commit)?;
+                }
+                tar.write_entry(&entry.hash, &path, mode, &content)?;
+            }
+        }
+    }
+    tar.finish()
+}
+
+/// The `.gitattributes` file of the tree `tree`, at `base` in the tree archived, if it has
+/// one.
+fn gitattributes_of(tree: &Sha, base: &str) -> anyhow::Result<Option<(String, Vec<u8>)>> {
+    let tree = GitObject::read_tree(tree)?;
+    let Some(entry) = tree.entries.iter().find(|entry| {
+        entry.name == ".gitattributes" && entry.permission != TreeEntryPermission::Directory
+    }) else {
+        return Ok(None);
+    };
+    let (_, content) = ObjectStorage::read_object(&entry.hash)?;
+    Ok(Some((base.to_string(), content)))
+}
+
+/// Replaces each `$Format:<format>$` in `content` with the pretty format `<format>` of the
+/// commit `sha`. An unterminated one is left as it is.
+fn substitute(content: &[u8], sha: &Sha, commit: &Commit) -> anyhow::Result<Vec<u8>> {
+    const START: &[u8] = b"$Format:";
+    let mut out = Vec::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(start) = rest.windows(START.len()).position(|window| window == START) {
+        let after = &rest[start + START.len()..];
+        let Some(end) = after.iter().position(|byte| *byte == b'$') else {
+            break;
+        };
+        out.extend_from_slice(&rest[..start]);
+        let format = String::from_utf8_lossy(&after[..end]);
+        out.extend_from_slice(pretty::format_commit(&format, sha, commit)?.as_bytes());
+        rest = &after[end + 1..];
+    }
+    out.extend_from_slice(rest);
+    Ok(out)
+}
+
+struct TarWriter<'a> {
+    out: &'a mut dyn Write,
+    /// How many bytes have been written, to end the last block.
+    written: usize,
+    mtime: i64,
+    umask: u32,
+}
+
+impl TarWriter<'_> {
+    /// Writes `data`, padded with zeros to whole records.
+    fn write_blocked(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        let padding = (RECORD_SIZE - data.len() % RECORD_SIZE) % RECORD_SIZE;
+        self.out.write_all(data)?;
+        self.out.write_all(&vec![0; padding])?;
+        self.written += data.len() + padding;
+        Ok(())
+    }
+
+    fn write_global_header(&mut self, commit: &Sha) -> anyhow::Result<()> {
+        let mut ext_header = vec![];
+        append_ext_header(
+            &mut ext_header,
+            "comment",
+            ObjectStorage::sha_to_hex_string(commit).as_bytes(),
+        );
+        if self.mtime > USTAR_MAX_MTIME {
+            append_ext_header(&mut ext_header, "mtime", self.mtime.to_string().as_bytes());
+            self.mtime = USTAR_MAX_MTIME;
+        }
+        let mut header = Header::new(TYPEFLAG_GLOBAL_HEADER);
+        header.set_name(b"pax_global_header");
+        self.write_header(header, 0o100666, ext_header.len() as u64)?;
+        self.write_blocked(&ext_header)
+    }
+
+    /// Writes the entry for the object `sha` at `path`, whose mode in the tree is `mode`;
+    /// `content` is a file's content or a symlink's target.
+    fn write_entry(
+        &mut self,
+        sha: &Sha,
+        path: &str,
+        mode: u32,
+        content: &[u8],
+    ) -> anyhow::Result<()> {
+        let hex = ObjectStorage::sha_to_hex_string(sha);
+        let mut ext_header = vec![];
+        let (typeflag, mode) = match mode & 0o170000 {
+            0o40000 => (TYPEFLAG_DIR, (mode | 0o777) & !self.umask),
+            0o120000 => (TYPEFLAG_LNK, mode | 0o777),
+            _ if mode & 0o100 != 0 => (TYPEFLAG_REG, (mode | 0o777) & !self.umask),
+            _ => (TYPEFLAG_REG, (mode | 0o666) & !self.umask),
+        };
+        let mut header = Header::new(typeflag);
+        let path = path.as_bytes();
+        if path.len() > NAME_SIZE {
+            // A long path is split at a slash between the prefix field and the name field,
+            // or else only fits in a pax header.
+            let prefix_len = path_prefix_len(path);
+            let rest = path.len() - prefix_len - 1;
+            if prefix_len > 0 && rest <= NAME_SIZE {
+                header.set_prefix(&path[..prefix_len]);
+                header.set_name(&path[prefix_len + 1..]);
+            } else {
+                header.set_name(format!("{}.data", hex).as_bytes());
+                append_ext_header(&mut ext_header, "path", path);
+            }
+        } else {
+            header.set_name(path);
+        }
+        let mut size = 0;
+        if typeflag == TYPEFLAG_LNK {
+            if content.len() > NAME_SIZE {
+                header.set_linkname(format!("see {}.paxheader", hex).as_bytes());
+                append_ext_header(&mut ext_header, "linkpath", content);
+            } else {
+                header.set_linkname(content);
+            }
+        } else if typeflag == TYPEFLAG_REG {
+            size = content.len() as u64;
+            if size > USTAR_MAX_SIZE {
+                append_ext_header(&mut ext_header, "size", size.to_string().as_bytes());
+                size = 0;
+            }
+        }
+        if !ext_header.is_empty() {
+            let mut pax = Header::new(TYPEFLAG_EXT_HEADER);
+            pax.set_name(format!("{}.paxheader", hex).as_bytes());
+            self.write_header(pax, 0o100666, ext_header.len() as u64)?;
+            self.write_blocked(&ext_header)?;
+        }
+        self.write_header(header, mode, size)?;
+        if typeflag == TYPEFLAG_REG && !content.is_empty() {
+            self.write_blocked(content)?;
+        }
+        Ok(())
+    }
+
+    /// Fills in the fields every header has and writes it.
+    fn write_header(&mut self, mut header: Header, mode: u32, size: u64) -> anyhow::Result<()> {
+        header.set_octal(MODE, 7, (mode & 0o7777) as u64);
+        header.set_octal(SIZE, 11, size);
+        header.set_octal(MTIME, 11, self.mtime.max(0) as u64);
+        header.set_octal(UID, 7, 0);
+        header.set_octal(GID, 7, 0);
+        header.0[UNAME..UNAME + 4].copy_from_slice(b"root");
+        header.0[GNAME..GNAME + 4].copy_from_slice(b"root");
+        header.set_octal(DEVMAJOR, 7, 0);
+        header.set_octal(DEVMINOR, 7, 0);
+        header.0[MAGIC..MAGIC + 6].copy_from_slice(b"ustar\0");
+        header.0[VERSION..VERSION + 2].copy_from_slice(b"00");
+        let checksum = header.checksum();
+        header.set_octal(CHKSUM, 7, checksum);
+        self.write_blocked(&header.0)
+    }
+
+    /// Ends the archive with at least two zero records, filling the last block.
+    fn finish(self) -> anyhow::Result<()> {
+        let offset = self.written % BLOCK_SIZE;
+        let tail = BLOCK_SIZE - offset;
+        self.out.write_all(&vec![0; tail])?;
+        if tail < 2 * RECORD_SIZE {
+            self.out.write_all(&[0; BLOCK_SIZE])?;
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// The offsets of the ustar header fields.
+const NAME: usize = 0;
+const MODE: usize = 100;
+const UID: usize = 108;
+const GID: usize = 116;
+const SIZE: usize = 124;
+const MTIME: usize = 136;
+const CHKSUM: usize = 148;
+const TYPEFLAG: usize = 156;
+const LINKNAME: usize = 157;
+const MAGIC: usize = 257;
+const VERSION: usize = 263;
+const UNAME: usize = 265;
+const GNAME: usize = 297;
+const DEVMAJOR: usize = 329;
+const DEVMINOR: usize = 337;
+const PREFIX: usize = 345;
+const NAME_SIZE: usize = 100;
+const PREFIX_SIZE: usize = 155;
+
+struct Header([u8; RECORD_SIZE]);
+
+impl Header {
+    fn new(typeflag: u8) -> Self {
+        let mut header = Self([0; RECORD_SIZE]);
+        header.0[TYPEFLAG] = typeflag;
+        header
+    }
+
+    fn set_name(&mut self, name: &[u8]) {
+        let len = name.len().min(NAME_SIZE);
+        self.0[NAME..NAME + len].copy_from_slice(&name[..len]);
+    }
+
+    fn set_linkname(&mut self, target: &[u8]) {
+        let len = target.len().min(NAME_SIZE);
+        self.0[LINKNAME..LINKNAME + len].copy_from_slice(&target[..len]);
+    }
+
+    fn set_prefix(&mut self, prefix: &[u8]) {
+        self.0[PREFIX..PREFIX + prefix.len()].copy_from_slice(prefix);
+    }
+
+    /// Writes `value` as `digits` octal digits and a NUL, as `%0<digits>o` does.
+    fn set_octal(&mut self, offset: usize, digits: usize, value: u64) {
+        let text = format!("{:0width$o}", value, width = digits);
+        self.0[offset..offset + digits].copy_from_slice(&text.as_bytes()[text.len() - digits..]);
+        self.0[offset + digits] = 0;
+    }
+
+    /// The sum of the header's bytes, with the checksum field counted as spaces.
+    fn checksum(&self) -> u64 {
+        self.0
+            .iter()
+            .enumerate()
+            .map(|(i, byte)| match i {
+                CHKSUM..=155 => b' ' as u64,
+                _ => *byte as u64,
+            })
+            .sum()
+    }
+}
+
+/// How much of the long path `path` can go in the prefix field: up to the last slash that
+/// leaves the prefix short enough, not counting a slash that ends the path.
+fn path_prefix_len(path: &[u8]) -> usize {
+    let mut i = path.len();
+    if i > 1 && path[i - 1] == b'/' {
+        i -= 1;
+    }
+    i = i.min(PREFIX_SIZE);
+    loop {
+        i -= 1;
+        if i == 0 || path[i] == b'/' {
+            return i;
+        }
+    }
+}
+
+/// Appends a pax header record, `<length> <keyword>=<value>\n`, whose length counts itself.
+fn append_ext_header(ext_header: &mut Vec<u8>, keyword: &str, value: &[u8]) {
+    let mut len = 1 + 1 + keyword.len() + 1 + value.len() + 1;
+    let mut tmp = 1;
+    while len / 10 >= tmp {
+        len += 1;
+        tmp *= 10;
+    }
+    ext_header.extend_from_slice(format!("{} {}=", len, keyword).as_bytes());
+    ext_header.extend_from_slice(value);
+    ext_header.push(b'\n');
+}
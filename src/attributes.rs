@@ -0,0 +1,229 @@
+use crate::config::Config;
+use crate::object_storage::{ObjectStorage, expand_home_dir};
+use crate::pathspec::wildmatch;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// What a `.gitattributes` line says about an attribute.
+#[derive(Clone, Debug, PartialEq)]
+pub enum AttributeValue {
+    /// `name`
+    Set,
+    /// `-name`
+    Unset,
+    /// `name=value`
+    Value(String),
+    /// `!name`, which takes back what a less specific line said.
+    Unspecified,
+}
+
+/// One line of an attributes file: a pattern and the attributes it assigns, in order.
+struct Line {
+    pattern: String,
+    /// Patterns with a slash match the path relative to the directory of the file; the others
+    /// match the file name at any depth.
+    anchored: bool,
+    assignments: Vec<(String, AttributeValue)>,
+}
+
+/// The attributes that apply to one path of the working tree, read from the global
+/// attributes file (`core.attributesFile`), the `.gitattributes` files of the path's
+/// directory and its parents, and `.git/info/attributes`, from lowest to highest precedence.
+pub struct Attributes {
+    path: String,
+    /// With the directory each file is relative to, lowest precedence first.
+    files: Vec<(String, Vec<Line>)>,
+    /// `[attr]name` definitions, starting with git's builtin `binary`.
+    macros: HashMap<String, Vec<(String, AttributeValue)>>,
+}
+
+impl Attributes {
+    /// Reads the attribute files that apply to `path`, relative to the top of the working tree.
+    pub fn for_path(path: &str) -> anyhow::Result<Self> {
+        let mut attributes = Self::new(path)?;
+        let work_tree = ObjectStorage::work_tree();
+        attributes.load(&work_tree.join(".gitattributes"), "", true)?;
+        let components: Vec<&str> = path.split('/').collect();
+        for i in 1..components.len() {
+            let dir = components[..i].join("/");
+            attributes.load(
+                &work_tree.join(&dir).join(".gitattributes"),
+                &format!("{}/", dir),
+                false,
+            )?;
+        }
+        attributes.load_info()?;
+        Ok(attributes)
+    }
+
+    /// The attributes of `path` in a tree rather than the working tree, as `archive` reads
+    /// them: `gitattributes` has the content of each `.gitattributes` file of the tree along
+    /// the path, with its directory (`""` for the top, else `dir/`), top first.
+    pub fn for_tree_path(path: &str, gitattributes: &[(String, Vec<u8>)]) -> anyhow::Result<Self> {
+        let mut attributes = Self::new(path)?;
+        for (base, content) in gitattributes {
+            attributes.parse(
+                content,
+                base,
+                base.is_empty(),
+                &format!("{}.gitattributes", base),
+            );
+        }
+        attributes.load_info()?;
+        Ok(attributes)
+    }
+
+    /// No attributes yet but those of the global attributes file.
+    fn new(path: &str) -> anyhow::Result<Self> {
+        let mut attributes = Self {
+            path: path.to_string(),
+            files: vec![],
+            macros: HashMap::from([(
+                "binary".to_string(),
+                vec![
+                    ("diff".to_string(), AttributeValue::Unset),
+                    ("merge".to_string(), AttributeValue::Unset),
+                    ("text".to_string(), AttributeValue::Unset),
+                ],
+            )]),
+        };
+        if let Some(global) = global_attributes_file()? {
+            attributes.load(&global, "", true)?;
+        }
+        Ok(attributes)
+    }
+
+    /// `.git/info/attributes`, which overrides every other file.
+    fn load_info(&mut self) -> anyhow::Result<()> {
+        let info = ObjectStorage::git_dir().join("info").join("attributes");
+        self.load(&info, "", true)
+    }
+
+    /// Macros may only be defined at the top level: in the global file, the top-level
+    /// `.gitattributes` and `.git/info/attributes`.
+    fn load(&mut self, file: &Path, base: &str, macros_allowed: bool) -> anyhow::Result<()> {
+        let content = match fs::read(file) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => Err(anyhow::anyhow!("cannot read '{}': {}", file.display(), err))?,
+        };
+        self.parse(&content, base, macros_allowed, &file.display().to_string());
+        Ok(())
+    }
+
+    /// Adds the lines of an attributes file with the content `content`, called `file` in
+    /// warnings.
+    fn parse(&mut self, content: &[u8], base: &str, macros_allowed: bool, file: &str) {
+        let mut lines = vec![];
+        for line in String::from_utf8_lossy(content).lines() {
+            let mut words = line.split_whitespace();
+            let Some(pattern) = words.next().filter(|pattern| !pattern.starts_with('#')) else {
+                continue;
+            };
+            let assignments: Vec<(String, AttributeValue)> = words.map(parse_assignment).collect();
+            if let Some(name) = pattern.strip_prefix("[attr]") {
+                if macros_allowed {
+                    self.macros.insert(name.to_string(), assignments);
+                } else {
+                    eprintln!("warning: [attr]{} not allowed: {}", name, file);
+                }
+                continue;
+            }
+            // Negative patterns are not allowed in attribute files, and ignored like git does.
+            if pattern.starts_with('!') {
+                continue;
+            }
+            lines.push(Line {
+                anchored: pattern.contains('/'),
+                pattern: pattern.strip_prefix('/').unwrap_or(pattern).to_string(),
+                assignments,
+            });
+        }
+        if !lines.is_empty() {
+            self.files.push((base.to_string(), lines));
+        }
+    }
+
+    /// The value of the attribute `name` for the path, or `None` when no line specifies it.
+    /// Later lines override earlier ones, and deeper files override higher ones.
+    pub fn get(&self, name: &str) -> Option<AttributeValue> {
+        for (base, lines) in self.files.iter().rev() {
+            let Some(relative) = self.path.strip_prefix(base.as_str()) else {
+                continue;
+            };
+            for line in lines.iter().rev() {
+                if !line.matches(relative) {
+                    continue;
+                }
+                if let Some(value) = self.assigned(&line.assignments, name, 0) {
+                    return match value {
+                        AttributeValue::Unspecified => None,
+                        value => Some(value),
+                    };
+                }
+            }
+        }
+        None
+    }
+
+    /// The last assignment to `name` on a line, looking into the macros the line sets.
+    fn assigned(
+        &self,
+        assignments: &[(String, AttributeValue)],
+        name: &str,
+        depth: usize,
+    ) -> Option<AttributeValue> {
+        for (assigned, value) in assignments.iter().rev() {
+            if assigned == name {
+                return Some(value.clone());
+            }
+            if *value == AttributeValue::Set
+                && depth < 8
+                && let Some(expansion) = self.macros.get(assigned)
+                && let Some(value) = self.assigned(expansion, name, depth + 1)
+            {
+                return Some(value);
+            }
+        }
+        None
+    }
+}
+
+impl Line {
+    fn matches(&self, relative: &str) -> bool {
+        if self.anchored {
+            wildmatch(&self.pattern, relative, true)
+        } else {
+            let name = relative.rsplit('/').next().unwrap_or(relative);
+            wildmatch(&self.pattern, name, true)
+        }
+    }
+}
+
+fn parse_assignment(word: &str) -> (String, AttributeValue) {
+    if let Some(name) = word.strip_prefix('-') {
+        (name.to_string(), AttributeValue::Unset)
+    } else if let Some(name) = word.strip_prefix('!') {
+        (name.to_string(), AttributeValue::Unspecified)
+    } else if let Some((name, value)) = word.split_once('=') {
+        (name.to_string(), AttributeValue::Value(value.to_string()))
+    } else {
+        (word.to_string(), AttributeValue::Set)
+    }
+}
+
+/// `core.attributesFile`, defaulting to `$XDG_CONFIG_HOME/git/attributes` or
+/// `~/.config/git/attributes`.
+fn global_attributes_file() -> anyhow::Result<Option<PathBuf>> {
+    if let Some(path) = Config::get()?.value("core", "attributesFile") {
+        return Ok(Some(expand_home_dir(&path)));
+    }
+    let config_dir = match (env::var_os("XDG_CONFIG_HOME"), env::var_os("HOME")) {
+        (Some(config), _) if !config.is_empty() => PathBuf::from(config),
+        (_, Some(home)) => PathBuf::from(home).join(".config"),
+        _ => return Ok(None),
+    };
+    Ok(Some(config_dir.join("git").join("attributes")))
+}
@@ -0,0 +1,167 @@
+use crate::object_storage::ObjectStorage;
+use crate::trace2;
+use anyhow::anyhow;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+use std::process::{self, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
+
+/// A lock older than this is considered left behind by a crashed job, as git does for `gc.pid`.
+const STALE_LOCK_AGE: Duration = Duration::from_secs(12 * 60 * 60);
+/// How long a failed job's log keeps the job from being started again automatically
+/// (git's default `gc.logExpiry`).
+const LOG_EXPIRY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Whether jobs may be started in the background, which [`run_program`](crate::run_program)
+/// allows: [`spawn`] starts the current executable, which is this program only then. Commands
+/// run through the library run their jobs in the foreground instead.
+static DETACH_ALLOWED: AtomicBool = AtomicBool::new(false);
+
+/// Lets [`spawn`] be used, for a process whose executable is this program.
+pub fn allow_detach() {
+    DETACH_ALLOWED.store(true, Ordering::Relaxed);
+}
+
+/// Whether jobs may be started with [`spawn`] rather than run in the foreground.
+pub fn can_detach() -> bool {
+    DETACH_ALLOWED.load(Ordering::Relaxed)
+}
+
+/// Starts this program with `args` detached from the terminal, with stdin closed and stdout
+/// and stderr going to `.git/<job>.log`, and returns without waiting for it. The job takes its
+/// own [`JobLock`], so starting it while it's already running is harmless.
+pub fn spawn(job: &str, args: &[&str]) -> anyhow::Result<()> {
+    let log = File::create(log_path(job))?;
+    let mut command = Command::new(std::env::current_exe()?);
+    command
+        .arg(format!(
+            "--git-dir={}",
+            std::path::absolute(ObjectStorage::git_dir())?.display()
+        ))
+        .arg(format!(
+            "--work-tree={}",
+            std::path::absolute(ObjectStorage::work_tree())?.display()
+        ))
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log);
+    trace2::child_env(&mut command);
+    detach(&mut command);
+    command
+        .spawn()
+        .map_err(|err| anyhow!("cannot start background {}: {}", job, err))?;
+    Ok(())
+}
+
+/// Puts the child in its own session, so closing the terminal or pressing Ctrl-C in it doesn't
+/// kill the job.
+#[cfg(unix)]
+fn detach(command: &mut Command) {
+    use std::os::unix::process::CommandExt;
+    // SAFETY: setsid is async-signal-safe, as required between fork and exec.
+    unsafe {
+        command.pre_exec(|| {
+            libc::setsid();
+            Ok(())
+        });
+    }
+}
+
+#[cfg(not(unix))]
+fn detach(_command: &mut Command) {}
+
+/// The output of the last run of `job` if it reported something, which git treats as a
+/// failure: the job is not started automatically again until the log is removed or expires.
+pub fn last_failure(job: &str) -> anyhow::Result<Option<String>> {
+    let path = log_path(job);
+    let Ok(metadata) = fs::metadata(&path) else {
+        return Ok(None);
+    };
+    let expired = metadata
+        .modified()?
+        .elapsed()
+        .is_ok_and(|age| age > LOG_EXPIRY);
+    if metadata.len() == 0 || expired {
+        return Ok(None);
+    }
+    Ok(Some(fs::read_to_string(path)?))
+}
+
+/// Removes the log of `job` after a run that wrote nothing to it.
+pub fn clear_empty_log(job: &str) -> anyhow::Result<()> {
+    let path = log_path(job);
+    if fs::metadata(&path).is_ok_and(|metadata| metadata.len() == 0) {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+fn log_path(job: &str) -> PathBuf {
+    ObjectStorage::git_dir().join(format!("{}.log", job))
+}
+
+/// `.git/<job>.pid`, held while a job runs so that a second invocation (in the background or
+/// not) doesn't race it. It holds the owner's process id; a lock whose process is gone or
+/// that is older than 12 hours is taken over.
+pub struct JobLock {
+    path: PathBuf,
+}
+
+impl JobLock {
+    /// Takes the lock, or returns the process id of the live job holding it.
+    pub fn acquire(job: &str) -> anyhow::Result<Result<Self, u32>> {
+        let path = ObjectStorage::git_dir().join(format!("{}.pid", job));
+        for _ in 0..2 {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    writeln!(file, "{}", process::id())?;
+                    return Ok(Ok(Self { path }));
+                }
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                    let owner = fs::read_to_string(&path)
+                        .ok()
+                        .and_then(|content| content.trim().parse::<u32>().ok());
+                    let age = fs::metadata(&path)
+                        .and_then(|metadata| metadata.modified())
+                        .map(|modified| {
+                            SystemTime::now()
+                                .duration_since(modified)
+                                .unwrap_or_default()
+                        })
+                        .unwrap_or_default();
+                    if let Some(owner) = owner
+                        && is_running(owner)
+                        && age < STALE_LOCK_AGE
+                    {
+                        return Ok(Err(owner));
+                    }
+                    let _ = fs::remove_file(&path);
+                }
+                Err(err) => Err(anyhow!("cannot create '{}': {}", path.display(), err))?,
+            }
+        }
+        Err(anyhow!("cannot take over '{}'", path.display()))
+    }
+}
+
+impl Drop for JobLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    // SAFETY: signal 0 only checks whether the process exists and may be signalled.
+    let result = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    result == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    // Without a cheap way to check, only the age of the lock tells it's stale.
+    true
+}
@@ -0,0 +1,81 @@
+//! The file `backup` writes and `restore` reads: a whole repository, with its config, refs,
+//! index and a pack of every object they lead to, in one file. After a signature line, each
+//! section is a `<name> <length>` line and that many bytes, and a SHA-1 of everything before
+//! it ends the file:
+//!
+//! ```text
+//! # codecrafters-git backup v1
+//! config <length>
+//! <the config file>
+//! refs <length>
+//! <id> <name> or ref: <target> <name>, for HEAD and then every ref
+//! index <length>
+//! <the index file>
+//! pack <length>
+//! <a pack of every reachable object>
+//! <20-byte SHA-1>
+//! ```
+//!
+//! A bare repository has an empty `bare` section instead of an index. Sections that are not
+//! known are skipped, so that later versions can add some.
+
+use crate::object_storage::ObjectStorage;
+use anyhow::anyhow;
+
+const SIGNATURE: &[u8] = b"# codecrafters-git backup v1\n";
+
+#[derive(Default)]
+pub struct Backup {
+    sections: Vec<(String, Vec<u8>)>,
+}
+
+impl Backup {
+    pub fn add(&mut self, name: &str, content: Vec<u8>) {
+        self.sections.push((name.to_string(), content));
+    }
+
+    pub fn section(&self, name: &str) -> Option<&[u8]> {
+        self.sections
+            .iter()
+            .find(|(section, _)| section == name)
+            .map(|(_, content)| content.as_slice())
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut data = SIGNATURE.to_vec();
+        for (name, content) in &self.sections {
+            data.extend_from_slice(format!("{} {}\n", name, content.len()).as_bytes());
+            data.extend_from_slice(content);
+        }
+        let checksum = ObjectStorage::hash_content(&data);
+        data.extend_from_slice(&checksum);
+        data
+    }
+
+    pub fn parse(data: &[u8]) -> anyhow::Result<Self> {
+        let content = data
+            .strip_prefix(SIGNATURE)
+            .ok_or(anyhow!("not a backup file"))?;
+        let (mut content, checksum) = content
+            .split_at_checked(content.len().saturating_sub(20))
+            .filter(|(_, checksum)| checksum.len() == 20)
+            .ok_or(anyhow!("backup file is truncated"))?;
+        if ObjectStorage::hash_content(&data[..data.len() - 20]) != checksum {
+            Err(anyhow!("backup file checksum mismatch"))?
+        }
+        let mut backup = Self::default();
+        while !content.is_empty() {
+            let invalid = || anyhow!("backup file has an invalid section header");
+            let end = content.iter().position(|&byte| byte == b'\n');
+            let header = std::str::from_utf8(&content[..end.ok_or_else(invalid)?])?;
+            let (name, length) = header.split_once(' ').ok_or_else(invalid)?;
+            let length: usize = length.parse().map_err(|_| invalid())?;
+            let section = content[header.len() + 1..]
+                .get(..length)
+                .ok_or(anyhow!("backup file is truncated"))?;
+            backup.add(name, section.to_vec());
+            content = &content[header.len() + 1 + length..];
+        }
+        Ok(backup)
+    }
+}
@@ -0,0 +1,94 @@
+//! Cancelling long operations, such as a clone a user gave up on, from another thread.
+//!
+//! An embedding application installs a [`CancellationToken`] with
+//! [`Repository::set_cancellation_token`] and cancels it when the user asks. Fetching, cloning,
+//! checking out, packing and gc check it at points where they can stop cleanly: the operation
+//! then fails with [`Cancelled`] after undoing what it did, so that a cancelled clone leaves no
+//! directory behind and a cancelled checkout leaves the working tree as it was. Once
+//! cancelled, a token stays cancelled; install a new one for the next operation.
+//!
+//! ```
+//! use codecrafters_git::{CancellationToken, Cancelled, Repository};
+//!
+//! # let dir = std::env::temp_dir().join(format!("cancel-{}", std::process::id()));
+//! let repo = Repository::init(&dir)?;
+//! let token = CancellationToken::new();
+//! repo.set_cancellation_token(token.clone());
+//! token.cancel();
+//! let err = repo.run_command("gc", &[]).unwrap_err();
+//! assert!(err.is::<Cancelled>());
+//! # std::fs::remove_dir_all(&dir)?;
+//! # anyhow::Ok(())
+//! ```
+//!
+//! [`Repository::set_cancellation_token`]: crate::Repository::set_cancellation_token
+
+use crate::context;
+use std::cell::Cell;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+thread_local! {
+    /// How many [`shielded`] calls are running on this thread.
+    static SHIELDED: Cell<usize> = const { Cell::new(0) };
+}
+
+/// A flag shared between the thread running an operation and the one that may cancel it.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks the running operation, and any later one, to stop.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// The error of a cancelled operation; `err.is::<Cancelled>()` tells it from other failures.
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "operation cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+pub(crate) fn set_token(token: CancellationToken) {
+    *context::current().cancellation_token.write().unwrap() = Some(token);
+}
+
+/// Fails with [`Cancelled`] if the installed token was cancelled, unless it is called within
+/// [`shielded`].
+pub(crate) fn check() -> anyhow::Result<()> {
+    let cancelled = context::current()
+        .cancellation_token
+        .read()
+        .unwrap()
+        .as_ref()
+        .is_some_and(CancellationToken::is_cancelled);
+    if cancelled && SHIELDED.get() == 0 {
+        Err(Cancelled)?
+    }
+    Ok(())
+}
+
+/// Runs `work` without checking for cancellation, for work that has to be finished once it
+/// has started, such as rolling back a cancelled operation.
+pub(crate) fn shielded<T>(work: impl FnOnce() -> T) -> T {
+    SHIELDED.set(SHIELDED.get() + 1);
+    let result = work();
+    SHIELDED.set(SHIELDED.get() - 1);
+    result
+}
@@ -0,0 +1,153 @@
+//! The chunk-based file layout git shares between the commit-graph and the multi-pack-index:
+//! a header of the file's own, then a table of contents, then the chunks, then a SHA-1 of all
+//! that comes before it. Each table entry is a 4-byte chunk id and the 8-byte offset where
+//! the chunk starts. A last entry with id 0 gives the offset where the last chunk ends, so
+//! every chunk's size is known from the table alone.
+//!
+//! Reading checks the table against the file, so that a truncated or damaged file is an error
+//! rather than a chunk running past its end; the checksum is only checked on request, as it
+//! means hashing the whole file.
+
+use crate::object_storage::ObjectStorage;
+use anyhow::anyhow;
+use std::ops::Range;
+
+/// A chunk id and an offset.
+const TOC_ENTRY_SIZE: usize = 4 + 8;
+/// The SHA-1 checksum that ends the file.
+const CHECKSUM_SIZE: usize = 20;
+
+pub type ChunkId = [u8; 4];
+
+/// Where each chunk of a file is, as its table of contents says.
+pub struct Chunks {
+    /// What the file is, such as `commit-graph`, to start error messages with.
+    kind: &'static str,
+    chunks: Vec<(ChunkId, Range<usize>)>,
+}
+
+impl Chunks {
+    /// Reads the table of contents of `chunk_count` chunks that starts at `start` in `data`, a
+    /// file of the kind `kind`.
+    pub fn read(
+        data: &[u8],
+        start: usize,
+        chunk_count: usize,
+        kind: &'static str,
+    ) -> anyhow::Result<Self> {
+        let table_end = start + (chunk_count + 1) * TOC_ENTRY_SIZE;
+        if data.len() < table_end + CHECKSUM_SIZE {
+            Err(anyhow!(
+                "{} file is too small to hold {} chunks",
+                kind,
+                chunk_count
+            ))?
+        }
+        let entry = |i: usize| {
+            let entry = &data[start + i * TOC_ENTRY_SIZE..start + (i + 1) * TOC_ENTRY_SIZE];
+            let id: ChunkId = entry[..4].try_into().unwrap();
+            (id, u64::from_be_bytes(entry[4..].try_into().unwrap()))
+        };
+        let chunks_end = (data.len() - CHECKSUM_SIZE) as u64;
+        let mut chunks: Vec<(ChunkId, Range<usize>)> = vec![];
+        for i in 0..chunk_count {
+            let (id, offset) = entry(i);
+            let (_, next_offset) = entry(i + 1);
+            if id == [0; 4] {
+                Err(anyhow!(
+                    "{}: terminating chunk id appears earlier than expected",
+                    kind
+                ))?
+            }
+            if offset < table_end as u64 || next_offset < offset || next_offset > chunks_end {
+                Err(anyhow!(
+                    "{}: improper chunk offset(s) {:x} and {:x}",
+                    kind,
+                    offset,
+                    next_offset
+                ))?
+            }
+            if chunks.iter().any(|(seen, _)| *seen == id) {
+                Err(anyhow!(
+                    "{}: duplicate chunk ID {:x} found",
+                    kind,
+                    u32::from_be_bytes(id)
+                ))?
+            }
+            chunks.push((id, offset as usize..next_offset as usize));
+        }
+        let (last_id, _) = entry(chunk_count);
+        if last_id != [0; 4] {
+            Err(anyhow!(
+                "{}: final chunk has non-zero id {:x}",
+                kind,
+                u32::from_be_bytes(last_id)
+            ))?
+        }
+        Ok(Self { kind, chunks })
+    }
+
+    /// Where the chunk `id` is, if the file has one.
+    pub fn get(&self, id: &ChunkId) -> Option<Range<usize>> {
+        self.chunks
+            .iter()
+            .find(|(chunk_id, _)| chunk_id == id)
+            .map(|(_, range)| range.clone())
+    }
+
+    /// Where the chunk `id` is, which the file must have.
+    pub fn require(&self, id: &ChunkId) -> anyhow::Result<Range<usize>> {
+        self.get(id).ok_or_else(|| {
+            anyhow!(
+                "{} required {} chunk missing or corrupted",
+                self.kind,
+                String::from_utf8_lossy(id)
+            )
+        })
+    }
+}
+
+/// Whether `data`, a whole chunked file, ends with the checksum of what comes before it.
+pub fn checksum_matches(data: &[u8]) -> bool {
+    let Some(end) = data.len().checked_sub(CHECKSUM_SIZE) else {
+        return false;
+    };
+    ObjectStorage::hash_content(&data[..end]) == data[end..]
+}
+
+/// Collects the chunks of a file to write them after its header, with the table of contents
+/// and checksum.
+#[derive(Default)]
+pub struct ChunkWriter {
+    chunks: Vec<(ChunkId, Vec<u8>)>,
+}
+
+impl ChunkWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the chunk `id`, to be written after those added before it.
+    pub fn add(&mut self, id: ChunkId, content: Vec<u8>) {
+        self.chunks.push((id, content));
+    }
+
+    /// The whole file: `header`, the table of contents, the chunks and the checksum.
+    pub fn finish(self, header: &[u8]) -> Vec<u8> {
+        let mut offset = header.len() + (self.chunks.len() + 1) * TOC_ENTRY_SIZE;
+        let mut data = header.to_vec();
+        for (id, content) in &self.chunks {
+            data.extend_from_slice(id);
+            data.extend_from_slice(&(offset as u64).to_be_bytes());
+            offset += content.len();
+        }
+        data.extend_from_slice(&[0; 4]);
+        data.extend_from_slice(&(offset as u64).to_be_bytes());
+        for (_, content) in self.chunks {
+            data.extend_from_slice(&content);
+        }
+        let checksum = ObjectStorage::hash_content(&data);
+        data.extend_from_slice(&checksum);
+        data
+    }
+}
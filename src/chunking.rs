@@ -0,0 +1,62 @@
+//! Content-defined chunking with FastCDC (Xia et al., 2016): data is cut where a rolling gear
+//! hash over the last bytes matches a mask, so an insertion or deletion only changes the chunks
+//! around it and the chunks of similar files mostly come out the same. Chunks are between
+//! [`MIN_SIZE`] and [`MAX_SIZE`] bytes; normalized chunking, a stricter mask before
+//! [`AVERAGE_SIZE`] and a looser one after it, keeps most of them close to the average.
+
+use std::ops::Range;
+
+pub const MIN_SIZE: usize = 2 * 1024;
+pub const AVERAGE_SIZE: usize = 8 * 1024;
+pub const MAX_SIZE: usize = 64 * 1024;
+
+/// The masks the paper gives for an 8KiB average, with 15 and 11 bits spread over the hash.
+const MASK_SMALL: u64 = 0x0003_5907_0353_0000;
+const MASK_LARGE: u64 = 0x0000_d900_0353_0000;
+
+/// A random value for each byte, from splitmix64 with a fixed seed, so chunk boundaries are
+/// the same on every run.
+const GEAR: [u64; 256] = {
+    let mut table = [0; 256];
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut value = state;
+        value = (value ^ (value >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        value = (value ^ (value >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = value ^ (value >> 31);
+        i += 1;
+    }
+    table
+};
+
+/// The chunks of `data`, in order and covering all of it.
+pub fn chunks(data: &[u8]) -> Vec<Range<usize>> {
+    let mut chunks = vec![];
+    let mut start = 0;
+    while start < data.len() {
+        let end = start + cut_point(&data[start..]);
+        chunks.push(start..end);
+        start = end;
+    }
+    chunks
+}
+
+/// The length of the chunk `data` starts with.
+fn cut_point(data: &[u8]) -> usize {
+    if data.len() <= MIN_SIZE {
+        return data.len();
+    }
+    let end = data.len().min(MAX_SIZE);
+    let normal = end.min(AVERAGE_SIZE);
+    let mut hash: u64 = 0;
+    for (i, &byte) in data.iter().enumerate().take(end).skip(MIN_SIZE) {
+        hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+        let mask = if i < normal { MASK_SMALL } else { MASK_LARGE };
+        if hash & mask == 0 {
+            return i + 1;
+        }
+    }
+    end
+}
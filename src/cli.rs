@@ -0,0 +1,1447 @@
+use crate::repository::CommandExit;
+use std::env;
+use std::path::Path;
+
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub summary: &'static str,
+    pub usage: &'static [&'static str],
+    pub options: &'static [(&'static str, &'static str)],
+    pub arguments: ArgumentKind,
+    /// Whether the command operates on an existing repository.
+    pub repository: bool,
+}
+
+/// What the positional arguments of a command refer to, used for shell completion.
+#[derive(PartialEq)]
+pub enum ArgumentKind {
+    None,
+    Files,
+    Refs,
+    Commands,
+    Shells,
+}
+
+/// Exit code git uses for usage errors.
+pub const USAGE_EXIT_CODE: i32 = 129;
+
+pub const GLOBAL_OPTIONS: &[(&str, &str)] = &[
+    ("-C <path>", "run as if started in <path>"),
+    ("--git-dir=<path>", "path to the repository"),
+    ("--work-tree=<path>", "path to the working tree"),
+    (
+        "--dry-run",
+        "only report what gc, repack or push would delete or update",
+    ),
+];
+
+pub const COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "init",
+        summary: "Create an empty Git repository",
+        usage: &["init [-b <branch-name> | --initial-branch=<branch-name>] \
+                  [--template=<directory>] [--shared[=<permissions>]]"],
+        options: &[
+            (
+                "-b, --initial-branch=<branch-name>",
+                "override the name of the initial branch",
+            ),
+            (
+                "--template=<directory>",
+                "directory from which templates will be used",
+            ),
+            (
+                "--shared[=<permissions>]",
+                "make the repository writable by the group or everybody (default: group)",
+            ),
+        ],
+        arguments: ArgumentKind::None,
+        repository: false,
+    },
+    CommandSpec {
+        name: "cat-file",
+        summary: "Provide contents of repository objects",
+        usage: &[
+            "cat-file (-t | -s | -e | -p) <object>",
+            "cat-file (--batch | --batch-check) [--batch-all-objects [--unordered]]",
+        ],
+        options: &[
+            ("-t", "show object type"),
+            ("-s", "show object size"),
+            (
+                "-e",
+                "exit with zero status if <object> exists and is valid",
+            ),
+            ("-p", "pretty-print <object> content"),
+            (
+                "--batch",
+                "show the header and content of each object named on stdin",
+            ),
+            (
+                "--batch-check",
+                "show the type and size of each object named on stdin",
+            ),
+            (
+                "--batch-all-objects",
+                "show all objects in the repository instead of those named on stdin",
+            ),
+            (
+                "--unordered",
+                "show objects in the order the packs store them rather than sorted",
+            ),
+        ],
+        arguments: ArgumentKind::Refs,
+        repository: true,
+    },
+    CommandSpec {
+        name: "unpack-file",
+        summary: "Create a temporary file with a blob's contents",
+        usage: &["unpack-file <blob>"],
+        options: &[],
+        arguments: ArgumentKind::Refs,
+        repository: true,
+    },
+    CommandSpec {
+        name: "hash-object",
+        summary: "Compute object ID and optionally create an object from a file",
+        usage: &["hash-object [-w] [-t <type>] [--literally] [--stdin] [--] <file>..."],
+        options: &[
+            ("-w", "write the object into the object database"),
+            ("-t <type>", "object type (default: blob)"),
+            ("--stdin", "read the object from standard input"),
+            ("--literally", "skip validation of the type and content"),
+        ],
+        arguments: ArgumentKind::Files,
+        repository: false,
+    },
+    CommandSpec {
+        name: "ls-tree",
+        summary: "List the contents of a tree object",
+        usage: &[
+            "ls-tree [-d] [-r] [-t] [-l] [-z] [--name-only | --format=<format>] <tree-ish> \
+             [<pathspec>...]",
+        ],
+        options: &[
+            ("-d", "only show trees"),
+            ("-r", "recurse into subtrees"),
+            ("-t", "show trees when recursing"),
+            ("-l, --long", "include object size"),
+            ("-z", "terminate entries with NUL byte"),
+            ("--name-only", "list only filenames"),
+            (
+                "--format <format>",
+                "print each entry with %(objectmode), %(objecttype), %(objectname), \
+                 %(objectsize), %(objectsize:padded) and %(path) filled in",
+            ),
+        ],
+        arguments: ArgumentKind::Refs,
+        repository: true,
+    },
+    CommandSpec {
+        name: "add",
+        summary: "Add file contents to the index",
+        usage: &[
+            "add [-f] [--] <pathspec>...",
+            "add (-p | --patch) [--patch-script <file>] [--] [<pathspec>...]",
+        ],
+        options: &[
+            ("-f, --force", "allow adding otherwise ignored files"),
+            ("-p, --patch", "select hunks interactively"),
+            (
+                "--patch-script <file>",
+                "read the answers to the hunk prompts from <file>, one per line",
+            ),
+        ],
+        arguments: ArgumentKind::Files,
+        repository: true,
+    },
+    CommandSpec {
+        name: "diff",
+        summary: "Show changes between the working tree, the index and trees",
+        usage: &[
+            "diff [<options>] [--] [<pathspec>...]",
+            "diff [<options>] --cached [<tree-ish>] [--] [<pathspec>...]",
+            "diff [<options>] <tree-ish> [<tree-ish>] [--] [<pathspec>...]",
+        ],
+        options: &[
+            ("--cached", "compare the index with HEAD or <tree-ish>"),
+            ("-U, --unified <n>", "show <n> lines of context (default 3)"),
+            ("--name-only", "show only the names of changed files"),
+            (
+                "--name-status",
+                "show the names and status of changed files",
+            ),
+            (
+                "-z",
+                "with --name-only and --name-status, end names with NUL and leave paths unquoted",
+            ),
+            ("--exit-code", "exit with 1 if there are differences"),
+        ],
+        arguments: ArgumentKind::Refs,
+        repository: true,
+    },
+    CommandSpec {
+        name: "difftool",
+        summary: "Show changes using an external diff tool",
+        usage: &[
+            "difftool [-t <tool>] [-y] [--cached] [<tree-ish> [<tree-ish>]] [--] [<pathspec>...]",
+            "difftool --tool-help",
+        ],
+        options: &[
+            ("-t, --tool <tool>", "use the diff tool <tool>"),
+            ("-y, --no-prompt", "launch the tool without asking first"),
+            ("--prompt", "ask before launching the tool for each file"),
+            ("--cached", "compare the index with HEAD or <tree-ish>"),
+            (
+                "--trust-exit-code",
+                "stop and exit with the tool's exit code when it fails",
+            ),
+            ("--tool-help", "list the tools that can be used with --tool"),
+        ],
+        arguments: ArgumentKind::Refs,
+        repository: true,
+    },
+    CommandSpec {
+        name: "mergetool",
+        summary: "Resolve merge conflicts using an external merge tool",
+        usage: &[
+            "mergetool [-t <tool>] [-y] [--] [<file>...]",
+            "mergetool --tool-help",
+        ],
+        options: &[
+            ("-t, --tool <tool>", "use the merge tool <tool>"),
+            ("-y, --no-prompt", "launch the tool without asking first"),
+            ("--prompt", "ask before launching the tool for each file"),
+            ("--tool-help", "list the tools that can be used with --tool"),
+        ],
+        arguments: ArgumentKind::Files,
+        repository: true,
+    },
+    CommandSpec {
+        name: "merge-file",
+        summary: "Run a three-way file merge",
+        usage: &[
+            "merge-file [-L <name>]... [--ours | --theirs | --union] [-p] [--marker-size <n>] <current> <base> <other>",
+        ],
+        options: &[
+            (
+                "-L <name>",
+                "label for the conflict markers, for <current>, <base> and <other> in turn",
+            ),
+            ("--ours", "resolve conflicts in favor of <current>"),
+            ("--theirs", "resolve conflicts in favor of <other>"),
+            ("--union", "keep the lines of both sides of a conflict"),
+            (
+                "-p, --stdout",
+                "write the result to standard output instead of <current>",
+            ),
+            ("--marker-size <n>", "the length of the conflict markers"),
+        ],
+        arguments: ArgumentKind::Files,
+        repository: false,
+    },
+    CommandSpec {
+        name: "merge",
+        summary: "Join two or more development histories together",
+        usage: &["merge [-m <message>] [--log[=<n>] | --no-log] <commit>..."],
+        options: &[
+            ("-m <message>", "the message of the merge commit"),
+            (
+                "--log[=<n>]",
+                "add the subjects of up to <n> merged commits to the message",
+            ),
+            ("--no-log", "do not list the merged commits"),
+        ],
+        arguments: ArgumentKind::Refs,
+        repository: true,
+    },
+    CommandSpec {
+        name: "merge-tree",
+        summary: "Merge two commits without touching the working tree or index",
+        usage: &["merge-tree [--write-tree] [<options>] <branch1> <branch2>"],
+        options: &[
+            (
+                "--write-tree",
+                "write the merged tree and print its id (the default)",
+            ),
+            (
+                "--name-only",
+                "list the conflicting paths without modes, objects and stages",
+            ),
+            (
+                "--messages",
+                "also show informational and conflict messages (the default)",
+            ),
+            ("--no-messages", "do not show the messages"),
+            (
+                "--allow-unrelated-histories",
+                "allow merging histories without a common ancestor",
+            ),
+        ],
+        arguments: ArgumentKind::Refs,
+        repository: true,
+    },
+    CommandSpec {
+        name: "merge-base",
+        summary: "Find as good common ancestors as possible for a merge",
+        usage: &[
+            "merge-base [-a | --all] <commit> <commit>...",
+            "merge-base --is-ancestor <commit> <commit>",
+        ],
+        options: &[
+            ("-a, --all", "output all merge bases instead of just one"),
+            (
+                "--is-ancestor",
+                "only exit with 0 when the first commit is an ancestor of the second",
+            ),
+        ],
+        arguments: ArgumentKind::Refs,
+        repository: true,
+    },
+    CommandSpec {
+        name: "fmt-merge-msg",
+        summary: "Produce a merge commit message",
+        usage: &["fmt-merge-msg [-m <message>] [--log[=<n>] | --no-log] [-F <file>]"],
+        options: &[
+            (
+                "-m, --message <message>",
+                "use <message> as the first line instead of the names of what is merged",
+            ),
+            (
+                "--log[=<n>]",
+                "add the subjects of up to <n> merged commits to the message",
+            ),
+            ("--no-log", "do not list the merged commits"),
+            ("-F, --file <file>", "read the FETCH_HEAD lines from <file>"),
+        ],
+        arguments: ArgumentKind::Files,
+        repository: true,
+    },
+    CommandSpec {
+        name: "apply",
+        summary: "Apply a patch to files and/or to the index",
+        usage: &["apply [--index | --cached] [-3 | --3way] [<patch>...]"],
+        options: &[
+            (
+                "--index",
+                "apply to the index as well, which has to match the working tree",
+            ),
+            ("--cached", "apply to the index only"),
+            (
+                "-3, --3way",
+                "fall back to a three-way merge with the blobs the patch names",
+            ),
+        ],
+        arguments: ArgumentKind::Files,
+        repository: true,
+    },
+    CommandSpec {
+        name: "archive",
+        summary: "Create an archive of files from a named tree",
+        usage: &[
+            "archive [--format=<fmt>] [--prefix=<prefix>] [-o <file>] [--mtime=<time>] <tree-ish>",
+            "archive -l",
+        ],
+        options: &[
+            ("--format=<fmt>", "archive format: tar, tgz or tar.gz"),
+            (
+                "--prefix=<prefix>",
+                "prepend <prefix> to each path in the archive",
+            ),
+            ("-o, --output <file>", "write the archive to <file>"),
+            (
+                "--mtime=<time>",
+                "give every entry this time instead of the committer time",
+            ),
+            ("-l, --list", "list the supported archive formats"),
+        ],
+        arguments: ArgumentKind::Refs,
+        repository: true,
+    },
+    CommandSpec {
+        name: "format-patch",
+        summary: "Prepare patches for e-mail submission",
+        usage: &[
+            "format-patch [--stdout | -o <dir>] [-n | -N] [-v <n>] [--cover-letter] [--rfc] [--subject-prefix <prefix>] [--to <address>] [--cc <address>] (<since> | <revision-range> | -<n>)",
+        ],
+        options: &[
+            ("--stdout", "write all patches to stdout as one mbox"),
+            (
+                "-o, --output-directory <dir>",
+                "write the patch files into <dir>",
+            ),
+            (
+                "-n, --numbered",
+                "number the patches even if there is only one",
+            ),
+            ("-N, --no-numbered", "do not number the patches"),
+            (
+                "-v, --reroll-count <n>",
+                "mark the series as its <n>th version, [PATCH v<n>]",
+            ),
+            (
+                "--cover-letter",
+                "add a message introducing the series, with its shortlog and diffstat",
+            ),
+            ("--rfc", "use [RFC PATCH] instead of [PATCH]"),
+            (
+                "--subject-prefix <prefix>",
+                "use [<prefix>] instead of [PATCH]",
+            ),
+            ("--to <address>", "add a To: header"),
+            ("--cc <address>", "add a Cc: header"),
+        ],
+        arguments: ArgumentKind::Refs,
+        repository: true,
+    },
+    CommandSpec {
+        name: "am",
+        summary: "Apply a series of patches from a mailbox",
+        usage: &["am [--keep-cr | --no-keep-cr] [-3 | --3way] [<mbox>...]"],
+        options: &[
+            (
+                "--keep-cr",
+                "keep the CR at the end of lines that end in CRLF",
+            ),
+            ("--no-keep-cr", "remove it, overriding am.keepCR"),
+            (
+                "-3, --3way",
+                "fall back to a three-way merge when a patch does not apply",
+            ),
+        ],
+        arguments: ArgumentKind::Files,
+        repository: true,
+    },
+    CommandSpec {
+        name: "cherry-pick",
+        summary: "Apply the changes introduced by some existing commits",
+        usage: &[
+            "cherry-pick [-x] [-m <parent-number>] <commit>...",
+            "cherry-pick (--continue | --skip | --abort | --quit)",
+        ],
+        options: &[
+            (
+                "-x",
+                "add a line saying which commit was cherry-picked to the message",
+            ),
+            (
+                "-m, --mainline <parent-number>",
+                "take the changes of a merge relative to this parent",
+            ),
+            (
+                "--continue",
+                "commit the resolved conflicts and carry on with the rest",
+            ),
+            (
+                "--skip",
+                "skip the commit that stopped and carry on with the rest",
+            ),
+            ("--abort", "go back to where the cherry-pick started"),
+            (
+                "--quit",
+                "forget the cherry-pick in progress, keeping what it did",
+            ),
+        ],
+        arguments: ArgumentKind::Refs,
+        repository: true,
+    },
+    CommandSpec {
+        name: "revert",
+        summary: "Revert some existing commits",
+        usage: &[
+            "revert [-m <parent-number>] <commit>...",
+            "revert (--continue | --skip | --abort | --quit)",
+        ],
+        options: &[
+            (
+                "-m, --mainline <parent-number>",
+                "take the changes of a merge relative to this parent",
+            ),
+            (
+                "--continue",
+                "commit the resolved conflicts and carry on with the rest",
+            ),
+            (
+                "--skip",
+                "skip the commit that stopped and carry on with the rest",
+            ),
+            ("--abort", "go back to where the revert started"),
+            (
+                "--quit",
+                "forget the revert in progress, keeping what it did",
+            ),
+        ],
+        arguments: ArgumentKind::Refs,
+        repository: true,
+    },
+    CommandSpec {
+        name: "status",
+        summary: "Show staged, unstaged and untracked changes",
+        usage: &["status [-s] [-b] [--porcelain] [-z] [--] [<pathspec>...]"],
+        options: &[
+            ("-s, --short", "give the output in the short format"),
+            ("-b, --branch", "show the branch in the short format"),
+            ("--porcelain", "give the output in a stable, short format"),
+            (
+                "-z",
+                "terminate entries with NUL and leave paths unquoted; implies --porcelain",
+            ),
+        ],
+        arguments: ArgumentKind::Files,
+        repository: true,
+    },
+    CommandSpec {
+        name: "prompt",
+        summary: "Show the branch and its state in one line for a shell prompt",
+        usage: &["prompt [--no-dirty] [--no-upstream]"],
+        options: &[
+            (
+                "--no-dirty",
+                "leave out the unstaged (*) and staged (+) change markers",
+            ),
+            (
+                "--no-upstream",
+                "leave out how far the branch is ahead of and behind its upstream",
+            ),
+        ],
+        arguments: ArgumentKind::None,
+        repository: false,
+    },
+    CommandSpec {
+        name: "write-tree",
+        summary: "Create a tree object from the index",
+        usage: &["write-tree"],
+        options: &[],
+        arguments: ArgumentKind::None,
+        repository: true,
+    },
+    CommandSpec {
+        name: "commit",
+        summary: "Record changes to the repository",
+        usage: &[
+            "commit [-n] [--allow-empty] [--allow-empty-message] (-m <message>... | -F <file>)",
+        ],
+        options: &[
+            (
+                "-m, --message <message>",
+                "commit message; several -m make paragraphs",
+            ),
+            (
+                "-F, --file <file>",
+                "read the message from <file> (- for stdin)",
+            ),
+            (
+                "-n, --no-verify",
+                "bypass the pre-commit and commit-msg hooks",
+            ),
+            (
+                "--allow-empty",
+                "allow a commit with the same tree as its parent",
+            ),
+            (
+                "--allow-empty-message",
+                "allow a commit with an empty message",
+            ),
+        ],
+        arguments: ArgumentKind::None,
+        repository: true,
+    },
+    CommandSpec {
+        name: "commit-tree",
+        summary: "Create a new commit object",
+        usage: &["commit-tree <tree> [(-p <parent>)...] [(-m <message>)... | -F <file>]"],
+        options: &[
+            ("-p <parent>", "id of a parent commit object"),
+            ("-m <message>", "commit message; several -m make paragraphs"),
+            ("-F <file>", "read the message from <file> (- for stdin)"),
+        ],
+        arguments: ArgumentKind::Refs,
+        repository: true,
+    },
+    CommandSpec {
+        name: "checkout",
+        summary: "Switch branches, check out a commit or restore files in the working tree",
+        usage: &[
+            "checkout [-f] <branch>",
+            "checkout [-f] <commit>",
+            "checkout [<tree-ish>] -- <pathspec>...",
+            "checkout (-p | --patch) [--patch-script <file>] [<tree-ish>] [--] [<pathspec>...]",
+        ],
+        options: &[
+            (
+                "-f, --force",
+                "discard local changes and untracked files in the way",
+            ),
+            ("-p, --patch", "select hunks interactively"),
+            (
+                "--patch-script <file>",
+                "read the answers to the hunk prompts from <file>, one per line",
+            ),
+        ],
+        arguments: ArgumentKind::Refs,
+        repository: true,
+    },
+    CommandSpec {
+        name: "clone",
+        summary: "Clone a repository over the smart HTTP protocol",
+        usage: &["clone [--template=<directory>] <url> [<directory>]"],
+        options: &[(
+            "--template=<directory>",
+            "directory from which templates will be used",
+        )],
+        arguments: ArgumentKind::None,
+        repository: false,
+    },
+    CommandSpec {
+        name: "fetch",
+        summary: "Download objects and refs from a remote over the smart HTTP protocol",
+        usage: &[
+            "fetch [--refresh] [<remote>]",
+            "fetch [--refresh] [-j | --jobs <n>] --all",
+        ],
+        options: &[
+            (
+                "--refresh",
+                "ask the remote for its refs even if fetch.refCacheTTL allows a cached answer",
+            ),
+            ("--all", "fetch from every remote"),
+            (
+                "-j, --jobs <n>",
+                "fetch from up to <n> remotes at once, overriding fetch.parallel",
+            ),
+        ],
+        arguments: ArgumentKind::None,
+        repository: true,
+    },
+    CommandSpec {
+        name: "push",
+        summary: "Update remote refs along with associated objects",
+        usage: &["push [-f | --force] [-u | --set-upstream] [<remote> [<refspec>...]]"],
+        options: &[
+            (
+                "-f, --force",
+                "update the remote refs even when it is not a fast-forward",
+            ),
+            (
+                "-u, --set-upstream",
+                "make the pushed branches track what they were pushed to",
+            ),
+        ],
+        arguments: ArgumentKind::Refs,
+        repository: true,
+    },
+    CommandSpec {
+        name: "log",
+        summary: "Show commit logs",
+        usage: &[
+            "log [(-S <string> [--pickaxe-regex]) | -G <regex>] [--author <pattern>] \
+             [--grep <pattern>] [-i] [--all] [--branches[=<pattern>]] [--tags[=<pattern>]] \
+             [--remotes[=<pattern>]] [--glob=<pattern>] [--exclude=<pattern>] \
+             [--pretty[=<format>]] [--parents] [<revision>...]",
+        ],
+        options: &[
+            ("--all", "walk from every ref and HEAD"),
+            (
+                "--branches[=<pattern>]",
+                "walk from every branch, or those matching <pattern>",
+            ),
+            (
+                "--tags[=<pattern>]",
+                "walk from every tag, or those matching <pattern>",
+            ),
+            (
+                "--remotes[=<pattern>]",
+                "walk from every remote-tracking branch, or those matching <pattern>",
+            ),
+            ("--glob=<pattern>", "walk from the refs matching <pattern>"),
+            (
+                "--exclude=<pattern>",
+                "leave the refs matching <pattern> out of the next ref option",
+            ),
+            (
+                "--author <pattern>",
+                "only commits whose author matches the regex <pattern>",
+            ),
+            (
+                "--grep <pattern>",
+                "only commits whose message matches the regex <pattern>",
+            ),
+            (
+                "-i, --regexp-ignore-case",
+                "match --author and --grep patterns regardless of case",
+            ),
+            (
+                "-S <string>",
+                "only commits changing the number of occurrences of <string>",
+            ),
+            (
+                "--pickaxe-regex",
+                "treat the -S string as a regular expression",
+            ),
+            (
+                "-G <regex>",
+                "only commits adding or removing lines matching <regex>",
+            ),
+            (
+                "--pretty[=<format>], --format=<format>",
+                "show commits as medium (the default) or raw, with their headers as stored",
+            ),
+            ("--parents", "print the parents of each commit after its id"),
+        ],
+        arguments: ArgumentKind::Refs,
+        repository: true,
+    },
+    CommandSpec {
+        name: "rev-list",
+        summary: "Lists commit objects in reverse chronological order",
+        usage: &["rev-list [--count] [--left-right] <commit>..."],
+        options: &[
+            (
+                "--count",
+                "print the number of commits instead of listing them",
+            ),
+            (
+                "--left-right",
+                "mark or count the commits of each side of a symmetric difference",
+            ),
+        ],
+        arguments: ArgumentKind::Refs,
+        repository: true,
+    },
+    CommandSpec {
+        name: "branch",
+        summary: "List or create branches",
+        usage: &[
+            "branch [--contains <commit>] [--no-contains <commit>] [--merged [<commit>]] \
+             [--no-merged [<commit>]] [--glob=<pattern>] [--exclude=<pattern>]",
+            "branch <name> [<start-point>]",
+        ],
+        options: &[
+            (
+                "--contains <commit>",
+                "only list branches which contain <commit>",
+            ),
+            (
+                "--no-contains <commit>",
+                "only list branches which don't contain <commit>",
+            ),
+            (
+                "--merged [<commit>]",
+                "only list branches reachable from <commit> (default: HEAD)",
+            ),
+            (
+                "--no-merged [<commit>]",
+                "only list branches not reachable from <commit> (default: HEAD)",
+            ),
+            (
+                "--glob=<pattern>",
+                "only list branches matching <pattern>, or under it",
+            ),
+            (
+                "--exclude=<pattern>",
+                "leave out branches matching <pattern>",
+            ),
+        ],
+        arguments: ArgumentKind::Refs,
+        repository: true,
+    },
+    CommandSpec {
+        name: "tag",
+        summary: "Create, list or delete tags",
+        usage: &[
+            "tag [-f] [-a] [(-m <message>)... | -F <file>] <name> [<commit>]",
+            "tag -d <name>...",
+            "tag [-n] [-l] [--contains <commit>] [--no-contains <commit>] [--merged [<commit>]] \
+             [--no-merged [<commit>]] [--glob=<pattern>] [--exclude=<pattern>] [<pattern>...]",
+        ],
+        options: &[
+            ("-a", "make an annotated tag object"),
+            ("-m <message>", "tag message; several -m make paragraphs"),
+            ("-F <file>", "read the message from <file> (- for stdin)"),
+            ("-f", "replace an existing tag"),
+            ("-d", "delete tags"),
+            ("-l", "list tags matching <pattern>"),
+            ("-n", "print the first line of each tag message"),
+            (
+                "--contains <commit>",
+                "only list tags which contain <commit>",
+            ),
+            (
+                "--no-contains <commit>",
+                "only list tags which don't contain <commit>",
+            ),
+            (
+                "--merged [<commit>]",
+                "only list tags reachable from <commit> (default: HEAD)",
+            ),
+            (
+                "--no-merged [<commit>]",
+                "only list tags not reachable from <commit> (default: HEAD)",
+            ),
+            (
+                "--glob=<pattern>",
+                "only list tags matching <pattern>, or under it",
+            ),
+            ("--exclude=<pattern>", "leave out tags matching <pattern>"),
+        ],
+        arguments: ArgumentKind::Refs,
+        repository: true,
+    },
+    CommandSpec {
+        name: "verify-tag",
+        summary: "Check the signature of tags",
+        usage: &["verify-tag [-v] [--raw] <tag>..."],
+        options: &[
+            ("-v, --verbose", "print the tag contents before the result"),
+            (
+                "--raw",
+                "print the raw gpg status output instead of the human-readable one",
+            ),
+        ],
+        arguments: ArgumentKind::Refs,
+        repository: true,
+    },
+    CommandSpec {
+        name: "rev-parse",
+        summary: "Turn revisions into object ids",
+        usage: &[
+            "rev-parse [--verify] [-q] [--short] [--output-object-format <format>] <revision>...",
+            "rev-parse [--git-dir] [--show-toplevel] [--is-inside-work-tree]",
+        ],
+        options: &[
+            (
+                "--verify",
+                "require exactly one revision that names an object",
+            ),
+            (
+                "-q, --quiet",
+                "with --verify, exit with status 1 instead of failing",
+            ),
+            (
+                "--short",
+                "print the shortest unique abbreviation of at least 7 characters",
+            ),
+            (
+                "--output-object-format <format>",
+                "print sha1 or, with a compatibility map, sha256 object ids",
+            ),
+            (
+                "--git-dir",
+                "show the path of the repository's git directory",
+            ),
+            ("--show-toplevel", "show the top of the working tree"),
+            (
+                "--is-inside-work-tree",
+                "show whether the current directory is inside the working tree",
+            ),
+        ],
+        arguments: ArgumentKind::Refs,
+        repository: true,
+    },
+    CommandSpec {
+        name: "update-ref",
+        summary: "Update the object name stored in a ref safely",
+        usage: &[
+            "update-ref [--no-deref] <ref> <new-value> [<old-value>]",
+            "update-ref -d <ref> [<old-value>]",
+        ],
+        options: &[
+            ("-d", "delete the reference"),
+            (
+                "--no-deref",
+                "update <ref> itself, not the ref it points to",
+            ),
+        ],
+        arguments: ArgumentKind::Refs,
+        repository: true,
+    },
+    CommandSpec {
+        name: "symbolic-ref",
+        summary: "Read or modify symbolic refs",
+        usage: &["symbolic-ref [--short] <name>", "symbolic-ref <name> <ref>"],
+        options: &[("--short", "shorten the ref name when reading")],
+        arguments: ArgumentKind::Refs,
+        repository: true,
+    },
+    CommandSpec {
+        name: "show-ref",
+        summary: "List references in a local repository",
+        usage: &[
+            "show-ref [--head] [-s | --hash[=<n>]] [--heads] [--tags] [--glob=<pattern>] \
+             [--exclude=<pattern>] [<pattern>...]",
+            "show-ref --verify [-q | --quiet] [-s | --hash[=<n>]] <ref>...",
+        ],
+        options: &[
+            (
+                "--head",
+                "show the HEAD reference, even if it would be filtered out",
+            ),
+            ("--heads", "only show branches"),
+            ("--tags", "only show tags"),
+            (
+                "--glob=<pattern>",
+                "only show refs matching <pattern>, refs/ implied",
+            ),
+            ("--exclude=<pattern>", "leave out refs matching <pattern>"),
+            (
+                "-s, --hash[=<n>]",
+                "only show the object name, abbreviated to <n> hex digits",
+            ),
+            (
+                "--verify",
+                "only show refs named in full, which must all exist",
+            ),
+            (
+                "-q, --quiet",
+                "do not print anything; the exit status tells",
+            ),
+        ],
+        arguments: ArgumentKind::Refs,
+        repository: true,
+    },
+    CommandSpec {
+        name: "verify-snapshot",
+        summary: "Compute or check a digest of the refs and reachable objects",
+        usage: &["verify-snapshot [--list] [<digest>]"],
+        options: &[(
+            "--list",
+            "print the refs and objects that make up the digest instead",
+        )],
+        arguments: ArgumentKind::None,
+        repository: true,
+    },
+    CommandSpec {
+        name: "backup",
+        summary: "Write the refs, config, index and all reachable objects to one file",
+        usage: &["backup <file>"],
+        options: &[],
+        arguments: ArgumentKind::Files,
+        repository: true,
+    },
+    CommandSpec {
+        name: "restore",
+        summary: "Recreate a repository from a file written by backup",
+        usage: &["restore <file> <directory>"],
+        options: &[],
+        arguments: ArgumentKind::Files,
+        repository: false,
+    },
+    CommandSpec {
+        name: "interop-check",
+        summary: "Compare how objects, trees and pack indexes are written with system git",
+        usage: &["interop-check [--git=<path>]"],
+        options: &[(
+            "--git=<path>",
+            "the git to compare with (default: git on the PATH)",
+        )],
+        arguments: ArgumentKind::None,
+        repository: true,
+    },
+    CommandSpec {
+        name: "fsck",
+        summary: "Verify the connectivity and validity of the objects in the database",
+        usage: &["fsck [--no-dangling]"],
+        options: &[(
+            "--no-dangling",
+            "do not report objects that nothing refers to",
+        )],
+        arguments: ArgumentKind::None,
+        repository: true,
+    },
+    CommandSpec {
+        name: "commit-graph",
+        summary: "Write or verify the commit-graph file that speeds up history walks",
+        usage: &["commit-graph write [--reachable]", "commit-graph verify"],
+        options: &[(
+            "--reachable",
+            "record the commits reachable from the refs (the default)",
+        )],
+        arguments: ArgumentKind::None,
+        repository: true,
+    },
+    CommandSpec {
+        name: "stats",
+        summary: "Show how many objects the repository stores and how much space they take",
+        usage: &["stats [--dedup]"],
+        options: &[(
+            "--dedup",
+            "also report what storing each distinct chunk of blob content once would save",
+        )],
+        arguments: ArgumentKind::None,
+        repository: true,
+    },
+    CommandSpec {
+        name: "gc",
+        summary: "Clean up the repository: pack the refs and objects",
+        usage: &["gc [--auto]"],
+        options: &[(
+            "--auto",
+            "only run when there are too many loose objects or packs",
+        )],
+        arguments: ArgumentKind::None,
+        repository: true,
+    },
+    CommandSpec {
+        name: "migrate",
+        summary: "Upgrade a loose-only repository: pack it, write a commit-graph and an index",
+        usage: &["migrate [-q]"],
+        options: &[("-q, --quiet", "only report errors")],
+        arguments: ArgumentKind::None,
+        repository: true,
+    },
+    CommandSpec {
+        name: "repack",
+        summary: "Pack objects that are not packed yet",
+        usage: &["repack [-a] [-d] [-q] [--window <n>] [--depth <n>]"],
+        options: &[
+            (
+                "-a",
+                "pack everything into a single pack, not just loose objects",
+            ),
+            ("-d", "remove packs and loose objects made redundant"),
+            ("-q, --quiet", "don't report on the new pack"),
+            (
+                "--window <n>",
+                "number of objects to consider for each delta",
+            ),
+            ("--depth <n>", "maximum length of delta chains"),
+        ],
+        arguments: ArgumentKind::None,
+        repository: true,
+    },
+    CommandSpec {
+        name: "update-server-info",
+        summary: "Update auxiliary info files to help dumb servers",
+        usage: &["update-server-info"],
+        options: &[],
+        arguments: ArgumentKind::None,
+        repository: true,
+    },
+    CommandSpec {
+        name: "config",
+        summary: "Get and set repository or global options",
+        usage: &[
+            "config [--get] <name>",
+            "config [--global] [--set] <name> <value>",
+            "config [--global] --unset <name>",
+        ],
+        options: &[
+            ("--get", "print the value of <name>"),
+            ("--global", "use the global config file"),
+            (
+                "--set",
+                "set <name> to <value>, keeping the rest of the file intact",
+            ),
+            ("--unset", "remove <name>"),
+        ],
+        arguments: ArgumentKind::None,
+        repository: true,
+    },
+    CommandSpec {
+        name: "completions",
+        summary: "Generate a shell completion script",
+        usage: &["completions (bash | zsh | fish)", "completions --refs"],
+        options: &[(
+            "--refs",
+            "list branch and tag names for the completion scripts",
+        )],
+        arguments: ArgumentKind::Shells,
+        repository: false,
+    },
+    CommandSpec {
+        name: "help",
+        summary: "Display help information about a command",
+        usage: &["help [<command>]"],
+        options: &[],
+        arguments: ArgumentKind::Commands,
+        repository: false,
+    },
+];
+
+/// The options and operands of one invocation, parsed against the command's [`CommandSpec`].
+/// Options are stored under the first of their aliases, e.g. `-n` for `-n, --no-verify`.
+pub struct ParsedArgs {
+    options: Vec<(&'static str, Option<String>)>,
+    pub operands: Vec<String>,
+    /// How many operands came before `--`, when it was given.
+    pub separator: Option<usize>,
+}
+
+impl ParsedArgs {
+    pub fn has(&self, name: &str) -> bool {
+        self.options.iter().any(|(option, _)| *option == name)
+    }
+
+    /// The value of the last occurrence of `name`.
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.values(name).pop()
+    }
+
+    pub fn values(&self, name: &str) -> Vec<&str> {
+        self.options
+            .iter()
+            .filter(|(option, _)| *option == name)
+            .filter_map(|(_, value)| value.as_deref())
+            .collect()
+    }
+
+    /// Which of `names`, say `--log` and `--no-log`, was given last.
+    pub fn last_of(&self, names: &[&str]) -> Option<&str> {
+        self.options
+            .iter()
+            .rev()
+            .find(|(option, _)| names.contains(option))
+            .map(|(option, _)| *option)
+    }
+
+    /// Every occurrence of any of `names`, with its value, in the order given, for options
+    /// such as `--exclude` that apply to the ones after them.
+    pub fn in_order<'a>(
+        &'a self,
+        names: &'a [&str],
+    ) -> impl Iterator<Item = (&'a str, Option<&'a str>)> {
+        self.options
+            .iter()
+            .filter(|(option, _)| names.contains(option))
+            .map(|(option, value)| (*option, value.as_deref()))
+    }
+
+    /// The values of every occurrence of an option whose value is optional
+    /// (`--merged [<commit>]`), with `default` for occurrences without one.
+    pub fn values_or<'a>(&'a self, name: &str, default: &'a str) -> Vec<&'a str> {
+        self.options
+            .iter()
+            .filter(|(option, _)| *option == name)
+            .map(|(_, value)| value.as_deref().unwrap_or(default))
+            .collect()
+    }
+}
+
+/// Whether an option takes a value, going by its placeholder in the [`CommandSpec`]: `-t <type>`
+/// requires one, `--merged [<commit>]` takes the next argument if there is one, like git's
+/// "last argument default" options, and `--log[=<n>]` only takes one attached with `=`.
+#[derive(Clone, Copy, PartialEq)]
+enum OptionValue {
+    None,
+    Required,
+    Optional,
+    Attached,
+}
+
+/// The names an option flag is spelled with, without value placeholders: `-n, --no-verify`
+/// gives `-n` and `--no-verify`, `-t <type>` gives `-t` and `--log[=<n>]` gives `--log`.
+pub fn option_aliases(flag: &str) -> impl Iterator<Item = &str> {
+    flag.split(", ")
+        .map(|alias| alias.split([' ', '=', '[']).next().unwrap_or(alias))
+}
+
+/// Parses `args` like git's parse-options: options may appear anywhere before `--`, short
+/// flags can be bundled (`-rz`), and values can be attached (`-tblob`, `--message=x`) or
+/// separate. Unknown options and missing values print the usage and fail with a
+/// [`CommandExit`] of status 129; `-h` and `--help`, unless the command has options of that
+/// name, print it and fail with one of status 0, which ends the command successfully.
+pub fn parse_args(name: &str, args: &[String]) -> anyhow::Result<ParsedArgs> {
+    let command = find_command(name).unwrap_or_else(|| panic!("{} has no CommandSpec", name));
+    let lookup = |alias: &str| {
+        command
+            .options
+            .iter()
+            .find(|(flag, _)| option_aliases(flag).any(|known| known == alias))
+            .map(|(flag, _)| {
+                let canonical = option_aliases(flag).next().unwrap_or(flag);
+                let value = if flag.contains("[=<") {
+                    OptionValue::Attached
+                } else if flag.contains(" [<") {
+                    OptionValue::Optional
+                } else if flag.contains('<') {
+                    OptionValue::Required
+                } else {
+                    OptionValue::None
+                };
+                (canonical, value)
+            })
+    };
+    let mut parsed = ParsedArgs {
+        options: vec![],
+        operands: vec![],
+        separator: None,
+    };
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--" {
+            parsed.separator = Some(parsed.operands.len());
+            parsed.operands.extend(iter.by_ref().cloned());
+        } else if let Some(long) = arg.strip_prefix("--") {
+            let (alias, inline_value) = match long.split_once('=') {
+                Some((alias, value)) => (alias, Some(value.to_string())),
+                None => (long, None),
+            };
+            let Some((canonical, takes_value)) = lookup(&format!("--{}", alias)) else {
+                if alias == "help" {
+                    return Err(help_requested(command));
+                }
+                return Err(option_error(name, &format!("unknown option `{}'", alias)));
+            };
+            let value = match (takes_value, inline_value) {
+                (OptionValue::None, Some(_)) => {
+                    return Err(option_error(
+                        name,
+                        &format!("option `{}' takes no value", alias),
+                    ));
+                }
+                (_, Some(value)) => Some(value),
+                (OptionValue::Required, None) => match iter.next() {
+                    Some(value) => Some(value.clone()),
+                    None => {
+                        return Err(option_error(
+                            name,
+                            &format!("option `{}' requires a value", alias),
+                        ));
+                    }
+                },
+                (OptionValue::Optional, None) => iter.next().cloned(),
+                (OptionValue::None | OptionValue::Attached, None) => None,
+            };
+            parsed.options.push((canonical, value));
+        } else if let Some(shorts) = arg.strip_prefix('-').filter(|shorts| !shorts.is_empty()) {
+            let mut rest = shorts;
+            while let Some(c) = rest.chars().next() {
+                rest = &rest[c.len_utf8()..];
+                let Some((canonical, takes_value)) = lookup(&format!("-{}", c)) else {
+                    if c == 'h' {
+                        return Err(help_requested(command));
+                    }
+                    return Err(option_error(name, &format!("unknown switch `{}'", c)));
+                };
+                let value = if takes_value == OptionValue::None {
+                    None
+                } else if !rest.is_empty() {
+                    Some(std::mem::take(&mut rest).to_string())
+                } else if takes_value == OptionValue::Attached {
+                    None
+                } else if takes_value == OptionValue::Optional {
+                    iter.next().cloned()
+                } else {
+                    match iter.next() {
+                        Some(value) => Some(value.clone()),
+                        None => {
+                            return Err(option_error(
+                                name,
+                                &format!("switch `{}' requires a value", c),
+                            ));
+                        }
+                    }
+                };
+                parsed.options.push((canonical, value));
+            }
+        } else {
+            parsed.operands.push(arg.clone());
+        }
+    }
+    Ok(parsed)
+}
+
+/// Prints the usage of `command` to stdout, as `help <command>` does, and returns the
+/// successful exit that ends the command.
+fn help_requested(command: &CommandSpec) -> anyhow::Error {
+    print!("{}", command_usage(command));
+    CommandExit { code: 0 }.into()
+}
+
+fn option_error(name: &str, message: &str) -> anyhow::Error {
+    eprintln!("error: {}", message);
+    usage_error(name)
+}
+
+pub fn find_command(name: &str) -> Option<&'static CommandSpec> {
+    COMMANDS.iter().find(|command| command.name == name)
+}
+
+pub fn needs_repository(name: &str) -> bool {
+    find_command(name).is_some_and(|command| command.repository)
+}
+
+/// Whether the command creates a repository instead of working in an existing one.
+pub fn creates_repository(name: &str) -> bool {
+    matches!(name, "init" | "clone")
+}
+
+/// Whether the command deletes or replaces repository data in a way the global `--dry-run`
+/// can report instead.
+pub fn supports_dry_run(name: &str) -> bool {
+    matches!(name, "gc" | "repack" | "push")
+}
+
+/// Whether the command is left out of the command list, completions and suggestions, being
+/// meant for checking the tool rather than for everyday use.
+pub fn is_hidden(name: &str) -> bool {
+    matches!(name, "interop-check")
+}
+
+/// The commands that are listed, in the order of [`COMMANDS`].
+pub fn listed_commands() -> impl Iterator<Item = &'static CommandSpec> {
+    COMMANDS.iter().filter(|command| !is_hidden(command.name))
+}
+
+pub fn is_builtin(name: &str) -> bool {
+    find_command(name).is_some()
+}
+
+/// The builtin commands closest to the mistyped `name`, best first; empty when none is close
+/// enough. Like git, commands `name` is the start of come first, then those within a weighted
+/// edit distance where adding a character costs 1, changing one 2, dropping one 3 and swapping
+/// two neighbours nothing.
+pub fn similar_commands(name: &str) -> Vec<&'static str> {
+    // Git's SIMILARITY_FLOOR.
+    const SIMILARITY_FLOOR: usize = 7;
+    let mut candidates: Vec<(usize, &'static str)> = listed_commands()
+        .map(|command| {
+            let similarity = if command.name.starts_with(name) {
+                1
+            } else {
+                edit_distance(name, command.name) + 1
+            };
+            (similarity, command.name)
+        })
+        .collect();
+    candidates.sort();
+    let Some(&(best, _)) = candidates.first() else {
+        return vec![];
+    };
+    if best >= SIMILARITY_FLOOR {
+        return vec![];
+    }
+    candidates
+        .into_iter()
+        .take_while(|(similarity, _)| *similarity == best)
+        .map(|(_, name)| name)
+        .collect()
+}
+
+/// Git's weighted Damerau-Levenshtein distance for turning `from` into `to`.
+fn edit_distance(from: &str, to: &str) -> usize {
+    const SWAP: usize = 0;
+    const SUBSTITUTION: usize = 2;
+    const INSERTION: usize = 1;
+    const DELETION: usize = 3;
+    let (from, to) = (from.as_bytes(), to.as_bytes());
+    let mut before_previous = vec![0; to.len() + 1];
+    let mut previous: Vec<usize> = (0..=to.len()).map(|j| j * INSERTION).collect();
+    let mut current = vec![0; to.len() + 1];
+    for i in 0..from.len() {
+        current[0] = (i + 1) * DELETION;
+        for j in 0..to.len() {
+            let mut cost = previous[j] + SUBSTITUTION * usize::from(from[i] != to[j]);
+            if i > 0 && j > 0 && from[i - 1] == to[j] && from[i] == to[j - 1] {
+                cost = cost.min(before_previous[j - 1] + SWAP);
+            }
+            cost = cost.min(previous[j + 1] + DELETION);
+            cost = cost.min(current[j] + INSERTION);
+            current[j + 1] = cost;
+        }
+        std::mem::swap(&mut before_previous, &mut previous);
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[to.len()]
+}
+
+pub fn program_name() -> String {
+    env::args()
+        .next()
+        .and_then(|arg0| {
+            Path::new(&arg0)
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+        })
+        .unwrap_or_else(|| "git".to_string())
+}
+
+fn format_options(options: &[(&str, &str)]) -> String {
+    options
+        .iter()
+        .map(|(flag, description)| {
+            // Like git, a flag too long for the column gets its description on the next line.
+            if flag.len() >= 22 {
+                format!("    {}\n    {:<22}{}\n", flag, "", description)
+            } else {
+                format!("    {:<22}{}\n", flag, description)
+            }
+        })
+        .collect()
+}
+
+pub fn command_usage(command: &CommandSpec) -> String {
+    let program = program_name();
+    let mut text = String::new();
+    for (i, usage) in command.usage.iter().enumerate() {
+        let prefix = if i == 0 { "usage:" } else { "   or:" };
+        text.push_str(&format!("{} {} {}\n", prefix, program, usage));
+    }
+    if !command.options.is_empty() {
+        text.push('\n');
+        text.push_str(&format_options(command.options));
+    }
+    text
+}
+
+pub fn general_usage() -> String {
+    let program = program_name();
+    let mut text = format!(
+        "usage: {} [-C <path>] [--git-dir=<path>] [--work-tree=<path>] [--dry-run] <command> \
+         [<args>]\n\n",
+        program
+    );
+    text.push_str(&format_options(GLOBAL_OPTIONS));
+    text.push_str("\nThese are the available commands:\n");
+    for command in listed_commands() {
+        text.push_str(&format!("   {:<20}{}\n", command.name, command.summary));
+    }
+    text.push_str(&format!(
+        "\nSee '{} help <command>' to read about a specific subcommand.\n",
+        program
+    ));
+    text
+}
+
+/// Quotes a path the way git does for human-readable output (`core.quotePath`): paths with
+/// control characters, quotes, backslashes or non-ASCII bytes are wrapped in double quotes with
+/// C-style escapes. Commands offer `-z` for output that is never quoted.
+pub fn quote_path(path: &str) -> String {
+    let needs_quoting = path
+        .bytes()
+        .any(|b| b < 0x20 || b == b'"' || b == b'\\' || b >= 0x7f);
+    if !needs_quoting {
+        return path.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for b in path.bytes() {
+        match b {
+            b'"' => quoted.push_str("\\\""),
+            b'\\' => quoted.push_str("\\\\"),
+            b'\n' => quoted.push_str("\\n"),
+            b'\t' => quoted.push_str("\\t"),
+            0x07 => quoted.push_str("\\a"),
+            0x08 => quoted.push_str("\\b"),
+            0x0b => quoted.push_str("\\v"),
+            0x0c => quoted.push_str("\\f"),
+            b'\r' => quoted.push_str("\\r"),
+            b if !(0x20..0x7f).contains(&b) => quoted.push_str(&format!("\\{:03o}", b)),
+            b => quoted.push(b as char),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Prints the usage of `name` to stderr and returns the error that ends the command with
+/// git's usage error status.
+pub fn usage_error(name: &str) -> anyhow::Error {
+    match find_command(name) {
+        Some(command) => eprint!("{}", command_usage(command)),
+        None => eprint!("{}", general_usage()),
+    }
+    CommandExit {
+        code: USAGE_EXIT_CODE,
+    }
+    .into()
+}
@@ -0,0 +1,130 @@
+mod add;
+mod am;
+mod apply;
+mod archive;
+mod backup;
+mod branch;
+mod cat_file;
+mod checkout;
+mod cherry_pick;
+mod clone;
+mod commit;
+mod commit_graph;
+mod commit_tree;
+mod completions;
+mod config;
+mod diff;
+mod difftool;
+mod fetch;
+mod fmt_merge_msg;
+mod format_patch;
+mod fsck;
+mod gc;
+mod hash_object;
+mod help;
+mod init;
+mod interop_check;
+mod log;
+mod ls_tree;
+mod merge;
+mod merge_base;
+mod merge_file;
+mod merge_tree;
+mod mergetool;
+mod migrate;
+mod prompt;
+mod push;
+mod repack;
+mod restore;
+mod rev_list;
+mod rev_parse;
+mod revert;
+mod show_ref;
+mod stats;
+mod status;
+mod symbolic_ref;
+mod tag;
+mod unpack_file;
+mod update_ref;
+mod update_server_info;
+mod verify_snapshot;
+mod verify_tag;
+mod write_tree;
+
+use crate::repository::CommandExit;
+use crate::usage;
+use anyhow::anyhow;
+
+/// Runs the builtin command `name`; every command has its own module with a `run` function
+/// taking the arguments after the command name. A command that ends with an exit code, as on
+/// a usage error, fails with a [`CommandExit`]; one with code 0, as for `-h`, is a success.
+pub fn run(name: &str, args: &[String]) -> anyhow::Result<()> {
+    usage::record(name, || match run_builtin(name, args) {
+        Err(err)
+            if err
+                .downcast_ref::<CommandExit>()
+                .is_some_and(|exit| exit.code == 0) =>
+        {
+            Ok(())
+        }
+        result => result,
+    })
+}
+
+fn run_builtin(name: &str, args: &[String]) -> anyhow::Result<()> {
+    match name {
+        "init" => init::run(args),
+        "cat-file" => cat_file::run(args),
+        "unpack-file" => unpack_file::run(args),
+        "hash-object" => hash_object::run(args),
+        "ls-tree" => ls_tree::run(args),
+        "add" => add::run(args),
+        "write-tree" => write_tree::run(args),
+        "status" => status::run(args),
+        "prompt" => prompt::run(args),
+        "diff" => diff::run(args),
+        "difftool" => difftool::run(args),
+        "mergetool" => mergetool::run(args),
+        "merge-file" => merge_file::run(args),
+        "merge" => merge::run(args),
+        "merge-base" => merge_base::run(args),
+        "merge-tree" => merge_tree::run(args),
+        "fmt-merge-msg" => fmt_merge_msg::run(args),
+        "apply" => apply::run(args),
+        "am" => am::run(args),
+        "format-patch" => format_patch::run(args),
+        "archive" => archive::run(args),
+        "cherry-pick" => cherry_pick::run(args),
+        "revert" => revert::run(args),
+        "commit" => commit::run(args),
+        "commit-tree" => commit_tree::run(args),
+        "checkout" => checkout::run(args),
+        "clone" => clone::run(args),
+        "fetch" => fetch::run(args),
+        "push" => push::run(args),
+        "log" => log::run(args),
+        "rev-list" => rev_list::run(args),
+        "branch" => branch::run(args),
+        "rev-parse" => rev_parse::run(args),
+        "update-ref" => update_ref::run(args),
+        "symbolic-ref" => symbolic_ref::run(args),
+        "show-ref" => show_ref::run(args),
+        "tag" => tag::run(args),
+        "verify-tag" => verify_tag::run(args),
+        "verify-snapshot" => verify_snapshot::run(args),
+        "backup" => backup::run(args),
+        "restore" => restore::run(args),
+        "interop-check" => interop_check::run(args),
+        "fsck" => fsck::run(args),
+        "commit-graph" => commit_graph::run(args),
+        "stats" => stats::run(args),
+        "gc" => gc::run(args),
+        "migrate" => migrate::run(args),
+        "repack" => repack::run(args),
+        "update-server-info" => update_server_info::run(args),
+        "config" => config::run(args),
+        "completions" => completions::run(args),
+        "help" => help::run(args),
+        _ => Err(anyhow!("'{}' is not a builtin command", name)),
+    }
+}
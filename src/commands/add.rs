@@ -0,0 +1,188 @@
+use crate::cli;
+use crate::ignore::IgnoreRules;
+use crate::index::{self, Index, IndexEntry};
+use crate::object_storage::ObjectStorage;
+use crate::patch_mode::{self, FileChange, Session, Side};
+use crate::pathspec::PathspecSet;
+use crate::repository::CommandExit;
+use crate::work_tree;
+use anyhow::anyhow;
+use std::fs;
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("add", args)?;
+    let paths = args.operands.clone();
+    if args.has("-p") || args.has("--patch-script") {
+        return add_patch(
+            &PathspecSet::from_args(&paths)?,
+            args.value("--patch-script"),
+        );
+    }
+    if paths.is_empty() {
+        eprintln!("Nothing specified, nothing added.");
+        eprintln!(
+            "hint: Maybe you wanted to say '{} add .'?",
+            cli::program_name()
+        );
+        return Ok(());
+    }
+    let pathspecs = PathspecSet::from_args(&paths)?;
+    let work_tree = ObjectStorage::work_tree();
+    let (mut index, lock) = Index::read_locked()?;
+    let ignore = if args.has("-f") {
+        None
+    } else {
+        Some(IgnoreRules::new()?)
+    };
+    let mut files: Vec<String> = vec![];
+    work_tree::collect_files(&work_tree, "", &pathspecs, ignore.as_ref(), &mut files)?;
+    // Ignore rules only keep untracked files out; tracked ones are updated regardless.
+    files.extend(
+        index
+            .entries
+            .iter()
+            .filter(|entry| pathspecs.matches(&entry.path, false))
+            .filter(|entry| {
+                fs::symlink_metadata(work_tree.join(&entry.path))
+                    .is_ok_and(|metadata| !metadata.is_dir())
+            })
+            .map(|entry| entry.path.clone()),
+    );
+    files.sort();
+    files.dedup();
+    let removed: Vec<String> = index
+        .entries
+        .iter()
+        .filter(|entry| pathspecs.matches(&entry.path, false))
+        .filter(|entry| fs::symlink_metadata(work_tree.join(&entry.path)).is_err())
+        .map(|entry| entry.path.clone())
+        .collect();
+    let mut ignored = vec![];
+    for path in &paths {
+        let spec = PathspecSet::from_args(std::slice::from_ref(path))?;
+        if files
+            .iter()
+            .chain(&removed)
+            .any(|file| spec.matches(file, false))
+        {
+            continue;
+        }
+        // A path that exists but only holds ignored files is not an error, unless the path
+        // itself (or a directory it is in) is ignored.
+        let metadata = spec.literal_path().and_then(|literal| {
+            Some((literal, fs::symlink_metadata(work_tree.join(literal)).ok()?))
+        });
+        let Some((literal, metadata)) = metadata else {
+            Err(anyhow!("pathspec '{}' did not match any files", path))?
+        };
+        if ignore.is_some()
+            && let Some(ignored_path) = IgnoreRules::ignored_prefix(literal, metadata.is_dir())?
+        {
+            ignored.push(ignored_path);
+        }
+    }
+
+    for path in files {
+        let file = work_tree.join(&path);
+        let metadata = fs::symlink_metadata(&file)?;
+        let staged = index.find(&path);
+        if staged.is_some_and(|entry| entry.is_up_to_date(&metadata)) {
+            continue;
+        }
+        let mode = staged.map(|entry| index::staged_file_mode(&metadata, entry.mode));
+        let sha = index::hash_work_tree_file(&file, &metadata, true)?;
+        let entry = IndexEntry::from_file(&path, &file, sha)?;
+        index.add(IndexEntry {
+            mode: mode.unwrap_or(entry.mode),
+            ..entry
+        });
+    }
+    for path in removed {
+        index.remove(&path);
+    }
+    index.write(lock)?;
+    if !ignored.is_empty() {
+        eprintln!("The following paths are ignored by one of your .gitignore files:");
+        for path in &ignored {
+            eprintln!("{}", path);
+        }
+        eprintln!("hint: Use -f if you really want to add them.");
+        Err(CommandExit { code: 1 })?
+    }
+    Ok(())
+}
+
+/// `add -p`: offers the changes of the tracked files `pathspecs` matches hunk by hunk and
+/// stages the ones taken. Answers come from `script` instead of stdin when it is given.
+fn add_patch(pathspecs: &PathspecSet, script: Option<&str>) -> anyhow::Result<()> {
+    let work_tree = ObjectStorage::work_tree();
+    let (mut index, lock) = Index::read_locked()?;
+    let mut session = Session::new(&patch_mode::STAGE, script)?;
+    let mut changed = false;
+    let mut offered = false;
+    let entries: Vec<IndexEntry> = index
+        .entries
+        .iter()
+        .filter(|entry| entry.stage == 0 && pathspecs.matches(&entry.path, false))
+        .filter(|entry| index::mode_permission(entry.mode).is_some())
+        .cloned()
+        .collect();
+    for entry in entries {
+        if session.has_quit() {
+            break;
+        }
+        let file = work_tree.join(&entry.path);
+        if fs::symlink_metadata(&file).is_ok() && entry.matches_file(&file)? {
+            continue;
+        }
+        let new = patch_mode::work_tree_version(&entry.path, entry.mode)?;
+        // A file replaced by a symlink, or the other way round, is not offered.
+        if new.as_ref().is_some_and(|(mode, _)| {
+            (*mode == index::MODE_SYMLINK) != (entry.mode == index::MODE_SYMLINK)
+        }) {
+            continue;
+        }
+        let old = ObjectStorage::read_object(&entry.sha)?.1;
+        offered = true;
+        let choice = session.choose(&FileChange {
+            path: &entry.path,
+            old: Side {
+                mode: entry.mode,
+                content: &old,
+            },
+            new: new.as_ref().map(|(mode, content)| Side {
+                mode: *mode,
+                content,
+            }),
+        })?;
+        if choice.deletion {
+            index.remove(&entry.path);
+            changed = true;
+            continue;
+        }
+        let Some((new_mode, new_content)) = &new else {
+            continue;
+        };
+        if !choice.mode && choice.content.is_none() {
+            continue;
+        }
+        let mode = if choice.mode { *new_mode } else { entry.mode };
+        let content = choice.content.unwrap_or(old);
+        let sha = ObjectStorage::hash_object("blob", &content, true, false)?;
+        // Stat data is only kept when the file is staged as it is.
+        let staged = match mode == *new_mode && content == *new_content {
+            true => IndexEntry::from_file(&entry.path, &file, sha)?,
+            false => IndexEntry::without_stat(&entry.path, mode, sha),
+        };
+        index.add(staged);
+        changed = true;
+    }
+    session.finish()?;
+    if !offered {
+        eprintln!("No changes.");
+    }
+    if changed {
+        index.write(lock)?;
+    }
+    Ok(())
+}
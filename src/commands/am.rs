@@ -0,0 +1,116 @@
+use super::apply::{self, Target};
+use super::commit;
+use crate::cli;
+use crate::config::Config;
+use crate::diff;
+use crate::gc;
+use crate::index::Index;
+use crate::mail::{self, MailInfo};
+use crate::object_storage::{Commit, ObjectStorage, Signature};
+use crate::patch;
+use crate::refs;
+use crate::repository::CommandExit;
+use crate::search_index;
+use anyhow::anyhow;
+use std::fs;
+use std::io::{self, Read};
+
+/// Applies the patches mailed in the given mbox files, or stdin, each as a commit by the
+/// author, at the date, and with the subject and message of its mail. The patches go to the
+/// index and the working tree, and with `-3` fall back to a three-way merge like `apply -3`.
+/// CRs at the end of lines are removed unless `--keep-cr` or `am.keepCR` asks to keep them.
+///
+/// Unlike git, no state is kept to resume from: a patch that does not apply stops `am` with
+/// the ones before it committed, to be started again with the rest once it is fixed.
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("am", args)?;
+    let keep_cr = match args.last_of(&["--keep-cr", "--no-keep-cr"]) {
+        Some(option) => option == "--keep-cr",
+        None => Config::get()?.bool("am", "keepCR").unwrap_or(false),
+    };
+    let three_way = args.has("-3");
+    let mut input = vec![];
+    if args.operands.is_empty() {
+        io::stdin().read_to_end(&mut input)?;
+    }
+    for name in &args.operands {
+        input.extend(fs::read(name).map_err(|err| anyhow!("could not open '{}': {}", name, err))?);
+    }
+    let messages = mail::split_mbox(&input, keep_cr);
+    if messages.is_empty() {
+        Err(anyhow!("Patch format detection failed."))?
+    }
+    ensure_clean_index()?;
+    for (number, message) in messages.iter().enumerate() {
+        let info = MailInfo::parse(message)?;
+        println!("Applying: {}", info.subject);
+        let patches = patch::parse(&info.patch)?;
+        if patches.is_empty() {
+            eprintln!("Patch is empty.");
+            return Err(stop(number, &info));
+        }
+        match apply::apply_patches(&patches, Target::Both, three_way)? {
+            Some(conflicted) if conflicted.is_empty() => {}
+            Some(conflicted) => {
+                for path in &conflicted {
+                    eprintln!("U {}", path);
+                }
+                eprintln!("error: Failed to merge in the changes.");
+                return Err(stop(number, &info));
+            }
+            None => return Err(stop(number, &info)),
+        }
+        commit_patch(&info)?;
+    }
+    search_index::update()?;
+    gc::auto_gc()
+}
+
+/// Patches are applied on top of `HEAD`, so changes staged since would end up in their
+/// commits.
+fn ensure_clean_index() -> anyhow::Result<()> {
+    let tree = ObjectStorage::write_index_tree(&Index::read()?)?;
+    let head_tree = match refs::resolve_ref("HEAD")?.1 {
+        Some(head) => Some(ObjectStorage::peel_to_tree(&head)?),
+        None => None,
+    };
+    let dirty: Vec<String> = diff::diff_trees(head_tree.as_ref(), Some(&tree))?
+        .into_iter()
+        .map(|change| change.path)
+        .collect();
+    if !dirty.is_empty() {
+        Err(anyhow!(
+            "Dirty index: cannot apply patches (dirty: {})",
+            dirty.join(" ")
+        ))?
+    }
+    Ok(())
+}
+
+fn commit_patch(info: &MailInfo) -> anyhow::Result<()> {
+    let committer = Signature::committer()?;
+    let (time, tz_offset) = info.date.unwrap_or((committer.time, committer.tz_offset));
+    let author = Signature {
+        name: info.author_name.clone(),
+        email: info.author_email.clone(),
+        time,
+        tz_offset,
+    };
+    let parent = refs::resolve_ref("HEAD")?.1;
+    let commit = Commit {
+        tree: ObjectStorage::write_index_tree(&Index::read()?)?,
+        parents: parent.into_iter().collect(),
+        author,
+        committer,
+        message: commit::cleanup_message(&format!("{}\n\n{}", info.subject, info.body)),
+    };
+    let sha = commit.write_to_object_storage()?;
+    refs::update_ref("HEAD", &sha, Some(&parent.unwrap_or([0; 20])))
+}
+
+/// Reports the patch that could not be applied, numbered from 1 as git does, and returns the
+/// error that ends `am` with status 128.
+fn stop(number: usize, info: &MailInfo) -> anyhow::Error {
+    eprintln!("Patch failed at {:04} {}", number + 1, info.subject);
+    CommandExit { code: 128 }.into()
+}
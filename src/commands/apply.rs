@@ -0,0 +1,361 @@
+use super::checkout;
+use crate::cli;
+use crate::filters::Filters;
+use crate::index::{self, Index, IndexEntry};
+use crate::merge_file::{self, Labels};
+use crate::object_storage::{ObjectStorage, Sha};
+use crate::patch::{self, FilePatch};
+use crate::repository::CommandExit;
+use std::fs;
+use std::io::{self, Read};
+
+/// Where the patch is applied: the working tree, the index, or both (`--index`), in which
+/// case the two have to agree on each file the patch touches.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Target {
+    WorkTree,
+    Index,
+    Both,
+}
+
+impl Target {
+    fn work_tree(self) -> bool {
+        self != Target::Index
+    }
+
+    fn index(self) -> bool {
+        self != Target::WorkTree
+    }
+}
+
+/// What applying the patch of one file comes to, once all of them are known to apply.
+enum Outcome {
+    /// The file gets `content` with `mode`, or is deleted when that is `None`; a renamed
+    /// file's old path goes away.
+    Write {
+        old_path: Option<String>,
+        path: Option<String>,
+        content: Option<(Vec<u8>, u32)>,
+    },
+    /// The three-way merge left conflicts: `content` has the conflict markers and `versions`
+    /// are the index stages.
+    Conflict {
+        path: String,
+        content: Vec<u8>,
+        mode: u32,
+        versions: Vec<(u16, u32, Sha)>,
+    },
+}
+
+/// Applies git-style patches from the given files, or from stdin, to the working tree, the
+/// index (`--cached`) or both (`--index`). Nothing is changed unless every file patch
+/// applies. With `--3way`, which implies `--index`, a file patch that does not apply is
+/// applied to the blob its `index` line names instead and merged with the current version;
+/// conflicts are left in the file and the index, and the exit status is 1.
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("apply", args)?;
+    let three_way = args.has("-3");
+    let target = if args.has("--cached") {
+        Target::Index
+    } else if args.has("--index") || three_way {
+        Target::Both
+    } else {
+        Target::WorkTree
+    };
+    let mut input = vec![];
+    if args.operands.is_empty() {
+        io::stdin().read_to_end(&mut input)?;
+    }
+    for name in &args.operands {
+        match fs::read(name) {
+            Ok(content) => input.extend(content),
+            Err(err) => {
+                let message = format!("can't open patch '{}': {}", name, describe(&err));
+                return Err(fail(&message));
+            }
+        }
+    }
+    let patches = patch::parse(&input)?;
+    if patches.is_empty() {
+        return Err(fail("No valid patches in input"));
+    }
+
+    match apply_patches(&patches, target, three_way)? {
+        None => Err(CommandExit { code: 1 })?,
+        Some(conflicted) => {
+            for path in &conflicted {
+                eprintln!("U {}", path);
+            }
+            if !conflicted.is_empty() {
+                Err(CommandExit { code: 1 })?
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Applies `patches` to `target`, if every file patch applies; why one does not is reported
+/// and gives `None`. Otherwise returns the paths a three-way merge left conflicts in.
+pub fn apply_patches(
+    patches: &[FilePatch],
+    target: Target,
+    three_way: bool,
+) -> anyhow::Result<Option<Vec<String>>> {
+    let (mut index, lock) = Index::read_locked()?;
+    let mut outcomes = vec![];
+    let mut failed = false;
+    for file in patches {
+        match prepare(file, &index, target, three_way)? {
+            Some(outcome) => outcomes.push(outcome),
+            None => failed = true,
+        }
+    }
+    if failed {
+        return Ok(None);
+    }
+
+    let work_tree = ObjectStorage::work_tree();
+    let mut conflicted = vec![];
+    for outcome in outcomes {
+        match outcome {
+            Outcome::Write {
+                old_path,
+                path,
+                content,
+            } => {
+                if let Some(old_path) = old_path.filter(|old_path| path.as_ref() != Some(old_path))
+                {
+                    if target.work_tree() {
+                        checkout::remove_work_tree_file(&old_path)?;
+                    }
+                    index.remove(&old_path);
+                }
+                let (Some(path), Some((content, mode))) = (path, content) else {
+                    continue;
+                };
+                let sha = ObjectStorage::hash_object("blob", &content, target.index(), false)?;
+                if target.work_tree() {
+                    write_work_tree_file(&path, &content, mode, &sha)?;
+                }
+                if target == Target::Both {
+                    let file = work_tree.join(&path);
+                    index.add(IndexEntry {
+                        mode,
+                        ..IndexEntry::from_file(&path, &file, sha)?
+                    });
+                } else if target == Target::Index {
+                    index.add(IndexEntry::without_stat(&path, mode, sha));
+                }
+            }
+            Outcome::Conflict {
+                path,
+                content,
+                mode,
+                versions,
+            } => {
+                if target.work_tree() {
+                    write_work_tree_file(&path, &content, mode, &[0; 20])?;
+                }
+                index.add_conflict(&path, &versions);
+                conflicted.push(path);
+            }
+        }
+    }
+    if target.index() {
+        index.write(lock)?;
+    }
+    Ok(Some(conflicted))
+}
+
+/// Works out what the patch of one file does, reporting why when it does not apply.
+fn prepare(
+    file: &FilePatch,
+    index: &Index,
+    target: Target,
+    three_way: bool,
+) -> anyhow::Result<Option<Outcome>> {
+    let path = file.path();
+    let does_not_apply = || {
+        eprintln!("error: {}: patch does not apply", path);
+        Ok(None)
+    };
+    if file.binary {
+        eprintln!(
+            "error: cannot apply binary patch to '{}' without full index line",
+            path
+        );
+        return does_not_apply();
+    }
+    let work_tree = ObjectStorage::work_tree();
+    let (current, current_mode) = match &file.old_path {
+        None => {
+            if target.index() && index.find(path).is_some() {
+                eprintln!("error: {}: already exists in index", path);
+                return Ok(None);
+            }
+            if target.work_tree() && fs::symlink_metadata(work_tree.join(path)).is_ok() {
+                eprintln!("error: {}: already exists in working directory", path);
+                return Ok(None);
+            }
+            (vec![], None)
+        }
+        Some(old_path) => {
+            let file = work_tree.join(old_path);
+            if target.index() {
+                let Some(entry) = index.find(old_path).filter(|entry| entry.stage == 0) else {
+                    eprintln!("error: {}: does not exist in index", old_path);
+                    return Ok(None);
+                };
+                if target == Target::Both && !entry.matches_file(&file)? {
+                    eprintln!("error: {}: does not match index", old_path);
+                    return Ok(None);
+                }
+                (ObjectStorage::read_object(&entry.sha)?.1, Some(entry.mode))
+            } else {
+                let metadata = match fs::symlink_metadata(&file) {
+                    Ok(metadata) => metadata,
+                    Err(err) => {
+                        eprintln!("error: {}: {}", old_path, describe(&err));
+                        return Ok(None);
+                    }
+                };
+                let content = index::work_tree_content(&file, &metadata)?;
+                (content, Some(index::file_mode(&metadata)))
+            }
+        }
+    };
+    let mode = file
+        .new_mode
+        .or(current_mode)
+        .unwrap_or(index::MODE_REGULAR);
+
+    let line = match patch::apply(&current, &file.hunks) {
+        Ok(result) => {
+            if file.new_path.is_none() && !result.is_empty() {
+                eprintln!("error: removal patch leaves file contents");
+                return does_not_apply();
+            }
+            return Ok(Some(Outcome::Write {
+                old_path: file.old_path.clone(),
+                path: file.new_path.clone(),
+                content: file.new_path.as_ref().map(|_| (result, mode)),
+            }));
+        }
+        Err(line) => line,
+    };
+    eprintln!("error: patch failed: {}:{}", path, line);
+    if !three_way || file.new_path.is_none() {
+        return does_not_apply();
+    }
+
+    eprintln!("Falling back to three-way merge...");
+    let Some(base_sha) = preimage_blob(file)? else {
+        eprintln!("error: repository lacks the necessary blob to fall back on 3-way merge.");
+        return does_not_apply();
+    };
+    let base = ObjectStorage::read_object(&base_sha)?.1;
+    let Ok(theirs) = patch::apply(&base, &file.hunks) else {
+        return does_not_apply();
+    };
+    let labels = Labels {
+        ours: "ours",
+        base: "base",
+        theirs: "theirs",
+    };
+    let merged = merge_file::merge_file(path, &base, &current, &theirs, &labels)?;
+    if merged.conflicts == 0 {
+        eprintln!("Applied patch to '{}' cleanly.", path);
+        return Ok(Some(Outcome::Write {
+            old_path: file.old_path.clone(),
+            path: file.new_path.clone(),
+            content: Some((merged.content, mode)),
+        }));
+    }
+    eprintln!("Applied patch to '{}' with conflicts.", path);
+    let base_mode = file.old_mode.or(current_mode).unwrap_or(mode);
+    let ours_sha = ObjectStorage::hash_object("blob", &current, true, false)?;
+    let theirs_sha = ObjectStorage::hash_object("blob", &theirs, true, false)?;
+    Ok(Some(Outcome::Conflict {
+        path: path.to_string(),
+        content: merged.content,
+        mode,
+        versions: vec![
+            (1, base_mode, base_sha),
+            (2, current_mode.unwrap_or(mode), ours_sha),
+            (3, mode, theirs_sha),
+        ],
+    }))
+}
+
+/// The blob the patch was made against, from the abbreviated id on its `index` line.
+fn preimage_blob(file: &FilePatch) -> anyhow::Result<Option<Sha>> {
+    let Some(abbreviated) = &file.old_blob else {
+        return Ok(None);
+    };
+    if abbreviated.bytes().all(|byte| byte == b'0') {
+        return Ok(None);
+    }
+    let mut blobs = vec![];
+    for sha in ObjectStorage::find_objects_by_prefix(abbreviated)? {
+        if ObjectStorage::open_object(&sha)?.object_type == "blob" {
+            blobs.push(sha);
+        }
+    }
+    Ok(match blobs.as_slice() {
+        [sha] => Some(*sha),
+        _ => None,
+    })
+}
+
+/// Writes `content`, the blob `sha`, to the file at `path` with `mode`, creating its
+/// directories.
+pub fn write_work_tree_file(
+    path: &str,
+    content: &[u8],
+    mode: u32,
+    sha: &Sha,
+) -> anyhow::Result<()> {
+    let file = ObjectStorage::work_tree().join(path);
+    if let Some(parent) = file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::symlink_metadata(&file).is_ok() {
+        fs::remove_file(&file)?;
+    }
+    #[cfg(unix)]
+    if mode == index::MODE_SYMLINK {
+        std::os::unix::fs::symlink(String::from_utf8_lossy(content).as_ref(), &file)?;
+        return Ok(());
+    }
+    fs::write(
+        &file,
+        Filters::for_file(&file)?.to_work_tree(content.to_vec(), sha),
+    )?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let permissions = if mode == index::MODE_EXECUTABLE {
+            0o755
+        } else {
+            0o644
+        };
+        fs::set_permissions(&file, fs::Permissions::from_mode(permissions))?;
+    }
+    Ok(())
+}
+
+/// An I/O error the way git words it, e.g. `No such file or directory`.
+fn describe(err: &io::Error) -> String {
+    let message = err.to_string();
+    match message.split_once(" (os error") {
+        Some((message, _)) => message.to_string(),
+        None => message,
+    }
+}
+
+/// Reports a problem with the input like git: an `error:` line, and returns the error that
+/// ends the command with exit status 128.
+fn fail(message: &str) -> anyhow::Error {
+    eprintln!("error: {}", message);
+    CommandExit { code: 128 }.into()
+}
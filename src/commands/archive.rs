@@ -0,0 +1,95 @@
+use crate::archive::{self, ArchiveOptions};
+use crate::cli;
+use crate::config::Config;
+use crate::object_storage::{self, GitObject, ObjectStorage};
+use crate::revision;
+use anyhow::anyhow;
+use flate2::Compression;
+use flate2::GzBuilder;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const FORMATS: &[&str] = &["tar", "tgz", "tar.gz"];
+
+/// Writes an archive of a tree to stdout or `-o <file>`. With `--mtime` the entries get that
+/// time instead of the committer time, which also makes the archive of a bare tree the same
+/// from one run to the next.
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("archive", args)?;
+    if args.has("-l") {
+        for format in FORMATS {
+            println!("{}", format);
+        }
+        return Ok(());
+    }
+    let [tree_ish] = args.operands.as_slice() else {
+        return Err(cli::usage_error("archive"));
+    };
+    let output = args.value("-o");
+    let format = match (args.value("--format"), output) {
+        (Some(format), _) => format,
+        // Without --format, the output's file name tells.
+        (None, Some(output)) => FORMATS
+            .iter()
+            .find(|format| output.ends_with(&format!(".{}", format)))
+            .copied()
+            .unwrap_or("tar"),
+        (None, None) => "tar",
+    };
+    if !FORMATS.contains(&format) {
+        Err(anyhow!("Unknown archive format '{}'", format))?
+    }
+
+    let sha = revision::resolve(tree_ish)?;
+    let peeled = ObjectStorage::peel_tag(&sha)?;
+    let (tree, commit) = match GitObject::from_sha(&peeled)? {
+        GitObject::Commit(commit) => (commit.tree, Some((peeled, commit.committer.time))),
+        GitObject::Tree(_) => (peeled, None),
+        _ => Err(anyhow!("not a tree object: {}", tree_ish))?,
+    };
+    let mtime = match args.value("--mtime") {
+        Some(mtime) => parse_mtime(mtime)?,
+        None => match commit {
+            Some((_, time)) => time,
+            None => SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64,
+        },
+    };
+    let umask = match Config::get()?.value("tar", "umask") {
+        Some(umask) => u32::from_str_radix(&umask, 8)
+            .map_err(|_| anyhow!("bad numeric config value '{}' for 'tar.umask'", umask))?,
+        None => 0o002,
+    };
+    let options = ArchiveOptions {
+        prefix: args.value("--prefix").unwrap_or("").to_string(),
+        mtime,
+        commit: commit.map(|(sha, _)| sha),
+        umask,
+    };
+
+    let mut out: Box<dyn Write> = match output {
+        Some(path) => {
+            Box::new(BufWriter::new(File::create(path).map_err(|err| {
+                anyhow!("could not create archive file '{}': {}", path, err)
+            })?))
+        }
+        None => Box::new(BufWriter::new(std::io::stdout().lock())),
+    };
+    if format == "tar" {
+        archive::write_tar(&tree, &options, &mut out)?;
+    } else {
+        // The gzip header gets no time or file name, so that it is reproducible too.
+        let mut gzip = GzBuilder::new().mtime(0).write(out, Compression::default());
+        archive::write_tar(&tree, &options, &mut gzip)?;
+        gzip.finish()?.flush()?;
+    }
+    Ok(())
+}
+
+/// A time for `--mtime`: seconds since the epoch, or a date as `GIT_COMMITTER_DATE` takes.
+fn parse_mtime(mtime: &str) -> anyhow::Result<i64> {
+    if let Ok(seconds) = mtime.trim_start_matches('@').parse::<i64>() {
+        return Ok(seconds);
+    }
+    Ok(object_storage::parse_date(mtime)?.0)
+}
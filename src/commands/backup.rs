@@ -0,0 +1,67 @@
+use crate::backup::Backup;
+use crate::cli;
+use crate::index::Index;
+use crate::object_storage::{ObjectStorage, pack};
+use crate::refs::{self, RefValue};
+use crate::repack::{self, RepackOptions};
+use anyhow::anyhow;
+use std::fs;
+use std::io::ErrorKind;
+
+/// Writes the repository to one file that `restore` turns back into a working repository: the
+/// config, `HEAD` and every ref, the index, and a pack of everything they reach. Unlike a
+/// bundle it keeps the config, so remotes and branch tracking come back too. Changes in the
+/// working tree that are not staged, reflogs and hooks are left out.
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("backup", args)?;
+    let [file] = args.operands.as_slice() else {
+        return Err(cli::usage_error("backup"));
+    };
+    let git_dir = ObjectStorage::git_dir();
+    let mut backup = Backup::default();
+    let config = match fs::read(git_dir.join("config")) {
+        Ok(config) => config,
+        Err(err) if err.kind() == ErrorKind::NotFound => vec![],
+        Err(err) => Err(err)?,
+    };
+    backup.add("config", config);
+
+    let mut names = vec!["HEAD".to_string()];
+    names.extend(refs::list_refs()?.into_iter().map(|(name, _)| name));
+    let mut ref_lines = String::new();
+    for name in &names {
+        match refs::read_ref(name)? {
+            Some(RefValue::Direct(sha)) => ref_lines.push_str(&format!(
+                "{} {}\n",
+                ObjectStorage::sha_to_hex_string(&sha),
+                name
+            )),
+            Some(RefValue::Symbolic(target)) => {
+                ref_lines.push_str(&format!("ref: {} {}\n", target, name))
+            }
+            None => (),
+        }
+    }
+    backup.add("refs", ref_lines.into_bytes());
+
+    if !ObjectStorage::has_work_tree() {
+        backup.add("bare", vec![]);
+    } else if Index::path().exists() {
+        backup.add("index", fs::read(Index::path())?);
+    }
+    let objects = repack::reachable_objects(|_| false)?;
+    if !objects.is_empty() {
+        let options = RepackOptions::new(false, false, true)?;
+        let (data, _) = pack::build_pack(&objects, options.window, options.depth)?;
+        backup.add("pack", data);
+    }
+    fs::write(file, backup.serialize())
+        .map_err(|err| anyhow!("could not write '{}': {}", file, err))?;
+    eprintln!(
+        "Backed up {} refs and {} objects to '{}'",
+        names.len() - 1,
+        objects.len(),
+        file
+    );
+    Ok(())
+}
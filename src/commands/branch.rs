@@ -0,0 +1,74 @@
+use crate::cli;
+use crate::commit_walk::ReachabilityFilter;
+use crate::object_storage::{ObjectStorage, Sha};
+use crate::refglob::{self, Excludes};
+use crate::refs;
+use crate::revision;
+use anyhow::anyhow;
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("branch", args)?;
+    let (name, start_point) = match args.operands.as_slice() {
+        [] => {
+            let filter = ReachabilityFilter::new(
+                &args.values("--contains"),
+                &args.values("--no-contains"),
+                &args.values_or("--merged", "HEAD"),
+                &args.values_or("--no-merged", "HEAD"),
+            )?;
+            let current = refs::current_branch()?;
+            let excludes = Excludes::from_patterns(&args.values("--exclude"));
+            let branches: Vec<(String, Sha)> =
+                refglob::listed_refs(Some("refs/heads/"), &args.values("--glob"), &excludes)?;
+            let tips: Vec<Sha> = branches.iter().map(|(_, sha)| *sha).collect();
+            for ((branch, _), selected) in branches.iter().zip(filter.select(&tips)?) {
+                if selected {
+                    let full_name = format!("refs/heads/{}", branch);
+                    let marker = if current.as_deref() == Some(&full_name) {
+                        '*'
+                    } else {
+                        ' '
+                    };
+                    println!("{} {}", marker, branch);
+                }
+            }
+            return Ok(());
+        }
+        _ if [
+            "--contains",
+            "--no-contains",
+            "--merged",
+            "--no-merged",
+            "--glob",
+            "--exclude",
+        ]
+        .iter()
+        .any(|filter| args.has(filter)) =>
+        {
+            return Err(cli::usage_error("branch"));
+        }
+        [name] => (name, "HEAD"),
+        [name, start_point] => (name, start_point.as_str()),
+        _ => return Err(cli::usage_error("branch")),
+    };
+    let full_name = format!("refs/heads/{}", name);
+    refs::check_ref_format(&full_name)
+        .map_err(|_| anyhow!("'{}' is not a valid branch name", name))?;
+    if refs::read_ref(&full_name)?.is_some() {
+        Err(anyhow!("a branch named '{}' already exists", name))?
+    }
+    let sha = revision::resolve(start_point).map_err(|_| {
+        // On a branch without commits yet, git names the branch rather than `HEAD`.
+        let name = match refs::resolve_ref(start_point) {
+            Ok((target, None)) if start_point == "HEAD" => target
+                .strip_prefix("refs/heads/")
+                .unwrap_or(&target)
+                .to_string(),
+            _ => start_point.to_string(),
+        };
+        anyhow!("not a valid object name: '{}'", name)
+    })?;
+    let sha = ObjectStorage::peel_to_commit(&sha)
+        .map_err(|_| anyhow!("not a valid branch point: '{}'", start_point))?;
+    refs::update_ref(&full_name, &sha, Some(&[0; 20]))
+}
@@ -0,0 +1,213 @@
+use crate::cli;
+use crate::object_storage::{GitObject, ObjectStorage, Sha, TreeEntryPermission, pack};
+use crate::repository::CommandExit;
+use crate::revision;
+use anyhow::anyhow;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+/// How many bytes of object content batch mode keeps around for objects asked for again.
+const CACHE_LIMIT: usize = 32 << 20;
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("cat-file", args)?;
+    let modes = ["-t", "-s", "-e", "-p", "--batch", "--batch-check"];
+    let selected: Vec<&str> = modes.into_iter().filter(|mode| args.has(mode)).collect();
+    if args.has("--batch-all-objects") {
+        let ([mode @ ("--batch" | "--batch-check")], []) =
+            (selected.as_slice(), args.operands.as_slice())
+        else {
+            return Err(cli::usage_error("cat-file"));
+        };
+        return batch_all_objects(*mode == "--batch", args.has("--unordered"));
+    }
+    if let ([mode @ ("--batch" | "--batch-check")], []) =
+        (selected.as_slice(), args.operands.as_slice())
+    {
+        return batch(*mode == "--batch");
+    }
+    let ([mode], [object]) = (selected.as_slice(), args.operands.as_slice()) else {
+        return Err(cli::usage_error("cat-file"));
+    };
+    let sha = revision::resolve(object);
+    if *mode == "-e" {
+        // A `<revision>:<path>` whose path is not there is an error, as in git.
+        let sha = match sha {
+            Err(err) if object.contains(':') => return Err(err),
+            sha => sha,
+        };
+        let exists = sha.is_ok_and(|sha| ObjectStorage::open_object(&sha).is_ok());
+        return match exists {
+            true => Ok(()),
+            false => Err(CommandExit { code: 1 })?,
+        };
+    }
+    // A `<revision>:<path>` says which of its parts is wrong.
+    let sha = sha.map_err(|err| match object.contains(':') {
+        true => err,
+        false => anyhow!("Not a valid object name {}", object),
+    })?;
+    // Only the header is read for -t and -s, and blobs are streamed to stdout.
+    let mut reader = ObjectStorage::open_object(&sha)?;
+    let mut out = std::io::stdout().lock();
+    match *mode {
+        "-t" => writeln!(out, "{}", reader.object_type)?,
+        "-s" => writeln!(out, "{}", reader.size)?,
+        _ if reader.object_type == "tree" => {
+            let object_type = reader.object_type.clone();
+            let content = reader.into_content()?;
+            let GitObject::Tree(tree) = GitObject::from_parts(&object_type, &content)? else {
+                Err(anyhow!("bad tree object {}", object))?
+            };
+            for entry in tree.entries {
+                let entry_type = match entry.permission {
+                    TreeEntryPermission::Directory => "tree",
+                    _ => "blob",
+                };
+                let mode = format!("{:0>6}", entry.permission.to_string_repr());
+                writeln!(
+                    out,
+                    "{} {} {}\t{}",
+                    mode,
+                    entry_type,
+                    entry.to_hash_hex_string(),
+                    cli::quote_path(&entry.name)
+                )?;
+            }
+        }
+        _ => _ = std::io::copy(&mut reader, &mut out)?,
+    }
+    Ok(())
+}
+
+/// Reads object names from stdin, one per line, and prints `<sha> <type> <size>` for each,
+/// followed by the raw content and a newline when `contents` is set, or `<name> missing`.
+/// The output is flushed after every object, so that a script can write the next name once
+/// it has read the answer.
+fn batch(contents: bool) -> anyhow::Result<()> {
+    let mut cache = ObjectCache::default();
+    let mut out = std::io::stdout().lock();
+    for line in std::io::stdin().lock().lines() {
+        let name = line?;
+        let sha = match revision::resolve(&name) {
+            Ok(sha) => sha,
+            Err(err) if err.to_string().contains("is ambiguous") => {
+                writeln!(out, "{} ambiguous", name)?;
+                out.flush()?;
+                continue;
+            }
+            Err(_) => {
+                writeln!(out, "{} missing", name)?;
+                out.flush()?;
+                continue;
+            }
+        };
+        let hex = ObjectStorage::sha_to_hex_string(&sha);
+        if !contents {
+            match cache.header(&sha) {
+                Ok((object_type, size)) => writeln!(out, "{} {} {}", hex, object_type, size)?,
+                Err(_) => writeln!(out, "{} missing", name)?,
+            }
+        } else {
+            match cache.object(&sha) {
+                Ok((object_type, content)) => {
+                    writeln!(out, "{} {} {}", hex, object_type, content.len())?;
+                    out.write_all(&content)?;
+                    writeln!(out)?;
+                }
+                Err(_) => writeln!(out, "{} missing", name)?,
+            }
+        }
+        out.flush()?;
+    }
+    Ok(())
+}
+
+/// Prints every object in the repository, loose or packed, like batch mode prints the objects
+/// named on stdin: sorted by id, or with `unordered` the loose objects and then each pack's in
+/// the order the pack stores them, which reads the packs front to back. Only the entry
+/// headers are read for the types and sizes, so that the largest objects can be found without
+/// inflating any of them.
+fn batch_all_objects(contents: bool, unordered: bool) -> anyhow::Result<()> {
+    let mut seen = HashSet::new();
+    let mut objects: Vec<(Sha, String, u64)> = vec![];
+    let mut loose = ObjectStorage::loose_objects()?;
+    loose.sort_unstable();
+    for (sha, _) in loose {
+        let reader = ObjectStorage::open_object(&sha)?;
+        seen.insert(sha);
+        objects.push((sha, reader.object_type.clone(), reader.size));
+    }
+    for pack in pack::loaded_packs()?.iter() {
+        let mut reader = pack.open_reader()?;
+        for (offset, sha) in pack.objects_in_pack_order() {
+            if seen.insert(sha) {
+                let (object_type, size) = pack.object_info_at(&mut reader, offset)?;
+                objects.push((sha, object_type.to_string(), size));
+            }
+        }
+    }
+    if !unordered {
+        objects.sort_unstable_by_key(|(sha, ..)| *sha);
+    }
+    let mut out = std::io::stdout().lock();
+    for (sha, object_type, size) in objects {
+        let hex = ObjectStorage::sha_to_hex_string(&sha);
+        writeln!(out, "{} {} {}", hex, object_type, size)?;
+        if contents {
+            out.write_all(&ObjectStorage::read_object(&sha)?.1)?;
+            writeln!(out)?;
+        }
+    }
+    Ok(())
+}
+
+/// The objects batch mode has read, so that a name asked for again is answered without
+/// inflating the object, or resolving its deltas, a second time. Contents are kept up to
+/// [`CACHE_LIMIT`] bytes, dropping the oldest first; headers are kept for every object.
+#[derive(Default)]
+struct ObjectCache {
+    headers: HashMap<Sha, (String, u64)>,
+    contents: HashMap<Sha, (String, Rc<Vec<u8>>)>,
+    order: VecDeque<Sha>,
+    used: usize,
+}
+
+impl ObjectCache {
+    /// The type and size of `sha`; only the header of a loose object is read.
+    fn header(&mut self, sha: &Sha) -> anyhow::Result<(String, u64)> {
+        if let Some(header) = self.headers.get(sha) {
+            return Ok(header.clone());
+        }
+        let reader = ObjectStorage::open_object(sha)?;
+        let header = (reader.object_type.clone(), reader.size);
+        self.headers.insert(*sha, header.clone());
+        Ok(header)
+    }
+
+    fn object(&mut self, sha: &Sha) -> anyhow::Result<(String, Rc<Vec<u8>>)> {
+        if let Some(object) = self.contents.get(sha) {
+            return Ok(object.clone());
+        }
+        let (object_type, content) = ObjectStorage::read_object(sha)?;
+        let content = Rc::new(content);
+        self.headers
+            .insert(*sha, (object_type.clone(), content.len() as u64));
+        if content.len() <= CACHE_LIMIT {
+            while self.used + content.len() > CACHE_LIMIT {
+                let Some(oldest) = self.order.pop_front() else {
+                    break;
+                };
+                if let Some((_, evicted)) = self.contents.remove(&oldest) {
+                    self.used -= evicted.len();
+                }
+            }
+            self.used += content.len();
+            self.order.push_back(*sha);
+            self.contents
+                .insert(*sha, (object_type.clone(), content.clone()));
+        }
+        Ok((object_type, content))
+    }
+}
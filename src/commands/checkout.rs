@@ -0,0 +1,609 @@
+use super::apply;
+use crate::cancel::{self, Cancelled};
+use crate::cli;
+use crate::diff::{self, FileChange};
+use crate::hooks;
+use crate::index::{self, Index, IndexEntry, IndexLock};
+use crate::object_storage::{CheckoutReport, GitObject, ObjectStorage, Sha, TreeEntryPermission};
+use crate::patch;
+use crate::patch_mode::{self, Session, Side};
+use crate::pathspec::PathspecSet;
+use crate::refs;
+use crate::repository::CommandExit;
+use crate::revision;
+use anyhow::anyhow;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("checkout", args)?;
+    if args.has("-p") || args.has("--patch-script") {
+        let (revision, paths) = match args.separator {
+            Some(separator) => match args.operands.split_at(separator) {
+                ([], paths) => (None, paths),
+                ([revision], paths) => (Some(revision.as_str()), paths),
+                _ => return Err(cli::usage_error("checkout")),
+            },
+            // Without `--`, the first operand is the tree-ish if it names one.
+            None => match args.operands.split_first() {
+                Some((first, paths)) if revision::resolve(first).is_ok() => {
+                    (Some(first.as_str()), paths)
+                }
+                _ => (None, args.operands.as_slice()),
+            },
+        };
+        return checkout_patch(
+            revision,
+            &PathspecSet::from_args(paths)?,
+            args.value("--patch-script"),
+        );
+    }
+    if let Some(separator) = args.separator {
+        let (revisions, paths) = args.operands.split_at(separator);
+        return match revisions {
+            [] if !paths.is_empty() => checkout_paths(None, paths),
+            [revision] if !paths.is_empty() => checkout_paths(Some(revision), paths),
+            _ => return Err(cli::usage_error("checkout")),
+        };
+    }
+    let [revision] = args.operands.as_slice() else {
+        return Err(cli::usage_error("checkout"));
+    };
+    let branch = format!("refs/heads/{}", revision);
+    let branch = refs::read_ref(&branch)?.is_some().then_some(branch);
+    let target = ObjectStorage::peel_to_commit(&revision::resolve(revision)?)?;
+    let target_tree = ObjectStorage::peel_to_tree(&target)?;
+    let (_, head) = refs::resolve_ref("HEAD")?;
+    if args.has("-f") {
+        // Local changes are thrown away, so there is nothing to roll back to: once it
+        // has started, the checkout is finished.
+        cancel::check()?;
+        cancel::shielded(|| force_checkout(&target, &target_tree))?
+    } else {
+        let head_tree = head
+            .map(|head| ObjectStorage::peel_to_tree(&head))
+            .transpose()?;
+        switch(
+            head_tree.as_ref(),
+            &target_tree,
+            "checkout",
+            "switch branches",
+        )?
+    }
+
+    let previous_branch = refs::current_branch()?;
+    match &branch {
+        Some(branch) => refs::write_symbolic_ref("HEAD", branch)?,
+        None => refs::update_ref_no_deref("HEAD", &target, None)?,
+    }
+    match (&branch, previous_branch) {
+        (Some(branch), Some(previous)) if *branch == previous => {
+            eprintln!("Already on '{}'", revision)
+        }
+        (Some(_), _) => eprintln!("Switched to branch '{}'", revision),
+        (None, _) => eprintln!("HEAD is now at {}", describe(&target)?),
+    }
+    let zeros = [0; 20];
+    let previous_head = ObjectStorage::sha_to_hex_string(head.as_ref().unwrap_or(&zeros));
+    let new_head = ObjectStorage::sha_to_hex_string(&target);
+    hooks::run_hook("post-checkout", &[&previous_head, &new_head, "1"])
+}
+
+/// Writes the files that `paths` match, as the tree of `revision` or else the index has them,
+/// over those in the working tree, and stages them. Files the pathspecs match that the source
+/// does not have are left alone. Nothing is written when a pathspec matches nothing.
+fn checkout_paths(revision: Option<&str>, paths: &[String]) -> anyhow::Result<()> {
+    let pathspecs = paths
+        .iter()
+        .map(|path| PathspecSet::from_args(std::slice::from_ref(path)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let (mut index, lock) = Index::read_locked()?;
+    let files: Vec<(String, TreeEntryPermission, Sha)> = match revision {
+        Some(revision) => {
+            let tree = ObjectStorage::peel_to_tree(&revision::resolve(revision)?)?;
+            diff::diff_trees(None, Some(&tree))?
+                .into_iter()
+                .filter_map(|change| Some((change.path, change.new_mode?, change.new?)))
+                .collect()
+        }
+        None => {
+            let mut files = vec![];
+            for entry in index.entries.iter().filter(|entry| entry.stage == 0) {
+                let permission = index::mode_permission(entry.mode).ok_or(anyhow!(
+                    "invalid mode {:o} for '{}'",
+                    entry.mode,
+                    entry.path
+                ))?;
+                files.push((entry.path.clone(), permission, entry.sha));
+            }
+            files
+        }
+    };
+    // Which pathspecs matched something, for reporting those that did not.
+    let mut matched = vec![false; pathspecs.len()];
+    let mut select = |path: &str| {
+        let mut selected = false;
+        for (pathspec, matched) in pathspecs.iter().zip(&mut matched) {
+            if pathspec.matches(path, false) {
+                *matched = true;
+                selected = true;
+            }
+        }
+        selected
+    };
+    let files: Vec<_> = files
+        .into_iter()
+        .filter(|(path, _, _)| select(path))
+        .collect();
+    let mut failed = false;
+    if revision.is_none() {
+        // A conflicted path has no one version in the index to take.
+        let mut unmerged: Vec<&str> = index
+            .entries
+            .iter()
+            .filter(|entry| entry.stage != 0)
+            .map(|entry| entry.path.as_str())
+            .collect();
+        unmerged.dedup();
+        for path in unmerged.into_iter().filter(|path| select(path)) {
+            eprintln!("error: path '{}' is unmerged", path);
+            failed = true;
+        }
+    }
+    for (path, _) in paths.iter().zip(&matched).filter(|(_, matched)| !**matched) {
+        eprintln!(
+            "error: pathspec '{}' did not match any file(s) known to git",
+            path
+        );
+        failed = true;
+    }
+    if failed {
+        Err(CommandExit { code: 1 })?
+    }
+
+    cancel::check()?;
+    let report = ObjectStorage::checkout_files(&files)?;
+    let work_tree = ObjectStorage::work_tree();
+    for (path, permission, sha) in &files {
+        let file = work_tree.join(path);
+        if let Ok(entry) = IndexEntry::from_file(path, &file, *sha) {
+            index.add(IndexEntry {
+                mode: index::tree_entry_mode(*permission),
+                ..entry
+            });
+        }
+    }
+    index.write(lock)?;
+    finish_checkout(report)?;
+    let head = match refs::resolve_ref("HEAD")? {
+        (_, Some(head)) => ObjectStorage::sha_to_hex_string(&head),
+        (_, None) => ObjectStorage::sha_to_hex_string(&[0; 20]),
+    };
+    hooks::run_hook("post-checkout", &[&head, &head, "0"])
+}
+
+/// `checkout -p`: offers the differences between the working tree and the index, or the tree
+/// of `revision`, hunk by hunk for the files `pathspecs` matches. Without a revision the hunks
+/// taken are discarded from the working tree; with `HEAD` they are discarded from the index
+/// too, and with another tree they are applied to both. When the index has changes the hunks
+/// do not apply to, they can still go to the working tree alone. Answers come from `script`
+/// instead of stdin when it is given.
+fn checkout_patch(
+    revision: Option<&str>,
+    pathspecs: &PathspecSet,
+    script: Option<&str>,
+) -> anyhow::Result<()> {
+    let (mut index, lock) = Index::read_locked()?;
+    let (mode, sources): (_, Vec<(String, u32, Sha)>) = match revision {
+        Some(revision) => {
+            let tree = ObjectStorage::peel_to_tree(&revision::resolve(revision)?)?;
+            let files = diff::diff_trees(None, Some(&tree))?
+                .into_iter()
+                .filter_map(|change| {
+                    let mode = index::tree_entry_mode(change.new_mode?);
+                    Some((change.path, mode, change.new?))
+                })
+                .collect();
+            match revision {
+                "HEAD" => (&patch_mode::DISCARD_BOTH, files),
+                _ => (&patch_mode::APPLY_BOTH, files),
+            }
+        }
+        None => {
+            let files = index
+                .entries
+                .iter()
+                .filter(|entry| entry.stage == 0 && index::mode_permission(entry.mode).is_some())
+                .map(|entry| (entry.path.clone(), entry.mode, entry.sha))
+                .collect();
+            (&patch_mode::DISCARD, files)
+        }
+    };
+    let mut session = Session::new(mode, script)?;
+    let work_tree = ObjectStorage::work_tree();
+    let mut offered = false;
+    let mut index_changed = false;
+    for (path, source_mode, sha) in sources {
+        if session.has_quit() {
+            break;
+        }
+        if !pathspecs.matches(&path, false) {
+            continue;
+        }
+        let source = ObjectStorage::read_object(&sha)?.1;
+        let work = patch_mode::work_tree_version(&path, source_mode)?;
+        match &work {
+            Some((work_mode, content)) if *work_mode == source_mode && *content == source => {
+                continue;
+            }
+            // A file replaced by a symlink, or the other way round, is not offered.
+            Some((work_mode, _))
+                if (*work_mode == index::MODE_SYMLINK) != (source_mode == index::MODE_SYMLINK) =>
+            {
+                continue;
+            }
+            _ => {}
+        }
+        let source_side = Side {
+            mode: source_mode,
+            content: &source,
+        };
+        let work_side = work.as_ref().map(|(mode, content)| Side {
+            mode: *mode,
+            content,
+        });
+        // Discarding goes from the source to the working tree, applying the other way round,
+        // and a file missing from the working tree cannot be offered that way.
+        let change = match (mode.revert, work_side) {
+            (true, new) => patch_mode::FileChange {
+                path: &path,
+                old: source_side,
+                new,
+            },
+            (false, Some(old)) => patch_mode::FileChange {
+                path: &path,
+                old,
+                new: Some(source_side),
+            },
+            (false, None) => continue,
+        };
+        offered = true;
+        let choice = session.choose(&change)?;
+        let result = match &work {
+            _ if choice.deletion => (source_mode, source.clone()),
+            Some((work_mode, content)) if choice.mode || choice.content.is_some() => (
+                if choice.mode { source_mode } else { *work_mode },
+                choice.content.unwrap_or_else(|| content.clone()),
+            ),
+            _ => continue,
+        };
+
+        let staged = match index.find(&path) {
+            Some(entry) => Some((entry.mode, ObjectStorage::read_object(&entry.sha)?.1)),
+            None => None,
+        };
+        let staged_result = match revision {
+            Some(_) => apply_to_staged(&path, staged, work.as_ref(), &result),
+            // Discarding from the working tree only, the file may be back as it is staged.
+            None => staged.filter(|staged| *staged == result),
+        };
+        if revision.is_some() && staged_result.is_none() {
+            eprintln!("The selected hunks do not apply to the index!");
+            if !session.ask_yes_no("Apply them to the worktree anyway? ")? {
+                eprintln!("Nothing was applied.\n");
+                continue;
+            }
+        }
+        let result_sha = ObjectStorage::hash_object("blob", &result.1, false, false)?;
+        apply::write_work_tree_file(&path, &result.1, result.0, &result_sha)?;
+        if let Some((mode, content)) = staged_result {
+            let sha = ObjectStorage::hash_object("blob", &content, true, false)?;
+            // Stat data is only kept for a file staged as it is.
+            index.add(match (mode, &content) == (result.0, &result.1) {
+                true => IndexEntry {
+                    mode,
+                    ..IndexEntry::from_file(&path, &work_tree.join(&path), sha)?
+                },
+                false => IndexEntry::without_stat(&path, mode, sha),
+            });
+            index_changed = true;
+        }
+    }
+    session.finish()?;
+    if !offered {
+        eprintln!("No changes.");
+    }
+    if index_changed {
+        index.write(lock)?;
+    }
+    Ok(())
+}
+
+/// The staged version of `path` with the change from `work`, its working tree version, to
+/// `result` made to it as well, or `None` when that does not apply, saying why.
+fn apply_to_staged(
+    path: &str,
+    staged: Option<(u32, Vec<u8>)>,
+    work: Option<&(u32, Vec<u8>)>,
+    result: &(u32, Vec<u8>),
+) -> Option<(u32, Vec<u8>)> {
+    if staged.as_ref() == work {
+        return Some(result.clone());
+    }
+    let Some((staged_mode, staged_content)) = staged else {
+        eprintln!("error: {}: does not exist in index", path);
+        return None;
+    };
+    let (work_mode, work_content) = work?;
+    let mode = match result.0 != *work_mode {
+        true => result.0,
+        false => staged_mode,
+    };
+    match patch::apply(
+        &staged_content,
+        &patch::hunks_between(work_content, &result.1),
+    ) {
+        Ok(content) => Some((mode, content)),
+        Err(line) => {
+            eprintln!("error: patch failed: {}:{}", path, line);
+            eprintln!("error: {}: patch does not apply", path);
+            None
+        }
+    }
+}
+
+/// Replaces the working tree and index with the tree of `target`.
+fn force_checkout(target: &Sha, target_tree: &Sha) -> anyhow::Result<()> {
+    // Files tracked now but not in the target would otherwise be left behind.
+    let target_files: HashSet<String> = diff::diff_trees(None, Some(target_tree))?
+        .into_iter()
+        .map(|change| change.path)
+        .collect();
+    let (index, lock) = Index::read_locked()?;
+    for entry in index.entries {
+        if !target_files.contains(&entry.path) {
+            remove_work_tree_file(&entry.path)?;
+        }
+    }
+    finish_checkout(ObjectStorage::checkout(target)?)?;
+    Index::from_tree(target_tree)?.write(lock)
+}
+
+/// Checks out the tree of the commit `sha` into the working tree and writes a matching index.
+pub fn checkout(sha: &Sha) -> anyhow::Result<()> {
+    let lock = IndexLock::acquire()?;
+    finish_checkout(ObjectStorage::checkout(sha)?)?;
+    Index::from_tree(&ObjectStorage::peel_to_tree(sha)?)?.write(lock)?;
+    let previous_head = ObjectStorage::sha_to_hex_string(&[0; 20]);
+    let new_head = ObjectStorage::sha_to_hex_string(sha);
+    hooks::run_hook("post-checkout", &[&previous_head, &new_head, "1"])
+}
+
+pub fn finish_checkout(report: CheckoutReport) -> anyhow::Result<()> {
+    eprintln!(
+        "Checked out {} of {} files ({} bytes)",
+        report.files_written, report.total_files, report.bytes_written
+    );
+    if report.symlink_fallbacks > 0 {
+        eprintln!(
+            "warning: wrote {} symlink(s) as plain files (core.symlinks=false)",
+            report.symlink_fallbacks
+        );
+    }
+    if report.failures.is_empty() {
+        return Ok(());
+    }
+    for (path, message) in &report.failures {
+        eprintln!("error: unable to write '{}': {}", path.display(), message);
+    }
+    Err(anyhow!(
+        "checkout failed for {} of {} files",
+        report.failures.len(),
+        report.total_files
+    ))
+}
+
+/// Moves the working tree and index from `head_tree` to `target_tree`, touching only the files
+/// that differ, so local changes to other files are carried over; without a `head_tree`, on a
+/// branch with no commits yet, every file of the target is new. Refuses to start when a file
+/// that has to change has local changes or an untracked file is in the way, saying they
+/// would be overwritten by `command` and should be dealt with before you `action`. When
+/// cancelled, the files are put back as `head_tree` has them.
+pub fn switch(
+    head_tree: Option<&Sha>,
+    target_tree: &Sha,
+    command: &str,
+    action: &str,
+) -> anyhow::Result<()> {
+    let changes = diff::diff_trees(head_tree, Some(target_tree))?;
+    let (mut index, lock) = Index::read_locked()?;
+    let (modified, untracked) = local_changes(&changes, &index)?;
+    if !modified.is_empty() || !untracked.is_empty() {
+        if !modified.is_empty() {
+            eprintln!(
+                "error: Your local changes to the following files would be overwritten by {}:",
+                command
+            );
+            for path in &modified {
+                eprintln!("\t{}", path);
+            }
+            eprintln!(
+                "Please commit your changes or stash them before you {}.",
+                action
+            );
+        }
+        if !untracked.is_empty() {
+            eprintln!(
+                "error: The following untracked working tree files would be overwritten by {}:",
+                command
+            );
+            for path in &untracked {
+                eprintln!("\t{}", path);
+            }
+            eprintln!("Please move or remove them before you {}.", action);
+        }
+        eprintln!("Aborting");
+        Err(CommandExit { code: 1 })?
+    }
+
+    let target_version = |change: &FileChange| Some((change.new?, change.new_mode?));
+    let head_version = |change: &FileChange| Some((change.old?, change.old_mode?));
+    if let Err(err) = update_files(&changes, target_version) {
+        if err.is::<Cancelled>() {
+            // None of the files had local changes, so nothing is lost going back.
+            cancel::shielded(|| update_files(&changes, head_version))?;
+        }
+        return Err(err);
+    }
+    let mut files = vec![];
+    for change in &changes {
+        if let Some((sha, mode)) = target_version(change) {
+            files.push((change.path.clone(), mode, sha));
+        }
+    }
+    for change in &changes {
+        index.remove(&change.path);
+    }
+    for (path, mode, sha) in files {
+        let file = ObjectStorage::work_tree().join(&path);
+        index.add(IndexEntry {
+            mode: index::tree_entry_mode(mode),
+            ..IndexEntry::from_file(&path, &file, sha)?
+        });
+    }
+    index.write(lock)
+}
+
+/// Puts the index back as `target_tree` has it, along with the working tree files of the paths
+/// that change, throwing away their staged changes and conflicts. Like `git reset --merge`,
+/// the unstaged changes of other files are kept.
+pub fn reset(target_tree: &Sha) -> anyhow::Result<()> {
+    let (mut index, lock) = Index::read_locked()?;
+    let mut changes = diff::diff_tree_to_index(Some(target_tree), &index)?;
+    // Conflicted paths the target does not have are not among the staged changes.
+    for entry in index.entries.iter().filter(|entry| entry.stage != 0) {
+        if !changes.iter().any(|change| change.path == entry.path) {
+            changes.push(FileChange {
+                path: entry.path.clone(),
+                old: None,
+                new: None,
+                old_mode: None,
+                new_mode: None,
+            });
+        }
+    }
+    if changes.is_empty() {
+        return Ok(());
+    }
+    let target_version = |change: &FileChange| Some((change.old?, change.old_mode?));
+    update_files(&changes, target_version)?;
+    for change in &changes {
+        index.remove(&change.path);
+        if let Some((sha, mode)) = target_version(change) {
+            let file = ObjectStorage::work_tree().join(&change.path);
+            index.add(IndexEntry {
+                mode: index::tree_entry_mode(mode),
+                ..IndexEntry::from_file(&change.path, &file, sha)?
+            });
+        }
+    }
+    index.write(lock)
+}
+
+/// Brings each changed path to the version `version` picks, deleting those it has none of.
+fn update_files(
+    changes: &[FileChange],
+    version: impl Fn(&FileChange) -> Option<(Sha, TreeEntryPermission)>,
+) -> anyhow::Result<()> {
+    let mut files = vec![];
+    for change in changes {
+        match version(change) {
+            Some((sha, mode)) => files.push((change.path.clone(), mode, sha)),
+            None => remove_work_tree_file(&change.path)?,
+        }
+    }
+    finish_checkout(ObjectStorage::checkout_files(&files)?)
+}
+
+/// The changed paths whose staged or working tree version differs from the current commit,
+/// and those where the target needs a file (or a directory) an untracked file occupies.
+fn local_changes(
+    changes: &[FileChange],
+    index: &Index,
+) -> anyhow::Result<(Vec<String>, Vec<String>)> {
+    let work_tree = ObjectStorage::work_tree();
+    let removed: HashSet<&str> = changes
+        .iter()
+        .filter(|change| change.new.is_none())
+        .map(|change| change.path.as_str())
+        .collect();
+    let mut modified = vec![];
+    let mut untracked = vec![];
+    for change in changes {
+        let staged = index.find(&change.path);
+        let staged_version = staged.map(|entry| (entry.mode, entry.sha));
+        let version = |sha: Option<Sha>, mode: Option<TreeEntryPermission>| {
+            Some((index::tree_entry_mode(mode?), sha?))
+        };
+        let head_version = version(change.old, change.old_mode);
+        let target_version = version(change.new, change.new_mode);
+        let file = work_tree.join(&change.path);
+        match staged {
+            // Staged as the target has it, switching loses nothing either.
+            Some(entry) if staged_version == head_version || staged_version == target_version => {
+                if !entry.matches_file(&file)? {
+                    modified.push(change.path.clone());
+                }
+            }
+            Some(_) => modified.push(change.path.clone()),
+            None if head_version.is_some() => modified.push(change.path.clone()),
+            None => {
+                if fs::symlink_metadata(&file).is_ok() {
+                    untracked.push(change.path.clone());
+                }
+            }
+        }
+        if change.new.is_some() {
+            // A file where the target needs one of the parent directories.
+            for (end, _) in change.path.match_indices('/') {
+                let parent = &change.path[..end];
+                let in_the_way = fs::symlink_metadata(work_tree.join(parent))
+                    .is_ok_and(|metadata| !metadata.is_dir());
+                if in_the_way && !removed.contains(parent) {
+                    untracked.push(parent.to_string());
+                }
+            }
+        }
+    }
+    untracked.sort();
+    untracked.dedup();
+    Ok((modified, untracked))
+}
+
+/// Deletes a tracked file and then any parent directories that became empty.
+pub fn remove_work_tree_file(path: &str) -> anyhow::Result<()> {
+    let work_tree = ObjectStorage::work_tree();
+    let file = work_tree.join(path);
+    match fs::remove_file(&file) {
+        Ok(()) => (),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+        Err(err) => Err(anyhow!("unable to remove '{}': {}", path, err))?,
+    }
+    let mut dir: Option<&Path> = Path::new(path).parent();
+    while let Some(parent) = dir.filter(|parent| !parent.as_os_str().is_empty()) {
+        if fs::remove_dir(work_tree.join(parent)).is_err() {
+            break;
+        }
+        dir = parent.parent();
+    }
+    Ok(())
+}
+
+/// `abc1234 subject` for the "HEAD is now at" message.
+fn describe(sha: &Sha) -> anyhow::Result<String> {
+    let commit = GitObject::read_commit(sha)?;
+    let hex = ObjectStorage::sha_to_hex_string(sha);
+    let subject = commit.message.lines().next().unwrap_or_default();
+    Ok(format!("{} {}", &hex[..7], subject))
+}
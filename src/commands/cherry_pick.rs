@@ -0,0 +1,463 @@
+use super::checkout;
+use super::commit::{self, cleanup_message};
+use super::merge as merge_command;
+use crate::cli;
+use crate::commit_walk;
+use crate::diff;
+use crate::gc;
+use crate::index::Index;
+use crate::merge;
+use crate::merge_file::Labels;
+use crate::object_storage::{Commit, GitObject, ObjectStorage, Sha, Signature};
+use crate::oid_set::OidSet;
+use crate::refs;
+use crate::repository::CommandExit;
+use crate::revision;
+use crate::search_index;
+use crate::sequencer::{Action, Options, Sequence, Step};
+use anyhow::anyhow;
+use std::fs;
+
+/// Applies the changes the given commits introduce, each as a new commit on top of `HEAD`.
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    run_sequence(Action::Pick, args)
+}
+
+/// Cherry-picks or reverts the commits `args` names, or with `--continue`, `--skip`, `--abort`
+/// or `--quit` goes on with, or stops, the cherry-pick or revert in progress. A step whose
+/// changes conflict stops with the conflicts left for the user, `CHERRY_PICK_HEAD` or
+/// `REVERT_HEAD` naming its commit and the message prepared in `MERGE_MSG`; with more than one
+/// commit the rest is kept in `.git/sequencer`, as git does, so either can carry on.
+pub fn run_sequence(action: Action, args: &[String]) -> anyhow::Result<()> {
+    let command = action.command();
+    let args = cli::parse_args(command, args)?;
+    let subcommand = args.last_of(&["--continue", "--skip", "--abort", "--quit"]);
+    if subcommand.is_some() && !args.operands.is_empty() {
+        return Err(cli::usage_error(command));
+    }
+    match subcommand {
+        Some("--continue") => resume(action),
+        Some("--skip") => skip(action),
+        Some("--abort") => abort(action),
+        Some(_) => quit(),
+        None => start(action, &args),
+    }
+}
+
+fn start(action: Action, args: &cli::ParsedArgs) -> anyhow::Result<()> {
+    let command = action.command();
+    if args.operands.is_empty() {
+        return Err(cli::usage_error(command));
+    }
+    if Sequence::in_progress() {
+        eprintln!("error: {} is already in progress", command);
+        eprintln!(
+            "hint: try \"{} {} (--continue | --abort | --quit)\"",
+            cli::program_name(),
+            command
+        );
+        Err(anyhow!("{} failed", command))?
+    }
+    let (_, Some(head)) = refs::resolve_ref("HEAD")? else {
+        Err(anyhow!("can't {} into an unborn branch", command))?
+    };
+    refuse_unmerged(action)?;
+    refuse_staged_changes(action, &head)?;
+    let options = Options {
+        record_origin: args.has("-x"),
+        mainline: match args.value("-m") {
+            Some(mainline) => Some(
+                mainline
+                    .parse()
+                    .ok()
+                    .filter(|mainline| *mainline > 0)
+                    .ok_or_else(|| {
+                        anyhow!("option `mainline' expects a number greater than zero")
+                    })?,
+            ),
+            None => None,
+        },
+    };
+    let mut todo = vec![];
+    for operand in &args.operands {
+        for commit in commits(operand)? {
+            todo.push(Step { action, commit });
+        }
+    }
+    if todo.is_empty() {
+        eprintln!("error: empty commit set passed");
+        Err(anyhow!("{} failed", command))?
+    }
+    if todo.len() == 1 {
+        // A single commit is carried out without keeping a sequence.
+        return run_todo(todo, &options, false);
+    }
+    let sequence = Sequence {
+        todo,
+        options,
+        head,
+    };
+    sequence.save()?;
+    run_todo(sequence.todo, &sequence.options, true)
+}
+
+/// Commits the resolved step that stopped, then carries out the rest of the sequence.
+fn resume(action: Action) -> anyhow::Result<()> {
+    let sequence = Sequence::load()?;
+    let stopped = refs::read_pseudo_ref(action.pseudo_ref())?;
+    if sequence.is_none() && stopped.is_empty() {
+        Err(anyhow!("no cherry-pick or revert in progress"))?
+    }
+    if Index::read()?.entries.iter().any(|entry| entry.stage != 0) {
+        eprintln!("error: Committing is not possible because you have unmerged files.");
+        eprintln!("hint: Fix them up in the work tree, and then use 'git add/rm <file>'");
+        eprintln!("hint: as appropriate to mark resolution and make a commit.");
+        Err(anyhow!("Exiting because of an unresolved conflict."))?
+    }
+    if stopped.is_empty() {
+        // The user committed the step, or reset it away; changes staged since would be lost.
+        if let (_, Some(head)) = refs::resolve_ref("HEAD")? {
+            refuse_staged_changes(action, &head)?;
+        }
+    } else {
+        commit::run(&[])?;
+    }
+    match sequence {
+        Some(mut sequence) => {
+            if !sequence.todo.is_empty() {
+                sequence.todo.remove(0);
+            }
+            if let (_, Some(head)) = refs::resolve_ref("HEAD")? {
+                Sequence::record_abort_safety(&head)?;
+            }
+            run_todo(sequence.todo, &sequence.options, true)
+        }
+        None => Ok(()),
+    }
+}
+
+/// Throws away the step that stopped and carries out the rest of the sequence.
+fn skip(action: Action) -> anyhow::Result<()> {
+    let command = action.command();
+    let sequence = Sequence::load()?;
+    if refs::read_pseudo_ref(action.pseudo_ref())?.is_empty() {
+        match sequence {
+            Some(_) => {
+                eprintln!("error: there is nothing to skip");
+                eprintln!("hint: have you committed already?");
+                eprintln!(
+                    "hint: try \"{} {} --continue\"",
+                    cli::program_name(),
+                    command
+                );
+            }
+            None => eprintln!("error: no cherry-pick or revert in progress"),
+        }
+        Err(anyhow!("{} failed", command))?
+    }
+    if let (_, Some(head)) = refs::resolve_ref("HEAD")? {
+        checkout::reset(&ObjectStorage::peel_to_tree(&head)?)?;
+    }
+    refs::clear_operation_state()?;
+    match sequence {
+        Some(mut sequence) => {
+            if !sequence.todo.is_empty() {
+                sequence.todo.remove(0);
+            }
+            run_todo(sequence.todo, &sequence.options, true)
+        }
+        None => Ok(()),
+    }
+}
+
+/// Goes back to where the sequence started, unless `HEAD` was moved since its last commit;
+/// a single step that stopped is just undone.
+fn abort(action: Action) -> anyhow::Result<()> {
+    let sequence = Sequence::load()?;
+    let stopped = refs::read_pseudo_ref(action.pseudo_ref())?;
+    if sequence.is_none() && stopped.is_empty() {
+        eprintln!("error: no cherry-pick or revert in progress");
+        Err(anyhow!("{} failed", action.command()))?
+    }
+    let (_, head) = refs::resolve_ref("HEAD")?;
+    let Some(head) = head else {
+        Err(anyhow!("cannot abort from a branch yet to be born"))?
+    };
+    let target = match &sequence {
+        Some(_) if Sequence::abort_safety()? != Some(head) => {
+            eprintln!("warning: You seem to have moved HEAD. Not rewinding, check your HEAD!");
+            None
+        }
+        Some(sequence) => Some(sequence.head),
+        None => Some(head),
+    };
+    if let Some(target) = target {
+        checkout::reset(&ObjectStorage::peel_to_tree(&target)?)?;
+        if target != head {
+            refs::update_ref("HEAD", &target, Some(&head))?;
+        }
+    }
+    refs::clear_operation_state()?;
+    Sequence::remove()
+}
+
+/// Forgets the cherry-pick or revert in progress, keeping what it has done.
+fn quit() -> anyhow::Result<()> {
+    refs::clear_operation_state()?;
+    Sequence::remove()
+}
+
+/// Carries out the steps in order, keeping what is left in the sequence when `in_sequence`,
+/// and forgets the sequence once all of them are done.
+fn run_todo(mut todo: Vec<Step>, options: &Options, in_sequence: bool) -> anyhow::Result<()> {
+    while let Some(step) = todo.first() {
+        if in_sequence {
+            Sequence::save_todo(&todo)?;
+        }
+        let committed = apply(step, options)?;
+        if in_sequence {
+            Sequence::record_abort_safety(&committed)?;
+        }
+        todo.remove(0);
+    }
+    if in_sequence {
+        Sequence::remove()?;
+    }
+    search_index::update()?;
+    gc::auto_gc()
+}
+
+/// Cherry-picks or reverts one commit and commits the result, returning the new commit. Exits
+/// when the changes conflict or turn out to be empty, leaving the step for the user.
+fn apply(step: &Step, options: &Options) -> anyhow::Result<Sha> {
+    let action = step.action;
+    let command = action.command();
+    let hex = ObjectStorage::sha_to_hex_string(&step.commit);
+    let picked = GitObject::read_commit(&step.commit)?;
+    let parent = match (options.mainline, picked.parents.as_slice()) {
+        (None, [_, _, ..]) => {
+            eprintln!(
+                "error: commit {} is a merge but no -m option was given.",
+                hex
+            );
+            Err(anyhow!("{} failed", command))?
+        }
+        (Some(mainline), parents) => Some(
+            *parents
+                .get(mainline - 1)
+                .ok_or_else(|| anyhow!("commit {} does not have parent {}", hex, mainline))?,
+        ),
+        (None, parents) => parents.first().copied(),
+    };
+    let (_, Some(head)) = refs::resolve_ref("HEAD")? else {
+        Err(anyhow!("can't {} into an unborn branch", command))?
+    };
+    let head_tree = ObjectStorage::peel_to_tree(&head)?;
+    let subject = picked
+        .message
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    let abbreviated = revision::abbreviate(&step.commit, 7)?;
+    let description = format!("{}... {}", abbreviated, subject);
+    // The conflict markers name the sides like `abc1234 (subject)`.
+    let label = format!("{} ({})", abbreviated, subject);
+    let parent_label = format!("parent of {}", label);
+    let picked_tree = ObjectStorage::peel_to_tree(&step.commit)?;
+    let parent_tree = parent
+        .map(|parent| ObjectStorage::peel_to_tree(&parent))
+        .transpose()?;
+    let (base_tree, their_tree, labels, message) = match action {
+        Action::Pick => {
+            let mut message = picked.message.clone();
+            if options.record_origin {
+                if !message.ends_with('\n') {
+                    message.push('\n');
+                }
+                message.push_str(&format!("\n(cherry picked from commit {})\n", hex));
+            }
+            let labels = Labels {
+                ours: "HEAD",
+                base: &parent_label,
+                theirs: &label,
+            };
+            (parent_tree, Some(picked_tree), labels, message)
+        }
+        Action::Revert => {
+            let mut message = format!("Revert \"{}\"\n\nThis reverts commit {}", subject, hex);
+            match parent {
+                Some(parent) if picked.parents.len() > 1 => message.push_str(&format!(
+                    ", reversing\nchanges made to {}.\n",
+                    ObjectStorage::sha_to_hex_string(&parent)
+                )),
+                _ => message.push_str(".\n"),
+            }
+            let labels = Labels {
+                ours: "HEAD",
+                base: &label,
+                theirs: &parent_label,
+            };
+            (Some(picked_tree), parent_tree, labels, message)
+        }
+    };
+    // The other side of a revert of a root commit is empty.
+    let their_tree = match their_tree {
+        Some(tree) => tree,
+        None => merge::write_tree(&diff::tree_snapshot(None)?)?,
+    };
+    let merged = merge::merge_trees(base_tree.as_ref(), &head_tree, &their_tree, &labels)?;
+    let written: Vec<&str> = merged
+        .conflicts
+        .iter()
+        .filter(|conflict| conflict.content.is_some())
+        .map(|conflict| conflict.path.as_str())
+        .collect();
+    merge_command::refuse_local_changes(&head_tree, &written)?;
+    merge_command::report_moved(&merged.moved, &label);
+    for path in &merged.auto_merged {
+        println!("Auto-merging {}", path);
+    }
+    let tree = merge::write_tree(&merged.files)?;
+    if tree != head_tree {
+        checkout::switch(Some(&head_tree), &tree, "merge", "merge")?;
+    }
+    let git_dir = ObjectStorage::git_dir();
+    let program = cli::program_name();
+    if !merged.conflicts.is_empty() {
+        merge_command::record_conflicts(&merged.conflicts, &label)?;
+        refs::write_pseudo_ref(action.pseudo_ref(), &[step.commit])?;
+        let mut merge_message = format!("{}\n\n# Conflicts:\n", message.trim_end());
+        for conflict in &merged.conflicts {
+            merge_message.push_str(&format!("#\t{}\n", conflict.path));
+        }
+        fs::write(git_dir.join("MERGE_MSG"), merge_message)?;
+        let verb = match action {
+            Action::Pick => "apply",
+            Action::Revert => "revert",
+        };
+        eprintln!("error: could not {} {}", verb, description);
+        eprintln!("hint: After resolving the conflicts, mark them with");
+        eprintln!("hint: \"{} add/rm <pathspec>\", then run", program);
+        eprintln!("hint: \"{} {} --continue\".", program, command);
+        eprintln!(
+            "hint: You can instead skip this commit with \"{} {} --skip\".",
+            program, command
+        );
+        eprintln!(
+            "hint: To abort and get back to the state before \"{} {}\",",
+            program, command
+        );
+        eprintln!("hint: run \"{} {} --abort\".", program, command);
+        Err(CommandExit { code: 1 })?
+    }
+    if tree == head_tree {
+        refs::write_pseudo_ref(action.pseudo_ref(), &[step.commit])?;
+        fs::write(git_dir.join("MERGE_MSG"), &message)?;
+        eprintln!(
+            "The previous {} is now empty, possibly due to conflict resolution.",
+            command
+        );
+        eprintln!("If you wish to commit it anyway, use:");
+        eprintln!();
+        eprintln!("    {} commit --allow-empty", program);
+        eprintln!();
+        eprintln!("Otherwise, please use '{} {} --skip'", program, command);
+        Err(CommandExit { code: 1 })?
+    }
+
+    let commit = Commit {
+        tree,
+        parents: vec![head],
+        author: match action {
+            Action::Pick => picked.author,
+            Action::Revert => Signature::author()?,
+        },
+        committer: Signature::committer()?,
+        message: cleanup_message(&message),
+    };
+    let sha = commit.write_to_object_storage()?;
+    refs::update_ref("HEAD", &sha, Some(&head))?;
+    let branch = refs::current_branch()?;
+    let branch = match &branch {
+        Some(branch) => branch.strip_prefix("refs/heads/").unwrap_or(branch),
+        None => "detached HEAD",
+    };
+    println!(
+        "[{} {}] {}",
+        branch,
+        &ObjectStorage::sha_to_hex_string(&sha)[..7],
+        commit.message.lines().next().unwrap_or_default()
+    );
+    Ok(sha)
+}
+
+/// The commits `operand` names, oldest first: one commit, or with `<from>..<to>` those
+/// reachable from `<to>` but not from `<from>`.
+fn commits(operand: &str) -> anyhow::Result<Vec<Sha>> {
+    let resolve = |revision: &str| -> anyhow::Result<Sha> {
+        let revision = if revision.is_empty() {
+            "HEAD"
+        } else {
+            revision
+        };
+        revision::resolve(revision)
+            .and_then(|sha| ObjectStorage::peel_to_commit(&sha))
+            .map_err(|_| anyhow!("bad revision '{}'", revision))
+    };
+    let Some((from, to)) = operand.split_once("..") else {
+        return Ok(vec![resolve(operand)?]);
+    };
+    let excluded = commit_walk::reachable_from(&[resolve(from)?])?;
+    // Depth first, listing each commit after its parents.
+    let mut commits = vec![];
+    let mut seen = OidSet::new()?;
+    let mut pending = vec![(resolve(to)?, false)];
+    while let Some((sha, parents_done)) = pending.pop() {
+        if parents_done {
+            commits.push(sha);
+            continue;
+        }
+        if excluded.contains(&sha) || !seen.insert(sha) {
+            continue;
+        }
+        pending.push((sha, true));
+        let GitObject::Commit(commit) = GitObject::from_sha(&sha)? else {
+            continue;
+        };
+        for parent in commit.parents.iter().rev() {
+            pending.push((*parent, false));
+        }
+    }
+    Ok(commits)
+}
+
+fn refuse_unmerged(action: Action) -> anyhow::Result<()> {
+    if !Index::read()?.entries.iter().any(|entry| entry.stage != 0) {
+        return Ok(());
+    }
+    let doing = match action {
+        Action::Pick => "Cherry-picking",
+        Action::Revert => "Reverting",
+    };
+    eprintln!(
+        "error: {} is not possible because you have unmerged files.",
+        doing
+    );
+    eprintln!("hint: Fix them up in the work tree, and then use 'git add/rm <file>'");
+    eprintln!("hint: as appropriate to mark resolution and make a commit.");
+    Err(anyhow!("{} failed", action.command()))
+}
+
+fn refuse_staged_changes(action: Action, head: &Sha) -> anyhow::Result<()> {
+    let head_tree = ObjectStorage::peel_to_tree(head)?;
+    if diff::diff_tree_to_index(Some(&head_tree), &Index::read()?)?.is_empty() {
+        return Ok(());
+    }
+    eprintln!(
+        "error: your local changes would be overwritten by {}.",
+        action.command()
+    );
+    eprintln!("hint: commit your changes or stash them to proceed.");
+    Err(anyhow!("{} failed", action.command()))
+}
@@ -0,0 +1,165 @@
+use super::checkout;
+use super::init;
+use crate::cli;
+use crate::config;
+use crate::context;
+use crate::gc;
+use crate::object_storage::{ObjectStorage, Sha, pack};
+use crate::refs;
+use crate::repository::CommandExit;
+use crate::search_index;
+use crate::transport;
+use anyhow::anyhow;
+use std::fs;
+use std::path::{self, PathBuf};
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("clone", args)?;
+    let (url, directory) = match args.operands.as_slice() {
+        [url] => (url.trim_end_matches('/'), None),
+        [url, directory] => (url.trim_end_matches('/'), Some(PathBuf::from(directory))),
+        _ => return Err(cli::usage_error("clone")),
+    };
+    let directory = match directory {
+        Some(directory) => directory,
+        None => default_clone_directory(url)?,
+    };
+    let existed = directory.exists();
+    if existed && fs::read_dir(&directory)?.next().is_some() {
+        Err(anyhow!(
+            "destination path '{}' already exists and is not an empty directory.",
+            directory.display()
+        ))?
+    }
+    fs::create_dir_all(&directory)?;
+    let work_tree = path::absolute(&directory)?;
+    let git_dir = work_tree.join(".git");
+    eprintln!("Cloning into '{}'...", directory.display());
+    let interrupt_guard = transport::InterruptGuard::new();
+    let context = context::current().for_repository(git_dir.clone(), Some(work_tree));
+    let result = context::enter(context, || clone_into(url, args.value("--template")));
+    if result.is_err() {
+        // Like git, don't leave a half-cloned repository behind.
+        let _ = if existed {
+            fs::remove_dir_all(git_dir)
+        } else {
+            fs::remove_dir_all(&directory)
+        };
+        if transport::interrupted() {
+            eprintln!("clone interrupted; removed '{}'", directory.display());
+            Err(CommandExit { code: 130 })?
+        }
+    }
+    drop(interrupt_guard);
+    result
+}
+
+fn clone_into(url: &str, template: Option<&str>) -> anyhow::Result<()> {
+    ObjectStorage::init_cwd(None)?;
+    init::copy_templates(template)?;
+    let config_path = ObjectStorage::git_dir().join("config");
+    config::set_value(&config_path, "remote.origin", "url", Some(url))?;
+    config::set_value(
+        &config_path,
+        "remote.origin",
+        "fetch",
+        Some("+refs/heads/*:refs/remotes/origin/*"),
+    )?;
+    let remote = transport::discover_refs(url)?;
+    let branches: Vec<(&str, &Sha)> = remote
+        .refs
+        .iter()
+        .filter_map(|(name, sha)| name.strip_prefix("refs/heads/").map(|branch| (branch, sha)))
+        .collect();
+    let tags: Vec<(&String, &Sha)> = remote
+        .refs
+        .iter()
+        .filter(|(name, _)| name.starts_with("refs/tags/") && !name.ends_with("^{}"))
+        .map(|(name, sha)| (name, sha))
+        .collect();
+    let mut wants: Vec<Sha> = branches
+        .iter()
+        .map(|(_, sha)| **sha)
+        .chain(tags.iter().map(|(_, sha)| **sha))
+        .collect();
+    wants.sort();
+    wants.dedup();
+    if wants.is_empty() {
+        eprintln!("warning: You appear to have cloned an empty repository.");
+        return Ok(());
+    }
+
+    let pack_data = transport::fetch_pack(url, &remote, &wants, &[])?;
+    pack::write_pack(&pack_data)?;
+    for (branch, sha) in &branches {
+        refs::update_ref(&format!("refs/remotes/origin/{}", branch), sha, None)?;
+    }
+    for (name, sha) in &tags {
+        refs::update_ref(name, sha, None)?;
+    }
+    search_index::update()?;
+    gc::auto_gc()?;
+
+    // Without the symref capability, guess HEAD's branch from the advertised HEAD id.
+    let head_sha = remote
+        .refs
+        .iter()
+        .find(|(name, _)| name == "HEAD")
+        .map(|(_, sha)| *sha);
+    let default_branch = remote
+        .head
+        .as_deref()
+        .and_then(|head| head.strip_prefix("refs/heads/"))
+        .or_else(|| {
+            let candidates = branches.iter().filter(|(_, sha)| Some(**sha) == head_sha);
+            let names: Vec<&str> = candidates.map(|(branch, _)| *branch).collect();
+            ["main", "master"]
+                .into_iter()
+                .find(|preferred| names.contains(preferred))
+                .or(names.first().copied())
+        });
+    let checkout_sha = match default_branch.and_then(|branch| {
+        branches
+            .iter()
+            .find(|(name, _)| *name == branch)
+            .map(|(name, sha)| (*name, **sha))
+    }) {
+        Some((branch, sha)) => {
+            refs::update_ref(&format!("refs/heads/{}", branch), &sha, None)?;
+            refs::write_symbolic_ref("HEAD", &format!("refs/heads/{}", branch))?;
+            refs::write_symbolic_ref(
+                "refs/remotes/origin/HEAD",
+                &format!("refs/remotes/origin/{}", branch),
+            )?;
+            let section = format!("branch.{}", branch);
+            config::set_value(&config_path, &section, "remote", Some("origin"))?;
+            config::set_value(
+                &config_path,
+                &section,
+                "merge",
+                Some(&format!("refs/heads/{}", branch)),
+            )?;
+            sha
+        }
+        None => {
+            let sha = head_sha.ok_or(anyhow!("remote HEAD refers to nonexistent ref"))?;
+            refs::update_ref_no_deref("HEAD", &sha, None)?;
+            sha
+        }
+    };
+    checkout::checkout(&checkout_sha)
+}
+
+/// The directory `clone` creates when none is given: the last path component of the URL
+/// without a `.git` suffix.
+fn default_clone_directory(url: &str) -> anyhow::Result<PathBuf> {
+    let name = url.rsplit('/').next().unwrap_or(url);
+    let name = name.strip_suffix(".git").unwrap_or(name);
+    if name.is_empty() || name.contains(':') {
+        Err(anyhow!(
+            "cannot guess a directory name from '{}'; please specify one",
+            url
+        ))?
+    }
+    Ok(PathBuf::from(name))
+}
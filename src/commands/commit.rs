@@ -0,0 +1,130 @@
+use super::status;
+use crate::cli;
+use crate::gc;
+use crate::hooks;
+use crate::index::Index;
+use crate::object_storage::{Commit, GitObject, ObjectStorage, Signature};
+use crate::refs::{self, PseudoRef};
+use crate::repository::CommandExit;
+use crate::search_index;
+use anyhow::anyhow;
+use std::fs;
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("commit", args)?;
+    let paragraphs = args.values("-m");
+    let message_file = args.value("-F");
+    let no_verify = args.has("-n");
+    let allow_empty = args.has("--allow-empty");
+    let allow_empty_message = args.has("--allow-empty-message");
+    if !args.operands.is_empty() {
+        return Err(cli::usage_error("commit"));
+    }
+    let git_dir = ObjectStorage::git_dir();
+    let message = match (paragraphs.is_empty(), message_file) {
+        (false, None) => paragraphs.join("\n\n"),
+        (true, Some("-")) => std::io::read_to_string(std::io::stdin())?,
+        (true, Some(file)) => fs::read_to_string(file)
+            .map_err(|err| anyhow!("could not read log file '{}': {}", file, err))?,
+        (false, Some(_)) => Err(anyhow!("options '-m' and '-F' cannot be used together"))?,
+        // Concluding a merge, cherry-pick or revert, whose message was prepared.
+        (true, None) if git_dir.join("MERGE_MSG").exists() => {
+            let message = fs::read_to_string(git_dir.join("MERGE_MSG"))?;
+            message
+                .lines()
+                .filter(|line| !line.starts_with('#'))
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+        (true, None) => Err(anyhow!(
+            "no commit message given; use -m <message> or -F <file>"
+        ))?,
+    };
+
+    if !no_verify {
+        hooks::run_hook("pre-commit", &[])?;
+    }
+    let index = Index::read()?;
+    let tree = ObjectStorage::write_index_tree(&index)?;
+    let (head_ref, parent) = refs::resolve_ref("HEAD")?;
+    // The other parents of a merge that stopped for conflicts.
+    let merge_heads = refs::read_pseudo_ref(PseudoRef::Merge)?;
+    let unchanged = merge_heads.is_empty()
+        && match parent {
+            Some(parent) => ObjectStorage::peel_to_tree(&parent)? == tree,
+            None => index.entries.is_empty(),
+        };
+    if unchanged && !allow_empty {
+        status::show_for_commit()?;
+        Err(CommandExit { code: 1 })?
+    }
+
+    // Like git, the message goes through COMMIT_EDITMSG so the commit-msg hook can edit it.
+    let mut message = cleanup_message(&message);
+    if !no_verify && hooks::find_hook("commit-msg")?.is_some() {
+        let message_path = ObjectStorage::git_dir().join("COMMIT_EDITMSG");
+        fs::write(&message_path, format!("{}\n", message))?;
+        hooks::run_hook("commit-msg", &[&message_path.to_string_lossy()])?;
+        message = cleanup_message(&fs::read_to_string(&message_path)?);
+    }
+    if message.is_empty() && !allow_empty_message {
+        eprintln!("Aborting commit due to empty commit message.");
+        Err(CommandExit { code: 1 })?
+    }
+
+    // A cherry-pick keeps the author of the commit it picks.
+    let author = match refs::read_pseudo_ref(PseudoRef::CherryPick)?.first() {
+        Some(picked) => match GitObject::from_sha(picked)? {
+            GitObject::Commit(picked) => picked.author,
+            _ => Err(anyhow!("CHERRY_PICK_HEAD is not a commit"))?,
+        },
+        None => Signature::author()?,
+    };
+    let commit = Commit {
+        tree,
+        parents: parent.into_iter().chain(merge_heads).collect(),
+        author,
+        committer: Signature::committer()?,
+        message,
+    };
+    let sha = commit.write_to_object_storage()?;
+    refs::update_ref("HEAD", &sha, Some(&parent.unwrap_or([0; 20])))?;
+    refs::clear_operation_state()?;
+    // The post-commit hook cannot affect the outcome of the commit.
+    if let Err(err) = hooks::run_hook("post-commit", &[]) {
+        eprintln!("warning: {}", err);
+    }
+
+    let branch = head_ref
+        .strip_prefix("refs/heads/")
+        .unwrap_or("detached HEAD");
+    let root = if parent.is_none() {
+        " (root-commit)"
+    } else {
+        ""
+    };
+    println!(
+        "[{}{} {}] {}",
+        branch,
+        root,
+        &ObjectStorage::sha_to_hex_string(&sha)[..7],
+        commit.message.lines().next().unwrap_or_default()
+    );
+    search_index::update()?;
+    gc::auto_gc()
+}
+
+/// Strips trailing whitespace from every line, collapses runs of blank lines and removes blank
+/// lines at the start and end, like git's default `whitespace` cleanup for `-m` messages.
+pub fn cleanup_message(message: &str) -> String {
+    let mut lines: Vec<&str> = vec![];
+    for line in message.lines().map(str::trim_end) {
+        if !(line.is_empty() && lines.last().is_none_or(|last| last.is_empty())) {
+            lines.push(line);
+        }
+    }
+    while lines.last().is_some_and(|last| last.is_empty()) {
+        lines.pop();
+    }
+    lines.join("\n")
+}
@@ -0,0 +1,25 @@
+use crate::cli;
+use crate::commit_graph;
+use crate::repository::CommandExit;
+
+/// `write` writes the commit-graph for the commits reachable from the refs; `verify` checks
+/// the one there is and exits with 1 when something is wrong with it.
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("commit-graph", args)?;
+    match args.operands.as_slice() {
+        [command] if command == "write" => {
+            commit_graph::write()?;
+        }
+        [command] if command == "verify" => {
+            let problems = commit_graph::verify()?;
+            for problem in &problems {
+                eprintln!("error: {}", problem);
+            }
+            if !problems.is_empty() {
+                Err(CommandExit { code: 1 })?
+            }
+        }
+        _ => return Err(cli::usage_error("commit-graph")),
+    }
+    Ok(())
+}
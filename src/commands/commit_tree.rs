@@ -0,0 +1,33 @@
+use crate::cli;
+use crate::object_storage::{ObjectStorage, Sha};
+use crate::revision;
+use anyhow::anyhow;
+use std::fs;
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("commit-tree", args)?;
+    let [tree] = args.operands.as_slice() else {
+        return Err(cli::usage_error("commit-tree"));
+    };
+    let tree_sha = ObjectStorage::peel_to_tree(&revision::resolve(tree)?)?;
+    let mut parents: Vec<Sha> = vec![];
+    for parent in args.values("-p") {
+        let sha = ObjectStorage::peel_to_commit(&revision::resolve(parent)?)?;
+        // git silently drops a parent given twice.
+        if !parents.contains(&sha) {
+            parents.push(sha);
+        }
+    }
+    let paragraphs = args.values("-m");
+    // Without -m or -F the message is read from standard input, as in git.
+    let message = match (paragraphs.is_empty(), args.value("-F")) {
+        (false, None) => paragraphs.join("\n\n"),
+        (true, None | Some("-")) => std::io::read_to_string(std::io::stdin())?,
+        (true, Some(file)) => fs::read_to_string(file)
+            .map_err(|err| anyhow!("could not read log file '{}': {}", file, err))?,
+        (false, Some(_)) => Err(anyhow!("options '-m' and '-F' cannot be used together"))?,
+    };
+    let sha = ObjectStorage::commit_tree(&tree_sha, &parents, message.trim_end_matches('\n'))?;
+    println!("{}", ObjectStorage::sha_to_hex_string(&sha));
+    Ok(())
+}
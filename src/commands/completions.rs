@@ -0,0 +1,14 @@
+use crate::cli;
+use crate::completions;
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("completions", args)?;
+    match (args.has("--refs"), args.operands.as_slice()) {
+        (true, []) => completions::print_ref_names(),
+        (false, [shell]) => {
+            print!("{}", completions::script(shell)?);
+            Ok(())
+        }
+        _ => Err(cli::usage_error("completions")),
+    }
+}
@@ -0,0 +1,54 @@
+use crate::cli;
+use crate::config::{self, Config};
+use crate::object_storage::ObjectStorage;
+use crate::repository::CommandExit;
+use anyhow::anyhow;
+use std::env;
+use std::path::PathBuf;
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("config", args)?;
+    let operands: Vec<&str> = args.operands.iter().map(String::as_str).collect();
+    let (name, value) = match (
+        args.has("--get"),
+        args.has("--set"),
+        args.has("--unset"),
+        operands.as_slice(),
+    ) {
+        (false, false, false, [name, value]) | (false, true, false, [name, value]) => {
+            (*name, Some(*value))
+        }
+        (false, false, true, [name]) => (*name, None),
+        (_, false, false, [name]) => return get(name),
+        _ => return Err(cli::usage_error("config")),
+    };
+    let (section, key) = split_name(name)?;
+    let config_path = if args.has("--global") {
+        match env::var_os("GIT_CONFIG_GLOBAL") {
+            Some(path) => PathBuf::from(path),
+            None => PathBuf::from(env::var_os("HOME").ok_or(anyhow!("$HOME is not set"))?)
+                .join(".gitconfig"),
+        }
+    } else {
+        ObjectStorage::git_dir().join("config")
+    };
+    config::set_value(&config_path, section, key, value)
+}
+
+/// Prints the value of `name` as every config file together gives it; like git, exits with
+/// status 1 when it is not set.
+fn get(name: &str) -> anyhow::Result<()> {
+    let (section, key) = split_name(name)?;
+    match Config::get()?.value(section, key) {
+        Some(value) => println!("{}", value),
+        None => Err(CommandExit { code: 1 })?,
+    }
+    Ok(())
+}
+
+/// Splits `remote.origin.url` into the section `remote.origin` and the key `url`.
+fn split_name(name: &str) -> anyhow::Result<(&str, &str)> {
+    name.rsplit_once('.')
+        .filter(|(section, _)| !section.is_empty())
+        .ok_or(anyhow!("key does not contain a section: {}", name))
+}
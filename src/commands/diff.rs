@@ -0,0 +1,225 @@
+use crate::cli;
+use crate::config::Config;
+use crate::diff::{self, FileChange, Snapshot};
+use crate::index::{self, Index};
+use crate::object_storage::{ObjectStorage, Sha, TreeEntryPermission};
+use crate::pathspec::PathspecSet;
+use crate::refs;
+use crate::repository::CommandExit;
+use crate::revision;
+use anyhow::anyhow;
+use std::fs;
+use std::io::{self, Write};
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("diff", args)?;
+    let context = match args.value("-U") {
+        Some(lines) => lines
+            .parse::<usize>()
+            .map_err(|_| anyhow!("invalid context length '{}'", lines))?,
+        None => 3,
+    };
+    let (changes, work_tree) = select_changes("diff", &args)?;
+    let mut out = io::stdout().lock();
+    if let Err(err) = write_changes(&mut out, &args, &changes, work_tree, context) {
+        // Stop quietly when the reader (e.g. `less`) goes away.
+        if err.kind() != io::ErrorKind::BrokenPipe {
+            Err(err)?
+        }
+    }
+    if args.has("--exit-code") && !changes.is_empty() {
+        Err(CommandExit { code: 1 })?
+    }
+    Ok(())
+}
+
+/// The changes `diff`, and `difftool`, show for the revisions and paths in `args`, and
+/// whether the new side is the working tree: the working tree against the index, or with
+/// `--cached` the index against `HEAD` or a revision, or two revisions against each other.
+/// Other combinations are a usage error of `command`.
+pub fn select_changes(
+    command: &str,
+    args: &cli::ParsedArgs,
+) -> anyhow::Result<(Vec<FileChange>, bool)> {
+    // Without `--`, leading operands that name revisions are revisions and the rest paths.
+    let split = args.separator.unwrap_or_else(|| {
+        args.operands
+            .iter()
+            .take_while(|operand| revision::resolve(operand).is_ok() || operand.contains(".."))
+            .count()
+    });
+    let (revisions, paths) = args.operands.split_at(split);
+    let revisions: Vec<&str> = match revisions {
+        [range] if range.contains("..") => {
+            // `A..B` compares two trees like `A B`; a missing side means HEAD.
+            let (from, to) = range.split_once("..").unwrap_or_default();
+            [from, to]
+                .into_iter()
+                .map(|side| if side.is_empty() { "HEAD" } else { side })
+                .collect()
+        }
+        revisions => revisions.iter().map(String::as_str).collect(),
+    };
+    let pathspecs = PathspecSet::from_args(paths)?;
+    let index = Index::read()?;
+    let (old, new, work_tree) = match (args.has("--cached"), revisions.as_slice()) {
+        (false, []) => (
+            diff::index_snapshot(&index),
+            work_tree_snapshot(&index)?,
+            true,
+        ),
+        (false, [revision]) => (tree(revision)?, work_tree_snapshot(&index)?, true),
+        (true, []) => (head_tree()?, diff::index_snapshot(&index), false),
+        (true, [revision]) => (tree(revision)?, diff::index_snapshot(&index), false),
+        (false, [from, to]) => (tree(from)?, tree(to)?, false),
+        _ => return Err(cli::usage_error(command)),
+    };
+    let changes: Vec<FileChange> = diff::diff_snapshots(&old, &new)
+        .into_iter()
+        .filter(|change| pathspecs.matches(&change.path, false))
+        .collect();
+    Ok((changes, work_tree))
+}
+
+fn write_changes(
+    out: &mut impl Write,
+    args: &cli::ParsedArgs,
+    changes: &[FileChange],
+    work_tree: bool,
+    context: usize,
+) -> io::Result<()> {
+    let big_file_threshold = Config::get()
+        .and_then(|config| config.big_file_threshold())
+        .map_err(io::Error::other)?;
+    // With -z, the status and each name end in NUL and paths are written as they are.
+    let (separator, end) = match args.has("-z") {
+        true => ('\0', '\0'),
+        false => ('\t', '\n'),
+    };
+    let name = |path: &str| match args.has("-z") {
+        true => path.to_string(),
+        false => cli::quote_path(path),
+    };
+    for change in changes {
+        if args.has("--name-only") {
+            write!(out, "{}{}", name(&change.path), end)?;
+            continue;
+        }
+        if args.has("--name-status") {
+            let letter = status_letter(change);
+            write!(out, "{}{}{}{}", letter, separator, name(&change.path), end)?;
+            continue;
+        }
+        let file = ObjectStorage::work_tree().join(&change.path);
+        let size = |sha: Option<Sha>, from_work_tree: bool| -> io::Result<u64> {
+            match sha {
+                None => Ok(0),
+                Some(_) if from_work_tree => Ok(fs::symlink_metadata(&file)?.len()),
+                Some(sha) => Ok(ObjectStorage::open_object(&sha)
+                    .map_err(io::Error::other)?
+                    .size),
+            }
+        };
+        let read = |sha, from_work_tree: bool| -> io::Result<Vec<u8>> {
+            match sha {
+                None => Ok(vec![]),
+                Some(_) if from_work_tree => {
+                    index::work_tree_content(&file, &fs::symlink_metadata(&file)?)
+                        .map_err(io::Error::other)
+                }
+                Some(sha) => ObjectStorage::read_object(&sha)
+                    .map(|(_, content)| content)
+                    .map_err(io::Error::other),
+            }
+        };
+        let too_big = size(change.old, false)? > big_file_threshold
+            || size(change.new, work_tree)? > big_file_threshold;
+        let (old, new) = if too_big {
+            (None, None)
+        } else {
+            (
+                Some(read(change.old, false)?),
+                Some(read(change.new, work_tree)?),
+            )
+        };
+        if status_letter(change) == 'T' {
+            // git shows a file replaced by a symlink (or the other way round) as a deletion
+            // and an addition.
+            let deletion = FileChange {
+                new: None,
+                new_mode: None,
+                path: change.path.clone(),
+                ..*change
+            };
+            let addition = FileChange {
+                old: None,
+                old_mode: None,
+                path: change.path.clone(),
+                ..*change
+            };
+            let old = old.as_deref().map(|old| (old, &[][..]));
+            let new = new.as_deref().map(|new| (&[][..], new));
+            diff::write_patch(out, &deletion, old, context)?;
+            diff::write_patch(out, &addition, new, context)?;
+        } else {
+            let contents = old.as_deref().zip(new.as_deref());
+            diff::write_patch(out, change, contents, context)?;
+        }
+    }
+    Ok(())
+}
+
+fn status_letter(change: &FileChange) -> char {
+    let is_symlink = |mode: TreeEntryPermission| mode == TreeEntryPermission::SymbolicLink;
+    match (change.old_mode, change.new_mode) {
+        (None, _) => 'A',
+        (_, None) => 'D',
+        (Some(old), Some(new)) if is_symlink(old) != is_symlink(new) => 'T',
+        _ => 'M',
+    }
+}
+
+fn tree(revision: &str) -> anyhow::Result<Snapshot> {
+    let tree = ObjectStorage::peel_to_tree(&revision::resolve(revision)?)?;
+    diff::tree_snapshot(Some(&tree))
+}
+
+/// The tree of `HEAD`, or no files before the first commit.
+fn head_tree() -> anyhow::Result<Snapshot> {
+    match refs::resolve_ref("HEAD")? {
+        (_, Some(head)) => diff::tree_snapshot(Some(&ObjectStorage::peel_to_tree(&head)?)),
+        (_, None) => Ok(Snapshot::new()),
+    }
+}
+
+/// The staged files as they are in the working tree, leaving out deleted ones. Files whose
+/// stat data still matches the index keep the staged blob id; the others are hashed.
+fn work_tree_snapshot(index: &Index) -> anyhow::Result<Snapshot> {
+    let work_tree = ObjectStorage::work_tree();
+    let mut snapshot = Snapshot::new();
+    for (path, staged) in diff::index_snapshot(index) {
+        let file = work_tree.join(&path);
+        let Ok(metadata) = fs::symlink_metadata(&file) else {
+            continue;
+        };
+        if metadata.is_dir() {
+            continue;
+        }
+        let staged_mode = index::tree_entry_mode(staged.0);
+        let Some(permission) =
+            index::mode_permission(index::staged_file_mode(&metadata, staged_mode))
+        else {
+            continue;
+        };
+        let unchanged = index
+            .find(&path)
+            .is_some_and(|entry| entry.is_up_to_date(&metadata));
+        let sha = if unchanged {
+            staged.1
+        } else {
+            index::hash_work_tree_file(&file, &metadata, false)?
+        };
+        snapshot.insert(path, (permission, sha));
+    }
+    Ok(snapshot)
+}
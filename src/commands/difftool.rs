@@ -0,0 +1,77 @@
+use super::diff::select_changes;
+use crate::cli;
+use crate::config::Config;
+use crate::merge_tools::{self, Mode, Tool, ToolFiles};
+use crate::object_storage::{ObjectStorage, Sha};
+use crate::repository::CommandExit;
+use crate::temp_file::TempFile;
+use std::env;
+use std::path::Path;
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("difftool", args)?;
+    if args.has("--tool-help") {
+        merge_tools::print_tool_help(Mode::Diff);
+        return Ok(());
+    }
+    let (changes, work_tree) = select_changes("difftool", &args)?;
+    if changes.is_empty() {
+        return Ok(());
+    }
+    let tool = Tool::find(Mode::Diff, args.value("-t"))?;
+    let config = Config::get()?;
+    let prompt = if args.has("-y") {
+        false
+    } else {
+        args.has("--prompt") || config.bool("difftool", "prompt").unwrap_or(true)
+    };
+    let trust_exit_code =
+        args.has("--trust-exit-code") || config.bool("difftool", "trustExitCode").unwrap_or(false);
+    let temp_dir = env::temp_dir();
+    for (i, change) in changes.iter().enumerate() {
+        if prompt {
+            let question = format!(
+                "\nViewing ({}/{}): '{}'\nLaunch '{}' [Y/n]? ",
+                i + 1,
+                changes.len(),
+                change.path,
+                tool.name
+            );
+            if !merge_tools::ask(&question, true)? {
+                continue;
+            }
+        }
+        let file_name = change.path.rsplit('/').next().unwrap_or(&change.path);
+        let suffix = format!("_{}", file_name);
+        let local = version(change.old, &temp_dir, &suffix)?;
+        // The working tree file is handed over as it is, so edits in the tool are kept.
+        let work_tree_file = ObjectStorage::work_tree().join(&change.path);
+        let remote = match change.new {
+            Some(_) if work_tree => None,
+            new => Some(version(new, &temp_dir, &suffix)?),
+        };
+        let remote_path = remote
+            .as_ref()
+            .map_or(work_tree_file.as_path(), TempFile::path);
+        let status = tool.run(&ToolFiles {
+            local: local.path(),
+            remote: remote_path,
+            merged: Path::new(&change.path),
+            base: None,
+        })?;
+        if trust_exit_code && !status.success() {
+            Err(CommandExit {
+                code: status.code().unwrap_or(1),
+            })?
+        }
+    }
+    Ok(())
+}
+
+/// One side of a change in a temporary file; a side that doesn't exist is an empty file.
+fn version(sha: Option<Sha>, dir: &Path, suffix: &str) -> anyhow::Result<TempFile> {
+    match sha {
+        Some(sha) => TempFile::from_blob(&sha, dir, "", suffix),
+        None => TempFile::create(dir, "", suffix, &mut &[][..]),
+    }
+}
@@ -0,0 +1,346 @@
+use crate::cancel::Cancelled;
+use crate::cli;
+use crate::commit_walk;
+use crate::config::Config;
+use crate::context;
+use crate::gc;
+use crate::object_storage::{ObjectStorage, Sha};
+use crate::quarantine::Quarantine;
+use crate::refglob;
+use crate::refs;
+use crate::repository::CommandExit;
+use crate::search_index;
+use crate::transport::{self, RemoteRefs};
+use anyhow::anyhow;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("fetch", args)?;
+    let refresh = args.has("--refresh");
+    let _interrupt_guard = transport::InterruptGuard::new();
+    if args.has("--all") {
+        if !args.operands.is_empty() {
+            return Err(cli::usage_error("fetch"));
+        }
+        let jobs = match args.value("--jobs") {
+            Some(jobs) => jobs
+                .parse::<usize>()
+                .map_err(|_| anyhow!("invalid number of jobs '{}'", jobs))?,
+            None => Config::get()?.int("fetch", "parallel")?.unwrap_or(1).max(0) as usize,
+        };
+        return fetch_all(jobs, refresh);
+    }
+    let name = match args.operands.as_slice() {
+        [] => default_remote()?,
+        [name] => name.clone(),
+        _ => return Err(cli::usage_error("fetch")),
+    };
+    download(&name, refresh)?.apply()?;
+    search_index::update()?;
+    gc::auto_gc()
+}
+
+/// Fetches from every remote not set to `skipFetchAll`, with up to `jobs` of them downloading
+/// at once, or one per CPU for 0. The main thread then takes the downloads in the order the
+/// remotes are configured, moving each out of its quarantine and updating its refs, so the
+/// output reads as if the remotes were fetched one after the other. A remote that fails is
+/// reported and the others are still fetched; the exit status is then 1.
+fn fetch_all(jobs: usize, refresh: bool) -> anyhow::Result<()> {
+    let mut names: Vec<String> = vec![];
+    let config = Config::get()?;
+    for name in config.subsections("remote") {
+        if config.bool(&format!("remote.{}", name), "skipFetchAll") != Some(true) {
+            names.push(name);
+        }
+    }
+    let jobs = match jobs {
+        0 => thread::available_parallelism().map_or(1, usize::from),
+        jobs => jobs,
+    };
+    let next = AtomicUsize::new(0);
+    let mut failed = false;
+    thread::scope(|scope| -> anyhow::Result<()> {
+        let (sender, receiver) = mpsc::channel();
+        for _ in 0..jobs.min(names.len()) {
+            let (sender, next, names) = (sender.clone(), &next, &names);
+            let context = context::current();
+            scope.spawn(move || {
+                context::enter(context, || {
+                    while let Some(name) = names.get(next.fetch_add(1, Ordering::SeqCst)) {
+                        // Nobody is listening any more once the main thread gave up.
+                        if sender.send((name, download(name, refresh))).is_err() {
+                            break;
+                        }
+                    }
+                })
+            });
+        }
+        drop(sender);
+        let mut downloaded: HashMap<&String, anyhow::Result<Fetched>> = HashMap::new();
+        for name in &names {
+            println!("Fetching {}", name);
+            let fetched = loop {
+                if let Some(fetched) = downloaded.remove(name) {
+                    break fetched;
+                }
+                let (name, fetched) = receiver.recv()?;
+                downloaded.insert(name, fetched);
+            };
+            if let Err(err) = fetched.and_then(Fetched::apply) {
+                if transport::interrupted() || err.is::<Cancelled>() {
+                    return Err(err);
+                }
+                eprintln!("fatal: {}", err);
+                eprintln!("error: could not fetch {}", name);
+                failed = true;
+            }
+        }
+        Ok(())
+    })?;
+    search_index::update()?;
+    gc::auto_gc()?;
+    if failed {
+        Err(CommandExit { code: 1 })?
+    }
+    Ok(())
+}
+
+/// Downloads what the remote `name` has that is not here yet into a quarantine. With
+/// `remote.<name>.refCacheTTL` or `fetch.refCacheTTL` set, and unless `refresh` is asked for,
+/// the refs the remote advertised last time are used while they are recent enough.
+fn download(name: &str, refresh: bool) -> anyhow::Result<Fetched> {
+    let section = format!("remote.{}", name);
+    let config = Config::get()?;
+    let url = config
+        .value(&section, "url")
+        .ok_or(anyhow!("'{}' does not appear to be a git repository", name))?;
+    let url = url.trim_end_matches('/');
+    let refspec = config
+        .value(&section, "fetch")
+        .unwrap_or(format!("+refs/heads/*:refs/remotes/{}/*", name));
+    let refspec = Refspec::parse(&refspec)?;
+    let max_age = match config.int(&section, "refCacheTTL")? {
+        Some(seconds) => seconds,
+        None => config.int("fetch", "refCacheTTL")?.unwrap_or(0),
+    };
+    let promisor = config.bool(&section, "promisor").unwrap_or(false);
+    let cache = ObjectStorage::git_dir().join("advertised-refs").join(name);
+
+    if max_age <= 0 || refresh {
+        let _ = fs::remove_file(&cache);
+        let remote = transport::discover_refs(url)?;
+        return receive(url, &remote, &refspec, promisor);
+    }
+    let max_age = Duration::from_secs(max_age as u64);
+    let (remote, cached) = transport::discover_refs_cached(url, &cache, max_age)?;
+    match receive(url, &remote, &refspec, promisor) {
+        // The refs may have moved on since they were cached, so that the server no longer
+        // has what we asked for: ask it again what it has.
+        Err(err) if cached => {
+            if transport::interrupted() {
+                return Err(err);
+            }
+            fs::remove_file(&cache)?;
+            let (remote, _) = transport::discover_refs_cached(url, &cache, max_age)?;
+            receive(url, &remote, &refspec, promisor)
+        }
+        result => result,
+    }
+}
+
+/// The remote of the current branch, or `origin`.
+fn default_remote() -> anyhow::Result<String> {
+    let remote = match refs::current_branch()? {
+        Some(branch) => Config::get()?.value(&format!("branch.{}", branch), "remote"),
+        None => None,
+    };
+    Ok(remote.unwrap_or_else(|| "origin".to_string()))
+}
+
+/// A `[+]<src>:<dst>` fetch refspec, where both sides may have one `*`.
+pub struct Refspec {
+    force: bool,
+    source: String,
+    destination: String,
+}
+
+impl Refspec {
+    pub fn parse(refspec: &str) -> anyhow::Result<Self> {
+        let (force, refspec) = match refspec.strip_prefix('+') {
+            Some(refspec) => (true, refspec),
+            None => (false, refspec),
+        };
+        let invalid = || anyhow!("invalid refspec '{}'", refspec);
+        let (source, destination) = refspec.split_once(':').ok_or_else(invalid)?;
+        let stars = |side: &str| side.matches('*').count();
+        if stars(source) > 1 || stars(source) != stars(destination) {
+            Err(invalid())?
+        }
+        Ok(Self {
+            force,
+            source: source.to_string(),
+            destination: destination.to_string(),
+        })
+    }
+
+    /// The local ref the remote ref `name` is fetched into, if the refspec covers it.
+    pub fn map(&self, name: &str) -> Option<String> {
+        let matched = refglob::match_star(&self.source, name)?;
+        Some(refglob::replace_star(&self.destination, matched))
+    }
+}
+
+/// What was received from a remote, waiting in quarantine until [`Fetched::apply`].
+struct Fetched {
+    url: String,
+    /// Whether the refspec allows updates that are not fast-forwards.
+    force: bool,
+    /// The remote name, local name and new value of each ref to update.
+    updates: Vec<(String, String, Sha)>,
+    quarantine: Option<Quarantine>,
+}
+
+/// Receives the refs `refspec` covers, along with the tags that point into what is fetched or
+/// already here. Everything the remote-tracking refs point to is sent as a `have` right away
+/// instead of negotiating over several requests. The pack from a `promisor` remote gets a
+/// `.promisor` file listing the refs it was fetched for.
+fn receive(
+    url: &str,
+    remote: &RemoteRefs,
+    refspec: &Refspec,
+    promisor: bool,
+) -> anyhow::Result<Fetched> {
+    let mut updates: Vec<(String, String, Sha)> = remote
+        .refs
+        .iter()
+        .filter(|(name, _)| !name.ends_with("^{}"))
+        .filter_map(|(name, sha)| Some((name.clone(), refspec.map(name)?, *sha)))
+        .collect();
+    let mut wants: Vec<Sha> = vec![];
+    for (_, _, sha) in &updates {
+        if !ObjectStorage::contains(sha)? {
+            wants.push(*sha);
+        }
+    }
+    // Follow tags that point at a commit being fetched or one we have.
+    let fetched: HashSet<Sha> = updates.iter().map(|(_, _, sha)| *sha).collect();
+    for (name, sha) in &remote.refs {
+        if !name.starts_with("refs/tags/") || name.ends_with("^{}") {
+            continue;
+        }
+        if refs::read_ref(name)?.is_some() || updates.iter().any(|(_, local, _)| local == name) {
+            continue;
+        }
+        let peeled_name = format!("{}^{{}}", name);
+        let peeled = remote
+            .refs
+            .iter()
+            .find(|(name, _)| *name == peeled_name)
+            .map_or(*sha, |(_, sha)| *sha);
+        if fetched.contains(&peeled) || ObjectStorage::contains(&peeled)? {
+            if !ObjectStorage::contains(sha)? {
+                wants.push(*sha);
+            }
+            updates.push((name.clone(), name.clone(), *sha));
+        }
+    }
+    wants.sort();
+    wants.dedup();
+
+    let mut quarantine = None;
+    if !wants.is_empty() {
+        let mut haves: Vec<Sha> = vec![];
+        for (name, sha) in refs::list_refs()? {
+            if name.starts_with("refs/remotes/") || name.starts_with("refs/heads/") {
+                haves.push(sha);
+            }
+        }
+        haves.sort();
+        haves.dedup();
+        let pack_data = transport::fetch_pack(url, remote, &wants, &haves)?;
+        let incoming = Quarantine::new()?;
+        let pack_path = incoming.write_pack(&pack_data)?;
+        if promisor {
+            let fetched: String = updates
+                .iter()
+                .map(|(name, _, sha)| {
+                    format!("{} {}\n", ObjectStorage::sha_to_hex_string(sha), name)
+                })
+                .collect();
+            fs::write(pack_path.with_extension("promisor"), fetched)?;
+        }
+        quarantine = Some(incoming);
+    }
+    Ok(Fetched {
+        url: url.to_string(),
+        force: refspec.force,
+        updates,
+        quarantine,
+    })
+}
+
+impl Fetched {
+    /// Moves the received objects into the object storage and updates the local refs.
+    fn apply(self) -> anyhow::Result<()> {
+        if let Some(quarantine) = self.quarantine {
+            quarantine.migrate()?;
+        }
+        let mut reported = false;
+        for (remote_name, local_name, sha) in &self.updates {
+            let old = refs::resolve_ref(local_name)?.1;
+            if old == Some(*sha) {
+                continue;
+            }
+            let is_tag = local_name.starts_with("refs/tags/");
+            let (flag, summary, note) = match old {
+                None if is_tag => ('*', "[new tag]".to_string(), ""),
+                None => ('*', "[new branch]".to_string(), ""),
+                Some(old) if commit_walk::is_ancestor(&old, sha)? => {
+                    (' ', format!("{}..{}", short(&old), short(sha)), "")
+                }
+                Some(_) if !self.force || is_tag => {
+                    ('!', "[rejected]".to_string(), "  (non-fast-forward)")
+                }
+                Some(old) => (
+                    '+',
+                    format!("{}...{}", short(&old), short(sha)),
+                    "  (forced update)",
+                ),
+            };
+            if !reported {
+                eprintln!("From {}", self.url);
+                reported = true;
+            }
+            eprintln!(
+                " {} {:<17} {:<10} -> {}{}",
+                flag,
+                summary,
+                short_name(remote_name),
+                short_name(local_name),
+                note
+            );
+            if flag == '!' {
+                continue;
+            }
+            refs::update_ref(local_name, sha, None)?;
+        }
+        Ok(())
+    }
+}
+
+fn short(sha: &Sha) -> String {
+    ObjectStorage::sha_to_hex_string(sha)[..7].to_string()
+}
+
+/// A ref name as `fetch` shows it: without `refs/heads/`, `refs/tags/` or `refs/remotes/`.
+pub fn short_name(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
@@ -0,0 +1,32 @@
+use crate::cli;
+use crate::merge_message::{self, MergeSource};
+use anyhow::anyhow;
+use std::fs;
+use std::io::{self, Write};
+
+/// Prints the message of a merge of the commits the `FETCH_HEAD`-style lines on stdin (or in
+/// `-F <file>`) name, as `merge` writes it: a title, which `-m` replaces, and with `--log` or
+/// `merge.log` a shortlog of what each of them brings in.
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("fmt-merge-msg", args)?;
+    if !args.operands.is_empty() {
+        return Err(cli::usage_error("fmt-merge-msg"));
+    }
+    let input = match args.value("-F") {
+        Some(file) => {
+            fs::read_to_string(file).map_err(|err| anyhow!("could not read '{}': {}", file, err))?
+        }
+        None => io::read_to_string(io::stdin())?,
+    };
+    let sources = MergeSource::parse_fetch_head(&input)?;
+    if sources.is_empty() {
+        return Ok(());
+    }
+    let message = merge_message::format(
+        &sources,
+        args.value("-m"),
+        merge_message::log_length(&args)?,
+    )?;
+    io::stdout().write_all(message.as_bytes())?;
+    Ok(())
+}
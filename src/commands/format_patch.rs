@@ -0,0 +1,410 @@
+use crate::cli;
+use crate::commit_walk;
+use crate::config::Config;
+use crate::diff::{self, FileChange};
+use crate::diffstat::{self, FileStat};
+use crate::mail;
+use crate::object_storage::{Commit, ObjectStorage, Sha, Signature, TreeEntryPermission};
+use crate::refs;
+use crate::revision;
+use anyhow::anyhow;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+/// The width of the diffstat and the shortlog, git's for mail.
+const MAIL_WRAP: usize = 72;
+/// The longest file name written, `.patch` included.
+const MAX_FILE_NAME: usize = 64;
+
+/// How the messages of a series are labelled: `[PATCH v2 3/5]`.
+struct Series {
+    prefix: String,
+    reroll: Option<String>,
+    numbered: bool,
+    total: usize,
+}
+
+impl Series {
+    fn subject_prefix(&self, number: usize) -> String {
+        let mut prefix = self.prefix.clone();
+        if let Some(reroll) = &self.reroll {
+            prefix.push_str(&format!(" v{}", reroll));
+        }
+        if self.numbered {
+            let width = self.total.to_string().len();
+            prefix.push_str(&format!(" {:0width$}/{}", number, self.total));
+        }
+        format!("[{}]", prefix)
+    }
+
+    fn file_name(&self, number: usize, subject: &str) -> String {
+        let mut name = String::new();
+        if let Some(reroll) = &self.reroll {
+            name.push_str(&format!("{}-", sanitize(&format!("v{}", reroll))));
+        }
+        name.push_str(&format!("{:04}-{}", number, subject));
+        name.truncate(MAX_FILE_NAME - ".patch".len() - 1);
+        name + ".patch"
+    }
+}
+
+/// Writes each commit of a range as a mail with its patch, ready for `am` or to be sent:
+/// `<since>` stands for `<since>..HEAD` and `-<n>` for the last n commits; merges are left
+/// out. Each goes to its own numbered file in the current directory or `-o`, or with
+/// `--stdout` they all go to stdout as one mbox. A series of more than one patch is numbered
+/// as `[PATCH 1/2]`; `-v <n>` marks a reroll, and `--cover-letter` adds a `0/<n>` message
+/// with the shortlog and diffstat of the series for its introduction. `--to` and `--cc` add
+/// recipients.
+pub fn run(raw_args: &[String]) -> anyhow::Result<()> {
+    let (counts, raw_args): (Vec<&String>, Vec<&String>) = raw_args.iter().partition(|arg| {
+        arg.strip_prefix('-')
+            .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+    });
+    let raw_args: Vec<String> = raw_args.into_iter().cloned().collect();
+    let args = cli::parse_args("format-patch", &raw_args)?;
+    let max_count = match counts.last() {
+        Some(count) => Some(count[1..].parse::<usize>()?),
+        None => None,
+    };
+    let (tips, excluded) = match (args.operands.as_slice(), max_count) {
+        ([], Some(_)) => (vec![head()?], vec![]),
+        ([range], _) => match range.split_once("..") {
+            Some((from, to)) => (vec![commit(to)?], vec![commit(from)?]),
+            None => (vec![head()?], vec![commit(range)?]),
+        },
+        _ => return Err(cli::usage_error("format-patch")),
+    };
+    let mut commits: Vec<(Sha, Commit)> = commit_walk::commits_between(&tips, &excluded)?
+        .into_iter()
+        .filter(|(_, commit)| commit.parents.len() <= 1)
+        .collect();
+    commits.truncate(max_count.unwrap_or(usize::MAX));
+    commits.reverse();
+    if commits.is_empty() {
+        return Ok(());
+    }
+
+    let cover_letter = args.has("--cover-letter");
+    let prefix = match args.value("--subject-prefix") {
+        Some(prefix) => prefix.to_string(),
+        None if args.has("--rfc") => "RFC PATCH".to_string(),
+        None => "PATCH".to_string(),
+    };
+    let series = Series {
+        prefix,
+        reroll: args.value("-v").map(str::to_string),
+        numbered: match args.last_of(&["-n", "-N"]) {
+            Some(option) => option == "-n",
+            None => commits.len() > 1 || cover_letter,
+        },
+        total: commits.len(),
+    };
+    let recipients = Recipients {
+        to: args.values("--to"),
+        cc: args.values("--cc"),
+    };
+
+    let mut messages: Vec<(String, Vec<u8>)> = vec![];
+    if cover_letter {
+        let name = series.file_name(0, "cover-letter");
+        messages.push((name, write_cover_letter(&commits, &series, &recipients)?));
+    }
+    for (number, (sha, commit)) in commits.iter().enumerate() {
+        let (subject, _) = split_message(&commit.message);
+        let name = series.file_name(number + 1, &sanitize(&subject));
+        let message = write_patch_mail(sha, commit, number + 1, &series, &recipients)?;
+        messages.push((name, message));
+    }
+
+    if args.has("--stdout") {
+        let mut out = io::stdout().lock();
+        for (_, message) in &messages {
+            out.write_all(message)?;
+        }
+        return Ok(());
+    }
+    let dir = args.value("-o").map(PathBuf::from).unwrap_or_default();
+    if !dir.as_os_str().is_empty() {
+        fs::create_dir_all(&dir)
+            .map_err(|err| anyhow!("could not create directory '{}': {}", dir.display(), err))?;
+    }
+    for (name, message) in messages {
+        let path = dir.join(name);
+        fs::write(&path, message)?;
+        println!("{}", path.display());
+    }
+    Ok(())
+}
+
+struct Recipients<'a> {
+    to: Vec<&'a str>,
+    cc: Vec<&'a str>,
+}
+
+fn head() -> anyhow::Result<Sha> {
+    refs::resolve_ref("HEAD")?
+        .1
+        .ok_or(anyhow!("your current branch does not have any commits yet"))
+}
+
+fn commit(revision: &str) -> anyhow::Result<Sha> {
+    if revision.is_empty() {
+        return head();
+    }
+    ObjectStorage::peel_to_commit(&revision::resolve(revision)?)
+}
+
+/// Splits a commit message into its subject, the first paragraph on one line, and the body.
+fn split_message(message: &str) -> (String, &str) {
+    let message = message.trim_start_matches('\n');
+    let (title, body) = message.split_once("\n\n").unwrap_or((message, ""));
+    let subject = title.lines().map(str::trim).collect::<Vec<_>>().join(" ");
+    (subject, body.trim_start_matches('\n'))
+}
+
+/// The subject as a file name: runs of characters other than letters, digits, `.` and `_`
+/// become a `-`, and no `.` or `-` is left at the end.
+fn sanitize(subject: &str) -> String {
+    let mut name = String::new();
+    let mut separated = false;
+    let mut previous = None;
+    for character in subject.chars() {
+        let title_char = character.is_ascii_alphanumeric() || matches!(character, '.' | '_');
+        if !title_char {
+            separated = !name.is_empty();
+        } else if !(character == '.' && previous == Some('.')) {
+            if separated {
+                name.push('-');
+                separated = false;
+            }
+            name.push(character);
+        }
+        previous = Some(character);
+    }
+    name.trim_end_matches(['.', '-']).to_string()
+}
+
+/// The `From ` line and headers of a message.
+fn write_headers(
+    out: &mut Vec<u8>,
+    sha: &Sha,
+    from: &Signature,
+    subject: &str,
+    recipients: &Recipients,
+    non_ascii: bool,
+) -> io::Result<()> {
+    writeln!(
+        out,
+        "From {} Mon Sep 17 00:00:00 2001",
+        ObjectStorage::sha_to_hex_string(sha)
+    )?;
+    writeln!(
+        out,
+        "{}",
+        mail::format_address("From", &from.name, &from.email)
+    )?;
+    writeln!(out, "Date: {}", from.format_rfc2822_date())?;
+    writeln!(out, "{}", subject)?;
+    for (header, addresses) in [("To", &recipients.to), ("Cc", &recipients.cc)] {
+        if !addresses.is_empty() {
+            writeln!(out, "{}: {}", header, addresses.join(",\n    "))?;
+        }
+    }
+    if non_ascii {
+        writeln!(
+            out,
+            "MIME-Version: 1.0\nContent-Type: text/plain; charset=UTF-8\nContent-Transfer-Encoding: 8bit"
+        )?;
+    }
+    writeln!(out)
+}
+
+fn write_signature(out: &mut Vec<u8>) -> io::Result<()> {
+    writeln!(out, "-- \n{}\n", env!("CARGO_PKG_VERSION"))
+}
+
+fn write_patch_mail(
+    sha: &Sha,
+    commit: &Commit,
+    number: usize,
+    series: &Series,
+    recipients: &Recipients,
+) -> anyhow::Result<Vec<u8>> {
+    let (subject, body) = split_message(&commit.message);
+    let non_ascii = [&commit.author.name, &commit.committer.name, &commit.message]
+        .iter()
+        .any(|text| !text.is_ascii());
+    let mut out = vec![];
+    let subject = mail::format_subject(&series.subject_prefix(number), &subject);
+    write_headers(
+        &mut out,
+        sha,
+        &commit.author,
+        &subject,
+        recipients,
+        non_ascii,
+    )?;
+    if !body.is_empty() {
+        writeln!(out, "{}", body.trim_end_matches('\n'))?;
+    }
+    writeln!(out, "---")?;
+    let parent_tree = match commit.parents.first() {
+        Some(parent) => Some(ObjectStorage::peel_to_tree(parent)?),
+        None => None,
+    };
+    let changes = diff::diff_trees(parent_tree.as_ref(), Some(&commit.tree))?;
+    let mut patch = vec![];
+    let mut stats = vec![];
+    for change in &changes {
+        let contents = read_contents(change)?;
+        stats.push(match &contents {
+            Some((old, new)) => FileStat::new(&change.path, old, new),
+            None => FileStat::binary(&change.path, size(change.old)?, size(change.new)?),
+        });
+        write_change(&mut patch, change, contents)?;
+    }
+    diffstat::write_stat(&mut out, &stats, MAIL_WRAP)?;
+    diffstat::write_summary(&mut out, &changes)?;
+    writeln!(out)?;
+    out.extend(patch);
+    write_signature(&mut out)?;
+    Ok(out)
+}
+
+/// The `0/<n>` message: placeholders for the subject and introduction, then the commits of
+/// each author and what the series changes as a whole.
+fn write_cover_letter(
+    commits: &[(Sha, Commit)],
+    series: &Series,
+    recipients: &Recipients,
+) -> anyhow::Result<Vec<u8>> {
+    let mut by_author: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for (_, commit) in commits {
+        by_author
+            .entry(&commit.author.name)
+            .or_default()
+            .push(split_message(&commit.message).0);
+    }
+    let mut shortlog = String::new();
+    for (author, subjects) in &by_author {
+        shortlog.push_str(&format!("{} ({}):\n", author, subjects.len()));
+        for subject in subjects {
+            shortlog.push_str(&wrap(subject, 2, 4, MAIL_WRAP));
+        }
+        shortlog.push('\n');
+    }
+
+    let (tip, _) = commits.last().unwrap();
+    let (_, first) = &commits[0];
+    let base_tree = match first.parents.first() {
+        Some(parent) => Some(ObjectStorage::peel_to_tree(parent)?),
+        None => None,
+    };
+    let tip_tree = ObjectStorage::peel_to_tree(tip)?;
+    let changes = diff::diff_trees(base_tree.as_ref(), Some(&tip_tree))?;
+    let mut stats = vec![];
+    for change in &changes {
+        stats.push(match read_contents(change)? {
+            Some((old, new)) => FileStat::new(&change.path, &old, &new),
+            None => FileStat::binary(&change.path, size(change.old)?, size(change.new)?),
+        });
+    }
+
+    let committer = Signature::committer()?;
+    let non_ascii = !committer.name.is_ascii() || !shortlog.is_ascii();
+    let subject = mail::format_subject(&series.subject_prefix(0), "*** SUBJECT HERE ***");
+    let mut out = vec![];
+    write_headers(&mut out, tip, &committer, &subject, recipients, non_ascii)?;
+    writeln!(out, "*** BLURB HERE ***\n")?;
+    out.extend_from_slice(shortlog.as_bytes());
+    diffstat::write_stat(&mut out, &stats, MAIL_WRAP)?;
+    diffstat::write_summary(&mut out, &changes)?;
+    writeln!(out)?;
+    write_signature(&mut out)?;
+    Ok(out)
+}
+
+/// Wraps `text` between words at `width` columns, indenting the first line by `first_indent`
+/// and the others by `indent`.
+fn wrap(text: &str, first_indent: usize, indent: usize, width: usize) -> String {
+    let mut wrapped = " ".repeat(first_indent);
+    let mut column = first_indent;
+    let mut line_start = true;
+    for word in text.split_whitespace() {
+        let length = word.chars().count();
+        if !line_start && column + 1 + length > width {
+            wrapped.push('\n');
+            wrapped.push_str(&" ".repeat(indent));
+            column = indent;
+            line_start = true;
+        }
+        if !line_start {
+            wrapped.push(' ');
+            column += 1;
+        }
+        wrapped.push_str(word);
+        column += length;
+        line_start = false;
+    }
+    wrapped.push('\n');
+    wrapped
+}
+
+fn size(sha: Option<Sha>) -> anyhow::Result<u64> {
+    match sha {
+        Some(sha) => Ok(ObjectStorage::open_object(&sha)?.size),
+        None => Ok(0),
+    }
+}
+
+/// Both sides of a change, or `None` when one is over `core.bigFileThreshold` and the change
+/// is shown as binary.
+fn read_contents(change: &FileChange) -> anyhow::Result<Option<(Vec<u8>, Vec<u8>)>> {
+    let threshold = Config::get()?.big_file_threshold()?;
+    if size(change.old)? > threshold || size(change.new)? > threshold {
+        return Ok(None);
+    }
+    let read = |sha: Option<Sha>| match sha {
+        Some(sha) => ObjectStorage::read_object(&sha).map(|(_, content)| content),
+        None => Ok(vec![]),
+    };
+    Ok(Some((read(change.old)?, read(change.new)?)))
+}
+
+/// Writes the patch of one change; a file replaced by a symlink, or the other way round, is
+/// a deletion and an addition, as in `diff`.
+fn write_change(
+    out: &mut Vec<u8>,
+    change: &FileChange,
+    contents: Option<(Vec<u8>, Vec<u8>)>,
+) -> io::Result<()> {
+    let is_symlink = |mode: Option<TreeEntryPermission>| {
+        mode.map(|mode| mode == TreeEntryPermission::SymbolicLink)
+    };
+    let type_changed = match (is_symlink(change.old_mode), is_symlink(change.new_mode)) {
+        (Some(old), Some(new)) => old != new,
+        _ => false,
+    };
+    if !type_changed {
+        let contents = contents.as_ref().map(|(old, new)| (&old[..], &new[..]));
+        return diff::write_patch(out, change, contents, 3);
+    }
+    let deletion = FileChange {
+        new: None,
+        new_mode: None,
+        path: change.path.clone(),
+        ..*change
+    };
+    let addition = FileChange {
+        old: None,
+        old_mode: None,
+        path: change.path.clone(),
+        ..*change
+    };
+    let old = contents.as_ref().map(|(old, _)| (&old[..], &[][..]));
+    let new = contents.as_ref().map(|(_, new)| (&[][..], &new[..]));
+    diff::write_patch(out, &deletion, old, 3)?;
+    diff::write_patch(out, &addition, new, 3)
+}
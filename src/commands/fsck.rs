@@ -0,0 +1,15 @@
+use crate::cli;
+use crate::fsck;
+use crate::repository::CommandExit;
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("fsck", args)?;
+    if !args.operands.is_empty() {
+        return Err(cli::usage_error("fsck"));
+    }
+    let status = fsck::fsck(!args.has("--no-dangling"))?;
+    if status != 0 {
+        Err(CommandExit { code: status })?
+    }
+    Ok(())
+}
@@ -0,0 +1,13 @@
+use crate::cli;
+use crate::gc;
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("gc", args)?;
+    if !args.operands.is_empty() {
+        return Err(cli::usage_error("gc"));
+    }
+    if args.has("--auto") && !gc::needs_gc()? {
+        return Ok(());
+    }
+    gc::run_gc(args.has("--auto"), args.has("--auto"))
+}
@@ -0,0 +1,37 @@
+use crate::cli;
+use crate::object_storage::ObjectStorage;
+use anyhow::anyhow;
+use std::fs::File;
+use std::io::Read;
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("hash-object", args)?;
+    let object_type = args.value("-t").unwrap_or("blob");
+    let write = args.has("-w");
+    let literally = args.has("--literally");
+    if args.operands.is_empty() && !args.has("--stdin") {
+        return Err(cli::usage_error("hash-object"));
+    }
+    // Like git, standard input is hashed before any files.
+    if args.has("--stdin") {
+        let mut content = vec![];
+        std::io::stdin().read_to_end(&mut content)?;
+        let sha = ObjectStorage::hash_object(object_type, &content, write, literally)?;
+        println!("{}", ObjectStorage::sha_to_hex_string(&sha));
+    }
+    for path in &args.operands {
+        let unreadable = |err| anyhow!("could not open '{}' for reading: {}", path, err);
+        let mut file = File::open(path).map_err(unreadable)?;
+        // Blobs need no validation, so they are streamed instead of read into memory.
+        let sha = if object_type == "blob" {
+            let size = file.metadata()?.len();
+            ObjectStorage::stream_object("blob", size, &mut file, write)?
+        } else {
+            let mut content = vec![];
+            file.read_to_end(&mut content).map_err(unreadable)?;
+            ObjectStorage::hash_object(object_type, &content, write, literally)?
+        };
+        println!("{}", ObjectStorage::sha_to_hex_string(&sha));
+    }
+    Ok(())
+}
@@ -0,0 +1,22 @@
+use crate::cli;
+use crate::config::Config;
+use anyhow::anyhow;
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("help", args)?;
+    match args.operands.as_slice() {
+        [] => print!("{}", cli::general_usage()),
+        [name] => match cli::find_command(name) {
+            Some(command) => print!("{}", cli::command_usage(command)),
+            None => {
+                if let Some(alias) = Config::get()?.value("alias", name) {
+                    println!("'{}' is aliased to '{}'", name, alias);
+                } else {
+                    Err(anyhow!("no help available for '{}'", name))?
+                }
+            }
+        },
+        _ => return Err(cli::usage_error("help")),
+    }
+    Ok(())
+}
@@ -0,0 +1,125 @@
+use crate::cli::{self, ParsedArgs};
+use crate::config::{self, Config};
+use crate::context;
+use crate::object_storage::{self, ObjectStorage};
+use crate::shared_repository::{self, Sharing};
+use anyhow::anyhow;
+use std::env;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{self, Path, PathBuf};
+
+/// Creates the repository with the content of the template directory, on the branch
+/// `--initial-branch` or `init.defaultBranch` names; `--shared` makes it
+/// writable by the group (or everybody, or with the given mode) through
+/// `core.sharedRepository`.
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("init", args)?;
+    if !args.operands.is_empty() {
+        return Err(cli::usage_error("init"));
+    }
+    let sharing = match args.values_or("--shared", "group").pop() {
+        Some(permissions) => Sharing::parse(permissions)?,
+        None => Sharing::Umask,
+    };
+    // Like git, `GIT_DIR` (or `--git-dir`) says where to create the repository.
+    let git_dir = env::var_os("GIT_DIR").map_or_else(|| PathBuf::from(".git"), PathBuf::from);
+    let context = context::current().for_repository(path::absolute(git_dir)?, None);
+    context::enter(context, || init(&args, sharing))
+}
+
+fn init(args: &ParsedArgs, sharing: Sharing) -> anyhow::Result<()> {
+    ObjectStorage::init_cwd(args.value("-b"))?;
+    let copied = copy_templates(args.value("--template"))?;
+    if sharing != Sharing::Umask {
+        let git_dir = ObjectStorage::git_dir();
+        config::set_value(
+            &git_dir.join("config"),
+            "core",
+            "sharedRepository",
+            Some(&sharing.config_value()),
+        )?;
+        for path in ["", "objects", "refs", "HEAD", "config"] {
+            shared_repository::adjust(&git_dir.join(path))?;
+        }
+        for path in &copied {
+            shared_repository::adjust(path)?;
+        }
+    }
+    println!("Initialized git directory");
+    Ok(())
+}
+
+/// Copies the template directory into the new git directory, as git does for `init` and
+/// `clone`: hooks, `info/exclude`, a `config` and whatever else it has, leaving out names
+/// starting with a dot and never replacing what is there. The directory is `template`
+/// (from `--template`), or else `GIT_TEMPLATE_DIR`, or else `init.templateDir` from the
+/// system or global config; an empty one means no templates. Returns the paths created.
+pub fn copy_templates(template: Option<&str>) -> anyhow::Result<Vec<PathBuf>> {
+    let template = match template {
+        Some(template) => Some(template.to_string()),
+        None => match env::var("GIT_TEMPLATE_DIR") {
+            Ok(template) => Some(template),
+            Err(_) => Config::get()?.protected_values("init", "templateDir").pop(),
+        },
+    };
+    let Some(template) = template.filter(|template| !template.is_empty()) else {
+        return Ok(vec![]);
+    };
+    let template = object_storage::expand_home_dir(&template);
+    if !template.is_dir() {
+        eprintln!("warning: templates not found in {}", template.display());
+        return Ok(vec![]);
+    }
+    let mut copied = vec![];
+    copy_directory(&template, &ObjectStorage::git_dir(), &mut copied).map_err(|err| {
+        anyhow!(
+            "cannot copy templates from '{}': {}",
+            template.display(),
+            err
+        )
+    })?;
+    // The templates may have brought a config.
+    Config::invalidate();
+    Ok(copied)
+}
+
+fn copy_directory(from: &Path, to: &Path, copied: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+    let mut entries = fs::read_dir(from)?.collect::<Result<Vec<_>, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        if entry.file_name().as_encoded_bytes().starts_with(b".") {
+            continue;
+        }
+        let (source, target) = (entry.path(), to.join(entry.file_name()));
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            match fs::create_dir(&target) {
+                Ok(()) => copied.push(target.clone()),
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => {}
+                Err(err) => Err(err)?,
+            }
+            copy_directory(&source, &target, copied)?;
+        } else if fs::symlink_metadata(&target).is_ok() {
+            continue;
+        } else if file_type.is_symlink() {
+            copy_symlink(&source, &target)?;
+            copied.push(target);
+        } else {
+            // `fs::copy` keeps the permissions, so hooks stay executable.
+            fs::copy(&source, &target)?;
+            copied.push(target);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn copy_symlink(source: &Path, target: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(fs::read_link(source)?, target)
+}
+
+#[cfg(not(unix))]
+fn copy_symlink(source: &Path, target: &Path) -> std::io::Result<()> {
+    fs::copy(source, target).map(|_| ())
+}
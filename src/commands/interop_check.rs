@@ -0,0 +1,168 @@
+use crate::cli;
+use crate::index::Index;
+use crate::object_storage::{GitObject, ObjectStorage, Sha, pack};
+use crate::repository::CommandExit;
+use crate::trace2;
+use anyhow::anyhow;
+use std::collections::HashSet;
+use std::fs;
+use std::process::Command;
+
+/// Checks that this tool writes what system git writes, byte for byte: every tree, commit and
+/// tag in the repository is parsed and written again and compared with what is stored, the
+/// index of every pack is built again and compared with the `.idx` file next to it, and the
+/// tree written from the index is compared with the one `git write-tree` writes. Each
+/// difference is reported with the first byte at which the two differ, and the command exits
+/// with 1 when there is one. Git runs with `LC_ALL=C`, so its output does not depend on the
+/// locale.
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("interop-check", args)?;
+    if !args.operands.is_empty() {
+        return Err(cli::usage_error("interop-check"));
+    }
+    let git = args.value("--git").unwrap_or("git");
+    let mut check = Check::default();
+    let mut seen: HashSet<Sha> = HashSet::new();
+    for (sha, _) in ObjectStorage::loose_objects()? {
+        if seen.insert(sha) {
+            check.object(&sha)?;
+        }
+    }
+    for pack in pack::loaded_packs()?.iter() {
+        for sha in pack.shas() {
+            if seen.insert(*sha) {
+                check.object(sha)?;
+            }
+        }
+        check.pack_index(pack)?;
+    }
+    if Index::path().exists() {
+        check.write_tree(git)?;
+    }
+    println!(
+        "checked {} trees, {} commits, {} tags and {} pack indexes: {} differences",
+        check.trees, check.commits, check.tags, check.packs, check.differences
+    );
+    if check.differences > 0 {
+        Err(CommandExit { code: 1 })?
+    }
+    Ok(())
+}
+
+#[derive(Default)]
+struct Check {
+    trees: usize,
+    commits: usize,
+    tags: usize,
+    packs: usize,
+    differences: usize,
+}
+
+impl Check {
+    /// Writes a tree, commit or tag again from its parsed form. Blobs are written as they are,
+    /// so there is nothing to compare.
+    fn object(&mut self, sha: &Sha) -> anyhow::Result<()> {
+        let (object_type, content) = ObjectStorage::read_object(sha)?;
+        let name = format!("{} {}", object_type, ObjectStorage::sha_to_hex_string(sha));
+        let ours = match GitObject::from_parts(&object_type, &content) {
+            Ok(GitObject::Tree(tree)) => {
+                self.trees += 1;
+                tree.serialize()
+            }
+            Ok(GitObject::Commit(commit)) => {
+                self.commits += 1;
+                commit.serialize()?
+            }
+            Ok(GitObject::Tag(tag)) => {
+                self.tags += 1;
+                tag.serialize()
+            }
+            Ok(GitObject::Blob(_)) => return Ok(()),
+            Err(err) => {
+                println!("{}: cannot be read: {}", name, err);
+                self.differences += 1;
+                return Ok(());
+            }
+        };
+        self.compare(&name, &content, &ours);
+        Ok(())
+    }
+
+    fn pack_index(&mut self, pack: &pack::Pack) -> anyhow::Result<()> {
+        self.packs += 1;
+        let idx_path = pack.pack_path().with_extension("idx");
+        let name = idx_path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        let ours = pack::index_pack(&fs::read(pack.pack_path())?)?;
+        self.compare(&name, &fs::read(&idx_path)?, &ours);
+        Ok(())
+    }
+
+    /// Both trees end up in the object store, so they can be compared whenever the ids differ.
+    fn write_tree(&mut self, git: &str) -> anyhow::Result<()> {
+        let mut command = Command::new(git);
+        trace2::child_env(&mut command);
+        let output = command
+            .arg("write-tree")
+            .env("LC_ALL", "C")
+            .env("GIT_DIR", ObjectStorage::git_dir())
+            .env("GIT_WORK_TREE", ObjectStorage::work_tree())
+            .env("GIT_INDEX_FILE", Index::path())
+            .current_dir(ObjectStorage::work_tree())
+            .output()
+            .map_err(|err| anyhow!("cannot run {}: {}", git, err))?;
+        if !output.status.success() {
+            println!(
+                "write-tree: skipped, {} write-tree failed: {}",
+                git,
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            );
+            return Ok(());
+        }
+        let theirs = ObjectStorage::hex_string_to_sha(String::from_utf8(output.stdout)?.trim())?;
+        let ours = ObjectStorage::write_index_tree(&Index::read()?)?;
+        if theirs != ours {
+            println!(
+                "write-tree: git wrote {}, this tool {}",
+                ObjectStorage::sha_to_hex_string(&theirs),
+                ObjectStorage::sha_to_hex_string(&ours)
+            );
+            let (_, theirs) = ObjectStorage::read_object(&theirs)?;
+            let (_, ours) = ObjectStorage::read_object(&ours)?;
+            self.compare("write-tree", &theirs, &ours);
+        }
+        Ok(())
+    }
+
+    fn compare(&mut self, name: &str, theirs: &[u8], ours: &[u8]) {
+        let Some(offset) = first_difference(theirs, ours) else {
+            return;
+        };
+        let byte = |content: &[u8]| match content.get(offset) {
+            Some(byte) => format!("{:#04x}", byte),
+            None => "end of data".to_string(),
+        };
+        println!(
+            "{}: differs at byte {} of {} (git {}, this tool {})",
+            name,
+            offset,
+            theirs.len(),
+            byte(theirs),
+            byte(ours)
+        );
+        self.differences += 1;
+    }
+}
+
+/// Where `a` and `b` first differ, which is the end of the shorter one when it is the start of
+/// the other.
+fn first_difference(a: &[u8], b: &[u8]) -> Option<usize> {
+    match a.iter().zip(b).position(|(a, b)| a != b) {
+        Some(offset) => Some(offset),
+        None if a.len() != b.len() => Some(a.len().min(b.len())),
+        None => None,
+    }
+}
@@ -0,0 +1,386 @@
+use crate::cli;
+use crate::diff::{self, Edit};
+use crate::object_storage::{Commit, GitObject, ObjectStorage, Sha};
+use crate::oid_set::OidSet;
+use crate::promisor;
+use crate::refglob;
+use crate::refs;
+use crate::revision;
+use crate::search_index::{IndexedCommit, SearchIndex};
+use anyhow::anyhow;
+use regex::bytes::{Regex, RegexBuilder};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::Write;
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("log", args)?;
+    let pickaxe = match (args.value("-S"), args.value("-G")) {
+        (Some(_), Some(_)) => Err(anyhow!("options '-G' and '-S' cannot be used together"))?,
+        (Some(""), None) | (None, Some("")) => Err(anyhow!("pickaxe requires a search string"))?,
+        (Some(text), None) if args.has("--pickaxe-regex") => {
+            Some(Pickaxe::CountRegex(Regex::new(text).map_err(|err| {
+                anyhow!("invalid regex given to -S: {}", err)
+            })?))
+        }
+        (Some(text), None) => Some(Pickaxe::Count(text.as_bytes().to_vec())),
+        (None, Some(pattern)) => {
+            Some(Pickaxe::Grep(Regex::new(pattern).map_err(|err| {
+                anyhow!("invalid regex given to -G: {}", err)
+            })?))
+        }
+        (None, None) => None,
+    };
+    let filter = MessageFilter::new(
+        &args.values("--author"),
+        &args.values("--grep"),
+        args.has("-i"),
+    )?;
+    // The search index spares reading commits that cannot match, and their parents for the
+    // walk. Commits it does not have yet are read as usual.
+    let index = if filter.is_empty() {
+        None
+    } else {
+        SearchIndex::load()?
+    };
+    let format = match args.value("--pretty").unwrap_or("medium") {
+        "medium" => Format::Medium,
+        "raw" => Format::Raw,
+        other => Err(anyhow!("invalid --pretty format: {}", other))?,
+    };
+    let show_parents = args.has("--parents");
+    let tips = tips(&args)?;
+    let mut out = std::io::stdout().lock();
+    // Walk newest-first by committer date so merged lines of history interleave like git's
+    // default order; the sequence number keeps ties in discovery order.
+    let mut queue: BinaryHeap<(i64, Reverse<usize>, Sha)> = BinaryHeap::new();
+    let mut seen = OidSet::new()?;
+    let mut sequence = 0;
+    let mut push = |queue: &mut BinaryHeap<_>, sha: Sha| -> anyhow::Result<()> {
+        if !seen.insert(sha) {
+            return Ok(());
+        }
+        if let Some(indexed) = index.as_ref().and_then(|index| index.get(&sha)) {
+            queue.push((indexed.time, Reverse(sequence), sha));
+            sequence += 1;
+        } else if !promisor::may_skip(&sha)? {
+            let commit = GitObject::read_commit(&sha)?;
+            queue.push((commit.committer.time, Reverse(sequence), sha));
+            sequence += 1;
+        }
+        Ok(())
+    };
+    for tip in tips {
+        push(&mut queue, tip)?;
+    }
+    let mut first = true;
+    while let Some((_, _, sha)) = queue.pop() {
+        let indexed = index.as_ref().and_then(|index| index.get(&sha));
+        if let Some(indexed) = indexed {
+            for parent in &indexed.parents {
+                push(&mut queue, *parent)?;
+            }
+            if !filter.may_match(indexed) {
+                continue;
+            }
+        }
+        let GitObject::Commit(commit) = GitObject::from_sha(&sha)? else {
+            continue;
+        };
+        if indexed.is_none() {
+            for parent in &commit.parents {
+                push(&mut queue, *parent)?;
+            }
+        }
+        if !filter.matches(&commit) {
+            continue;
+        }
+        if let Some(pickaxe) = &pickaxe
+            && !pickaxe.matches(&commit)?
+        {
+            continue;
+        }
+        let mut entry = String::new();
+        if !first {
+            entry.push('\n');
+        }
+        first = false;
+        entry.push_str(&format!(
+            "commit {}",
+            ObjectStorage::sha_to_hex_string(&sha)
+        ));
+        if show_parents {
+            for parent in &commit.parents {
+                entry.push_str(&format!(" {}", ObjectStorage::sha_to_hex_string(parent)));
+            }
+        }
+        entry.push('\n');
+        match format {
+            Format::Medium => {
+                if commit.parents.len() > 1 {
+                    let parents: Vec<String> = commit
+                        .parents
+                        .iter()
+                        .map(|parent| ObjectStorage::sha_to_hex_string(parent)[..7].to_string())
+                        .collect();
+                    entry.push_str(&format!("Merge: {}\n", parents.join(" ")));
+                }
+                entry.push_str(&format!(
+                    "Author: {} <{}>\nDate:   {}\n",
+                    commit.author.name,
+                    commit.author.email,
+                    commit.author.format_date()
+                ));
+            }
+            Format::Raw => entry.push_str(&raw_headers(&sha)?),
+        }
+        entry.push('\n');
+        for line in commit.message.trim_end_matches('\n').lines() {
+            entry.push_str(&format!("    {}\n", line));
+        }
+        if let Err(err) = out.write_all(entry.as_bytes()) {
+            // Stop quietly when the reader (e.g. `head`) goes away.
+            if err.kind() == std::io::ErrorKind::BrokenPipe {
+                return Ok(());
+            }
+            Err(err)?
+        }
+    }
+    Ok(())
+}
+
+/// How each commit is shown, as `--pretty` names it.
+#[derive(Clone, Copy)]
+enum Format {
+    /// The id, author and date, then the message.
+    Medium,
+    /// The id, the headers as the commit object stores them, then the message: what tools
+    /// that draw the history, like gitk and tig, read.
+    Raw,
+}
+
+/// The header lines of the commit `sha` exactly as stored, signatures and all, each ending
+/// in a newline.
+fn raw_headers(sha: &Sha) -> anyhow::Result<String> {
+    let (_, content) = ObjectStorage::read_object(sha)?;
+    let end = match content.windows(2).position(|pair| pair == b"\n\n") {
+        Some(end) => end + 1,
+        None => content.len(),
+    };
+    Ok(String::from_utf8_lossy(&content[..end]).into_owned())
+}
+
+/// The options that pick refs to walk from, and `--exclude`, which leaves refs out of the
+/// next of them.
+const REF_OPTIONS: &[&str] = &[
+    "--all",
+    "--branches",
+    "--tags",
+    "--remotes",
+    "--glob",
+    "--exclude",
+];
+
+/// The commits to walk back from: those the refs `--all`, `--branches`, `--tags`,
+/// `--remotes` and `--glob` pick point to, then the revisions given, or else `HEAD`.
+fn tips(args: &cli::ParsedArgs) -> anyhow::Result<Vec<Sha>> {
+    let mut tips: Vec<Sha> = vec![];
+    let mut excludes = refglob::Excludes::default();
+    for (option, value) in args.in_order(REF_OPTIONS) {
+        // --exclude patterns are matched against the names git gives the option: full names
+        // for --all and --glob, names under the prefix for the others.
+        let (pattern, prefix) = match option {
+            "--exclude" => {
+                excludes.add(value.unwrap_or_default());
+                continue;
+            }
+            "--all" => ("*", None),
+            "--branches" => (value.unwrap_or("*"), Some("refs/heads/")),
+            "--tags" => (value.unwrap_or("*"), Some("refs/tags/")),
+            "--remotes" => (value.unwrap_or("*"), Some("refs/remotes/")),
+            _ => (value.unwrap_or_default(), None),
+        };
+        for (name, sha) in refglob::matching_refs(pattern, prefix)? {
+            if !excludes.excludes(&name) {
+                tips.push(sha);
+            }
+        }
+        // Ties in the walk go to the tips found first, and git adds HEAD after the refs.
+        if option == "--all"
+            && let (_, Some(head)) = refs::resolve_ref("HEAD")?
+            && !excludes.excludes("HEAD")
+        {
+            tips.push(head);
+        }
+        excludes.clear();
+    }
+    // Refs may point at trees and blobs, which have no history to show.
+    let mut tips: Vec<Sha> = tips
+        .iter()
+        .filter_map(|sha| ObjectStorage::peel_to_commit(sha).ok())
+        .collect();
+    for revision in &args.operands {
+        tips.push(ObjectStorage::peel_to_commit(&revision::resolve(
+            revision,
+        )?)?);
+    }
+    if tips.is_empty()
+        && !REF_OPTIONS
+            .iter()
+            .any(|option| *option != "--exclude" && args.has(option))
+    {
+        match refs::resolve_ref("HEAD")? {
+            (_, Some(sha)) => tips.push(sha),
+            (branch, None) => Err(anyhow!(
+                "your current branch '{}' does not have any commits yet",
+                branch.strip_prefix("refs/heads/").unwrap_or(&branch)
+            ))?,
+        }
+    }
+    Ok(tips)
+}
+
+/// `--author` and `--grep`: a commit must match one of the author patterns, if there are any,
+/// and one of the message patterns, if there are any, as in git.
+struct MessageFilter {
+    authors: Vec<Regex>,
+    greps: Vec<Regex>,
+    /// For each `--grep` pattern that is a plain word, the word lowercased: a message that
+    /// matches has a word containing it, which the search index can tell.
+    grep_words: Vec<Option<String>>,
+}
+
+impl MessageFilter {
+    fn new(authors: &[&str], greps: &[&str], ignore_case: bool) -> anyhow::Result<Self> {
+        let compile = |option: &str, pattern: &str| {
+            RegexBuilder::new(pattern)
+                .case_insensitive(ignore_case)
+                .multi_line(true)
+                .build()
+                .map_err(|err| anyhow!("invalid regex given to {}: {}", option, err))
+        };
+        Ok(Self {
+            authors: authors
+                .iter()
+                .map(|pattern| compile("--author", pattern))
+                .collect::<anyhow::Result<_>>()?,
+            greps: greps
+                .iter()
+                .map(|pattern| compile("--grep", pattern))
+                .collect::<anyhow::Result<_>>()?,
+            grep_words: greps
+                .iter()
+                .map(|pattern| {
+                    let is_word = !pattern.is_empty() && pattern.chars().all(char::is_alphanumeric);
+                    is_word.then(|| pattern.to_lowercase())
+                })
+                .collect(),
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.authors.is_empty() && self.greps.is_empty()
+    }
+
+    fn matches(&self, commit: &Commit) -> bool {
+        let author = format!("{} <{}>", commit.author.name, commit.author.email);
+        (self.authors.is_empty()
+            || self
+                .authors
+                .iter()
+                .any(|regex| regex.is_match(author.as_bytes())))
+            && (self.greps.is_empty()
+                || self
+                    .greps
+                    .iter()
+                    .any(|regex| regex.is_match(commit.message.as_bytes())))
+    }
+
+    /// Whether the commit the index describes can match; `matches` has the final say.
+    fn may_match(&self, indexed: &IndexedCommit) -> bool {
+        let author_matches = self.authors.is_empty()
+            || self
+                .authors
+                .iter()
+                .any(|regex| regex.is_match(indexed.author.as_bytes()));
+        let message_may_match = self.greps.is_empty()
+            || self.grep_words.iter().any(|word| match word {
+                Some(word) => indexed
+                    .words
+                    .iter()
+                    .any(|indexed| indexed.contains(word.as_str())),
+                None => true,
+            });
+        author_matches && message_may_match
+    }
+}
+
+/// `-S` and `-G`: only show commits that change how often a string occurs in a file, or that
+/// add or remove a line matching a regex.
+enum Pickaxe {
+    Count(Vec<u8>),
+    CountRegex(Regex),
+    Grep(Regex),
+}
+
+impl Pickaxe {
+    /// Compares the commit with its parent file by file and stops at the first file that
+    /// matches. Like git without `-m`, merges are never matched.
+    fn matches(&self, commit: &Commit) -> anyhow::Result<bool> {
+        let parent_tree = match commit.parents.as_slice() {
+            [] => None,
+            [parent] => Some(ObjectStorage::peel_to_tree(parent)?),
+            _ => return Ok(false),
+        };
+        for change in diff::diff_trees(parent_tree.as_ref(), Some(&commit.tree))? {
+            let read = |sha: Option<Sha>| -> anyhow::Result<Vec<u8>> {
+                match sha {
+                    Some(sha) => Ok(ObjectStorage::read_object(&sha)?.1),
+                    None => Ok(vec![]),
+                }
+            };
+            let (old, new) = (read(change.old)?, read(change.new)?);
+            let found = match self {
+                Pickaxe::Count(needle) => count(&old, needle) != count(&new, needle),
+                Pickaxe::CountRegex(regex) => {
+                    regex.find_iter(&old).count() != regex.find_iter(&new).count()
+                }
+                Pickaxe::Grep(regex) => {
+                    !diff::is_binary(&old)
+                        && !diff::is_binary(&new)
+                        && changed_line_matches(&old, &new, regex)
+                }
+            };
+            if found {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// Non-overlapping occurrences of `needle` in `haystack`.
+fn count(haystack: &[u8], needle: &[u8]) -> usize {
+    let mut occurrences = 0;
+    let mut position = 0;
+    while position + needle.len() <= haystack.len() {
+        if haystack[position..].starts_with(needle) {
+            occurrences += 1;
+            position += needle.len();
+        } else {
+            position += 1;
+        }
+    }
+    occurrences
+}
+
+fn changed_line_matches(old: &[u8], new: &[u8], regex: &Regex) -> bool {
+    let (old_lines, new_lines) = (diff::split_lines(old), diff::split_lines(new));
+    diff::diff_lines(&old_lines, &new_lines)
+        .into_iter()
+        .any(|edit| match edit {
+            Edit::Delete(i) => regex.is_match(old_lines[i]),
+            Edit::Insert(i) => regex.is_match(new_lines[i]),
+            Edit::Equal(..) => false,
+        })
+}
@@ -0,0 +1,129 @@
+use crate::cli;
+use crate::format;
+use crate::object_storage::{ObjectStorage, Sha, TreeEntryPermission};
+use crate::pathspec::PathspecSet;
+use crate::revision;
+use crate::tree_walk::TreeWalk;
+use anyhow::anyhow;
+use std::io::Write;
+
+struct LsTreeOptions {
+    only_trees: bool,
+    recursive: bool,
+    show_trees: bool,
+    long: bool,
+    name_only: bool,
+    null_terminated: bool,
+    format: Option<String>,
+    pathspecs: PathspecSet,
+}
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("ls-tree", args)?;
+    let Some((hash, paths)) = args.operands.split_first() else {
+        return Err(cli::usage_error("ls-tree"));
+    };
+    if args.has("--format") && (args.has("-l") || args.has("--name-only")) {
+        Err(anyhow!(
+            "--format can't be combined with other format-altering options"
+        ))?
+    }
+    let options = LsTreeOptions {
+        only_trees: args.has("-d"),
+        recursive: args.has("-r"),
+        show_trees: args.has("-t"),
+        long: args.has("-l"),
+        name_only: args.has("--name-only"),
+        null_terminated: args.has("-z"),
+        format: args.value("--format").map(str::to_string),
+        pathspecs: PathspecSet::from_args(paths)?,
+    };
+    let sha = ObjectStorage::peel_to_tree(&revision::resolve(hash)?)?;
+    print_tree_entries(&sha, &options)
+}
+
+/// Prints the entries of `tree`, descending only into subtrees that lead to a pathspec match
+/// and, with `-r`, into matched subtrees.
+fn print_tree_entries(tree: &Sha, options: &LsTreeOptions) -> anyhow::Result<()> {
+    let mut walk = TreeWalk::new((*tree).into());
+    while let Some(entry) = walk.next() {
+        let (path, entry) = entry?;
+        let is_dir = matches!(entry.permission, TreeEntryPermission::Directory);
+        let matched = options.pathspecs.matches(&path, is_dir);
+        let descend = is_dir
+            && if matched {
+                options.recursive
+            } else {
+                options.pathspecs.could_match_within(&path)
+            };
+        if matched
+            && (!is_dir || !descend || options.show_trees || options.only_trees)
+            && (is_dir || !options.only_trees)
+        {
+            let mode = format!("{:0>6}", entry.permission.to_string_repr());
+            print_entry(&path, &mode, &entry.hash, is_dir, options)?;
+        }
+        if !descend {
+            walk.skip_subtree();
+        }
+    }
+    Ok(())
+}
+
+fn print_entry(
+    path: &str,
+    mode: &str,
+    sha: &[u8; 20],
+    is_dir: bool,
+    options: &LsTreeOptions,
+) -> anyhow::Result<()> {
+    let name = if options.null_terminated {
+        path.to_string()
+    } else {
+        cli::quote_path(path)
+    };
+    let terminator = if options.null_terminated { '\0' } else { '\n' };
+    let object_type = if is_dir { "tree" } else { "blob" };
+    let hex = ObjectStorage::sha_to_hex_string(sha);
+    let size = || -> anyhow::Result<String> {
+        if is_dir {
+            Ok("-".to_string())
+        } else {
+            Ok(ObjectStorage::open_object(sha)?.size.to_string())
+        }
+    };
+    if let Some(template) = &options.format {
+        let mut line = format::expand(template, "ls-tree", |field| {
+            Ok(match field {
+                "objectmode" => Some(mode.to_string()),
+                "objecttype" => Some(object_type.to_string()),
+                "objectname" => Some(hex.clone()),
+                "objectsize" => Some(size()?),
+                "objectsize:padded" => Some(format!("{:>7}", size()?)),
+                "path" => Some(name.clone()),
+                _ => None,
+            })
+        })?;
+        line.push(terminator as u8);
+        std::io::stdout().write_all(&line)?;
+        return Ok(());
+    }
+    if options.name_only {
+        print!("{}{}", name, terminator);
+        return Ok(());
+    }
+    if options.long {
+        print!(
+            "{} {} {} {:>7}\t{}{}",
+            mode,
+            object_type,
+            hex,
+            size()?,
+            name,
+            terminator
+        );
+    } else {
+        print!("{} {} {}\t{}{}", mode, object_type, hex, name, terminator);
+    }
+    Ok(())
+}
@@ -0,0 +1,353 @@
+use super::checkout;
+use crate::cli;
+use crate::commit_walk;
+use crate::diff;
+use crate::gc;
+use crate::index::{self, Index};
+use crate::merge::{self, Conflict, MovedFile};
+use crate::merge_file::Labels;
+use crate::merge_message::{self, MergeSource};
+use crate::object_storage::{Commit, ObjectStorage, Sha, Signature};
+use crate::refs::{self, PseudoRef};
+use crate::repository::CommandExit;
+use crate::revision;
+use crate::search_index;
+use anyhow::anyhow;
+use std::fs;
+
+/// Merges one or more commits into the current branch. A commit that contains `HEAD` is
+/// fast-forwarded to. Otherwise the trees are merged in memory and a merge commit is made;
+/// when one commit's merge conflicts, the conflicts are left in the working tree and index,
+/// with `MERGE_HEAD` and `MERGE_MSG` for `commit` to conclude the merge. Several commits are
+/// merged like git's octopus strategy: one after the other into the result so far, refusing,
+/// without touching anything, if any of them conflicts, and committing a merge with all of
+/// them as parents. Either way `ORIG_HEAD` is left where `HEAD` was. A branch with no commits
+/// yet is just pointed at the commit merged.
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("merge", args)?;
+    if args.operands.is_empty() {
+        return Err(cli::usage_error("merge"));
+    }
+    let index = Index::read()?;
+    if index.entries.iter().any(|entry| entry.stage != 0) {
+        eprintln!("error: Merging is not possible because you have unmerged files.");
+        eprintln!("hint: Fix them up in the work tree, and then use 'git add/rm <file>'");
+        eprintln!("hint: as appropriate to mark resolution and make a commit.");
+        Err(anyhow!("Exiting because of an unresolved conflict."))?
+    }
+    for (pseudo_ref, operation) in [
+        (PseudoRef::Merge, "merge"),
+        (PseudoRef::CherryPick, "cherry-pick"),
+    ] {
+        if !refs::read_pseudo_ref(pseudo_ref)?.is_empty() {
+            Err(anyhow!(
+                "You have not concluded your {} ({} exists).\n\
+                 Please, commit your changes before you merge.",
+                operation,
+                pseudo_ref.name()
+            ))?
+        }
+    }
+    let mut heads: Vec<(String, Sha)> = vec![];
+    for name in &args.operands {
+        let sha = revision::resolve(name)
+            .and_then(|sha| ObjectStorage::peel_to_commit(&sha))
+            .map_err(|_| anyhow!("{} - not something we can merge", name))?;
+        heads.push((name.clone(), sha));
+    }
+    let (_, Some(head)) = refs::resolve_ref("HEAD")? else {
+        return merge_into_unborn(&heads);
+    };
+
+    // Commits already merged add nothing, nor do those another one contains.
+    let mut remaining: Vec<(String, Sha)> = vec![];
+    for (name, sha) in &heads {
+        if commit_walk::is_ancestor(sha, &head)? || remaining.iter().any(|(_, seen)| seen == sha) {
+            continue;
+        }
+        let mut contained = false;
+        for (_, other) in &heads {
+            contained |= other != sha && commit_walk::is_ancestor(sha, other)?;
+        }
+        if !contained {
+            remaining.push((name.clone(), *sha));
+        }
+    }
+    let mut sources = vec![];
+    for (name, sha) in &remaining {
+        sources.push(MergeSource::from_revision(name, *sha)?);
+    }
+    let log_length = merge_message::log_length(&args)?;
+    let message = merge_message::format(&sources, args.value("-m"), log_length)?;
+    let message = message.trim_end().to_string();
+    let head_tree = ObjectStorage::peel_to_tree(&head)?;
+    if !remaining.is_empty() {
+        refs::write_pseudo_ref(PseudoRef::Orig, &[head])?;
+    }
+    match remaining.as_slice() {
+        [] => println!("Already up to date."),
+        [(_, target)] if commit_walk::is_ancestor(&head, target)? => {
+            println!(
+                "Updating {}..{}",
+                revision::abbreviate(&head, 7)?,
+                revision::abbreviate(target, 7)?
+            );
+            println!("Fast-forward");
+            refuse_local_changes(&head_tree, &[])?;
+            let target_tree = ObjectStorage::peel_to_tree(target)?;
+            checkout::switch(Some(&head_tree), &target_tree, "merge", "merge")?;
+            refs::update_ref("HEAD", target, Some(&head))?;
+        }
+        [(name, theirs)] => merge_one(head, &head_tree, name, theirs, message)?,
+        _ => merge_octopus(head, &head_tree, &remaining, message)?,
+    }
+    search_index::update()?;
+    gc::auto_gc()
+}
+
+/// Merging into a branch with no commits yet, as `git pull` does into a new repository, just
+/// points the branch at the one commit merged and checks it out.
+fn merge_into_unborn(heads: &[(String, Sha)]) -> anyhow::Result<()> {
+    let target = &heads[0].1;
+    if heads.iter().any(|(_, sha)| sha != target) {
+        Err(anyhow!("Can merge only exactly one commit into empty head"))?
+    }
+    let target_tree = ObjectStorage::peel_to_tree(target)?;
+    checkout::switch(None, &target_tree, "merge", "merge")?;
+    refs::update_ref("HEAD", target, Some(&[0; 20]))?;
+    search_index::update()
+}
+
+/// A merge of `theirs` into `head`, which leaves conflicts for the user to resolve.
+fn merge_one(
+    head: Sha,
+    head_tree: &Sha,
+    name: &str,
+    theirs: &Sha,
+    message: String,
+) -> anyhow::Result<()> {
+    let labels = Labels {
+        ours: "HEAD",
+        base: "merged common ancestors",
+        theirs: name,
+    };
+    let merged = merge::merge_commits(&head, theirs, &labels)?;
+    let written: Vec<&str> = merged
+        .conflicts
+        .iter()
+        .filter(|conflict| conflict.content.is_some())
+        .map(|conflict| conflict.path.as_str())
+        .collect();
+    refuse_local_changes(head_tree, &written)?;
+    report_moved(&merged.moved, name);
+    for path in &merged.auto_merged {
+        println!("Auto-merging {}", path);
+    }
+    let tree = merge::write_tree(&merged.files)?;
+    if tree != *head_tree {
+        checkout::switch(Some(head_tree), &tree, "merge", "merge")?;
+    }
+    if merged.conflicts.is_empty() {
+        println!("Merge made by the 'ort' strategy.");
+        return commit_merge(head, tree, vec![head, *theirs], message);
+    }
+
+    record_conflicts(&merged.conflicts, name)?;
+    refs::write_pseudo_ref(PseudoRef::Merge, &[*theirs])?;
+    let git_dir = ObjectStorage::git_dir();
+    let mut merge_message = format!("{}\n\n# Conflicts:\n", message);
+    for conflict in &merged.conflicts {
+        merge_message.push_str(&format!("#\t{}\n", conflict.path));
+    }
+    fs::write(git_dir.join("MERGE_MSG"), merge_message)?;
+    fs::write(git_dir.join("MERGE_MODE"), "")?;
+    println!("Automatic merge failed; fix conflicts and then commit the result.");
+    Err(CommandExit { code: 1 })?
+}
+
+/// Merges each of `heads` into the result of merging the ones before it, fast-forwarding while
+/// that is possible, and commits the result only if none of them conflicted.
+fn merge_octopus(
+    head: Sha,
+    head_tree: &Sha,
+    heads: &[(String, Sha)],
+    message: String,
+) -> anyhow::Result<()> {
+    refuse_local_changes(head_tree, &[])?;
+    // The commits merged so far and their merged tree.
+    let mut merged_commits = vec![head];
+    let mut merged_tree = *head_tree;
+    let mut fast_forward = true;
+    for (name, sha) in heads {
+        let their_tree = ObjectStorage::peel_to_tree(sha)?;
+        let bases = commit_walk::merge_bases(sha, &merged_commits)?;
+        if fast_forward && bases == merged_commits {
+            println!("Fast-forwarding to: {}", name);
+            merged_commits = vec![*sha];
+            merged_tree = their_tree;
+            continue;
+        }
+        fast_forward = false;
+        println!("Trying simple merge with {}", name);
+        let base_tree = bases.first().map(ObjectStorage::peel_to_tree).transpose()?;
+        let labels = Labels {
+            ours: "HEAD",
+            base: "merged common ancestors",
+            theirs: name,
+        };
+        let merged = merge::merge_trees(base_tree.as_ref(), &merged_tree, &their_tree, &labels)?;
+        report_moved(&merged.moved, name);
+        if !merged.auto_merged.is_empty() {
+            println!("Simple merge did not work, trying automatic merge.");
+            for path in &merged.auto_merged {
+                println!("Auto-merging {}", path);
+            }
+        }
+        if !merged.conflicts.is_empty() {
+            for conflict in &merged.conflicts {
+                eprintln!("ERROR: {} conflict in {}", conflict.kind, conflict.path);
+            }
+            println!("Automated merge did not work.");
+            println!("Should not be doing an octopus.");
+            println!("Merge with strategy octopus failed.");
+            Err(CommandExit { code: 2 })?
+        }
+        merged_commits.push(*sha);
+        merged_tree = merge::write_tree(&merged.files)?;
+    }
+
+    checkout::switch(Some(head_tree), &merged_tree, "merge", "merge")?;
+    println!("Merge made by the 'octopus' strategy.");
+    // `HEAD` is only a parent if none of the merged commits contains it.
+    let mut parents = vec![];
+    let mut head_contained = false;
+    for (_, sha) in heads {
+        head_contained |= commit_walk::is_ancestor(&head, sha)?;
+    }
+    if !head_contained {
+        parents.push(head);
+    }
+    parents.extend(heads.iter().map(|(_, sha)| *sha));
+    commit_merge(head, merged_tree, parents, message)
+}
+
+fn commit_merge(head: Sha, tree: Sha, parents: Vec<Sha>, message: String) -> anyhow::Result<()> {
+    let commit = Commit {
+        tree,
+        parents,
+        author: Signature::author()?,
+        committer: Signature::committer()?,
+        message,
+    };
+    let sha = commit.write_to_object_storage()?;
+    refs::update_ref("HEAD", &sha, Some(&head))
+}
+
+/// Reports the files a merge of `theirs` into `HEAD` moved along with a renamed directory.
+pub fn report_moved(moved: &[MovedFile], theirs: &str) {
+    for file in moved {
+        println!("{}", describe_move(file, "HEAD", theirs));
+    }
+}
+
+/// The `Path updated: ...` line for a file moved merging `theirs` into `ours`.
+pub fn describe_move(file: &MovedFile, ours: &str, theirs: &str) -> String {
+    let (added_in, renamed_in) = match file.added_by_us {
+        true => (ours, theirs),
+        false => (theirs, ours),
+    };
+    format!(
+        "Path updated: {} added in {} inside a directory that was renamed in {}; moving it to {}.",
+        file.from, added_in, renamed_in, file.to
+    )
+}
+
+/// Reports the conflicts of merging `theirs` into `HEAD` and leaves them in the working tree
+/// and the index, for the user to resolve.
+pub fn record_conflicts(conflicts: &[Conflict], theirs: &str) -> anyhow::Result<()> {
+    let (mut index, lock) = Index::read_locked()?;
+    for conflict in conflicts {
+        println!("{}", describe_conflict(conflict, "HEAD", theirs));
+        if let Some(content) = &conflict.content {
+            fs::write(ObjectStorage::work_tree().join(&conflict.path), content)?;
+        }
+        let versions: Vec<(u16, u32, Sha)> = [conflict.base, conflict.ours, conflict.theirs]
+            .into_iter()
+            .zip(1..)
+            .filter_map(|(version, stage)| {
+                let (mode, sha) = version?;
+                Some((stage, index::tree_entry_mode(mode), sha))
+            })
+            .collect();
+        index.add_conflict(&conflict.path, &versions);
+    }
+    index.write(lock)
+}
+
+/// Refuses to merge when changes are staged, or when a file the merge writes conflict markers
+/// into has local changes; `checkout::switch` checks the other files it changes.
+pub fn refuse_local_changes(head_tree: &Sha, written: &[&str]) -> anyhow::Result<()> {
+    let index = Index::read()?;
+    let mut changed: Vec<String> = diff::diff_tree_to_index(Some(head_tree), &index)?
+        .into_iter()
+        .map(|change| change.path)
+        .collect();
+    for path in written {
+        let file = ObjectStorage::work_tree().join(path);
+        let clean = match index.find(path) {
+            Some(entry) => entry.matches_file(&file)?,
+            None => false,
+        };
+        if !clean && !changed.iter().any(|changed| changed == path) {
+            changed.push(path.to_string());
+        }
+    }
+    if changed.is_empty() {
+        return Ok(());
+    }
+    changed.sort();
+    eprintln!("error: Your local changes to the following files would be overwritten by merge:");
+    for path in &changed {
+        eprintln!("\t{}", path);
+    }
+    eprintln!("Please commit your changes or stash them before you merge.");
+    eprintln!("Aborting");
+    Err(CommandExit { code: 1 })?
+}
+
+/// The `CONFLICT (<kind>): ...` line for a conflict merging `theirs` into `ours`.
+pub fn describe_conflict(conflict: &Conflict, ours: &str, theirs: &str) -> String {
+    let path = &conflict.path;
+    match conflict.kind {
+        "modify/delete" => {
+            let (deleted_in, kept) = match conflict.ours {
+                Some(_) => (theirs, ours),
+                None => (ours, theirs),
+            };
+            format!(
+                "CONFLICT (modify/delete): {} deleted in {} and modified in {}.  \
+                 Version {} of {} left in tree.",
+                path, deleted_in, kept, kept, path
+            )
+        }
+        "file location" => {
+            let (added_in, renamed_in) = match conflict.ours {
+                Some(_) => (ours, theirs),
+                None => (theirs, ours),
+            };
+            format!(
+                "CONFLICT (file location): {} added in {} inside a directory that was renamed \
+                 in {}, suggesting it should perhaps be moved to {}.",
+                conflict.moved_from.as_deref().unwrap_or_default(),
+                added_in,
+                renamed_in,
+                path
+            )
+        }
+        "file/directory" => format!(
+            "CONFLICT (file/directory): There is a directory with name {} in one side.",
+            path
+        ),
+        kind => format!("CONFLICT ({}): Merge conflict in {}", kind, path),
+    }
+}
@@ -0,0 +1,54 @@
+use crate::cli;
+use crate::commit_walk;
+use crate::object_storage::{GitObject, ObjectStorage, Sha};
+use crate::repository::CommandExit;
+use crate::revision;
+use anyhow::anyhow;
+
+/// Prints the best common ancestor of the first commit and the others, or with `--all` every
+/// one of them, newest first; exits with 1 when there is none. `--is-ancestor A B` prints
+/// nothing and only exits with 0 when `A` is an ancestor of `B` and with 1 when it is not.
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("merge-base", args)?;
+    let commits = args
+        .operands
+        .iter()
+        .map(|revision| {
+            let sha = revision::resolve(revision)
+                .map_err(|_| anyhow!("Not a valid object name {}", revision))?;
+            ObjectStorage::peel_to_commit(&sha)
+                .map_err(|_| anyhow!("Not a valid commit name {}", revision))
+        })
+        .collect::<anyhow::Result<Vec<Sha>>>()?;
+    if args.has("--is-ancestor") {
+        let [ancestor, descendant] = commits.as_slice() else {
+            return Err(cli::usage_error("merge-base"));
+        };
+        return match commit_walk::is_ancestor(ancestor, descendant)? {
+            true => Ok(()),
+            false => Err(CommandExit { code: 1 })?,
+        };
+    }
+    let [first, others @ ..] = commits.as_slice() else {
+        return Err(cli::usage_error("merge-base"));
+    };
+    if others.is_empty() {
+        return Err(cli::usage_error("merge-base"));
+    }
+    let mut bases = vec![];
+    for sha in commit_walk::merge_bases(first, others)? {
+        let GitObject::Commit(commit) = GitObject::from_sha(&sha)? else {
+            continue;
+        };
+        bases.push((commit.committer.time, sha));
+    }
+    if bases.is_empty() {
+        Err(CommandExit { code: 1 })?
+    }
+    bases.sort_by(|a, b| b.cmp(a));
+    let shown = if args.has("-a") { bases.len() } else { 1 };
+    for (_, sha) in &bases[..shown] {
+        println!("{}", ObjectStorage::sha_to_hex_string(sha));
+    }
+    Ok(())
+}
@@ -0,0 +1,66 @@
+use crate::cli;
+use crate::merge_file::{self, DEFAULT_MARKER_SIZE, Favor, Labels};
+use crate::object_storage::ObjectStorage;
+use crate::repository::CommandExit;
+use anyhow::anyhow;
+use std::fs;
+use std::io::{self, Write};
+use std::path;
+
+/// Merges the changes from `<base>` to `<other>` into `<current>`, like `git merge-file`, and
+/// exits with the number of conflicts. Without `--ours`, `--theirs` or `--union`, a file in the
+/// working tree is merged with the driver its `merge` attribute names.
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("merge-file", args)?;
+    let [current, base, other] = args.operands.as_slice() else {
+        return Err(cli::usage_error("merge-file"));
+    };
+    let names = args.values("-L");
+    let labels = Labels {
+        ours: names.first().copied().unwrap_or(current),
+        base: names.get(1).copied().unwrap_or(base),
+        theirs: names.get(2).copied().unwrap_or(other),
+    };
+    let read =
+        |file: &String| fs::read(file).map_err(|err| anyhow!("could not open '{}': {}", file, err));
+    let (ours, base_content, theirs) = (read(current)?, read(base)?, read(other)?);
+    let favor = if args.has("--ours") {
+        Favor::Ours
+    } else if args.has("--theirs") {
+        Favor::Theirs
+    } else if args.has("--union") {
+        Favor::Union
+    } else {
+        Favor::None
+    };
+    let work_tree = path::absolute(ObjectStorage::work_tree())?;
+    let path_in_work_tree = path::absolute(current)?
+        .strip_prefix(&work_tree)
+        .ok()
+        .map(|relative| relative.to_string_lossy().to_string());
+    let result = match (favor, path_in_work_tree) {
+        (Favor::None, Some(path)) => {
+            merge_file::merge_file(&path, &base_content, &ours, &theirs, &labels)?
+        }
+        _ => {
+            let marker_size = match args.value("--marker-size") {
+                Some(size) => size
+                    .parse()
+                    .map_err(|_| anyhow!("invalid marker size '{}'", size))?,
+                None => DEFAULT_MARKER_SIZE,
+            };
+            merge_file::merge_text(&base_content, &ours, &theirs, &labels, favor, marker_size)
+        }
+    };
+    if args.has("-p") {
+        io::stdout().write_all(&result.content)?;
+    } else {
+        fs::write(current, &result.content)?;
+    }
+    if result.conflicts > 0 {
+        Err(CommandExit {
+            code: result.conflicts.min(127) as i32,
+        })?
+    }
+    Ok(())
+}
@@ -0,0 +1,85 @@
+use super::merge as merge_command;
+use crate::cli;
+use crate::commit_walk;
+use crate::index;
+use crate::merge;
+use crate::merge_file::Labels;
+use crate::object_storage::{ObjectStorage, Sha};
+use crate::repository::CommandExit;
+use crate::revision;
+use anyhow::anyhow;
+
+/// Merges two commits in memory, as `merge` would, without touching the working tree, the
+/// index or any ref, and prints the id of the merged tree. When the merge conflicts, the tree
+/// has the conflict markers in it, and the tree is followed by the staged versions of each
+/// conflicting path, `<mode> <object> <stage>\t<path>` like `ls-files -s` (or with
+/// `--name-only` just the paths), an empty line and the `Auto-merging` and `CONFLICT` messages;
+/// then the exit code is 1. Services can use it to tell whether a merge would be clean.
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("merge-tree", args)?;
+    let [branch1, branch2] = args.operands.as_slice() else {
+        return Err(cli::usage_error("merge-tree"));
+    };
+    let commit = |name: &str| -> anyhow::Result<Sha> {
+        revision::resolve(name)
+            .and_then(|sha| ObjectStorage::peel_to_commit(&sha))
+            .map_err(|_| anyhow!("merge-tree: {} - not something we can merge", name))
+    };
+    let (ours, theirs) = (commit(branch1)?, commit(branch2)?);
+    if !args.has("--allow-unrelated-histories")
+        && commit_walk::merge_bases(&theirs, &[ours])?.is_empty()
+    {
+        Err(anyhow!("refusing to merge unrelated histories"))?
+    }
+    let labels = Labels {
+        ours: branch1,
+        base: "merged common ancestors",
+        theirs: branch2,
+    };
+    let merged = merge::merge_commits(&ours, &theirs, &labels)?;
+    println!("{}", ObjectStorage::sha_to_hex_string(&merged.tree()?));
+    if merged.conflicts.is_empty() {
+        return Ok(());
+    }
+
+    for conflict in &merged.conflicts {
+        if args.has("--name-only") {
+            println!("{}", cli::quote_path(&conflict.path));
+            continue;
+        }
+        let versions = [conflict.base, conflict.ours, conflict.theirs];
+        for (stage, version) in (1..).zip(versions) {
+            let Some((mode, sha)) = version else {
+                continue;
+            };
+            println!(
+                "{:06o} {} {}\t{}",
+                index::tree_entry_mode(mode),
+                ObjectStorage::sha_to_hex_string(&sha),
+                stage,
+                cli::quote_path(&conflict.path)
+            );
+        }
+    }
+    if args.last_of(&["--messages", "--no-messages"]) != Some("--no-messages") {
+        // The messages come path by path, as the merge went through them.
+        let mut messages: Vec<(&str, String)> = vec![];
+        for file in &merged.moved {
+            let message = merge_command::describe_move(file, branch1, branch2);
+            messages.push((&file.to, message));
+        }
+        for path in &merged.auto_merged {
+            messages.push((path, format!("Auto-merging {}", path)));
+        }
+        for conflict in &merged.conflicts {
+            let message = merge_command::describe_conflict(conflict, branch1, branch2);
+            messages.push((&conflict.path, message));
+        }
+        messages.sort_by_key(|(path, _)| *path);
+        println!();
+        for (_, message) in messages {
+            println!("{}", message);
+        }
+    }
+    Err(CommandExit { code: 1 })?
+}
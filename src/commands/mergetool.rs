@@ -0,0 +1,151 @@
+use crate::cli;
+use crate::config::Config;
+use crate::index::{self, Index, IndexEntry};
+use crate::merge_tools::{self, Mode, Tool, ToolFiles};
+use crate::object_storage::{ObjectStorage, Sha};
+use crate::pathspec::PathspecSet;
+use crate::repository::CommandExit;
+use crate::temp_file::TempFile;
+use std::fs;
+use std::path::Path;
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("mergetool", args)?;
+    if args.has("--tool-help") {
+        merge_tools::print_tool_help(Mode::Merge);
+        return Ok(());
+    }
+    let pathspecs = PathspecSet::from_args(&args.operands)?;
+    let (mut index, lock) = Index::read_locked()?;
+    let mut conflicts: Vec<String> = index
+        .entries
+        .iter()
+        .filter(|entry| entry.stage != 0 && pathspecs.matches(&entry.path, false))
+        .map(|entry| entry.path.clone())
+        .collect();
+    conflicts.dedup();
+    if conflicts.is_empty() {
+        println!("No files need merging");
+        return Ok(());
+    }
+    let tool = Tool::find(Mode::Merge, args.value("-t"))?;
+    let config = Config::get()?;
+    let prompt = if args.has("-y") {
+        false
+    } else {
+        args.has("--prompt") || config.bool("mergetool", "prompt").unwrap_or(true)
+    };
+    let keep_backup = config.bool("mergetool", "keepBackup").unwrap_or(true);
+    println!("Merging:");
+    for path in &conflicts {
+        println!("{}", path);
+    }
+
+    let mut failed = false;
+    for path in &conflicts {
+        let stage = |number: u16| {
+            index
+                .entries
+                .iter()
+                .find(|entry| entry.path == *path && entry.stage == number)
+                .map(|entry| entry.sha)
+        };
+        let (base, local, remote) = (stage(1), stage(2), stage(3));
+        let (Some(local), Some(remote)) = (local, remote) else {
+            // Taking one side of a modify/delete conflict is left to `add` and `rm`.
+            println!(
+                "\nDeleted merge conflict for '{}':\n  {{local}}: {}\n  {{remote}}: {}",
+                path,
+                describe(local),
+                describe(remote)
+            );
+            eprintln!("{}: deleted on one side; resolve it by hand", path);
+            failed = true;
+            continue;
+        };
+        println!(
+            "\nNormal merge conflict for '{}':\n  {{local}}: modified file\n  {{remote}}: modified file",
+            path
+        );
+        if prompt {
+            let question = format!(
+                "Hit return to start merge resolution tool ({}): ",
+                tool.name
+            );
+            merge_tools::ask(&question, true)?;
+        }
+        if resolve(&tool, path, base, local, remote, keep_backup)? {
+            let file = ObjectStorage::work_tree().join(path);
+            let sha = index::hash_work_tree_file(&file, &fs::symlink_metadata(&file)?, true)?;
+            index.remove(path);
+            index.add(IndexEntry::from_file(path, &file, sha)?);
+        } else {
+            eprintln!("merge of {} failed", path);
+            failed = true;
+        }
+    }
+    index.write(lock)?;
+    if failed {
+        Err(CommandExit { code: 1 })?
+    }
+    Ok(())
+}
+
+/// Writes the three versions next to the conflicted file as `<name>_BASE_XXXXXX.<ext>` and
+/// so on, runs the tool on them and tells whether the conflict was resolved: by the tool's
+/// exit code if it can be trusted, otherwise by whether the file changed, or by asking when it
+/// didn't.
+fn resolve(
+    tool: &Tool,
+    path: &str,
+    base: Option<Sha>,
+    local: Sha,
+    remote: Sha,
+    keep_backup: bool,
+) -> anyhow::Result<bool> {
+    let merged = ObjectStorage::work_tree().join(path);
+    let dir = merged.parent().unwrap_or(Path::new("."));
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+    let (stem, extension) = match file_name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{}", extension)),
+        _ => (file_name, String::new()),
+    };
+    let version = |label: &str, sha: &Sha| {
+        TempFile::from_blob(sha, dir, &format!("{}_{}_", stem, label), &extension)
+    };
+    let base = base.map(|base| version("BASE", &base)).transpose()?;
+    let local = version("LOCAL", &local)?;
+    let remote = version("REMOTE", &remote)?;
+    let backup = merged.with_file_name(format!("{}.orig", file_name));
+    let before = fs::read(&merged)?;
+    if keep_backup {
+        fs::write(&backup, &before)?;
+    }
+    let status = tool.run(&ToolFiles {
+        local: local.path(),
+        remote: remote.path(),
+        // Relative to the top of the working tree, where the tool runs, as git passes it.
+        merged: Path::new(path),
+        base: base.as_ref().map(TempFile::path),
+    })?;
+    if tool.trust_exit_code {
+        return Ok(status.success());
+    }
+    if fs::read(&merged)? != before {
+        return Ok(true);
+    }
+    merge_tools::ask(
+        &format!(
+            "{} seems unchanged.\nWas the merge successful [y/n]? ",
+            path
+        ),
+        false,
+    )
+}
+
+fn describe(sha: Option<Sha>) -> &'static str {
+    match sha {
+        Some(_) => "modified file",
+        None => "deleted",
+    }
+}
@@ -0,0 +1,58 @@
+use crate::cli;
+use crate::commit_graph;
+use crate::gc;
+use crate::index::{self, Index, IndexEntry, IndexLock};
+use crate::object_storage::{ObjectStorage, Sha};
+use crate::refs;
+use std::fs;
+
+/// Brings a repository written by the early versions of this program, with only loose
+/// objects and loose refs and without an index, up to the layout the later commands are
+/// quick with: the refs go into `packed-refs` and the objects into one pack, as `gc` does,
+/// a commit-graph is written, and an index is built from `HEAD` unless there is one. Running
+/// it again on a migrated repository only repacks.
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("migrate", args)?;
+    if !args.operands.is_empty() {
+        return Err(cli::usage_error("migrate"));
+    }
+    let quiet = args.has("-q");
+    gc::run_gc(false, quiet)?;
+    let commits = commit_graph::write()?;
+    if !quiet {
+        println!("Wrote a commit-graph of {} commits", commits);
+    }
+    if ObjectStorage::has_work_tree()
+        && !Index::path().exists()
+        && let (_, Some(head)) = refs::resolve_ref("HEAD")?
+    {
+        let lock = IndexLock::acquire()?;
+        let index = index_from_head(&ObjectStorage::peel_to_tree(&head)?)?;
+        index.write(lock)?;
+        if !quiet {
+            println!("Built the index from HEAD: {} files", index.entries.len());
+        }
+    }
+    Ok(())
+}
+
+/// The index for the tree `tree`. The working tree may have changed since the tree was
+/// committed, so only files that still have the committed content keep their stat data;
+/// the others are looked at again by the next `status` or `add`.
+fn index_from_head(tree: &Sha) -> anyhow::Result<Index> {
+    let mut index = Index::from_tree(tree)?;
+    let work_tree = ObjectStorage::work_tree();
+    for entry in &mut index.entries {
+        let file = work_tree.join(&entry.path);
+        let unchanged = match fs::symlink_metadata(&file) {
+            Ok(metadata) if !metadata.is_dir() => {
+                index::hash_work_tree_file(&file, &metadata, false)? == entry.sha
+            }
+            _ => false,
+        };
+        if !unchanged {
+            *entry = IndexEntry::without_stat(&entry.path, entry.mode, entry.sha);
+        }
+    }
+    Ok(index)
+}
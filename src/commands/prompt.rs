@@ -0,0 +1,123 @@
+use crate::cli;
+use crate::commit_walk;
+use crate::config::Config;
+use crate::diff;
+use crate::index::Index;
+use crate::object_storage::{ObjectStorage, Sha};
+use crate::refs::{self, PseudoRef};
+use crate::sequencer::{Action, Sequence};
+
+/// Prints the state of the repository in one line for a shell prompt, the way git's
+/// `git-prompt.sh` shows it with its dirty state and verbose upstream options:
+/// `main *+|MERGING u+1-2` is the branch `main` with unstaged (`*`) and staged (`+`) changes,
+/// in the middle of a merge, one commit ahead of its upstream and two behind. A detached
+/// `HEAD` shows as `(1a2b3c4...)`. Outside a repository nothing is printed, so that the
+/// command can go into `PS1` as it is.
+///
+/// Only what a prompt needs is looked at: the stat data in the index decides whether files
+/// changed, and the working tree is not searched for untracked files.
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("prompt", args)?;
+    if !args.operands.is_empty() {
+        return Err(cli::usage_error("prompt"));
+    }
+    if !ObjectStorage::git_dir().is_dir() {
+        return Ok(());
+    }
+    ObjectStorage::ensure_safe_directory()?;
+    let branch = refs::current_branch()?;
+    let (_, head) = refs::resolve_ref("HEAD")?;
+    let mut prompt = match (&branch, head) {
+        (Some(branch), _) => short_branch(branch).to_string(),
+        (None, Some(head)) => format!("({}...)", &ObjectStorage::sha_to_hex_string(&head)[..7]),
+        (None, None) => "HEAD".to_string(),
+    };
+    if !ObjectStorage::has_work_tree() {
+        prompt.insert_str(0, "BARE:");
+    } else if !args.has("--no-dirty") {
+        let flags = dirty_flags(head.as_ref())?;
+        if !flags.is_empty() {
+            prompt.push(' ');
+            prompt.push_str(&flags);
+        }
+    }
+    if let Some(operation) = operation()? {
+        prompt.push('|');
+        prompt.push_str(operation);
+    }
+    if let (Some(branch), Some(head), false) = (&branch, head, args.has("--no-upstream"))
+        && let Some(upstream) = upstream(short_branch(branch))?
+    {
+        prompt.push_str(&match commit_walk::ahead_behind(&head, &upstream)? {
+            (0, 0) => " u=".to_string(),
+            (ahead, 0) => format!(" u+{}", ahead),
+            (0, behind) => format!(" u-{}", behind),
+            (ahead, behind) => format!(" u+{}-{}", ahead, behind),
+        });
+    }
+    println!("{}", prompt);
+    Ok(())
+}
+
+fn short_branch(branch: &str) -> &str {
+    branch.strip_prefix("refs/heads/").unwrap_or(branch)
+}
+
+/// `*` when a tracked file differs from the index or a path is unmerged, and `+` when the
+/// index differs from `HEAD`. Files are compared by their stat data, and only read when it
+/// changed; the first changed file is enough.
+fn dirty_flags(head: Option<&Sha>) -> anyhow::Result<String> {
+    let index = Index::read()?;
+    let work_tree = ObjectStorage::work_tree();
+    let mut flags = String::new();
+    for entry in &index.entries {
+        if entry.stage != 0 || !entry.matches_file(&work_tree.join(&entry.path))? {
+            flags.push('*');
+            break;
+        }
+    }
+    let head_tree = head.map(ObjectStorage::peel_to_tree).transpose()?;
+    if !diff::diff_tree_to_index(head_tree.as_ref(), &index)?.is_empty() {
+        flags.push('+');
+    }
+    Ok(flags)
+}
+
+/// The merge, cherry-pick or revert in progress, named as git's prompt names it.
+fn operation() -> anyhow::Result<Option<&'static str>> {
+    if !refs::read_pseudo_ref(PseudoRef::Merge)?.is_empty() {
+        return Ok(Some("MERGING"));
+    }
+    let sequence_action =
+        Sequence::load()?.and_then(|sequence| sequence.todo.first().map(|step| step.action));
+    for (action, name) in [
+        (Action::Pick, "CHERRY-PICKING"),
+        (Action::Revert, "REVERTING"),
+    ] {
+        if sequence_action == Some(action)
+            || !refs::read_pseudo_ref(action.pseudo_ref())?.is_empty()
+        {
+            return Ok(Some(name));
+        }
+    }
+    Ok(None)
+}
+
+/// The commit the upstream of `branch` points to, from `branch.<name>.remote` and
+/// `branch.<name>.merge`: a remote-tracking branch, or a local branch for the remote `.`.
+/// `None` when the branch has no upstream or it was never fetched.
+fn upstream(branch: &str) -> anyhow::Result<Option<Sha>> {
+    let section = format!("branch.{}", branch);
+    let config = Config::get()?;
+    let (Some(remote), Some(merge)) = (
+        config.value(&section, "remote"),
+        config.value(&section, "merge"),
+    ) else {
+        return Ok(None);
+    };
+    let tracking = match remote.as_str() {
+        "." => merge,
+        _ => format!("refs/remotes/{}/{}", remote, short_branch(&merge)),
+    };
+    Ok(refs::resolve_ref(&tracking)?.1)
+}
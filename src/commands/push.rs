@@ -0,0 +1,655 @@
+use super::fetch::{Refspec, short_name};
+use crate::cli;
+use crate::commit_walk;
+use crate::config::{self, Config};
+use crate::dry_run;
+use crate::object_storage::{GitObject, ObjectStorage, Sha, pack};
+use crate::oid_set::OidSet;
+use crate::promisor;
+use crate::refs;
+use crate::repack::RepackOptions;
+use crate::repository::CommandExit;
+use crate::revision;
+use crate::transport::{self, RefUpdate, RemoteRefs};
+use anyhow::anyhow;
+
+/// Updates refs on a remote from local ones, sending the objects it needs. Each
+/// `[+]<src>[:<dst>]` refspec pushes the commit `src` names to `dst`, by default the ref of
+/// the same name, and `:<dst>` deletes `dst`. Without refspecs, `push.default` decides what is
+/// pushed; without a remote, the branch's `pushRemote`, `remote.pushDefault` or the branch's
+/// remote is pushed to. An update that is not a fast-forward is refused unless forced.
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("push", args)?;
+    let branch = refs::current_branch()?;
+    let branch = branch
+        .as_deref()
+        .and_then(|name| name.strip_prefix("refs/heads/"));
+    let (remote, specs) = match args.operands.split_first() {
+        Some((remote, specs)) => (remote.clone(), specs),
+        None => (default_remote(branch)?, &[][..]),
+    };
+    let section = format!("remote.{}", remote);
+    let config = Config::get()?;
+    let url = match config.value(&section, "pushurl") {
+        Some(url) => url,
+        None => config.value(&section, "url").ok_or(anyhow!(
+            "'{}' does not appear to be a git repository",
+            remote
+        ))?,
+    };
+    let url = url.trim_end_matches('/');
+
+    let _interrupt_guard = transport::InterruptGuard::new();
+    let remote_refs = transport::discover_push_refs(url)?;
+    let (mut pushes, mut set_upstream) = if specs.is_empty() {
+        default_pushes(&remote, branch, &remote_refs)?
+    } else {
+        let pushes = specs
+            .iter()
+            .map(|spec| Push::parse(spec, branch, &remote_refs))
+            .collect::<anyhow::Result<_>>()?;
+        (pushes, false)
+    };
+    set_upstream |= args.has("-u");
+    if args.has("-f") {
+        for push in &mut pushes {
+            push.force = true;
+        }
+    }
+    push(&remote, url, &remote_refs, &pushes, set_upstream, branch)
+}
+
+/// Where a push without a remote goes: the current branch's `pushRemote`, then
+/// `remote.pushDefault`, then the branch's remote, then `origin`.
+fn default_remote(branch: Option<&str>) -> anyhow::Result<String> {
+    let section = branch.map(|branch| format!("branch.{}", branch));
+    let config = Config::get()?;
+    if let Some(section) = &section
+        && let Some(remote) = config.value(section, "pushRemote")
+    {
+        return Ok(remote);
+    }
+    if let Some(remote) = config.value("remote", "pushDefault") {
+        return Ok(remote);
+    }
+    fetch_remote(branch)
+}
+
+/// The remote the current branch is fetched from: its `remote`, or `origin`.
+fn fetch_remote(branch: Option<&str>) -> anyhow::Result<String> {
+    let remote = match branch {
+        Some(branch) => Config::get()?.value(&format!("branch.{}", branch), "remote"),
+        None => None,
+    };
+    Ok(remote.unwrap_or_else(|| "origin".to_string()))
+}
+
+/// What a push without refspecs pushes, as `push.default` says, and whether the current
+/// branch is to track what it is pushed to (`push.autoSetupRemote`, for a branch without an
+/// upstream):
+/// - `simple` (the default): the current branch to its upstream, which has to have the same
+///   name, or to the branch of the same name when pushing to another remote than the one the
+///   branch is fetched from;
+/// - `upstream`: the current branch to its upstream;
+/// - `current`: the current branch to the branch of the same name;
+/// - `matching`: every branch to the branch of the same name, if the remote has one;
+/// - `nothing`: refuses to push anything.
+fn default_pushes(
+    remote: &str,
+    branch: Option<&str>,
+    remote_refs: &RemoteRefs,
+) -> anyhow::Result<(Vec<Push>, bool)> {
+    let program = cli::program_name();
+    let config = Config::get()?;
+    let mode = config.value("push", "default");
+    let mode = mode.as_deref().unwrap_or("simple");
+    match mode {
+        "nothing" => Err(anyhow!(
+            "You didn't specify any refspecs to push, and push.default is \"nothing\"."
+        ))?,
+        "matching" => {
+            let mut pushes = vec![];
+            for (name, sha) in refs::list_refs()? {
+                if let Some(local) = name.strip_prefix("refs/heads/")
+                    && remote_refs.refs.iter().any(|(remote, _)| *remote == name)
+                {
+                    pushes.push(Push::new(local, Some(sha), name.clone()));
+                }
+            }
+            if pushes.is_empty() {
+                eprintln!("No refs in common and none specified; doing nothing.");
+                eprintln!("Perhaps you should specify a branch.");
+            }
+            return Ok((pushes, false));
+        }
+        "simple" | "upstream" | "tracking" | "current" => (),
+        _ => Err(anyhow!(
+            "malformed value for push.default: {}; must be one of nothing, matching, simple, \
+             upstream or current",
+            mode
+        ))?,
+    }
+    let Some(branch) = branch else {
+        Err(anyhow!(
+            "You are not currently on a branch.\n\
+             To push the history leading to the current (detached HEAD)\n\
+             state now, use\n\n    {} push {} HEAD:<name-of-remote-branch>\n",
+            program,
+            remote
+        ))?
+    };
+    let head = refs::resolve_ref("HEAD")?
+        .1
+        .ok_or(anyhow!("src refspec {} does not match any", branch))?;
+    let same_name = Push::new(branch, Some(head), format!("refs/heads/{}", branch));
+    let section = format!("branch.{}", branch);
+    let upstream = match config.value(&section, "remote") {
+        Some(_) => config.value(&section, "merge"),
+        None => None,
+    };
+    let triangular = fetch_remote(Some(branch))? != remote;
+    if mode == "current" || (mode == "simple" && triangular) {
+        let auto_setup = upstream.is_none() && auto_setup_remote()?;
+        return Ok((vec![same_name], auto_setup));
+    }
+    let Some(upstream) = upstream else {
+        if auto_setup_remote()? {
+            return Ok((vec![same_name], true));
+        }
+        Err(anyhow!(
+            "The current branch {branch} has no upstream branch.\n\
+             To push the current branch and set the remote as upstream, use\n\n    \
+             {program} push --set-upstream {remote} {branch}\n\n\
+             To have this happen automatically for branches without a tracking\n\
+             upstream, see 'push.autoSetupRemote' in 'git help config'.\n"
+        ))?
+    };
+    if triangular {
+        Err(anyhow!(
+            "You are pushing to remote '{}', which is not the upstream of\n\
+             your current branch '{}', without telling me what to push\n\
+             to update which remote branch.",
+            remote,
+            branch
+        ))?
+    }
+    if mode == "simple" && upstream != same_name.destination {
+        Err(anyhow!(
+            "The upstream branch of your current branch does not match\n\
+             the name of your current branch.  To push to the upstream branch\n\
+             on the remote, use\n\n    {program} push {remote} HEAD:{}\n\n\
+             To push to the branch of the same name on the remote, use\n\n    \
+             {program} push {remote} HEAD\n\n\
+             To choose either option permanently, see push.default in 'git help config'.\n\n\
+             To avoid automatically configuring an upstream branch when its name\n\
+             won't match the local branch, see option 'simple' of branch.autoSetupMerge\n\
+             in 'git help config'.\n",
+            short_name(&upstream)
+        ))?
+    }
+    Ok((vec![Push::new(branch, Some(head), upstream)], false))
+}
+
+fn auto_setup_remote() -> anyhow::Result<bool> {
+    Ok(Config::get()?
+        .bool("push", "autoSetupRemote")
+        .unwrap_or(false))
+}
+
+/// One ref to update on the remote.
+struct Push {
+    /// The source as it was given, to show it.
+    source_name: String,
+    /// The commit to push, or `None` to delete the remote ref.
+    source: Option<Sha>,
+    /// The local branch the commit comes from, if it is one.
+    branch: Option<String>,
+    destination: String,
+    force: bool,
+}
+
+impl Push {
+    fn new(branch: &str, source: Option<Sha>, destination: String) -> Self {
+        Self {
+            source_name: branch.to_string(),
+            source,
+            branch: Some(branch.to_string()),
+            destination,
+            force: false,
+        }
+    }
+
+    /// Parses a `[+]<src>[:<dst>]` refspec. A `dst` that is not a full ref name is the
+    /// remote's branch or tag of that name, or else a branch when `src` is one and a tag when
+    /// it is a tag.
+    fn parse(spec: &str, current: Option<&str>, remote_refs: &RemoteRefs) -> anyhow::Result<Self> {
+        let (force, spec) = match spec.strip_prefix('+') {
+            Some(spec) => (true, spec),
+            None => (false, spec),
+        };
+        let (source_name, destination) = match spec.split_once(':') {
+            Some((source, destination)) => (source, Some(destination)),
+            None => (spec, None),
+        };
+        // The local ref the source names, if it names one.
+        let local = match source_name {
+            "" => None,
+            "HEAD" => current.map(|branch| format!("refs/heads/{}", branch)),
+            _ => [
+                source_name.to_string(),
+                format!("refs/heads/{}", source_name),
+                format!("refs/tags/{}", source_name),
+            ]
+            .into_iter()
+            .find(|name| {
+                name.starts_with("refs/") && refs::read_ref(name).ok().flatten().is_some()
+            }),
+        };
+        let source = match source_name {
+            "" => None,
+            _ => Some(
+                revision::resolve(source_name)
+                    .map_err(|_| anyhow!("src refspec {} does not match any", source_name))?,
+            ),
+        };
+        let destination = match destination {
+            None => local.clone().ok_or(anyhow!(
+                "The destination you provided is not a full refname (i.e.,\n\
+                 starting with \"refs/\"): push '{}' to a named branch, as in\n\
+                 '{}:<name-of-remote-branch>'.",
+                source_name,
+                source_name
+            ))?,
+            Some(destination) if destination.starts_with("refs/") => destination.to_string(),
+            Some(destination) => {
+                let on_remote = ["refs/heads/", "refs/tags/"]
+                    .iter()
+                    .map(|prefix| format!("{}{}", prefix, destination))
+                    .find(|name| remote_refs.refs.iter().any(|(remote, _)| remote == name));
+                match (on_remote, local.as_deref()) {
+                    (Some(name), _) => name,
+                    (None, Some(local)) if local.starts_with("refs/tags/") => {
+                        format!("refs/tags/{}", destination)
+                    }
+                    (None, Some(_)) => format!("refs/heads/{}", destination),
+                    // Reported as not there when the others are pushed.
+                    (None, None) if source.is_none() => format!("refs/heads/{}", destination),
+                    (None, None) => Err(anyhow!(
+                        "The destination you provided is not a full refname (i.e.,\n\
+                         starting with \"refs/\"): use 'refs/heads/{}' to push to a branch.",
+                        destination
+                    ))?,
+                }
+            }
+        };
+        refs::check_ref_format(&destination)?;
+        Ok(Self {
+            source_name: source_name.to_string(),
+            source,
+            branch: local.and_then(|name| name.strip_prefix("refs/heads/").map(str::to_string)),
+            destination,
+            force,
+        })
+    }
+}
+
+/// What becomes of a ref that is pushed, decided before anything is sent.
+enum Outcome {
+    UpToDate,
+    Create,
+    FastForward(Sha),
+    Forced(Sha),
+    Delete,
+    Rejected(&'static str),
+    /// Deleting a ref the remote does not have.
+    NoSuchRef,
+}
+
+/// Why updates were refused, for the advice after the error, most important first.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Rejection {
+    NonFastForwardHead,
+    NonFastForwardOther,
+    AlreadyExists,
+    FetchFirst,
+}
+
+fn push(
+    remote: &str,
+    url: &str,
+    remote_refs: &RemoteRefs,
+    pushes: &[Push],
+    set_upstream: bool,
+    current: Option<&str>,
+) -> anyhow::Result<()> {
+    let remote_sha = |name: &str| {
+        remote_refs
+            .refs
+            .iter()
+            .find(|(remote, _)| remote == name)
+            .map(|(_, sha)| *sha)
+    };
+    let mut outcomes = vec![];
+    for push in pushes {
+        let old = remote_sha(&push.destination);
+        let outcome = match (push.source, old) {
+            (None, None) => Outcome::NoSuchRef,
+            (None, Some(_)) => Outcome::Delete,
+            (Some(new), Some(old)) if new == old => Outcome::UpToDate,
+            (Some(_), None) => Outcome::Create,
+            (Some(_), Some(_)) if push.destination.starts_with("refs/tags/") && !push.force => {
+                Outcome::Rejected("already exists")
+            }
+            (Some(new), Some(old)) => {
+                if !ObjectStorage::contains(&old)? {
+                    if push.force {
+                        Outcome::Forced(old)
+                    } else {
+                        Outcome::Rejected("fetch first")
+                    }
+                } else if is_fast_forward(&old, &new)? {
+                    Outcome::FastForward(old)
+                } else if push.force {
+                    Outcome::Forced(old)
+                } else {
+                    Outcome::Rejected("non-fast-forward")
+                }
+            }
+        };
+        outcomes.push(outcome);
+    }
+
+    let mut failed = false;
+    for (push, outcome) in pushes.iter().zip(&outcomes) {
+        if let Outcome::NoSuchRef = outcome {
+            eprintln!(
+                "error: unable to delete '{}': remote ref does not exist",
+                short_name(&push.destination)
+            );
+            failed = true;
+        }
+    }
+    let updates: Vec<RefUpdate> = pushes
+        .iter()
+        .zip(&outcomes)
+        .filter_map(|(push, outcome)| {
+            let old = match outcome {
+                Outcome::Create => [0; 20],
+                Outcome::FastForward(old) | Outcome::Forced(old) => *old,
+                Outcome::Delete => remote_sha(&push.destination)?,
+                _ => return None,
+            };
+            Some(RefUpdate {
+                name: push.destination.clone(),
+                old,
+                new: push.source.unwrap_or([0; 20]),
+            })
+        })
+        .collect();
+    let shown = outcomes
+        .iter()
+        .any(|outcome| !matches!(outcome, Outcome::UpToDate | Outcome::NoSuchRef));
+    if !shown && !failed {
+        eprintln!("Everything up-to-date");
+    }
+
+    let statuses = if updates.is_empty() {
+        vec![]
+    } else if dry_run::is_active() {
+        for update in &updates {
+            dry_run::intercept(|| {
+                let action = if update.new == [0; 20] {
+                    "delete"
+                } else {
+                    "update"
+                };
+                format!("{} {} on {}", action, update.name, url)
+            });
+        }
+        updates
+            .iter()
+            .map(|update| (update.name.clone(), None))
+            .collect()
+    } else {
+        let tips: Vec<Sha> = updates
+            .iter()
+            .filter(|update| update.new != [0; 20])
+            .map(|update| update.new)
+            .collect();
+        let pack_data = if tips.is_empty() {
+            vec![]
+        } else {
+            let mut haves = vec![];
+            for (_, sha) in &remote_refs.refs {
+                if ObjectStorage::contains(sha)? {
+                    haves.push(*sha);
+                }
+            }
+            let objects = objects_to_send(&tips, &haves)?;
+            let options = RepackOptions::new(false, false, true)?;
+            pack::build_pack(&objects, options.window, options.depth)?.0
+        };
+        transport::send_pack(url, remote_refs, &updates, &pack_data)?
+    };
+
+    let tracking = remote_tracking_refspec(remote)?;
+    let mut rejections = vec![];
+    if shown {
+        eprintln!("To {}", url);
+    }
+    for (push, outcome) in pushes.iter().zip(&outcomes) {
+        let destination = short_name(&push.destination);
+        let source = &push.source_name;
+        let status = statuses
+            .iter()
+            .find(|(name, _)| *name == push.destination)
+            .map(|(_, status)| status.as_deref());
+        let (flag, summary, note) = match (outcome, status) {
+            (Outcome::UpToDate | Outcome::NoSuchRef, _) => continue,
+            (Outcome::Rejected(reason), _) => {
+                rejections.push(match *reason {
+                    "already exists" => Rejection::AlreadyExists,
+                    "fetch first" => Rejection::FetchFirst,
+                    _ if push.branch.is_some() && push.branch.as_deref() == current => {
+                        Rejection::NonFastForwardHead
+                    }
+                    _ => Rejection::NonFastForwardOther,
+                });
+                ('!', "[rejected]".to_string(), format!(" ({})", reason))
+            }
+            (_, Some(Some(reason))) => (
+                '!',
+                "[remote rejected]".to_string(),
+                format!(" ({})", reason),
+            ),
+            (_, None) => (
+                '!',
+                "[remote failure]".to_string(),
+                " (remote failed to report status)".to_string(),
+            ),
+            (Outcome::Delete, _) => ('-', "[deleted]".to_string(), String::new()),
+            (Outcome::Create, _) => {
+                let kind = if push.destination.starts_with("refs/tags/") {
+                    "[new tag]"
+                } else if push.destination.starts_with("refs/heads/") {
+                    "[new branch]"
+                } else {
+                    "[new reference]"
+                };
+                ('*', kind.to_string(), String::new())
+            }
+            (Outcome::FastForward(old), _) => (
+                ' ',
+                format!(
+                    "{}..{}",
+                    short(old),
+                    short(&push.source.unwrap_or_default())
+                ),
+                String::new(),
+            ),
+            (Outcome::Forced(old), _) => (
+                '+',
+                format!(
+                    "{}...{}",
+                    short(old),
+                    short(&push.source.unwrap_or_default())
+                ),
+                " (forced update)".to_string(),
+            ),
+        };
+        if flag == '-' {
+            eprintln!(" {} {:<17} {}", flag, summary, destination);
+        } else {
+            eprintln!(
+                " {} {:<17} {} -> {}{}",
+                flag, summary, source, destination, note
+            );
+        }
+        if flag == '!' {
+            failed = true;
+            continue;
+        }
+        if let Some(tracking) = tracking.map(&push.destination) {
+            match push.source {
+                Some(sha) => refs::update_ref(&tracking, &sha, None)?,
+                None if refs::read_ref(&tracking)?.is_some() => refs::delete_ref(&tracking, None)?,
+                None => (),
+            }
+        }
+    }
+
+    if set_upstream {
+        for (push, outcome) in pushes.iter().zip(&outcomes) {
+            let pushed = matches!(
+                outcome,
+                Outcome::UpToDate | Outcome::Create | Outcome::FastForward(_) | Outcome::Forced(_)
+            );
+            if let Some(branch) = &push.branch
+                && pushed
+                && push.destination.starts_with("refs/heads/")
+            {
+                let upstream = format!("{}/{}", remote, short_name(&push.destination));
+                if dry_run::intercept(|| format!("set up '{}' to track '{}'", branch, upstream)) {
+                    continue;
+                }
+                let config_path = ObjectStorage::git_dir().join("config");
+                let section = format!("branch.{}", branch);
+                config::set_value(&config_path, &section, "remote", Some(remote))?;
+                config::set_value(&config_path, &section, "merge", Some(&push.destination))?;
+                println!("branch '{}' set up to track '{}'.", branch, upstream);
+            }
+        }
+    }
+
+    if failed {
+        eprintln!("error: failed to push some refs to '{}'", url);
+        if let Some(rejection) = rejections.into_iter().min() {
+            advise(rejection);
+        }
+        Err(CommandExit { code: 1 })?
+    }
+    Ok(())
+}
+
+fn is_fast_forward(old: &Sha, new: &Sha) -> anyhow::Result<bool> {
+    let (Ok(old), Ok(new)) = (
+        ObjectStorage::peel_to_commit(old),
+        ObjectStorage::peel_to_commit(new),
+    ) else {
+        return Ok(false);
+    };
+    commit_walk::is_ancestor(&old, &new)
+}
+
+/// The refspec mapping the remote's branches to their remote-tracking refs.
+fn remote_tracking_refspec(remote: &str) -> anyhow::Result<Refspec> {
+    let refspec = Config::get()?
+        .value(&format!("remote.{}", remote), "fetch")
+        .unwrap_or(format!("+refs/heads/*:refs/remotes/{}/*", remote));
+    Refspec::parse(&refspec)
+}
+
+/// Git's advice for the most important reason updates were rejected.
+fn advise(rejection: Rejection) {
+    let advice = match rejection {
+        Rejection::NonFastForwardHead => {
+            "Updates were rejected because the tip of your current branch is behind\n\
+             its remote counterpart. Integrate the remote changes (e.g.\n\
+             'git pull ...') before pushing again.\n\
+             See the 'Note about fast-forwards' in 'git push --help' for details."
+        }
+        Rejection::NonFastForwardOther => {
+            "Updates were rejected because a pushed branch tip is behind its remote\n\
+             counterpart. Check out this branch and integrate the remote changes\n\
+             (e.g. 'git pull ...') before pushing again.\n\
+             See the 'Note about fast-forwards' in 'git push --help' for details."
+        }
+        Rejection::AlreadyExists => {
+            "Updates were rejected because the tag already exists in the remote."
+        }
+        Rejection::FetchFirst => {
+            "Updates were rejected because the remote contains work that you do\n\
+             not have locally. This is usually caused by another repository pushing\n\
+             to the same ref. You may want to first integrate the remote changes\n\
+             (e.g., 'git pull ...') before pushing again.\n\
+             See the 'Note about fast-forwards' in 'git push --help' for details."
+        }
+    };
+    for line in advice.lines() {
+        eprintln!("hint: {}", line);
+    }
+}
+
+/// The objects reachable from `tips` that are not reachable from `haves`, which the remote
+/// has, each with the path it was reached by.
+fn objects_to_send(tips: &[Sha], haves: &[Sha]) -> anyhow::Result<Vec<(Sha, String)>> {
+    let mut excluded = OidSet::new()?;
+    walk(haves, |sha, _| excluded.insert(*sha))?;
+    let mut objects = vec![];
+    walk(tips, |sha, path| {
+        let new = !excluded.contains(sha);
+        if new {
+            objects.push((*sha, path.to_string()));
+        }
+        new
+    })?;
+    Ok(objects)
+}
+
+/// Visits every object reachable from `tips` once, with the path it was reached by, going
+/// on to the objects it refers to only when `visit` returns true. Blobs are not read.
+fn walk(tips: &[Sha], mut visit: impl FnMut(&Sha, &str) -> bool) -> anyhow::Result<()> {
+    let mut seen = OidSet::new()?;
+    let mut pending: Vec<(Sha, String)> = tips.iter().map(|sha| (*sha, String::new())).collect();
+    while let Some((sha, path)) = pending.pop() {
+        if !seen.insert(sha) || promisor::may_skip(&sha)? || !visit(&sha, &path) {
+            continue;
+        }
+        if ObjectStorage::open_object(&sha)?.object_type == "blob" {
+            continue;
+        }
+        match GitObject::from_sha(&sha)? {
+            GitObject::Commit(commit) => {
+                pending.extend(commit.parents.iter().map(|parent| (*parent, String::new())));
+                pending.push((commit.tree, String::new()));
+            }
+            GitObject::Tree(tree) => {
+                for entry in tree.entries {
+                    let entry_path = if path.is_empty() {
+                        entry.name
+                    } else {
+                        format!("{}/{}", path, entry.name)
+                    };
+                    pending.push((entry.hash, entry_path));
+                }
+            }
+            GitObject::Tag(tag) => pending.push((tag.object, String::new())),
+            GitObject::Blob(_) => (),
+        }
+    }
+    Ok(())
+}
+
+fn short(sha: &Sha) -> String {
+    ObjectStorage::sha_to_hex_string(sha)[..7].to_string()
+}
@@ -0,0 +1,23 @@
+use crate::cli;
+use crate::repack::{self, RepackOptions};
+use anyhow::anyhow;
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("repack", args)?;
+    if !args.operands.is_empty() {
+        return Err(cli::usage_error("repack"));
+    }
+    let mut options = RepackOptions::new(args.has("-a"), args.has("-d"), args.has("-q"))?;
+    let number = |name: &str, value: &str| {
+        value
+            .parse::<usize>()
+            .map_err(|_| anyhow!("invalid {} '{}'", name, value))
+    };
+    if let Some(window) = args.value("--window") {
+        options.window = number("window", window)?;
+    }
+    if let Some(depth) = args.value("--depth") {
+        options.depth = number("depth", depth)?;
+    }
+    repack::repack(&options)
+}
@@ -0,0 +1,111 @@
+use super::checkout;
+use crate::backup::Backup;
+use crate::cli;
+use crate::config::Config;
+use crate::context;
+use crate::index::{self, Index, IndexEntry, IndexLock};
+use crate::object_storage::{ObjectStorage, pack};
+use crate::refs;
+use crate::search_index;
+use anyhow::anyhow;
+use std::fs;
+use std::path::{self, PathBuf};
+
+/// Recreates the repository a `backup` file was written from in a new directory, checking
+/// out the files of its index when it had a working tree.
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("restore", args)?;
+    let [file, directory] = args.operands.as_slice() else {
+        return Err(cli::usage_error("restore"));
+    };
+    let data = fs::read(file).map_err(|err| anyhow!("could not read '{}': {}", file, err))?;
+    let backup = Backup::parse(&data)?;
+    let directory = PathBuf::from(directory);
+    let existed = directory.exists();
+    if existed && fs::read_dir(&directory)?.next().is_some() {
+        Err(anyhow!(
+            "destination path '{}' already exists and is not an empty directory.",
+            directory.display()
+        ))?
+    }
+    let bare = backup.section("bare").is_some();
+    fs::create_dir_all(&directory)?;
+    let top = path::absolute(&directory)?;
+    let (git_dir, work_tree) = match bare {
+        true => (top, None),
+        false => (top.join(".git"), Some(top)),
+    };
+    eprintln!("Restoring into '{}'...", directory.display());
+    let context = context::current().for_repository(git_dir.clone(), work_tree);
+    let result = context::enter(context, || restore_into(&backup));
+    if result.is_err() {
+        // Like clone, don't leave a half-restored repository behind.
+        let _ = if existed && !bare {
+            fs::remove_dir_all(git_dir)
+        } else if existed {
+            fs::read_dir(&directory).and_then(|entries| {
+                entries
+                    .flatten()
+                    .try_for_each(|entry| fs::remove_dir_all(entry.path()))
+            })
+        } else {
+            fs::remove_dir_all(&directory)
+        };
+    }
+    result
+}
+
+fn restore_into(backup: &Backup) -> anyhow::Result<()> {
+    let git_dir = ObjectStorage::git_dir();
+    fs::create_dir_all(git_dir.join("objects"))?;
+    fs::create_dir_all(git_dir.join("refs"))?;
+    fs::write(
+        git_dir.join("config"),
+        backup.section("config").unwrap_or_default(),
+    )?;
+    Config::invalidate();
+    if let Some(data) = backup.section("pack") {
+        pack::write_pack(data)?;
+    }
+    let ref_lines = backup
+        .section("refs")
+        .ok_or(anyhow!("backup file has no refs"))?;
+    for line in String::from_utf8_lossy(ref_lines).lines() {
+        let invalid = || anyhow!("backup file has an invalid ref line '{}'", line);
+        if let Some(symbolic) = line.strip_prefix("ref: ") {
+            let (target, name) = symbolic.split_once(' ').ok_or_else(invalid)?;
+            refs::write_symbolic_ref(name, target)?;
+        } else {
+            let (sha, name) = line.split_once(' ').ok_or_else(invalid)?;
+            let sha = ObjectStorage::hex_string_to_sha(sha).map_err(|_| invalid())?;
+            refs::update_ref_no_deref(name, &sha, None)?;
+        }
+    }
+
+    if let Some(data) = backup.section("index") {
+        let lock = IndexLock::acquire()?;
+        fs::write(Index::path(), data)?;
+        let mut index = Index::read()?;
+        let mut files = vec![];
+        for entry in index.entries.iter().filter(|entry| entry.stage == 0) {
+            let permission = index::mode_permission(entry.mode).ok_or(anyhow!(
+                "invalid mode {:o} for '{}'",
+                entry.mode,
+                entry.path
+            ))?;
+            files.push((entry.path.clone(), permission, entry.sha));
+        }
+        checkout::finish_checkout(ObjectStorage::checkout_files(&files)?)?;
+        // The stat data in the backup is that of the files it was made from.
+        let work_tree = ObjectStorage::work_tree();
+        for entry in index.entries.iter_mut().filter(|entry| entry.stage == 0) {
+            let file = work_tree.join(&entry.path);
+            *entry = IndexEntry {
+                mode: entry.mode,
+                ..IndexEntry::from_file(&entry.path, &file, entry.sha)?
+            };
+        }
+        index.write(lock)?;
+    }
+    search_index::update()
+}
@@ -0,0 +1,77 @@
+use crate::cli;
+use crate::commit_walk;
+use crate::object_storage::{ObjectStorage, Sha};
+use crate::oid_set::OidSet;
+use crate::revision;
+use anyhow::anyhow;
+
+/// Lists the commits reachable from the given ones, newest first, leaving out those reachable
+/// from a `^<commit>`; `A..B` stands for `^A B`, and `A...B` for the commits reachable from
+/// either but not both. `--count` prints how many there are instead, and with `--left-right`
+/// and `A...B` how many are on each side, as `<left>\t<right>`. Counting only follows
+/// parents, which the commit-graph has, so it doesn't read the commits themselves.
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("rev-list", args)?;
+    let (mut tips, mut excluded) = (vec![], vec![]);
+    let mut symmetric = None;
+    for operand in &args.operands {
+        if let Some((left, right)) = operand.split_once("...") {
+            let (left, right) = (commit(left)?, commit(right)?);
+            tips.extend([left, right]);
+            excluded.extend(commit_walk::merge_bases(&left, &[right])?);
+            symmetric = Some((left, right));
+        } else if let Some((from, to)) = operand.split_once("..") {
+            excluded.push(commit(from)?);
+            tips.push(commit(to)?);
+        } else if let Some(revision) = operand.strip_prefix('^') {
+            excluded.push(commit(revision)?);
+        } else {
+            tips.push(commit(operand)?);
+        }
+    }
+    if tips.is_empty() {
+        return Err(cli::usage_error("rev-list"));
+    }
+    let left_right = match symmetric {
+        Some(sides) if args.has("--left-right") && args.operands.len() == 1 => Some(sides),
+        _ => None,
+    };
+    if args.has("--count") {
+        match left_right {
+            Some((left, right)) => {
+                let (ahead, behind) = commit_walk::ahead_behind(&left, &right)?;
+                println!("{}\t{}", ahead, behind);
+            }
+            None => println!("{}", commit_walk::count_between(&tips, &excluded)?),
+        }
+        return Ok(());
+    }
+    let from_left: Option<OidSet> = match left_right {
+        Some((left, _)) => Some(commit_walk::reachable_from(&[left])?),
+        None => None,
+    };
+    for (sha, _) in commit_walk::commits_between(&tips, &excluded)? {
+        let side = match &from_left {
+            Some(from_left) if from_left.contains(&sha) => "<",
+            Some(_) => ">",
+            None => "",
+        };
+        println!("{}{}", side, ObjectStorage::sha_to_hex_string(&sha));
+    }
+    Ok(())
+}
+
+fn commit(revision: &str) -> anyhow::Result<Sha> {
+    let revision = if revision.is_empty() {
+        "HEAD"
+    } else {
+        revision
+    };
+    let sha = revision::resolve(revision).map_err(|_| {
+        anyhow!(
+            "ambiguous argument '{}': unknown revision or path not in the working tree.",
+            revision
+        )
+    })?;
+    ObjectStorage::peel_to_commit(&sha)
+}
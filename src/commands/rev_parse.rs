@@ -0,0 +1,90 @@
+use crate::cli;
+use crate::compat_map;
+use crate::object_storage::{ObjectStorage, Sha};
+use crate::repository::CommandExit;
+use crate::revision;
+use anyhow::anyhow;
+use std::env;
+
+/// The length `--short` abbreviates to when that is unique, git's default `core.abbrev`.
+const DEFAULT_ABBREV: usize = 7;
+
+/// Prints the object id each revision names. With `--verify` exactly one revision is expected,
+/// and `-q` turns a failure into a silent exit status of 1. `--git-dir`, `--show-toplevel` and
+/// `--is-inside-work-tree` print what repository discovery found, in the order they are
+/// given, before any revisions. `--output-object-format=sha256` prints the SHA-256 names a
+/// compatibility map gives the objects instead.
+pub fn run(raw_args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("rev-parse", raw_args)?;
+    for arg in raw_args {
+        if matches!(
+            arg.as_str(),
+            "--git-dir" | "--show-toplevel" | "--is-inside-work-tree"
+        ) {
+            show_repository(arg)?;
+        }
+    }
+    if args.operands.is_empty() && !args.has("--verify") {
+        return Ok(());
+    }
+    let compat = match args.value("--output-object-format") {
+        None | Some("sha1") => false,
+        Some("sha256") if compat_map::enabled()? => true,
+        Some(format) => Err(anyhow!("unsupported object format: {}", format))?,
+    };
+    if args.has("--verify") {
+        let sha = match args.operands.as_slice() {
+            [revision] => revision::resolve(revision).ok(),
+            _ => None,
+        };
+        let Some(sha) = sha else {
+            if args.has("-q") {
+                Err(CommandExit { code: 1 })?
+            }
+            Err(anyhow!("Needed a single revision"))?
+        };
+        return print(&sha, args.has("--short"), compat);
+    }
+    for revision in &args.operands {
+        print(&revision::resolve(revision)?, args.has("--short"), compat)?;
+    }
+    Ok(())
+}
+
+/// Prints `sha`, or with `compat` its SHA-256 name, whose abbreviation is not checked for
+/// uniqueness.
+fn print(sha: &Sha, short: bool, compat: bool) -> anyhow::Result<()> {
+    if compat {
+        let hex = compat_map::to_hex(&compat_map::compat_id(sha)?);
+        println!("{}", if short { &hex[..DEFAULT_ABBREV] } else { &hex });
+    } else if short {
+        println!("{}", revision::abbreviate(sha, DEFAULT_ABBREV)?);
+    } else {
+        println!("{}", ObjectStorage::sha_to_hex_string(sha));
+    }
+    Ok(())
+}
+
+fn show_repository(query: &str) -> anyhow::Result<()> {
+    let cwd = env::current_dir()?;
+    let git_dir = ObjectStorage::git_dir();
+    let work_tree = ObjectStorage::work_tree();
+    match query {
+        // Relative to the current directory when that is the top of the working tree or the
+        // git directory itself, like git.
+        "--git-dir" if git_dir == cwd => println!("."),
+        "--git-dir" if git_dir == cwd.join(".git") => println!(".git"),
+        "--git-dir" => println!("{}", git_dir.display()),
+        "--show-toplevel" if !ObjectStorage::has_work_tree() => {
+            Err(anyhow!("this operation must be run in a work tree"))?
+        }
+        "--show-toplevel" => println!("{}", work_tree.display()),
+        _ => {
+            let inside = ObjectStorage::has_work_tree()
+                && cwd.starts_with(&work_tree)
+                && !cwd.starts_with(&git_dir);
+            println!("{}", inside);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,8 @@
+use super::cherry_pick;
+use crate::sequencer::Action;
+
+/// Records new commits that undo the changes the given commits introduce; see
+/// [`cherry_pick::run_sequence`].
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    cherry_pick::run_sequence(Action::Revert, args)
+}
@@ -0,0 +1,86 @@
+use crate::cli;
+use crate::object_storage::{ObjectStorage, Sha};
+use crate::refglob::{self, Excludes};
+use crate::refs;
+use crate::repository::CommandExit;
+use crate::revision;
+use anyhow::anyhow;
+
+/// Lists the refs, those `--glob` picks if given and less those `--exclude` leaves out, or
+/// with `--verify` checks that each ref named in full exists: a script can test for a branch
+/// with `show-ref --verify -q refs/heads/<name>`. Either way the status is 1 when nothing was
+/// shown.
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("show-ref", args)?;
+    let quiet = args.has("-q");
+    let abbrev = match args.values_or("-s", "40").pop() {
+        Some(length) => Some(
+            length
+                .parse::<usize>()
+                .map_err(|_| anyhow!("option `hash' expects a numerical value"))?
+                .clamp(4, 40),
+        ),
+        None => None,
+    };
+    let show = |name: &str, sha: &Sha| -> anyhow::Result<()> {
+        if quiet {
+            return Ok(());
+        }
+        match abbrev {
+            Some(40) => println!("{}", ObjectStorage::sha_to_hex_string(sha)),
+            Some(length) => println!("{}", revision::abbreviate(sha, length)?),
+            None => println!("{} {}", ObjectStorage::sha_to_hex_string(sha), name),
+        }
+        Ok(())
+    };
+
+    if args.has("--verify") {
+        if args.operands.is_empty() {
+            Err(anyhow!("--verify requires a reference"))?
+        }
+        for name in &args.operands {
+            let sha = if name == "HEAD" || name.starts_with("refs/") {
+                refs::resolve_ref(name)?.1
+            } else {
+                None
+            };
+            match sha {
+                Some(sha) => show(name, &sha)?,
+                None if quiet => Err(CommandExit { code: 1 })?,
+                None => Err(anyhow!("'{}' - not a valid ref", name))?,
+            }
+        }
+        return Ok(());
+    }
+
+    let mut found = false;
+    if args.has("--head")
+        && let (_, Some(sha)) = refs::resolve_ref("HEAD")?
+    {
+        show("HEAD", &sha)?;
+        found = true;
+    }
+    let (heads, tags) = (args.has("--heads"), args.has("--tags"));
+    let excludes = Excludes::from_patterns(&args.values("--exclude"));
+    for (name, sha) in refglob::listed_refs(None, &args.values("--glob"), &excludes)? {
+        if (heads || tags)
+            && !(heads && name.starts_with("refs/heads/") || tags && name.starts_with("refs/tags/"))
+        {
+            continue;
+        }
+        if !args.operands.is_empty()
+            && !args
+                .operands
+                .iter()
+                .any(|pattern| refglob::matches_tail(pattern, &name))
+        {
+            continue;
+        }
+        show(&name, &sha)?;
+        found = true;
+    }
+    if !found {
+        Err(CommandExit { code: 1 })?
+    }
+    Ok(())
+}
@@ -0,0 +1,88 @@
+use crate::cancel;
+use crate::chunking;
+use crate::cli;
+use crate::object_storage::{ObjectStorage, Sha, pack};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+
+/// Prints how many objects of each type the repository stores and their size uncompressed,
+/// then how much the object files and packs take on disk. With `--dedup`, the content of every
+/// blob is also cut into chunks with FastCDC to report how many bytes are left when each
+/// distinct chunk is stored once: what deduplicating the blobs across history could save at
+/// best, to compare with what the packs already save.
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("stats", args)?;
+    if !args.operands.is_empty() {
+        return Err(cli::usage_error("stats"));
+    }
+    let dedup = args.has("--dedup");
+
+    let mut objects: Vec<Sha> = vec![];
+    let mut seen: HashSet<Sha> = HashSet::new();
+    let mut disk_size = 0;
+    let loose = ObjectStorage::loose_objects()?;
+    for (sha, path) in &loose {
+        disk_size += fs::metadata(path)?.len();
+        if seen.insert(*sha) {
+            objects.push(*sha);
+        }
+    }
+    let packs = pack::loaded_packs()?;
+    for pack in packs.iter() {
+        disk_size += fs::metadata(pack.pack_path())?.len();
+        for sha in pack.shas() {
+            if seen.insert(*sha) {
+                objects.push(*sha);
+            }
+        }
+    }
+
+    let mut types: BTreeMap<String, (usize, u64)> = BTreeMap::new();
+    let mut blob_size = 0;
+    let mut chunks = 0;
+    let mut unique_chunks: HashSet<Sha> = HashSet::new();
+    let mut unique_size = 0;
+    for sha in &objects {
+        cancel::check()?;
+        let object = ObjectStorage::open_object(sha)?;
+        let entry = types.entry(object.object_type.clone()).or_default();
+        entry.0 += 1;
+        entry.1 += object.size;
+        if !dedup || object.object_type != "blob" {
+            continue;
+        }
+        blob_size += object.size;
+        let content = object.into_content()?;
+        for chunk in chunking::chunks(&content) {
+            chunks += 1;
+            if unique_chunks.insert(ObjectStorage::hash_content(&content[chunk.clone()])) {
+                unique_size += chunk.len() as u64;
+            }
+        }
+    }
+
+    for object_type in ["commit", "tree", "blob", "tag"] {
+        let (count, size) = types.get(object_type).copied().unwrap_or_default();
+        println!("{}s: {}, {} bytes", object_type, count, size);
+    }
+    println!(
+        "on disk: {} bytes ({} loose objects, {} packs)",
+        disk_size,
+        loose.len(),
+        packs.len()
+    );
+    if dedup {
+        let savings = blob_size - unique_size;
+        let percentage = match blob_size {
+            0 => 0.0,
+            _ => savings as f64 * 100.0 / blob_size as f64,
+        };
+        println!("blob chunks: {}, {} unique", chunks, unique_chunks.len());
+        println!("unique chunk bytes: {}", unique_size);
+        println!(
+            "potential savings: {} bytes ({:.1}% of the blob bytes)",
+            savings, percentage
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,354 @@
+use crate::cli;
+use crate::diff::{self, FileChange};
+use crate::ignore::IgnoreRules;
+use crate::index::{self, Index};
+use crate::object_storage::{ObjectStorage, TreeEntryPermission};
+use crate::pathspec::PathspecSet;
+use crate::refs::{self, PseudoRef};
+use crate::sequencer::{Action, Sequence};
+use crate::work_tree;
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    show(&cli::parse_args("status", args)?, false)
+}
+
+/// Shows the status as `commit` does when there is nothing to commit, which calls the first
+/// commit of a branch the initial commit.
+pub fn show_for_commit() -> anyhow::Result<()> {
+    show(&cli::parse_args("status", &[])?, true)
+}
+
+fn show(args: &cli::ParsedArgs, committing: bool) -> anyhow::Result<()> {
+    let pathspecs = PathspecSet::from_args(&args.operands)?;
+    let index = Index::read()?;
+    let (_, head) = refs::resolve_ref("HEAD")?;
+    let head_tree = head
+        .map(|sha| ObjectStorage::peel_to_tree(&sha))
+        .transpose()?;
+    // The stages each conflicted path has, as bits 1 (common), 2 (ours) and 4 (theirs).
+    let mut unmerged: BTreeMap<&str, u8> = BTreeMap::new();
+    for entry in index.entries.iter().filter(|entry| entry.stage != 0) {
+        if pathspecs.matches(&entry.path, false) {
+            *unmerged.entry(&entry.path).or_default() |= 1 << (entry.stage - 1);
+        }
+    }
+    let staged: Vec<(String, char)> = diff::diff_tree_to_index(head_tree.as_ref(), &index)?
+        .iter()
+        .filter(|change| pathspecs.matches(&change.path, false))
+        .filter(|change| !unmerged.contains_key(change.path.as_str()))
+        .map(|change| (change.path.clone(), staged_status(change)))
+        .collect();
+    let unstaged = unstaged_changes(&index, &pathspecs)?;
+    let mut untracked = vec![];
+    let tracked_dirs: HashSet<&str> = index
+        .entries
+        .iter()
+        .flat_map(|entry| {
+            entry
+                .path
+                .match_indices('/')
+                .map(|(end, _)| &entry.path[..end])
+        })
+        .collect();
+    collect_untracked(
+        &ObjectStorage::work_tree(),
+        "",
+        &index,
+        &tracked_dirs,
+        &pathspecs,
+        &IgnoreRules::new()?,
+        &mut untracked,
+    )?;
+
+    let branch = refs::current_branch()?.map(|branch| {
+        branch
+            .strip_prefix("refs/heads/")
+            .unwrap_or(&branch)
+            .to_string()
+    });
+    let null_terminated = args.has("-z");
+    if args.has("-s") || args.has("--porcelain") || null_terminated {
+        // With -z, entries end in NUL and paths are written as they are.
+        let (end, quote): (&str, fn(&str) -> String) = match null_terminated {
+            true => ("\0", str::to_string),
+            false => ("\n", cli::quote_path),
+        };
+        if args.has("-b") {
+            match (&branch, head) {
+                (Some(branch), Some(_)) => print!("## {}{}", branch, end),
+                (Some(branch), None) => print!("## No commits yet on {}{}", branch, end),
+                (None, _) => print!("## HEAD (no branch){}", end),
+            }
+        }
+        let mut codes: BTreeMap<&str, [char; 2]> = BTreeMap::new();
+        for (path, status) in &staged {
+            codes.entry(path).or_insert([' ', ' '])[0] = *status;
+        }
+        for (path, status) in &unstaged {
+            codes.entry(path).or_insert([' ', ' '])[1] = *status;
+        }
+        for (path, stages) in &unmerged {
+            codes.insert(path, unmerged_status(*stages).0);
+        }
+        for (path, [x, y]) in codes {
+            print!("{}{} {}{}", x, y, quote(path), end);
+        }
+        for path in &untracked {
+            print!("?? {}{}", quote(path), end);
+        }
+        return Ok(());
+    }
+
+    let program = cli::program_name();
+    match (&branch, head) {
+        (Some(branch), _) => println!("On branch {}", branch),
+        (None, Some(head)) => println!(
+            "HEAD detached at {}",
+            &ObjectStorage::sha_to_hex_string(&head)[..7]
+        ),
+        (None, None) => println!("Not currently on any branch."),
+    }
+    print_operation_state(!unmerged.is_empty())?;
+    if head.is_none() {
+        if committing {
+            println!("\nInitial commit\n");
+        } else {
+            println!("\nNo commits yet\n");
+        }
+    }
+    if !staged.is_empty() {
+        println!("Changes to be committed:");
+        print_changes(&staged);
+    }
+    if !unmerged.is_empty() {
+        println!("Unmerged paths:");
+        println!("  (use \"{} add <file>...\" to mark resolution)", program);
+        for (path, stages) in &unmerged {
+            println!("\t{:<17}{}", unmerged_status(*stages).1, path);
+        }
+        println!();
+    }
+    if !unstaged.is_empty() {
+        println!("Changes not staged for commit:");
+        println!(
+            "  (use \"{} add <file>...\" to update what will be committed)",
+            program
+        );
+        print_changes(&unstaged);
+    }
+    if !untracked.is_empty() {
+        println!("Untracked files:");
+        println!(
+            "  (use \"{} add <file>...\" to include in what will be committed)",
+            program
+        );
+        for path in &untracked {
+            println!("\t{}", path);
+        }
+        println!();
+    }
+    if !staged.is_empty() {
+        return Ok(());
+    }
+    if !unstaged.is_empty() || !unmerged.is_empty() {
+        println!(
+            "no changes added to commit (use \"{} add\" to stage them)",
+            program
+        );
+    } else if !untracked.is_empty() {
+        println!(
+            "nothing added to commit but untracked files present (use \"{} add\" to track)",
+            program
+        );
+    } else if head.is_none() {
+        println!(
+            "nothing to commit (create/copy files and use \"{} add\" to track)",
+            program
+        );
+    } else {
+        println!("nothing to commit, working tree clean");
+    }
+    Ok(())
+}
+
+/// Says which merge, cherry-pick or revert is in progress, and how to conclude it.
+fn print_operation_state(has_conflicts: bool) -> anyhow::Result<()> {
+    let program = cli::program_name();
+    if !refs::read_pseudo_ref(PseudoRef::Merge)?.is_empty() {
+        if has_conflicts {
+            println!("You have unmerged paths.");
+            println!("  (fix conflicts and run \"{} commit\")", program);
+        } else {
+            println!("All conflicts fixed but you are still merging.");
+            println!("  (use \"{} commit\" to conclude merge)", program);
+        }
+        println!();
+        return Ok(());
+    }
+    // A sequence of several commits is in progress as a whole, whichever step it is at.
+    let sequence_action =
+        Sequence::load()?.and_then(|sequence| sequence.todo.first().map(|step| step.action));
+    for action in [Action::Pick, Action::Revert] {
+        let command = action.command();
+        let stopped = refs::read_pseudo_ref(action.pseudo_ref())?;
+        let (doing, operation) = match action {
+            Action::Pick => ("cherry-picking", "Cherry-pick"),
+            Action::Revert => ("reverting", "Revert"),
+        };
+        let in_sequence = sequence_action == Some(action);
+        match stopped.first() {
+            _ if in_sequence => println!("{} currently in progress.", operation),
+            Some(sha) => println!(
+                "You are currently {} commit {}.",
+                doing,
+                &ObjectStorage::sha_to_hex_string(sha)[..7]
+            ),
+            None => continue,
+        }
+        if has_conflicts {
+            println!(
+                "  (fix conflicts and run \"{} {} --continue\")",
+                program, command
+            );
+        } else if in_sequence || stopped.is_empty() {
+            println!("  (run \"{} {} --continue\" to continue)", program, command);
+        } else {
+            println!(
+                "  (all conflicts fixed: run \"{} {} --continue\")",
+                program, command
+            );
+        }
+        println!(
+            "  (use \"{} {} --skip\" to skip this patch)",
+            program, command
+        );
+        println!(
+            "  (use \"{} {} --abort\" to cancel the {} operation)",
+            program, command, command
+        );
+        println!();
+        return Ok(());
+    }
+    Ok(())
+}
+
+/// The short status code and the long status label of a conflicted path with `stages`.
+fn unmerged_status(stages: u8) -> ([char; 2], &'static str) {
+    match stages {
+        0b001 => (['D', 'D'], "both deleted:"),
+        0b010 => (['A', 'U'], "added by us:"),
+        0b011 => (['U', 'D'], "deleted by them:"),
+        0b100 => (['U', 'A'], "added by them:"),
+        0b101 => (['D', 'U'], "deleted by us:"),
+        0b110 => (['A', 'A'], "both added:"),
+        _ => (['U', 'U'], "both modified:"),
+    }
+}
+
+fn print_changes(changes: &[(String, char)]) {
+    for (path, status) in changes {
+        let label = match status {
+            'A' => "new file:",
+            'D' => "deleted:",
+            'T' => "typechange:",
+            _ => "modified:",
+        };
+        println!("\t{:<12}{}", label, path);
+    }
+    println!();
+}
+
+/// `A`, `D`, `T` (a file became a symlink or the other way round) or `M`.
+fn staged_status(change: &FileChange) -> char {
+    match (change.old, change.new) {
+        (None, _) => 'A',
+        (_, None) => 'D',
+        _ if change.old_mode.map(is_symlink) != change.new_mode.map(is_symlink) => 'T',
+        _ => 'M',
+    }
+}
+
+fn is_symlink(permission: TreeEntryPermission) -> bool {
+    permission == TreeEntryPermission::SymbolicLink
+}
+
+/// Staged files whose working tree copy was deleted (`D`), changed between file and symlink
+/// (`T`) or modified (`M`). The stat data recorded in the index avoids hashing unchanged files.
+fn unstaged_changes(index: &Index, pathspecs: &PathspecSet) -> anyhow::Result<Vec<(String, char)>> {
+    let work_tree = ObjectStorage::work_tree();
+    let mut changes = vec![];
+    for entry in index.entries.iter().filter(|entry| entry.stage == 0) {
+        if !pathspecs.matches(&entry.path, false) {
+            continue;
+        }
+        let file = work_tree.join(&entry.path);
+        let status = match fs::symlink_metadata(&file) {
+            Err(_) => 'D',
+            Ok(metadata) if metadata.is_dir() => 'D',
+            Ok(metadata)
+                if (index::staged_file_mode(&metadata, entry.mode) == index::MODE_SYMLINK)
+                    != (entry.mode == index::MODE_SYMLINK) =>
+            {
+                'T'
+            }
+            Ok(_) if !entry.matches_file(&file)? => 'M',
+            Ok(_) => continue,
+        };
+        changes.push((entry.path.clone(), status));
+    }
+    Ok(changes)
+}
+
+/// Collects the files below `dir` that are neither in the index nor ignored. A directory
+/// without any tracked file is listed once as `dir/`, like git does, provided it holds some
+/// file that is not ignored.
+fn collect_untracked(
+    dir: &Path,
+    prefix: &str,
+    index: &Index,
+    tracked_dirs: &HashSet<&str>,
+    pathspecs: &PathspecSet,
+    ignore: &IgnoreRules,
+    untracked: &mut Vec<String>,
+) -> anyhow::Result<()> {
+    let ignore = ignore.enter(dir, prefix)?;
+    for entry in work_tree::sorted_entries(dir)? {
+        let path = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        let is_dir = entry.file_type()?.is_dir();
+        if ignore.is_ignored(&path, is_dir) {
+            continue;
+        }
+        if !is_dir {
+            if index.find(&path).is_none() && pathspecs.matches(&path, false) {
+                untracked.push(path);
+            }
+        } else if tracked_dirs.contains(path.as_str()) {
+            let subdir_prefix = format!("{}/", path);
+            collect_untracked(
+                &entry.path(),
+                &subdir_prefix,
+                index,
+                tracked_dirs,
+                pathspecs,
+                &ignore,
+                untracked,
+            )?;
+        } else if pathspecs.could_match_within(&path) {
+            let mut files = vec![];
+            work_tree::collect_files(
+                &entry.path(),
+                &format!("{}/", path),
+                pathspecs,
+                Some(&ignore),
+                &mut files,
+            )?;
+            if !files.is_empty() {
+                untracked.push(format!("{}/", path));
+            }
+        }
+    }
+    Ok(())
+}
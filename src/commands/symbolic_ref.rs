@@ -0,0 +1,28 @@
+use crate::cli;
+use crate::refs;
+use anyhow::anyhow;
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("symbolic-ref", args)?;
+    let short = args.has("--short");
+    let operands = &args.operands;
+    match operands.as_slice() {
+        [name] => match refs::read_ref(name)? {
+            Some(refs::RefValue::Symbolic(target)) => {
+                let shown = if short {
+                    ["refs/heads/", "refs/tags/", "refs/remotes/", "refs/"]
+                        .iter()
+                        .find_map(|prefix| target.strip_prefix(prefix))
+                        .unwrap_or(&target)
+                } else {
+                    &target
+                };
+                println!("{}", shown);
+                Ok(())
+            }
+            _ => Err(anyhow!("ref {} is not a symbolic ref", name)),
+        },
+        [name, target] if !short => refs::write_symbolic_ref(name, target),
+        _ => Err(cli::usage_error("symbolic-ref")),
+    }
+}
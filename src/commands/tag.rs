@@ -0,0 +1,122 @@
+use super::commit::cleanup_message;
+use crate::cli;
+use crate::commit_walk::ReachabilityFilter;
+use crate::object_storage::{GitObject, ObjectStorage, Sha, Signature, Tag};
+use crate::refglob::{self, Excludes};
+use crate::refs;
+use crate::revision;
+use anyhow::anyhow;
+use std::fs;
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("tag", args)?;
+    if args.has("-d") {
+        if args.operands.is_empty() {
+            return Err(cli::usage_error("tag"));
+        }
+        for name in &args.operands {
+            let full_name = format!("refs/tags/{}", name);
+            let Some((_, sha)) = refs::dwim_ref(&full_name)? else {
+                Err(anyhow!("tag '{}' not found.", name))?
+            };
+            refs::delete_ref(&full_name, Some(&sha))?;
+            println!(
+                "Deleted tag '{}' (was {})",
+                name,
+                &ObjectStorage::sha_to_hex_string(&sha)[..7]
+            );
+        }
+        return Ok(());
+    }
+    let annotated = args.has("-a") || args.has("-m") || args.has("-F");
+    if args.has("-l") || (args.operands.is_empty() && !annotated) {
+        return list(&args);
+    }
+    let (name, target) = match args.operands.as_slice() {
+        [name] => (name, "HEAD"),
+        [name, target] => (name, target.as_str()),
+        _ => return Err(cli::usage_error("tag")),
+    };
+    let full_name = format!("refs/tags/{}", name);
+    refs::check_ref_format(&full_name)
+        .map_err(|_| anyhow!("'{}' is not a valid tag name.", name))?;
+    let force = args.has("-f");
+    if !force && refs::read_ref(&full_name)?.is_some() {
+        Err(anyhow!("tag '{}' already exists", name))?
+    }
+    let target_sha = revision::resolve(target)
+        .map_err(|_| anyhow!("Failed to resolve '{}' as a valid ref.", target))?;
+    let sha = if annotated {
+        let paragraphs = args.values("-m");
+        let message = match (paragraphs.is_empty(), args.value("-F")) {
+            (false, None) => paragraphs.join("\n\n"),
+            (true, Some("-")) => std::io::read_to_string(std::io::stdin())?,
+            (true, Some(file)) => fs::read_to_string(file)
+                .map_err(|err| anyhow!("could not open or read '{}': {}", file, err))?,
+            (false, Some(_)) => Err(anyhow!("options '-m' and '-F' cannot be used together"))?,
+            (true, None) => Err(anyhow!(
+                "no tag message given; use -m <message> or -F <file>"
+            ))?,
+        };
+        let (object_type, _) = ObjectStorage::read_object(&target_sha)?;
+        Tag {
+            object: target_sha,
+            object_type,
+            name: name.to_string(),
+            tagger: Some(Signature::committer()?),
+            message: cleanup_message(&message),
+            signature: None,
+        }
+        .write_to_object_storage()?
+    } else {
+        target_sha
+    };
+    let expected_old = if force { None } else { Some([0; 20]) };
+    refs::update_ref_no_deref(&full_name, &sha, expected_old.as_ref())
+}
+
+/// Lists tags, optionally only those matching one of the operands as a glob pattern and
+/// passing the reachability filters. `--glob` picks tags as `log --tags=<pattern>` does, and
+/// `--exclude` leaves out the tags it matches.
+fn list(args: &cli::ParsedArgs) -> anyhow::Result<()> {
+    let filter = ReachabilityFilter::new(
+        &args.values("--contains"),
+        &args.values("--no-contains"),
+        &args.values_or("--merged", "HEAD"),
+        &args.values_or("--no-merged", "HEAD"),
+    )?;
+    let excludes = Excludes::from_patterns(&args.values("--exclude"));
+    let mut tags = vec![];
+    for (tag, sha) in refglob::listed_refs(Some("refs/tags/"), &args.values("--glob"), &excludes)? {
+        if args.operands.is_empty()
+            || args
+                .operands
+                .iter()
+                .any(|pattern| refglob::matches(pattern, &tag))
+        {
+            tags.push((tag, sha));
+        }
+    }
+    let tips: Vec<Sha> = tags.iter().map(|(_, sha)| *sha).collect();
+    for ((tag, sha), selected) in tags.iter().zip(filter.select(&tips)?) {
+        if !selected {
+            continue;
+        }
+        if args.has("-n") {
+            println!("{:<15} {}", tag, annotation(sha)?);
+        } else {
+            println!("{}", tag);
+        }
+    }
+    Ok(())
+}
+
+/// The first line of a tag's message, or of the tagged commit's message for lightweight tags.
+fn annotation(sha: &[u8; 20]) -> anyhow::Result<String> {
+    let message = match GitObject::from_sha(sha)? {
+        GitObject::Tag(tag) => tag.message,
+        GitObject::Commit(commit) => commit.message,
+        _ => String::new(),
+    };
+    Ok(message.lines().next().unwrap_or_default().to_string())
+}
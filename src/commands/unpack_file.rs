@@ -0,0 +1,21 @@
+use crate::cli;
+use crate::object_storage::ObjectStorage;
+use crate::revision;
+use crate::temp_file::TempFile;
+use anyhow::anyhow;
+
+/// Writes the blob to `.merge_file_XXXXXX` at the top of the working tree and prints the
+/// file name; the file is left for the caller to remove.
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("unpack-file", args)?;
+    let [object] = args.operands.as_slice() else {
+        return Err(cli::usage_error("unpack-file"));
+    };
+    let sha =
+        revision::resolve(object).map_err(|_| anyhow!("Not a valid object name {}", object))?;
+    let work_tree = ObjectStorage::work_tree();
+    let path = TempFile::from_blob(&sha, &work_tree, ".merge_file_", "")?.keep();
+    let name = path.file_name().unwrap_or_default();
+    println!("{}", name.to_string_lossy());
+    Ok(())
+}
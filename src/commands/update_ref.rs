@@ -0,0 +1,33 @@
+use crate::cli;
+use crate::object_storage::{ObjectStorage, Sha};
+use crate::refs;
+use crate::revision;
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("update-ref", args)?;
+    let delete = args.has("-d");
+    let no_deref = args.has("--no-deref");
+    let operands: Vec<&str> = args.operands.iter().map(String::as_str).collect();
+    let old_value = |value: &str| -> anyhow::Result<Sha> {
+        // An empty old value, like all zeros, requires that the ref does not exist yet.
+        if value.is_empty() {
+            Ok([0; 20])
+        } else {
+            ObjectStorage::hex_string_to_sha(value).or_else(|_| revision::resolve(value))
+        }
+    };
+    match (delete, operands.as_slice()) {
+        (true, [name]) => refs::delete_ref(name, None),
+        (true, [name, old]) => refs::delete_ref(name, Some(&old_value(old)?)),
+        (false, [name, new, rest @ ..]) if rest.len() <= 1 => {
+            let new = revision::resolve(new)?;
+            let old = rest.first().map(|old| old_value(old)).transpose()?;
+            if no_deref {
+                refs::update_ref_no_deref(name, &new, old.as_ref())
+            } else {
+                refs::update_ref(name, &new, old.as_ref())
+            }
+        }
+        _ => Err(cli::usage_error("update-ref")),
+    }
+}
@@ -0,0 +1,12 @@
+use crate::cli;
+use crate::object_storage::ObjectStorage;
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    if !cli::parse_args("update-server-info", args)?
+        .operands
+        .is_empty()
+    {
+        return Err(cli::usage_error("update-server-info"));
+    }
+    ObjectStorage::update_server_info()
+}
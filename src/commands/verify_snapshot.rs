@@ -0,0 +1,106 @@
+use crate::cli;
+use crate::object_storage::{GitObject, ObjectStorage, Sha};
+use crate::promisor;
+use crate::refs::{self, RefValue};
+use crate::repository::CommandExit;
+use std::collections::{BTreeSet, HashSet};
+use std::io::{self, Write};
+
+/// Prints a digest of what the repository holds, independent of how it is stored: two
+/// repositories have the same digest when `HEAD`, their refs and the objects reachable from
+/// them are the same, whether the objects are loose or packed. Given a digest, checks the
+/// repository against it instead and exits with 1 when it differs. `--list` prints what goes
+/// into the digest, one ref or object per line, so that two listings can be diffed.
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("verify-snapshot", args)?;
+    let expected = match args.operands.as_slice() {
+        [] => None,
+        [digest] => Some(digest.as_str()),
+        _ => return Err(cli::usage_error("verify-snapshot")),
+    };
+    let snapshot = snapshot()?;
+    if args.has("--list") {
+        io::stdout().write_all(snapshot.as_bytes())?;
+        return Ok(());
+    }
+    let digest =
+        ObjectStorage::sha_to_hex_string(&ObjectStorage::hash_content(snapshot.as_bytes()));
+    match expected {
+        None => println!("{}", digest),
+        Some(expected) if expected.eq_ignore_ascii_case(&digest) => (),
+        Some(expected) => {
+            eprintln!(
+                "error: snapshot mismatch: expected {}, found {}",
+                expected, digest
+            );
+            Err(CommandExit { code: 1 })?
+        }
+    }
+    Ok(())
+}
+
+/// `HEAD` (`HEAD ref: <target>` or `HEAD <id>`), then each ref as `<name> <id>` by name, then
+/// each reachable object as `<type> <id>` by id. Objects a promisor remote has are left out.
+fn snapshot() -> anyhow::Result<String> {
+    let mut snapshot = String::new();
+    let mut pending: Vec<Sha> = vec![];
+    match refs::read_ref("HEAD")? {
+        Some(RefValue::Symbolic(target)) => snapshot.push_str(&format!("HEAD ref: {}\n", target)),
+        Some(RefValue::Direct(sha)) => {
+            snapshot.push_str(&format!(
+                "HEAD {}\n",
+                ObjectStorage::sha_to_hex_string(&sha)
+            ));
+            pending.push(sha);
+        }
+        None => (),
+    }
+    let mut refs = refs::list_refs()?;
+    refs.sort();
+    for (name, sha) in refs {
+        snapshot.push_str(&format!(
+            "{} {}\n",
+            name,
+            ObjectStorage::sha_to_hex_string(&sha)
+        ));
+        pending.push(sha);
+    }
+
+    let mut seen: HashSet<Sha> = HashSet::new();
+    let mut objects: BTreeSet<(Sha, &str)> = BTreeSet::new();
+    while let Some(sha) = pending.pop() {
+        if !seen.insert(sha) || promisor::may_skip(&sha)? {
+            continue;
+        }
+        // Blobs are not read, only their type.
+        if ObjectStorage::open_object(&sha)?.object_type == "blob" {
+            objects.insert((sha, "blob"));
+            continue;
+        }
+        let object_type = match GitObject::from_sha(&sha)? {
+            GitObject::Commit(commit) => {
+                pending.extend(commit.parents);
+                pending.push(commit.tree);
+                "commit"
+            }
+            GitObject::Tree(tree) => {
+                pending.extend(tree.entries.iter().map(|entry| entry.hash));
+                "tree"
+            }
+            GitObject::Tag(tag) => {
+                pending.push(tag.object);
+                "tag"
+            }
+            GitObject::Blob(_) => "blob",
+        };
+        objects.insert((sha, object_type));
+    }
+    for (sha, object_type) in objects {
+        snapshot.push_str(&format!(
+            "{} {}\n",
+            object_type,
+            ObjectStorage::sha_to_hex_string(&sha)
+        ));
+    }
+    Ok(snapshot)
+}
@@ -0,0 +1,55 @@
+use crate::cli;
+use crate::gpg;
+use crate::object_storage::{self, GitObject, ObjectStorage};
+use crate::repository::CommandExit;
+use crate::revision;
+use std::io::{self, Write};
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    let args = cli::parse_args("verify-tag", args)?;
+    if args.operands.is_empty() {
+        return Err(cli::usage_error("verify-tag"));
+    }
+    let mut failed = false;
+    for name in &args.operands {
+        if !verify(name, args.has("-v"), args.has("--raw"))? {
+            failed = true;
+        }
+    }
+    if failed {
+        Err(CommandExit { code: 1 })?
+    }
+    Ok(())
+}
+
+/// Checks the signature of the tag `name`, reporting problems like git does; returns whether
+/// it is good.
+fn verify(name: &str, verbose: bool, raw: bool) -> anyhow::Result<bool> {
+    let Ok(sha) = revision::resolve(name) else {
+        eprintln!("error: tag '{}' not found.", name);
+        return Ok(false);
+    };
+    let (object_type, content) = ObjectStorage::read_object(&sha)?;
+    let GitObject::Tag(tag) = GitObject::from_parts(&object_type, &content)? else {
+        eprintln!(
+            "error: {}: cannot verify a non-tag object of type {}.",
+            name, object_type
+        );
+        return Ok(false);
+    };
+    let (payload, _) = object_storage::split_signature(&content);
+    if verbose {
+        io::stdout().write_all(payload)?;
+    }
+    let Some(signature) = tag.signature else {
+        eprintln!("error: no signature found");
+        return Ok(false);
+    };
+    let verification = gpg::verify(payload, &signature)?;
+    if raw && !verification.status.is_empty() {
+        eprint!("{}", verification.status);
+    } else {
+        eprint!("{}", verification.output);
+    }
+    Ok(verification.good)
+}
@@ -0,0 +1,11 @@
+use crate::cli;
+use crate::object_storage::ObjectStorage;
+
+pub fn run(args: &[String]) -> anyhow::Result<()> {
+    if !cli::parse_args("write-tree", args)?.operands.is_empty() {
+        return Err(cli::usage_error("write-tree"));
+    }
+    let hash = ObjectStorage::write_tree_cwd()?;
+    println!("{}", ObjectStorage::sha_to_hex_string(&hash));
+    Ok(())
+}
@@ -0,0 +1,496 @@
+//! Generation numbers from the commit-graph file git writes to `objects/info/commit-graph`
+//! (`git commit-graph write`, or `gc` with `gc.writeCommitGraph`). A commit's generation is one
+//! more than the highest of its parents', so a commit can only be reached from commits with a
+//! higher one, which lets reachability walks stop early. The graph covers the history of every
+//! commit it has, so commits missing from it, written since, count as newer than all of them.
+//! The graph also records each commit's parents, so that walks over the history can follow
+//! them without inflating the commits. Split graphs (`objects/info/commit-graphs`) are not
+//! read, and with `core.commitGraph` set to false the graph is not used at all.
+//!
+//! [`write`] writes a graph of the commits reachable from the refs, as `git commit-graph
+//! write --reachable` does, with the topological levels as generations; [`verify`] checks a
+//! graph against the commits it describes.
+
+use crate::chunk_format::{self, ChunkId, ChunkWriter, Chunks};
+use crate::config::Config;
+use crate::context;
+use crate::object_storage::{GitObject, ObjectStorage, Sha};
+use crate::refs;
+use crate::shared_repository;
+use crate::trace2;
+use anyhow::anyhow;
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// The generation of commits the graph does not have, as git calls it.
+pub const GENERATION_NUMBER_INFINITY: u32 = u32::MAX;
+/// The highest generation the commit data has room for; deeper commits share it.
+const GENERATION_NUMBER_MAX: u32 = 0x3fff_ffff;
+
+const SIGNATURE: &[u8] = b"CGPH";
+const CHUNK_OID_FANOUT: ChunkId = *b"OIDF";
+const CHUNK_OID_LOOKUP: ChunkId = *b"OIDL";
+const CHUNK_COMMIT_DATA: ChunkId = *b"CDAT";
+const CHUNK_EXTRA_EDGES: ChunkId = *b"EDGE";
+/// A tree id, two parent positions and the generation and commit time.
+const COMMIT_DATA_SIZE: usize = 20 + 4 + 4 + 8;
+
+/// The parent position of a commit without that parent.
+const GRAPH_PARENT_NONE: u32 = 0x7000_0000;
+/// Set in the second parent position of an octopus merge, whose other parents are listed in
+/// the extra edges chunk from the position in the lower bits; set there on the last one.
+const GRAPH_EXTRA_EDGES: u32 = 0x8000_0000;
+
+pub(crate) struct CommitGraph {
+    data: Vec<u8>,
+    commits: usize,
+    oid_fanout: usize,
+    oid_lookup: usize,
+    commit_data: usize,
+    extra_edges: Option<(usize, usize)>,
+}
+
+/// The generation of the commit `sha`: its topological level in the commit-graph, or
+/// [`GENERATION_NUMBER_INFINITY`] when there is no graph or it does not have the commit.
+pub fn generation(sha: &Sha) -> u32 {
+    graph()
+        .and_then(|graph| graph.generation(graph.position(sha)?))
+        .unwrap_or(GENERATION_NUMBER_INFINITY)
+}
+
+/// The parents of the commit `sha` as the commit-graph records them, or `None` when there is
+/// no graph or it does not have the commit.
+pub fn parents(sha: &Sha) -> Option<Vec<Sha>> {
+    let graph = graph()?;
+    graph.parents(graph.position(sha)?)
+}
+
+/// Whether the commit-graph has `sha`, which makes it a commit without reading it.
+pub fn contains(sha: &Sha) -> bool {
+    graph().is_some_and(|graph| graph.position(sha).is_some())
+}
+
+/// The repository's commit-graph, read on first use. A graph that cannot be read is ignored
+/// with a warning, as git does.
+fn graph() -> Option<Arc<CommitGraph>> {
+    context::current()
+        .commit_graph
+        .get_or_init(
+            || match trace2::region("commit-graph", "read", None, CommitGraph::read) {
+                Ok(graph) => graph.map(Arc::new),
+                Err(err) => {
+                    eprintln!("warning: ignoring commit-graph: {}", err);
+                    None
+                }
+            },
+        )
+        .clone()
+}
+
+fn graph_path() -> PathBuf {
+    ObjectStorage::git_dir()
+        .join("objects")
+        .join("info")
+        .join("commit-graph")
+}
+
+/// What the graph records about a commit it is written with.
+struct GraphCommit {
+    tree: Sha,
+    parents: Vec<Sha>,
+    time: i64,
+}
+
+/// Writes a commit-graph of every commit reachable from the refs and `HEAD`, replacing the
+/// graph there was, and returns how many commits it has.
+pub fn write() -> anyhow::Result<usize> {
+    // Refs to trees and blobs have no history to record.
+    let mut pending: Vec<Sha> = refs::all_tips()?
+        .iter()
+        .filter_map(|sha| ObjectStorage::peel_to_commit(sha).ok())
+        .collect();
+    let mut commits: HashMap<Sha, GraphCommit> = HashMap::new();
+    while let Some(sha) = pending.pop() {
+        if commits.contains_key(&sha) {
+            continue;
+        }
+        let commit = GitObject::read_commit(&sha)?;
+        pending.extend(&commit.parents);
+        commits.insert(
+            sha,
+            GraphCommit {
+                tree: commit.tree,
+                parents: commit.parents,
+                time: commit.committer.time,
+            },
+        );
+    }
+
+    let mut shas: Vec<Sha> = commits.keys().copied().collect();
+    shas.sort();
+    let positions: HashMap<Sha, u32> = shas
+        .iter()
+        .enumerate()
+        .map(|(position, sha)| (*sha, position as u32))
+        .collect();
+    let parent_positions: Vec<Vec<u32>> = shas
+        .iter()
+        .map(|sha| {
+            commits[sha]
+                .parents
+                .iter()
+                .map(|parent| positions[parent])
+                .collect()
+        })
+        .collect();
+    let generations = topological_levels(&parent_positions);
+
+    let mut fanout = vec![0u32; 256];
+    for sha in &shas {
+        fanout[sha[0] as usize] += 1;
+    }
+    let mut oid_fanout = Vec::with_capacity(256 * 4);
+    let mut total = 0;
+    for count in fanout {
+        total += count;
+        oid_fanout.extend_from_slice(&total.to_be_bytes());
+    }
+    let mut commit_data = Vec::with_capacity(shas.len() * COMMIT_DATA_SIZE);
+    let mut extra_edges: Vec<u8> = vec![];
+    for (position, sha) in shas.iter().enumerate() {
+        let commit = &commits[sha];
+        let parents = &parent_positions[position];
+        commit_data.extend_from_slice(&commit.tree);
+        let first = parents.first().copied().unwrap_or(GRAPH_PARENT_NONE);
+        let second = match parents.len() {
+            0 | 1 => GRAPH_PARENT_NONE,
+            2 => parents[1],
+            _ => {
+                let edge = (extra_edges.len() / 4) as u32;
+                for (i, parent) in parents[1..].iter().enumerate() {
+                    let last = if i == parents.len() - 2 {
+                        GRAPH_EXTRA_EDGES
+                    } else {
+                        0
+                    };
+                    extra_edges.extend_from_slice(&(parent | last).to_be_bytes());
+                }
+                GRAPH_EXTRA_EDGES | edge
+            }
+        };
+        commit_data.extend_from_slice(&first.to_be_bytes());
+        commit_data.extend_from_slice(&second.to_be_bytes());
+        // 34 bits of commit time: the top two after the generation, the rest in a word.
+        let time = commit.time.max(0) as u64;
+        let word = (generations[position] << 2) | ((time >> 32) as u32 & 3);
+        commit_data.extend_from_slice(&word.to_be_bytes());
+        commit_data.extend_from_slice(&(time as u32).to_be_bytes());
+    }
+    let oid_lookup: Vec<u8> = shas.iter().flatten().copied().collect();
+
+    let mut chunks = ChunkWriter::new();
+    chunks.add(CHUNK_OID_FANOUT, oid_fanout);
+    chunks.add(CHUNK_OID_LOOKUP, oid_lookup);
+    chunks.add(CHUNK_COMMIT_DATA, commit_data);
+    let mut chunk_count = 3;
+    if !extra_edges.is_empty() {
+        chunks.add(CHUNK_EXTRA_EDGES, extra_edges);
+        chunk_count += 1;
+    }
+    // Version 1, SHA-1, and no base graphs.
+    let mut header = SIGNATURE.to_vec();
+    header.extend_from_slice(&[1, 1, chunk_count, 0]);
+    let data = chunks.finish(&header);
+
+    let path = graph_path();
+    shared_repository::create_dir_all(path.parent().unwrap())?;
+    let lock_path = path.with_extension("lock");
+    fs::write(&lock_path, data)?;
+    shared_repository::adjust(&lock_path)?;
+    fs::rename(&lock_path, &path)?;
+    Ok(shas.len())
+}
+
+/// The topological level of each commit, whose parents are at `parents`: one for a root,
+/// otherwise one more than the highest of its parents', up to [`GENERATION_NUMBER_MAX`].
+fn topological_levels(parents: &[Vec<u32>]) -> Vec<u32> {
+    let mut levels = vec![0u32; parents.len()];
+    let mut stack: Vec<usize> = vec![];
+    for start in 0..parents.len() {
+        stack.push(start);
+        while let Some(&position) = stack.last() {
+            if levels[position] != 0 {
+                stack.pop();
+                continue;
+            }
+            let mut highest = 0;
+            let mut ready = true;
+            for &parent in &parents[position] {
+                match levels[parent as usize] {
+                    0 => {
+                        stack.push(parent as usize);
+                        ready = false;
+                    }
+                    level => highest = highest.max(level),
+                }
+            }
+            if ready {
+                levels[position] = (highest + 1).min(GENERATION_NUMBER_MAX);
+                stack.pop();
+            }
+        }
+    }
+    levels
+}
+
+/// Checks the commit-graph against its checksum and the commits it describes, returning what
+/// is wrong with it in git's words. A repository without a graph has nothing wrong.
+pub fn verify() -> anyhow::Result<Vec<String>> {
+    let data = match fs::read(graph_path()) {
+        Ok(data) => data,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => Err(err)?,
+    };
+    let mut problems = vec![];
+    if !chunk_format::checksum_matches(&data) {
+        problems
+            .push("the commit-graph file has incorrect checksum and is likely corrupt".to_string());
+    }
+    let graph = match CommitGraph::parse(data) {
+        Ok(graph) => graph,
+        Err(err) => {
+            problems.push(err.to_string());
+            return Ok(problems);
+        }
+    };
+    let hex = |sha: &[u8]| ObjectStorage::sha_to_hex_string(sha.try_into().unwrap());
+    let mut fanout = [0u32; 256];
+    for position in 0..graph.commits {
+        let oid = graph.oid(position);
+        if position > 0 && graph.oid(position - 1) >= oid {
+            problems.push(format!(
+                "commit-graph has incorrect OID order: {} then {}",
+                hex(graph.oid(position - 1)),
+                hex(oid)
+            ));
+        }
+        fanout[oid[0] as usize] += 1;
+    }
+    let mut total = 0;
+    for (byte, count) in fanout.iter().enumerate() {
+        total += count;
+        let recorded = graph.word(graph.oid_fanout + byte * 4).unwrap_or(0);
+        if recorded != total {
+            problems.push(format!(
+                "commit-graph has incorrect fanout value: fanout[{}] = {} != {}",
+                byte, recorded, total
+            ));
+        }
+    }
+    for position in 0..graph.commits {
+        let sha: Sha = graph.oid(position).try_into().unwrap();
+        let commit = match GitObject::from_sha(&sha) {
+            Ok(GitObject::Commit(commit)) => commit,
+            _ => {
+                problems.push(format!(
+                    "failed to parse commit {} from object database for commit-graph",
+                    hex(&sha)
+                ));
+                continue;
+            }
+        };
+        let tree = graph.tree(position);
+        if tree != commit.tree {
+            problems.push(format!(
+                "root tree OID for commit {} in commit-graph is {} != {}",
+                hex(&sha),
+                hex(tree),
+                hex(&commit.tree)
+            ));
+        }
+        let Some(parents) = graph.parents(position) else {
+            problems.push(format!(
+                "commit-graph parent list for commit {} is too long",
+                hex(&sha)
+            ));
+            continue;
+        };
+        if parents.len() > commit.parents.len() {
+            problems.push(format!(
+                "commit-graph parent list for commit {} is too long",
+                hex(&sha)
+            ));
+        } else if parents.len() < commit.parents.len() {
+            problems.push(format!(
+                "commit-graph parent list for commit {} terminates early",
+                hex(&sha)
+            ));
+        }
+        for (graph_parent, parent) in parents.iter().zip(&commit.parents) {
+            if graph_parent != parent {
+                problems.push(format!(
+                    "commit-graph parent for {} is {} != {}",
+                    hex(&sha),
+                    hex(graph_parent),
+                    hex(parent)
+                ));
+            }
+        }
+        let generation = graph.generation(position).unwrap_or(0);
+        let expected = parents
+            .iter()
+            .filter_map(|parent| graph.generation(graph.position(parent)?))
+            .max()
+            .map_or(1, |highest| (highest + 1).min(GENERATION_NUMBER_MAX));
+        if generation < expected {
+            problems.push(format!(
+                "commit-graph generation for commit {} is {} < {}",
+                hex(&sha),
+                generation,
+                expected
+            ));
+        }
+        let time = graph.commit_time(position).unwrap_or(0);
+        if time != commit.committer.time {
+            problems.push(format!(
+                "commit date for commit {} in commit-graph is {} != {}",
+                hex(&sha),
+                time,
+                commit.committer.time
+            ));
+        }
+    }
+    Ok(problems)
+}
+
+impl CommitGraph {
+    fn read() -> anyhow::Result<Option<Self>> {
+        if Config::get()?.bool("core", "commitGraph") == Some(false) {
+            return Ok(None);
+        }
+        let data = match fs::read(graph_path()) {
+            Ok(data) => data,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => Err(err)?,
+        };
+        Self::parse(data).map(Some)
+    }
+
+    fn parse(data: Vec<u8>) -> anyhow::Result<Self> {
+        if data.len() < 8 || &data[..4] != SIGNATURE {
+            Err(anyhow!("commit-graph signature does not match"))?
+        }
+        if data[4] != 1 {
+            Err(anyhow!("commit-graph version {} does not match", data[4]))?
+        }
+        if data[5] != 1 {
+            Err(anyhow!(
+                "commit-graph hash version {} does not match",
+                data[5]
+            ))?
+        }
+        let chunks = Chunks::read(&data, 8, data[6] as usize, "commit-graph")?;
+        let fanout = chunks.require(&CHUNK_OID_FANOUT)?;
+        if fanout.len() != 256 * 4 {
+            Err(anyhow!("commit-graph oid fanout chunk is wrong size"))?
+        }
+        let commits = u32::from_be_bytes(data[fanout.end - 4..fanout.end].try_into()?);
+        let commits = commits as usize;
+        let oid_lookup = chunks.require(&CHUNK_OID_LOOKUP)?;
+        let commit_data = chunks.require(&CHUNK_COMMIT_DATA)?;
+        if oid_lookup.len() != commits * 20 || commit_data.len() != commits * COMMIT_DATA_SIZE {
+            Err(anyhow!(
+                "commit-graph chunks do not match its {} commits",
+                commits
+            ))?
+        }
+        let extra_edges = chunks
+            .get(&CHUNK_EXTRA_EDGES)
+            .map(|range| (range.start, range.end));
+        Ok(Self {
+            data,
+            commits,
+            oid_fanout: fanout.start,
+            oid_lookup: oid_lookup.start,
+            commit_data: commit_data.start,
+            extra_edges,
+        })
+    }
+
+    fn oid(&self, position: usize) -> &[u8] {
+        let start = self.oid_lookup + position * 20;
+        &self.data[start..start + 20]
+    }
+
+    fn word(&self, start: usize) -> Option<u32> {
+        Some(u32::from_be_bytes(
+            self.data.get(start..start + 4)?.try_into().ok()?,
+        ))
+    }
+
+    /// Where `sha` is in the graph's sorted list of commits.
+    fn position(&self, sha: &Sha) -> Option<usize> {
+        let (mut low, mut high) = (0, self.commits);
+        while low < high {
+            let middle = (low + high) / 2;
+            match self.oid(middle).cmp(sha.as_slice()) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => return Some(middle),
+            }
+        }
+        None
+    }
+
+    fn tree(&self, position: usize) -> &[u8] {
+        let start = self.commit_data + position * COMMIT_DATA_SIZE;
+        &self.data[start..start + 20]
+    }
+
+    fn generation(&self, position: usize) -> Option<u32> {
+        // The upper 30 bits of the word after the parents.
+        let word = self.word(self.commit_data + position * COMMIT_DATA_SIZE + 28)?;
+        Some(word >> 2)
+    }
+
+    /// The commit time, whose upper two bits are the lower two of the generation's word.
+    fn commit_time(&self, position: usize) -> Option<i64> {
+        let start = self.commit_data + position * COMMIT_DATA_SIZE + 28;
+        let (high, low) = (self.word(start)? & 3, self.word(start + 4)?);
+        Some(((high as i64) << 32) | low as i64)
+    }
+
+    fn parents(&self, position: usize) -> Option<Vec<Sha>> {
+        let start = self.commit_data + position * COMMIT_DATA_SIZE + 20;
+        let mut positions = vec![];
+        for word in [self.word(start)?, self.word(start + 4)?] {
+            if word == GRAPH_PARENT_NONE {
+                break;
+            }
+            if word & GRAPH_EXTRA_EDGES == 0 {
+                positions.push(word);
+                continue;
+            }
+            let (edges, edges_end) = self.extra_edges?;
+            let mut edge = edges + (word & !GRAPH_EXTRA_EDGES) as usize * 4;
+            loop {
+                let word = self.word(edge).filter(|_| edge < edges_end)?;
+                positions.push(word & !GRAPH_EXTRA_EDGES);
+                if word & GRAPH_EXTRA_EDGES != 0 {
+                    break;
+                }
+                edge += 4;
+            }
+        }
+        positions
+            .into_iter()
+            .map(|position| {
+                let position = position as usize;
+                (position < self.commits).then(|| self.oid(position).try_into().unwrap())
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,310 @@
+use crate::commit_graph;
+use crate::object_storage::{Commit, GitObject, ObjectStorage, Sha};
+use crate::oid_set::OidSet;
+use crate::promisor;
+use crate::revision;
+use anyhow::anyhow;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+/// Every commit reachable from `tips`, including the tips themselves.
+pub fn reachable_from(tips: &[Sha]) -> anyhow::Result<OidSet> {
+    let mut seen = OidSet::new()?;
+    let mut pending: Vec<Sha> = tips.to_vec();
+    while let Some(sha) = pending.pop() {
+        if seen.insert(sha) {
+            pending.extend(parents(&sha)?);
+        }
+    }
+    Ok(seen)
+}
+
+/// The commits reachable from `tips` but not from `excluded`, as `A..B` names them, newest
+/// first by committer date; commits with the same date come in the order they were reached.
+pub fn commits_between(tips: &[Sha], excluded: &[Sha]) -> anyhow::Result<Vec<(Sha, Commit)>> {
+    let mut seen = reachable_from(excluded)?;
+    let mut queue: BinaryHeap<(i64, Reverse<usize>, Sha)> = BinaryHeap::new();
+    let mut loaded: Vec<Option<Commit>> = vec![];
+    let mut push = |queue: &mut BinaryHeap<_>,
+                    loaded: &mut Vec<Option<Commit>>,
+                    sha: Sha|
+     -> anyhow::Result<()> {
+        if !seen.insert(sha) || promisor::may_skip(&sha)? {
+            return Ok(());
+        }
+        let commit = GitObject::read_commit(&sha)?;
+        queue.push((commit.committer.time, Reverse(loaded.len()), sha));
+        loaded.push(Some(commit));
+        Ok(())
+    };
+    for tip in tips {
+        push(&mut queue, &mut loaded, *tip)?;
+    }
+    let mut commits = vec![];
+    while let Some((_, Reverse(position), sha)) = queue.pop() {
+        let commit = loaded[position].take().unwrap();
+        for parent in &commit.parents {
+            push(&mut queue, &mut loaded, *parent)?;
+        }
+        commits.push((sha, commit));
+    }
+    Ok(commits)
+}
+
+/// How many commits are reachable from `tips` but not from `excluded`, as `rev-list --count`
+/// counts `A..B`. Only parents are looked at, which the commit-graph has for most commits.
+pub fn count_between(tips: &[Sha], excluded: &[Sha]) -> anyhow::Result<usize> {
+    let mut seen = reachable_from(excluded)?;
+    let mut pending: Vec<Sha> = tips.to_vec();
+    let mut count = 0;
+    while let Some(sha) = pending.pop() {
+        if seen.insert(sha) {
+            count += 1;
+            pending.extend(parents(&sha)?);
+        }
+    }
+    Ok(count)
+}
+
+/// How many commits are reachable from `left` but not `right`, and from `right` but not
+/// `left`: how far two branches are ahead of and behind each other.
+pub fn ahead_behind(left: &Sha, right: &Sha) -> anyhow::Result<(usize, usize)> {
+    let from_left = reachable_from(&[*left])?;
+    let from_right = reachable_from(&[*right])?;
+    Ok((
+        from_left
+            .iter()
+            .filter(|sha| !from_right.contains(sha))
+            .count(),
+        from_right
+            .iter()
+            .filter(|sha| !from_left.contains(sha))
+            .count(),
+    ))
+}
+
+/// Whether `ancestor` is reachable from `descendant` (a commit is its own ancestor). Commits
+/// with a lower generation than `ancestor` cannot reach it, so the walk does not go past them.
+pub fn is_ancestor(ancestor: &Sha, descendant: &Sha) -> anyhow::Result<bool> {
+    let min_generation = commit_graph::generation(ancestor);
+    let mut seen = OidSet::new()?;
+    let mut pending: Vec<Sha> = vec![*descendant];
+    while let Some(sha) = pending.pop() {
+        if sha == *ancestor {
+            return Ok(true);
+        }
+        if seen.insert(sha) && commit_graph::generation(&sha) >= min_generation {
+            pending.extend(parents(&sha)?);
+        }
+    }
+    Ok(false)
+}
+
+/// For each of `tips`, whether one of `commits` is reachable from it: the question
+/// `--contains` asks, for many refs at once. Rather than a walk per tip, the history below
+/// the tips is walked once, each commit after all its children, with a bit per tip for the
+/// tips that reach it; a commit's bits are dropped once they are passed on to its parents.
+/// The walk stops at the commits and below their lowest generation, where none of them can
+/// be reached, and ends once every tip has reached one.
+pub fn tips_containing(tips: &[Sha], commits: &[Sha]) -> anyhow::Result<Vec<bool>> {
+    let mut found = vec![false; tips.len()];
+    let Some(min_generation) = commits.iter().map(commit_graph::generation).min() else {
+        return Ok(found);
+    };
+    let targets: HashSet<&Sha> = commits.iter().collect();
+    // The parents of each commit the walk covers, and how many children it has there.
+    let mut walked: HashMap<Sha, Vec<Sha>> = HashMap::new();
+    let mut children: HashMap<Sha, usize> = HashMap::new();
+    let mut pending: Vec<Sha> = tips.to_vec();
+    while let Some(sha) = pending.pop() {
+        if walked.contains_key(&sha) {
+            continue;
+        }
+        let mut below = vec![];
+        if !targets.contains(&sha) {
+            for parent in parents(&sha)? {
+                if commit_graph::generation(&parent) >= min_generation {
+                    *children.entry(parent).or_default() += 1;
+                    below.push(parent);
+                }
+            }
+        }
+        pending.extend(&below);
+        walked.insert(sha, below);
+    }
+
+    let words = tips.len().div_ceil(64);
+    let mut bits: HashMap<Sha, Vec<u64>> = HashMap::new();
+    for (i, tip) in tips.iter().enumerate() {
+        bits.entry(*tip).or_insert_with(|| vec![0; words])[i / 64] |= 1 << (i % 64);
+    }
+    let mut ready: Vec<Sha> = walked
+        .keys()
+        .filter(|sha| !children.contains_key(*sha))
+        .copied()
+        .collect();
+    let mut remaining = tips.len();
+    while let Some(sha) = ready.pop() {
+        let Some(reached) = bits.remove(&sha) else {
+            continue;
+        };
+        if targets.contains(&sha) {
+            for (i, found) in found.iter_mut().enumerate() {
+                if !*found && reached[i / 64] & (1 << (i % 64)) != 0 {
+                    *found = true;
+                    remaining -= 1;
+                }
+            }
+            if remaining == 0 {
+                break;
+            }
+        }
+        for parent in &walked[&sha] {
+            let parent_bits = bits.entry(*parent).or_insert_with(|| vec![0; words]);
+            for (word, reached) in parent_bits.iter_mut().zip(&reached) {
+                *word |= reached;
+            }
+            let count = children.get_mut(parent).unwrap();
+            *count -= 1;
+            if *count == 0 {
+                ready.push(*parent);
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// The best common ancestors of `a` and the commits `others` taken together: the commits
+/// reachable from both that are not ancestors of another such commit. There is more than one
+/// after criss-cross merges, and none when the histories are unrelated.
+pub fn merge_bases(a: &Sha, others: &[Sha]) -> anyhow::Result<Vec<Sha>> {
+    let from_a = reachable_from(&[*a])?;
+    let common: Vec<Sha> = reachable_from(others)?
+        .iter()
+        .filter(|sha| from_a.contains(sha))
+        .collect();
+    // The common commits that are ancestors of others are those reachable from their parents.
+    let mut parents_of_common = vec![];
+    for sha in &common {
+        parents_of_common.extend(parents(sha)?);
+    }
+    let below = reachable_from(&parents_of_common)?;
+    let mut bases: Vec<Sha> = common
+        .into_iter()
+        .filter(|sha| !below.contains(sha))
+        .collect();
+    bases.sort();
+    Ok(bases)
+}
+
+/// The parents of the commit `sha`, from the commit-graph when it has the commit; a missing
+/// commit that may be skipped has none.
+fn parents(sha: &Sha) -> anyhow::Result<Vec<Sha>> {
+    if let Some(parents) = commit_graph::parents(sha) {
+        return Ok(parents);
+    }
+    if promisor::may_skip(sha)? {
+        return Ok(vec![]);
+    }
+    match GitObject::from_sha(sha)? {
+        GitObject::Commit(commit) => Ok(commit.parents),
+        _ => Err(anyhow!(
+            "{} is not a commit",
+            ObjectStorage::sha_to_hex_string(sha)
+        )),
+    }
+}
+
+/// The `--contains`/`--no-contains`/`--merged`/`--no-merged` filters of ref listings. A ref
+/// passes if it contains at least one `--contains` commit and none of the `--no-contains`
+/// ones, and is reachable from at least one `--merged` commit and from no `--no-merged` one.
+pub struct ReachabilityFilter {
+    contains: Vec<Sha>,
+    no_contains: Vec<Sha>,
+    merged: Option<OidSet>,
+    no_merged: Option<OidSet>,
+}
+
+impl ReachabilityFilter {
+    pub fn new(
+        contains: &[&str],
+        no_contains: &[&str],
+        merged: &[&str],
+        no_merged: &[&str],
+    ) -> anyhow::Result<Self> {
+        let commits = |revisions: &[&str]| -> anyhow::Result<Vec<Sha>> {
+            revisions
+                .iter()
+                .map(|revision| {
+                    ObjectStorage::peel_to_commit(&revision::resolve(revision)?)
+                        .map_err(|_| anyhow!("'{}' is not a commit", revision))
+                })
+                .collect()
+        };
+        let reachable = |revisions: &[&str]| -> anyhow::Result<Option<OidSet>> {
+            if revisions.is_empty() {
+                Ok(None)
+            } else {
+                Ok(Some(reachable_from(&commits(revisions)?)?))
+            }
+        };
+        Ok(Self {
+            contains: commits(contains)?,
+            no_contains: commits(no_contains)?,
+            merged: reachable(merged)?,
+            no_merged: reachable(no_merged)?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.contains.is_empty()
+            && self.no_contains.is_empty()
+            && self.merged.is_none()
+            && self.no_merged.is_none()
+    }
+
+    /// Which of the refs pointing at `tips` are listed; annotated tags are judged by the
+    /// commit they point to. Refs that don't lead to a commit are only listed when no filter
+    /// is active. The `--contains` filters are worked out for all the refs in one walk.
+    pub fn select(&self, tips: &[Sha]) -> anyhow::Result<Vec<bool>> {
+        if self.is_empty() {
+            return Ok(vec![true; tips.len()]);
+        }
+        let mut selected = vec![false; tips.len()];
+        // The refs the `--merged` filters leave, by position, and the commits they lead to.
+        let mut candidates: Vec<(usize, Sha)> = vec![];
+        for (i, tip) in tips.iter().enumerate() {
+            // Most refs point at commits the commit-graph has, which need not be read.
+            let tip = match commit_graph::contains(tip) {
+                true => *tip,
+                false => ObjectStorage::peel_tag(tip)?,
+            };
+            if !commit_graph::contains(&tip)
+                && !matches!(GitObject::from_sha(&tip)?, GitObject::Commit(_))
+            {
+                continue;
+            }
+            if let Some(merged) = &self.merged
+                && !merged.contains(&tip)
+            {
+                continue;
+            }
+            if let Some(no_merged) = &self.no_merged
+                && no_merged.contains(&tip)
+            {
+                continue;
+            }
+            candidates.push((i, tip));
+        }
+        let commits: Vec<Sha> = candidates.iter().map(|(_, tip)| *tip).collect();
+        let contains = match self.contains.is_empty() {
+            true => vec![true; commits.len()],
+            false => tips_containing(&commits, &self.contains)?,
+        };
+        let no_contains = tips_containing(&commits, &self.no_contains)?;
+        for (((i, _), contains), no_contains) in candidates.iter().zip(contains).zip(no_contains) {
+            selected[*i] = contains && !no_contains;
+        }
+        Ok(selected)
+    }
+}
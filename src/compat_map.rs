@@ -0,0 +1,439 @@
+//! The compatibility object map of a repository moving from SHA-1 to SHA-256. With
+//! `extensions.compatObjectFormat` set to `sha256` (in a repository whose
+//! `core.repositoryFormatVersion` is 1), objects are still stored under their SHA-1 names, but
+//! each also has a SHA-256 name: the SHA-256 of the object as a SHA-256 repository would store
+//! it, where trees, commits and tags refer to other objects by their SHA-256 names too. Blobs
+//! hash the same content either way.
+//!
+//! Revisions may then name objects by either id, and `rev-parse --output-object-format` prints
+//! the other one. The translation is kept in `objects/loose-object-idx`, as git keeps it, and
+//! grows as objects are written:
+//!
+//! ```text
+//! # loose-object-idx
+//! <sha-1> <sha-256>
+//! ```
+//!
+//! Objects the map does not have yet, such as those stored before the extension was turned on,
+//! are translated when they are first asked for. Signatures and embedded tags in commits are
+//! copied unchanged, and submodule commits cannot be translated, since the map of the
+//! submodule's repository would have to be consulted.
+
+use crate::config::Config;
+use crate::context;
+use crate::object_storage::pack;
+use crate::object_storage::{ObjectStorage, Sha};
+use anyhow::anyhow;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// A SHA-256 object id.
+pub type Sha256 = [u8; 32];
+
+const HEADER: &str = "# loose-object-idx\n";
+const SUBMODULE_MODE: &[u8] = b"160000";
+
+#[derive(Default)]
+pub(crate) struct CompatMap {
+    to_compat: HashMap<Sha, Sha256>,
+    to_storage: HashMap<Sha256, Sha>,
+}
+
+/// Whether the repository keeps SHA-256 names for its objects. Any compatibility format other
+/// than `sha256` is an error; like git, a repository of format version 0 ignores extensions.
+pub fn enabled() -> anyhow::Result<bool> {
+    let context = context::current();
+    if let Some(enabled) = context.compat_enabled.get() {
+        return Ok(*enabled);
+    }
+    let config = Config::get()?;
+    let enabled = match config.value("extensions", "compatObjectFormat").as_deref() {
+        None => false,
+        Some(_) if config.int("core", "repositoryFormatVersion")? != Some(1) => false,
+        Some(format) if format.eq_ignore_ascii_case("sha256") => true,
+        Some(format) => Err(anyhow!("unknown compatibility object format '{}'", format))?,
+    };
+    Ok(*context.compat_enabled.get_or_init(|| enabled))
+}
+
+/// The SHA-256 name of the object `sha`, translating it and every object it leads to that
+/// the map does not have yet.
+pub fn compat_id(sha: &Sha) -> anyhow::Result<Sha256> {
+    let context = context::current();
+    let mut cached = context.compat_map.lock().unwrap();
+    let map = loaded(&mut cached)?;
+    translate(map, sha, false)?.ok_or_else(|| {
+        anyhow!(
+            "unable to map object {}",
+            ObjectStorage::sha_to_hex_string(sha)
+        )
+    })
+}
+
+/// The object whose SHA-256 name is `compat`, if the repository has one. When the map does
+/// not know it, every object stored is translated first.
+pub fn storage_id(compat: &Sha256) -> anyhow::Result<Option<Sha>> {
+    let context = context::current();
+    let mut cached = context.compat_map.lock().unwrap();
+    let map = loaded(&mut cached)?;
+    if let Some(sha) = map.to_storage.get(compat) {
+        return Ok(Some(*sha));
+    }
+    let mut shas: Vec<Sha> = ObjectStorage::loose_objects()?
+        .into_iter()
+        .map(|(sha, _)| sha)
+        .collect();
+    for pack in pack::loaded_packs()?.iter() {
+        shas.extend_from_slice(pack.shas());
+    }
+    for sha in shas {
+        translate(map, &sha, true)?;
+    }
+    Ok(map.to_storage.get(compat).copied())
+}
+
+/// Adds the new object `sha` to the map. An object that refers to one not stored yet, as can
+/// happen while objects arrive in any order, is left to be translated when it is asked for.
+pub fn record(sha: &Sha) -> anyhow::Result<()> {
+    if !enabled()? {
+        return Ok(());
+    }
+    let context = context::current();
+    let mut cached = context.compat_map.lock().unwrap();
+    translate(loaded(&mut cached)?, sha, true)?;
+    Ok(())
+}
+
+/// Adds the objects of the newly stored pack at `pack_path` to the map.
+pub fn record_pack(pack_path: &Path) -> anyhow::Result<()> {
+    if !enabled()? {
+        return Ok(());
+    }
+    let packs = pack::loaded_packs()?;
+    let Some(pack) = packs.iter().find(|pack| pack.pack_path() == pack_path) else {
+        return Ok(());
+    };
+    let context = context::current();
+    let mut cached = context.compat_map.lock().unwrap();
+    let map = loaded(&mut cached)?;
+    for sha in pack.shas() {
+        translate(map, sha, true)?;
+    }
+    Ok(())
+}
+
+pub fn to_hex(compat: &Sha256) -> String {
+    compat.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn from_hex(hex: &str) -> anyhow::Result<Sha256> {
+    let invalid = || anyhow!("invalid SHA-256 object id '{}'", hex);
+    if hex.len() != 64 || !hex.is_ascii() {
+        return Err(invalid());
+    }
+    let mut compat = [0u8; 32];
+    for (index, byte) in compat.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * index..2 * index + 2], 16).map_err(|_| invalid())?;
+    }
+    Ok(compat)
+}
+
+fn map_path() -> PathBuf {
+    ObjectStorage::git_dir()
+        .join("objects")
+        .join("loose-object-idx")
+}
+
+fn loaded(cached: &mut Option<CompatMap>) -> anyhow::Result<&mut CompatMap> {
+    if cached.is_none() {
+        *cached = Some(read_map()?);
+    }
+    Ok(cached.as_mut().unwrap())
+}
+
+fn read_map() -> anyhow::Result<CompatMap> {
+    let content = match fs::read_to_string(map_path()) {
+        Ok(content) => content,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(CompatMap::default()),
+        Err(err) => Err(err)?,
+    };
+    let mut map = CompatMap::default();
+    for line in content.lines().filter(|line| !line.starts_with('#')) {
+        let (sha, compat) = line
+            .split_once(' ')
+            .ok_or_else(|| anyhow!("invalid line in loose-object-idx: '{}'", line))?;
+        let sha = ObjectStorage::hex_string_to_sha(sha)?;
+        let compat = from_hex(compat)?;
+        map.to_compat.insert(sha, compat);
+        map.to_storage.insert(compat, sha);
+    }
+    Ok(map)
+}
+
+/// Translates `sha` and what it refers to, children before their parents, and appends what
+/// was new to the map file. Missing objects are an error, or with `tolerate_missing` leave
+/// `sha` untranslated, as do trees with submodules.
+fn translate(
+    map: &mut CompatMap,
+    sha: &Sha,
+    tolerate_missing: bool,
+) -> anyhow::Result<Option<Sha256>> {
+    if let Some(compat) = map.to_compat.get(sha) {
+        return Ok(Some(*compat));
+    }
+    let mut added: Vec<(Sha, Sha256)> = vec![];
+    let mut pending = vec![*sha];
+    while let Some(&next) = pending.last() {
+        if map.to_compat.contains_key(&next) {
+            pending.pop();
+            continue;
+        }
+        if tolerate_missing && !ObjectStorage::contains(&next)? {
+            break;
+        }
+        let (object_type, content) = ObjectStorage::read_object(&next)?;
+        let Some(references) = references(&object_type, &content)? else {
+            if tolerate_missing {
+                break;
+            }
+            Err(anyhow!(
+                "cannot map tree {}, which has a submodule",
+                ObjectStorage::sha_to_hex_string(&next)
+            ))?
+        };
+        let unmapped: Vec<Sha> = references
+            .into_iter()
+            .filter(|reference| !map.to_compat.contains_key(reference))
+            .collect();
+        if !unmapped.is_empty() {
+            pending.extend(unmapped);
+            continue;
+        }
+        let converted = convert(&object_type, &content, &map.to_compat)?;
+        let mut object = ObjectStorage::header_for_content_length(&object_type, converted.len())?;
+        object.extend_from_slice(&converted);
+        let compat = sha256(&object);
+        map.to_compat.insert(next, compat);
+        map.to_storage.insert(compat, next);
+        added.push((next, compat));
+        pending.pop();
+    }
+    append_to_map_file(&added)?;
+    Ok(map.to_compat.get(sha).copied())
+}
+
+fn append_to_map_file(added: &[(Sha, Sha256)]) -> anyhow::Result<()> {
+    if added.is_empty() {
+        return Ok(());
+    }
+    let path = map_path();
+    let mut lines = String::new();
+    if !path.exists() {
+        lines.push_str(HEADER);
+    }
+    for (sha, compat) in added {
+        lines.push_str(&format!(
+            "{} {}\n",
+            ObjectStorage::sha_to_hex_string(sha),
+            to_hex(compat)
+        ));
+    }
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)?
+        .write_all(lines.as_bytes())?;
+    Ok(())
+}
+
+/// The objects a tree, commit or tag names, which have to be translated before it can be, or
+/// `None` for a tree with a submodule commit in it.
+fn references(object_type: &str, content: &[u8]) -> anyhow::Result<Option<Vec<Sha>>> {
+    let mut references = vec![];
+    match object_type {
+        "tree" => {
+            for (mode, _, sha) in tree_entries(content)? {
+                if mode == SUBMODULE_MODE {
+                    return Ok(None);
+                }
+                references.push(sha);
+            }
+        }
+        "commit" | "tag" => {
+            for line in header_lines(content) {
+                if let Some(hex) = referring_line(object_type, line) {
+                    references.push(ObjectStorage::hex_string_to_sha(hex)?);
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(Some(references))
+}
+
+/// The content of the object as a SHA-256 repository stores it.
+fn convert(
+    object_type: &str,
+    content: &[u8],
+    to_compat: &HashMap<Sha, Sha256>,
+) -> anyhow::Result<Vec<u8>> {
+    let compat = |sha: &Sha| {
+        to_compat.get(sha).copied().ok_or_else(|| {
+            anyhow!(
+                "unable to map object {}",
+                ObjectStorage::sha_to_hex_string(sha)
+            )
+        })
+    };
+    match object_type {
+        "tree" => {
+            let mut converted = vec![];
+            for (mode, name, sha) in tree_entries(content)? {
+                converted.extend_from_slice(mode);
+                converted.push(b' ');
+                converted.extend_from_slice(name);
+                converted.push(0);
+                converted.extend_from_slice(&compat(&sha)?);
+            }
+            Ok(converted)
+        }
+        "commit" | "tag" => {
+            let header_length: usize = header_lines(content).map(|line| line.len() + 1).sum();
+            let mut converted = vec![];
+            for line in header_lines(content) {
+                match referring_line(object_type, line) {
+                    Some(hex) => {
+                        let key = &line[..line.len() - hex.len()];
+                        converted.extend_from_slice(key);
+                        let sha = ObjectStorage::hex_string_to_sha(hex)?;
+                        converted.extend_from_slice(to_hex(&compat(&sha)?).as_bytes());
+                    }
+                    None => converted.extend_from_slice(line),
+                }
+                converted.push(b'\n');
+            }
+            converted.extend_from_slice(content.get(header_length..).unwrap_or_default());
+            Ok(converted)
+        }
+        _ => Ok(content.to_vec()),
+    }
+}
+
+/// A tree entry as it is stored: mode, name and id.
+type RawTreeEntry<'a> = (&'a [u8], &'a [u8], Sha);
+
+fn tree_entries(mut content: &[u8]) -> anyhow::Result<Vec<RawTreeEntry<'_>>> {
+    let invalid = || anyhow!("invalid tree object");
+    let mut entries = vec![];
+    while !content.is_empty() {
+        let space = content.iter().position(|&byte| byte == b' ');
+        let (mode, rest) = content.split_at(space.ok_or_else(invalid)?);
+        let rest = &rest[1..];
+        let nul = rest
+            .iter()
+            .position(|&byte| byte == 0)
+            .ok_or_else(invalid)?;
+        let (name, rest) = rest.split_at(nul);
+        let sha: Sha = rest
+            .get(1..21)
+            .ok_or_else(invalid)?
+            .try_into()
+            .map_err(|_| invalid())?;
+        entries.push((mode, name, sha));
+        content = &rest[21..];
+    }
+    Ok(entries)
+}
+
+/// The lines of a commit or tag before the blank line that starts its message, without their
+/// line feeds.
+fn header_lines(content: &[u8]) -> impl Iterator<Item = &[u8]> {
+    content
+        .split(|&byte| byte == b'\n')
+        .take_while(|line| !line.is_empty())
+}
+
+/// The id the header line names an object by: a commit's `tree` and `parent` lines and a
+/// tag's `object` line.
+fn referring_line<'a>(object_type: &str, line: &'a [u8]) -> Option<&'a str> {
+    let keys: &[&[u8]] = match object_type {
+        "commit" => &[b"tree ", b"parent "],
+        _ => &[b"object "],
+    };
+    let hex = keys.iter().find_map(|key| line.strip_prefix(*key))?;
+    std::str::from_utf8(hex).ok().filter(|hex| hex.len() == 40)
+}
+
+const ROUND_CONSTANTS: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// SHA-256 as FIPS 180-4 defines it.
+fn sha256(data: &[u8]) -> Sha256 {
+    let mut state: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+    let mut padded = data.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+    for block in padded.chunks_exact(64) {
+        let mut schedule = [0u32; 64];
+        for (word, bytes) in schedule.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_be_bytes(bytes.try_into().unwrap());
+        }
+        for index in 16..64 {
+            let s0 = schedule[index - 15].rotate_right(7)
+                ^ schedule[index - 15].rotate_right(18)
+                ^ (schedule[index - 15] >> 3);
+            let s1 = schedule[index - 2].rotate_right(17)
+                ^ schedule[index - 2].rotate_right(19)
+                ^ (schedule[index - 2] >> 10);
+            schedule[index] = schedule[index - 16]
+                .wrapping_add(s0)
+                .wrapping_add(schedule[index - 7])
+                .wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for (constant, word) in ROUND_CONSTANTS.iter().zip(schedule) {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let choice = (e & f) ^ (!e & g);
+            let temp1 = h
+                .wrapping_add(s1)
+                .wrapping_add(choice)
+                .wrapping_add(*constant)
+                .wrapping_add(word);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let majority = (a & b) ^ (a & c) ^ (b & c);
+            let temp2 = s0.wrapping_add(majority);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(temp1);
+            d = c;
+            c = b;
+            b = a;
+            a = temp1.wrapping_add(temp2);
+        }
+        for (value, added) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *value = value.wrapping_add(added);
+        }
+    }
+    let mut digest = [0u8; 32];
+    for (bytes, value) in digest.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
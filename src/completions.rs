@@ -0,0 +1,234 @@
+use crate::cli::{self, ArgumentKind, CommandSpec};
+use crate::refs;
+use anyhow::anyhow;
+
+const SHELLS: &[&str] = &["bash", "zsh", "fish"];
+
+/// Generates the completion script for `shell`, derived from the command definitions in `cli`.
+pub fn script(shell: &str) -> anyhow::Result<String> {
+    let program = cli::program_name();
+    match shell {
+        "bash" => Ok(bash_script(&program)),
+        "zsh" => Ok(zsh_script(&program)),
+        "fish" => Ok(fish_script(&program)),
+        _ => Err(anyhow!(
+            "unsupported shell '{}' (expected one of: {})",
+            shell,
+            SHELLS.join(", ")
+        )),
+    }
+}
+
+/// Prints the short names of all branches and tags, one per line; called by the scripts.
+pub fn print_ref_names() -> anyhow::Result<()> {
+    for (name, _) in refs::list_refs()? {
+        if let Some(short) = name
+            .strip_prefix("refs/heads/")
+            .or_else(|| name.strip_prefix("refs/tags/"))
+        {
+            println!("{}", short);
+        }
+    }
+    Ok(())
+}
+
+/// The option names of a command without their value placeholders (`-t <type>` becomes `-t`).
+fn option_names(options: &[(&str, &str)]) -> Vec<String> {
+    options
+        .iter()
+        .flat_map(|(flag, _)| flag.split(", "))
+        .map(|alias| {
+            let name = alias.split(' ').next().unwrap_or(alias);
+            match name.split_once('=') {
+                Some((name, _)) => format!("{}=", name),
+                None => name.to_string(),
+            }
+        })
+        .collect()
+}
+
+fn command_names() -> String {
+    cli::listed_commands()
+        .map(|command| command.name)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn bash_words(program: &str, command: &CommandSpec) -> String {
+    let mut words = option_names(command.options).join(" ");
+    let dynamic = match command.arguments {
+        ArgumentKind::Refs => format!("$({} completions --refs 2>/dev/null)", program),
+        ArgumentKind::Commands => command_names(),
+        ArgumentKind::Shells => SHELLS.join(" "),
+        ArgumentKind::None | ArgumentKind::Files => String::new(),
+    };
+    if !dynamic.is_empty() {
+        if !words.is_empty() {
+            words.push(' ');
+        }
+        words.push_str(&dynamic);
+    }
+    words
+}
+
+fn bash_script(program: &str) -> String {
+    let function = format!("_{}", program.replace('-', "_"));
+    let mut cases = String::new();
+    for command in cli::listed_commands() {
+        let files = if command.arguments == ArgumentKind::Files {
+            "-f "
+        } else {
+            ""
+        };
+        cases.push_str(&format!(
+            "        {}) COMPREPLY=($(compgen {}-W \"{}\" -- \"$cur\")) ;;\n",
+            command.name,
+            files,
+            bash_words(program, command)
+        ));
+    }
+    format!(
+        r#"# bash completion for {program}
+{function}() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}"
+    local i=1
+    while [ $i -lt $COMP_CWORD ]; do
+        case "${{COMP_WORDS[$i]}}" in
+            -C|--git-dir|--work-tree) i=$((i + 2)) ;;
+            -*) i=$((i + 1)) ;;
+            *) break ;;
+        esac
+    done
+    if [ $i -ge $COMP_CWORD ]; then
+        COMPREPLY=($(compgen -W "{global} {commands}" -- "$cur"))
+        return
+    fi
+    case "${{COMP_WORDS[$i]}}" in
+{cases}    esac
+}}
+complete -F {function} {program}
+"#,
+        global = option_names(cli::GLOBAL_OPTIONS).join(" "),
+        commands = command_names(),
+    )
+}
+
+fn zsh_script(program: &str) -> String {
+    let function = format!("_{}", program.replace('-', "_"));
+    let mut descriptions = String::new();
+    for command in cli::listed_commands() {
+        descriptions.push_str(&format!(
+            "        '{}:{}'\n",
+            command.name,
+            command.summary.replace('\'', "'\\''")
+        ));
+    }
+    let mut cases = String::new();
+    for command in cli::listed_commands() {
+        let options = option_names(command.options).join(" ");
+        let arguments = match command.arguments {
+            ArgumentKind::Files => "_files".to_string(),
+            ArgumentKind::Refs => format!(
+                "compadd -- ${{(f)\"$({} completions --refs 2>/dev/null)\"}}",
+                program
+            ),
+            ArgumentKind::Commands => format!("compadd -- {}", command_names()),
+            ArgumentKind::Shells => format!("compadd -- {}", SHELLS.join(" ")),
+            ArgumentKind::None => String::new(),
+        };
+        let mut actions: Vec<String> = vec![];
+        if !options.is_empty() {
+            actions.push(format!("compadd -- {}", options));
+        }
+        if !arguments.is_empty() {
+            actions.push(arguments);
+        }
+        if !actions.is_empty() {
+            cases.push_str(&format!(
+                "        {}) {} ;;\n",
+                command.name,
+                actions.join("; ")
+            ));
+        }
+    }
+    format!(
+        r#"#compdef {program}
+{function}() {{
+    local -a commands
+    commands=(
+{descriptions}    )
+    if (( CURRENT == 2 )); then
+        _describe 'command' commands
+        return
+    fi
+    case $words[2] in
+{cases}    esac
+}}
+compdef {function} {program}
+"#
+    )
+}
+
+fn fish_option(program: &str, condition: &str, flag: &str, description: &str) -> String {
+    let switch = flag
+        .split(", ")
+        .map(|alias| {
+            let name = alias.split([' ', '=']).next().unwrap_or(alias);
+            match name.strip_prefix("--") {
+                Some(long) => format!("-l {}", long),
+                None => format!("-s {}", name.trim_start_matches('-')),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    let takes_value = flag.contains(['<', '=']);
+    format!(
+        "complete -c {} {}{} {}-d '{}'\n",
+        program,
+        condition,
+        switch,
+        if takes_value { "-r " } else { "" },
+        description.replace('\'', "\\'")
+    )
+}
+
+fn fish_script(program: &str) -> String {
+    let mut script = format!(
+        "# fish completion for {}\ncomplete -c {} -f\n",
+        program, program
+    );
+    for (flag, description) in cli::GLOBAL_OPTIONS {
+        script.push_str(&fish_option(
+            program,
+            "-n '__fish_use_subcommand' ",
+            flag,
+            description,
+        ));
+    }
+    for command in cli::listed_commands() {
+        script.push_str(&format!(
+            "complete -c {} -n '__fish_use_subcommand' -a {} -d '{}'\n",
+            program,
+            command.name,
+            command.summary.replace('\'', "\\'")
+        ));
+    }
+    for command in cli::listed_commands() {
+        let condition = format!("-n '__fish_seen_subcommand_from {}' ", command.name);
+        for (flag, description) in command.options {
+            script.push_str(&fish_option(program, &condition, flag, description));
+        }
+        let arguments = match command.arguments {
+            ArgumentKind::Files => "-F".to_string(),
+            ArgumentKind::Refs => format!("-a '({} completions --refs 2>/dev/null)'", program),
+            ArgumentKind::Commands => format!("-a '{}'", command_names()),
+            ArgumentKind::Shells => format!("-a '{}'", SHELLS.join(" ")),
+            ArgumentKind::None => continue,
+        };
+        script.push_str(&format!(
+            "complete -c {} {}{}\n",
+            program, condition, arguments
+        ));
+    }
+    script
+}
@@ -0,0 +1,473 @@
+//! git's config: the system, global and repository config files, with the files they
+//! include, and the `GIT_CONFIG_KEY_<n>` entries of the environment. They are parsed once into
+//! a [`Config`] that every lookup shares; [`set_value`] and the other writers of config files
+//! make the next lookup read them again.
+
+use crate::context;
+use crate::object_storage::{self, ObjectStorage};
+use crate::pathspec;
+use anyhow::anyhow;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// git's default for `core.bigFileThreshold`.
+const DEFAULT_BIG_FILE_THRESHOLD: u64 = 512 << 20;
+
+/// How deeply `include.path` may nest before the includes are taken for a loop.
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+/// Config entries from lowest to highest precedence, so that the last value of a key is the
+/// one that counts.
+#[derive(Default)]
+pub struct Config {
+    entries: Vec<Entry>,
+}
+
+struct Entry {
+    /// The section name, lowercased.
+    section: String,
+    subsection: Option<String>,
+    key: String,
+    value: String,
+    /// Whether the entry comes from the repository config rather than from the user or the
+    /// system, which git does not take some settings from.
+    from_repository: bool,
+}
+
+impl Entry {
+    /// Whether this is `section.key`, where `section` may include a subsection, e.g.
+    /// `remote.origin`.
+    fn is(&self, section: &str, key: &str) -> bool {
+        let (wanted_section, wanted_subsection) = split_section(section);
+        self.section.eq_ignore_ascii_case(wanted_section)
+            && self.subsection.as_deref() == wanted_subsection
+            && self.key.eq_ignore_ascii_case(key)
+    }
+}
+
+impl Config {
+    /// The config of the current repository: the system, global and repository config files,
+    /// in that order, and then the `GIT_CONFIG_KEY_<n>` entries. It is read on first use and
+    /// shared until a config file is written.
+    pub fn get() -> anyhow::Result<Arc<Config>> {
+        let context = context::current();
+        if let Some(config) = context.config.read().unwrap().as_ref() {
+            return Ok(config.clone());
+        }
+        let config = Arc::new(Self::load()?);
+        *context.config.write().unwrap() = Some(config.clone());
+        Ok(config)
+    }
+
+    /// Makes the next [`Config::get`] read the config files again, after one of them changed
+    /// or the repository was set.
+    pub fn invalidate() {
+        *context::current().config.write().unwrap() = None;
+    }
+
+    /// The entries of the config-format file `config_path` alone, such as a sequencer's
+    /// `opts`.
+    pub fn read_file(config_path: &Path) -> anyhow::Result<Self> {
+        let mut config = Self::default();
+        config.read(config_path, false, 0)?;
+        Ok(config)
+    }
+
+    fn load() -> anyhow::Result<Self> {
+        let mut config = Self::default();
+        if env::var_os("GIT_CONFIG_NOSYSTEM").is_none() {
+            let system = env::var_os("GIT_CONFIG_SYSTEM")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from("/etc/gitconfig"));
+            config.read_if_exists(&system, false)?;
+        }
+        if let Some(global) = env::var_os("GIT_CONFIG_GLOBAL") {
+            config.read_if_exists(Path::new(&global), false)?;
+        } else if let Some(home) = env::var_os("HOME") {
+            config.read_if_exists(&PathBuf::from(home).join(".gitconfig"), false)?;
+        }
+        let repository_entries = config.entries.len();
+        for (name, value) in environment_entries()? {
+            let Some((section, key)) = name.rsplit_once('.') else {
+                continue;
+            };
+            let (section, subsection) = split_section(section);
+            config.entries.push(Entry {
+                section: section.to_lowercase(),
+                subsection: subsection.map(str::to_string),
+                key: key.to_string(),
+                value,
+                from_repository: false,
+            });
+        }
+        // The repository config of a dubiously owned repository is never read, so only the
+        // other entries can say whether it is safe.
+        let config_path = ObjectStorage::git_dir().join("config");
+        if config_path.is_file()
+            && ObjectStorage::ensure_listed_safe(&config.protected_values("safe", "directory"))
+                .is_ok()
+        {
+            let mut repository = Self::default();
+            repository.read(&config_path, true, 0)?;
+            config
+                .entries
+                .splice(repository_entries..repository_entries, repository.entries);
+        }
+        Ok(config)
+    }
+
+    fn read_if_exists(&mut self, config_path: &Path, from_repository: bool) -> anyhow::Result<()> {
+        if config_path.is_file() {
+            self.read(config_path, from_repository, 0)?;
+        }
+        Ok(())
+    }
+
+    /// Adds the entries of `config_path`, following `include.path` and matching
+    /// `includeIf.<condition>.path` entries where they appear.
+    fn read(
+        &mut self,
+        config_path: &Path,
+        from_repository: bool,
+        depth: usize,
+    ) -> anyhow::Result<()> {
+        if depth > MAX_INCLUDE_DEPTH {
+            Err(anyhow!(
+                "exceeded maximum include depth ({}) while including {}",
+                MAX_INCLUDE_DEPTH,
+                config_path.display()
+            ))?
+        }
+        let mut current_section = String::new();
+        let mut current_subsection: Option<String> = None;
+        let mut including = false;
+        for line in fs::read_to_string(config_path)?.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            if let Some((name, subsection)) = parse_section_header(line) {
+                current_section = name;
+                current_subsection = subsection;
+                including = match (current_section.as_str(), &current_subsection) {
+                    ("include", None) => true,
+                    ("includeif", Some(condition)) => {
+                        include_condition_holds(condition, config_path)?
+                    }
+                    _ => false,
+                };
+                continue;
+            }
+            let (name, value) = match line.split_once('=') {
+                Some((name, value)) => (name.trim(), parse_value(value)),
+                // A key without a value is a boolean true.
+                None => (line, "true".to_string()),
+            };
+            if including && name.eq_ignore_ascii_case("path") {
+                let include_path = object_storage::expand_home_dir(&value);
+                let include_path = match config_path.parent() {
+                    Some(dir) if include_path.is_relative() => dir.join(include_path),
+                    _ => include_path,
+                };
+                // Missing include files are silently ignored, like git does.
+                if include_path.is_file() {
+                    self.read(&include_path, from_repository, depth + 1)?;
+                }
+                continue;
+            }
+            self.entries.push(Entry {
+                section: current_section.clone(),
+                subsection: current_subsection.clone(),
+                key: name.to_string(),
+                value,
+                from_repository,
+            });
+        }
+        Ok(())
+    }
+
+    /// The value of `section.key` that counts, the last one. The section may include a
+    /// subsection, e.g. `remote.origin`.
+    pub fn value(&self, section: &str, key: &str) -> Option<String> {
+        self.values(section, key).pop()
+    }
+
+    /// Every value of a multi-valued `section.key`, from lowest to highest precedence.
+    pub fn values(&self, section: &str, key: &str) -> Vec<String> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.is(section, key))
+            .map(|entry| entry.value.clone())
+            .collect()
+    }
+
+    /// Every value of a multi-valued `section.key` from the system and global config files
+    /// and the environment only, for settings git refuses to take from a possibly untrusted
+    /// repository.
+    pub fn protected_values(&self, section: &str, key: &str) -> Vec<String> {
+        self.entries
+            .iter()
+            .filter(|entry| !entry.from_repository && entry.is(section, key))
+            .map(|entry| entry.value.clone())
+            .collect()
+    }
+
+    /// The boolean option `section.key`: `false`, `no`, `off` and `0` are false, any other
+    /// value is true.
+    pub fn bool(&self, section: &str, key: &str) -> Option<bool> {
+        self.value(section, key)
+            .map(|value| !matches!(value.to_lowercase().as_str(), "false" | "no" | "off" | "0"))
+    }
+
+    /// The integer option `section.key`, accepting git's `k`, `m` and `g` suffixes.
+    pub fn int(&self, section: &str, key: &str) -> anyhow::Result<Option<i64>> {
+        let Some(value) = self.value(section, key) else {
+            return Ok(None);
+        };
+        let invalid = || {
+            anyhow!(
+                "bad numeric config value '{}' for '{}.{}'",
+                value,
+                section,
+                key
+            )
+        };
+        let lowered = value.trim().to_lowercase();
+        let (digits, factor) = match lowered.chars().last() {
+            Some('k') => (&lowered[..lowered.len() - 1], 1 << 10),
+            Some('m') => (&lowered[..lowered.len() - 1], 1 << 20),
+            Some('g') => (&lowered[..lowered.len() - 1], 1 << 30),
+            _ => (lowered.as_str(), 1),
+        };
+        let number = digits.parse::<i64>().map_err(|_| invalid())?;
+        number.checked_mul(factor).map(Some).ok_or_else(invalid)
+    }
+
+    /// `core.bigFileThreshold`: larger files are treated as binary by diff and are stored
+    /// in packs without deltas, so they never have to be held in memory.
+    pub fn big_file_threshold(&self) -> anyhow::Result<u64> {
+        Ok(self
+            .int("core", "bigFileThreshold")?
+            .map_or(DEFAULT_BIG_FILE_THRESHOLD, |threshold| {
+                threshold.max(0) as u64
+            }))
+    }
+
+    /// The subsections of `section` that have at least one entry, e.g. the names of the
+    /// remotes for `remote`, in the order they first appear.
+    pub fn subsections(&self, section: &str) -> Vec<String> {
+        let mut subsections: Vec<String> = vec![];
+        for entry in &self.entries {
+            if let Some(subsection) = &entry.subsection
+                && entry.section.eq_ignore_ascii_case(section)
+                && !subsections.contains(subsection)
+            {
+                subsections.push(subsection.clone());
+            }
+        }
+        subsections
+    }
+}
+
+/// Splits `remote.origin` into the section and the subsection.
+fn split_section(section: &str) -> (&str, Option<&str>) {
+    match section.split_once('.') {
+        Some((name, subsection)) => (name, Some(subsection)),
+        None => (section, None),
+    }
+}
+
+/// Reads the `GIT_CONFIG_COUNT`/`GIT_CONFIG_KEY_<n>`/`GIT_CONFIG_VALUE_<n>` entries.
+fn environment_entries() -> anyhow::Result<Vec<(String, String)>> {
+    let Ok(count) = env::var("GIT_CONFIG_COUNT") else {
+        return Ok(vec![]);
+    };
+    let count: usize = count
+        .trim()
+        .parse()
+        .map_err(|_| anyhow!("bogus count in GIT_CONFIG_COUNT: '{}'", count))?;
+    let mut entries = vec![];
+    for i in 0..count {
+        let key = env::var(format!("GIT_CONFIG_KEY_{}", i))
+            .map_err(|_| anyhow!("missing config key GIT_CONFIG_KEY_{}", i))?;
+        let value = env::var(format!("GIT_CONFIG_VALUE_{}", i))
+            .map_err(|_| anyhow!("missing config value GIT_CONFIG_VALUE_{}", i))?;
+        entries.push((key, value));
+    }
+    Ok(entries)
+}
+
+/// Evaluates an `includeIf` condition: `gitdir:`, `gitdir/i:` or `onbranch:`.
+fn include_condition_holds(condition: &str, config_path: &Path) -> anyhow::Result<bool> {
+    if let Some(pattern) = condition.strip_prefix("onbranch:") {
+        let head = fs::read_to_string(ObjectStorage::git_dir().join("HEAD")).unwrap_or_default();
+        let Some(branch) = head.trim().strip_prefix("ref: refs/heads/") else {
+            return Ok(false);
+        };
+        let mut pattern = pattern.to_string();
+        if pattern.ends_with('/') {
+            pattern.push_str("**");
+        }
+        return Ok(pathspec::wildmatch(&pattern, branch, true));
+    }
+    let (pattern, icase) = if let Some(pattern) = condition.strip_prefix("gitdir:") {
+        (pattern, false)
+    } else if let Some(pattern) = condition.strip_prefix("gitdir/i:") {
+        (pattern, true)
+    } else {
+        return Ok(false);
+    };
+    let Ok(git_dir) = ObjectStorage::git_dir().canonicalize() else {
+        return Ok(false);
+    };
+    let mut pattern = if let Some(relative) = pattern.strip_prefix("./") {
+        let base = config_path.parent().unwrap_or(Path::new("."));
+        base.join(relative).to_string_lossy().to_string()
+    } else {
+        object_storage::expand_home_dir(pattern)
+            .to_string_lossy()
+            .to_string()
+    };
+    if !pattern.starts_with('/') {
+        pattern = format!("**/{}", pattern);
+    }
+    if pattern.ends_with('/') {
+        pattern.push_str("**");
+    }
+    let git_dir = git_dir.to_string_lossy().to_string();
+    Ok(if icase {
+        pathspec::wildmatch(&pattern.to_lowercase(), &git_dir.to_lowercase(), true)
+    } else {
+        pathspec::wildmatch(&pattern, &git_dir, true)
+    })
+}
+
+fn parse_value(raw: &str) -> String {
+    let mut value = String::new();
+    let mut in_quotes = false;
+    let mut chars = raw.trim().chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            '\\' => match chars.next() {
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                Some(other) => value.push(other),
+                None => (),
+            },
+            '#' | ';' if !in_quotes => break,
+            _ => value.push(c),
+        }
+    }
+    if in_quotes {
+        value
+    } else {
+        value.trim_end().to_string()
+    }
+}
+
+/// Parses a `[section]` or `[section "subsection"]` header into the lowercased section
+/// name and the case-sensitive subsection.
+fn parse_section_header(line: &str) -> Option<(String, Option<String>)> {
+    let header = line.trim().strip_prefix('[')?.split(']').next()?.trim();
+    Some(match header.split_once(char::is_whitespace) {
+        Some((name, subsection)) => (
+            name.to_lowercase(),
+            Some(subsection.trim().trim_matches('"').to_string()),
+        ),
+        None => (header.to_lowercase(), None),
+    })
+}
+
+/// Sets (or with `None` removes) `section.key` in `config_path`. The file is edited line by
+/// line: the last existing assignment is replaced in place, a new key goes at the end of the
+/// last matching section, and everything else (comments, blank lines, indentation and
+/// unrelated sections) is left untouched.
+pub fn set_value(
+    config_path: &Path,
+    section: &str,
+    key: &str,
+    value: Option<&str>,
+) -> anyhow::Result<()> {
+    let (wanted_section, wanted_subsection) = split_section(section);
+    let content = if config_path.exists() {
+        fs::read_to_string(config_path)?
+    } else {
+        String::new()
+    };
+    let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+    let mut in_section = false;
+    let mut last_assignment: Option<usize> = None;
+    let mut section_end: Option<usize> = None;
+    for (i, line) in lines.iter().enumerate() {
+        if let Some((name, subsection)) = parse_section_header(line) {
+            in_section = name.eq_ignore_ascii_case(wanted_section)
+                && subsection.as_deref() == wanted_subsection;
+            if in_section {
+                section_end = Some(i);
+            }
+            continue;
+        }
+        if !in_section {
+            continue;
+        }
+        let trimmed = line.trim();
+        if !trimmed.is_empty() && !trimmed.starts_with('#') && !trimmed.starts_with(';') {
+            section_end = Some(i);
+            let name = trimmed.split('=').next().unwrap_or(trimmed).trim();
+            if name.eq_ignore_ascii_case(key) {
+                last_assignment = Some(i);
+            }
+        }
+    }
+    match (value, last_assignment, section_end) {
+        (Some(value), Some(i), _) => {
+            let indent: String = lines[i].chars().take_while(|c| c.is_whitespace()).collect();
+            let indent = if indent.is_empty() { "\t" } else { &indent };
+            lines[i] = format!("{}{} = {}", indent, key, quote_value(value));
+        }
+        (Some(value), None, Some(end)) => {
+            lines.insert(end + 1, format!("\t{} = {}", key, quote_value(value)));
+        }
+        (Some(value), None, None) => {
+            lines.push(match wanted_subsection {
+                Some(subsection) => format!(
+                    "[{} \"{}\"]",
+                    wanted_section,
+                    subsection.replace('\\', "\\\\").replace('"', "\\\"")
+                ),
+                None => format!("[{}]", wanted_section),
+            });
+            lines.push(format!("\t{} = {}", key, quote_value(value)));
+        }
+        (None, Some(i), _) => {
+            lines.remove(i);
+        }
+        (None, None, _) => Err(anyhow!("no such key: {}.{}", section, key))?,
+    }
+    let mut output = lines.join("\n");
+    output.push('\n');
+    let lock_path = config_path.with_extension("lock");
+    fs::write(&lock_path, output)?;
+    fs::rename(&lock_path, config_path)?;
+    Config::invalidate();
+    Ok(())
+}
+
+fn quote_value(value: &str) -> String {
+    let needs_quotes = value.starts_with(char::is_whitespace)
+        || value.ends_with(char::is_whitespace)
+        || value.contains(['#', ';']);
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+    if needs_quotes {
+        format!("\"{}\"", escaped)
+    } else {
+        escaped
+    }
+}
@@ -0,0 +1,112 @@
+//! The repository a thread works in. A [`Context`] has a repository's paths, what has been
+//! read from its files, such as its config and its packs, and the settings an embedding
+//! application made through its [`Repository`](crate::Repository). The object storage, the
+//! refs and the index read and write the repository of the context the thread has entered,
+//! so that a process can work with several repositories, each through its own `Repository`.
+//! Outside any, as when `init` creates one, it is `.git` in the current directory.
+
+use crate::cancel::CancellationToken;
+use crate::commit_graph::CommitGraph;
+use crate::compat_map::CompatMap;
+use crate::config::Config;
+use crate::object_storage::Sha;
+use crate::object_storage::pack::{DeltaBaseCache, Pack};
+use crate::progress::{EventSink, ProgressReporter};
+use crate::shared_repository::Sharing;
+use crate::usage::UsageRecorder;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+
+pub(crate) struct Context {
+    pub git_dir: PathBuf,
+    /// `None` for a bare repository, or when running inside the repository directory.
+    pub work_tree: Option<PathBuf>,
+    pub config: RwLock<Option<Arc<Config>>>,
+    pub packs: Mutex<Option<Arc<Vec<Pack>>>>,
+    pub delta_base_cache: Mutex<Option<DeltaBaseCache>>,
+    pub promised: Mutex<Option<Arc<HashSet<Sha>>>>,
+    pub sharing: OnceLock<Sharing>,
+    pub commit_graph: OnceLock<Option<Arc<CommitGraph>>>,
+    pub compat_enabled: OnceLock<bool>,
+    pub compat_map: Mutex<Option<CompatMap>>,
+    pub dry_run: AtomicBool,
+    pub cancellation_token: RwLock<Option<CancellationToken>>,
+    pub reporter: RwLock<Option<Arc<dyn ProgressReporter>>>,
+    pub event_sink: RwLock<Option<Arc<dyn EventSink>>>,
+    pub usage_recorder: RwLock<Option<Arc<dyn UsageRecorder>>>,
+}
+
+thread_local! {
+    /// The contexts this thread has entered, innermost last.
+    static ENTERED: RefCell<Vec<Arc<Context>>> = const { RefCell::new(vec![]) };
+}
+
+/// The context of threads that have not entered one.
+static DEFAULT: OnceLock<Arc<Context>> = OnceLock::new();
+
+impl Context {
+    pub fn new(git_dir: PathBuf, work_tree: Option<PathBuf>) -> Arc<Self> {
+        Arc::new(Self {
+            git_dir,
+            work_tree,
+            config: RwLock::new(None),
+            packs: Mutex::new(None),
+            delta_base_cache: Mutex::new(None),
+            promised: Mutex::new(None),
+            sharing: OnceLock::new(),
+            commit_graph: OnceLock::new(),
+            compat_enabled: OnceLock::new(),
+            compat_map: Mutex::new(None),
+            dry_run: AtomicBool::new(false),
+            cancellation_token: RwLock::new(None),
+            reporter: RwLock::new(None),
+            event_sink: RwLock::new(None),
+            usage_recorder: RwLock::new(None),
+        })
+    }
+
+    /// A context for another repository, such as the one `clone` creates, with this one's
+    /// settings: the dry-run mode, the cancellation token, the reporter, the event sink and
+    /// the usage recorder.
+    pub fn for_repository(&self, git_dir: PathBuf, work_tree: Option<PathBuf>) -> Arc<Self> {
+        let context = Self::new(git_dir, work_tree);
+        context
+            .dry_run
+            .store(self.dry_run.load(Ordering::SeqCst), Ordering::SeqCst);
+        *context.cancellation_token.write().unwrap() =
+            self.cancellation_token.read().unwrap().clone();
+        *context.reporter.write().unwrap() = self.reporter.read().unwrap().clone();
+        *context.event_sink.write().unwrap() = self.event_sink.read().unwrap().clone();
+        *context.usage_recorder.write().unwrap() = self.usage_recorder.read().unwrap().clone();
+        context
+    }
+}
+
+/// The context this thread last entered and has not left yet.
+pub(crate) fn current() -> Arc<Context> {
+    ENTERED
+        .with_borrow(|entered| entered.last().cloned())
+        .unwrap_or_else(|| {
+            DEFAULT
+                .get_or_init(|| Context::new(PathBuf::from(".git"), None))
+                .clone()
+        })
+}
+
+/// Runs `work` in `context`, leaving it again when `work` returns or unwinds.
+pub(crate) fn enter<T>(context: Arc<Context>, work: impl FnOnce() -> T) -> T {
+    struct Leave;
+
+    impl Drop for Leave {
+        fn drop(&mut self) {
+            ENTERED.with_borrow_mut(|entered| entered.pop());
+        }
+    }
+
+    ENTERED.with_borrow_mut(|entered| entered.push(context));
+    let _leave = Leave;
+    work()
+}
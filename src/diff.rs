@@ -0,0 +1,382 @@
+use crate::index::{self, Index};
+use crate::object_storage::{GitObject, ObjectStorage, Sha, TreeEntry, TreeEntryPermission};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+use std::ops::Range;
+
+/// A file whose content or mode differs between two trees. `None` on one side means the file
+/// was added or deleted.
+pub struct FileChange {
+    pub path: String,
+    pub old: Option<Sha>,
+    pub new: Option<Sha>,
+    pub old_mode: Option<TreeEntryPermission>,
+    pub new_mode: Option<TreeEntryPermission>,
+}
+
+/// One step of a line diff, with indexes into the old and new sequences.
+#[derive(Clone, Copy)]
+pub enum Edit {
+    Equal(usize, usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+/// Lists the files whose blob or mode differs between the trees `old` and `new` (either may be absent,
+/// like the parent of a root commit). Subtrees with the same object id are skipped without
+/// being read.
+pub fn diff_trees(old: Option<&Sha>, new: Option<&Sha>) -> anyhow::Result<Vec<FileChange>> {
+    let mut changes = vec![];
+    diff_subtrees(old, new, "", &mut changes)?;
+    Ok(changes)
+}
+
+fn diff_subtrees(
+    old: Option<&Sha>,
+    new: Option<&Sha>,
+    prefix: &str,
+    changes: &mut Vec<FileChange>,
+) -> anyhow::Result<()> {
+    if old == new {
+        return Ok(());
+    }
+    let mut entries: BTreeMap<String, (Option<TreeEntry>, Option<TreeEntry>)> = BTreeMap::new();
+    for entry in tree_entries(old)? {
+        let name = entry.name.clone();
+        entries.entry(name).or_default().0 = Some(entry);
+    }
+    for entry in tree_entries(new)? {
+        let name = entry.name.clone();
+        entries.entry(name).or_default().1 = Some(entry);
+    }
+    for (name, (old_entry, new_entry)) in entries {
+        let path = format!("{}{}", prefix, name);
+        let is_dir = |entry: &TreeEntry| matches!(entry.permission, TreeEntryPermission::Directory);
+        let (old_dir, old_file): (Option<&TreeEntry>, Option<&TreeEntry>) = match &old_entry {
+            Some(entry) if is_dir(entry) => (Some(entry), None),
+            entry => (None, entry.as_ref()),
+        };
+        let (new_dir, new_file): (Option<&TreeEntry>, Option<&TreeEntry>) = match &new_entry {
+            Some(entry) if is_dir(entry) => (Some(entry), None),
+            entry => (None, entry.as_ref()),
+        };
+        if old_dir.is_some() || new_dir.is_some() {
+            diff_subtrees(
+                old_dir.map(|entry| &entry.hash),
+                new_dir.map(|entry| &entry.hash),
+                &format!("{}/", path),
+                changes,
+            )?;
+        }
+        let old_sha = old_file.map(|entry| entry.hash);
+        let new_sha = new_file.map(|entry| entry.hash);
+        let old_mode = old_file.map(|entry| entry.permission);
+        let new_mode = new_file.map(|entry| entry.permission);
+        if old_sha != new_sha || old_mode != new_mode {
+            changes.push(FileChange {
+                path,
+                old: old_sha,
+                new: new_sha,
+                old_mode,
+                new_mode,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// The files of a tree, the index or the working tree: path to mode and blob id.
+pub type Snapshot = BTreeMap<String, (TreeEntryPermission, Sha)>;
+
+/// The files of the tree `tree` (none when absent).
+pub fn tree_snapshot(tree: Option<&Sha>) -> anyhow::Result<Snapshot> {
+    Ok(diff_trees(None, tree)?
+        .into_iter()
+        .filter_map(|file| Some((file.path, (file.new_mode?, file.new?))))
+        .collect())
+}
+
+/// The staged files, without unmerged entries and modes a tree can't hold.
+pub fn index_snapshot(index: &Index) -> Snapshot {
+    index
+        .entries
+        .iter()
+        .filter(|entry| entry.stage == 0)
+        .filter_map(|entry| {
+            let permission = index::mode_permission(entry.mode)?;
+            Some((entry.path.clone(), (permission, entry.sha)))
+        })
+        .collect()
+}
+
+/// Lists the files whose content or mode differs between two snapshots, sorted by path.
+pub fn diff_snapshots(old: &Snapshot, new: &Snapshot) -> Vec<FileChange> {
+    let paths: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    paths
+        .into_iter()
+        .filter_map(|path| {
+            let (old, new) = (old.get(path), new.get(path));
+            (old != new).then(|| FileChange {
+                path: path.clone(),
+                old: old.map(|(_, sha)| *sha),
+                new: new.map(|(_, sha)| *sha),
+                old_mode: old.map(|(mode, _)| *mode),
+                new_mode: new.map(|(mode, _)| *mode),
+            })
+        })
+        .collect()
+}
+
+/// Lists the staged files that differ from the tree `tree` (absent before the first commit),
+/// sorted by path. Unmerged entries are skipped.
+pub fn diff_tree_to_index(tree: Option<&Sha>, index: &Index) -> anyhow::Result<Vec<FileChange>> {
+    Ok(diff_snapshots(
+        &tree_snapshot(tree)?,
+        &index_snapshot(index),
+    ))
+}
+
+fn tree_entries(sha: Option<&Sha>) -> anyhow::Result<Vec<TreeEntry>> {
+    match sha {
+        Some(sha) => match GitObject::from_sha(sha)? {
+            GitObject::Tree(tree) => Ok(tree.entries),
+            _ => Err(anyhow::anyhow!("expected a tree object")),
+        },
+        None => Ok(vec![]),
+    }
+}
+
+/// A shortest edit script from `old` to `new` using Myers' O(ND) algorithm.
+pub fn diff_lines<T: PartialEq>(old: &[T], new: &[T]) -> Vec<Edit> {
+    let n = old.len() as isize;
+    let m = new.len() as isize;
+    let max = (n + m) as usize;
+    let offset = max as isize + 1;
+    let index = |k: isize| (k + offset) as usize;
+    let mut v = vec![0isize; 2 * max + 3];
+    // The furthest-reaching x on every diagonal, before each round d, for backtracking.
+    let mut trace: Vec<Vec<isize>> = vec![];
+    'search: for d in 0..=max as isize {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let mut x = if k == -d || (k != d && v[index(k - 1)] < v[index(k + 1)]) {
+                v[index(k + 1)]
+            } else {
+                v[index(k - 1)] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && old[x as usize] == new[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[index(k)] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let mut edits = vec![];
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let previous_k = if k == -d || (k != d && v[index(k - 1)] < v[index(k + 1)]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let previous_x = v[index(previous_k)];
+        let previous_y = previous_x - previous_k;
+        while x > previous_x && y > previous_y {
+            x -= 1;
+            y -= 1;
+            edits.push(Edit::Equal(x as usize, y as usize));
+        }
+        if d > 0 {
+            if x == previous_x {
+                y -= 1;
+                edits.push(Edit::Insert(y as usize));
+            } else {
+                x -= 1;
+                edits.push(Edit::Delete(x as usize));
+            }
+        }
+    }
+    edits.reverse();
+    edits
+}
+
+/// Splits `content` into lines, each keeping its trailing newline.
+pub fn split_lines(content: &[u8]) -> Vec<&[u8]> {
+    content.split_inclusive(|&b| b == b'\n').collect()
+}
+
+/// Whether `content` looks binary to git: a NUL byte in the first 8000 bytes.
+pub fn is_binary(content: &[u8]) -> bool {
+    content.iter().take(8000).any(|&b| b == 0)
+}
+
+/// Writes the git-style patch for `change`, whose contents are `old` and `new` (empty for the
+/// missing side), with `context` lines of context around each hunk; without contents, for
+/// files over `core.bigFileThreshold`, it is reported as a binary change. A change between a
+/// file and a symlink has to be given as a deletion and an addition.
+pub fn write_patch(
+    out: &mut impl Write,
+    change: &FileChange,
+    contents: Option<(&[u8], &[u8])>,
+    context: usize,
+) -> io::Result<()> {
+    let path = &change.path;
+    writeln!(out, "diff --git a/{} b/{}", path, path)?;
+    let mode = |permission: Option<TreeEntryPermission>| {
+        permission.map(|permission| format!("{:0>6}", permission.to_string_repr()))
+    };
+    let (old_mode, new_mode) = (mode(change.old_mode), mode(change.new_mode));
+    match (&old_mode, &new_mode) {
+        (None, Some(new_mode)) => writeln!(out, "new file mode {}", new_mode)?,
+        (Some(old_mode), None) => writeln!(out, "deleted file mode {}", old_mode)?,
+        (Some(old_mode), Some(new_mode)) if old_mode != new_mode => {
+            writeln!(out, "old mode {}\nnew mode {}", old_mode, new_mode)?
+        }
+        _ => (),
+    }
+    if change.old == change.new {
+        return Ok(());
+    }
+    let abbreviated = |sha: Option<Sha>| {
+        ObjectStorage::sha_to_hex_string(&sha.unwrap_or([0; 20]))[..7].to_string()
+    };
+    write!(
+        out,
+        "index {}..{}",
+        abbreviated(change.old),
+        abbreviated(change.new)
+    )?;
+    match (&old_mode, &new_mode) {
+        (Some(old_mode), Some(new_mode)) if old_mode == new_mode => writeln!(out, " {}", old_mode)?,
+        _ => writeln!(out)?,
+    }
+    let old_name = match change.old {
+        Some(_) => format!("a/{}", path),
+        None => "/dev/null".to_string(),
+    };
+    let new_name = match change.new {
+        Some(_) => format!("b/{}", path),
+        None => "/dev/null".to_string(),
+    };
+    let Some((old, new)) = contents.filter(|(old, new)| !is_binary(old) && !is_binary(new)) else {
+        return writeln!(out, "Binary files {} and {} differ", old_name, new_name);
+    };
+    writeln!(out, "--- {}\n+++ {}", old_name, new_name)?;
+    write_hunks(out, old, new, context)
+}
+
+/// Writes the `@@` hunks of a unified diff. Changes less than two contexts apart share a hunk,
+/// as in git.
+pub fn write_hunks(out: &mut impl Write, old: &[u8], new: &[u8], context: usize) -> io::Result<()> {
+    let (old_lines, new_lines) = (split_lines(old), split_lines(new));
+    let edits = diff_lines(&old_lines, &new_lines);
+    for range in hunk_ranges(&edits, context) {
+        write_hunk(out, &old_lines, &new_lines, &edits, range, true)?;
+    }
+    Ok(())
+}
+
+/// Where the hunks of a unified diff with `context` lines of context are in `edits`.
+pub fn hunk_ranges(edits: &[Edit], context: usize) -> Vec<Range<usize>> {
+    let changed: Vec<usize> = (0..edits.len())
+        .filter(|&i| !matches!(edits[i], Edit::Equal(..)))
+        .collect();
+    let mut ranges = vec![];
+    let mut next = 0;
+    while next < changed.len() {
+        let mut last = next;
+        while last + 1 < changed.len() && changed[last + 1] - changed[last] - 1 <= 2 * context {
+            last += 1;
+        }
+        let start = changed[next].saturating_sub(context);
+        let end = (changed[last] + 1 + context).min(edits.len());
+        ranges.push(start..end);
+        next = last + 1;
+    }
+    ranges
+}
+
+/// Writes the hunk made of the edits in `range` of the line diff `edits` from `old_lines` to
+/// `new_lines`, with its `@@` header, followed by the function the hunk is in when
+/// `function_context` is set.
+pub fn write_hunk(
+    out: &mut impl Write,
+    old_lines: &[&[u8]],
+    new_lines: &[&[u8]],
+    edits: &[Edit],
+    range: Range<usize>,
+    function_context: bool,
+) -> io::Result<()> {
+    let old_before = edits[..range.start]
+        .iter()
+        .filter(|edit| !matches!(edit, Edit::Insert(_)))
+        .count();
+    let new_before = edits[..range.start]
+        .iter()
+        .filter(|edit| !matches!(edit, Edit::Delete(_)))
+        .count();
+    let hunk = &edits[range];
+    let old_count = hunk
+        .iter()
+        .filter(|edit| !matches!(edit, Edit::Insert(_)))
+        .count();
+    let new_count = hunk
+        .iter()
+        .filter(|edit| !matches!(edit, Edit::Delete(_)))
+        .count();
+    write!(
+        out,
+        "@@ -{} +{} @@",
+        hunk_range(old_before, old_count),
+        hunk_range(new_before, new_count)
+    )?;
+    match function_line(&old_lines[..old_before]).filter(|_| function_context) {
+        Some(function) => {
+            out.write_all(b" ")?;
+            out.write_all(function)?;
+            out.write_all(b"\n")?;
+        }
+        None => writeln!(out)?,
+    }
+    for edit in hunk {
+        let (marker, line) = match *edit {
+            Edit::Equal(i, _) => (b' ', old_lines[i]),
+            Edit::Delete(i) => (b'-', old_lines[i]),
+            Edit::Insert(j) => (b'+', new_lines[j]),
+        };
+        out.write_all(&[marker])?;
+        out.write_all(line)?;
+        if !line.ends_with(b"\n") {
+            out.write_all(b"\n\\ No newline at end of file\n")?;
+        }
+    }
+    Ok(())
+}
+
+/// `start,count` with a 1-based start; an empty range names the line before it, and a count
+/// of one is left out.
+fn hunk_range(before: usize, count: usize) -> String {
+    match count {
+        0 => format!("{},0", before),
+        1 => format!("{}", before + 1),
+        _ => format!("{},{}", before + 1, count),
+    }
+}
+
+/// The hunk header context git shows by default: the closest line above the hunk that starts
+/// with a letter, `_` or `$`, without trailing whitespace and cut to 80 bytes.
+fn function_line<'a>(lines_above: &[&'a [u8]]) -> Option<&'a [u8]> {
+    let line = lines_above.iter().rev().find(|line| {
+        line.first()
+            .is_some_and(|&b| b.is_ascii_alphabetic() || b == b'_' || b == b'$')
+    })?;
+    let trimmed = line.trim_ascii_end();
+    Some(&trimmed[..trimmed.len().min(80)])
+}
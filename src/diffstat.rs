@@ -0,0 +1,195 @@
+//! The `--stat` and `--summary` parts of a diff, as `format-patch` puts them between a
+//! commit's message and its patch: a line per file with the number of lines changed and a
+//! graph of `+` and `-` scaled to fit the width like git scales it, a total, and the files
+//! created, deleted or whose mode changed.
+
+use crate::diff::{self, Edit, FileChange};
+use crate::object_storage::TreeEntryPermission;
+use std::io::{self, Write};
+
+/// How one file changed, for the stat line about it.
+pub struct FileStat {
+    pub path: String,
+    pub added: usize,
+    pub deleted: usize,
+    /// The sizes before and after, for a binary file, whose lines are not counted.
+    pub binary_sizes: Option<(u64, u64)>,
+}
+
+impl FileStat {
+    /// Counts the lines added and deleted between `old` and `new`; binary contents are only
+    /// measured.
+    pub fn new(path: &str, old: &[u8], new: &[u8]) -> Self {
+        if diff::is_binary(old) || diff::is_binary(new) {
+            return Self::binary(path, old.len() as u64, new.len() as u64);
+        }
+        let (old_lines, new_lines) = (diff::split_lines(old), diff::split_lines(new));
+        let (mut added, mut deleted) = (0, 0);
+        for edit in diff::diff_lines(&old_lines, &new_lines) {
+            match edit {
+                Edit::Insert(_) => added += 1,
+                Edit::Delete(_) => deleted += 1,
+                Edit::Equal(..) => {}
+            }
+        }
+        Self {
+            path: path.to_string(),
+            added,
+            deleted,
+            binary_sizes: None,
+        }
+    }
+
+    pub fn binary(path: &str, old_size: u64, new_size: u64) -> Self {
+        Self {
+            path: path.to_string(),
+            added: 0,
+            deleted: 0,
+            binary_sizes: Some((old_size, new_size)),
+        }
+    }
+}
+
+/// Writes a stat line per file and the total, in at most `width` columns where possible.
+pub fn write_stat(out: &mut impl Write, stats: &[FileStat], width: usize) -> io::Result<()> {
+    let max_len = stats
+        .iter()
+        .map(|stat| stat.path.chars().count())
+        .max()
+        .unwrap_or(0);
+    let max_change = stats
+        .iter()
+        .filter(|stat| stat.binary_sizes.is_none())
+        .map(|stat| stat.added + stat.deleted)
+        .max()
+        .unwrap_or(0);
+    let bin_width = stats
+        .iter()
+        .filter_map(|stat| stat.binary_sizes)
+        .map(|(old, new)| format!("Bin {} -> {} bytes", old, new).len())
+        .max()
+        .unwrap_or(0);
+    let mut number_width = max_change.to_string().len();
+    if bin_width > 0 {
+        number_width = number_width.max(3);
+    }
+    // At least 6 columns for the graph and 10 for the name.
+    let width = width.max(16 + 6 + number_width) as isize;
+    let number_width_signed = number_width as isize;
+    let mut graph_width = if max_change + 4 > bin_width {
+        max_change
+    } else {
+        bin_width - 4
+    } as isize;
+    let mut name_width = max_len as isize;
+    if name_width + number_width_signed + 6 + graph_width > width {
+        if graph_width > width * 3 / 8 - number_width_signed - 6 {
+            graph_width = (width * 3 / 8 - number_width_signed - 6).max(6);
+        }
+        if name_width > width - number_width_signed - 6 - graph_width {
+            name_width = width - number_width_signed - 6 - graph_width;
+        } else {
+            graph_width = width - number_width_signed - 6 - name_width;
+        }
+    }
+    let (name_width, graph_width) = (name_width.max(0) as usize, graph_width as usize);
+
+    for stat in stats {
+        // A name too long to fit loses its start, up to a directory boundary.
+        let mut name = stat.path.as_str();
+        let mut prefix = "";
+        let mut room = name_width;
+        if name.chars().count() > name_width {
+            prefix = "...";
+            room = name_width.saturating_sub(3);
+            let skip = name.chars().count() - room;
+            name = &name[name.char_indices().nth(skip).map_or(name.len(), |(i, _)| i)..];
+            if let Some(slash) = name.find('/') {
+                name = &name[slash..];
+            }
+        }
+        let padding = room.saturating_sub(name.chars().count());
+        write!(out, " {}{}{} | ", prefix, name, " ".repeat(padding))?;
+        if let Some((old_size, new_size)) = stat.binary_sizes {
+            write!(out, "{:>width$}", "Bin", width = number_width)?;
+            if old_size == 0 && new_size == 0 {
+                writeln!(out)?;
+            } else {
+                writeln!(out, " {} -> {} bytes", old_size, new_size)?;
+            }
+            continue;
+        }
+        let changed = stat.added + stat.deleted;
+        write!(out, "{:>width$}", changed, width = number_width)?;
+        if changed > 0 {
+            write!(out, " ")?;
+        }
+        let (mut added, mut deleted) = (stat.added, stat.deleted);
+        if graph_width <= max_change {
+            let mut total = scale_linear(added + deleted, graph_width, max_change);
+            if total < 2 && added > 0 && deleted > 0 {
+                total = 2;
+            }
+            if added < deleted {
+                added = scale_linear(added, graph_width, max_change);
+                deleted = total - added;
+            } else {
+                deleted = scale_linear(deleted, graph_width, max_change);
+                added = total - deleted;
+            }
+        }
+        writeln!(out, "{}{}", "+".repeat(added), "-".repeat(deleted))?;
+    }
+
+    let files = stats.len();
+    let insertions: usize = stats.iter().map(|stat| stat.added).sum();
+    let deletions: usize = stats.iter().map(|stat| stat.deleted).sum();
+    let plural = |count: usize, one: &str, many: &str| {
+        format!("{} {}", count, if count == 1 { one } else { many })
+    };
+    write!(out, " {}", plural(files, "file changed", "files changed"))?;
+    if insertions > 0 || deletions == 0 {
+        write!(
+            out,
+            ", {}",
+            plural(insertions, "insertion(+)", "insertions(+)")
+        )?;
+    }
+    if deletions > 0 || insertions == 0 {
+        write!(
+            out,
+            ", {}",
+            plural(deletions, "deletion(-)", "deletions(-)")
+        )?;
+    }
+    writeln!(out)
+}
+
+/// A share of `width` columns proportional to `count` out of `max_change`, and at least one
+/// column for any change at all.
+fn scale_linear(count: usize, width: usize, max_change: usize) -> usize {
+    if count == 0 {
+        return 0;
+    }
+    1 + count * (width - 1) / max_change
+}
+
+/// Writes the `create mode`, `delete mode` and `mode change` lines of `--summary`.
+pub fn write_summary(out: &mut impl Write, changes: &[FileChange]) -> io::Result<()> {
+    let mode = |permission: TreeEntryPermission| format!("{:0>6}", permission.to_string_repr());
+    for change in changes {
+        match (change.old_mode, change.new_mode) {
+            (None, Some(new)) => writeln!(out, " create mode {} {}", mode(new), change.path)?,
+            (Some(old), None) => writeln!(out, " delete mode {} {}", mode(old), change.path)?,
+            (Some(old), Some(new)) if old != new => writeln!(
+                out,
+                " mode change {} => {} {}",
+                mode(old),
+                mode(new),
+                change.path
+            )?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
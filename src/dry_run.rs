@@ -0,0 +1,46 @@
+//! The `--dry-run` mode of commands that delete or replace repository data. The places
+//! where they do so go through [`intercept`] (or [`remove_file`]), which in a dry run reports
+//! the change as `Would <action>` on stdout and has the caller skip it. Everything else the
+//! command does, such as reading objects and taking locks, happens as usual, so the report
+//! lists exactly the changes a real run would make.
+
+use crate::context;
+use crate::object_storage::ObjectStorage;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+
+/// Puts the current repository in dry-run mode, or takes it out.
+pub(crate) fn set_active(active: bool) {
+    context::current().dry_run.store(active, Ordering::SeqCst);
+}
+
+pub(crate) fn is_active() -> bool {
+    context::current().dry_run.load(Ordering::SeqCst)
+}
+
+/// In a dry run, prints `Would <action>` and returns true, for the caller to skip the change.
+pub(crate) fn intercept(action: impl FnOnce() -> String) -> bool {
+    if is_active() {
+        println!("Would {}", action());
+    }
+    is_active()
+}
+
+/// Deletes the file at `path`, or in a dry run reports that it would.
+pub(crate) fn remove_file(path: &Path) -> io::Result<()> {
+    if intercept(|| format!("remove {}", display(path))) {
+        return Ok(());
+    }
+    fs::remove_file(path)
+}
+
+/// `path` relative to the git directory when it is inside it, like `refs/heads/main`.
+pub(crate) fn display(path: &Path) -> String {
+    let git_dir = ObjectStorage::git_dir();
+    path.strip_prefix(&git_dir)
+        .unwrap_or(path)
+        .display()
+        .to_string()
+}
@@ -0,0 +1,97 @@
+use crate::attributes::{AttributeValue, Attributes};
+use crate::object_storage::{ObjectStorage, Sha};
+use std::path::{self, Path};
+
+/// The conversions a file's attributes ask for between its content in the working tree and
+/// the blob stored in the repository. So far that is the `ident` attribute, which keeps `$Id$`
+/// in the blob and `$Id: <blob id> $` in the working tree.
+pub struct Filters {
+    ident: bool,
+}
+
+impl Filters {
+    /// The conversions for the working tree file `file`; a file outside the working tree has
+    /// none.
+    pub fn for_file(file: &Path) -> anyhow::Result<Self> {
+        let work_tree = path::absolute(ObjectStorage::work_tree())?;
+        let Ok(relative) = path::absolute(file)?
+            .strip_prefix(&work_tree)
+            .map(Path::to_owned)
+        else {
+            return Ok(Self { ident: false });
+        };
+        Ok(Self::for_attributes(&Attributes::for_path(
+            &relative.to_string_lossy(),
+        )?))
+    }
+
+    /// The conversions `attributes` ask for.
+    pub fn for_attributes(attributes: &Attributes) -> Self {
+        Self {
+            ident: attributes.get("ident") == Some(AttributeValue::Set),
+        }
+    }
+
+    /// Whether the content is stored as it is in the working tree, so that files can be
+    /// hashed and compared without being read into memory.
+    pub fn is_empty(&self) -> bool {
+        !self.ident
+    }
+
+    /// The content to store for a file with this content in the working tree: every
+    /// `$Id: ... $` is collapsed to `$Id$`.
+    pub fn to_git(&self, content: Vec<u8>) -> Vec<u8> {
+        if !self.ident {
+            return content;
+        }
+        replace_idents(&content, |_| true, b"$Id$")
+    }
+
+    /// The content to check out for the blob `sha`: `$Id$` becomes `$Id: <sha> $`. An
+    /// expanded id that has spaces in it was probably written by another version control
+    /// system and is left alone.
+    pub fn to_work_tree(&self, content: Vec<u8>, sha: &Sha) -> Vec<u8> {
+        if !self.ident {
+            return content;
+        }
+        let expanded = format!("$Id: {} $", ObjectStorage::sha_to_hex_string(sha));
+        let is_ours = |value: &[u8]| {
+            let value = value.strip_prefix(b" ").unwrap_or(value);
+            let value = value.strip_suffix(b" ").unwrap_or(value);
+            !value.contains(&b' ')
+        };
+        replace_idents(&content, is_ours, expanded.as_bytes())
+    }
+}
+
+/// Replaces each `$Id$`, and each `$Id:<value>$` on a single line whose value `replace`
+/// accepts, with `replacement`.
+fn replace_idents(content: &[u8], replace: impl Fn(&[u8]) -> bool, replacement: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(start) = rest.windows(3).position(|window| window == b"$Id") {
+        out.extend_from_slice(&rest[..start]);
+        let after = &rest[start + 3..];
+        let end = match after.first() {
+            Some(b'$') => Some(1),
+            Some(b':') => after[1..]
+                .iter()
+                .position(|&byte| byte == b'$' || byte == b'\n')
+                .filter(|&length| after[1 + length] == b'$' && replace(&after[1..1 + length]))
+                .map(|length| length + 2),
+            _ => None,
+        };
+        match end {
+            Some(end) => {
+                out.extend_from_slice(replacement);
+                rest = &after[end..];
+            }
+            None => {
+                out.extend_from_slice(b"$Id");
+                rest = after;
+            }
+        }
+    }
+    out.extend_from_slice(rest);
+    out
+}
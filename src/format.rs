@@ -0,0 +1,55 @@
+use anyhow::anyhow;
+
+/// Expands the `--format` string `format`: each `%(<field>)` becomes what `field` returns
+/// for the name, `%%` a percent sign, `%n` a newline and `%x<hex>` the byte with that code, as
+/// in git. `command` names the format in errors: for fields that `field` does not know (it
+/// returns `None`), an unterminated `%(` and any other `%`.
+pub fn expand(
+    format: &str,
+    command: &str,
+    mut field: impl FnMut(&str) -> anyhow::Result<Option<String>>,
+) -> anyhow::Result<Vec<u8>> {
+    let mut out = vec![];
+    let mut rest = format;
+    while let Some(start) = rest.find('%') {
+        out.extend_from_slice(&rest.as_bytes()[..start]);
+        let after = &rest[start + 1..];
+        if let Some(inner) = after.strip_prefix('(') {
+            let end = inner.find(')').ok_or(anyhow!(
+                "bad {} format: element '{}' does not end in ')'",
+                command,
+                after
+            ))?;
+            let name = &inner[..end];
+            let value = field(name)?.ok_or(anyhow!("bad {} format: %({})", command, name))?;
+            out.extend_from_slice(value.as_bytes());
+            rest = &inner[end + 1..];
+            continue;
+        }
+        let hex = after
+            .strip_prefix('x')
+            .and_then(|hex| hex.get(..2))
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        rest = match (after.chars().next(), hex) {
+            (Some('%'), _) => {
+                out.push(b'%');
+                &after[1..]
+            }
+            (Some('n'), _) => {
+                out.push(b'\n');
+                &after[1..]
+            }
+            (Some('x'), Some(byte)) => {
+                out.push(byte);
+                &after[3..]
+            }
+            _ => Err(anyhow!(
+                "bad {} format: element '{}' does not start with '('",
+                command,
+                after
+            ))?,
+        };
+    }
+    out.extend_from_slice(rest.as_bytes());
+    Ok(out)
+}
@@ -0,0 +1,227 @@
+use crate::index::Index;
+use crate::object_storage::pack;
+use crate::object_storage::{GitObject, ObjectStorage, Sha, TreeEntryPermission};
+use crate::promisor;
+use crate::refs;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// Bits of the exit status, as in git: corrupt objects, missing reachable objects, broken
+/// packs and refs pointing nowhere.
+const ERROR_OBJECT: i32 = 0o1;
+const ERROR_REACHABLE: i32 = 0o2;
+const ERROR_PACK: i32 = 0o4;
+const ERROR_REFS: i32 = 0o10;
+
+/// An object found in the repository, with the objects it refers to and the type each of
+/// them should have.
+struct Checked {
+    object_type: String,
+    links: Vec<(Sha, &'static str)>,
+}
+
+/// Checks the repository like `git fsck`: every loose and packed object must hash to its id
+/// and parse strictly, every pack must match its checksum, and everything reachable from the
+/// refs, `HEAD` and the index must be there, unless a promisor remote promised it (see
+/// `core.missingAction`). Problems are reported as they are found, and
+/// objects that nothing refers to as dangling when `show_dangling` is set. Returns the exit
+/// status, 0 when nothing is wrong.
+pub fn fsck(show_dangling: bool) -> anyhow::Result<i32> {
+    let mut status = 0;
+    let mut objects: HashMap<Sha, Checked> = HashMap::new();
+    for (sha, path) in ObjectStorage::loose_objects()? {
+        match ObjectStorage::read_object(&sha) {
+            Ok((object_type, content)) => {
+                status |= check_object(&sha, &object_type, &content, &mut objects);
+            }
+            Err(err) => {
+                eprintln!(
+                    "error: {}: object corrupt or missing: {}",
+                    path.display(),
+                    err
+                );
+                status |= ERROR_OBJECT;
+            }
+        }
+    }
+    for pack in pack::loaded_packs()?.iter() {
+        if !pack.verify_checksum()? {
+            eprintln!(
+                "error: {}: pack checksum mismatch",
+                pack.pack_path().display()
+            );
+            status |= ERROR_PACK;
+        }
+        for sha in pack.shas() {
+            let Some(offset) = pack.find_offset(sha) else {
+                continue;
+            };
+            match pack.read_at(offset) {
+                Ok((object_type, content)) => {
+                    status |= check_object(sha, object_type, &content, &mut objects);
+                }
+                Err(err) => {
+                    eprintln!(
+                        "error: cannot unpack {} from {}: {}",
+                        ObjectStorage::sha_to_hex_string(sha),
+                        pack.pack_path().display(),
+                        err
+                    );
+                    status |= ERROR_PACK;
+                }
+            }
+        }
+    }
+
+    let mut pending: Vec<Sha> = vec![];
+    for (name, sha) in refs::list_refs()? {
+        if objects.contains_key(&sha) {
+            pending.push(sha);
+        } else {
+            eprintln!(
+                "error: {}: invalid sha1 pointer {}",
+                name,
+                ObjectStorage::sha_to_hex_string(&sha)
+            );
+            status |= ERROR_REFS;
+        }
+    }
+    match refs::resolve_ref("HEAD")? {
+        (_, Some(head)) => pending.push(head),
+        (target, None) => println!(
+            "notice: HEAD points to an unborn branch ({})",
+            target.strip_prefix("refs/heads/").unwrap_or(&target)
+        ),
+    }
+    if Index::path().exists() {
+        for entry in Index::read()?.entries {
+            if objects.contains_key(&entry.sha) {
+                pending.push(entry.sha);
+            } else {
+                eprintln!("error: {}: invalid sha1 pointer in index", entry.path);
+                status |= ERROR_OBJECT;
+            }
+        }
+    }
+
+    let mut reachable: HashSet<Sha> = HashSet::new();
+    let mut missing: HashSet<Sha> = HashSet::new();
+    while let Some(sha) = pending.pop() {
+        if !reachable.insert(sha) {
+            continue;
+        }
+        let Some(object) = objects.get(&sha) else {
+            continue;
+        };
+        for (target, target_type) in &object.links {
+            if objects.contains_key(target) {
+                pending.push(*target);
+                continue;
+            }
+            if promisor::may_skip(target)? {
+                continue;
+            }
+            println!(
+                "broken link from {:>7} {}\n              to {:>7} {}",
+                object.object_type,
+                ObjectStorage::sha_to_hex_string(&sha),
+                target_type,
+                ObjectStorage::sha_to_hex_string(target)
+            );
+            if missing.insert(*target) {
+                println!(
+                    "missing {} {}",
+                    target_type,
+                    ObjectStorage::sha_to_hex_string(target)
+                );
+            }
+            status |= ERROR_REACHABLE;
+        }
+    }
+
+    if show_dangling {
+        let referenced: HashSet<&Sha> = objects
+            .values()
+            .flat_map(|object| object.links.iter().map(|(target, _)| target))
+            .collect();
+        let dangling: BTreeSet<&Sha> = objects
+            .keys()
+            .filter(|sha| !reachable.contains(*sha) && !referenced.contains(sha))
+            .collect();
+        for sha in dangling {
+            println!(
+                "dangling {} {}",
+                objects[sha].object_type,
+                ObjectStorage::sha_to_hex_string(sha)
+            );
+        }
+    }
+    Ok(status)
+}
+
+/// Checks that `content` hashes to `sha` and parses strictly, and records the object with
+/// the objects it refers to. An object stored twice (loose and packed) is checked each time.
+fn check_object(
+    sha: &Sha,
+    object_type: &str,
+    content: &[u8],
+    objects: &mut HashMap<Sha, Checked>,
+) -> i32 {
+    let hex = ObjectStorage::sha_to_hex_string(sha);
+    let mut stored = match ObjectStorage::header_for_content_length(object_type, content.len()) {
+        Ok(header) => header,
+        Err(err) => {
+            eprintln!("error: {}: {}", hex, err);
+            return ERROR_OBJECT;
+        }
+    };
+    stored.extend_from_slice(content);
+    let actual = ObjectStorage::hash_content(&stored);
+    if actual != *sha {
+        eprintln!(
+            "error: hash mismatch for {} (the content hashes to {})",
+            hex,
+            ObjectStorage::sha_to_hex_string(&actual)
+        );
+        return ERROR_OBJECT;
+    }
+    let (links, status) = match GitObject::from_parts_strict(object_type, content) {
+        Ok(GitObject::Commit(commit)) => {
+            let mut links = vec![(commit.tree, "tree")];
+            links.extend(commit.parents.iter().map(|parent| (*parent, "commit")));
+            (links, 0)
+        }
+        Ok(GitObject::Tree(tree)) => {
+            let links = tree
+                .entries
+                .iter()
+                .map(|entry| {
+                    let entry_type = match entry.permission {
+                        TreeEntryPermission::Directory => "tree",
+                        _ => "blob",
+                    };
+                    (entry.hash, entry_type)
+                })
+                .collect();
+            (links, 0)
+        }
+        Ok(GitObject::Tag(tag)) => {
+            let tag_type = match tag.object_type.as_str() {
+                "commit" => "commit",
+                "tree" => "tree",
+                "tag" => "tag",
+                _ => "blob",
+            };
+            (vec![(tag.object, tag_type)], 0)
+        }
+        Ok(GitObject::Blob(_)) => (vec![], 0),
+        Err(err) => {
+            eprintln!("error in {} {}: {}", object_type, hex, err);
+            (vec![], ERROR_OBJECT)
+        }
+    };
+    objects.entry(*sha).or_insert(Checked {
+        object_type: object_type.to_string(),
+        links,
+    });
+    status
+}
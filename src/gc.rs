@@ -0,0 +1,133 @@
+use crate::background;
+use crate::cancel;
+use crate::cli;
+use crate::config::Config;
+use crate::dry_run;
+use crate::object_storage::ObjectStorage;
+use crate::refs;
+use crate::repack::{self, RepackOptions};
+use anyhow::anyhow;
+use std::fs;
+
+/// git's defaults for `gc.auto` and `gc.autoPackLimit`.
+const DEFAULT_AUTO_THRESHOLD: u64 = 6700;
+const DEFAULT_AUTO_PACK_LIMIT: u64 = 50;
+
+/// The job name of the background gc, which locks `.git/gc.pid` and logs to `.git/gc.log`.
+const JOB: &str = "gc";
+
+/// Runs `gc --auto` when the `gc.auto` and `gc.autoPackLimit` thresholds are exceeded, after a
+/// command wrote objects. With `gc.autoDetach` (the default) the command line runs it in the
+/// background; commands run through the library run it before they return. If the last
+/// background run reported a problem, that is shown instead of running again.
+pub fn auto_gc() -> anyhow::Result<()> {
+    if !needs_gc()? {
+        return Ok(());
+    }
+    if let Some(log) = background::last_failure(JOB)? {
+        eprintln!(
+            "warning: The last gc run reported the following. Please correct the root cause\n\
+             and remove {}.\n\
+             Automatic cleanup will not be performed until the file is removed.\n\n{}",
+            ObjectStorage::git_dir().join("gc.log").display(),
+            log
+        );
+        return Ok(());
+    }
+    // A dry run reports what it would do before the command finishes.
+    let detach = background::can_detach()
+        && Config::get()?.bool("gc", "autoDetach").unwrap_or(true)
+        && !dry_run::is_active();
+    if detach {
+        eprintln!("Auto packing the repository in background for optimum performance.");
+    } else {
+        eprintln!("Auto packing the repository for optimum performance.");
+    }
+    eprintln!(
+        "See \"{} help gc\" for manual housekeeping.",
+        cli::program_name()
+    );
+    if detach {
+        background::spawn(JOB, &["gc", "--auto"])
+    } else {
+        run_gc(true, true)
+    }
+}
+
+/// Whether there are more loose objects than `gc.auto` or more packs than
+/// `gc.autoPackLimit`; setting either option to 0 disables its check, and `gc.auto=0` disables
+/// automatic gc altogether.
+pub fn needs_gc() -> anyhow::Result<bool> {
+    let threshold = gc_config("auto", DEFAULT_AUTO_THRESHOLD)?;
+    let pack_limit = gc_config("autoPackLimit", DEFAULT_AUTO_PACK_LIMIT)?;
+    if threshold == 0 {
+        return Ok(false);
+    }
+    Ok(too_many_loose_objects(threshold)? || (pack_limit > 0 && pack_count()? > pack_limit))
+}
+
+/// Packs the refs and the objects, under the gc lock: everything reachable goes into one
+/// pack, replacing the existing packs and the loose objects. An `auto` run only packs the
+/// loose objects unless there are too many packs, and gives way to a gc that is already
+/// running.
+pub fn run_gc(auto: bool, quiet: bool) -> anyhow::Result<()> {
+    let _lock = match background::JobLock::acquire(JOB)? {
+        Ok(lock) => lock,
+        Err(_) if auto => return Ok(()),
+        Err(pid) => Err(anyhow!(
+            "gc is already running (pid {}); remove {} if it is not",
+            pid,
+            ObjectStorage::git_dir().join("gc.pid").display()
+        ))?,
+    };
+    refs::pack_refs()?;
+    cancel::check()?;
+    let pack_limit = gc_config("autoPackLimit", DEFAULT_AUTO_PACK_LIMIT)?;
+    let all = !auto || (pack_limit > 0 && pack_count()? > pack_limit);
+    repack::repack(&RepackOptions::new(all, true, quiet)?)?;
+    background::clear_empty_log(JOB)
+}
+
+/// Estimates the number of loose objects from the `objects/17` directory as git does: object
+/// ids are evenly distributed, so it holds about 1/256 of them.
+fn too_many_loose_objects(threshold: u64) -> anyhow::Result<bool> {
+    let sample_dir = ObjectStorage::git_dir().join("objects").join("17");
+    let Ok(entries) = fs::read_dir(sample_dir) else {
+        return Ok(false);
+    };
+    let sampled = entries
+        .flatten()
+        .filter(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            name.len() == 38 && name.bytes().all(|b| b.is_ascii_hexdigit())
+        })
+        .count() as u64;
+    Ok(sampled > threshold.div_ceil(256))
+}
+
+/// Packs that `gc` would combine; packs with a `.keep` or `.promisor` file are left alone.
+fn pack_count() -> anyhow::Result<u64> {
+    let pack_dir = ObjectStorage::git_dir().join("objects").join("pack");
+    let Ok(entries) = fs::read_dir(&pack_dir) else {
+        return Ok(0);
+    };
+    Ok(entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "pack")
+        })
+        .filter(|path| {
+            !path.with_extension("keep").exists() && !path.with_extension("promisor").exists()
+        })
+        .count() as u64)
+}
+
+/// Reads the integer option `gc.<key>`; negative values disable the check, like 0.
+fn gc_config(key: &str, default: u64) -> anyhow::Result<u64> {
+    Ok(Config::get()?
+        .int("gc", key)?
+        .map_or(default, |number| number.max(0) as u64))
+}
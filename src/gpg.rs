@@ -0,0 +1,166 @@
+use crate::config::Config;
+use crate::object_storage;
+use crate::temp_file::TempFile;
+use anyhow::anyhow;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// The kinds of signatures git can verify, each with its own program.
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    OpenPgp,
+    X509,
+    Ssh,
+}
+
+/// The result of checking a signature.
+pub struct Verification {
+    pub good: bool,
+    /// What the verifying program reported for people, e.g. gpg's "Good signature from ...".
+    pub output: String,
+    /// gpg's machine-readable `[GNUPG:]` status lines; empty for SSH signatures.
+    pub status: String,
+}
+
+impl Format {
+    fn detect(signature: &str) -> Option<Self> {
+        let first_line = signature.lines().next()?;
+        match first_line {
+            "-----BEGIN PGP SIGNATURE-----" | "-----BEGIN PGP MESSAGE-----" => Some(Self::OpenPgp),
+            "-----BEGIN SIGNED MESSAGE-----" => Some(Self::X509),
+            "-----BEGIN SSH SIGNATURE-----" => Some(Self::Ssh),
+            _ => None,
+        }
+    }
+
+    /// The program from `gpg.<format>.program`, or for OpenPGP also `gpg.program`.
+    fn program(self) -> anyhow::Result<String> {
+        let (subsection, default) = match self {
+            Self::OpenPgp => ("gpg.openpgp", "gpg"),
+            Self::X509 => ("gpg.x509", "gpgsm"),
+            Self::Ssh => ("gpg.ssh", "ssh-keygen"),
+        };
+        let config = Config::get()?;
+        let mut program = config.value(subsection, "program");
+        if self == Self::OpenPgp && program.is_none() {
+            program = config.value("gpg", "program");
+        }
+        Ok(program.unwrap_or_else(|| default.to_string()))
+    }
+}
+
+/// Checks `signature` over `payload` with gpg, gpgsm or ssh-keygen depending on the kind of
+/// signature, taking the program from the `gpg.*.program` options like git.
+pub fn verify(payload: &[u8], signature: &str) -> anyhow::Result<Verification> {
+    let format = Format::detect(signature).ok_or(anyhow!("unknown signature format"))?;
+    let program = format.program()?;
+    let signature_file = TempFile::create(
+        &std::env::temp_dir(),
+        ".git_vtag_tmp",
+        "",
+        &mut signature.as_bytes(),
+    )?;
+    if format == Format::Ssh {
+        return verify_ssh(&program, payload, signature_file.path());
+    }
+    let mut command = Command::new(&program);
+    command.arg("--status-fd=1");
+    if format == Format::OpenPgp {
+        command.arg("--keyid-format=long");
+    }
+    command.arg("--verify").arg(signature_file.path()).arg("-");
+    let (succeeded, status, output) = run(&mut command, &program, payload)?;
+    // Like git, trust the status lines rather than the exit code alone.
+    let good = succeeded
+        && status
+            .lines()
+            .any(|line| line.starts_with("[GNUPG:] GOODSIG "));
+    Ok(Verification {
+        good,
+        output,
+        status,
+    })
+}
+
+/// Verifies an SSH signature against `gpg.ssh.allowedSignersFile`, looking up the principal
+/// of the signing key first, as git does.
+fn verify_ssh(
+    program: &str,
+    payload: &[u8],
+    signature_file: &Path,
+) -> anyhow::Result<Verification> {
+    let Some(allowed_signers) = Config::get()?.value("gpg.ssh", "allowedSignersFile") else {
+        Err(anyhow!(
+            "gpg.ssh.allowedSignersFile needs to be configured and exist for ssh signature \
+             verification"
+        ))?
+    };
+    let allowed_signers = object_storage::expand_home_dir(&allowed_signers);
+    let (_, principals, _) = run(
+        Command::new(program)
+            .args(["-Y", "find-principals", "-f"])
+            .arg(&allowed_signers)
+            .arg("-s")
+            .arg(signature_file),
+        program,
+        &[],
+    )?;
+    let Some(principal) = principals.lines().next().filter(|line| !line.is_empty()) else {
+        // Not an allowed signer, but say whether the signature itself is intact.
+        let (good, output, errors) = run(
+            Command::new(program)
+                .args(["-Y", "check-novalidate", "-n", "git", "-s"])
+                .arg(signature_file),
+            program,
+            payload,
+        )?;
+        let mut output = output + &errors;
+        if good {
+            output.push_str("No principal matched.\n");
+        }
+        return Ok(Verification {
+            good: false,
+            output,
+            status: String::new(),
+        });
+    };
+    let (good, output, errors) = run(
+        Command::new(program)
+            .args(["-Y", "verify", "-n", "git", "-f"])
+            .arg(&allowed_signers)
+            .args(["-I", principal, "-s"])
+            .arg(signature_file),
+        program,
+        payload,
+    )?;
+    Ok(Verification {
+        good,
+        output: output + &errors,
+        status: String::new(),
+    })
+}
+
+/// Runs `command` with `input` on stdin; returns whether it succeeded, its stdout and stderr.
+fn run(
+    command: &mut Command,
+    program: &str,
+    input: &[u8],
+) -> anyhow::Result<(bool, String, String)> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| anyhow!("cannot run {}: {}", program, err))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // A program that exits without reading its input is not an error here.
+        let _ = stdin.write_all(input);
+    }
+    let output = child.wait_with_output()?;
+    Ok((
+        output.status.success(),
+        String::from_utf8_lossy(&output.stdout).to_string(),
+        String::from_utf8_lossy(&output.stderr).to_string(),
+    ))
+}
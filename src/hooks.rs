@@ -0,0 +1,60 @@
+use crate::config::Config;
+use crate::object_storage::{self, ObjectStorage};
+use crate::trace2;
+use anyhow::anyhow;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The directory hooks are looked up in: `core.hooksPath` when set (relative paths are taken
+/// from the top of the working tree), otherwise `.git/hooks`.
+pub fn hooks_dir() -> anyhow::Result<PathBuf> {
+    match Config::get()?.value("core", "hooksPath") {
+        Some(hooks_path) => {
+            let hooks_path = object_storage::expand_home_dir(&hooks_path);
+            if hooks_path.is_absolute() {
+                Ok(hooks_path)
+            } else {
+                Ok(ObjectStorage::work_tree().join(hooks_path))
+            }
+        }
+        None => Ok(ObjectStorage::git_dir().join("hooks")),
+    }
+}
+
+/// Returns the path of the hook `name` if it exists and is executable.
+pub fn find_hook(name: &str) -> anyhow::Result<Option<PathBuf>> {
+    let path = hooks_dir()?.join(name);
+    Ok(is_executable(&path).then_some(path))
+}
+
+/// Runs the hook `name` with `args` from the top of the working tree. A missing hook counts as
+/// success; a hook exiting non-zero is reported as an error.
+pub fn run_hook(name: &str, args: &[&str]) -> anyhow::Result<()> {
+    let Some(path) = find_hook(name)? else {
+        return Ok(());
+    };
+    let mut command = Command::new(&path);
+    command
+        .args(args)
+        .current_dir(ObjectStorage::work_tree())
+        .env("GIT_DIR", ObjectStorage::git_dir());
+    let status = trace2::status(&mut command, "hook", Some(name))
+        .map_err(|err| anyhow!("cannot run {} hook: {}", name, err))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(anyhow!("{} hook exited with {}", name, status))
+    }
+}
+
+#[cfg(unix)]
+pub fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+pub fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
@@ -0,0 +1,159 @@
+use crate::object_storage::ObjectStorage;
+use crate::pathspec::wildmatch;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+/// One line of a `.gitignore` file.
+struct Pattern {
+    pattern: String,
+    negated: bool,
+    dir_only: bool,
+    /// Patterns with a slash before the end match the path relative to the directory of the
+    /// `.gitignore`; the others match the file name at any depth.
+    anchored: bool,
+}
+
+/// The patterns of one ignore file, with the directory they are relative to.
+struct PatternList {
+    base: String,
+    patterns: Vec<Pattern>,
+}
+
+/// The ignore rules that apply in one directory of the working tree: `.git/info/exclude` and
+/// the `.gitignore` files of the directory and its parents. Walks call [`IgnoreRules::enter`]
+/// for each directory they descend into.
+#[derive(Clone, Default)]
+pub struct IgnoreRules {
+    /// From lowest to highest precedence: a deeper `.gitignore` overrides a higher one.
+    lists: Vec<Rc<PatternList>>,
+}
+
+impl IgnoreRules {
+    /// The rules above the top of the working tree, from `.git/info/exclude`.
+    pub fn new() -> anyhow::Result<Self> {
+        let mut rules = Self::default();
+        let exclude = ObjectStorage::git_dir().join("info").join("exclude");
+        rules.load(&exclude, "")?;
+        Ok(rules)
+    }
+
+    /// The rules for the directory `dir`, whose path in the working tree is `prefix` (ending
+    /// in `/` unless it is the top): these rules plus its own `.gitignore`.
+    pub fn enter(&self, dir: &Path, prefix: &str) -> anyhow::Result<Self> {
+        let mut rules = self.clone();
+        rules.load(&dir.join(".gitignore"), prefix)?;
+        Ok(rules)
+    }
+
+    fn load(&mut self, file: &Path, base: &str) -> anyhow::Result<()> {
+        let content = match fs::read(file) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => Err(anyhow::anyhow!("cannot read '{}': {}", file.display(), err))?,
+        };
+        let patterns: Vec<Pattern> = String::from_utf8_lossy(&content)
+            .lines()
+            .filter_map(Pattern::parse)
+            .collect();
+        if !patterns.is_empty() {
+            self.lists.push(Rc::new(PatternList {
+                base: base.to_string(),
+                patterns,
+            }));
+        }
+        Ok(())
+    }
+
+    /// Whether `path` (relative to the top of the working tree, in the directory these rules
+    /// are for) is ignored. The last matching pattern of the deepest file that has one decides,
+    /// so `!pattern` can re-include what an earlier line excluded.
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        for list in self.lists.iter().rev() {
+            let Some(relative) = path.strip_prefix(list.base.as_str()) else {
+                continue;
+            };
+            if let Some(pattern) = list
+                .patterns
+                .iter()
+                .rev()
+                .find(|pattern| pattern.matches(relative, is_dir))
+            {
+                return !pattern.negated;
+            }
+        }
+        false
+    }
+
+    /// For a path named on the command line, the path itself or the first of its parent
+    /// directories that is ignored, if any: since walks never enter an ignored directory,
+    /// nothing below it can be re-included.
+    pub fn ignored_prefix(path: &str, is_dir: bool) -> anyhow::Result<Option<String>> {
+        let work_tree = ObjectStorage::work_tree();
+        let mut rules = Self::new()?.enter(&work_tree, "")?;
+        let components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+        for (i, _) in components.iter().enumerate() {
+            let current = components[..=i].join("/");
+            let last = i + 1 == components.len();
+            if rules.is_ignored(&current, !last || is_dir) {
+                return Ok(Some(current));
+            }
+            if !last {
+                rules = rules.enter(&work_tree.join(&current), &format!("{}/", current))?;
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl Pattern {
+    /// Parses a line of an ignore file; blank lines and comments give `None`.
+    fn parse(line: &str) -> Option<Self> {
+        let line = trim_trailing_spaces(line);
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(rest) => (true, rest),
+            // `\!` and `\#` reach wildmatch as escaped literals.
+            None => (false, line),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let pattern = line.strip_prefix('/').unwrap_or(line);
+        if pattern.is_empty() {
+            return None;
+        }
+        Some(Self {
+            pattern: pattern.to_string(),
+            negated,
+            dir_only,
+            anchored,
+        })
+    }
+
+    fn matches(&self, relative: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            wildmatch(&self.pattern, relative, true)
+        } else {
+            let name = relative.rsplit('/').next().unwrap_or(relative);
+            wildmatch(&self.pattern, name, true)
+        }
+    }
+}
+
+/// Drops trailing spaces, except one escaped with a backslash.
+fn trim_trailing_spaces(line: &str) -> &str {
+    let trimmed = line.trim_end_matches(' ');
+    if trimmed.ends_with('\\') && trimmed.len() < line.len() {
+        &line[..trimmed.len() + 1]
+    } else {
+        trimmed
+    }
+}
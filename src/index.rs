@@ -0,0 +1,544 @@
+use crate::filters::Filters;
+use crate::object_storage::{Blob, ObjectStorage, Sha, TreeEntryPermission};
+use crate::shared_repository;
+use crate::trace2;
+use crate::tree_walk::TreeWalk;
+use anyhow::anyhow;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+pub const MODE_DIRECTORY: u32 = 0o040000;
+pub const MODE_REGULAR: u32 = 0o100644;
+pub const MODE_EXECUTABLE: u32 = 0o100755;
+pub const MODE_SYMLINK: u32 = 0o120000;
+
+/// The id of the empty blob, whose entries have size 0 without being smudged.
+const EMPTY_BLOB: Sha = [
+    0xe6, 0x9d, 0xe2, 0x9b, 0xb2, 0xd1, 0xd6, 0x43, 0x4b, 0x8b, 0x29, 0xae, 0x77, 0x5a, 0xd8, 0xc2,
+    0xe4, 0x8c, 0x53, 0x91,
+];
+
+const ENTRY_FIXED_SIZE: usize = 62;
+const FLAG_EXTENDED: u16 = 0x4000;
+const NAME_MASK: u16 = 0x0fff;
+
+/// The staging area (`.git/index`), with entries sorted by path.
+pub struct Index {
+    pub entries: Vec<IndexEntry>,
+}
+
+/// An exclusively created `index.lock`, taken before the index is read for a change so that
+/// no other process changes it in between. [`Index::write`] renames the new index over the
+/// index through it; dropping an unused lock removes it.
+pub struct IndexLock {
+    lock_path: PathBuf,
+    file: Option<File>,
+}
+
+impl IndexLock {
+    pub fn acquire() -> anyhow::Result<Self> {
+        let lock_path = Index::path().with_extension("lock");
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+            .map_err(|err| match err.kind() {
+                ErrorKind::AlreadyExists => anyhow!(
+                    "Unable to create '{}': File exists.\n\nAnother git process seems to be \
+                     running in this repository, or a previous one crashed; if so, remove the \
+                     file manually.",
+                    lock_path.display()
+                ),
+                _ => anyhow!("Unable to create '{}': {}", lock_path.display(), err),
+            })?;
+        Ok(Self {
+            lock_path,
+            file: Some(file),
+        })
+    }
+}
+
+impl Drop for IndexLock {
+    fn drop(&mut self) {
+        if self.file.is_some() {
+            let _ = fs::remove_file(&self.lock_path);
+        }
+    }
+}
+
+/// One staged file with the stat data git uses to tell whether it changed since it was added.
+#[derive(Clone)]
+pub struct IndexEntry {
+    pub ctime: (u32, u32),
+    pub mtime: (u32, u32),
+    pub dev: u32,
+    pub ino: u32,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+    pub sha: Sha,
+    /// The merge stage; 0 for a normal entry.
+    pub stage: u16,
+    /// `/`-separated path relative to the top of the working tree.
+    pub path: String,
+}
+
+impl Index {
+    pub fn path() -> PathBuf {
+        ObjectStorage::git_dir().join("index")
+    }
+
+    /// Takes `index.lock` and then reads the index, for a command that changes it and writes it
+    /// back through the lock.
+    pub fn read_locked() -> anyhow::Result<(Self, IndexLock)> {
+        let lock = IndexLock::acquire()?;
+        Ok((Self::read()?, lock))
+    }
+
+    /// Reads `.git/index`; a repository without one has an empty index. Entries whose files
+    /// were modified in the same second the index was written are smudged, as git does: the
+    /// file could have changed again within that second without its stat data showing it.
+    pub fn read() -> anyhow::Result<Self> {
+        let path = Self::path();
+        if !path.exists() {
+            return Ok(Self { entries: vec![] });
+        }
+        let message = path.display().to_string();
+        trace2::region("index", "do_read_index", Some(&message), || {
+            let data = fs::read(&path)?;
+            let mut index = Self::parse(&data)?;
+            if let Some(version) = read_u32(&data, 4) {
+                trace2::data("index", "read/version", version);
+            }
+            trace2::data("index", "read/cache_nr", index.entries.len());
+            let written = stat_fields(&fs::metadata(&path)?).mtime.0;
+            for entry in &mut index.entries {
+                entry.smudge_if_racy(written);
+            }
+            Ok(index)
+        })
+    }
+
+    /// Parses the version 2 and 3 index formats. Optional extensions are skipped and dropped
+    /// on the next write; unknown required ones are an error.
+    pub fn parse(data: &[u8]) -> anyhow::Result<Self> {
+        let corrupt = || anyhow!("index file corrupt");
+        let (content, checksum) = data
+            .split_at_checked(data.len().saturating_sub(20))
+            .filter(|_| data.len() >= 12 + 20)
+            .ok_or_else(corrupt)?;
+        if ObjectStorage::hash_content(content) != checksum {
+            Err(anyhow!("index file corrupt: bad checksum"))?
+        }
+        if !content.starts_with(b"DIRC") {
+            Err(anyhow!("index file corrupt: bad signature"))?
+        }
+        let version = read_u32(content, 4).ok_or_else(corrupt)?;
+        if !matches!(version, 2 | 3) {
+            Err(anyhow!("index file version {} is not supported", version))?
+        }
+        let count = read_u32(content, 8).ok_or_else(corrupt)?;
+        let mut entries = Vec::with_capacity(count as usize);
+        let mut position = 12;
+        for _ in 0..count {
+            let field = |i: usize| read_u32(content, position + i * 4).ok_or_else(corrupt);
+            let sha: Sha = content
+                .get(position + 40..position + 60)
+                .ok_or_else(corrupt)?
+                .try_into()?;
+            let flags = read_u16(content, position + 60).ok_or_else(corrupt)?;
+            let mut path_start = position + ENTRY_FIXED_SIZE;
+            if flags & FLAG_EXTENDED != 0 {
+                if version < 3 {
+                    Err(anyhow!("index file corrupt: extended flags in version 2"))?
+                }
+                path_start += 2;
+            }
+            let path_length = content
+                .get(path_start..)
+                .ok_or_else(corrupt)?
+                .iter()
+                .position(|&b| b == 0)
+                .ok_or_else(corrupt)?;
+            let path = String::from_utf8(content[path_start..path_start + path_length].to_vec())?;
+            entries.push(IndexEntry {
+                ctime: (field(0)?, field(1)?),
+                mtime: (field(2)?, field(3)?),
+                dev: field(4)?,
+                ino: field(5)?,
+                mode: field(6)?,
+                uid: field(7)?,
+                gid: field(8)?,
+                size: field(9)?,
+                sha,
+                stage: (flags >> 12) & 0x3,
+                path,
+            });
+            let entry_size = path_start - position + path_length;
+            position += (entry_size + 8) & !7;
+        }
+        while position + 8 <= content.len() {
+            let signature = &content[position..position + 4];
+            let size = read_u32(content, position + 4).ok_or_else(corrupt)? as usize;
+            if !signature[0].is_ascii_uppercase() {
+                Err(anyhow!(
+                    "index uses the {} extension, which is not supported",
+                    String::from_utf8_lossy(signature)
+                ))?
+            }
+            position += 8 + size;
+        }
+        Ok(Self { entries })
+    }
+
+    /// Writes the index in version 2 format through `lock`. Entries for files modified no
+    /// earlier than the second the index is written in are smudged in the file, so that they
+    /// are still compared by content once the index is older than their files.
+    pub fn write(&self, lock: IndexLock) -> anyhow::Result<()> {
+        let message = Self::path().display().to_string();
+        trace2::region("index", "do_write_index", Some(&message), || {
+            self.write_file(lock)
+        })
+    }
+
+    fn write_file(&self, mut lock: IndexLock) -> anyhow::Result<()> {
+        trace2::data("index", "write/version", 2);
+        trace2::data("index", "write/cache_nr", self.entries.len());
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs() as u32);
+        let mut data = b"DIRC".to_vec();
+        data.extend_from_slice(&2u32.to_be_bytes());
+        data.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+        for entry in &self.entries {
+            let size = if entry.is_racy(now) { 0 } else { entry.size };
+            let start = data.len();
+            for field in [
+                entry.ctime.0,
+                entry.ctime.1,
+                entry.mtime.0,
+                entry.mtime.1,
+                entry.dev,
+                entry.ino,
+                entry.mode,
+                entry.uid,
+                entry.gid,
+                size,
+            ] {
+                data.extend_from_slice(&field.to_be_bytes());
+            }
+            data.extend_from_slice(&entry.sha);
+            let name_length = entry.path.len().min(NAME_MASK as usize) as u16;
+            data.extend_from_slice(&((entry.stage << 12) | name_length).to_be_bytes());
+            data.extend_from_slice(entry.path.as_bytes());
+            let padded = (data.len() - start + 8) & !7;
+            data.resize(start + padded, 0);
+        }
+        let checksum = ObjectStorage::hash_content(&data);
+        data.extend_from_slice(&checksum);
+        let mut file = lock
+            .file
+            .take()
+            .ok_or(anyhow!("index lock already released"))?;
+        file.write_all(&data)?;
+        drop(file);
+        shared_repository::adjust(&lock.lock_path)?;
+        fs::rename(&lock.lock_path, Self::path())?;
+        Ok(())
+    }
+
+    /// Builds an index matching the tree `tree`, with stat data taken from the checked-out
+    /// files in the working tree. Files that cannot be stat'ed get empty stat data, so they are
+    /// looked at again on the next `add`.
+    pub fn from_tree(tree: &Sha) -> anyhow::Result<Self> {
+        let mut entries = vec![];
+        collect_tree_entries(tree, &mut entries)?;
+        entries.sort_by(|a: &IndexEntry, b| a.path.cmp(&b.path));
+        Ok(Self { entries })
+    }
+
+    pub fn find(&self, path: &str) -> Option<&IndexEntry> {
+        self.entries
+            .binary_search_by(|entry| entry.path.as_str().cmp(path))
+            .ok()
+            .map(|position| &self.entries[position])
+    }
+
+    /// Stages `entry`, replacing the entries for the same path (all merge stages of a conflict)
+    /// and removing entries that conflict with it (a file where the new path needs a directory,
+    /// or the other way round).
+    pub fn add(&mut self, entry: IndexEntry) {
+        let as_dir = format!("{}/", entry.path);
+        self.entries.retain(|existing| {
+            existing.path != entry.path
+                && !existing.path.starts_with(&as_dir)
+                && !entry
+                    .path
+                    .strip_prefix(existing.path.as_str())
+                    .is_some_and(|rest| rest.starts_with('/'))
+        });
+        let position = self
+            .entries
+            .partition_point(|existing| existing.path < entry.path);
+        self.entries.insert(position, entry);
+    }
+
+    pub fn remove(&mut self, path: &str) {
+        self.entries.retain(|entry| entry.path != path);
+    }
+
+    /// Records a merge conflict at `path`: its entries are replaced by `versions`, the
+    /// `(stage, mode, blob)` of the common version (stage 1), ours (2) and theirs (3), leaving
+    /// out the stages a side does not have.
+    pub fn add_conflict(&mut self, path: &str, versions: &[(u16, u32, Sha)]) {
+        self.remove(path);
+        let position = self
+            .entries
+            .partition_point(|entry| entry.path.as_str() < path);
+        let entries = versions.iter().map(|&(stage, mode, sha)| IndexEntry {
+            stage,
+            ..IndexEntry::without_stat(path, mode, sha)
+        });
+        self.entries.splice(position..position, entries);
+    }
+}
+
+impl IndexEntry {
+    /// Builds an entry for the file at `file` (not following symlinks) with blob id `sha`.
+    pub fn from_file(path: &str, file: &Path, sha: Sha) -> anyhow::Result<Self> {
+        let metadata = fs::symlink_metadata(file)?;
+        Ok(Self {
+            sha,
+            stage: 0,
+            path: path.to_string(),
+            mode: file_mode(&metadata),
+            size: metadata.len() as u32,
+            ..stat_fields(&metadata)
+        })
+    }
+
+    /// An entry for a blob that is not in the working tree as it is staged, with empty stat
+    /// data so that the file is always looked at.
+    pub fn without_stat(path: &str, mode: u32, sha: Sha) -> Self {
+        Self {
+            sha,
+            path: path.to_string(),
+            mode,
+            ..stat_fields_unknown()
+        }
+    }
+
+    /// Whether the file's current stat data still matches the entry, so its content can be
+    /// assumed unchanged without hashing it again. A smudged entry never matches.
+    pub fn is_up_to_date(&self, metadata: &fs::Metadata) -> bool {
+        let stat = stat_fields(metadata);
+        (self.size != 0 || self.sha == EMPTY_BLOB)
+            && self.mtime == stat.mtime
+            && self.ctime == stat.ctime
+            && self.ino == stat.ino
+            && self.size == metadata.len() as u32
+            && self.mode == staged_file_mode(metadata, self.mode)
+    }
+
+    /// Whether the file was modified in the second `written` the index was written in, or
+    /// later, when a change to it could share its stat data; timestamps are compared to the
+    /// second like git does by default.
+    fn is_racy(&self, written: u32) -> bool {
+        self.mtime != (0, 0) && self.mtime.0 >= written
+    }
+
+    /// Sets the size of a racy entry to 0, which makes it look modified to the stat check so
+    /// that its file is compared by content, until it is staged again.
+    fn smudge_if_racy(&mut self, written: u32) {
+        if self.is_racy(written) {
+            self.size = 0;
+        }
+    }
+
+    /// Whether the working tree file at `file` still has the staged content and mode, reading
+    /// it only when the stat data changed.
+    pub fn matches_file(&self, file: &Path) -> anyhow::Result<bool> {
+        let Ok(metadata) = fs::symlink_metadata(file) else {
+            return Ok(false);
+        };
+        if self.is_up_to_date(&metadata) {
+            return Ok(true);
+        }
+        if metadata.is_dir() || self.mode != staged_file_mode(&metadata, self.mode) {
+            return Ok(false);
+        }
+        if metadata.file_type().is_symlink() || !Filters::for_file(file)?.is_empty() {
+            return Ok(hash_work_tree_file(file, &metadata, false)? == self.sha);
+        }
+        Blob::matches_file(&self.sha, file)
+    }
+}
+
+fn collect_tree_entries(tree: &Sha, entries: &mut Vec<IndexEntry>) -> anyhow::Result<()> {
+    for entry in TreeWalk::new((*tree).into()) {
+        let (path, entry) = entry?;
+        if entry.permission == TreeEntryPermission::Directory {
+            continue;
+        }
+        let mode = tree_entry_mode(entry.permission);
+        let file = ObjectStorage::work_tree().join(&path);
+        let entry = match IndexEntry::from_file(&path, &file, entry.hash) {
+            Ok(staged) => IndexEntry { mode, ..staged },
+            Err(_) => IndexEntry {
+                mode,
+                sha: entry.hash,
+                path,
+                ..stat_fields_unknown()
+            },
+        };
+        entries.push(entry);
+    }
+    Ok(())
+}
+
+/// The index mode for a tree entry.
+pub fn tree_entry_mode(permission: TreeEntryPermission) -> u32 {
+    match permission {
+        TreeEntryPermission::Directory => MODE_DIRECTORY,
+        TreeEntryPermission::RegularFile => MODE_REGULAR,
+        TreeEntryPermission::Executable => MODE_EXECUTABLE,
+        TreeEntryPermission::SymbolicLink => MODE_SYMLINK,
+    }
+}
+
+/// The tree entry kind for an index mode, `None` for modes trees can't hold here (submodules).
+pub fn mode_permission(mode: u32) -> Option<TreeEntryPermission> {
+    match mode {
+        MODE_REGULAR => Some(TreeEntryPermission::RegularFile),
+        MODE_EXECUTABLE => Some(TreeEntryPermission::Executable),
+        MODE_SYMLINK => Some(TreeEntryPermission::SymbolicLink),
+        _ => None,
+    }
+}
+
+/// What git stores for the file at `file`: its content as its attributes convert it, or the
+/// target of a symlink.
+pub fn work_tree_content(file: &Path, metadata: &fs::Metadata) -> anyhow::Result<Vec<u8>> {
+    if metadata.file_type().is_symlink() {
+        Ok(fs::read_link(file)?
+            .to_string_lossy()
+            .into_owned()
+            .into_bytes())
+    } else {
+        Ok(Filters::for_file(file)?.to_git(fs::read(file)?))
+    }
+}
+
+/// The blob id of the file at `file`, optionally writing the blob. Regular files that need no
+/// conversion are streamed rather than read into memory.
+pub fn hash_work_tree_file(
+    file: &Path,
+    metadata: &fs::Metadata,
+    write: bool,
+) -> anyhow::Result<Sha> {
+    if metadata.file_type().is_symlink() || !Filters::for_file(file)?.is_empty() {
+        let content = work_tree_content(file, metadata)?;
+        ObjectStorage::hash_object("blob", &content, write, false)
+    } else {
+        Blob::hash_file(file, write)
+    }
+}
+
+/// The index mode for a file that is staged with mode `staged`: as [`file_mode`] gives it,
+/// except that with `core.symlinks` false a plain file staged as a symlink stays one, as git
+/// keeps it, since checkout writes symlinks there as plain files holding the target.
+pub fn staged_file_mode(metadata: &fs::Metadata, staged: u32) -> u32 {
+    if staged == MODE_SYMLINK
+        && metadata.is_file()
+        && ObjectStorage::core_symlinks_disabled().unwrap_or(false)
+    {
+        return MODE_SYMLINK;
+    }
+    file_mode(metadata)
+}
+
+/// The index mode for a file: symlink, executable or regular.
+pub fn file_mode(metadata: &fs::Metadata) -> u32 {
+    if metadata.file_type().is_symlink() {
+        return MODE_SYMLINK;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o111 != 0 {
+            return MODE_EXECUTABLE;
+        }
+    }
+    MODE_REGULAR
+}
+
+#[cfg(unix)]
+fn stat_fields(metadata: &fs::Metadata) -> IndexEntry {
+    use std::os::unix::fs::MetadataExt;
+    IndexEntry {
+        ctime: (metadata.ctime() as u32, metadata.ctime_nsec() as u32),
+        mtime: (metadata.mtime() as u32, metadata.mtime_nsec() as u32),
+        dev: metadata.dev() as u32,
+        ino: metadata.ino() as u32,
+        mode: 0,
+        uid: metadata.uid(),
+        gid: metadata.gid(),
+        size: 0,
+        sha: [0; 20],
+        stage: 0,
+        path: String::new(),
+    }
+}
+
+#[cfg(not(unix))]
+fn stat_fields(metadata: &fs::Metadata) -> IndexEntry {
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|since| (since.as_secs() as u32, since.subsec_nanos()))
+        .unwrap_or_default();
+    IndexEntry {
+        ctime: mtime,
+        mtime,
+        dev: 0,
+        ino: 0,
+        mode: 0,
+        uid: 0,
+        gid: 0,
+        size: 0,
+        sha: [0; 20],
+        stage: 0,
+        path: String::new(),
+    }
+}
+
+fn stat_fields_unknown() -> IndexEntry {
+    IndexEntry {
+        ctime: (0, 0),
+        mtime: (0, 0),
+        dev: 0,
+        ino: 0,
+        mode: 0,
+        uid: 0,
+        gid: 0,
+        size: 0,
+        sha: [0; 20],
+        stage: 0,
+        path: String::new(),
+    }
+}
+
+fn read_u32(data: &[u8], start: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(start..start + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_u16(data: &[u8], start: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        data.get(start..start + 2)?.try_into().ok()?,
+    ))
+}
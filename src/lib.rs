@@ -0,0 +1,116 @@
+//! A Git implementation, usable as a library as well as through the `codecrafters-git`
+//! command, which is a thin layer over [`run_program`] that applies `-C`, `--git-dir` and
+//! `--work-tree`.
+//!
+//! [`Repository`] is the entry point of the library API: it finds, opens or creates a
+//! repository, reads and writes its objects, identified by [`Oid`]s, and runs commands. A
+//! process may have several repositories open. [`TreeWalk`] goes through everything in a tree and [`refglob`] matches ref names against patterns. Programs
+//! that show progress or react to ref updates implement the traits in [`progress`], those that
+//! watch how commands perform the one in [`usage`], and can stop long operations through
+//! [`cancel`].
+//!
+//! ```
+//! use codecrafters_git::{Commit, Repository, Signature, Tree, TreeEntry, TreeEntryPermission};
+//!
+//! # let dir = std::env::temp_dir().join(format!("library-{}", std::process::id()));
+//! let repo = Repository::init(&dir)?;
+//! let blob = repo.write_blob(b"hello\n")?;
+//! assert_eq!(blob.to_string(), "ce013625030ba8dba906f756967f9e9ca394464a");
+//! assert_eq!(repo.find_blob(blob)?.content(), b"hello\n");
+//!
+//! let tree = repo.write_tree(&Tree {
+//!     entries: vec![TreeEntry {
+//!         permission: TreeEntryPermission::RegularFile,
+//!         name: "hello.txt".to_string(),
+//!         hash: *blob.as_bytes(),
+//!     }],
+//! })?;
+//! let author = Signature {
+//!     name: "A U Thor".to_string(),
+//!     email: "author@example.com".to_string(),
+//!     time: 1_700_000_000,
+//!     tz_offset: 60,
+//! };
+//! let commit = repo.write_commit(&Commit {
+//!     tree: *tree.as_bytes(),
+//!     parents: vec![],
+//!     author: author.clone(),
+//!     committer: author,
+//!     message: "Say hello".to_string(),
+//! })?;
+//!
+//! let history: Vec<String> = repo
+//!     .commits(commit)
+//!     .map(|entry| entry.map(|(_, commit)| commit.message))
+//!     .collect::<anyhow::Result<_>>()?;
+//! assert_eq!(history, ["Say hello"]);
+//! # std::fs::remove_dir_all(&dir)?;
+//! # anyhow::Ok(())
+//! ```
+
+mod archive;
+mod attributes;
+mod background;
+mod backup;
+pub mod cancel;
+mod chunk_format;
+mod chunking;
+mod cli;
+mod commands;
+mod commit_graph;
+mod commit_walk;
+mod compat_map;
+mod completions;
+mod compressibility;
+mod config;
+mod context;
+mod diff;
+mod diffstat;
+mod dry_run;
+mod filters;
+mod format;
+mod fsck;
+mod gc;
+mod gpg;
+mod hooks;
+mod ignore;
+mod index;
+mod mail;
+mod merge;
+mod merge_file;
+mod merge_message;
+mod merge_tools;
+pub mod object_storage;
+mod oid_set;
+mod patch;
+mod patch_mode;
+mod pathspec;
+mod pretty;
+mod program;
+pub mod progress;
+mod promisor;
+mod quarantine;
+pub mod refglob;
+mod refs;
+mod repack;
+pub mod repository;
+mod revision;
+mod search_index;
+mod sequencer;
+mod shared_repository;
+mod temp_file;
+mod trace2;
+mod transport;
+mod tree_walk;
+pub mod usage;
+mod work_tree;
+
+pub use cancel::{CancellationToken, Cancelled};
+pub use object_storage::{
+    Blob, Commit, GitObject, Oid, Signature, Tag, Tree, TreeEntry, TreeEntryPermission,
+};
+pub use program::run_program;
+pub use progress::{EventSink, Phase, Progress, ProgressReporter};
+pub use repository::{CommandExit, Commits, MergeOutcome, Repository};
+pub use tree_walk::TreeWalk;
+pub use usage::{CommandUsage, UsageRecorder};
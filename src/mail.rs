@@ -0,0 +1,495 @@
+//! Patches sent as mail, as `format-patch` writes them and mailing lists and GitHub's `.patch`
+//! pages serve them, for `am`. An mbox is split into messages at their `From ` lines, like
+//! `git mailsplit`, and each message gives its author, date and commit message and the patch,
+//! like `git mailinfo`: headers are unfolded and their RFC 2047 encoded words decoded, bodies
+//! sent as quoted-printable or base64 are decoded, and the text parts of a multipart message
+//! are read one after the other. The other way round, headers are encoded for `format-patch`
+//! the way git encodes them.
+
+use anyhow::anyhow;
+
+/// What a message says about the commit its patch makes.
+pub struct MailInfo {
+    pub author_name: String,
+    pub author_email: String,
+    /// The `Date` header as seconds and minutes east of UTC, if it has one that parses.
+    pub date: Option<(i64, i32)>,
+    /// The subject without `Re:` and `[PATCH ...]` prefixes.
+    pub subject: String,
+    /// The message body up to the `---` line or the patch.
+    pub body: String,
+    pub patch: Vec<u8>,
+}
+
+/// Splits an mbox into its messages, without their `From ` lines. Input that does not start
+/// with one is a single message. Unless `keep_cr`, lines lose the CR of a CRLF ending.
+pub fn split_mbox(data: &[u8], keep_cr: bool) -> Vec<Vec<u8>> {
+    let mut messages: Vec<Vec<u8>> = vec![];
+    let mut lines = data.split_inclusive(|&byte| byte == b'\n').peekable();
+    if lines.peek().is_some_and(|line| !is_from_line(line)) {
+        messages.push(vec![]);
+    }
+    for line in lines {
+        if is_from_line(line) {
+            messages.push(vec![]);
+            continue;
+        }
+        let message = messages.last_mut().unwrap();
+        match line.strip_suffix(b"\r\n").filter(|_| !keep_cr) {
+            Some(line) => {
+                message.extend_from_slice(line);
+                message.push(b'\n');
+            }
+            None => message.extend_from_slice(line),
+        }
+    }
+    messages.retain(|message| !message.iter().all(u8::is_ascii_whitespace));
+    messages
+}
+
+/// Whether `line` starts a message in an mbox: `From ` and, like git checks it, something
+/// ending in a time and a year, such as `From 1a2b3c Mon Sep 17 00:00:00 2001`.
+fn is_from_line(line: &[u8]) -> bool {
+    if line.len() < 20 || !line.starts_with(b"From ") {
+        return false;
+    }
+    let Some(colon) = line.iter().rposition(|&byte| byte == b':') else {
+        return false;
+    };
+    if colon < 9 {
+        return false;
+    }
+    let digits = [colon - 4, colon - 2, colon - 1, colon + 1, colon + 2];
+    if !digits
+        .iter()
+        .all(|&i| line.get(i).is_some_and(u8::is_ascii_digit))
+    {
+        return false;
+    }
+    let year: String = String::from_utf8_lossy(&line[colon + 3..])
+        .trim_start()
+        .chars()
+        .take_while(char::is_ascii_digit)
+        .collect();
+    year.parse::<u32>().is_ok_and(|year| year > 90)
+}
+
+impl MailInfo {
+    /// Reads a message, one of [`split_mbox`]'s. Headers at the start of the body (`From:`,
+    /// `Subject:` and `Date:` lines followed by a blank line) take precedence over the mail's,
+    /// as when a patch by someone else is sent on.
+    pub fn parse(message: &[u8]) -> anyhow::Result<Self> {
+        let (headers, body) = split_headers(message);
+        let mut text = vec![];
+        decode_part(&headers, body, &mut text);
+
+        let mut from = header(&headers, "From");
+        let mut subject = header(&headers, "Subject");
+        let mut date = header(&headers, "Date");
+        let mut body = text.as_slice();
+        while body.first() == Some(&b'\n') {
+            body = &body[1..];
+        }
+        let in_body = ["From:", "Subject:", "Date:"];
+        if in_body.iter().any(|name| starts_with_header(body, name)) {
+            let (in_body_headers, rest) = split_headers(body);
+            for (name, value) in in_body_headers {
+                let value = Some(decode_words(&value));
+                match name.to_ascii_lowercase().as_str() {
+                    "from" => from = value,
+                    "subject" => subject = value,
+                    "date" => date = value,
+                    _ => {}
+                }
+            }
+            body = rest;
+        }
+
+        let from = from.ok_or(anyhow!("Patch does not have a valid e-mail address."))?;
+        let (author_name, author_email) = parse_address(&from);
+        if author_email.is_empty() {
+            Err(anyhow!("Patch does not have a valid e-mail address."))?
+        }
+        let patch_start = patch_start(body);
+        Ok(Self {
+            author_name,
+            author_email,
+            date: date.and_then(|date| crate::object_storage::parse_date(&date).ok()),
+            subject: clean_subject(&subject.unwrap_or_default()),
+            body: String::from_utf8_lossy(&body[..patch_start]).into_owned(),
+            patch: body[patch_start..].to_vec(),
+        })
+    }
+}
+
+/// Splits off the unfolded headers, up to the first blank line, from the body.
+fn split_headers(message: &[u8]) -> (Vec<(String, String)>, &[u8]) {
+    let mut headers: Vec<(String, String)> = vec![];
+    let mut rest = message;
+    while !rest.is_empty() {
+        let end = rest.iter().position(|&byte| byte == b'\n');
+        let (line, next) = match end {
+            Some(end) => (&rest[..end], &rest[end + 1..]),
+            None => (rest, &rest[rest.len()..]),
+        };
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() {
+            return (headers, next);
+        }
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push_str(line);
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        } else {
+            // Not a header after all: the body starts here.
+            return (headers, rest);
+        }
+        rest = next;
+    }
+    (headers, rest)
+}
+
+fn starts_with_header(body: &[u8], name: &str) -> bool {
+    body.get(..name.len())
+        .is_some_and(|start| start.eq_ignore_ascii_case(name.as_bytes()))
+}
+
+/// The decoded value of the first header called `name`.
+fn header(headers: &[(String, String)], name: &str) -> Option<String> {
+    headers
+        .iter()
+        .find(|(header, _)| header.eq_ignore_ascii_case(name))
+        .map(|(_, value)| decode_words(value))
+}
+
+/// A parameter of a header such as `Content-Type: text/plain; charset=UTF-8`.
+fn parameter(value: &str, name: &str) -> Option<String> {
+    value.split(';').skip(1).find_map(|parameter| {
+        let (key, value) = parameter.split_once('=')?;
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then(|| value.trim().trim_matches('"').to_string())
+    })
+}
+
+/// Appends the decoded text of a part, with `headers`, to `text`; each text part of a
+/// multipart one is appended in turn.
+fn decode_part(headers: &[(String, String)], body: &[u8], text: &mut Vec<u8>) {
+    let content_type = header(headers, "Content-Type").unwrap_or_default();
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    if media_type.starts_with("multipart/")
+        && let Some(boundary) = parameter(&content_type, "boundary")
+    {
+        let delimiter = format!("--{}", boundary);
+        let mut parts: Vec<Vec<u8>> = vec![];
+        let mut in_part = false;
+        for line in body.split_inclusive(|&byte| byte == b'\n') {
+            let trimmed = String::from_utf8_lossy(line);
+            let trimmed = trimmed.trim_end();
+            if trimmed == format!("{}--", delimiter) {
+                break;
+            }
+            if trimmed == delimiter {
+                parts.push(vec![]);
+                in_part = true;
+            } else if in_part {
+                parts.last_mut().unwrap().extend_from_slice(line);
+            }
+        }
+        for part in parts {
+            let (part_headers, part_body) = split_headers(&part);
+            decode_part(&part_headers, part_body, text);
+        }
+        return;
+    }
+    if !(media_type.is_empty() || media_type.starts_with("text/")) {
+        return;
+    }
+    let encoding = header(headers, "Content-Transfer-Encoding").unwrap_or_default();
+    let decoded = match encoding.to_ascii_lowercase().as_str() {
+        "quoted-printable" => decode_quoted_printable(body, false),
+        "base64" => decode_base64(body),
+        _ => body.to_vec(),
+    };
+    match parameter(&content_type, "charset") {
+        Some(charset) if is_latin1(&charset) => {
+            text.extend_from_slice(latin1_to_utf8(&decoded).as_bytes())
+        }
+        _ => text.extend_from_slice(&decoded),
+    }
+}
+
+fn is_latin1(charset: &str) -> bool {
+    ["iso-8859-1", "latin1", "us-ascii"]
+        .iter()
+        .any(|name| charset.eq_ignore_ascii_case(name))
+}
+
+fn latin1_to_utf8(bytes: &[u8]) -> String {
+    bytes.iter().map(|&byte| byte as char).collect()
+}
+
+/// Decodes the RFC 2047 encoded words in a header, `=?UTF-8?q?caf=C3=A9?=` and
+/// `=?UTF-8?b?Y2Fmw6k=?=`; whitespace between two encoded words is dropped.
+fn decode_words(value: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = value;
+    let mut after_word = false;
+    while let Some(start) = rest.find("=?") {
+        let (before, word) = rest.split_at(start);
+        let Some((text, length)) = decode_word(word) else {
+            decoded.push_str(&rest[..start + 2]);
+            rest = &rest[start + 2..];
+            after_word = false;
+            continue;
+        };
+        if !(after_word && before.trim().is_empty()) {
+            decoded.push_str(before);
+        }
+        decoded.push_str(&text);
+        rest = &word[length..];
+        after_word = true;
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Decodes the encoded word `word` starts with, returning the text and the word's length.
+fn decode_word(word: &str) -> Option<(String, usize)> {
+    let inner = word.strip_prefix("=?")?;
+    let (charset, inner) = inner.split_once('?')?;
+    let (encoding, inner) = inner.split_once('?')?;
+    let end = inner.find("?=")?;
+    let encoded = &inner.as_bytes()[..end];
+    let bytes = match encoding {
+        "q" | "Q" => decode_quoted_printable(encoded, true),
+        "b" | "B" => decode_base64(encoded),
+        _ => return None,
+    };
+    let text = if is_latin1(charset) {
+        latin1_to_utf8(&bytes)
+    } else {
+        String::from_utf8_lossy(&bytes).into_owned()
+    };
+    Some((text, word.len() - inner.len() + end + 2))
+}
+
+/// Decodes quoted-printable, where `=` and two hex digits stand for a byte and a line ending
+/// in `=` continues on the next. In a header, `_` stands for a space.
+fn decode_quoted_printable(encoded: &[u8], header: bool) -> Vec<u8> {
+    let mut decoded = vec![];
+    let mut index = 0;
+    while index < encoded.len() {
+        let byte = encoded[index];
+        index += 1;
+        match byte {
+            b'_' if header => decoded.push(b' '),
+            b'=' => {
+                let rest = &encoded[index..];
+                if rest.starts_with(b"\r\n") {
+                    index += 2;
+                } else if rest.starts_with(b"\n") {
+                    index += 1;
+                } else if let Some(value) = rest
+                    .get(..2)
+                    .and_then(|hex| std::str::from_utf8(hex).ok())
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                {
+                    decoded.push(value);
+                    index += 2;
+                } else {
+                    decoded.push(b'=');
+                }
+            }
+            byte => decoded.push(byte),
+        }
+    }
+    decoded
+}
+
+/// Decodes base64, ignoring line breaks and anything else outside its alphabet.
+fn decode_base64(encoded: &[u8]) -> Vec<u8> {
+    let value = |byte: u8| match byte {
+        b'A'..=b'Z' => Some(byte - b'A'),
+        b'a'..=b'z' => Some(byte - b'a' + 26),
+        b'0'..=b'9' => Some(byte - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    };
+    let mut decoded = vec![];
+    let (mut bits, mut count) = (0u32, 0);
+    for byte in encoded.iter().take_while(|&&byte| byte != b'=') {
+        let Some(value) = value(*byte) else {
+            continue;
+        };
+        bits = bits << 6 | value as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            decoded.push((bits >> count) as u8);
+        }
+    }
+    decoded
+}
+
+/// `A U Thor <author@example.com>`, `"Thor, A U" <author@example.com>`,
+/// `author@example.com (A U Thor)` or a bare address, as name and address. Without a name,
+/// the address is used for both, as git does.
+fn parse_address(from: &str) -> (String, String) {
+    let from = from.trim();
+    let (name, email) = if let Some((name, rest)) = from.split_once('<') {
+        let email = rest.split('>').next().unwrap_or_default();
+        (name.trim().to_string(), email.trim().to_string())
+    } else if let Some((email, rest)) = from.split_once('(') {
+        let name = rest.trim_end().trim_end_matches(')');
+        (name.trim().to_string(), email.trim().to_string())
+    } else {
+        (String::new(), from.to_string())
+    };
+    let name = name
+        .strip_prefix('"')
+        .and_then(|name| name.strip_suffix('"'))
+        .map(|name| name.replace("\\\"", "\"").replace("\\\\", "\\"))
+        .unwrap_or(name);
+    if name.is_empty() {
+        return (email.clone(), email);
+    }
+    (name, email)
+}
+
+/// Strips what mail and `format-patch` put in front of the commit's subject: `Re:` and
+/// bracketed prefixes like `[PATCH v2 1/3]`, along with the whitespace around them.
+fn clean_subject(subject: &str) -> String {
+    let mut subject = subject.trim();
+    loop {
+        if let Some(rest) = subject
+            .get(..3)
+            .filter(|prefix| prefix.eq_ignore_ascii_case("re:"))
+            .map(|_| &subject[3..])
+        {
+            subject = rest.trim_start();
+        } else if let Some(rest) = subject.strip_prefix('[')
+            && let Some((_, rest)) = rest.split_once(']')
+        {
+            subject = rest.trim_start();
+        } else {
+            break;
+        }
+    }
+    subject.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Where the patch starts in a body: at the `---` line that separates it from the message,
+/// or at the first `diff -` or `Index: ` line.
+fn patch_start(body: &[u8]) -> usize {
+    let mut offset = 0;
+    for line in body.split_inclusive(|&byte| byte == b'\n') {
+        let trimmed = line.trim_ascii_end();
+        if trimmed == b"---" || line.starts_with(b"diff -") || line.starts_with(b"Index: ") {
+            return offset;
+        }
+        offset += line.len();
+    }
+    body.len()
+}
+
+/// The longest header line, per RFC 2822, before a subject is folded.
+const MAX_HEADER_LENGTH: usize = 78;
+/// The longest encoded word, per RFC 2047.
+const MAX_ENCODED_LENGTH: usize = 76;
+
+/// Whether a header value has to be sent as RFC 2047 encoded words: when it is not ASCII, or
+/// could be mistaken for an encoded word.
+fn needs_encoding(text: &str) -> bool {
+    !text.is_ascii() || text.contains("=?")
+}
+
+/// `text` as `=?UTF-8?q?...?=` encoded words, continuing a header line that is `line_length`
+/// long so far. Words are split between characters to keep lines short, and in an address
+/// only letters, digits and `!*+-/` are sent as they are.
+fn encode_words(text: &str, line_length: usize, address: bool) -> String {
+    let mut encoded = String::from("=?UTF-8?q?");
+    let mut line_length = line_length + "=?UTF-8?q?".len();
+    for character in text.chars() {
+        let mut bytes = [0; 4];
+        let bytes = character.encode_utf8(&mut bytes).as_bytes();
+        let special = bytes.len() > 1 || is_special(bytes[0], address);
+        let piece: String = if special {
+            bytes.iter().map(|byte| format!("={:02X}", byte)).collect()
+        } else {
+            character.to_string()
+        };
+        if line_length + piece.len() + 2 > MAX_ENCODED_LENGTH {
+            encoded.push_str("?=\n =?UTF-8?q?");
+            line_length = " =?UTF-8?q?".len();
+        }
+        line_length += piece.len();
+        encoded.push_str(&piece);
+    }
+    encoded.push_str("?=");
+    encoded
+}
+
+fn is_special(byte: u8, address: bool) -> bool {
+    if !byte.is_ascii_graphic() || matches!(byte, b'=' | b'?' | b'_') {
+        return true;
+    }
+    address && !(byte.is_ascii_alphanumeric() || b"!*+-/".contains(&byte))
+}
+
+/// The `From:`, `To:` or `Cc:` form of `name <email>`: an encoded name if it is not ASCII, a
+/// quoted one if it has characters an unquoted name may not have, such as a comma.
+pub fn format_address(header: &str, name: &str, email: &str) -> String {
+    let prefix = format!("{}: ", header);
+    let name = if needs_encoding(name) {
+        encode_words(name, prefix.len(), true)
+    } else if name
+        .bytes()
+        .any(|byte| !(byte.is_ascii_alphanumeric() || b" !#$%&'*+-/=?^_`{|}~".contains(&byte)))
+    {
+        format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        name.to_string()
+    };
+    format!("{}{} <{}>", prefix, name, email)
+}
+
+/// The `Subject:` header for `subject` after `prefix`, e.g. `[PATCH 1/2]`: encoded if it is
+/// not ASCII, folded between words if it is too long.
+pub fn format_subject(prefix: &str, subject: &str) -> String {
+    let mut header = String::from("Subject: ");
+    if !prefix.is_empty() {
+        header.push_str(prefix);
+        header.push(' ');
+    }
+    if needs_encoding(subject) {
+        let line_length = header.len();
+        header.push_str(&encode_words(subject, line_length, false));
+        return header;
+    }
+    let mut line_length = header.len();
+    let mut first = true;
+    for word in subject.split(' ') {
+        if !first && line_length + 1 + word.len() > MAX_HEADER_LENGTH {
+            header.push('\n');
+            line_length = 0;
+        }
+        if !first {
+            header.push(' ');
+            line_length += 1;
+        }
+        header.push_str(word);
+        line_length += word.len();
+        first = false;
+    }
+    header
+}
@@ -1,119 +1,60 @@
-mod object_storage;
+//! The `codecrafters-git` command. The options before the command name that change where it
+//! runs, `-C`, `--git-dir` and `--work-tree`, change the process's working directory and
+//! environment, so they are applied here; [`codecrafters_git::run_program`] runs the rest.
 
-use crate::object_storage::{Blob, GitObject, ObjectStorage};
-#[allow(unused_imports)]
+use anyhow::anyhow;
 use std::env;
-#[allow(unused_imports)]
-use std::fs;
-use std::path::PathBuf;
-use std::string::String;
-
-fn main() -> anyhow::Result<()> {
-    let args: Vec<String> = env::args().collect();
-    if args.len() > 1 {
-        if args[1] == "init" {
-            init_cwd()?;
-        } else if args[1] == "cat-file" {
-            if args.len() > 3 && args[2] == "-p" {
-                let hash = args[3].to_string();
-                cat_file(hash.as_str())?;
-            }
-        } else if args[1] == "hash-object" {
-            if args.len() > 3 && args[2] == "-w" {
-                let path = args[3].to_string();
-                hash_object(path.as_str())?
-            }
-        } else if args[1] == "ls-tree" {
-            if args.len() > 2 {
-                let name_only = args[2] == "--name-only";
-                let hash = args.last().unwrap().to_string();
-                ls_tree(hash.as_str(), name_only)?;
-            }
-        } else if args[1] == "write-tree" {
-            write_tree_cwd()?;
-        } else if args[1] == "commit-tree" {
-            if args.len() > 6 {
-                let tree_sha = args[2].as_str();
-                // -p
-                let parent = args[4].as_str();
-                // -m
-                let message = args[6].as_str();
-                commit_tree(tree_sha, parent, message)?;
-            }
-        } else if args[1] == "checkout" {
-            if args.len() > 2 {
-                let sha = args[2].as_str();
-                checkout(sha)?;
-            }
-        } else {
-            println!("unknown command: {}", args[1]);
+use std::path;
+use std::process;
+
+/// Exit code git uses for fatal errors.
+const FATAL_EXIT_CODE: i32 = 128;
+
+fn main() {
+    let args = match apply_location_options(env::args().collect()) {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("fatal: {:#}", err);
+            process::exit(FATAL_EXIT_CODE);
         }
-    } else {
-        println!("Usage init or cat-file");
-    }
-    Ok(())
-}
-
-fn ls_tree(hash: &str, name_only: bool) -> anyhow::Result<()> {
-    let file_path = ObjectStorage::get_path_for_hash(hash)?;
-    if let GitObject::Tree(tree) = GitObject::from_file_path(&file_path)? {
-        for entry in tree.entries {
-            if name_only {
-                println!("{}", entry.name)
-            } else {
-                println!(
-                    "{} {} {}",
-                    entry.permission.to_string_repr(),
-                    entry.name,
-                    entry.to_hash_hex_string()
-                )
-            }
+    };
+    process::exit(codecrafters_git::run_program(args));
+}
+
+/// Applies and removes the `-C`, `--git-dir` and `--work-tree` options before the command
+/// name, leaving `--dry-run` among them for `run_program`. Like git, the paths are passed on
+/// in `GIT_DIR` and `GIT_WORK_TREE`, so that hooks and shell aliases see them too.
+fn apply_location_options(args: Vec<String>) -> anyhow::Result<Vec<String>> {
+    let mut remaining = args.into_iter();
+    let mut result: Vec<String> = remaining.next().into_iter().collect();
+    while let Some(arg) = remaining.next() {
+        if arg == "--dry-run" {
+            result.push(arg);
+            continue;
+        }
+        let (option, inline_value) = match arg.split_once('=') {
+            Some((option, value)) => (option, Some(value.to_string())),
+            None => (arg.as_str(), None),
+        };
+        if !matches!(option, "-C" | "--git-dir" | "--work-tree") {
+            result.push(arg);
+            break;
+        }
+        let value = match inline_value {
+            Some(value) => value,
+            None => remaining
+                .next()
+                .ok_or(anyhow!("option {} requires a path", option))?,
+        };
+        match option {
+            "-C" => env::set_current_dir(&value)
+                .map_err(|err| anyhow!("cannot change to '{}': {}", value, err))?,
+            // The paths are relative to the directory `-C` changed to, if it came first.
+            // SAFETY: no other threads have been started yet.
+            "--git-dir" => unsafe { env::set_var("GIT_DIR", path::absolute(&value)?) },
+            _ => unsafe { env::set_var("GIT_WORK_TREE", path::absolute(&value)?) },
         }
-    } else {
-        eprintln!("not a tree object");
-    }
-    Ok(())
-}
-
-fn write_tree_cwd() -> anyhow::Result<()> {
-    let hash = ObjectStorage::write_tree_cwd()?;
-    let hash_string = ObjectStorage::sha_to_hex_string(&hash);
-    println!("{}", &hash_string);
-    Ok(())
-}
-
-fn commit_tree(tree_sha: &str, parent_sha: &str, commit: &str) -> anyhow::Result<()> {
-    let tree_sha = ObjectStorage::hex_string_to_sha(tree_sha)?;
-    let parent_sha = ObjectStorage::hex_string_to_sha(parent_sha)?;
-    let sha = ObjectStorage::commit_tree(&tree_sha, &parent_sha, commit)?;
-    println!("{}", ObjectStorage::sha_to_hex_string(&sha));
-    Ok(())
-}
-
-fn cat_file(hash: &str) -> anyhow::Result<()> {
-    let file_path = ObjectStorage::get_path_for_hash(hash)?;
-    if let GitObject::Blob(blob) = GitObject::from_file_path(&file_path)? {
-        print!("{}", &blob.as_str()?)
     }
-    Ok(())
-}
-
-fn hash_object(path: &str) -> anyhow::Result<()> {
-    let path = PathBuf::from(path);
-    let blob = Blob::new_with_file_path(&path)?;
-    let sha = blob.write_to_object_storage()?;
-    println!("{}", ObjectStorage::sha_to_hex_string(&sha));
-    Ok(())
-}
-
-fn init_cwd() -> anyhow::Result<()> {
-    ObjectStorage::init_cwd()?;
-    println!("Initialized git directory");
-    Ok(())
-}
-
-fn checkout(sha: &str) -> anyhow::Result<()> {
-    let sha = ObjectStorage::hex_string_to_sha(sha)?;
-    ObjectStorage::checkout(&sha)?;
-    Ok(())
+    result.extend(remaining);
+    Ok(result)
 }
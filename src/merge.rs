@@ -0,0 +1,420 @@
+use crate::commit_walk;
+use crate::config::Config;
+use crate::diff::{self, Snapshot};
+use crate::index::{self, Index, IndexEntry};
+use crate::merge_file::{self, Labels};
+use crate::object_storage::{ObjectStorage, Sha, TreeEntryPermission};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// The version of a file one side of a merge has.
+pub type Version = (TreeEntryPermission, Sha);
+
+/// A path the merge could not resolve.
+pub struct Conflict {
+    pub path: String,
+    /// `content`, `add/add`, `modify/delete` or `file/directory`, as in git's `CONFLICT (...)`
+    /// lines.
+    pub kind: &'static str,
+    /// The common, our and their version; a side that deleted the file has none.
+    pub base: Option<Version>,
+    pub ours: Option<Version>,
+    pub theirs: Option<Version>,
+    /// What goes into the working tree: the text merge with conflict markers, or `None` to
+    /// leave our version (or theirs where we have none) as it is.
+    pub content: Option<Vec<u8>>,
+    /// For a `file location` conflict, where the side that added the file put it.
+    pub moved_from: Option<String>,
+}
+
+/// A file one side added inside a directory the other side renamed, moved into the directory's
+/// new name.
+pub struct MovedFile {
+    pub from: String,
+    pub to: String,
+    /// Whether we added the file and they renamed the directory, rather than the other way
+    /// round.
+    pub added_by_us: bool,
+}
+
+/// What `merge.directoryRenames` says to do with a file added inside a directory the other side
+/// renamed: leave it where it was added, move it and record a `file location` conflict so that
+/// the user confirms the move (the default), or just move it.
+#[derive(Clone, Copy, PartialEq)]
+enum DirectoryRenames {
+    Off,
+    Conflict,
+    Move,
+}
+
+/// The outcome of merging two trees.
+pub struct TreeMerge {
+    /// The merged files; a conflicted path has our version here, or theirs where we have
+    /// none, so that the snapshot is what the working tree holds around the conflicts. A file
+    /// where the other side has a directory is left out.
+    pub files: Snapshot,
+    pub conflicts: Vec<Conflict>,
+    /// The paths whose content was merged line by line, conflicting or not, for
+    /// `Auto-merging <path>`.
+    pub auto_merged: Vec<String>,
+    /// The files moved along with a renamed directory without a conflict, for `Path updated`.
+    pub moved: Vec<MovedFile>,
+}
+
+/// Merges the trees `ours` and `theirs`, file by file, relative to their common ancestor
+/// `base` (`None` when they have no common history). A file only one side changed takes that
+/// side's version; one both sides changed is merged with [`merge_file::merge_file`] and its
+/// result written as a blob. Nothing is read from or written to the working tree or index.
+///
+/// When one side renamed a directory, files the other side added to it are moved along, as
+/// `merge.directoryRenames` says. Like git, a directory counts as renamed when the side left
+/// nothing in it and most of its files that moved went to the same new directory; only files
+/// moved without changes are recognised as moved.
+pub fn merge_trees(
+    base: Option<&Sha>,
+    ours: &Sha,
+    theirs: &Sha,
+    labels: &Labels,
+) -> anyhow::Result<TreeMerge> {
+    let base_files = diff::tree_snapshot(base)?;
+    let mut our_files = diff::tree_snapshot(Some(ours))?;
+    let mut their_files = diff::tree_snapshot(Some(theirs))?;
+    let directory_renames = match Config::get()?.value("merge", "directoryRenames") {
+        Some(value) => match value.to_lowercase().as_str() {
+            "true" | "yes" | "on" | "1" => DirectoryRenames::Move,
+            "false" | "no" | "off" | "0" => DirectoryRenames::Off,
+            _ => DirectoryRenames::Conflict,
+        },
+        None => DirectoryRenames::Conflict,
+    };
+    let mut moved = vec![];
+    if directory_renames != DirectoryRenames::Off {
+        let mut our_renames = renamed_directories(&base_files, &our_files);
+        let mut their_renames = renamed_directories(&base_files, &their_files);
+        // A directory both sides renamed is a rename/rename of each file in it, not handled
+        // here.
+        our_renames.retain(|dir, _| their_renames.remove(dir).is_none());
+        moved.extend(move_into_renamed(
+            &base_files,
+            &mut their_files,
+            &our_renames,
+            false,
+        ));
+        moved.extend(move_into_renamed(
+            &base_files,
+            &mut our_files,
+            &their_renames,
+            true,
+        ));
+    }
+    let paths: BTreeSet<&String> = base_files
+        .keys()
+        .chain(our_files.keys())
+        .chain(their_files.keys())
+        .collect();
+
+    let mut merge = TreeMerge {
+        files: Snapshot::new(),
+        conflicts: vec![],
+        auto_merged: vec![],
+        moved: vec![],
+    };
+    for path in paths {
+        let base = base_files.get(path).copied();
+        let ours = our_files.get(path).copied();
+        let theirs = their_files.get(path).copied();
+        let resolved = if ours == theirs || base == theirs {
+            Some(ours)
+        } else if base == ours {
+            Some(theirs)
+        } else {
+            None
+        };
+        if let Some(resolved) = resolved {
+            if let Some(version) = resolved {
+                merge.files.insert(path.clone(), version);
+            }
+            continue;
+        }
+        let conflict = |kind, content| Conflict {
+            path: path.clone(),
+            kind,
+            base,
+            ours,
+            theirs,
+            content,
+            moved_from: None,
+        };
+        let (Some(our_version), Some(their_version)) = (ours, theirs) else {
+            // Deleted on one side, changed on the other: the changed version stays.
+            let kept = ours.or(theirs).unwrap();
+            merge.files.insert(path.clone(), kept);
+            merge.conflicts.push(conflict("modify/delete", None));
+            continue;
+        };
+        merge.files.insert(path.clone(), our_version);
+        let kind = if base.is_some() { "content" } else { "add/add" };
+        let Some(mode) = merged_mode(base, our_version.0, their_version.0) else {
+            merge.conflicts.push(conflict(kind, None));
+            continue;
+        };
+        let base_content = match base {
+            Some((_, sha)) => ObjectStorage::read_object(&sha)?.1,
+            None => vec![],
+        };
+        let our_content = ObjectStorage::read_object(&our_version.1)?.1;
+        let their_content = ObjectStorage::read_object(&their_version.1)?.1;
+        merge.auto_merged.push(path.clone());
+        let result =
+            merge_file::merge_file(path, &base_content, &our_content, &their_content, labels)?;
+        if result.conflicts > 0 {
+            merge.conflicts.push(conflict(kind, Some(result.content)));
+        } else {
+            let sha = ObjectStorage::hash_object("blob", &result.content, true, false)?;
+            merge.files.insert(path.clone(), (mode, sha));
+        }
+    }
+
+    // A file on one side where the other has a directory.
+    let directories: BTreeSet<&str> = merge
+        .files
+        .keys()
+        .flat_map(|path| path.match_indices('/').map(|(end, _)| &path[..end]))
+        .collect();
+    let in_the_way: Vec<String> = merge
+        .files
+        .keys()
+        .filter(|path| directories.contains(path.as_str()))
+        .cloned()
+        .collect();
+    for path in in_the_way {
+        // The directory is kept.
+        merge.files.remove(&path);
+        let version = |files: &Snapshot| files.get(&path).copied();
+        merge.conflicts.push(Conflict {
+            base: version(&base_files),
+            ours: version(&our_files),
+            theirs: version(&their_files),
+            kind: "file/directory",
+            content: None,
+            moved_from: None,
+            path,
+        });
+    }
+
+    for file in moved {
+        let conflicted = merge
+            .conflicts
+            .iter()
+            .any(|conflict| conflict.path == file.to);
+        if directory_renames == DirectoryRenames::Move || conflicted {
+            merge.moved.push(file);
+            continue;
+        }
+        let version = merge.files.get(&file.to).copied();
+        merge.conflicts.push(Conflict {
+            path: file.to,
+            kind: "file location",
+            base: None,
+            ours: version.filter(|_| file.added_by_us),
+            theirs: version.filter(|_| !file.added_by_us),
+            content: None,
+            moved_from: Some(file.from),
+        });
+    }
+    merge.conflicts.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(merge)
+}
+
+/// The directories `side` renamed since `base`, by their old name, worked out from the files
+/// it moved unchanged: each such file votes for its old directory having become its new one.
+fn renamed_directories(base: &Snapshot, side: &Snapshot) -> HashMap<String, String> {
+    let mut added: HashMap<Sha, Vec<&str>> = HashMap::new();
+    for (path, (_, sha)) in side {
+        if !base.contains_key(path) {
+            added.entry(*sha).or_default().push(path);
+        }
+    }
+    let file_name = |path: &str| path.rsplit('/').next().unwrap_or_default().to_string();
+    let mut votes: BTreeMap<String, BTreeMap<String, usize>> = BTreeMap::new();
+    for (old, (_, sha)) in base.iter().filter(|(path, _)| !side.contains_key(*path)) {
+        let Some(candidates) = added
+            .get_mut(sha)
+            .filter(|candidates| !candidates.is_empty())
+        else {
+            continue;
+        };
+        // Several copies of the content were added: prefer one with the same name.
+        let position = candidates
+            .iter()
+            .position(|new| file_name(new) == file_name(old))
+            .unwrap_or(0);
+        let new = candidates.remove(position);
+        if let Some((old_dir, new_dir)) = renamed_directory(old, new) {
+            *votes
+                .entry(old_dir)
+                .or_default()
+                .entry(new_dir)
+                .or_default() += 1;
+        }
+    }
+    let mut renames = HashMap::new();
+    for (old_dir, targets) in votes {
+        let prefix = format!("{}/", old_dir);
+        let still_used = side
+            .range(prefix.clone()..)
+            .next()
+            .is_some_and(|(path, _)| path.starts_with(&prefix));
+        let most = targets.values().max().copied().unwrap_or_default();
+        let mut best = targets.into_iter().filter(|(_, count)| *count == most);
+        // A directory split evenly between new names has no one new name.
+        if let (false, Some((new_dir, _)), None) = (still_used, best.next(), best.next()) {
+            renames.insert(old_dir, new_dir);
+        }
+    }
+    renames
+}
+
+/// The directory a file moving from `old` to `new` says was renamed, and its new name: the
+/// directories of the two paths without the trailing components they share, so that moving
+/// `a/b/file` to `x/b/file` renames `a` to `x`. `None` when the file stayed in its directory or
+/// was at the top, which cannot be renamed.
+fn renamed_directory(old: &str, new: &str) -> Option<(String, String)> {
+    let mut old_dirs: Vec<&str> = old.split('/').collect();
+    let mut new_dirs: Vec<&str> = new.split('/').collect();
+    old_dirs.pop();
+    new_dirs.pop();
+    while !old_dirs.is_empty() && old_dirs.last() == new_dirs.last() {
+        old_dirs.pop();
+        new_dirs.pop();
+    }
+    if old_dirs.is_empty() {
+        return None;
+    }
+    Some((old_dirs.join("/"), new_dirs.join("/")))
+}
+
+/// Moves the files `files` added since `base` inside a directory in `renames` to where the
+/// directory went, unless `files` already has a file there.
+fn move_into_renamed(
+    base: &Snapshot,
+    files: &mut Snapshot,
+    renames: &HashMap<String, String>,
+    added_by_us: bool,
+) -> Vec<MovedFile> {
+    if renames.is_empty() {
+        return vec![];
+    }
+    let mut moved = vec![];
+    let added: Vec<String> = files
+        .keys()
+        .filter(|path| !base.contains_key(*path))
+        .cloned()
+        .collect();
+    for from in added {
+        // The innermost renamed directory the file is in decides.
+        let to = from.match_indices('/').rev().find_map(|(end, _)| {
+            let new_dir = renames.get(&from[..end])?;
+            let rest = &from[end + 1..];
+            Some(match new_dir.is_empty() {
+                true => rest.to_string(),
+                false => format!("{}/{}", new_dir, rest),
+            })
+        });
+        let Some(to) = to.filter(|to| !files.contains_key(to)) else {
+            continue;
+        };
+        let version = files.remove(&from).unwrap();
+        files.insert(to.clone(), version);
+        moved.push(MovedFile {
+            from,
+            to,
+            added_by_us,
+        });
+    }
+    moved
+}
+
+impl TreeMerge {
+    /// Writes the tree of the merge result: the merged files, with the text merges of
+    /// conflicting files written with their conflict markers, as they go into the working
+    /// tree. It is the whole result for programs that have no working tree to leave conflicts
+    /// in.
+    pub fn tree(&self) -> anyhow::Result<Sha> {
+        let mut files = self.files.clone();
+        for conflict in &self.conflicts {
+            if let (Some(content), Some((mode, _))) = (&conflict.content, conflict.ours) {
+                let sha = ObjectStorage::hash_object("blob", content, true, false)?;
+                files.insert(conflict.path.clone(), (mode, sha));
+            }
+        }
+        write_tree(&files)
+    }
+}
+
+/// Merges the commits `ours` and `theirs`, in memory like [`merge_trees`], relative to their
+/// merge base. When criss-cross merges left several, they are merged into one first, as git's
+/// `ort` strategy does, and the tree of that merge, conflict markers and all, is the base.
+pub fn merge_commits(ours: &Sha, theirs: &Sha, labels: &Labels) -> anyhow::Result<TreeMerge> {
+    let bases = commit_walk::merge_bases(theirs, &[*ours])?;
+    let base_tree = merged_base_tree(&bases)?;
+    let our_tree = ObjectStorage::peel_to_tree(ours)?;
+    let their_tree = ObjectStorage::peel_to_tree(theirs)?;
+    merge_trees(base_tree.as_ref(), &our_tree, &their_tree, labels)
+}
+
+/// The tree of the merge of the commits `bases`, each merged into the result of merging the
+/// ones before it relative to their own merge bases; `None` without bases.
+fn merged_base_tree(bases: &[Sha]) -> anyhow::Result<Option<Sha>> {
+    let Some((first, rest)) = bases.split_first() else {
+        return Ok(None);
+    };
+    let mut merged = vec![*first];
+    let mut tree = ObjectStorage::peel_to_tree(first)?;
+    let labels = Labels {
+        ours: "Temporary merge branch 1",
+        base: "merged common ancestors",
+        theirs: "Temporary merge branch 2",
+    };
+    for base in rest {
+        let inner_base = merged_base_tree(&commit_walk::merge_bases(base, &merged)?)?;
+        let base_tree = ObjectStorage::peel_to_tree(base)?;
+        tree = merge_trees(inner_base.as_ref(), &tree, &base_tree, &labels)?.tree()?;
+        merged.push(*base);
+    }
+    Ok(Some(tree))
+}
+
+/// The mode of a file both sides changed: the one a side changed it to, or `None` when they
+/// differ or the file is not a regular file on both sides, which cannot be merged line by line.
+fn merged_mode(
+    base: Option<Version>,
+    ours: TreeEntryPermission,
+    theirs: TreeEntryPermission,
+) -> Option<TreeEntryPermission> {
+    let regular = |mode| {
+        matches!(
+            mode,
+            TreeEntryPermission::RegularFile | TreeEntryPermission::Executable
+        )
+    };
+    if !regular(ours) || !regular(theirs) {
+        return None;
+    }
+    match base {
+        _ if ours == theirs => Some(ours),
+        Some((base, _)) if base == ours => Some(theirs),
+        Some((base, _)) if base == theirs => Some(ours),
+        _ => None,
+    }
+}
+
+/// Writes the tree holding `files`.
+pub fn write_tree(files: &Snapshot) -> anyhow::Result<Sha> {
+    let entries = files
+        .iter()
+        .map(|(path, (mode, sha))| {
+            IndexEntry::without_stat(path, index::tree_entry_mode(*mode), *sha)
+        })
+        .collect();
+    ObjectStorage::write_index_tree(&Index { entries })
+}
@@ -0,0 +1,305 @@
+use crate::attributes::{AttributeValue, Attributes};
+use crate::config::Config;
+use crate::diff::{self, Edit};
+use crate::object_storage::ObjectStorage;
+use crate::progress;
+use crate::temp_file::TempFile;
+use crate::trace2;
+use anyhow::anyhow;
+use std::fs;
+use std::process::Command;
+
+/// The length of `<<<<<<<`, `=======` and `>>>>>>>` unless the `conflict-marker-size`
+/// attribute says otherwise.
+pub const DEFAULT_MARKER_SIZE: usize = 7;
+
+/// The names of the three versions, shown after the conflict markers.
+pub struct Labels<'a> {
+    pub ours: &'a str,
+    pub base: &'a str,
+    pub theirs: &'a str,
+}
+
+/// How hunks changed differently on both sides are resolved by the text merge.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Favor {
+    /// Leave conflict markers around both versions.
+    None,
+    Ours,
+    Theirs,
+    /// Take the lines of both sides, ours first, without markers.
+    Union,
+}
+
+pub struct MergeResult {
+    pub content: Vec<u8>,
+    /// How many hunks conflict; for drivers that do not count them, 1 when the merge failed.
+    pub conflicts: usize,
+}
+
+/// The merge drivers git knows, and those defined with `merge.<driver>.driver`.
+enum Driver {
+    Text,
+    Union,
+    /// Keeps our version and reports a conflict.
+    Binary,
+    Custom(String),
+}
+
+/// Merges the three versions of the file at `path` (relative to the top of the working tree)
+/// with the driver its `merge` attribute selects: `merge` or `merge=text` for a line merge,
+/// `merge=union`, `-merge` or `merge=binary` to keep our version as a conflict, or
+/// `merge=<driver>` for the command in `merge.<driver>.driver`. Without the attribute,
+/// `merge.default` names the driver. `base` is empty when both sides added the file.
+/// Conflicts are reported to the event sink.
+pub fn merge_file(
+    path: &str,
+    base: &[u8],
+    ours: &[u8],
+    theirs: &[u8],
+    labels: &Labels,
+) -> anyhow::Result<MergeResult> {
+    let result = merge_with_driver(path, base, ours, theirs, labels)?;
+    if result.conflicts > 0 {
+        progress::notify(|sink| sink.conflict(path, result.conflicts));
+    }
+    Ok(result)
+}
+
+fn merge_with_driver(
+    path: &str,
+    base: &[u8],
+    ours: &[u8],
+    theirs: &[u8],
+    labels: &Labels,
+) -> anyhow::Result<MergeResult> {
+    let attributes = Attributes::for_path(path)?;
+    let marker_size = match attributes.get("conflict-marker-size") {
+        Some(AttributeValue::Value(size)) => size.parse().unwrap_or(DEFAULT_MARKER_SIZE),
+        _ => DEFAULT_MARKER_SIZE,
+    };
+    let config = Config::get()?;
+    let name = match attributes.get("merge") {
+        Some(AttributeValue::Set) => Some("text".to_string()),
+        Some(AttributeValue::Unset) => Some("binary".to_string()),
+        Some(AttributeValue::Value(name)) => Some(name),
+        _ => config.value("merge", "default"),
+    };
+    let driver = match name.as_deref() {
+        None | Some("text") => Driver::Text,
+        Some("union") => Driver::Union,
+        Some("binary") => Driver::Binary,
+        // An undefined driver falls back to the line merge, as in git.
+        Some(name) => match config.value(&format!("merge.{}", name), "driver") {
+            Some(command) => Driver::Custom(command),
+            None => Driver::Text,
+        },
+    };
+    let favor = match driver {
+        Driver::Custom(command) => {
+            return run_driver(&command, path, base, ours, theirs, labels, marker_size);
+        }
+        Driver::Binary => return Ok(keep_ours(path, ours, labels)),
+        Driver::Text => Favor::None,
+        Driver::Union => Favor::Union,
+    };
+    if diff::is_binary(base) || diff::is_binary(ours) || diff::is_binary(theirs) {
+        return Ok(keep_ours(path, ours, labels));
+    }
+    Ok(merge_text(base, ours, theirs, labels, favor, marker_size))
+}
+
+/// The binary driver: the file cannot be merged, so our version stays as a conflict.
+fn keep_ours(path: &str, ours: &[u8], labels: &Labels) -> MergeResult {
+    eprintln!(
+        "warning: Cannot merge binary files: {} ({} vs. {})",
+        path, labels.ours, labels.theirs
+    );
+    MergeResult {
+        content: ours.to_vec(),
+        conflicts: 1,
+    }
+}
+
+/// Runs a `merge.<driver>.driver` command through the shell in the top of the working tree,
+/// with `%O`, `%A` and `%B` replaced by temporary files holding the base, our and their
+/// version, `%L` by the conflict marker size, `%P` by the path, and `%S`, `%X` and `%Y` by the
+/// labels. The driver leaves the result in the `%A` file, and exits with a non-zero status if
+/// there are conflicts.
+fn run_driver(
+    command: &str,
+    path: &str,
+    base: &[u8],
+    ours: &[u8],
+    theirs: &[u8],
+    labels: &Labels,
+    marker_size: usize,
+) -> anyhow::Result<MergeResult> {
+    let work_tree = ObjectStorage::work_tree();
+    let version = |content: &[u8]| TempFile::create(&work_tree, ".merge_file_", "", &mut &*content);
+    let (base_file, ours_file, theirs_file) = (version(base)?, version(ours)?, version(theirs)?);
+    let file_arg = |file: &TempFile| shell_quote(&file.path().to_string_lossy());
+    let mut expanded = String::new();
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('O') => expanded.push_str(&file_arg(&base_file)),
+            Some('A') => expanded.push_str(&file_arg(&ours_file)),
+            Some('B') => expanded.push_str(&file_arg(&theirs_file)),
+            Some('L') => expanded.push_str(&marker_size.to_string()),
+            Some('P') => expanded.push_str(&shell_quote(path)),
+            Some('S') => expanded.push_str(&shell_quote(labels.base)),
+            Some('X') => expanded.push_str(&shell_quote(labels.ours)),
+            Some('Y') => expanded.push_str(&shell_quote(labels.theirs)),
+            Some('%') => expanded.push('%'),
+            Some(other) => {
+                expanded.push('%');
+                expanded.push(other);
+            }
+            None => expanded.push('%'),
+        }
+    }
+    let mut driver = Command::new("sh");
+    driver.arg("-c").arg(&expanded).current_dir(&work_tree);
+    let status = trace2::status(&mut driver, "merge_driver", None)
+        .map_err(|err| anyhow!("cannot run merge driver '{}': {}", command, err))?;
+    Ok(MergeResult {
+        content: fs::read(ours_file.path())?,
+        conflicts: usize::from(!status.success()),
+    })
+}
+
+/// Wraps `value` in single quotes for the shell.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// A line-based three-way merge: hunks changed on one side only take that side, hunks changed
+/// the same way on both sides are taken once, and the others are resolved as `favor` says.
+pub fn merge_text(
+    base: &[u8],
+    ours: &[u8],
+    theirs: &[u8],
+    labels: &Labels,
+    favor: Favor,
+    marker_size: usize,
+) -> MergeResult {
+    let base_lines = diff::split_lines(base);
+    let ours_lines = diff::split_lines(ours);
+    let theirs_lines = diff::split_lines(theirs);
+    let in_ours = unchanged_lines(&base_lines, &ours_lines);
+    let in_theirs = unchanged_lines(&base_lines, &theirs_lines);
+    let mut result = MergeResult {
+        content: vec![],
+        conflicts: 0,
+    };
+    let (mut b, mut o, mut t) = (0, 0, 0);
+    loop {
+        // Lines that neither side touched.
+        while b < base_lines.len() && in_ours[b] == Some(o) && in_theirs[b] == Some(t) {
+            result.content.extend_from_slice(base_lines[b]);
+            (b, o, t) = (b + 1, o + 1, t + 1);
+        }
+        if b == base_lines.len() && o == ours_lines.len() && t == theirs_lines.len() {
+            break;
+        }
+        // The hunk runs up to the next base line both sides kept.
+        let (next_b, next_o, next_t) = (b..base_lines.len())
+            .find_map(|i| Some((i, in_ours[i]?, in_theirs[i]?)))
+            .unwrap_or((base_lines.len(), ours_lines.len(), theirs_lines.len()));
+        let base_hunk = &base_lines[b..next_b];
+        let ours_hunk = &ours_lines[o..next_o];
+        let theirs_hunk = &theirs_lines[t..next_t];
+        if ours_hunk == base_hunk || ours_hunk == theirs_hunk {
+            result.content.extend(theirs_hunk.concat());
+        } else if theirs_hunk == base_hunk {
+            result.content.extend(ours_hunk.concat());
+        } else {
+            if favor == Favor::None {
+                result.conflicts += 1;
+            }
+            resolve_conflict(
+                &mut result.content,
+                ours_hunk,
+                theirs_hunk,
+                labels,
+                favor,
+                marker_size,
+            );
+        }
+        (b, o, t) = (next_b, next_o, next_t);
+    }
+    result
+}
+
+/// For each line of `base`, the index of the same line in `other` if it was kept.
+fn unchanged_lines(base: &[&[u8]], other: &[&[u8]]) -> Vec<Option<usize>> {
+    let mut unchanged = vec![None; base.len()];
+    for edit in diff::diff_lines(base, other) {
+        if let Edit::Equal(base_index, other_index) = edit {
+            unchanged[base_index] = Some(other_index);
+        }
+    }
+    unchanged
+}
+
+/// Writes a hunk both sides changed. Lines the two versions start or end with alike are
+/// left outside the conflict markers.
+fn resolve_conflict(
+    out: &mut Vec<u8>,
+    ours: &[&[u8]],
+    theirs: &[&[u8]],
+    labels: &Labels,
+    favor: Favor,
+    marker_size: usize,
+) {
+    let prefix = ours
+        .iter()
+        .zip(theirs)
+        .take_while(|(ours, theirs)| ours == theirs)
+        .count();
+    let suffix = ours[prefix..]
+        .iter()
+        .rev()
+        .zip(theirs[prefix..].iter().rev())
+        .take_while(|(ours, theirs)| ours == theirs)
+        .count();
+    let ours_only = &ours[prefix..ours.len() - suffix];
+    let theirs_only = &theirs[prefix..theirs.len() - suffix];
+    out.extend(ours[..prefix].concat());
+    let marker = |out: &mut Vec<u8>, c: u8, label: &str| {
+        out.extend(std::iter::repeat_n(c, marker_size));
+        if !label.is_empty() {
+            out.push(b' ');
+            out.extend_from_slice(label.as_bytes());
+        }
+        out.push(b'\n');
+    };
+    // A side ending without a newline gets one, so that the next line starts on its own.
+    let lines = |out: &mut Vec<u8>, lines: &[&[u8]]| {
+        out.extend(lines.concat());
+        if out.last().is_some_and(|&byte| byte != b'\n') && !lines.is_empty() {
+            out.push(b'\n');
+        }
+    };
+    match favor {
+        Favor::None => {
+            marker(out, b'<', labels.ours);
+            lines(out, ours_only);
+            marker(out, b'=', "");
+            lines(out, theirs_only);
+            marker(out, b'>', labels.theirs);
+        }
+        Favor::Ours => out.extend(ours_only.concat()),
+        Favor::Theirs => out.extend(theirs_only.concat()),
+        Favor::Union => {
+            lines(out, ours_only);
+            out.extend(theirs_only.concat());
+        }
+    }
+    out.extend(ours[ours.len() - suffix..].concat());
+}
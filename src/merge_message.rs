@@ -0,0 +1,271 @@
+use crate::cli::ParsedArgs;
+use crate::commit_walk;
+use crate::config::Config;
+use crate::object_storage::{GitObject, ObjectStorage, Sha};
+use crate::pathspec;
+use crate::refs;
+use anyhow::anyhow;
+
+/// How many commits `merge.log = true` and a bare `--log` list per merged commit.
+pub const DEFAULT_LOG_LENGTH: usize = 20;
+
+/// A commit to merge and what it is, as a line of `FETCH_HEAD` has it:
+/// `<id>\t\tbranch 'topic' of <url>`, where the url is `.` for the repository itself.
+pub struct MergeSource {
+    pub sha: Sha,
+    /// `branch 'topic'`, `tag 'v1.0'`, `remote-tracking branch 'origin/topic'`,
+    /// `commit 'abc1234'` or `'<ref>'`.
+    pub description: String,
+    /// Where it comes from; `None` for a line with the description alone, such as a remote's
+    /// `HEAD` (described by its url) or a commit named by id.
+    pub repository: Option<String>,
+}
+
+impl MergeSource {
+    /// Describes the commit `sha` that `name` names in this repository, the way `merge` tells
+    /// `fmt-merge-msg` about it.
+    pub fn from_revision(name: &str, sha: Sha) -> anyhow::Result<Self> {
+        let local = |description: String| {
+            Ok(Self {
+                sha,
+                description,
+                repository: Some(".".to_string()),
+            })
+        };
+        match refs::dwim_ref(name)? {
+            Some((full_name, _)) if full_name.starts_with("refs/heads/") => {
+                local(format!("branch '{}'", name))
+            }
+            Some((full_name, _)) if full_name.starts_with("refs/remotes/") => {
+                local(format!("remote-tracking branch '{}'", name))
+            }
+            Some((full_name, _)) if full_name.starts_with("refs/tags/") => {
+                local(format!("tag '{}'", name))
+            }
+            _ => Ok(Self {
+                sha,
+                description: format!("commit '{}'", name),
+                repository: None,
+            }),
+        }
+    }
+
+    /// Parses the lines of a `FETCH_HEAD` file, leaving out those marked `not-for-merge`.
+    pub fn parse_fetch_head(content: &str) -> anyhow::Result<Vec<Self>> {
+        let mut sources = vec![];
+        for line in content.lines().filter(|line| !line.is_empty()) {
+            let invalid = || anyhow!("invalid FETCH_HEAD line: {}", line);
+            let mut fields = line.splitn(3, '\t');
+            let (Some(hex), Some(marker), Some(description)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                Err(invalid())?
+            };
+            if marker == "not-for-merge" {
+                continue;
+            }
+            let sha = ObjectStorage::hex_string_to_sha(hex).map_err(|_| invalid())?;
+            let (description, repository) = match description.rsplit_once(" of ") {
+                Some((description, repository)) => (description, Some(repository.to_string())),
+                None => (description, None),
+            };
+            sources.push(Self {
+                sha,
+                description: description.to_string(),
+                repository,
+            });
+        }
+        Ok(sources)
+    }
+
+    /// What the shortlog calls the merged commits: a branch by its name, unquoted when it is
+    /// one of this repository's, other sources by their description; those of another
+    /// repository with ` of <repository>`.
+    fn origin(&self) -> String {
+        let origin = ["branch ", "remote-tracking branch "]
+            .iter()
+            .find_map(|kind| self.description.strip_prefix(kind))
+            .unwrap_or(&self.description);
+        match self.repository.as_deref() {
+            Some(".") | None => match origin
+                .strip_prefix('\'')
+                .and_then(|name| name.strip_suffix('\''))
+            {
+                Some(name) => name.to_string(),
+                None => origin.to_string(),
+            },
+            Some(repository) => format!("{} of {}", origin, repository),
+        }
+    }
+}
+
+/// How many commits to list per merged commit, from `merge.log`: a number, or true for
+/// [`DEFAULT_LOG_LENGTH`].
+fn configured_log_length() -> anyhow::Result<usize> {
+    let config = Config::get()?;
+    let Some(value) = config.value("merge", "log") else {
+        return Ok(0);
+    };
+    Ok(match value.parse::<i64>() {
+        Ok(length) => length.max(0) as usize,
+        Err(_) if config.bool("merge", "log") == Some(true) => DEFAULT_LOG_LENGTH,
+        Err(_) => 0,
+    })
+}
+
+/// The shortlog length `--log[=<n>]` or `--no-log` asks for, whichever is given last, or else
+/// the one `merge.log` configures.
+pub fn log_length(args: &ParsedArgs) -> anyhow::Result<usize> {
+    match args.last_of(&["--log", "--no-log"]) {
+        Some("--log") => match args.values_or("--log", "").pop() {
+            Some("") | None => Ok(DEFAULT_LOG_LENGTH),
+            Some(length) => length
+                .parse()
+                .map_err(|_| anyhow!("option `log' expects a numerical value")),
+        },
+        Some(_) => Ok(0),
+        None => configured_log_length(),
+    }
+}
+
+/// The message of a merge of `sources` into the current branch, like git's: a title such as
+/// `Merge branches 'a' and 'b' into topic` (`into` is left out for the branches
+/// `merge.suppressDest` matches, by default `main` and `master`), or `title` instead, then,
+/// when `log_length` is not 0, a shortlog of up to that many subjects of the commits each
+/// source brings in. Ends with a newline.
+pub fn format(
+    sources: &[MergeSource],
+    title: Option<&str>,
+    log_length: usize,
+) -> anyhow::Result<String> {
+    let mut message = match title {
+        Some(title) => title.to_string(),
+        None => default_title(sources)?,
+    };
+    message.push('\n');
+    if log_length == 0 {
+        return Ok(message);
+    }
+    let head = refs::resolve_ref("HEAD")?.1;
+    let merged = commit_walk::reachable_from(&head.into_iter().collect::<Vec<_>>())?;
+    for source in sources {
+        let mut commits = vec![];
+        for sha in commit_walk::reachable_from(&[source.sha])?.iter() {
+            if merged.contains(&sha) {
+                continue;
+            }
+            let GitObject::Commit(commit) = GitObject::from_sha(&sha)? else {
+                continue;
+            };
+            // Merges are not listed.
+            if commit.parents.len() < 2 {
+                commits.push((commit.committer.time, sha, commit));
+            }
+        }
+        if commits.is_empty() {
+            continue;
+        }
+        commits.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+        message.push_str(&format!("\n* {}:", source.origin()));
+        if commits.len() > log_length {
+            message.push_str(&format!(" ({} commits)", commits.len()));
+        }
+        message.push('\n');
+        for (_, sha, commit) in commits.iter().take(log_length) {
+            let subject = commit.message.lines().next().unwrap_or_default().trim();
+            match subject {
+                "" => message.push_str(&format!("  {}\n", ObjectStorage::sha_to_hex_string(sha))),
+                subject => message.push_str(&format!("  {}\n", subject)),
+            }
+        }
+        if commits.len() > log_length {
+            message.push_str("  ...\n");
+        }
+    }
+    Ok(message)
+}
+
+/// `Merge <what>[ of <repository>][; <what> of <repository>...][ into <branch>]`, where the
+/// sources of each repository are named by kind: branches, remote-tracking branches, tags,
+/// then others.
+fn default_title(sources: &[MergeSource]) -> anyhow::Result<String> {
+    // The sources of each repository, in the order they were first named; a description
+    // without one stands alone.
+    let mut groups: Vec<(String, Vec<&str>)> = vec![];
+    for source in sources {
+        let key = source
+            .repository
+            .clone()
+            .unwrap_or_else(|| source.description.clone());
+        match groups.iter_mut().find(|(existing, _)| *existing == key) {
+            Some((_, descriptions)) => descriptions.push(&source.description),
+            None => groups.push((key, vec![&source.description])),
+        }
+    }
+    let mut parts = vec![];
+    for (key, descriptions) in &groups {
+        if sources
+            .iter()
+            .any(|source| source.repository.is_none() && source.description == *key)
+        {
+            parts.push(key.clone());
+            continue;
+        }
+        let mut kinds: [(&str, &str, Vec<&str>); 4] = [
+            ("branch ", "branches ", vec![]),
+            (
+                "remote-tracking branch ",
+                "remote-tracking branches ",
+                vec![],
+            ),
+            ("tag ", "tags ", vec![]),
+            ("commit ", "commits ", vec![]),
+        ];
+        for description in descriptions {
+            let kind = kinds
+                .iter()
+                .position(|(one, _, _)| *one != "commit " && description.starts_with(one));
+            match kind {
+                Some(kind) => {
+                    let prefix = kinds[kind].0;
+                    kinds[kind].2.push(&description[prefix.len()..]);
+                }
+                None => kinds[3].2.push(description),
+            }
+        }
+        let mut part = kinds
+            .iter()
+            .filter(|(_, _, names)| !names.is_empty())
+            .map(|(one, many, names)| match names.as_slice() {
+                [name] => format!("{}{}", one, name),
+                [rest @ .., last] => format!("{}{} and {}", many, rest.join(", "), last),
+                [] => unreachable!(),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        if key != "." {
+            part.push_str(&format!(" of {}", key));
+        }
+        parts.push(part);
+    }
+    let mut title = format!("Merge {}", parts.join("; "));
+    let branch = refs::current_branch()?;
+    let branch = match &branch {
+        Some(branch) => branch.strip_prefix("refs/heads/").unwrap_or(branch),
+        None => "HEAD",
+    };
+    // An empty value clears the patterns before it.
+    let values = Config::get()?.values("merge", "suppressDest");
+    let suppressed = match values.iter().rposition(String::is_empty) {
+        Some(cleared) => values[cleared + 1..].to_vec(),
+        None if values.is_empty() => vec!["main".to_string(), "master".to_string()],
+        None => values,
+    };
+    if !suppressed
+        .iter()
+        .any(|pattern| pathspec::wildmatch(pattern, branch, false))
+    {
+        title.push_str(&format!(" into {}", branch));
+    }
+    Ok(title)
+}
@@ -0,0 +1,263 @@
+use crate::cli;
+use crate::config::Config;
+use crate::hooks;
+use crate::object_storage::ObjectStorage;
+use crate::trace2;
+use anyhow::anyhow;
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+
+/// A tool git knows how to launch without `difftool.<tool>.cmd` or `mergetool.<tool>.cmd`.
+/// The commands are run by the shell with the files in `$LOCAL`, `$REMOTE`, `$MERGED` and
+/// `$BASE`.
+struct Preset {
+    name: &'static str,
+    program: &'static str,
+    diff: &'static str,
+    merge: &'static str,
+    /// For conflicts where both sides added the file, so there is no common version.
+    merge_without_base: &'static str,
+    /// Whether the exit code says if the merge was resolved, unless
+    /// `mergetool.<tool>.trustExitCode` says otherwise.
+    trust_exit_code: bool,
+}
+
+/// In the order they are tried when no tool is configured, like git's guess list.
+const PRESETS: &[Preset] = &[
+    Preset {
+        name: "meld",
+        program: "meld",
+        diff: r#"meld "$LOCAL" "$REMOTE""#,
+        merge: r#"meld --output="$MERGED" "$LOCAL" "$BASE" "$REMOTE""#,
+        merge_without_base: r#"meld --output="$MERGED" "$LOCAL" "$MERGED" "$REMOTE""#,
+        trust_exit_code: false,
+    },
+    Preset {
+        name: "kdiff3",
+        program: "kdiff3",
+        diff: r#"kdiff3 --L1 "$MERGED (A)" --L2 "$MERGED (B)" "$LOCAL" "$REMOTE""#,
+        merge: r#"kdiff3 --auto --L1 "$MERGED (Base)" --L2 "$MERGED (Local)" --L3 "$MERGED (Remote)" -o "$MERGED" "$BASE" "$LOCAL" "$REMOTE""#,
+        merge_without_base: r#"kdiff3 --auto --L1 "$MERGED (Local)" --L2 "$MERGED (Remote)" -o "$MERGED" "$LOCAL" "$REMOTE""#,
+        trust_exit_code: true,
+    },
+    Preset {
+        name: "tkdiff",
+        program: "tkdiff",
+        diff: r#"tkdiff "$LOCAL" "$REMOTE""#,
+        merge: r#"tkdiff -a "$BASE" -o "$MERGED" "$LOCAL" "$REMOTE""#,
+        merge_without_base: r#"tkdiff -o "$MERGED" "$LOCAL" "$REMOTE""#,
+        trust_exit_code: true,
+    },
+    Preset {
+        name: "opendiff",
+        program: "opendiff",
+        diff: r#"opendiff "$LOCAL" "$REMOTE" | cat"#,
+        merge: r#"opendiff "$LOCAL" "$REMOTE" -ancestor "$BASE" -merge "$MERGED" | cat"#,
+        merge_without_base: r#"opendiff "$LOCAL" "$REMOTE" -merge "$MERGED" | cat"#,
+        trust_exit_code: false,
+    },
+    Preset {
+        name: "vscode",
+        program: "code",
+        diff: r#"code --wait --diff "$LOCAL" "$REMOTE""#,
+        merge: r#"code --wait --merge "$REMOTE" "$LOCAL" "$BASE" "$MERGED""#,
+        merge_without_base: r#"code --wait "$MERGED""#,
+        trust_exit_code: false,
+    },
+    Preset {
+        name: "gvimdiff",
+        program: "gvim",
+        diff: r#"gvim -R -f -d -c "wincmd l" "$LOCAL" "$REMOTE""#,
+        merge: r#"gvim -f -d -c "4wincmd w | wincmd J" "$LOCAL" "$BASE" "$REMOTE" "$MERGED""#,
+        merge_without_base: r#"gvim -f -d -c "wincmd l" "$LOCAL" "$MERGED" "$REMOTE""#,
+        trust_exit_code: false,
+    },
+    Preset {
+        name: "nvimdiff",
+        program: "nvim",
+        diff: r#"nvim -R -f -d -c "wincmd l" "$LOCAL" "$REMOTE""#,
+        merge: r#"nvim -f -d -c "4wincmd w | wincmd J" "$LOCAL" "$BASE" "$REMOTE" "$MERGED""#,
+        merge_without_base: r#"nvim -f -d -c "wincmd l" "$LOCAL" "$MERGED" "$REMOTE""#,
+        trust_exit_code: false,
+    },
+    Preset {
+        name: "vimdiff",
+        program: "vim",
+        diff: r#"vim -R -f -d -c "wincmd l" "$LOCAL" "$REMOTE""#,
+        merge: r#"vim -f -d -c "4wincmd w | wincmd J" "$LOCAL" "$BASE" "$REMOTE" "$MERGED""#,
+        merge_without_base: r#"vim -f -d -c "wincmd l" "$LOCAL" "$MERGED" "$REMOTE""#,
+        trust_exit_code: false,
+    },
+];
+
+/// Whether a tool shows differences (`difftool`) or resolves conflicts (`mergetool`).
+#[derive(Clone, Copy, PartialEq)]
+pub enum Mode {
+    Diff,
+    Merge,
+}
+
+impl Mode {
+    /// The config section of the tool definitions, and the command name.
+    fn section(self) -> &'static str {
+        match self {
+            Self::Diff => "difftool",
+            Self::Merge => "mergetool",
+        }
+    }
+}
+
+/// The versions of one file handed to a tool.
+pub struct ToolFiles<'a> {
+    pub local: &'a Path,
+    pub remote: &'a Path,
+    pub merged: &'a Path,
+    /// The common version of a conflict, if there is one.
+    pub base: Option<&'a Path>,
+}
+
+/// A tool chosen for `difftool` or `mergetool`, with the shell command to run it.
+pub struct Tool {
+    pub name: String,
+    command: String,
+    command_without_base: String,
+    pub trust_exit_code: bool,
+}
+
+impl Tool {
+    /// The tool given with `--tool`, else `diff.tool` (for diffs) or `merge.tool`, else the
+    /// first preset whose program is installed. A `difftool.<tool>.cmd` or
+    /// `mergetool.<tool>.cmd` defines a tool of one's own or replaces a preset.
+    pub fn find(mode: Mode, requested: Option<&str>) -> anyhow::Result<Self> {
+        let config = Config::get()?;
+        let mut name = requested.map(str::to_string);
+        if name.is_none() && mode == Mode::Diff {
+            name = config.value("diff", "tool");
+        }
+        if name.is_none() {
+            name = config.value("merge", "tool");
+        }
+        let name = match name {
+            Some(name) => name,
+            None => guess(mode)?,
+        };
+        let section = format!("{}.{}", mode.section(), name);
+        let preset = PRESETS.iter().find(|preset| preset.name == name);
+        let custom = config.value(&section, "cmd");
+        let (command, command_without_base) = match (custom, preset) {
+            (Some(command), _) => (command.clone(), command),
+            (None, Some(preset)) if mode == Mode::Diff => {
+                (preset.diff.to_string(), preset.diff.to_string())
+            }
+            (None, Some(preset)) => (
+                preset.merge.to_string(),
+                preset.merge_without_base.to_string(),
+            ),
+            (None, None) => Err(anyhow!(
+                "unknown {} tool {}",
+                if mode == Mode::Diff { "diff" } else { "merge" },
+                name
+            ))?,
+        };
+        let trust_exit_code = config
+            .bool(&section, "trustExitCode")
+            .unwrap_or(preset.is_some_and(|preset| preset.trust_exit_code));
+        Ok(Self {
+            name,
+            command,
+            command_without_base,
+            trust_exit_code,
+        })
+    }
+
+    /// Runs the tool from the top of the working tree and waits for it to finish.
+    pub fn run(&self, files: &ToolFiles) -> anyhow::Result<ExitStatus> {
+        let command = match files.base {
+            Some(_) => &self.command,
+            None => &self.command_without_base,
+        };
+        let mut shell = Command::new("sh");
+        shell
+            .arg("-c")
+            .arg(command)
+            .current_dir(ObjectStorage::work_tree())
+            .env("LOCAL", files.local)
+            .env("REMOTE", files.remote)
+            .env("MERGED", files.merged)
+            .env("BASE", files.base.unwrap_or(files.merged));
+        trace2::status(&mut shell, "tool", None)
+            .map_err(|err| anyhow!("cannot run {}: {}", self.name, err))
+    }
+}
+
+/// Prints the presets for `--tool-help`, with the ones that are installed first.
+pub fn print_tool_help(mode: Mode) {
+    let (installed, missing): (Vec<&Preset>, Vec<&Preset>) = PRESETS
+        .iter()
+        .partition(|preset| find_program(preset.program).is_some());
+    let command = mode.section();
+    println!(
+        "'{} {} --tool=<tool>' may be set to one of the following:",
+        cli::program_name(),
+        command
+    );
+    for preset in installed {
+        println!("\t\t{}", preset.name);
+    }
+    println!("\nThe following tools are valid, but not currently available:");
+    for preset in missing {
+        println!("\t\t{}", preset.name);
+    }
+    println!(
+        "\nSome of the tools listed above only work in a windowed\n\
+         environment. If run in a terminal-only session, they will fail."
+    );
+}
+
+/// Asks `question` on stderr and reads the answer from stdin; an empty answer (or end of
+/// input) gives `default`.
+pub fn ask(question: &str, default: bool) -> anyhow::Result<bool> {
+    eprint!("{}", question);
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    Ok(match answer.trim().to_lowercase().as_str() {
+        "" => default,
+        answer => answer.starts_with('y'),
+    })
+}
+
+/// Picks the first installed preset when no tool is configured, telling the user so.
+fn guess(mode: Mode) -> anyhow::Result<String> {
+    let (command, option) = match mode {
+        Mode::Diff => ("difftool", "diff.tool"),
+        Mode::Merge => ("mergetool", "merge.tool"),
+    };
+    let names: Vec<&str> = PRESETS.iter().map(|preset| preset.name).collect();
+    eprintln!(
+        "\nThis message is displayed because '{}' is not configured.\n\
+         See '{} {} --tool-help' or '{} help config' for more details.\n\
+         '{} {}' will now attempt to use one of the following tools:\n{}\n",
+        option,
+        cli::program_name(),
+        command,
+        cli::program_name(),
+        cli::program_name(),
+        command,
+        names.join(" ")
+    );
+    PRESETS
+        .iter()
+        .find(|preset| find_program(preset.program).is_some())
+        .map(|preset| preset.name.to_string())
+        .ok_or(anyhow!("no known {} tool is available.", command))
+}
+
+/// The executable `program` in `PATH`, if there is one.
+fn find_program(program: &str) -> Option<PathBuf> {
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|path| hooks::is_executable(path))
+}
@@ -1,9 +1,24 @@
+mod loose;
+pub mod pack;
+
+use crate::cancel;
+use crate::config::{self, Config};
+use crate::context;
+use crate::filters::Filters;
+use crate::ignore::IgnoreRules;
+use crate::index::{self, Index, IndexEntry};
+use crate::progress::{self, Phase, Progress};
+use crate::refs;
+use crate::tree_walk::TreeWalk;
+use crate::usage;
+use crate::work_tree;
 use anyhow::anyhow;
 use bytes::{Buf, BufMut};
-use flate2::Compression;
-use flate2::read::ZlibDecoder;
-use flate2::write::ZlibEncoder;
+pub use loose::{ObjectReader, ObjectWriter};
 use sha1::{Digest, Sha1};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
 use std::fs::File;
 use std::io::{BufRead, Read, Write};
 use std::path::PathBuf;
@@ -13,6 +28,7 @@ pub enum GitObject {
     Blob(Blob),
     Tree(Tree),
     Commit(Commit),
+    Tag(Tag),
 }
 
 pub struct Blob {
@@ -21,14 +37,123 @@ pub struct Blob {
 
 pub type Sha = [u8; 20];
 
+/// The most a thread keeps of [`CONTENT_BUFFER`] between objects; a buffer grown past it by
+/// a huge tree or commit is given back.
+const MAX_KEPT_BUFFER: usize = 1024 * 1024;
+
+thread_local! {
+    /// Where trees, commits and tags are read and serialized: each is parsed into its own
+    /// structure or written out right away, so walks over thousands of them reuse one buffer.
+    static CONTENT_BUFFER: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Runs `f` on the thread's cleared [`CONTENT_BUFFER`], or on a buffer of its own when `f`
+/// runs within another use of it.
+fn with_content_buffer<T>(f: impl FnOnce(&mut Vec<u8>) -> T) -> T {
+    CONTENT_BUFFER.with(|cell| match cell.try_borrow_mut() {
+        Ok(mut buffer) => {
+            buffer.clear();
+            let result = f(&mut buffer);
+            if buffer.capacity() > MAX_KEPT_BUFFER {
+                *buffer = Vec::new();
+            }
+            result
+        }
+        Err(_) => f(&mut Vec::new()),
+    })
+}
+
+/// An object as it is stored on disk, still compressed, to be copied without inflating it.
+pub enum RawObject {
+    /// The file of a loose object: a zlib stream of the `<type> <size>\0` header followed by
+    /// the content.
+    Loose(Vec<u8>),
+    Packed(pack::RawEntry),
+}
+
+/// An object id in the library API, printed as hex; inside the crate ids are plain [`Sha`]
+/// arrays, which it converts from and to.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Oid(pub Sha);
+
+impl Oid {
+    pub fn from_hex(hex: &str) -> anyhow::Result<Self> {
+        Ok(Self(ObjectStorage::hex_string_to_sha(hex)?))
+    }
+
+    pub fn as_bytes(&self) -> &Sha {
+        &self.0
+    }
+}
+
+impl fmt::Display for Oid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&ObjectStorage::sha_to_hex_string(&self.0))
+    }
+}
+
+impl From<Sha> for Oid {
+    fn from(sha: Sha) -> Self {
+        Self(sha)
+    }
+}
+
 pub struct Commit {
-    tree: Sha,
-    parents: Vec<Sha>,
-    message: String,
+    pub tree: Sha,
+    pub parents: Vec<Sha>,
+    pub author: Signature,
+    pub committer: Signature,
+    pub message: String,
+}
+
+/// An annotated tag: a named, signed-off pointer to another object.
+pub struct Tag {
+    pub object: Sha,
+    /// The type of the tagged object, usually `commit`.
+    pub object_type: String,
+    // The tag name and tagger round-trip through parsing but nothing inspects them yet.
+    #[allow(dead_code)]
+    pub name: String,
+    /// Missing on some very old tags.
+    #[allow(dead_code)]
+    pub tagger: Option<Signature>,
+    pub message: String,
+    /// The armored signature appended to the message of a signed tag.
+    pub signature: Option<String>,
+}
+
+/// The identity and timestamp on an `author` or `committer` line.
+#[derive(Clone)]
+pub struct Signature {
+    pub name: String,
+    pub email: String,
+    /// Seconds since the Unix epoch.
+    pub time: i64,
+    /// The author's UTC offset in minutes.
+    pub tz_offset: i32,
 }
 
 pub struct ObjectStorage {}
 
+/// How many annotated tags pointing at tags are followed before giving up.
+const MAX_TAG_DEPTH: usize = 32;
+
+/// Day names from Thursday on, the weekday of the Unix epoch.
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+pub struct CheckoutReport {
+    pub total_files: usize,
+    pub files_written: usize,
+    pub bytes_written: u64,
+    pub failures: Vec<(PathBuf, String)>,
+    pub symlinks: bool,
+    pub symlink_fallbacks: usize,
+}
+
+#[derive(Clone, Copy, PartialEq)]
 pub enum TreeEntryPermission {
     Directory,
     RegularFile,
@@ -47,45 +172,205 @@ pub struct Tree {
 }
 
 impl GitObject {
-    pub fn from_file_path(path: &PathBuf) -> anyhow::Result<Self> {
-        let mut file = File::open(path)?;
-        let mut data = vec![];
-        file.read_to_end(&mut data)?;
-        Self::from_data(data.as_ref())
-    }
-
-    pub fn from_data(data: &[u8]) -> anyhow::Result<Self> {
-        let mut zlib_decoder = ZlibDecoder::new(data);
-        let mut result: Vec<u8> = vec![];
-        zlib_decoder.read_to_end(&mut result)?;
-        let mut type_prefix_buf: Vec<u8> = vec![0; 4];
-        let mut reader = result.reader();
-        reader.read_exact(&mut type_prefix_buf)?;
-        let type_prefix = String::from_utf8(type_prefix_buf)?;
-        let mut content: Vec<u8> = vec![];
-        let _ = (reader).skip_until(0);
-        let _ = reader.read_to_end(&mut content)?;
-        if type_prefix == "blob" {
-            Ok(GitObject::Blob(Blob::from(&content)?))
-        } else if type_prefix == "tree" {
-            Ok(GitObject::Tree(Tree::from(&content)?))
-        } else if type_prefix == "comm" {
-            Ok(GitObject::Commit(Commit::from(&content)?))
-        } else {
-            Err(anyhow!(
-                "Only blob and tree objects are supported ({})",
-                type_prefix
-            ))
+    /// Reads the object `sha` from its loose file or, failing that, from a pack.
+    /// A blob takes the content as it is read; other objects are read into a buffer that is
+    /// reused once they are parsed.
+    pub fn from_sha(sha: &Sha) -> anyhow::Result<Self> {
+        let mut object = ObjectStorage::open_object(sha)?;
+        if object.object_type == "blob" {
+            let content = object.into_content()?;
+            return Ok(GitObject::Blob(Blob { content }));
+        }
+        with_content_buffer(|content| {
+            content.reserve(object.size as usize);
+            object.read_to_end(content)?;
+            Self::from_parts(&object.object_type, content)
+        })
+    }
+
+    pub fn from_parts(object_type: &str, content: &[u8]) -> anyhow::Result<Self> {
+        Self::parse(object_type, content, false)
+    }
+
+    /// Like [`GitObject::from_parts`], but a tree or commit that git's fsck would report as
+    /// malformed is an error, prefixed with fsck's message id (`treeNotSorted: ...`), rather
+    /// than read as far as it makes sense.
+    pub fn from_parts_strict(object_type: &str, content: &[u8]) -> anyhow::Result<Self> {
+        Self::parse(object_type, content, true)
+    }
+
+    /// Reads the blob `sha`; any other object is an error naming its type.
+    pub fn read_blob(sha: &Sha) -> anyhow::Result<Blob> {
+        match Self::from_sha(sha)? {
+            GitObject::Blob(blob) => Ok(blob),
+            object => Err(object.wrong_kind("blob", Some(sha))),
+        }
+    }
+
+    /// Reads the tree `sha`; any other object is an error naming its type.
+    pub fn read_tree(sha: &Sha) -> anyhow::Result<Tree> {
+        match Self::from_sha(sha)? {
+            GitObject::Tree(tree) => Ok(tree),
+            object => Err(object.wrong_kind("tree", Some(sha))),
+        }
+    }
+
+    /// Reads the commit `sha`; any other object is an error naming its type.
+    pub fn read_commit(sha: &Sha) -> anyhow::Result<Commit> {
+        match Self::from_sha(sha)? {
+            GitObject::Commit(commit) => Ok(commit),
+            object => Err(object.wrong_kind("commit", Some(sha))),
+        }
+    }
+
+    /// Reads the tag `sha`; any other object is an error naming its type.
+    pub fn read_tag(sha: &Sha) -> anyhow::Result<Tag> {
+        match Self::from_sha(sha)? {
+            GitObject::Tag(tag) => Ok(tag),
+            object => Err(object.wrong_kind("tag", Some(sha))),
+        }
+    }
+
+    /// The type of the object as git names it: `blob`, `tree`, `commit` or `tag`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            GitObject::Blob(_) => "blob",
+            GitObject::Tree(_) => "tree",
+            GitObject::Commit(_) => "commit",
+            GitObject::Tag(_) => "tag",
+        }
+    }
+
+    pub fn as_blob(&self) -> Option<&Blob> {
+        match self {
+            GitObject::Blob(blob) => Some(blob),
+            _ => None,
+        }
+    }
+
+    pub fn as_tree(&self) -> Option<&Tree> {
+        match self {
+            GitObject::Tree(tree) => Some(tree),
+            _ => None,
+        }
+    }
+
+    pub fn as_commit(&self) -> Option<&Commit> {
+        match self {
+            GitObject::Commit(commit) => Some(commit),
+            _ => None,
+        }
+    }
+
+    pub fn as_tag(&self) -> Option<&Tag> {
+        match self {
+            GitObject::Tag(tag) => Some(tag),
+            _ => None,
+        }
+    }
+
+    pub fn try_into_blob(self) -> anyhow::Result<Blob> {
+        match self {
+            GitObject::Blob(blob) => Ok(blob),
+            object => Err(object.wrong_kind("blob", None)),
+        }
+    }
+
+    pub fn try_into_tree(self) -> anyhow::Result<Tree> {
+        match self {
+            GitObject::Tree(tree) => Ok(tree),
+            object => Err(object.wrong_kind("tree", None)),
+        }
+    }
+
+    pub fn try_into_commit(self) -> anyhow::Result<Commit> {
+        match self {
+            GitObject::Commit(commit) => Ok(commit),
+            object => Err(object.wrong_kind("commit", None)),
+        }
+    }
+
+    pub fn try_into_tag(self) -> anyhow::Result<Tag> {
+        match self {
+            GitObject::Tag(tag) => Ok(tag),
+            object => Err(object.wrong_kind("tag", None)),
+        }
+    }
+
+    /// The tree of a tree-ish: the tree itself, the tree of a commit, or either of those
+    /// behind annotated tags, which are only read when there are any.
+    pub fn peel_to_tree(self) -> anyhow::Result<Tree> {
+        match self {
+            GitObject::Tree(tree) => Ok(tree),
+            GitObject::Commit(commit) => Self::read_tree(&commit.tree),
+            GitObject::Tag(tag) => {
+                Self::from_sha(&ObjectStorage::peel_tag(&tag.object)?)?.peel_to_tree()
+            }
+            GitObject::Blob(_) => Err(self.wrong_kind("tree", None)),
+        }
+    }
+
+    /// The error for an object that is not of the type `expected`, with its id if known, as
+    /// git words it.
+    fn wrong_kind(&self, expected: &str, sha: Option<&Sha>) -> anyhow::Error {
+        match sha {
+            Some(sha) => anyhow!(
+                "object {} is a {}, not a {}",
+                ObjectStorage::sha_to_hex_string(sha),
+                self.kind(),
+                expected
+            ),
+            None => anyhow!("expected {}, found {}", expected, self.kind()),
+        }
+    }
+
+    fn parse(object_type: &str, content: &[u8], strict: bool) -> anyhow::Result<Self> {
+        match object_type {
+            "blob" => Ok(GitObject::Blob(Blob::from(content)?)),
+            "tree" => Ok(GitObject::Tree(Tree::from(content, strict)?)),
+            "commit" => Ok(GitObject::Commit(Commit::from(content, strict)?)),
+            "tag" => Ok(GitObject::Tag(Tag::from(content)?)),
+            _ => Err(anyhow!(
+                "Only blob, tree, commit and tag objects are supported ({})",
+                object_type
+            )),
         }
     }
 }
 
 impl Blob {
-    pub fn new_with_file_path(path: &PathBuf) -> anyhow::Result<Self> {
+    /// Whether the file at `path` has the content of the blob `sha`. Both are compared as
+    /// streams, stopping at the first difference, and a different size decides without
+    /// reading anything.
+    pub fn matches_file(sha: &Sha, path: &path::Path) -> anyhow::Result<bool> {
+        const CHUNK_SIZE: usize = 64 * 1024;
+        let mut blob = ObjectStorage::open_object(sha)?;
         let mut file = File::open(path)?;
-        let mut content: Vec<u8> = vec![];
-        file.read_to_end(&mut content)?;
-        Ok(Self { content })
+        if blob.object_type != "blob" || blob.size != file.metadata()?.len() {
+            return Ok(false);
+        }
+        let mut remaining = blob.size as usize;
+        let mut blob_chunk = vec![0; CHUNK_SIZE.min(remaining)];
+        let mut file_chunk = blob_chunk.clone();
+        while remaining > 0 {
+            let length = CHUNK_SIZE.min(remaining);
+            blob.read_exact(&mut blob_chunk[..length])?;
+            // The file may have shrunk since its size was checked.
+            if file.read_exact(&mut file_chunk[..length]).is_err()
+                || blob_chunk[..length] != file_chunk[..length]
+            {
+                return Ok(false);
+            }
+            remaining -= length;
+        }
+        Ok(true)
+    }
+
+    /// Hashes the file at `path` as a blob, streaming it, and optionally writes it.
+    pub fn hash_file(path: &path::Path, write: bool) -> anyhow::Result<Sha> {
+        let mut file = File::open(path)?;
+        let size = file.metadata()?.len();
+        ObjectStorage::stream_object("blob", size, &mut file, write)
     }
 
     fn from(content: &[u8]) -> anyhow::Result<Self> {
@@ -94,122 +379,271 @@ impl Blob {
         })
     }
 
+    pub fn content(&self) -> &[u8] {
+        &self.content
+    }
+
     pub fn as_str(&self) -> anyhow::Result<String> {
         let v = self.content.to_vec();
         Ok(String::from_utf8(v)?)
     }
-
-    pub fn write_to_object_storage(&self) -> anyhow::Result<Sha> {
-        let mut full_content: Vec<u8> = vec![];
-        let header = ObjectStorage::header_for_content_length("blob", self.content.len())?;
-        full_content.write_all(header.as_slice())?;
-        full_content.write_all(self.content.as_slice())?;
-        ObjectStorage::write_object(&full_content)
-    }
 }
 
 impl Tree {
-    fn write_to_object_storage(&self) -> anyhow::Result<Sha> {
-        let content: Vec<u8> = vec![];
-        let mut content_writer = content.writer();
+    /// The entries in their stored order, which git keeps sorted by name.
+    ///
+    /// ```
+    /// # use codecrafters_git::{Repository, Tree, TreeEntry, TreeEntryPermission};
+    /// # let dir = std::env::temp_dir().join(format!("tree-iter-{}", std::process::id()));
+    /// let repo = Repository::init(&dir)?;
+    /// let readme = repo.write_blob(b"# Example\n")?;
+    /// let tree = Tree {
+    ///     entries: vec![TreeEntry {
+    ///         permission: TreeEntryPermission::RegularFile,
+    ///         name: "README.md".to_string(),
+    ///         hash: *readme.as_bytes(),
+    ///     }],
+    /// };
+    /// let tree = repo.find_tree(repo.write_tree(&tree)?)?;
+    /// let names: Vec<&str> = tree.iter().map(|entry| entry.name.as_str()).collect();
+    /// assert_eq!(names, ["README.md"]);
+    /// # std::fs::remove_dir_all(&dir)?;
+    /// # anyhow::Ok(())
+    /// ```
+    pub fn iter(&self) -> std::slice::Iter<'_, TreeEntry> {
+        self.entries.iter()
+    }
+
+    /// The content of the tree object, as it is hashed after the header.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut content = vec![];
+        self.serialize_into(&mut content);
+        content
+    }
+
+    /// Appends the content of the tree object to `content`.
+    fn serialize_into(&self, content: &mut Vec<u8>) {
+        // Each entry is its mode, a space, its name, a NUL and its id.
+        content.reserve(self.entries.iter().map(|entry| entry.name.len() + 28).sum());
         for entry in &self.entries {
-            _ = content_writer.write(entry.permission.to_string_repr().as_bytes())?;
-            _ = content_writer.write(b" ")?;
-            _ = content_writer.write(entry.name.as_bytes())?;
-            _ = content_writer.write(b"\0")?;
-            _ = content_writer.write(&entry.hash)?;
+            content.extend_from_slice(entry.permission.as_str().as_bytes());
+            content.push(b' ');
+            content.extend_from_slice(entry.name.as_bytes());
+            content.push(0);
+            content.extend_from_slice(&entry.hash);
         }
-        let content = content_writer.get_ref();
-        let header = ObjectStorage::header_for_content_length("tree", content.len())?;
-        let mut full_content: Vec<u8> = vec![];
-        full_content.write_all(header.as_slice())?;
-        full_content.write_all(content)?;
-        let hash = ObjectStorage::write_object(&full_content)?;
-        Ok(hash)
     }
 
-    fn from(content: &[u8]) -> anyhow::Result<Self> {
-        let mut reader = content.reader();
-        let null_byte: u8 = 0;
-        let space_byte: u8 = 32;
+    pub fn write_to_object_storage(&self) -> anyhow::Result<Sha> {
+        with_content_buffer(|content| {
+            self.serialize_into(content);
+            ObjectStorage::write_typed_object("tree", content)
+        })
+    }
+
+    /// Parses the entries straight from `content`; only their names are copied out of it.
+    fn from(content: &[u8], strict: bool) -> anyhow::Result<Self> {
+        let truncated = || anyhow!("truncated tree entry");
         let mut entries: Vec<TreeEntry> = vec![];
-        loop {
-            let mut permission_buf: Vec<u8> = vec![];
-            if let Ok(size) = reader.read_until(space_byte, &mut permission_buf) {
-                if size == 0 {
-                    break;
-                }
-                let permission = String::from_utf8(permission_buf)?;
-                let mut name_buf: Vec<u8> = vec![];
-                let _ = reader.read_until(null_byte, &mut name_buf)?;
-                let name = String::from_utf8(name_buf)?;
-                let name = name.get(0..name.len() - 1).unwrap();
-                let mut hash_bytes_buf = vec![0; 20];
-                let permission = match permission.as_str().trim() {
-                    "100644" => TreeEntryPermission::RegularFile,
-                    "40000" => TreeEntryPermission::Directory,
-                    "100755" => TreeEntryPermission::Executable,
-                    "120000" => TreeEntryPermission::SymbolicLink,
-                    &_ => Err(anyhow!(
-                        "Unsupported permission value {}",
-                        permission.as_str()
-                    ))?,
-                };
-                (reader).read_exact(&mut hash_bytes_buf)?;
-                entries.push(TreeEntry {
-                    permission,
-                    name: String::from(name),
-                    hash: hash_bytes_buf.try_into().unwrap(),
-                });
-            } else {
-                break;
+        let mut rest = content;
+        while !rest.is_empty() {
+            let space = rest.iter().position(|&byte| byte == b' ');
+            let (permission, after_permission) = match space {
+                Some(space) => (&rest[..space], &rest[space + 1..]),
+                None => (rest, &rest[rest.len()..]),
+            };
+            let permission = std::str::from_utf8(permission)?;
+            let nul = after_permission
+                .iter()
+                .position(|&byte| byte == 0)
+                .ok_or_else(truncated)?;
+            let name = std::str::from_utf8(&after_permission[..nul])?.to_string();
+            if strict && (permission.starts_with('0') || permission.trim_start() != permission) {
+                Err(anyhow!(
+                    "zeroPaddedFilemode: contains zero-padded file modes"
+                ))?
             }
+            let permission = match permission.trim() {
+                "100644" => TreeEntryPermission::RegularFile,
+                "40000" => TreeEntryPermission::Directory,
+                "100755" => TreeEntryPermission::Executable,
+                "120000" => TreeEntryPermission::SymbolicLink,
+                _ => Err(anyhow!("Unsupported permission value {}", permission))?,
+            };
+            let hash = after_permission
+                .get(nul + 1..nul + 21)
+                .ok_or_else(truncated)?;
+            entries.push(TreeEntry {
+                permission,
+                name,
+                hash: hash.try_into().expect("20 bytes were taken"),
+            });
+            rest = &after_permission[nul + 21..];
+        }
+        if strict {
+            Self::check_entries(&entries)?;
         }
         Ok(Self { entries })
     }
+
+    /// The checks git's fsck makes of tree entries: names that cannot be checked out safely,
+    /// and the order, which decides the tree's id.
+    fn check_entries(entries: &[TreeEntry]) -> anyhow::Result<()> {
+        for entry in entries {
+            match entry.name.as_str() {
+                "" => Err(anyhow!("emptyName: contains empty pathname"))?,
+                "." => Err(anyhow!("hasDot: contains '.'"))?,
+                ".." => Err(anyhow!("hasDotdot: contains '..'"))?,
+                name if name.eq_ignore_ascii_case(".git") => {
+                    Err(anyhow!("hasDotgit: contains '.git'"))?
+                }
+                name if name.contains('/') => {
+                    Err(anyhow!("fullPathname: contains full pathnames"))?
+                }
+                _ => {}
+            }
+        }
+        // Directories sort as if their name ended with a slash.
+        let sort_key = |entry: &TreeEntry| {
+            let mut key = entry.name.as_bytes().to_vec();
+            if entry.permission == TreeEntryPermission::Directory {
+                key.push(b'/');
+            }
+            key
+        };
+        for pair in entries.windows(2) {
+            if pair[0].name == pair[1].name {
+                Err(anyhow!("duplicateEntries: contains duplicate file entries"))?
+            }
+            if sort_key(&pair[0]) > sort_key(&pair[1]) {
+                Err(anyhow!("treeNotSorted: not properly sorted"))?
+            }
+        }
+        Ok(())
+    }
 }
 
 impl TreeEntryPermission {
-    pub fn to_string_repr(&self) -> String {
+    /// The mode as a tree stores it, without leading zeros.
+    pub fn as_str(self) -> &'static str {
         match self {
             TreeEntryPermission::Directory => "40000",
             TreeEntryPermission::RegularFile => "100644",
             TreeEntryPermission::SymbolicLink => "120000",
             TreeEntryPermission::Executable => "100755",
         }
-        .to_string()
+    }
+
+    pub fn to_string_repr(self) -> String {
+        self.as_str().to_string()
     }
 }
 
 impl TreeEntry {
+    pub fn id(&self) -> Oid {
+        Oid(self.hash)
+    }
+
     pub fn to_hash_hex_string(&self) -> String {
         ObjectStorage::sha_to_hex_string(&self.hash)
     }
 }
 
 impl Commit {
-    fn from(content: &[u8]) -> anyhow::Result<Self> {
+    pub fn tree_id(&self) -> Oid {
+        Oid(self.tree)
+    }
+
+    pub fn parent_ids(&self) -> impl Iterator<Item = Oid> + '_ {
+        self.parents.iter().map(|parent| Oid(*parent))
+    }
+
+    fn from(content: &[u8], strict: bool) -> anyhow::Result<Self> {
+        if strict {
+            Self::check_headers(content)?;
+        }
         let mut reader: bytes::buf::Reader<&[u8]> = content.reader();
         let mut tree: Option<Sha> = None;
         let mut parents: Vec<Sha> = vec![];
+        let mut author: Option<Signature> = None;
+        let mut committer: Option<Signature> = None;
         while let Some((prefix, payload)) = Self::read_commit_line(&mut reader)? {
             match prefix.as_str() {
                 "tree" => tree = Some(ObjectStorage::hex_string_to_sha(&payload)?),
                 "parent" => parents.push(ObjectStorage::hex_string_to_sha(&payload)?),
-                "committer" => (),
-                "author" => (),
+                "committer" => committer = Some(Signature::parse(&payload)?),
+                "author" => author = Some(Signature::parse(&payload)?),
                 _ => (),
             }
         }
         let mut message: Vec<u8> = vec![];
         _ = reader.read_to_end(&mut message);
+        // Writing the commit adds the newline back.
+        if message.ends_with(b"\n") {
+            message.pop();
+        }
         Ok(Commit {
-            tree: tree.unwrap(),
+            tree: tree.ok_or(anyhow!("commit object has no tree"))?,
             parents,
+            author: author.ok_or(anyhow!("commit object has no author"))?,
+            committer: committer.ok_or(anyhow!("commit object has no committer"))?,
             message: String::from_utf8(message)?,
         })
     }
 
+    /// The checks git's fsck makes of a commit's headers: `tree`, any `parent`s, `author` and
+    /// `committer` come first and in that order, with full lowercase ids and well-formed
+    /// identities, and a blank line ends the headers.
+    fn check_headers(content: &[u8]) -> anyhow::Result<()> {
+        let header_end = match content.windows(2).position(|pair| pair == b"\n\n") {
+            Some(end) => end,
+            None if content.ends_with(b"\n") => content.len() - 1,
+            None => Err(anyhow!("unterminatedHeader: unterminated header"))?,
+        };
+        let header = &content[..header_end];
+        if let Some(offset) = header.iter().position(|&byte| byte == 0) {
+            Err(anyhow!("nulInHeader: NUL at offset {}", offset))?
+        }
+        let header = std::str::from_utf8(header)
+            .map_err(|_| anyhow!("badHeader: headers are not valid UTF-8"))?;
+        let mut lines = header.split('\n').peekable();
+        let tree = lines
+            .next()
+            .and_then(|line| line.strip_prefix("tree "))
+            .ok_or(anyhow!(
+                "missingTree: invalid format - expected 'tree' line"
+            ))?;
+        if !is_full_hex_id(tree) {
+            Err(anyhow!(
+                "badTreeSha1: invalid 'tree' line format - bad sha1"
+            ))?
+        }
+        while let Some(parent) = lines.next_if(|line| line.starts_with("parent ")) {
+            if !is_full_hex_id(&parent["parent ".len()..]) {
+                Err(anyhow!(
+                    "badParentSha1: invalid 'parent' line format - bad sha1"
+                ))?
+            }
+        }
+        for role in ["author", "committer"] {
+            let missing = || {
+                anyhow!(
+                    "missing{}{}: invalid format - expected '{}' line",
+                    role[..1].to_uppercase(),
+                    &role[1..],
+                    role
+                )
+            };
+            let line = lines.next().ok_or_else(missing)?;
+            let ident = line
+                .strip_prefix(role)
+                .and_then(|rest| rest.strip_prefix(' '))
+                .ok_or_else(missing)?;
+            check_ident(ident)?;
+        }
+        Ok(())
+    }
+
     fn read_commit_line(
         reader: &mut bytes::buf::Reader<&[u8]>,
     ) -> anyhow::Result<Option<(String, String)>> {
@@ -219,51 +653,446 @@ impl Commit {
         Ok(split.map(|(prefix, payload)| (prefix.to_owned(), payload.trim().to_owned())))
     }
 
-    fn write_to_object_storage(&self) -> anyhow::Result<Sha> {
+    /// The content of the commit object, as it is hashed after the header.
+    pub fn serialize(&self) -> anyhow::Result<Vec<u8>> {
         let content: Vec<u8> = vec![];
         let mut content_writer = content.writer();
-        content_writer.write(b"tree ")?;
-        content_writer.write(ObjectStorage::sha_to_hex_string(&self.tree).as_bytes())?;
-        content_writer.write(b"\n")?;
+        content_writer.write_all(b"tree ")?;
+        content_writer.write_all(ObjectStorage::sha_to_hex_string(&self.tree).as_bytes())?;
+        content_writer.write_all(b"\n")?;
         for parent in &self.parents {
-            content_writer.write(b"parent ")?;
-            content_writer.write(ObjectStorage::sha_to_hex_string(parent).as_bytes())?;
-            content_writer.write(b"\n")?;
+            content_writer.write_all(b"parent ")?;
+            content_writer.write_all(ObjectStorage::sha_to_hex_string(parent).as_bytes())?;
+            content_writer.write_all(b"\n")?;
         }
-        let author_line = format!(
-            "author {} <{}> {} +{:04}\n",
-            "Ruben Bakker", "ruben@uncomplex.ch", 0, 0
-        );
-        content_writer.write(author_line.as_bytes())?;
-        let committer_line = format!(
-            "committer {} <{}> {} +{:04}\n",
-            "Ruben Bakker", "ruben@uncomplex.ch", 0, 0
+        let author_line = format!("author {}\n", self.author.to_header());
+        content_writer.write_all(author_line.as_bytes())?;
+        let committer_line = format!("committer {}\n", self.committer.to_header());
+        content_writer.write_all(committer_line.as_bytes())?;
+        content_writer.write_all(b"\n")?;
+        // An empty message is stored as nothing at all, as git does.
+        if !self.message.is_empty() {
+            content_writer.write_all(self.message.as_bytes())?;
+            content_writer.write_all(b"\n")?;
+        }
+        Ok(content_writer.into_inner())
+    }
+
+    pub fn write_to_object_storage(&self) -> anyhow::Result<Sha> {
+        ObjectStorage::write_typed_object("commit", &self.serialize()?)
+    }
+}
+
+impl Tag {
+    /// The id of the tagged object.
+    pub fn target_id(&self) -> Oid {
+        Oid(self.object)
+    }
+
+    fn from(content: &[u8]) -> anyhow::Result<Self> {
+        let mut reader: bytes::buf::Reader<&[u8]> = content.reader();
+        let mut object: Option<Sha> = None;
+        let mut object_type: Option<String> = None;
+        let mut name: Option<String> = None;
+        let mut tagger: Option<Signature> = None;
+        while let Some((prefix, payload)) = Commit::read_commit_line(&mut reader)? {
+            match prefix.as_str() {
+                "object" => object = Some(ObjectStorage::hex_string_to_sha(&payload)?),
+                "type" => object_type = Some(payload),
+                "tag" => name = Some(payload),
+                "tagger" => tagger = Some(Signature::parse(&payload)?),
+                _ => (),
+            }
+        }
+        let mut body: Vec<u8> = vec![];
+        _ = reader.read_to_end(&mut body);
+        let (message, signature) = split_signature(&body);
+        let mut message = String::from_utf8(message.to_vec())?;
+        if message.ends_with('\n') {
+            message.pop();
+        }
+        Ok(Tag {
+            object: object.ok_or(anyhow!("tag object has no object"))?,
+            object_type: object_type.ok_or(anyhow!("tag object has no type"))?,
+            name: name.ok_or(anyhow!("tag object has no tag name"))?,
+            tagger,
+            message,
+            signature: signature
+                .map(|signature| String::from_utf8(signature.to_vec()))
+                .transpose()?,
+        })
+    }
+
+    /// The content of the tag object, as it is hashed after the header.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut content = format!(
+            "object {}\ntype {}\ntag {}\n",
+            ObjectStorage::sha_to_hex_string(&self.object),
+            self.object_type,
+            self.name
         );
-        content_writer.write(committer_line.as_bytes())?;
-        content_writer.write(b"\n")?;
-        content_writer.write(&self.message.as_bytes())?;
-        content_writer.write(b"\n")?;
-        let content = content_writer.get_ref();
-        let header = ObjectStorage::header_for_content_length("commit", content.len())?;
-        let mut full_content: Vec<u8> = vec![];
-        full_content.write_all(header.as_slice())?;
-        full_content.write_all(content)?;
-        let hash = ObjectStorage::write_object(&full_content)?;
-        Ok(hash)
+        if let Some(tagger) = &self.tagger {
+            content.push_str(&format!("tagger {}\n", tagger.to_header()));
+        }
+        content.push('\n');
+        content.push_str(&self.message);
+        content.push('\n');
+        if let Some(signature) = &self.signature {
+            content.push_str(signature);
+        }
+        content.into_bytes()
+    }
+
+    pub fn write_to_object_storage(&self) -> anyhow::Result<Sha> {
+        ObjectStorage::write_typed_object("tag", &self.serialize())
+    }
+}
+
+/// The lines an armored signature starts with: OpenPGP, X.509 (CMS) and SSH.
+const SIGNATURE_MARKERS: [&str; 4] = [
+    "-----BEGIN PGP SIGNATURE-----",
+    "-----BEGIN PGP MESSAGE-----",
+    "-----BEGIN SIGNED MESSAGE-----",
+    "-----BEGIN SSH SIGNATURE-----",
+];
+
+/// Splits a tag's raw content (or its message) at the signature git appends to signed tags:
+/// the signed payload comes first, then the signature from the last line that starts one.
+pub fn split_signature(content: &[u8]) -> (&[u8], Option<&[u8]>) {
+    let mut start = None;
+    let mut offset = 0;
+    for line in content.split_inclusive(|&b| b == b'\n') {
+        if SIGNATURE_MARKERS
+            .iter()
+            .any(|marker| line.starts_with(marker.as_bytes()))
+        {
+            start = Some(offset);
+        }
+        offset += line.len();
+    }
+    match start {
+        Some(start) => (&content[..start], Some(&content[start..])),
+        None => (content, None),
+    }
+}
+
+/// Whether `hex` is a whole object id as git writes it: 40 lowercase hex digits.
+fn is_full_hex_id(hex: &str) -> bool {
+    hex.len() == 40
+        && hex
+            .bytes()
+            .all(|byte| byte.is_ascii_digit() || (b'a'..=b'f').contains(&byte))
+}
+
+/// Checks an identity line the way git's fsck does: `Name <email> <time> <+hhmm>`.
+fn check_ident(ident: &str) -> anyhow::Result<()> {
+    let invalid =
+        |id: &str, problem: &str| anyhow!("{}: invalid author/committer line - {}", id, problem);
+    let (name, rest) = ident
+        .split_once('<')
+        .ok_or_else(|| invalid("missingEmail", "missing email"))?;
+    if !name.is_empty() && !name.ends_with(' ') {
+        Err(invalid(
+            "missingSpaceBeforeEmail",
+            "missing space before email",
+        ))?
+    }
+    if name.contains('>') {
+        Err(invalid("badName", "bad name"))?
+    }
+    let (email, rest) = rest
+        .split_once('>')
+        .ok_or_else(|| invalid("badEmail", "bad email"))?;
+    if email.contains('<') {
+        Err(invalid("badEmail", "bad email"))?
+    }
+    let rest = rest
+        .strip_prefix(' ')
+        .ok_or_else(|| invalid("missingSpaceBeforeDate", "missing space before date"))?;
+    let (time, timezone) = rest
+        .split_once(' ')
+        .ok_or_else(|| invalid("badDate", "bad date"))?;
+    if time.is_empty() || !time.bytes().all(|byte| byte.is_ascii_digit()) {
+        Err(invalid("badDate", "bad date"))?
+    }
+    if time.len() > 1 && time.starts_with('0') {
+        Err(invalid("zeroPaddedDate", "zero-padded date"))?
+    }
+    let valid_timezone = timezone.len() == 5
+        && matches!(timezone.as_bytes()[0], b'+' | b'-')
+        && timezone[1..].bytes().all(|byte| byte.is_ascii_digit());
+    if !valid_timezone {
+        Err(invalid("badTimezone", "bad time zone"))?
+    }
+    Ok(())
+}
+
+impl Signature {
+    /// Parses `Name <email> 1700000000 +0200`.
+    pub fn parse(line: &str) -> anyhow::Result<Self> {
+        let invalid = || anyhow!("invalid signature '{}'", line);
+        let (name, rest) = line.split_once('<').ok_or_else(invalid)?;
+        let (email, rest) = rest.split_once('>').ok_or_else(invalid)?;
+        let mut fields = rest.split_whitespace();
+        let time = fields
+            .next()
+            .and_then(|time| time.parse().ok())
+            .ok_or_else(invalid)?;
+        let tz_offset = parse_tz_offset(fields.next().unwrap_or("+0000")).ok_or_else(invalid)?;
+        Ok(Self {
+            name: name.trim().to_string(),
+            email: email.to_string(),
+            time,
+            tz_offset,
+        })
+    }
+
+    /// The identity recorded as a commit's author: `GIT_AUTHOR_NAME`, `GIT_AUTHOR_EMAIL` and
+    /// `GIT_AUTHOR_DATE`, falling back to `user.name`/`user.email` and the current time.
+    pub fn author() -> anyhow::Result<Self> {
+        Self::from_environment("AUTHOR", "Author")
+    }
+
+    /// Like [`Signature::author`], with the `GIT_COMMITTER_*` variables.
+    pub fn committer() -> anyhow::Result<Self> {
+        Self::from_environment("COMMITTER", "Committer")
+    }
+
+    fn from_environment(variable: &str, role: &str) -> anyhow::Result<Self> {
+        let from_env = |field: &str| {
+            std::env::var(format!("GIT_{}_{}", variable, field))
+                .ok()
+                .filter(|value| !value.is_empty())
+        };
+        let config = Config::get()?;
+        let name = match from_env("NAME") {
+            Some(name) => Some(name),
+            None => config.value("user", "name"),
+        };
+        let email = match from_env("EMAIL") {
+            Some(email) => Some(email),
+            None => config.value("user", "email").or_else(|| {
+                std::env::var("EMAIL")
+                    .ok()
+                    .filter(|email| !email.is_empty())
+            }),
+        };
+        let (Some(name), Some(email)) = (name, email) else {
+            Err(anyhow!(
+                "{} identity unknown\n\n*** Please tell me who you are.\n\nRun\n\n  \
+                 git config --global --set user.email \"you@example.com\"\n  \
+                 git config --global --set user.name \"Your Name\"\n\nto set your account's \
+                 default identity.",
+                role
+            ))?
+        };
+        let (time, tz_offset) = match from_env("DATE") {
+            Some(date) => parse_date(&date)?,
+            None => {
+                let now = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)?
+                    .as_secs() as i64;
+                (now, local_tz_offset(now))
+            }
+        };
+        // Angle brackets and newlines would break the header line.
+        let sanitize = |value: &str| value.replace(['<', '>', '\n'], "").trim().to_string();
+        Ok(Self {
+            name: sanitize(&name),
+            email: sanitize(&email),
+            time,
+            tz_offset,
+        })
+    }
+
+    pub fn to_header(&self) -> String {
+        format!(
+            "{} <{}> {} {}",
+            self.name,
+            self.email,
+            self.time,
+            self.format_tz_offset()
+        )
+    }
+
+    fn format_tz_offset(&self) -> String {
+        let sign = if self.tz_offset < 0 { '-' } else { '+' };
+        let minutes = self.tz_offset.abs();
+        format!("{}{:02}{:02}", sign, minutes / 60, minutes % 60)
+    }
+
+    /// Formats the timestamp in the signature's own time zone like git's default date format,
+    /// e.g. `Wed Oct 14 15:59:55 2026 +0200`.
+    pub fn format_date(&self) -> String {
+        let local = self.time + self.tz_offset as i64 * 60;
+        let days = local.div_euclid(86400);
+        let seconds = local.rem_euclid(86400);
+        let (year, month, day) = civil_from_days(days);
+        format!(
+            "{} {} {} {:02}:{:02}:{:02} {} {}",
+            WEEKDAYS[days.rem_euclid(7) as usize],
+            MONTHS[month as usize - 1],
+            day,
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60,
+            year,
+            self.format_tz_offset()
+        )
+    }
+
+    /// Formats the timestamp like ISO 8601, as git's `iso` date format does, e.g.
+    /// `2026-10-14 15:59:55 +0200`.
+    pub fn format_iso_date(&self) -> String {
+        let (date, time) = self.local_date_time();
+        format!("{} {} {}", date, time, self.format_tz_offset())
+    }
+
+    /// Formats the timestamp in strict ISO 8601, e.g. `2026-10-14T15:59:55+02:00`.
+    pub fn format_iso_strict_date(&self) -> String {
+        let (date, time) = self.local_date_time();
+        let offset = self.format_tz_offset();
+        format!("{}T{}{}:{}", date, time, &offset[..3], &offset[3..])
+    }
+
+    /// The date and time of day in the signature's own time zone, as `2026-10-14` and
+    /// `15:59:55`.
+    fn local_date_time(&self) -> (String, String) {
+        let local = self.time + self.tz_offset as i64 * 60;
+        let seconds = local.rem_euclid(86400);
+        let (year, month, day) = civil_from_days(local.div_euclid(86400));
+        (
+            format!("{:04}-{:02}-{:02}", year, month, day),
+            format!(
+                "{:02}:{:02}:{:02}",
+                seconds / 3600,
+                seconds / 60 % 60,
+                seconds % 60
+            ),
+        )
+    }
+
+    /// Formats the timestamp like mail `Date` headers, e.g. `Wed, 14 Oct 2026 15:59:55 +0200`.
+    pub fn format_rfc2822_date(&self) -> String {
+        let local = self.time + self.tz_offset as i64 * 60;
+        let days = local.div_euclid(86400);
+        let seconds = local.rem_euclid(86400);
+        let (year, month, day) = civil_from_days(days);
+        format!(
+            "{}, {} {} {} {:02}:{:02}:{:02} {}",
+            WEEKDAYS[days.rem_euclid(7) as usize],
+            day,
+            MONTHS[month as usize - 1],
+            year,
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60,
+            self.format_tz_offset()
+        )
+    }
+}
+
+impl CheckoutReport {
+    fn new(total_files: usize) -> Self {
+        Self {
+            total_files,
+            files_written: 0,
+            bytes_written: 0,
+            failures: vec![],
+            symlinks: true,
+            symlink_fallbacks: 0,
+        }
+    }
+
+    fn record_file(&mut self, bytes: usize) {
+        self.files_written += 1;
+        self.bytes_written += bytes as u64;
+        self.print_progress();
+    }
+
+    fn record_failure(&mut self, path: &path::Path, message: String) {
+        self.failures.push((path.to_path_buf(), message));
+        self.print_progress();
+    }
+
+    fn processed(&self) -> usize {
+        self.files_written + self.failures.len()
+    }
+
+    fn print_progress(&self) {
+        let progress = Progress {
+            phase: Phase::UpdatingFiles,
+            done: self.processed() as u64,
+            total: Some(self.total_files as u64),
+            bytes: None,
+        };
+        progress::report(|reporter| reporter.progress(&progress));
+    }
+
+    fn finish(&self) {
+        self.print_progress();
+        progress::report(|reporter| reporter.finish(Phase::UpdatingFiles));
     }
 }
 
 impl ObjectStorage {
-    pub fn init_cwd() -> anyhow::Result<()> {
-        fs::create_dir(".git")?;
-        fs::create_dir(".git/objects")?;
-        fs::create_dir(".git/refs")?;
-        fs::write(".git/HEAD", "ref: refs/heads/main\n")?;
+    /// The directory of the repository the thread works in, the one whose
+    /// [`Repository`](crate::Repository) method is running; outside any, `.git` in the current
+    /// directory, where `init` creates it.
+    pub fn git_dir() -> PathBuf {
+        context::current().git_dir.clone()
+    }
+
+    /// The top of the working tree; without one it is the current directory.
+    pub fn work_tree() -> PathBuf {
+        context::current()
+            .work_tree
+            .clone()
+            .unwrap_or_else(|| PathBuf::from("."))
+    }
+
+    /// Whether the repository has a working tree; a bare one, or one used from inside its
+    /// git directory, has none.
+    pub fn has_work_tree() -> bool {
+        context::current().work_tree.is_some()
+    }
+
+    /// Creates the git directory, with `HEAD` on `initial_branch`, or else the branch
+    /// `init.defaultBranch` in the system or global config names, or else `main`.
+    pub fn init_cwd(initial_branch: Option<&str>) -> anyhow::Result<()> {
+        let branch = match initial_branch {
+            Some(branch) => {
+                refs::check_ref_format(&format!("refs/heads/{}", branch))
+                    .map_err(|_| anyhow!("invalid initial branch name: '{}'", branch))?;
+                branch.to_string()
+            }
+            None => match Config::get()?
+                .protected_values("init", "defaultBranch")
+                .pop()
+            {
+                Some(branch) => {
+                    refs::check_ref_format(&format!("refs/heads/{}", branch)).map_err(|_| {
+                        anyhow!("invalid branch name: init.defaultBranch = {}", branch)
+                    })?;
+                    branch
+                }
+                None => "main".to_string(),
+            },
+        };
+        let git_dir = Self::git_dir();
+        fs::create_dir(&git_dir)?;
+        fs::create_dir(git_dir.join("objects"))?;
+        fs::create_dir(git_dir.join("refs"))?;
+        fs::write(
+            git_dir.join("HEAD"),
+            format!("ref: refs/heads/{}\n", branch),
+        )?;
         Ok(())
     }
+
     pub fn get_dir_for_hash(hash: &str) -> anyhow::Result<PathBuf> {
         let dir = hash.get(0..2).ok_or(anyhow!("invalid hex"))?;
-        let dir_path = path::Path::new(".git").join("objects").join(dir);
+        let dir_path = Self::git_dir().join("objects").join(dir);
         Ok(dir_path)
     }
 
@@ -273,78 +1102,298 @@ impl ObjectStorage {
         Ok(file_path)
     }
 
-    pub fn write_object(content: &[u8]) -> anyhow::Result<Sha> {
-        let hash: Sha = Sha1::digest(&content).to_vec().try_into().unwrap();
-        let hash_string = Self::sha_to_hex_string(&hash);
-        let mut e = ZlibEncoder::new(Vec::new(), Compression::default());
-        e.write_all(content.as_ref())?;
-        let compressed = e.finish()?;
-        let dir_path = ObjectStorage::get_dir_for_hash(hash_string.as_str())?;
-        if !dir_path.exists() {
-            let _result = fs::create_dir(dir_path)?;
+    /// Returns the type and content of the object `sha`, looking in the packs when there is
+    /// no loose object.
+    pub fn read_object(sha: &Sha) -> anyhow::Result<(String, Vec<u8>)> {
+        let object = Self::open_object(sha)?;
+        let object_type = object.object_type.clone();
+        Ok((object_type, object.into_content()?))
+    }
+
+    /// Opens the object `sha` for streaming its content. Packed objects may be deltas, which
+    /// are resolved in memory.
+    pub fn open_object(sha: &Sha) -> anyhow::Result<ObjectReader> {
+        usage::object_read();
+        let hash = Self::sha_to_hex_string(sha);
+        if let Some(object) = ObjectReader::open_if_exists(&Self::get_path_for_hash(&hash)?)? {
+            return Ok(object);
+        }
+        let (object_type, content) =
+            pack::read_object(sha)?.ok_or(anyhow!("object {} not found", hash))?;
+        Ok(ObjectReader::from_content(object_type, content))
+    }
+
+    /// Whether the object `sha` is in the repository, loose or packed.
+    pub fn contains(sha: &Sha) -> anyhow::Result<bool> {
+        let path = Self::get_path_for_hash(&Self::sha_to_hex_string(sha))?;
+        Ok(path.exists() || pack::contains(sha)?)
+    }
+
+    /// The object `sha` as it is stored: the loose object's file if there is one, otherwise
+    /// its entry in the first pack that has it.
+    pub fn raw_object(sha: &Sha) -> anyhow::Result<RawObject> {
+        let hash = Self::sha_to_hex_string(sha);
+        let path = Self::get_path_for_hash(&hash)?;
+        if path.exists() {
+            return Ok(RawObject::Loose(fs::read(&path)?));
         }
-        let output_file_path = ObjectStorage::get_path_for_hash(hash_string.as_str())?;
-        let mut output_file = File::create(output_file_path)?;
-        output_file.write_all(compressed.as_ref())?;
-        Ok(hash)
+        let entry = pack::find_raw_entry(sha)?.ok_or(anyhow!("object {} not found", hash))?;
+        Ok(RawObject::Packed(entry))
+    }
+
+    /// The loose objects with their files, going by the names of the files under
+    /// `objects/xx/`.
+    pub fn loose_objects() -> anyhow::Result<Vec<(Sha, PathBuf)>> {
+        let is_hex = |name: &str, length| {
+            name.len() == length && name.bytes().all(|byte| byte.is_ascii_hexdigit())
+        };
+        let mut objects = vec![];
+        for dir in fs::read_dir(Self::git_dir().join("objects"))?.flatten() {
+            let dir_name = dir.file_name().to_string_lossy().to_string();
+            if !is_hex(&dir_name, 2) {
+                continue;
+            }
+            for file in fs::read_dir(dir.path())?.flatten() {
+                let name = file.file_name().to_string_lossy().to_string();
+                if is_hex(&name, 38) {
+                    let sha = Self::hex_string_to_sha(&format!("{}{}", dir_name, name))?;
+                    objects.push((sha, file.path()));
+                }
+            }
+        }
+        Ok(objects)
+    }
+
+    /// The loose and packed objects whose id starts with the hex digits `prefix`, sorted
+    /// and without duplicates.
+    pub fn find_objects_by_prefix(prefix: &str) -> anyhow::Result<Vec<Sha>> {
+        let prefix = prefix.to_ascii_lowercase();
+        let mut found = std::collections::BTreeSet::new();
+        if prefix.len() >= 2 {
+            let dir = Self::git_dir().join("objects").join(&prefix[..2]);
+            if let Ok(entries) = fs::read_dir(dir) {
+                for entry in entries.flatten() {
+                    let name = entry.file_name().to_string_lossy().to_string();
+                    let hex = format!("{}{}", &prefix[..2], name);
+                    if hex.starts_with(&prefix)
+                        && let Ok(sha) = Self::hex_string_to_sha(&hex)
+                    {
+                        found.insert(sha);
+                    }
+                }
+            }
+        }
+        // Pack indexes are sorted: the matches follow the prefix padded with zeros.
+        let lowest = Self::hex_string_to_sha(&format!("{:0<40}", prefix))?;
+        for pack in pack::loaded_packs()?.iter() {
+            let shas = pack.shas();
+            let start = shas.partition_point(|sha| *sha < lowest);
+            found.extend(
+                shas[start..]
+                    .iter()
+                    .take_while(|sha| Self::sha_to_hex_string(sha).starts_with(&prefix)),
+            );
+        }
+        Ok(found.into_iter().collect())
+    }
+
+    pub fn hash_content(content: &[u8]) -> Sha {
+        Sha1::digest(content).to_vec().try_into().unwrap()
+    }
+
+    /// Hashes `content` as an object of `object_type`, optionally writing it to the store.
+    /// Unless `literally` is set, the type must be known and the content must parse as that type.
+    pub fn hash_object(
+        object_type: &str,
+        content: &[u8],
+        write: bool,
+        literally: bool,
+    ) -> anyhow::Result<Sha> {
+        if !literally {
+            match object_type {
+                "blob" => (),
+                "tree" => _ = Tree::from(content, false)?,
+                "commit" => _ = Commit::from(content, false)?,
+                "tag" => _ = Tag::from(content)?,
+                _ => Err(anyhow!("invalid object type \"{}\"", object_type))?,
+            }
+        }
+        let mut content = content;
+        Self::stream_object(object_type, content.len() as u64, &mut content, write)
+    }
+
+    /// Hashes the `size` bytes from `reader` as an object of `object_type` and optionally
+    /// writes it, without holding more than a small buffer of it in memory.
+    pub fn stream_object(
+        object_type: &str,
+        size: u64,
+        reader: &mut impl Read,
+        write: bool,
+    ) -> anyhow::Result<Sha> {
+        let mut writer = ObjectWriter::new(write)?;
+        writer.write_all(&Self::header_for_content_length(
+            object_type,
+            size as usize,
+        )?)?;
+        let copied = std::io::copy(&mut reader.take(size), &mut writer)?;
+        if copied != size {
+            Err(anyhow!(
+                "content shrank from {} to {} bytes while reading",
+                size,
+                copied
+            ))?
+        }
+        writer.finish()
+    }
+
+    /// Writes an object given with its header; returns its id.
+    pub fn write_object(content: &[u8]) -> anyhow::Result<Sha> {
+        let mut writer = ObjectWriter::new(true)?;
+        writer.write_all(content)?;
+        writer.finish()
+    }
+
+    /// Writes `content` as an object of `object_type`, its header going to the writer ahead
+    /// of it rather than into a copy of it; returns its id.
+    pub fn write_typed_object(object_type: &str, content: &[u8]) -> anyhow::Result<Sha> {
+        let mut writer = ObjectWriter::new(true)?;
+        writer.write_all(&Self::header_for_content_length(
+            object_type,
+            content.len(),
+        )?)?;
+        writer.write_all(content)?;
+        writer.finish()
     }
 
     pub fn header_for_content_length(header_type: &str, length: usize) -> anyhow::Result<Vec<u8>> {
-        Ok(format!("{} {}\0", header_type, length)
-            .as_bytes()
-            .to_vec()
-            .clone())
+        Ok(format!("{} {}\0", header_type, length).into_bytes())
     }
 
+    /// Writes the tree staged in the index. A repository that has never had anything added
+    /// has no index file; then the working tree is snapshotted as a whole instead.
     pub fn write_tree_cwd() -> anyhow::Result<Sha> {
-        Self::write_tree(&PathBuf::from("."))
+        if Index::path().exists() {
+            Self::write_index_tree(&Index::read()?)
+        } else {
+            Self::write_tree(&Self::work_tree(), "", &IgnoreRules::new()?)
+        }
     }
 
-    pub fn write_tree(path: &PathBuf) -> anyhow::Result<Sha> {
-        let dir = fs::read_dir(&path)?;
+    pub fn write_index_tree(index: &Index) -> anyhow::Result<Sha> {
+        if let Some(entry) = index.entries.iter().find(|entry| entry.stage != 0) {
+            Err(anyhow!("{}: unmerged entry, cannot write tree", entry.path))?
+        }
+        Self::write_index_subtree(&index.entries, 0)
+    }
+
+    /// Writes the tree for `entries`, which all share a directory prefix of `prefix_len` bytes.
+    /// Since the index is sorted by path, each subdirectory is a contiguous run of entries.
+    fn write_index_subtree(entries: &[IndexEntry], prefix_len: usize) -> anyhow::Result<Sha> {
         let mut tree_entries: Vec<TreeEntry> = vec![];
-        for entry in dir {
-            if let Ok(entry) = entry {
-                if entry.file_name() == ".git" {
-                    continue;
-                }
-                let file_name = entry.file_name().to_str().unwrap().to_string();
-                let file_type = entry.file_type()?;
-                if file_type.is_dir() {
-                    let hash = Self::write_tree(&entry.path())?;
+        let mut i = 0;
+        while i < entries.len() {
+            let entry = &entries[i];
+            let rest = &entry.path[prefix_len..];
+            match rest.split_once('/') {
+                Some((dir, _)) => {
+                    let dir_prefix = format!("{}/", &entry.path[..prefix_len + dir.len()]);
+                    let end = i + entries[i..]
+                        .iter()
+                        .take_while(|entry| entry.path.starts_with(&dir_prefix))
+                        .count();
+                    let hash = Self::write_index_subtree(&entries[i..end], dir_prefix.len())?;
                     tree_entries.push(TreeEntry {
                         permission: TreeEntryPermission::Directory,
-                        name: file_name,
+                        name: dir.to_string(),
                         hash,
                     });
-                } else {
-                    let blob = Blob::new_with_file_path(&entry.path())?;
-                    let hash = blob.write_to_object_storage()?;
-                    (tree_entries).push(TreeEntry {
-                        permission: TreeEntryPermission::RegularFile,
-                        name: file_name,
-                        hash,
+                    i = end;
+                }
+                None => {
+                    let permission = index::mode_permission(entry.mode).ok_or(anyhow!(
+                        "{}: unsupported mode {:o}",
+                        entry.path,
+                        entry.mode
+                    ))?;
+                    tree_entries.push(TreeEntry {
+                        permission,
+                        name: rest.to_string(),
+                        hash: entry.sha,
                     });
+                    i += 1;
                 }
             }
         }
-        tree_entries.sort_by(|a, b| a.name.cmp(&b.name));
-        // TDO
-        let tree = Tree {
+        Tree {
             entries: tree_entries,
+        }
+        .write_to_object_storage()
+    }
+
+    /// Snapshots the directory `path` (at `prefix` in the working tree) as a tree, leaving out
+    /// what the ignore rules of its parent, `ignore`, and its own `.gitignore` exclude.
+    pub fn write_tree(path: &PathBuf, prefix: &str, ignore: &IgnoreRules) -> anyhow::Result<Sha> {
+        let tree = Tree {
+            entries: Self::work_tree_entries(path, prefix, ignore)?,
         };
         tree.write_to_object_storage()
     }
 
+    /// The entries of the tree for the directory `path`, writing blobs and subtrees on the way.
+    /// Directories left without any files are left out, as git has no empty subtrees.
+    fn work_tree_entries(
+        path: &PathBuf,
+        prefix: &str,
+        ignore: &IgnoreRules,
+    ) -> anyhow::Result<Vec<TreeEntry>> {
+        let ignore = ignore.enter(path, prefix)?;
+        let dir = fs::read_dir(path)?;
+        let mut tree_entries: Vec<TreeEntry> = vec![];
+        for entry in dir.flatten() {
+            if work_tree::is_excluded(&entry.file_name()) {
+                continue;
+            }
+            let file_name = entry.file_name().to_str().unwrap().to_string();
+            let file_type = entry.file_type()?;
+            if ignore.is_ignored(&format!("{}{}", prefix, file_name), file_type.is_dir()) {
+                continue;
+            }
+            if file_type.is_dir() {
+                let subdir_prefix = format!("{}{}/", prefix, file_name);
+                let entries = Self::work_tree_entries(&entry.path(), &subdir_prefix, &ignore)?;
+                if entries.is_empty() {
+                    continue;
+                }
+                let hash = Tree { entries }.write_to_object_storage()?;
+                tree_entries.push(TreeEntry {
+                    permission: TreeEntryPermission::Directory,
+                    name: file_name,
+                    hash,
+                });
+            } else {
+                let hash = Blob::hash_file(&entry.path(), true)?;
+                (tree_entries).push(TreeEntry {
+                    permission: TreeEntryPermission::RegularFile,
+                    name: file_name,
+                    hash,
+                });
+            }
+        }
+        tree_entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(tree_entries)
+    }
+
+    /// Writes a commit of the tree `tree_sha` with the author and committer `commit` uses.
     pub(crate) fn commit_tree(
         tree_sha: &Sha,
-        parent_sha: &Sha,
+        parents: &[Sha],
         message: &str,
     ) -> anyhow::Result<Sha> {
-        let parents: Vec<Sha> = vec![parent_sha.to_owned()];
         let commit = Commit {
             tree: tree_sha.to_owned(),
-            parents,
+            parents: parents.to_vec(),
+            author: Signature::author()?,
+            committer: Signature::committer()?,
             message: String::from(message),
         };
         commit.write_to_object_storage()
@@ -355,55 +1404,634 @@ impl ObjectStorage {
     }
 
     pub fn hex_string_to_sha(hex_str: &str) -> anyhow::Result<Sha> {
+        if hex_str.len() != 40 {
+            Err(anyhow!("invalid object id '{}'", hex_str))?
+        }
         let mut sha = [0u8; 20];
         base16ct::mixed::decode(hex_str, &mut sha)?;
         Ok(sha)
     }
 
-    pub fn checkout_sha(path: &PathBuf, sha: &Sha) -> anyhow::Result<()> {
-        let object = ObjectStorage::git_object_from_sha(sha)?;
-        match object {
-            GitObject::Tree(tree) => {
-                for entry in &tree.entries {
-                    match entry.permission {
-                        TreeEntryPermission::Directory => {
-                            let mut dir = path.clone();
-                            dir.push(&entry.name);
-                            std::fs::create_dir(&dir)?;
-                            Self::checkout_sha(&dir, &entry.hash)?;
-                        }
-                        TreeEntryPermission::RegularFile => {
-                            let mut filepath = path.clone();
-                            filepath.push(&entry.name);
-                            Self::checkout_sha(&filepath, &entry.hash)?;
-                        }
-                        TreeEntryPermission::SymbolicLink => todo!(),
-                        TreeEntryPermission::Executable => todo!(),
+    pub fn checkout_sha(
+        path: &path::Path,
+        sha: &Sha,
+        report: &mut CheckoutReport,
+    ) -> anyhow::Result<()> {
+        let mut walk = TreeWalk::new((*sha).into());
+        while let Some(entry) = walk.next() {
+            let (entry_path, entry) = entry?;
+            let entry_path = path.join(entry_path);
+            match entry.permission {
+                TreeEntryPermission::Directory => {
+                    if let Err(err) = create_dir_replacing_file(&entry_path) {
+                        report.record_failure(&entry_path, err.to_string());
+                        walk.skip_subtree();
                     }
                 }
-                Ok(())
+                _ => Self::checkout_entry(&entry_path, &entry.permission, &entry.hash, report)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the blob `sha` to `path` as a file, executable or symlink depending on
+    /// `permission`, replacing a file, symlink or empty directory already there.
+    fn checkout_entry(
+        path: &path::Path,
+        permission: &TreeEntryPermission,
+        sha: &Sha,
+        report: &mut CheckoutReport,
+    ) -> anyhow::Result<()> {
+        cancel::check()?;
+        if let Err(err) = clear_path(path) {
+            report.record_failure(path, err.to_string());
+            return Ok(());
+        }
+        match permission {
+            TreeEntryPermission::SymbolicLink => {
+                let GitObject::Blob(blob) = Self::git_object_from_sha(sha)? else {
+                    report.record_failure(path, "symlink target is not a blob".to_string());
+                    return Ok(());
+                };
+                Self::checkout_symlink(path, &blob.content, report)
             }
-            GitObject::Blob(blob) => {
-                let mut file = File::create(path)?;
-                file.write_all(&blob.content)?;
+            _ => {
+                let mut blob = Self::open_object(sha)?;
+                if blob.object_type != "blob" {
+                    report.record_failure(path, "file content is not a blob".to_string());
+                    return Ok(());
+                }
+                let executable = matches!(permission, TreeEntryPermission::Executable);
+                let filters = Filters::for_file(path)?;
+                let written = if filters.is_empty() {
+                    File::create(path).and_then(|mut file| std::io::copy(&mut blob, &mut file))
+                } else {
+                    let content = filters.to_work_tree(blob.into_content()?, sha);
+                    fs::write(path, &content).map(|()| content.len() as u64)
+                };
+                match written.and_then(|written| set_executable(path, executable).map(|()| written))
+                {
+                    Ok(written) => report.record_file(written as usize),
+                    Err(err) => report.record_failure(path, err.to_string()),
+                }
                 Ok(())
             }
-            GitObject::Commit(_) => Ok(()),
         }
     }
 
-    pub(crate) fn checkout(sha: &Sha) -> anyhow::Result<()> {
+    /// Like `checkout_entry`, with the blob `sha` already read into `content`.
+    fn checkout_content(
+        path: &path::Path,
+        permission: &TreeEntryPermission,
+        sha: &Sha,
+        object_type: &str,
+        content: &[u8],
+        report: &mut CheckoutReport,
+    ) -> anyhow::Result<()> {
+        cancel::check()?;
+        if let Err(err) = clear_path(path) {
+            report.record_failure(path, err.to_string());
+            return Ok(());
+        }
+        if object_type != "blob" {
+            report.record_failure(path, "file content is not a blob".to_string());
+            return Ok(());
+        }
+        if matches!(permission, TreeEntryPermission::SymbolicLink) {
+            return Self::checkout_symlink(path, content, report);
+        }
+        let executable = matches!(permission, TreeEntryPermission::Executable);
+        let filters = Filters::for_file(path)?;
+        let written = if filters.is_empty() {
+            fs::write(path, content).map(|()| content.len())
+        } else {
+            let content = filters.to_work_tree(content.to_vec(), sha);
+            fs::write(path, &content).map(|()| content.len())
+        };
+        match written.and_then(|written| set_executable(path, executable).map(|()| written)) {
+            Ok(written) => report.record_file(written),
+            Err(err) => report.record_failure(path, err.to_string()),
+        }
+        Ok(())
+    }
+
+    fn checkout_symlink(
+        path: &path::Path,
+        target: &[u8],
+        report: &mut CheckoutReport,
+    ) -> anyhow::Result<()> {
+        if report.symlinks {
+            let link = PathBuf::from(std::str::from_utf8(target)?);
+            match create_symlink(&link, path) {
+                Ok(()) => {
+                    report.record_file(target.len());
+                    return Ok(());
+                }
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        std::io::ErrorKind::Unsupported | std::io::ErrorKind::PermissionDenied
+                    ) =>
+                {
+                    report.symlinks = false;
+                }
+                Err(err) => {
+                    report.record_failure(path, err.to_string());
+                    return Ok(());
+                }
+            }
+        }
+        // Without symlink support the link target is written as a plain file, like git does.
+        match File::create(path).and_then(|mut file| file.write_all(target)) {
+            Ok(()) => {
+                report.symlink_fallbacks += 1;
+                report.record_file(target.len());
+            }
+            Err(err) => report.record_failure(path, err.to_string()),
+        }
+        Ok(())
+    }
+
+    pub(crate) fn checkout(sha: &Sha) -> anyhow::Result<CheckoutReport> {
         if let GitObject::Commit(commit) = Self::git_object_from_sha(sha)? {
-            let path = std::path::absolute(".")?;
-            ObjectStorage::checkout_sha(&path, &commit.tree)?;
-            Ok(())
+            let path = std::path::absolute(Self::work_tree())?;
+            let total_files = Self::count_tree_files(&commit.tree)?;
+            Self::checkout_with_report(total_files, |report| {
+                ObjectStorage::checkout_sha(&path, &commit.tree, report)
+            })
         } else {
             Err(anyhow!("{:?} isn't a commit", sha))
         }
     }
 
+    /// Writes only the given files (paths relative to the top of the working tree), creating
+    /// missing parent directories; used when switching between two commits.
+    pub(crate) fn checkout_files(
+        files: &[(String, TreeEntryPermission, Sha)],
+    ) -> anyhow::Result<CheckoutReport> {
+        let work_tree = std::path::absolute(Self::work_tree())?;
+        Self::checkout_with_report(files.len(), |report| {
+            // Paths to write for each blob, in the order the blobs are first needed.
+            let mut wanted: HashMap<Sha, Vec<(PathBuf, &TreeEntryPermission)>> = HashMap::new();
+            let mut blobs = vec![];
+            for (file, permission, sha) in files {
+                let path = work_tree.join(file);
+                let mut parent = work_tree.clone();
+                let mut components: Vec<&str> = file.split('/').collect();
+                components.pop();
+                let mut created = true;
+                for component in components {
+                    parent.push(component);
+                    if let Err(err) = create_dir_replacing_file(&parent) {
+                        report.record_failure(&path, err.to_string());
+                        created = false;
+                        break;
+                    }
+                }
+                if created {
+                    let paths = wanted.entry(*sha).or_default();
+                    if paths.is_empty() {
+                        blobs.push(*sha);
+                    }
+                    paths.push((path, permission));
+                }
+            }
+            // Packed blobs are read in the order the packs store them and written as they come,
+            // which restores many files far quicker than looking each up on its own.
+            let big_file_threshold = Config::get()?.big_file_threshold()?;
+            let rest = pack::read_in_pack_order(
+                &blobs,
+                big_file_threshold,
+                |sha, object_type, content| {
+                    for (path, permission) in &wanted[sha] {
+                        Self::checkout_content(
+                            path,
+                            permission,
+                            sha,
+                            object_type,
+                            &content,
+                            report,
+                        )?;
+                    }
+                    Ok(())
+                },
+            )?;
+            for sha in rest {
+                for (path, permission) in &wanted[&sha] {
+                    Self::checkout_entry(path, permission, &sha, report)?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn checkout_with_report(
+        total_files: usize,
+        write: impl FnOnce(&mut CheckoutReport) -> anyhow::Result<()>,
+    ) -> anyhow::Result<CheckoutReport> {
+        let mut report = CheckoutReport::new(total_files);
+        let symlinks_configured = !Self::core_symlinks_disabled()?;
+        report.symlinks = symlinks_configured;
+        write(&mut report)?;
+        report.finish();
+        pack::trace_delta_base_cache();
+        if symlinks_configured && !report.symlinks {
+            Self::record_symlinks_disabled()?;
+        }
+        Ok(report)
+    }
+
+    /// Refuses to operate on a repository owned by someone else unless it is listed in
+    /// `safe.directory`, like git does since CVE-2022-24765.
+    pub fn ensure_safe_directory() -> anyhow::Result<()> {
+        Self::ensure_listed_safe(&Config::get()?.protected_values("safe", "directory"))
+    }
+
+    /// Like [`ensure_safe_directory`](Self::ensure_safe_directory), with the values of
+    /// `safe.directory`, for reading the config that has them.
+    pub fn ensure_listed_safe(safe_directories: &[String]) -> anyhow::Result<()> {
+        let git_dir = Self::git_dir();
+        if !git_dir.exists() {
+            return Ok(());
+        }
+        let Some((owner, current_user)) = foreign_owner(&git_dir)? else {
+            return Ok(());
+        };
+        let work_tree = Self::work_tree().canonicalize()?;
+        let directory = work_tree.to_string_lossy().to_string();
+        let mut allowed = false;
+        for value in safe_directories {
+            // An empty value resets the list accumulated so far.
+            allowed = match value.as_str() {
+                "" => false,
+                "*" => true,
+                value => {
+                    allowed
+                        || value.trim_end_matches('/') == directory
+                        || value
+                            .strip_suffix("/*")
+                            .is_some_and(|prefix| directory.starts_with(&format!("{}/", prefix)))
+                }
+            };
+        }
+        if allowed {
+            return Ok(());
+        }
+        Err(anyhow!(
+            "detected dubious ownership in repository at '{directory}'\n\
+             '{directory}' is owned by:\n\tuid {owner}\n\
+             but the current user is:\n\tuid {current_user}\n\
+             To add an exception for this directory, call:\n\n\
+             \tgit config --global --add safe.directory {directory}"
+        ))
+    }
+
+    pub fn core_symlinks_disabled() -> anyhow::Result<bool> {
+        Ok(Config::get()?.bool("core", "symlinks") == Some(false))
+    }
+
+    fn record_symlinks_disabled() -> anyhow::Result<()> {
+        config::set_value(
+            &Self::git_dir().join("config"),
+            "core",
+            "symlinks",
+            Some("false"),
+        )
+    }
+
+    fn count_tree_files(sha: &Sha) -> anyhow::Result<usize> {
+        let mut count = 0;
+        for entry in TreeWalk::new((*sha).into()) {
+            if entry?.1.permission != TreeEntryPermission::Directory {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Writes `info/refs` and `objects/info/packs` so the repository can be served over dumb HTTP.
+    pub fn update_server_info() -> anyhow::Result<()> {
+        let git_dir = Self::git_dir();
+        let mut info_refs = String::new();
+        for (name, sha) in &refs::list_refs()? {
+            info_refs.push_str(&format!("{}\t{}\n", Self::sha_to_hex_string(sha), name));
+            // Annotated tags are followed by the object they point to, like in packed-refs.
+            let peeled = Self::peel_tag(sha)?;
+            if peeled != *sha {
+                info_refs.push_str(&format!(
+                    "{}\t{}^{{}}\n",
+                    Self::sha_to_hex_string(&peeled),
+                    name
+                ));
+            }
+        }
+        fs::create_dir_all(git_dir.join("info"))?;
+        fs::write(git_dir.join("info").join("refs"), info_refs)?;
+
+        let mut packs: Vec<String> = vec![];
+        let pack_dir = git_dir.join("objects").join("pack");
+        if pack_dir.exists() {
+            for entry in fs::read_dir(pack_dir)?.flatten() {
+                let file_name = entry.file_name().to_string_lossy().to_string();
+                if file_name.ends_with(".pack") {
+                    packs.push(file_name);
+                }
+            }
+        }
+        packs.sort();
+        let mut info_packs = String::new();
+        for pack in &packs {
+            info_packs.push_str(&format!("P {}\n", pack));
+        }
+        info_packs.push('\n');
+        let objects_info_dir = git_dir.join("objects").join("info");
+        fs::create_dir_all(&objects_info_dir)?;
+        fs::write(objects_info_dir.join("packs"), info_packs)?;
+        Ok(())
+    }
+
+    /// Follows annotated tags (and tags of tags) to the object they finally point to; any
+    /// other object is returned as is.
+    pub fn peel_tag(sha: &Sha) -> anyhow::Result<Sha> {
+        let mut sha = *sha;
+        for _ in 0..MAX_TAG_DEPTH {
+            let GitObject::Tag(tag) = Self::git_object_from_sha(&sha)? else {
+                return Ok(sha);
+            };
+            let (actual_type, _) = Self::read_object(&tag.object)?;
+            if actual_type != tag.object_type {
+                Err(anyhow!(
+                    "object {} is a {}, not a {}",
+                    Self::sha_to_hex_string(&tag.object),
+                    actual_type,
+                    tag.object_type
+                ))?
+            }
+            sha = tag.object;
+        }
+        Err(anyhow!(
+            "tag chain at {} is too deep",
+            Self::sha_to_hex_string(&sha)
+        ))
+    }
+
+    /// The commit of a commit-ish: a commit itself, or the commit an annotated tag points to.
+    pub fn peel_to_commit(sha: &Sha) -> anyhow::Result<Sha> {
+        let peeled = Self::peel_tag(sha)?;
+        match Self::git_object_from_sha(&peeled)? {
+            GitObject::Commit(_) => Ok(peeled),
+            _ => Err(anyhow!("{} is not a commit", Self::sha_to_hex_string(sha))),
+        }
+    }
+
+    /// The tree of a tree-ish: a tree itself, the tree of a commit, or either of those behind
+    /// an annotated tag.
+    pub fn peel_to_tree(sha: &Sha) -> anyhow::Result<Sha> {
+        match Self::git_object_from_sha(&Self::peel_tag(sha)?)? {
+            GitObject::Tree(_) => Ok(Self::peel_tag(sha)?),
+            GitObject::Commit(commit) => Ok(commit.tree),
+            GitObject::Blob(_) | GitObject::Tag(_) => Err(anyhow!(
+                "{} is not a tree object",
+                Self::sha_to_hex_string(sha)
+            )),
+        }
+    }
+
     fn git_object_from_sha(sha: &Sha) -> anyhow::Result<GitObject> {
-        let file_path = ObjectStorage::get_path_for_hash(&ObjectStorage::sha_to_hex_string(sha))?;
-        GitObject::from_file_path(&file_path)
+        GitObject::from_sha(sha)
     }
 }
+
+/// Converts days since the Unix epoch to a (year, month, day) date in the proleptic Gregorian
+/// calendar (Howard Hinnant's `civil_from_days`).
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_index = if month > 2 { month - 3 } else { month + 9 } as i64;
+    let day_of_year = (153 * month_index + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Parses `+0200`, `-0430` or `+05:30` into minutes east of UTC.
+fn parse_tz_offset(tz: &str) -> Option<i32> {
+    let (sign, digits) = tz.split_at_checked(1)?;
+    let digits = digits.replace(':', "");
+    if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let digits: i32 = digits.parse().ok()?;
+    let minutes = digits / 100 * 60 + digits % 100;
+    match sign {
+        "+" => Some(minutes),
+        "-" => Some(-minutes),
+        _ => None,
+    }
+}
+
+/// Parses the date formats git accepts in `GIT_AUTHOR_DATE` and `GIT_COMMITTER_DATE`: its
+/// internal `<seconds> <offset>` (optionally with a leading `@`), ISO 8601 like
+/// `2026-10-14T15:59:55+0200` or `2026-10-14 15:59:55 +02:00`, and RFC 2822 like
+/// `Wed, 14 Oct 2026 15:59:55 +0200`, as mail `Date` headers have. A time without an offset
+/// is taken as local time.
+pub fn parse_date(date: &str) -> anyhow::Result<(i64, i32)> {
+    let invalid = || anyhow!("invalid date format: {}", date);
+    let date = date.trim();
+    if let Some((seconds, tz)) = date.trim_start_matches('@').split_once(' ')
+        && let Ok(seconds) = seconds.parse::<i64>()
+    {
+        return Ok((seconds, parse_tz_offset(tz.trim()).ok_or_else(invalid)?));
+    }
+    if let Some(date) = parse_rfc2822_date(date) {
+        return Ok(date);
+    }
+    let (day, rest) = date.split_once(['T', ' ']).ok_or_else(invalid)?;
+    let rest = rest.trim_start();
+    let time_end = rest
+        .find(|c: char| !(c.is_ascii_digit() || c == ':'))
+        .unwrap_or(rest.len());
+    let (time, tz) = rest.split_at(time_end);
+    let numbers = |text: &str, separator: char| -> Option<Vec<i64>> {
+        text.split(separator).map(|n| n.parse().ok()).collect()
+    };
+    let (Some([year, month, day]), Some([hour, minute, second])) = (
+        numbers(day, '-').and_then(|n| <[i64; 3]>::try_from(n).ok()),
+        numbers(time, ':').and_then(|n| <[i64; 3]>::try_from(n).ok()),
+    ) else {
+        Err(invalid())?
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        Err(invalid())?
+    }
+    let local = days_from_civil(year, month as u32, day as u32) * 86400
+        + hour * 3600
+        + minute * 60
+        + second;
+    let tz = tz.trim();
+    let tz_offset = match tz {
+        "" => local_tz_offset(local),
+        "Z" => 0,
+        tz => parse_tz_offset(tz).ok_or_else(invalid)?,
+    };
+    Ok((local - tz_offset as i64 * 60, tz_offset))
+}
+
+/// `[<weekday>,] <day> <month> <year> <hh>:<mm>[:<ss>] <offset>`, with an optional comment
+/// such as `(CEST)` after the offset.
+fn parse_rfc2822_date(date: &str) -> Option<(i64, i32)> {
+    let date = date.split_once(',').map_or(date, |(_, rest)| rest);
+    let mut fields = date.split_whitespace();
+    let day: u32 = fields.next()?.parse().ok()?;
+    let month = fields.next()?;
+    let month = MONTHS
+        .iter()
+        .position(|name| name.eq_ignore_ascii_case(month))? as u32
+        + 1;
+    let year: i64 = fields.next()?.parse().ok()?;
+    let time: Vec<i64> = fields
+        .next()?
+        .split(':')
+        .map(|n| n.parse().ok())
+        .collect::<Option<_>>()?;
+    let (hour, minute, second) = match time.as_slice() {
+        [hour, minute] => (*hour, *minute, 0),
+        [hour, minute, second] => (*hour, *minute, *second),
+        _ => return None,
+    };
+    let tz_offset = match fields.next()? {
+        "GMT" | "UT" | "UTC" | "Z" => 0,
+        tz => parse_tz_offset(tz)?,
+    };
+    if !(1..=31).contains(&day) || hour > 23 || minute > 59 {
+        return None;
+    }
+    let local = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    Some((local - tz_offset as i64 * 60, tz_offset))
+}
+
+/// The local time zone's UTC offset in minutes at `time`.
+#[cfg(unix)]
+fn local_tz_offset(time: i64) -> i32 {
+    // SAFETY: localtime_r only writes to the tm struct passed in.
+    unsafe {
+        let time = time as libc::time_t;
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&time, &mut tm).is_null() {
+            return 0;
+        }
+        (tm.tm_gmtoff / 60) as i32
+    }
+}
+
+#[cfg(not(unix))]
+fn local_tz_offset(_time: i64) -> i32 {
+    0
+}
+
+/// Expands a leading `~/` to the home directory.
+pub fn expand_home_dir(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Returns the owner and the current user when `path` is owned by another user. When running
+/// as root through sudo, the invoking user (`SUDO_UID`) is compared instead.
+#[cfg(unix)]
+fn foreign_owner(path: &path::Path) -> anyhow::Result<Option<(u32, u32)>> {
+    use std::os::unix::fs::MetadataExt;
+    let owner = fs::metadata(path)?.uid();
+    // SAFETY: geteuid has no preconditions and cannot fail.
+    let mut current_user = unsafe { libc::geteuid() };
+    if owner == current_user {
+        return Ok(None);
+    }
+    if current_user == 0
+        && let Some(sudo_uid) = std::env::var("SUDO_UID")
+            .ok()
+            .and_then(|uid| uid.parse().ok())
+    {
+        current_user = sudo_uid;
+    }
+    Ok((owner != current_user).then_some((owner, current_user)))
+}
+
+#[cfg(not(unix))]
+fn foreign_owner(_path: &path::Path) -> anyhow::Result<Option<(u32, u32)>> {
+    Ok(None)
+}
+
+/// Removes the file, symlink or empty directory at `path` so something else can be written
+/// there.
+fn clear_path(path: &path::Path) -> std::io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir(path),
+        Ok(_) => fs::remove_file(path),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+/// Creates the directory `path` unless it already exists, replacing a file or symlink.
+fn create_dir_replacing_file(path: &path::Path) -> std::io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => return Ok(()),
+        Ok(_) => fs::remove_file(path)?,
+        Err(_) => (),
+    }
+    fs::create_dir(path)
+}
+
+/// Sets the executable bits wherever the file is readable (755 for a 644 file), or clears
+/// them. Other platforms have no executable bit, so there is nothing to do.
+#[cfg(unix)]
+fn set_executable(path: &path::Path, executable: bool) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = fs::metadata(path)?.permissions().mode();
+    let mode = if executable {
+        mode | (mode & 0o444) >> 2
+    } else {
+        mode & !0o111
+    };
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_executable(_path: &path::Path, _executable: bool) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &path::Path, link: &path::Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn create_symlink(target: &path::Path, link: &path::Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn create_symlink(_target: &path::Path, _link: &path::Path) -> std::io::Result<()> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
@@ -0,0 +1,245 @@
+use crate::compat_map;
+use crate::compressibility;
+use crate::object_storage::{ObjectStorage, Sha};
+use crate::shared_repository;
+use crate::usage;
+use anyhow::anyhow;
+use flate2::Compression;
+use flate2::bufread::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use sha1::{Digest, Sha1};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// The longest `<type> <size>` header accepted before the NUL.
+const MAX_HEADER_LEN: usize = 32;
+
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// An object's type and size, followed by its content as a stream. The content is only
+/// inflated as it is read, so even a blob of several gigabytes can be copied out with a
+/// fixed-size buffer.
+pub struct ObjectReader {
+    pub object_type: String,
+    pub size: u64,
+    remaining: u64,
+    content: Box<dyn Read>,
+}
+
+impl ObjectReader {
+    /// A reader over content that is already in memory, such as a resolved delta.
+    pub fn from_content(object_type: String, content: Vec<u8>) -> Self {
+        let size = content.len() as u64;
+        Self {
+            object_type,
+            size,
+            remaining: size,
+            content: Box::new(io::Cursor::new(content)),
+        }
+    }
+
+    /// Opens the loose object at `path` and reads just its `<type> <size>\0` header.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        Self::from_file(File::open(path)?)
+    }
+
+    /// Like [`ObjectReader::open`], but a missing file is `None` rather than an error, which
+    /// saves checking for the file before opening it.
+    pub fn open_if_exists(path: &Path) -> anyhow::Result<Option<Self>> {
+        match File::open(path) {
+            Ok(file) => Ok(Some(Self::from_file(file)?)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn from_file(file: File) -> anyhow::Result<Self> {
+        let mut content = ZlibDecoder::new(BufReader::new(file));
+        let mut header = vec![];
+        loop {
+            let mut byte = [0];
+            content.read_exact(&mut byte)?;
+            if byte[0] == 0 {
+                break;
+            }
+            if header.len() == MAX_HEADER_LEN {
+                Err(anyhow!("object header is not terminated"))?
+            }
+            header.push(byte[0]);
+        }
+        let header = std::str::from_utf8(&header)?;
+        let invalid = || anyhow!("invalid object header \"{}\"", header);
+        let (object_type, size) = header.split_once(' ').ok_or_else(invalid)?;
+        let size = size.parse::<u64>().map_err(|_| invalid())?;
+        Ok(Self {
+            object_type: object_type.to_string(),
+            size,
+            remaining: size,
+            content: Box::new(content),
+        })
+    }
+
+    /// Reads the whole content, for objects that are parsed rather than copied.
+    pub fn into_content(mut self) -> anyhow::Result<Vec<u8>> {
+        let mut content = Vec::with_capacity(self.size as usize);
+        self.read_to_end(&mut content)?;
+        Ok(content)
+    }
+}
+
+impl Read for ObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            return Ok(0);
+        }
+        let limit = buf.len().min(self.remaining as usize);
+        let read = self.content.read(&mut buf[..limit])?;
+        if read == 0 {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "object size does not match its header",
+            ));
+        }
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+/// Hashes an object as it is written and, when it is to be stored, compresses it into a
+/// temporary file in `objects/` that becomes the loose object once its id is known. The
+/// caller writes the header first; see [`ObjectStorage::stream_object`]. The start of a blob
+/// is held back until there is enough of it to tell whether it is worth compressing.
+pub struct ObjectWriter {
+    hasher: Sha1,
+    /// The temporary file, when the object is to be stored.
+    temp_path: Option<PathBuf>,
+    /// `None` once writing failed.
+    output: Option<Output>,
+}
+
+enum Output {
+    /// The header and the content so far, with the file they go to.
+    Sampling(BufWriter<File>, Vec<u8>),
+    Compressing(ZlibEncoder<BufWriter<File>>),
+}
+
+/// Starts compressing the start of an object that was held back, at the level its content
+/// calls for.
+fn start_compressing(
+    file: BufWriter<File>,
+    pending: &[u8],
+) -> io::Result<ZlibEncoder<BufWriter<File>>> {
+    let level = match pending.iter().position(|&byte| byte == 0) {
+        Some(end) if pending.starts_with(b"blob ") => {
+            compressibility::compression_for(&pending[end + 1..])
+        }
+        _ => Compression::default(),
+    };
+    let mut encoder = ZlibEncoder::new(file, level);
+    encoder.write_all(pending)?;
+    Ok(encoder)
+}
+
+fn write_failed() -> io::Error {
+    io::Error::other("an earlier write of the object failed")
+}
+
+impl ObjectWriter {
+    pub fn new(write: bool) -> anyhow::Result<Self> {
+        let mut writer = Self {
+            hasher: Sha1::new(),
+            temp_path: None,
+            output: None,
+        };
+        if write {
+            let objects_dir = ObjectStorage::git_dir().join("objects");
+            let counter = TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed);
+            let path = objects_dir.join(format!("tmp_obj_{}_{}", process::id(), counter));
+            let file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+                .map_err(|err| anyhow!("unable to create '{}': {}", path.display(), err))?;
+            writer.temp_path = Some(path);
+            writer.output = Some(Output::Sampling(BufWriter::new(file), vec![]));
+        }
+        Ok(writer)
+    }
+
+    /// Returns the object id, moving the object into place unless it already exists.
+    pub fn finish(mut self) -> anyhow::Result<Sha> {
+        let sha: Sha = self.hasher.finalize_reset().into();
+        let Some(temp_path) = self.temp_path.clone() else {
+            return Ok(sha);
+        };
+        let encoder = match self.output.take().ok_or_else(write_failed)? {
+            Output::Sampling(file, pending) => start_compressing(file, &pending)?,
+            Output::Compressing(encoder) => encoder,
+        };
+        encoder.finish()?.flush()?;
+        let hash = ObjectStorage::sha_to_hex_string(&sha);
+        let path = ObjectStorage::get_path_for_hash(&hash)?;
+        if path.exists() {
+            return Ok(sha);
+        }
+        shared_repository::create_dir_all(&ObjectStorage::get_dir_for_hash(&hash)?)?;
+        shared_repository::adjust(&temp_path)?;
+        fs::rename(&temp_path, &path)?;
+        self.temp_path = None;
+        usage::objects_written(1);
+        compat_map::record(&sha)?;
+        Ok(sha)
+    }
+}
+
+impl Write for ObjectWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.temp_path.is_none() {
+            self.hasher.update(buf);
+            return Ok(buf.len());
+        }
+        let (output, written) = match self.output.take().ok_or_else(write_failed)? {
+            Output::Sampling(file, mut pending) => {
+                // The header comes first and is far shorter than a sample. Only what the
+                // sample needs is copied; the rest of a large write goes to the encoder as is.
+                let sample_end = MAX_HEADER_LEN + compressibility::SAMPLE_SIZE;
+                let needed = sample_end.saturating_sub(pending.len()).min(buf.len());
+                pending.extend_from_slice(&buf[..needed]);
+                if pending.len() >= sample_end {
+                    let mut encoder = start_compressing(file, &pending)?;
+                    encoder.write_all(&buf[needed..])?;
+                    (Output::Compressing(encoder), buf.len())
+                } else {
+                    (Output::Sampling(file, pending), buf.len())
+                }
+            }
+            Output::Compressing(mut encoder) => {
+                let written = encoder.write(buf)?;
+                (Output::Compressing(encoder), written)
+            }
+        };
+        self.output = Some(output);
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.output {
+            Some(Output::Compressing(encoder)) => encoder.flush(),
+            Some(Output::Sampling(..)) | None => Ok(()),
+        }
+    }
+}
+
+impl Drop for ObjectWriter {
+    /// An object that was abandoned halfway, or that was stored already, leaves no temporary
+    /// file behind.
+    fn drop(&mut self) {
+        if let Some(temp_path) = &self.temp_path {
+            let _ = fs::remove_file(temp_path);
+        }
+    }
+}
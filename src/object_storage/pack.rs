@@ -0,0 +1,1343 @@
+use crate::attributes::{AttributeValue, Attributes};
+use crate::cancel;
+use crate::compat_map;
+use crate::compressibility;
+use crate::config::Config;
+use crate::context;
+use crate::object_storage::{ObjectStorage, Sha};
+use crate::progress::{self, Phase, Progress};
+use crate::shared_repository;
+use crate::usage;
+use anyhow::anyhow;
+use flate2::bufread::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::{Compression, Crc};
+use sha1::{Digest, Sha1};
+use std::cmp::Reverse;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+const OBJ_COMMIT: u8 = 1;
+const OBJ_TREE: u8 = 2;
+const OBJ_BLOB: u8 = 3;
+const OBJ_TAG: u8 = 4;
+const OBJ_OFS_DELTA: u8 = 6;
+const OBJ_REF_DELTA: u8 = 7;
+
+/// A packfile together with its `.idx`, which maps object ids to offsets in the pack.
+pub struct Pack {
+    pack_path: PathBuf,
+    /// The pack's trailing checksum, which identifies it in the delta base cache.
+    checksum: Sha,
+    shas: Vec<Sha>,
+    offsets: Vec<u64>,
+    /// The CRC32 of each entry as stored, in the order of `shas`; version 1 indexes have none.
+    crcs: Vec<u32>,
+    /// Where the entries start, in the order they are in the pack, to find where each ends.
+    sorted_offsets: Vec<u64>,
+    /// Where the trailing checksum starts.
+    data_end: u64,
+}
+
+/// A pack entry as it is stored: its type and inflated size, with the data still compressed,
+/// so that it can be copied into another pack without inflating and deflating it again.
+pub struct RawEntry {
+    type_code: u8,
+    pub size: u64,
+    /// The zlib stream of the object, or of the delta it is stored as.
+    pub compressed: Vec<u8>,
+}
+
+/// How the data of a pack entry is stored.
+enum EntryKind {
+    Base(&'static str),
+    /// A delta against the entry at the given offset in the same pack.
+    OfsDelta(u64),
+    /// A delta against the object with the given id, in this pack or elsewhere.
+    RefDelta(Sha),
+}
+
+/// Deltas are looked up by blocks of this many bytes of the base.
+const DELTA_BLOCK_SIZE: usize = 16;
+
+/// Objects smaller than this are stored whole: a delta would hardly be any shorter.
+const MIN_DELTA_SIZE: u64 = 64;
+
+/// The most an insert or copy instruction of a delta covers (copies could be longer, but git
+/// keeps them to this size too).
+const MAX_INSERT_SIZE: usize = 0x7f;
+const MAX_COPY_SIZE: usize = 0x10000;
+
+/// Where the base of a REF_DELTA was found.
+enum DeltaBase {
+    Offset(u64),
+    Object(&'static str, Vec<u8>),
+}
+
+/// git's default for `core.deltaBaseCacheLimit`.
+const DEFAULT_DELTA_BASE_CACHE_LIMIT: usize = 96 << 20;
+
+/// Objects that deltas were applied to, so that the many objects deltified against the same
+/// base, or along one long chain, don't each resolve it again from the start. It holds at most
+/// `core.deltaBaseCacheLimit` bytes and drops the least recently used bases first.
+pub(crate) struct DeltaBaseCache {
+    limit: usize,
+    used: usize,
+    /// Keyed by the pack checksum and the offset of the entry.
+    entries: HashMap<(Sha, u64), CachedBase>,
+    /// The keys by last use, oldest first.
+    recency: BTreeMap<u64, (Sha, u64)>,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+struct CachedBase {
+    object_type: &'static str,
+    content: Arc<Vec<u8>>,
+    last_used: u64,
+}
+
+/// Looks `sha` up in the repository's packs and returns its type and inflated content, or
+/// `None` when no pack contains it.
+pub fn read_object(sha: &Sha) -> anyhow::Result<Option<(String, Vec<u8>)>> {
+    for pack in loaded_packs()?.iter() {
+        if let Some(offset) = pack.find_offset(sha) {
+            let (object_type, content) = pack.read_at(offset)?;
+            return Ok(Some((object_type.to_string(), content)));
+        }
+    }
+    Ok(None)
+}
+
+/// Whether any of the repository's packs contains `sha`.
+pub fn contains(sha: &Sha) -> anyhow::Result<bool> {
+    Ok(loaded_packs()?
+        .iter()
+        .any(|pack| pack.find_offset(sha).is_some()))
+}
+
+/// The repository's packs, in the order they are searched.
+pub fn loaded_packs() -> anyhow::Result<Arc<Vec<Pack>>> {
+    let context = context::current();
+    let mut cached = context.packs.lock().unwrap();
+    if let Some(packs) = cached.as_ref() {
+        return Ok(packs.clone());
+    }
+    let pack_dir = ObjectStorage::git_dir().join("objects").join("pack");
+    let mut packs = vec![];
+    if pack_dir.is_dir() {
+        let mut idx_paths: Vec<PathBuf> = fs::read_dir(&pack_dir)?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "idx"))
+            .collect();
+        idx_paths.sort();
+        for idx_path in idx_paths {
+            let pack_path = idx_path.with_extension("pack");
+            if pack_path.exists() {
+                packs.push(Pack::open(&idx_path, pack_path)?);
+            }
+        }
+    }
+    let packs = Arc::new(packs);
+    *cached = Some(packs.clone());
+    Ok(packs)
+}
+
+impl Pack {
+    /// Reads the index at `idx_path`; both the version 1 and version 2 formats are accepted.
+    pub fn open(idx_path: &Path, pack_path: PathBuf) -> anyhow::Result<Self> {
+        let data = fs::read(idx_path)?;
+        let invalid = || anyhow!("invalid pack index {}", idx_path.display());
+        let (version2, fanout_start) = if data.starts_with(b"\xfftOc") {
+            let version = read_u32(&data, 4).ok_or_else(invalid)?;
+            if version != 2 {
+                Err(anyhow!(
+                    "unsupported pack index version {} in {}",
+                    version,
+                    idx_path.display()
+                ))?
+            }
+            (true, 8)
+        } else {
+            (false, 0)
+        };
+        let count = read_u32(&data, fanout_start + 255 * 4).ok_or_else(invalid)? as usize;
+        let table_start = fanout_start + 256 * 4;
+        let mut shas = Vec::with_capacity(count);
+        let mut offsets = Vec::with_capacity(count);
+        let mut crcs = vec![];
+        if version2 {
+            let crcs_start = table_start + count * 20;
+            let offsets_start = table_start + count * (20 + 4);
+            let large_offsets_start = offsets_start + count * 4;
+            for i in 0..count {
+                let sha_start = table_start + i * 20;
+                shas.push(read_sha(&data, sha_start).ok_or_else(invalid)?);
+                crcs.push(read_u32(&data, crcs_start + i * 4).ok_or_else(invalid)?);
+                let offset = read_u32(&data, offsets_start + i * 4).ok_or_else(invalid)?;
+                if offset & 0x8000_0000 == 0 {
+                    offsets.push(offset as u64);
+                } else {
+                    let large = large_offsets_start + (offset & 0x7fff_ffff) as usize * 8;
+                    let high = read_u32(&data, large).ok_or_else(invalid)? as u64;
+                    let low = read_u32(&data, large + 4).ok_or_else(invalid)? as u64;
+                    offsets.push(high << 32 | low);
+                }
+            }
+        } else {
+            for i in 0..count {
+                let entry_start = table_start + i * 24;
+                offsets.push(read_u32(&data, entry_start).ok_or_else(invalid)? as u64);
+                shas.push(read_sha(&data, entry_start + 4).ok_or_else(invalid)?);
+            }
+        }
+        let checksum_start = data.len().checked_sub(40).ok_or_else(invalid)?;
+        let checksum = read_sha(&data, checksum_start).ok_or_else(invalid)?;
+        let mut sorted_offsets = offsets.clone();
+        sorted_offsets.sort_unstable();
+        let data_end = fs::metadata(&pack_path)?.len().saturating_sub(20);
+        Ok(Self {
+            pack_path,
+            checksum,
+            shas,
+            offsets,
+            crcs,
+            sorted_offsets,
+            data_end,
+        })
+    }
+
+    pub fn pack_path(&self) -> &Path {
+        &self.pack_path
+    }
+
+    /// The ids of the objects in the pack, sorted.
+    pub fn shas(&self) -> &[Sha] {
+        &self.shas
+    }
+
+    /// Whether the pack's content still hashes to the checksum at its end, which the index
+    /// also records.
+    pub fn verify_checksum(&self) -> anyhow::Result<bool> {
+        let mut file = File::open(&self.pack_path)?;
+        let length = file.metadata()?.len();
+        if length < 32 {
+            return Ok(false);
+        }
+        let mut hasher = Sha1::new();
+        let mut content = BufReader::new((&mut file).take(length - 20));
+        loop {
+            let chunk = content.fill_buf()?;
+            if chunk.is_empty() {
+                break;
+            }
+            hasher.update(chunk);
+            let consumed = chunk.len();
+            content.consume(consumed);
+        }
+        let mut trailer = [0u8; 20];
+        file.read_exact(&mut trailer)?;
+        let actual: Sha = hasher.finalize().into();
+        Ok(actual == trailer && trailer == self.checksum)
+    }
+
+    /// Whether a `.keep` file asks repack and gc to leave the pack alone.
+    pub fn is_kept(&self) -> bool {
+        self.pack_path.with_extension("keep").exists()
+    }
+
+    /// Whether the pack was fetched from a promisor remote, which a `.promisor` file marks.
+    pub fn is_promisor(&self) -> bool {
+        self.pack_path.with_extension("promisor").exists()
+    }
+
+    /// The offset of `sha` in the pack, found by binary search over the sorted index.
+    pub fn find_offset(&self, sha: &Sha) -> Option<u64> {
+        self.shas
+            .binary_search(sha)
+            .ok()
+            .map(|position| self.offsets[position])
+    }
+
+    /// The entry of `sha` as it is stored in the pack, or `None` when the pack does not have
+    /// it. Its CRC is checked against the index, so that a corrupt entry is not copied on.
+    pub fn raw_entry(&self, sha: &Sha) -> anyhow::Result<Option<RawEntry>> {
+        let Ok(position) = self.shas.binary_search(sha) else {
+            return Ok(None);
+        };
+        let offset = self.offsets[position];
+        let end = match self
+            .sorted_offsets
+            .partition_point(|&start| start <= offset)
+        {
+            next if next < self.sorted_offsets.len() => self.sorted_offsets[next],
+            _ => self.data_end,
+        };
+        let mut reader = BufReader::new(File::open(&self.pack_path)?);
+        let (type_code, size) = read_entry_header(&mut reader, offset)?;
+        let data_start = reader.stream_position()?;
+        let length = end
+            .checked_sub(data_start)
+            .ok_or(anyhow!("corrupt pack entry at offset {}", offset))?;
+        let mut compressed = vec![0; length as usize];
+        reader.read_exact(&mut compressed)?;
+        if let Some(&expected) = self.crcs.get(position) {
+            let mut entry = vec![0; (data_start - offset) as usize];
+            reader.seek(SeekFrom::Start(offset))?;
+            reader.read_exact(&mut entry)?;
+            let mut crc = Crc::new();
+            crc.update(&entry);
+            crc.update(&compressed);
+            if crc.sum() != expected {
+                Err(anyhow!(
+                    "bad CRC for object {} at offset {} in {}",
+                    ObjectStorage::sha_to_hex_string(sha),
+                    offset,
+                    self.pack_path.display()
+                ))?
+            }
+        }
+        Ok(Some(RawEntry {
+            type_code,
+            size: size as u64,
+            compressed,
+        }))
+    }
+
+    /// Reads the object stored at `offset`, following its delta chain down to the base object
+    /// and applying the deltas back up.
+    pub fn read_at(&self, offset: u64) -> anyhow::Result<(&'static str, Vec<u8>)> {
+        let mut reader = self.open_reader()?;
+        self.read_with(&mut reader, offset)
+    }
+
+    /// The ids of the objects in the pack with their offsets, in the order the pack stores
+    /// them.
+    pub fn objects_in_pack_order(&self) -> Vec<(u64, Sha)> {
+        let mut objects: Vec<(u64, Sha)> = self
+            .offsets
+            .iter()
+            .copied()
+            .zip(self.shas.iter().copied())
+            .collect();
+        objects.sort_unstable();
+        objects
+    }
+
+    /// The type and size of the object at `offset`, without resolving its deltas: the size of
+    /// a delta's result is in the first bytes of the delta, and only the type comes from the
+    /// base at the end of the chain.
+    pub fn object_info_at(
+        &self,
+        reader: &mut BufReader<File>,
+        offset: u64,
+    ) -> anyhow::Result<(&'static str, u64)> {
+        let (mut kind, size) = read_entry_kind(reader, offset)?;
+        let size = match kind {
+            EntryKind::Base(_) => size as u64,
+            EntryKind::OfsDelta(_) | EntryKind::RefDelta(_) => {
+                // The header holds the base size and then the result size, at most 10 bytes
+                // each.
+                let mut header = Vec::with_capacity(20);
+                ZlibDecoder::new(reader.by_ref())
+                    .take(20)
+                    .read_to_end(&mut header)?;
+                let mut position = 0;
+                read_size(&header, &mut position)
+                    .and_then(|_| read_size(&header, &mut position))
+                    .ok_or(anyhow!("corrupt delta at offset {}", offset))? as u64
+            }
+        };
+        loop {
+            kind = match kind {
+                EntryKind::Base(object_type) => return Ok((object_type, size)),
+                EntryKind::OfsDelta(base_offset) => read_entry_kind(reader, base_offset)?.0,
+                EntryKind::RefDelta(base_sha) => match self.find_offset(&base_sha) {
+                    Some(base_offset) => read_entry_kind(reader, base_offset)?.0,
+                    None => {
+                        let base = ObjectStorage::open_object(&base_sha)?;
+                        return Ok((object_type_name(&base.object_type)?, size));
+                    }
+                },
+            };
+        }
+    }
+
+    pub fn open_reader(&self) -> anyhow::Result<BufReader<File>> {
+        let mut reader = BufReader::new(File::open(&self.pack_path)?);
+        let mut header = [0u8; 12];
+        reader.read_exact(&mut header)?;
+        parse_header(&header).ok_or(anyhow!("{} is not a packfile", self.pack_path.display()))?;
+        Ok(reader)
+    }
+
+    fn read_with(
+        &self,
+        reader: &mut BufReader<File>,
+        offset: u64,
+    ) -> anyhow::Result<(&'static str, Vec<u8>)> {
+        let mut ref_base = |sha: &Sha| match self.find_offset(sha) {
+            Some(base_offset) => Ok(Some(DeltaBase::Offset(base_offset))),
+            None => {
+                let (object_type, content) = ObjectStorage::read_object(sha)?;
+                Ok(Some(DeltaBase::Object(
+                    object_type_name(&object_type)?,
+                    content,
+                )))
+            }
+        };
+        resolve_entry(reader, &self.checksum, offset, &mut ref_base)?.ok_or(anyhow!(
+            "delta base of the object at offset {} is missing",
+            offset
+        ))
+    }
+}
+
+/// Reads those of `shas` that are packed, calling `visit` with each id, object type and
+/// content, in the order the packs store them rather than the order given: each pack is read
+/// front to back through one open file, so that restoring many files costs about one pass over
+/// the pack instead of a seek and an open for each. Objects stored whole that are bigger than
+/// `max_size` are left alone, like those no pack has; both are returned for the caller to read
+/// one by one.
+pub fn read_in_pack_order(
+    shas: &[Sha],
+    max_size: u64,
+    mut visit: impl FnMut(&Sha, &'static str, Vec<u8>) -> anyhow::Result<()>,
+) -> anyhow::Result<Vec<Sha>> {
+    let packs = loaded_packs()?;
+    let mut located = vec![];
+    let mut rest = vec![];
+    for sha in shas {
+        let found = packs
+            .iter()
+            .enumerate()
+            .find_map(|(pack, candidate)| Some((pack, candidate.find_offset(sha)?)));
+        match found {
+            Some((pack, offset)) => located.push((pack, offset, *sha)),
+            None => rest.push(*sha),
+        }
+    }
+    located.sort_unstable();
+    located.dedup();
+    for group in located.chunk_by(|a, b| a.0 == b.0) {
+        let pack = &packs[group[0].0];
+        let mut reader = pack.open_reader()?;
+        for &(_, offset, sha) in group {
+            cancel::check()?;
+            let (type_code, size) = read_entry_header(&mut reader, offset)?;
+            if !matches!(type_code, OBJ_OFS_DELTA | OBJ_REF_DELTA) && size as u64 > max_size {
+                rest.push(sha);
+                continue;
+            }
+            let (object_type, content) = pack.read_with(&mut reader, offset)?;
+            usage::object_read();
+            visit(&sha, object_type, content)?;
+        }
+    }
+    Ok(rest)
+}
+
+impl RawEntry {
+    /// Whether the entry is a delta, which refers to its base by where that is in this pack
+    /// or by id, rather than the object itself.
+    pub fn is_delta(&self) -> bool {
+        matches!(self.type_code, OBJ_OFS_DELTA | OBJ_REF_DELTA)
+    }
+
+    /// Appends the entry to a pack being built; only whole objects can be copied like this.
+    fn write_whole(&self, pack: &mut Vec<u8>) {
+        debug_assert!(!self.is_delta());
+        write_entry_header(pack, self.type_code, self.size);
+        pack.extend_from_slice(&self.compressed);
+    }
+}
+
+/// The first entry for `sha` in the repository's packs, as it is stored.
+pub fn find_raw_entry(sha: &Sha) -> anyhow::Result<Option<RawEntry>> {
+    for pack in loaded_packs()?.iter() {
+        if let Some(entry) = pack.raw_entry(sha)? {
+            return Ok(Some(entry));
+        }
+    }
+    Ok(None)
+}
+
+/// Where [`write_pack`] puts the pack `data`, which is named after its trailing checksum.
+pub fn path_of(data: &[u8]) -> PathBuf {
+    let checksum = &data[data.len().saturating_sub(20)..];
+    ObjectStorage::git_dir()
+        .join("objects")
+        .join("pack")
+        .join(format!(
+            "pack-{}.pack",
+            base16ct::lower::encode_string(checksum)
+        ))
+}
+
+/// Stores a packfile, received from a remote or built by [`build_pack`], under `objects/pack`
+/// together with a freshly built version 2 index, and returns the path of the pack.
+pub fn write_pack(data: &[u8]) -> anyhow::Result<PathBuf> {
+    let pack_path = write_pack_to(data, &ObjectStorage::git_dir().join("objects").join("pack"))?;
+    reset_cache();
+    compat_map::record_pack(&pack_path)?;
+    Ok(pack_path)
+}
+
+/// Indexes the packfile `data` and writes it with its index into `pack_dir`, returning the
+/// path of the pack.
+pub fn write_pack_to(data: &[u8], pack_dir: &Path) -> anyhow::Result<PathBuf> {
+    let idx = index_pack(data)?;
+    let checksum = &data[data.len() - 20..];
+    shared_repository::create_dir_all(pack_dir)?;
+    let name = format!("pack-{}", base16ct::lower::encode_string(checksum));
+    let pack_path = pack_dir.join(format!("{}.pack", name));
+    // Write under temporary names and rename, index last, so an interrupted write never
+    // leaves a pack that looks complete.
+    let tmp_pack = pack_dir.join(format!("tmp_{}.pack", name));
+    let tmp_idx = pack_dir.join(format!("tmp_{}.idx", name));
+    fs::write(&tmp_pack, data)?;
+    fs::write(&tmp_idx, idx)?;
+    shared_repository::adjust(&tmp_pack)?;
+    shared_repository::adjust(&tmp_idx)?;
+    fs::rename(&tmp_pack, &pack_path)?;
+    fs::rename(&tmp_idx, pack_dir.join(format!("{}.idx", name)))?;
+    usage::objects_written(parse_header(data).unwrap_or_default() as u64);
+    Ok(pack_path)
+}
+
+/// Builds the version 2 index of the packfile `data`, as `git index-pack` would write it,
+/// after checking the pack's checksum. Every object is resolved once to compute its id, so
+/// deltas against objects outside the pack (thin packs) are rejected.
+pub fn index_pack(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let (content, trailer) = data
+        .split_at_checked(data.len().saturating_sub(20))
+        .filter(|(_, trailer)| trailer.len() == 20)
+        .ok_or(anyhow!("packfile is truncated"))?;
+    if ObjectStorage::hash_content(content) != trailer {
+        Err(anyhow!("packfile checksum mismatch"))?
+    }
+    let count = parse_header(content).ok_or(anyhow!("received data is not a packfile"))?;
+    let mut reader = Cursor::new(content);
+    let mut entries: Vec<(u64, u32)> = Vec::with_capacity(count as usize);
+    let mut offset = 12;
+    for done in 1..=count {
+        cancel::check()?;
+        read_entry(&mut reader, offset)?;
+        let end = reader.position();
+        let mut crc = Crc::new();
+        crc.update(&content[offset as usize..end as usize]);
+        entries.push((offset, crc.sum()));
+        offset = end;
+        report_progress(
+            Phase::IndexingObjects,
+            done as usize,
+            count as usize,
+            Some(end),
+        );
+    }
+    progress::report(|reporter| reporter.finish(Phase::IndexingObjects));
+    if offset as usize != content.len() {
+        Err(anyhow!(
+            "packfile has trailing data after {} objects",
+            count
+        ))?
+    }
+
+    // A REF_DELTA can only be resolved once its base's id is known, so keep making passes
+    // until every entry is resolved or a pass makes no progress.
+    let checksum: Sha = trailer.try_into()?;
+    let mut shas: Vec<Option<Sha>> = vec![None; entries.len()];
+    let mut known: HashMap<Sha, u64> = HashMap::new();
+    let mut resolved = 0;
+    loop {
+        let mut progress = false;
+        for (i, (offset, _)) in entries.iter().enumerate() {
+            if shas[i].is_some() {
+                continue;
+            }
+            cancel::check()?;
+            let mut ref_base = |sha: &Sha| Ok(known.get(sha).map(|&o| DeltaBase::Offset(o)));
+            if let Some((object_type, object)) =
+                resolve_entry(&mut reader, &checksum, *offset, &mut ref_base)?
+            {
+                let mut full = ObjectStorage::header_for_content_length(object_type, object.len())?;
+                full.extend_from_slice(&object);
+                let sha = ObjectStorage::hash_content(&full);
+                known.insert(sha, *offset);
+                shas[i] = Some(sha);
+                progress = true;
+                resolved += 1;
+                report_progress(Phase::ResolvingDeltas, resolved, entries.len(), None);
+            }
+        }
+        let unresolved = shas.iter().filter(|sha| sha.is_none()).count();
+        if unresolved == 0 {
+            progress::report(|reporter| reporter.finish(Phase::ResolvingDeltas));
+            break;
+        }
+        if !progress {
+            Err(anyhow!("pack has {} unresolved deltas", unresolved))?
+        }
+    }
+
+    let mut index: Vec<(Sha, u32, u64)> = shas
+        .into_iter()
+        .flatten()
+        .zip(entries)
+        .map(|(sha, (offset, crc))| (sha, crc, offset))
+        .collect();
+    index.sort();
+    let mut idx = b"\xfftOc".to_vec();
+    idx.extend_from_slice(&2u32.to_be_bytes());
+    for first_byte in 0..=255u8 {
+        let below = index.partition_point(|(sha, _, _)| sha[0] <= first_byte);
+        idx.extend_from_slice(&(below as u32).to_be_bytes());
+    }
+    for (sha, _, _) in &index {
+        idx.extend_from_slice(sha);
+    }
+    for (_, crc, _) in &index {
+        idx.extend_from_slice(&crc.to_be_bytes());
+    }
+    let mut large_offsets: Vec<u64> = vec![];
+    for (_, _, offset) in &index {
+        if *offset < 0x8000_0000 {
+            idx.extend_from_slice(&(*offset as u32).to_be_bytes());
+        } else {
+            idx.extend_from_slice(&(0x8000_0000 | large_offsets.len() as u32).to_be_bytes());
+            large_offsets.push(*offset);
+        }
+    }
+    for offset in large_offsets {
+        idx.extend_from_slice(&offset.to_be_bytes());
+    }
+    idx.extend_from_slice(trailer);
+    let idx_checksum = ObjectStorage::hash_content(&idx);
+    idx.extend_from_slice(&idx_checksum);
+    Ok(idx)
+}
+
+/// The objects of a pack being built, in the order they are written.
+struct PackEntry {
+    sha: Sha,
+    type_code: u8,
+    size: u64,
+    name_hash: u32,
+    /// Whether the object may become a delta or the base of one: blobs whose path has the
+    /// `-delta` attribute may not.
+    delta: bool,
+}
+
+/// An object in the delta window: a candidate base for the objects after it.
+struct WindowEntry {
+    position: usize,
+    offset: u64,
+    content: Vec<u8>,
+    /// Where each block of the content first occurs.
+    blocks: HashMap<[u8; DELTA_BLOCK_SIZE], usize>,
+    depth: usize,
+}
+
+/// Builds a version 2 packfile of `objects`, each given with the path it was reached by, and
+/// returns it with the number of objects stored as deltas. Like `git pack-objects`, objects
+/// are sorted by type, a hash of the path's last characters and size, so that versions of one
+/// file end up next to each other, and each is deltified against the best of the `window`
+/// objects before it, with delta chains of at most `depth` objects. Objects over
+/// `core.bigFileThreshold` are stored whole and streamed in rather than held in memory.
+/// Objects stored whole are copied from an existing pack as they are, still compressed,
+/// when one has them. Blobs whose path has the `-delta` attribute and blobs that look already
+/// compressed are never deltified, and the latter are stored with zlib's level 0.
+pub fn build_pack(
+    objects: &[(Sha, String)],
+    window: usize,
+    depth: usize,
+) -> anyhow::Result<(Vec<u8>, usize)> {
+    // Copy instructions address at most 4GiB of a base.
+    let big_file_threshold = Config::get()?.big_file_threshold()?.min(u32::MAX as u64);
+    let mut entries = Vec::with_capacity(objects.len());
+    // Versions of a file share its path and so its attributes.
+    let mut delta_by_path: HashMap<&str, bool> = HashMap::new();
+    for (sha, name) in objects {
+        let object = ObjectStorage::open_object(sha)?;
+        let type_code = type_code(&object.object_type)?;
+        let delta = type_code != OBJ_BLOB
+            || name.is_empty()
+            || match delta_by_path.entry(name) {
+                Entry::Occupied(entry) => *entry.get(),
+                Entry::Vacant(entry) => *entry.insert(
+                    Attributes::for_path(name)?.get("delta") != Some(AttributeValue::Unset),
+                ),
+            };
+        entries.push(PackEntry {
+            sha: *sha,
+            type_code,
+            size: object.size,
+            name_hash: name_hash(name),
+            delta,
+        });
+    }
+    // Larger objects first: deleting data makes smaller deltas than adding it.
+    entries.sort_by_key(|entry| (entry.type_code, entry.name_hash, Reverse(entry.size)));
+
+    let mut pack = b"PACK".to_vec();
+    pack.extend_from_slice(&2u32.to_be_bytes());
+    pack.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+    let mut candidates: VecDeque<WindowEntry> = VecDeque::new();
+    let mut deltas = 0;
+    for (position, entry) in entries.iter().enumerate() {
+        cancel::check()?;
+        let offset = pack.len() as u64;
+        report_progress(Phase::WritingObjects, position, entries.len(), Some(offset));
+        let reusable = find_raw_entry(&entry.sha)?.filter(|raw| !raw.is_delta());
+        // Without a window nothing is deltified, so a packed object need not be read at all.
+        if let Some(raw) = &reusable
+            && (entry.size > big_file_threshold || window == 0)
+        {
+            raw.write_whole(&mut pack);
+            continue;
+        }
+        let mut object = ObjectStorage::open_object(&entry.sha)?;
+        if entry.size > big_file_threshold {
+            write_entry_header(&mut pack, entry.type_code, entry.size);
+            let mut encoder = ZlibEncoder::new(&mut pack, Compression::default());
+            io::copy(&mut object, &mut encoder)?;
+            encoder.finish()?;
+            continue;
+        }
+        let content = object.into_content()?;
+        // Drop candidates of another type; the sort keeps each type together.
+        candidates.retain(|candidate| entries[candidate.position].type_code == entry.type_code);
+        let compression = match entry.type_code {
+            OBJ_BLOB => compressibility::compression_for(&content),
+            _ => Compression::default(),
+        };
+        let delta_candidate =
+            entry.delta && compression != Compression::none() && entry.size >= MIN_DELTA_SIZE;
+        let mut best: Option<(Vec<u8>, &WindowEntry)> = None;
+        if delta_candidate {
+            for candidate in candidates
+                .iter()
+                .filter(|candidate| candidate.depth < depth)
+            {
+                // A delta is only worth it when it is at most half the size of the object.
+                let limit = best
+                    .as_ref()
+                    .map_or(content.len() / 2, |(delta, _)| delta.len());
+                if candidate.content.len().abs_diff(content.len()) >= limit {
+                    continue;
+                }
+                if let Some(delta) = create_delta(candidate, &content, limit) {
+                    best = Some((delta, candidate));
+                }
+            }
+        }
+        let depth = match best {
+            Some((delta, base)) => {
+                write_entry_header(&mut pack, OBJ_OFS_DELTA, delta.len() as u64);
+                write_base_distance(&mut pack, offset - base.offset);
+                write_compressed(&mut pack, &delta, Compression::default())?;
+                deltas += 1;
+                base.depth + 1
+            }
+            None => {
+                match &reusable {
+                    Some(raw) => raw.write_whole(&mut pack),
+                    None => {
+                        write_entry_header(&mut pack, entry.type_code, entry.size);
+                        write_compressed(&mut pack, &content, compression)?;
+                    }
+                }
+                0
+            }
+        };
+        if window > 0 && delta_candidate {
+            if candidates.len() == window {
+                candidates.pop_front();
+            }
+            candidates.push_back(WindowEntry {
+                position,
+                offset,
+                blocks: block_index(&content),
+                content,
+                depth,
+            });
+        }
+    }
+    let checksum = ObjectStorage::hash_content(&pack);
+    pack.extend_from_slice(&checksum);
+    report_progress(
+        Phase::WritingObjects,
+        entries.len(),
+        entries.len(),
+        Some(pack.len() as u64),
+    );
+    progress::report(|reporter| reporter.finish(Phase::WritingObjects));
+    Ok((pack, deltas))
+}
+
+fn report_progress(phase: Phase, done: usize, total: usize, bytes: Option<u64>) {
+    let progress = Progress {
+        phase,
+        done: done as u64,
+        total: Some(total as u64),
+        bytes,
+    };
+    progress::report(|reporter| reporter.progress(&progress));
+}
+
+/// git's hash of the path an object was found at, which mostly depends on its last 16
+/// characters so that files with the same name or extension sort together.
+fn name_hash(name: &str) -> u32 {
+    name.bytes()
+        .filter(|byte| !byte.is_ascii_whitespace())
+        .fold(0u32, |hash, byte| {
+            (hash >> 2).wrapping_add((byte as u32) << 24)
+        })
+}
+
+fn block_index(content: &[u8]) -> HashMap<[u8; DELTA_BLOCK_SIZE], usize> {
+    let mut blocks = HashMap::new();
+    for (i, block) in content.chunks_exact(DELTA_BLOCK_SIZE).enumerate() {
+        let block: [u8; DELTA_BLOCK_SIZE] = block.try_into().expect("chunks are exact");
+        blocks.entry(block).or_insert(i * DELTA_BLOCK_SIZE);
+    }
+    blocks
+}
+
+/// Encodes `target` as a delta against `base`: every block of the target that also occurs in
+/// the base is extended as far as it matches both ways and copied, the rest inserted. Gives
+/// `None` unless the delta is smaller than `limit`.
+fn create_delta(base: &WindowEntry, target: &[u8], limit: usize) -> Option<Vec<u8>> {
+    let source = &base.content;
+    let mut delta = vec![];
+    write_size(&mut delta, source.len());
+    write_size(&mut delta, target.len());
+    let mut pending = 0;
+    let mut position = 0;
+    while position + DELTA_BLOCK_SIZE <= target.len() {
+        let block: &[u8; DELTA_BLOCK_SIZE] = target[position..position + DELTA_BLOCK_SIZE]
+            .try_into()
+            .expect("the block is in range");
+        let Some(&start) = base.blocks.get(block) else {
+            position += 1;
+            continue;
+        };
+        let mut length = DELTA_BLOCK_SIZE;
+        while start + length < source.len()
+            && position + length < target.len()
+            && source[start + length] == target[position + length]
+        {
+            length += 1;
+        }
+        let mut before = 0;
+        while position - before > pending
+            && start > before
+            && source[start - before - 1] == target[position - before - 1]
+        {
+            before += 1;
+        }
+        write_insert(&mut delta, &target[pending..position - before]);
+        write_copy(&mut delta, start - before, length + before);
+        position += length;
+        pending = position;
+        if delta.len() >= limit {
+            return None;
+        }
+    }
+    write_insert(&mut delta, &target[pending..]);
+    (delta.len() < limit).then_some(delta)
+}
+
+fn write_insert(delta: &mut Vec<u8>, data: &[u8]) {
+    for chunk in data.chunks(MAX_INSERT_SIZE) {
+        delta.push(chunk.len() as u8);
+        delta.extend_from_slice(chunk);
+    }
+}
+
+/// Writes copy instructions for `length` bytes of the base at `offset`; only the non-zero
+/// bytes of the offset and size are stored, flagged in the instruction byte.
+fn write_copy(delta: &mut Vec<u8>, offset: usize, length: usize) {
+    let mut offset = offset;
+    let mut remaining = length;
+    while remaining > 0 {
+        let size = remaining.min(MAX_COPY_SIZE);
+        let instruction = delta.len();
+        delta.push(0x80);
+        for i in 0..4 {
+            let byte = (offset >> (i * 8)) as u8;
+            if byte != 0 {
+                delta[instruction] |= 1 << i;
+                delta.push(byte);
+            }
+        }
+        for i in 0..3 {
+            let byte = (size >> (i * 8)) as u8;
+            if byte != 0 {
+                delta[instruction] |= 0x10 << i;
+                delta.push(byte);
+            }
+        }
+        offset += size;
+        remaining -= size;
+    }
+}
+
+/// Writes a size for the delta header, little-endian base-128; see [`read_size`].
+fn write_size(data: &mut Vec<u8>, size: usize) {
+    let mut size = size;
+    while size >= 0x80 {
+        data.push(0x80 | (size & 0x7f) as u8);
+        size >>= 7;
+    }
+    data.push(size as u8);
+}
+
+/// Writes an entry's type and size: four bits of the size in the first byte, seven in each
+/// following one.
+fn write_entry_header(pack: &mut Vec<u8>, type_code: u8, size: u64) {
+    let mut byte = (type_code << 4) | (size & 0x0f) as u8;
+    let mut size = size >> 4;
+    while size > 0 {
+        pack.push(byte | 0x80);
+        byte = (size & 0x7f) as u8;
+        size >>= 7;
+    }
+    pack.push(byte);
+}
+
+/// Writes how far back the base of an OFS_DELTA is, in the encoding [`read_entry`] decodes.
+fn write_base_distance(pack: &mut Vec<u8>, distance: u64) {
+    let mut bytes = vec![(distance & 0x7f) as u8];
+    let mut distance = distance >> 7;
+    while distance > 0 {
+        distance -= 1;
+        bytes.push(0x80 | (distance & 0x7f) as u8);
+        distance >>= 7;
+    }
+    bytes.reverse();
+    pack.extend_from_slice(&bytes);
+}
+
+fn write_compressed(
+    pack: &mut Vec<u8>,
+    data: &[u8],
+    compression: Compression,
+) -> anyhow::Result<()> {
+    let mut encoder = ZlibEncoder::new(pack, compression);
+    encoder.write_all(data)?;
+    encoder.finish()?;
+    Ok(())
+}
+
+fn type_code(object_type: &str) -> anyhow::Result<u8> {
+    match object_type {
+        "commit" => Ok(OBJ_COMMIT),
+        "tree" => Ok(OBJ_TREE),
+        "blob" => Ok(OBJ_BLOB),
+        "tag" => Ok(OBJ_TAG),
+        _ => Err(anyhow!("cannot pack an object of type \"{}\"", object_type)),
+    }
+}
+
+/// Forgets the packs read so far, so packs written or removed during this run are noticed.
+pub fn reset_cache() {
+    *context::current().packs.lock().unwrap() = None;
+}
+
+/// Checks the 12-byte pack header and returns the number of objects in the pack.
+fn parse_header(data: &[u8]) -> Option<u32> {
+    (data.starts_with(b"PACK") && matches!(read_u32(data, 4)?, 2 | 3)).then_some(())?;
+    read_u32(data, 8)
+}
+
+/// Reads the object at `offset` in the pack with checksum `pack` by collecting its chain of
+/// deltas down to a base object, or a base in the delta base cache, and applying them back up.
+/// `ref_base` locates the base of a REF_DELTA; `None` is returned when it cannot.
+fn resolve_entry<R: BufRead + Seek>(
+    reader: &mut R,
+    pack: &Sha,
+    offset: u64,
+    ref_base: &mut dyn FnMut(&Sha) -> anyhow::Result<Option<DeltaBase>>,
+) -> anyhow::Result<Option<(&'static str, Vec<u8>)>> {
+    // The deltas from the top down, each with the offset of its entry.
+    let mut deltas: Vec<(u64, Vec<u8>)> = vec![];
+    let mut offset = offset;
+    let (object_type, mut content) = loop {
+        if !deltas.is_empty()
+            && let Some((object_type, content)) =
+                with_delta_base_cache(|cache| cache.get(pack, offset))?
+        {
+            break (object_type, content);
+        }
+        let (kind, data) = read_entry(reader, offset)?;
+        match kind {
+            EntryKind::Base(object_type) => {
+                let content = Arc::new(data);
+                if !deltas.is_empty() {
+                    with_delta_base_cache(|cache| {
+                        cache.insert(pack, offset, object_type, &content)
+                    })?;
+                }
+                break (object_type, content);
+            }
+            EntryKind::OfsDelta(base_offset) => {
+                deltas.push((offset, data));
+                offset = base_offset;
+            }
+            EntryKind::RefDelta(base_sha) => {
+                deltas.push((offset, data));
+                match ref_base(&base_sha)? {
+                    Some(DeltaBase::Offset(base_offset)) => offset = base_offset,
+                    Some(DeltaBase::Object(object_type, content)) => {
+                        break (object_type, Arc::new(content));
+                    }
+                    None => return Ok(None),
+                }
+            }
+        }
+    };
+    for (i, (entry_offset, delta)) in deltas.iter().enumerate().rev() {
+        content = Arc::new(apply_delta(&content, delta)?);
+        // Every result but the requested object is the base of the delta above it.
+        if i > 0 {
+            with_delta_base_cache(|cache| {
+                cache.insert(pack, *entry_offset, object_type, &content)
+            })?;
+        }
+    }
+    let content = Arc::try_unwrap(content).unwrap_or_else(|shared| shared.to_vec());
+    Ok(Some((object_type, content)))
+}
+
+/// Runs `f` on the delta base cache, setting it up on first use.
+fn with_delta_base_cache<T>(f: impl FnOnce(&mut DeltaBaseCache) -> T) -> anyhow::Result<T> {
+    let context = context::current();
+    let mut cache = context.delta_base_cache.lock().unwrap();
+    if cache.is_none() {
+        let limit = Config::get()?
+            .int("core", "deltaBaseCacheLimit")?
+            .map_or(DEFAULT_DELTA_BASE_CACHE_LIMIT, |limit| {
+                limit.max(0) as usize
+            });
+        *cache = Some(DeltaBaseCache::new(limit));
+    }
+    Ok(f(cache.as_mut().expect("the cache was just set up")))
+}
+
+/// With `GIT_TRACE_DELTA_BASE_CACHE` set, reports on stderr how well the delta base cache did,
+/// to tune `core.deltaBaseCacheLimit`.
+pub fn trace_delta_base_cache() {
+    let enabled = std::env::var("GIT_TRACE_DELTA_BASE_CACHE")
+        .is_ok_and(|value| !matches!(value.as_str(), "" | "0" | "false"));
+    if !enabled {
+        return;
+    }
+    if let Some(cache) = context::current().delta_base_cache.lock().unwrap().as_ref() {
+        eprintln!(
+            "trace: delta base cache: {} hits, {} misses, {} evictions, {} of {} bytes in {} bases",
+            cache.hits,
+            cache.misses,
+            cache.evictions,
+            cache.used,
+            cache.limit,
+            cache.entries.len()
+        );
+    }
+}
+
+impl DeltaBaseCache {
+    fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            used: 0,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            clock: 0,
+            hits: 0,
+            misses: 0,
+            evictions: 0,
+        }
+    }
+
+    fn get(&mut self, pack: &Sha, offset: u64) -> Option<(&'static str, Arc<Vec<u8>>)> {
+        self.clock += 1;
+        let Some(entry) = self.entries.get_mut(&(*pack, offset)) else {
+            self.misses += 1;
+            return None;
+        };
+        self.hits += 1;
+        self.recency.remove(&entry.last_used);
+        entry.last_used = self.clock;
+        self.recency.insert(self.clock, (*pack, offset));
+        Some((entry.object_type, entry.content.clone()))
+    }
+
+    /// Remembers a base, dropping the least recently used ones to stay within the limit. A
+    /// base larger than the whole cache is not kept.
+    fn insert(
+        &mut self,
+        pack: &Sha,
+        offset: u64,
+        object_type: &'static str,
+        content: &Arc<Vec<u8>>,
+    ) {
+        if content.len() > self.limit || self.entries.contains_key(&(*pack, offset)) {
+            return;
+        }
+        while self.used + content.len() > self.limit {
+            let Some((_, key)) = self.recency.pop_first() else {
+                break;
+            };
+            if let Some(evicted) = self.entries.remove(&key) {
+                self.used -= evicted.content.len();
+                self.evictions += 1;
+            }
+        }
+        self.clock += 1;
+        self.used += content.len();
+        self.recency.insert(self.clock, (*pack, offset));
+        self.entries.insert(
+            (*pack, offset),
+            CachedBase {
+                object_type,
+                content: content.clone(),
+                last_used: self.clock,
+            },
+        );
+    }
+}
+
+/// The most memory reserved up front for data whose size a pack declares; larger data grows
+/// the buffer as it actually arrives, so a corrupt size cannot make us allocate it all.
+const MAX_RESERVATION: usize = 16 << 20;
+
+/// Reads the entry header at `offset` and inflates its data, which is either the object
+/// content or a delta.
+fn read_entry<R: BufRead + Seek>(
+    reader: &mut R,
+    offset: u64,
+) -> anyhow::Result<(EntryKind, Vec<u8>)> {
+    let (kind, size) = read_entry_kind(reader, offset)?;
+    let mut data = Vec::with_capacity(size.min(MAX_RESERVATION));
+    // One byte more than declared is enough to tell that the entry is corrupt.
+    ZlibDecoder::new(reader)
+        .take(size as u64 + 1)
+        .read_to_end(&mut data)?;
+    if data.len() != size {
+        Err(anyhow!("corrupt pack entry at offset {}", offset))?
+    }
+    Ok((kind, data))
+}
+
+/// Reads how the entry at `offset` is stored and the size of its inflated data, leaving
+/// `reader` at the start of that data.
+fn read_entry_kind<R: BufRead + Seek>(
+    reader: &mut R,
+    offset: u64,
+) -> anyhow::Result<(EntryKind, usize)> {
+    let (type_code, size) = read_entry_header(reader, offset)?;
+    let kind = match type_code {
+        OBJ_COMMIT => EntryKind::Base("commit"),
+        OBJ_TREE => EntryKind::Base("tree"),
+        OBJ_BLOB => EntryKind::Base("blob"),
+        OBJ_TAG => EntryKind::Base("tag"),
+        OBJ_OFS_DELTA => {
+            let mut byte = read_byte(reader)?;
+            let mut distance = (byte & 0x7f) as u64;
+            while byte & 0x80 != 0 {
+                byte = read_byte(reader)?;
+                distance = ((distance + 1) << 7) | (byte & 0x7f) as u64;
+            }
+            let base_offset = offset
+                .checked_sub(distance)
+                .ok_or(anyhow!("delta base offset out of range at {}", offset))?;
+            EntryKind::OfsDelta(base_offset)
+        }
+        OBJ_REF_DELTA => {
+            let mut base_sha = [0u8; 20];
+            reader.read_exact(&mut base_sha)?;
+            EntryKind::RefDelta(base_sha)
+        }
+        _ => Err(anyhow!(
+            "unknown object type {} in pack at offset {}",
+            type_code,
+            offset
+        ))?,
+    };
+    Ok((kind, size))
+}
+
+/// Reads the type and size an entry starts with, leaving `reader` after them.
+fn read_entry_header<R: BufRead + Seek>(
+    reader: &mut R,
+    offset: u64,
+) -> anyhow::Result<(u8, usize)> {
+    reader.seek(SeekFrom::Start(offset))?;
+    let mut byte = read_byte(reader)?;
+    let type_code = (byte >> 4) & 0x7;
+    let mut size = (byte & 0x0f) as usize;
+    let mut shift = 4;
+    while byte & 0x80 != 0 {
+        byte = read_byte(reader)?;
+        size |= ((byte & 0x7f) as usize)
+            .checked_shl(shift)
+            .ok_or(anyhow!("corrupt pack entry header at offset {}", offset))?;
+        shift += 7;
+    }
+    Ok((type_code, size))
+}
+
+/// Rebuilds an object from `base` and a git delta: a header with the base and result sizes,
+/// followed by copy-from-base and insert instructions.
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let corrupt = || anyhow!("corrupt delta");
+    let mut position = 0;
+    let base_size = read_size(delta, &mut position).ok_or_else(corrupt)?;
+    if base_size != base.len() {
+        Err(anyhow!(
+            "delta expects a base of {} bytes but got {}",
+            base_size,
+            base.len()
+        ))?
+    }
+    let result_size = read_size(delta, &mut position).ok_or_else(corrupt)?;
+    // Each instruction of at least one byte adds at most 0x10000 bytes, which bounds what a
+    // delta with a corrupt result size can really produce.
+    let mut result = Vec::with_capacity(result_size.min(delta.len().saturating_mul(0x10000)));
+    while position < delta.len() {
+        let instruction = delta[position];
+        position += 1;
+        if instruction & 0x80 != 0 {
+            let mut copy_offset = 0usize;
+            let mut copy_size = 0usize;
+            for i in 0..4 {
+                if instruction & (1 << i) != 0 {
+                    copy_offset |= (*delta.get(position).ok_or_else(corrupt)? as usize) << (i * 8);
+                    position += 1;
+                }
+            }
+            for i in 0..3 {
+                if instruction & (0x10 << i) != 0 {
+                    copy_size |= (*delta.get(position).ok_or_else(corrupt)? as usize) << (i * 8);
+                    position += 1;
+                }
+            }
+            if copy_size == 0 {
+                copy_size = 0x10000;
+            }
+            let chunk = base
+                .get(copy_offset..copy_offset + copy_size)
+                .ok_or_else(corrupt)?;
+            result.extend_from_slice(chunk);
+        } else if instruction != 0 {
+            let length = instruction as usize;
+            let chunk = delta.get(position..position + length).ok_or_else(corrupt)?;
+            result.extend_from_slice(chunk);
+            position += length;
+        } else {
+            Err(anyhow!("delta uses the reserved instruction 0"))?
+        }
+        if result.len() > result_size {
+            Err(corrupt())?
+        }
+    }
+    if result.len() != result_size {
+        Err(corrupt())?
+    }
+    Ok(result)
+}
+
+fn object_type_name(object_type: &str) -> anyhow::Result<&'static str> {
+    match object_type {
+        "commit" => Ok("commit"),
+        "tree" => Ok("tree"),
+        "blob" => Ok("blob"),
+        "tag" => Ok("tag"),
+        _ => Err(anyhow!("invalid delta base type \"{}\"", object_type)),
+    }
+}
+
+/// Reads a little-endian base-128 size from the delta header.
+fn read_size(data: &[u8], position: &mut usize) -> Option<usize> {
+    let mut size = 0usize;
+    let mut shift = 0;
+    loop {
+        let byte = *data.get(*position)?;
+        *position += 1;
+        size |= ((byte & 0x7f) as usize).checked_shl(shift)?;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Some(size);
+        }
+    }
+}
+
+fn read_byte(reader: &mut impl Read) -> anyhow::Result<u8> {
+    let mut byte = [0u8; 1];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn read_u32(data: &[u8], start: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(start..start + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_sha(data: &[u8], start: usize) -> Option<Sha> {
+    data.get(start..start + 20)?.try_into().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A delta for `base`, declaring `result_size`, with the given instructions.
+    fn delta(base: &[u8], result_size: usize, instructions: &[u8]) -> Vec<u8> {
+        let mut delta = vec![];
+        for mut size in [base.len(), result_size] {
+            while size >= 0x80 {
+                delta.push(size as u8 | 0x80);
+                size >>= 7;
+            }
+            delta.push(size as u8);
+        }
+        delta.extend_from_slice(instructions);
+        delta
+    }
+
+    #[test]
+    fn applies_copy_and_insert_instructions() {
+        let base = b"hello world";
+        // Copy 6 bytes from offset 0, then insert "there".
+        let delta = delta(base, 11, &[0x90, 6, 5, b't', b'h', b'e', b'r', b'e']);
+        assert_eq!(apply_delta(base, &delta).unwrap(), b"hello there");
+    }
+
+    #[test]
+    fn rejects_a_result_size_the_instructions_do_not_produce() {
+        let base = b"base";
+        // Claims 1 TiB from a single insert, which must fail rather than reserve it.
+        let huge = delta(base, 1 << 40, &[1, b'x']);
+        assert!(apply_delta(base, &huge).is_err());
+        let short = delta(base, 3, &[0x90, 4]);
+        assert!(apply_delta(base, &short).is_err());
+        let long = delta(base, 5, &[0x90, 4]);
+        assert!(apply_delta(base, &long).is_err());
+    }
+
+    #[test]
+    fn rejects_sizes_that_overflow() {
+        let mut delta = vec![0x80; 12];
+        delta.push(1);
+        assert!(apply_delta(b"", &delta).is_err());
+    }
+}
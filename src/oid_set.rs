@@ -0,0 +1,102 @@
+//! A set of object ids for walks that may cover every object of a large repository, where a
+//! `HashSet` of ids takes around 50 bytes per id. An object in a pack takes a bit at its
+//! position in the pack's index; the others are kept as a sorted list of ids, with the latest
+//! ones in a small batch that is merged into the list as it grows.
+
+use crate::object_storage::Sha;
+use crate::object_storage::pack::{self, Pack};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// The fewest ids collected in the batch before it is merged into the sorted list.
+const MIN_BATCH: usize = 4096;
+
+pub struct OidSet {
+    /// The packs when the set was created: objects packed later count as loose ones.
+    packs: Arc<Vec<Pack>>,
+    /// A bit for each object of each pack, by position, allocated with the pack's first.
+    packed: Vec<Vec<u64>>,
+    /// The ids of objects in none of the packs, sorted.
+    sorted: Vec<Sha>,
+    /// The ids of objects in none of the packs that are not in `sorted` yet.
+    batch: HashSet<Sha>,
+}
+
+impl OidSet {
+    pub fn new() -> anyhow::Result<Self> {
+        let packs = pack::loaded_packs()?;
+        Ok(Self {
+            packed: vec![vec![]; packs.len()],
+            packs,
+            sorted: vec![],
+            batch: HashSet::new(),
+        })
+    }
+
+    /// Adds `sha`; returns whether it was not in the set yet.
+    pub fn insert(&mut self, sha: Sha) -> bool {
+        let inserted = match self.locate(&sha) {
+            Some((pack, position)) => {
+                let bits = &mut self.packed[pack];
+                if bits.is_empty() {
+                    bits.resize(self.packs[pack].shas().len().div_ceil(64), 0);
+                }
+                let mask = 1 << (position % 64);
+                let new = bits[position / 64] & mask == 0;
+                bits[position / 64] |= mask;
+                new
+            }
+            None => self.sorted.binary_search(&sha).is_err() && self.batch.insert(sha),
+        };
+        if inserted && self.batch.len() >= MIN_BATCH.max(self.sorted.len() / 8) {
+            self.compact();
+        }
+        inserted
+    }
+
+    pub fn contains(&self, sha: &Sha) -> bool {
+        match self.locate(sha) {
+            Some((pack, position)) => self.packed[pack]
+                .get(position / 64)
+                .is_some_and(|word| word & (1 << (position % 64)) != 0),
+            None => self.sorted.binary_search(sha).is_ok() || self.batch.contains(sha),
+        }
+    }
+
+    /// The ids in the set: the packed ones pack by pack, then the others.
+    pub fn iter(&self) -> impl Iterator<Item = Sha> + '_ {
+        let packed = self
+            .packs
+            .iter()
+            .zip(&self.packed)
+            .flat_map(|(pack, bits)| {
+                bits.iter()
+                    .enumerate()
+                    .flat_map(move |(word_index, &word)| {
+                        (0..64)
+                            .filter(move |bit| word & (1 << bit) != 0)
+                            .map(move |bit| pack.shas()[word_index * 64 + bit])
+                    })
+            });
+        packed
+            .chain(self.sorted.iter().copied())
+            .chain(self.batch.iter().copied())
+    }
+
+    /// The first pack that has `sha`, and its position there.
+    fn locate(&self, sha: &Sha) -> Option<(usize, usize)> {
+        self.packs.iter().enumerate().find_map(|(i, pack)| {
+            pack.shas()
+                .binary_search(sha)
+                .ok()
+                .map(|position| (i, position))
+        })
+    }
+
+    /// Merges the batch into the sorted list. The batch is kept to a fraction of the list, so
+    /// the list is sorted again only a few times as often as it doubles.
+    fn compact(&mut self) {
+        self.sorted.extend(self.batch.drain());
+        self.sorted.sort_unstable();
+    }
+}
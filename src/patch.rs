@@ -0,0 +1,297 @@
+use crate::diff::{self, Edit};
+use anyhow::anyhow;
+
+/// The changes a patch makes to one file, from its `diff --git` header on.
+pub struct FilePatch {
+    /// The path before the change, `None` when the file is created.
+    pub old_path: Option<String>,
+    /// The path after the change, `None` when the file is deleted.
+    pub new_path: Option<String>,
+    pub old_mode: Option<u32>,
+    pub new_mode: Option<u32>,
+    /// The abbreviated blob ids of the `index <old>..<new>` line.
+    pub old_blob: Option<String>,
+    pub new_blob: Option<String>,
+    pub hunks: Vec<Hunk>,
+    /// `Binary files ... differ` or a `GIT binary patch`, which cannot be applied.
+    pub binary: bool,
+}
+
+/// An `@@ -<old_start>,<old_count> +<new_start>,<new_count> @@` hunk; where it starts in the
+/// new file follows from the hunks before it.
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_count: usize,
+    pub new_count: usize,
+    /// Each line with its ` `, `-` or `+` tag and its newline, unless the patch says there is
+    /// none at the end of the file.
+    pub lines: Vec<(u8, Vec<u8>)>,
+}
+
+impl FilePatch {
+    /// The path the patch is reported under: the new one, or the old one for a deletion.
+    pub fn path(&self) -> &str {
+        self.new_path
+            .as_deref()
+            .or(self.old_path.as_deref())
+            .unwrap_or_default()
+    }
+}
+
+impl Hunk {
+    /// The lines the file has where the hunk applies.
+    pub fn preimage(&self) -> Vec<&[u8]> {
+        self.side(b'-')
+    }
+
+    /// The lines it has there afterwards.
+    pub fn postimage(&self) -> Vec<&[u8]> {
+        self.side(b'+')
+    }
+
+    fn side(&self, changed: u8) -> Vec<&[u8]> {
+        self.lines
+            .iter()
+            .filter(|(tag, _)| *tag == b' ' || *tag == changed)
+            .map(|(_, line)| line.as_slice())
+            .collect()
+    }
+}
+
+/// Parses the file patches of a git-style patch, such as `diff` writes; text before the first
+/// `diff --git` line (a commit message, say) is skipped. Paths lose their `a/` and `b/`
+/// prefixes.
+pub fn parse(patch: &[u8]) -> anyhow::Result<Vec<FilePatch>> {
+    let lines = diff::split_lines(patch);
+    let mut patches: Vec<FilePatch> = vec![];
+    let mut i = 0;
+    while i < lines.len() {
+        let line = text(lines[i]);
+        i += 1;
+        let Some(names) = line.strip_prefix("diff --git ") else {
+            continue;
+        };
+        // Until `---` and `+++` say otherwise, both paths come from the header.
+        let header_path = names
+            .split_once(" b/")
+            .map(|(old, _)| old.strip_prefix("a/").unwrap_or(old).to_string());
+        let mut file = FilePatch {
+            old_path: header_path.clone(),
+            new_path: header_path,
+            old_mode: None,
+            new_mode: None,
+            old_blob: None,
+            new_blob: None,
+            hunks: vec![],
+            binary: false,
+        };
+        while i < lines.len() && !text(lines[i]).starts_with("diff --git ") {
+            let line = text(lines[i]);
+            i += 1;
+            if let Some(mode) = line.strip_prefix("new file mode ") {
+                file.old_path = None;
+                file.new_mode = Some(parse_mode(mode)?);
+            } else if let Some(mode) = line.strip_prefix("deleted file mode ") {
+                file.new_path = None;
+                file.old_mode = Some(parse_mode(mode)?);
+            } else if let Some(mode) = line.strip_prefix("old mode ") {
+                file.old_mode = Some(parse_mode(mode)?);
+            } else if let Some(mode) = line.strip_prefix("new mode ") {
+                file.new_mode = Some(parse_mode(mode)?);
+            } else if let Some(name) = line.strip_prefix("rename from ") {
+                file.old_path = Some(name.to_string());
+            } else if let Some(name) = line.strip_prefix("rename to ") {
+                file.new_path = Some(name.to_string());
+            } else if let Some(index) = line.strip_prefix("index ") {
+                let (blobs, mode) = index.split_once(' ').unwrap_or((index, ""));
+                let (old, new) = blobs
+                    .split_once("..")
+                    .ok_or(anyhow!("invalid index line: {}", line))?;
+                file.old_blob = Some(old.to_string());
+                file.new_blob = Some(new.to_string());
+                if !mode.is_empty() {
+                    file.old_mode = Some(parse_mode(mode)?);
+                    file.new_mode = file.old_mode;
+                }
+            } else if let Some(name) = line.strip_prefix("--- ") {
+                file.old_path = patch_path(name, "a/");
+            } else if let Some(name) = line.strip_prefix("+++ ") {
+                file.new_path = patch_path(name, "b/");
+            } else if line.starts_with("Binary files ") || line == "GIT binary patch" {
+                file.binary = true;
+            } else if line.starts_with("@@ ") {
+                let mut hunk = parse_hunk_header(line)?;
+                let (mut old_left, mut new_left) = (hunk.old_count, hunk.new_count);
+                while old_left > 0 || new_left > 0 {
+                    let Some(&content) = lines.get(i) else {
+                        Err(anyhow!("corrupt patch: hunk ends early: {}", line))?
+                    };
+                    i += 1;
+                    let (tag, rest) = match content.split_first() {
+                        Some((b'\\', _)) => {
+                            strip_last_newline(&mut hunk);
+                            continue;
+                        }
+                        Some((b'\n', _)) => (b' ', &b"\n"[..]),
+                        Some((&tag, rest)) if matches!(tag, b' ' | b'-' | b'+') => (tag, rest),
+                        _ => Err(anyhow!("corrupt patch: bad hunk line: {}", text(content)))?,
+                    };
+                    let (old_used, new_used) = match tag {
+                        b' ' => (1, 1),
+                        b'-' => (1, 0),
+                        _ => (0, 1),
+                    };
+                    if old_left < old_used || new_left < new_used {
+                        Err(anyhow!("corrupt patch: hunk is longer than {}", line))?
+                    }
+                    (old_left, new_left) = (old_left - old_used, new_left - new_used);
+                    hunk.lines.push((tag, rest.to_vec()));
+                }
+                // `\ No newline at end of file` after a hunk's last line.
+                if lines.get(i).is_some_and(|line| line.starts_with(b"\\")) {
+                    strip_last_newline(&mut hunk);
+                    i += 1;
+                }
+                file.hunks.push(hunk);
+            }
+        }
+        patches.push(file);
+    }
+    Ok(patches)
+}
+
+/// Applies `hunks` to `content`. A hunk whose lines moved is looked for above and below where
+/// it says it applies, but its context has to match exactly. Fails with the line number of
+/// the first hunk that does not apply.
+pub fn apply(content: &[u8], hunks: &[Hunk]) -> Result<Vec<u8>, usize> {
+    let lines = diff::split_lines(content);
+    let mut out: Vec<u8> = vec![];
+    // Where the part of `content` not yet copied to `out` starts, and how far the hunks
+    // applied so far were moved.
+    let mut copied = 0;
+    let mut offset: isize = 0;
+    for hunk in hunks {
+        let preimage = hunk.preimage();
+        // An empty hunk range starts after the line it names.
+        let stated = match hunk.old_count {
+            0 => hunk.old_start,
+            _ => hunk.old_start.saturating_sub(1),
+        };
+        let expected = (stated as isize + offset).max(copied as isize) as usize;
+        let matches_at = |start: usize| {
+            start >= copied
+                && start + preimage.len() <= lines.len()
+                && lines[start..start + preimage.len()] == preimage[..]
+        };
+        let found = (0..=lines.len()).find_map(|distance| {
+            [
+                expected.checked_add(distance),
+                expected.checked_sub(distance),
+            ]
+            .into_iter()
+            .flatten()
+            .find(|&start| matches_at(start))
+        });
+        let Some(start) = found else {
+            return Err(hunk.old_start);
+        };
+        for line in &lines[copied..start] {
+            out.extend_from_slice(line);
+        }
+        for line in hunk.postimage() {
+            out.extend_from_slice(line);
+        }
+        copied = start + preimage.len();
+        offset = start as isize - stated as isize;
+    }
+    for line in &lines[copied..] {
+        out.extend_from_slice(line);
+    }
+    Ok(out)
+}
+
+/// The hunks of a patch from `old` to `new`, with three lines of context, as `diff` would
+/// write it.
+pub fn hunks_between(old: &[u8], new: &[u8]) -> Vec<Hunk> {
+    let (old_lines, new_lines) = (diff::split_lines(old), diff::split_lines(new));
+    let edits = diff::diff_lines(&old_lines, &new_lines);
+    diff::hunk_ranges(&edits, 3)
+        .into_iter()
+        .map(|range| {
+            let old_before = edits[..range.start]
+                .iter()
+                .filter(|edit| !matches!(edit, Edit::Insert(_)))
+                .count();
+            let lines: Vec<(u8, Vec<u8>)> = edits[range]
+                .iter()
+                .map(|edit| match *edit {
+                    Edit::Equal(i, _) => (b' ', old_lines[i].to_vec()),
+                    Edit::Delete(i) => (b'-', old_lines[i].to_vec()),
+                    Edit::Insert(j) => (b'+', new_lines[j].to_vec()),
+                })
+                .collect();
+            let old_count = lines.iter().filter(|(tag, _)| *tag != b'+').count();
+            let new_count = lines.iter().filter(|(tag, _)| *tag != b'-').count();
+            Hunk {
+                // An empty range names the line it comes after.
+                old_start: if old_count == 0 {
+                    old_before
+                } else {
+                    old_before + 1
+                },
+                old_count,
+                new_count,
+                lines,
+            }
+        })
+        .collect()
+}
+
+/// For `\ No newline at end of file`, which follows the line it is about.
+fn strip_last_newline(hunk: &mut Hunk) {
+    if let Some((_, last)) = hunk.lines.last_mut()
+        && last.ends_with(b"\n")
+    {
+        last.pop();
+    }
+}
+
+fn text(line: &[u8]) -> &str {
+    std::str::from_utf8(line)
+        .unwrap_or_default()
+        .trim_end_matches('\n')
+}
+
+fn parse_mode(mode: &str) -> anyhow::Result<u32> {
+    u32::from_str_radix(mode.trim(), 8).map_err(|_| anyhow!("invalid mode '{}'", mode))
+}
+
+/// The path of a `---` or `+++` line without its `a/` or `b/` prefix; `None` for `/dev/null`.
+fn patch_path(name: &str, prefix: &str) -> Option<String> {
+    let name = name.split('\t').next().unwrap_or(name);
+    (name != "/dev/null").then(|| name.strip_prefix(prefix).unwrap_or(name).to_string())
+}
+
+fn parse_hunk_header(line: &str) -> anyhow::Result<Hunk> {
+    let invalid = || anyhow!("corrupt patch: bad hunk header: {}", line);
+    let ranges = line
+        .strip_prefix("@@ -")
+        .and_then(|rest| rest.split_once(" @@"))
+        .map(|(ranges, _)| ranges)
+        .ok_or_else(invalid)?;
+    let (old, new) = ranges.split_once(" +").ok_or_else(invalid)?;
+    let range = |range: &str| -> Option<(usize, usize)> {
+        match range.split_once(',') {
+            Some((start, count)) => Some((start.parse().ok()?, count.parse().ok()?)),
+            None => Some((range.parse().ok()?, 1)),
+        }
+    };
+    let (old_start, old_count) = range(old).ok_or_else(invalid)?;
+    let (_, new_count) = range(new).ok_or_else(invalid)?;
+    Ok(Hunk {
+        old_start,
+        old_count,
+        new_count,
+        lines: vec![],
+    })
+}
@@ -0,0 +1,662 @@
+//! Patch mode, as `add -p` and `checkout -p` use it: the changes to each file are shown hunk by hunk, and the
+//! user answers whether to take each one, may split a hunk into smaller ones or edit it, and
+//! the content with the chosen hunks is built from the line diff. A mode says what taking a
+//! hunk means: staging applies the chosen hunks to the old content, while discarding reverts
+//! them from the new one. Answers are read from stdin, or from a script of answers, one per
+//! line, for running the prompts without a terminal; when the answers run out, the rest of
+//! the file is left undecided.
+
+use crate::config::Config;
+use crate::diff::{self, Edit};
+use crate::index;
+use crate::object_storage::{ObjectStorage, Sha};
+use crate::trace2;
+use anyhow::anyhow;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::ops::Range;
+use std::process::Command;
+
+/// How many lines of context hunks have, as in `diff`.
+const CONTEXT: usize = 3;
+
+/// What taking a hunk does, with the words the prompts use for it.
+pub struct PatchMode {
+    /// "stage", "discard" or "unstage".
+    pub verb: &'static str,
+    /// Its `-ing` form, for the instructions for editing a hunk.
+    pub gerund: &'static str,
+    /// Where the change goes, such as " from worktree", or "".
+    pub target: &'static str,
+    /// The prefixes of the old and new paths in the `diff --git` header.
+    pub prefixes: (&'static str, &'static str),
+    /// Whether chosen hunks are reverted from the new content rather than applied to the old.
+    pub revert: bool,
+}
+
+/// The mode of `add -p`.
+pub const STAGE: PatchMode = PatchMode {
+    verb: "stage",
+    gerund: "staging",
+    target: "",
+    prefixes: ("a/", "b/"),
+    revert: false,
+};
+
+/// The mode of `checkout -p`, which discards changes to the index from the working tree.
+pub const DISCARD: PatchMode = PatchMode {
+    verb: "discard",
+    gerund: "discarding",
+    target: " from worktree",
+    prefixes: ("a/", "b/"),
+    revert: true,
+};
+
+/// The mode of `checkout -p HEAD`, which discards changes to `HEAD` from both.
+pub const DISCARD_BOTH: PatchMode = PatchMode {
+    verb: "discard",
+    gerund: "discarding",
+    target: " from index and worktree",
+    prefixes: ("a/", "b/"),
+    revert: true,
+};
+
+/// The mode of `checkout -p <tree-ish>` for another tree, whose differences from the working
+/// tree are applied to both; they are shown as the reverse of the diff to the working tree.
+pub const APPLY_BOTH: PatchMode = PatchMode {
+    verb: "apply",
+    gerund: "applying",
+    target: " to index and worktree",
+    prefixes: ("b/", "a/"),
+    revert: false,
+};
+
+/// One side of the change to a file: its mode and content.
+pub struct Side<'a> {
+    pub mode: u32,
+    pub content: &'a [u8],
+}
+
+/// The change to a file shown hunk by hunk; `None` as the new side is a deletion.
+pub struct FileChange<'a> {
+    pub path: &'a str,
+    pub old: Side<'a>,
+    pub new: Option<Side<'a>>,
+}
+
+/// What was chosen for a file.
+#[derive(Default)]
+pub struct Choice {
+    /// Whether the change of mode was taken.
+    pub mode: bool,
+    /// Whether the deletion of the file was taken.
+    pub deletion: bool,
+    /// The content with the chosen hunks, when any was chosen.
+    pub content: Option<Vec<u8>>,
+}
+
+/// Where answers come from, and whether the user has quit.
+pub struct Session {
+    mode: &'static PatchMode,
+    answers: Box<dyn BufRead>,
+    quit: bool,
+    /// Whether the line that ends the last file shown is still to be written, after any
+    /// question about applying its hunks.
+    end_of_file: bool,
+}
+
+/// A hunk as it is offered: the edits it covers, with their context, and the answer to it.
+struct Hunk {
+    range: Range<usize>,
+    answer: HunkAnswer,
+    /// Whether it came from splitting a hunk, which leaves its header without the function.
+    split: bool,
+}
+
+#[derive(Clone, PartialEq)]
+enum HunkAnswer {
+    Undecided,
+    Take,
+    Skip,
+    /// Taken as the user edited it.
+    Edited(Vec<TaggedLine>),
+}
+
+/// A line of an edited hunk with its ` `, `-` or `+` tag.
+type TaggedLine = (u8, Vec<u8>);
+
+/// The line diff of a file's content hunks refer to.
+struct LineDiff<'a> {
+    old: Vec<&'a [u8]>,
+    new: Vec<&'a [u8]>,
+    edits: Vec<Edit>,
+}
+
+impl Session {
+    /// Reads answers from `script`, a file of answers one per line, or else from stdin.
+    pub fn new(mode: &'static PatchMode, script: Option<&str>) -> anyhow::Result<Self> {
+        let answers: Box<dyn BufRead> = match script {
+            Some(script) => {
+                Box::new(BufReader::new(fs::File::open(script).map_err(|err| {
+                    anyhow!("could not open '{}': {}", script, err)
+                })?))
+            }
+            None => Box::new(BufReader::new(io::stdin())),
+        };
+        Ok(Self {
+            mode,
+            answers,
+            quit: false,
+            end_of_file: false,
+        })
+    }
+
+    /// Whether the user quit, which leaves the remaining files as they are.
+    pub fn has_quit(&self) -> bool {
+        self.quit
+    }
+
+    /// Offers the mode change, the deletion or the hunks of `change` and returns what was
+    /// taken. Binary content is not offered.
+    pub fn choose(&mut self, change: &FileChange) -> anyhow::Result<Choice> {
+        let mut choice = Choice::default();
+        if self.quit {
+            return Ok(choice);
+        }
+        self.finish()?;
+        let mut out = io::stdout().lock();
+        let Some(new) = &change.new else {
+            write_header(&mut out, change, self.mode)?;
+            choice.deletion = matches!(self.ask_whole("deletion", 1, 1)?, Some('y' | 'a'));
+            self.end_of_file = true;
+            return Ok(choice);
+        };
+        let binary = diff::is_binary(change.old.content) || diff::is_binary(new.content);
+        let line_diff = LineDiff::new(change.old.content, new.content);
+        let mut hunks: Vec<Hunk> = match binary {
+            true => vec![],
+            false => diff::hunk_ranges(&line_diff.edits, CONTEXT)
+                .into_iter()
+                .map(|range| Hunk {
+                    range,
+                    answer: HunkAnswer::Undecided,
+                    split: false,
+                })
+                .collect(),
+        };
+        let mode_changed = change.old.mode != new.mode;
+        if hunks.is_empty() && !mode_changed {
+            return Ok(choice);
+        }
+        write_header(&mut out, change, self.mode)?;
+        if mode_changed {
+            writeln!(
+                out,
+                "old mode {:06o}\nnew mode {:06o}",
+                change.old.mode, new.mode
+            )?;
+            let total = hunks.len() + 1;
+            let Some(letter) = self.ask_whole("mode change", 1, total)? else {
+                self.end_of_file = true;
+                return Ok(choice);
+            };
+            choice.mode = matches!(letter, 'y' | 'a');
+            // `a` and `d` are about the hunks after it too.
+            if let Some(answer) = match letter {
+                'a' => Some(HunkAnswer::Take),
+                'd' => Some(HunkAnswer::Skip),
+                _ => None,
+            } {
+                for hunk in &mut hunks {
+                    hunk.answer = answer.clone();
+                }
+            }
+        }
+        let mut current = 0;
+        while current < hunks.len() {
+            if hunks[current].answer != HunkAnswer::Undecided {
+                current += 1;
+                continue;
+            }
+            let number = current + 1 + mode_changed as usize;
+            let total = hunks.len() + mode_changed as usize;
+            let splits = line_diff.split(&hunks[current].range);
+            let mut options = "y,n,q,a,d".to_string();
+            if splits.len() > 1 {
+                options.push_str(",s");
+            }
+            options.push_str(",e,?");
+            line_diff.write_hunk(&mut out, &hunks[current])?;
+            write!(
+                out,
+                "({}/{}) {} this hunk{} [{}]? ",
+                number,
+                total,
+                capitalize(self.mode.verb),
+                self.mode.target,
+                options
+            )?;
+            out.flush()?;
+            let answer = self.read_answer()?;
+            match answer.as_deref() {
+                Some("y") => hunks[current].answer = HunkAnswer::Take,
+                Some("n") => hunks[current].answer = HunkAnswer::Skip,
+                Some(letter @ ("a" | "d")) => {
+                    let answer = match letter {
+                        "a" => HunkAnswer::Take,
+                        _ => HunkAnswer::Skip,
+                    };
+                    for hunk in &mut hunks[current..] {
+                        if hunk.answer == HunkAnswer::Undecided {
+                            hunk.answer = answer.clone();
+                        }
+                    }
+                }
+                None => break,
+                Some("") => continue,
+                Some("q") => {
+                    self.quit = true;
+                    break;
+                }
+                Some("s") if splits.len() > 1 => {
+                    writeln!(out, "Split into {} hunks.", splits.len())?;
+                    let split = splits.into_iter().map(|range| Hunk {
+                        range,
+                        answer: HunkAnswer::Undecided,
+                        split: true,
+                    });
+                    hunks.splice(current..current + 1, split);
+                    continue;
+                }
+                Some("e") => {
+                    if let Some(lines) = self.edit(&line_diff, &hunks[current])? {
+                        hunks[current].answer = HunkAnswer::Edited(lines);
+                    } else {
+                        continue;
+                    }
+                }
+                _ => {
+                    self.write_help(&mut out, splits.len() > 1)?;
+                    continue;
+                }
+            }
+            current += 1;
+        }
+        self.end_of_file = true;
+        if hunks
+            .iter()
+            .any(|hunk| matches!(hunk.answer, HunkAnswer::Take | HunkAnswer::Edited(_)))
+        {
+            choice.content = Some(line_diff.resolve(&hunks, self.mode.revert)?);
+        }
+        Ok(choice)
+    }
+
+    /// Ends the last file shown, once nothing more is asked about it.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if std::mem::take(&mut self.end_of_file) {
+            println!();
+        }
+        Ok(())
+    }
+
+    /// Asks about a change of the whole file, such as its mode, and returns the answer: `y`,
+    /// `n`, `a` or `d`, or `None` when the user quit or the answers ran out.
+    fn ask_whole(
+        &mut self,
+        what: &str,
+        number: usize,
+        total: usize,
+    ) -> anyhow::Result<Option<char>> {
+        let mut out = io::stdout().lock();
+        loop {
+            write!(
+                out,
+                "({}/{}) {} {}{} [y,n,q,a,d,?]? ",
+                number,
+                total,
+                capitalize(self.mode.verb),
+                what,
+                self.mode.target
+            )?;
+            out.flush()?;
+            match self.read_answer()?.as_deref() {
+                Some(letter @ ("y" | "n" | "a" | "d")) => return Ok(letter.chars().next()),
+                None => return Ok(None),
+                Some("") => {}
+                Some("q") => {
+                    self.quit = true;
+                    return Ok(None);
+                }
+                _ => self.write_help(&mut out, false)?,
+            }
+        }
+    }
+
+    /// Asks `question` until the answer is yes or no, and returns whether it was yes; the end
+    /// of the answers is no.
+    pub fn ask_yes_no(&mut self, question: &str) -> anyhow::Result<bool> {
+        let mut out = io::stdout().lock();
+        loop {
+            write!(out, "{}", question)?;
+            out.flush()?;
+            match self.read_answer()?.as_deref() {
+                Some("y") => return Ok(true),
+                Some("n") | None => return Ok(false),
+                _ => {}
+            }
+        }
+    }
+
+    /// The next answer, lowercased to its first letter and empty when blank; `None` is the end
+    /// of the answers.
+    fn read_answer(&mut self) -> anyhow::Result<Option<String>> {
+        let mut line = String::new();
+        if self.answers.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let first = line.trim().chars().next();
+        Ok(Some(
+            first
+                .map(|first| first.to_lowercase().to_string())
+                .unwrap_or_default(),
+        ))
+    }
+
+    fn write_help(&self, out: &mut impl Write, can_split: bool) -> io::Result<()> {
+        let (verb, target) = (self.mode.verb, self.mode.target);
+        writeln!(out, "y - {} this hunk{}", verb, target)?;
+        writeln!(out, "n - do not {} this hunk{}", verb, target)?;
+        writeln!(
+            out,
+            "q - quit; do not {} this hunk or any of the remaining ones",
+            verb
+        )?;
+        writeln!(
+            out,
+            "a - {} this hunk and all later hunks in the file",
+            verb
+        )?;
+        writeln!(
+            out,
+            "d - do not {} this hunk or any of the later hunks in the file",
+            verb
+        )?;
+        if can_split {
+            writeln!(out, "s - split the current hunk into smaller hunks")?;
+        }
+        writeln!(out, "e - manually edit the current hunk")?;
+        writeln!(out, "? - print help")
+    }
+
+    /// Lets the user edit the hunk in their editor and returns its lines, or `None` when they
+    /// removed them all or gave up on a hunk that does not apply.
+    fn edit(
+        &mut self,
+        line_diff: &LineDiff,
+        hunk: &Hunk,
+    ) -> anyhow::Result<Option<Vec<TaggedLine>>> {
+        let (remove, keep) = match self.mode.revert {
+            false => ('-', '+'),
+            true => ('+', '-'),
+        };
+        let mut text = b"# Manual hunk edit mode -- see bottom for a quick guide.\n".to_vec();
+        line_diff.write_hunk(&mut text, hunk)?;
+        write!(
+            text,
+            "# ---\n\
+             # To remove '{remove}' lines, make them ' ' lines (context).\n\
+             # To remove '{keep}' lines, delete them.\n\
+             # Lines starting with # will be removed.\n\
+             #\n\
+             # If the patch applies cleanly, the edited hunk will immediately be marked for {}.\n\
+             # If it does not apply cleanly, you will be given an opportunity to\n\
+             # edit again.  If all lines of the hunk are removed, then the edit is\n\
+             # aborted and the hunk is left unchanged.\n",
+            self.mode.gerund
+        )?;
+        let path = ObjectStorage::git_dir().join("addp-hunk-edit.diff");
+        loop {
+            fs::write(&path, &text)?;
+            run_editor(&path)?;
+            let edited = fs::read(&path)?;
+            fs::remove_file(&path)?;
+            let lines = parse_edited(&edited);
+            if lines.is_empty() {
+                return Ok(None);
+            }
+            if line_diff.fits(&hunk.range, &lines, self.mode.revert) {
+                return Ok(Some(lines));
+            }
+            if !self.ask_yes_no(
+                "Your edited hunk does not apply. Edit again (saying \"no\" discards!) [y/n]? ",
+            )? {
+                return Ok(None);
+            }
+            text = edited;
+        }
+    }
+}
+
+impl<'a> LineDiff<'a> {
+    fn new(old: &'a [u8], new: &'a [u8]) -> Self {
+        let (old, new) = (diff::split_lines(old), diff::split_lines(new));
+        let edits = diff::diff_lines(&old, &new);
+        Self { old, new, edits }
+    }
+
+    fn write_hunk(&self, out: &mut impl Write, hunk: &Hunk) -> io::Result<()> {
+        diff::write_hunk(
+            out,
+            &self.old,
+            &self.new,
+            &self.edits,
+            hunk.range.clone(),
+            !hunk.split,
+        )
+    }
+
+    /// The smaller hunks `range` splits into, one for each run of changes; the context
+    /// between two runs goes with both, as git shows it.
+    fn split(&self, range: &Range<usize>) -> Vec<Range<usize>> {
+        let is_change = |i: &usize| !matches!(self.edits[*i], Edit::Equal(..));
+        let changes: Vec<usize> = range.clone().filter(is_change).collect();
+        let mut runs: Vec<Range<usize>> = vec![];
+        for i in changes {
+            match runs.last_mut() {
+                Some(run) if run.end == i => run.end = i + 1,
+                _ => runs.push(i..i + 1),
+            }
+        }
+        (0..runs.len())
+            .map(|n| {
+                let start = if n == 0 { range.start } else { runs[n - 1].end };
+                let end = match runs.get(n + 1) {
+                    Some(next) => next.start,
+                    None => range.end,
+                };
+                start..end
+            })
+            .collect()
+    }
+
+    /// The lines of `range` on the old side, or the new.
+    fn side(&self, range: &Range<usize>, new: bool) -> Vec<&'a [u8]> {
+        self.edits[range.clone()]
+            .iter()
+            .filter_map(|edit| match (*edit, new) {
+                (Edit::Equal(i, _), false) | (Edit::Delete(i), false) => Some(self.old[i]),
+                (Edit::Equal(_, j), true) | (Edit::Insert(j), true) => Some(self.new[j]),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Whether an edited hunk still fits where `range` is: it starts from the old lines
+    /// there when it is applied, and ends with the new ones when it is reverted.
+    fn fits(&self, range: &Range<usize>, lines: &[TaggedLine], revert: bool) -> bool {
+        let (kept, expected) = match revert {
+            false => (b'-', self.side(range, false)),
+            true => (b'+', self.side(range, true)),
+        };
+        let side: Vec<&[u8]> = lines
+            .iter()
+            .filter(|(tag, _)| *tag == b' ' || *tag == kept)
+            .map(|(_, line)| line.as_slice())
+            .collect();
+        side == expected
+    }
+
+    /// The content with the hunks taken: applied to the old content, or reverted from the
+    /// new one. Every change is in one hunk; those of hunks not taken are left out when
+    /// applying and kept when reverting. Edited hunks replace their range as the user edited
+    /// them.
+    fn resolve(&self, hunks: &[Hunk], revert: bool) -> anyhow::Result<Vec<u8>> {
+        let mut taken = vec![false; self.edits.len()];
+        let mut edited: Vec<(&Range<usize>, &[TaggedLine])> = vec![];
+        for hunk in hunks {
+            match &hunk.answer {
+                HunkAnswer::Take => taken[hunk.range.clone()].fill(true),
+                HunkAnswer::Edited(lines) => edited.push((&hunk.range, lines)),
+                HunkAnswer::Undecided | HunkAnswer::Skip => {}
+            }
+        }
+        let mut out = vec![];
+        let mut i = 0;
+        while i < self.edits.len() {
+            if let Some((range, lines)) = edited.first().filter(|(range, _)| range.start <= i) {
+                // Context shared with the hunk before was already written.
+                let mut skip = i - range.start;
+                for (tag, line) in lines.iter() {
+                    let written = matches!((tag, revert), (b' ', _) | (b'+', false) | (b'-', true));
+                    if skip > 0 && *tag == b' ' {
+                        skip -= 1;
+                    } else if skip > 0 {
+                        Err(anyhow!("edited hunk does not apply"))?
+                    } else if written {
+                        out.extend_from_slice(line);
+                    }
+                }
+                i = i.max(range.end);
+                edited.remove(0);
+                continue;
+            }
+            match (self.edits[i], taken[i] != revert) {
+                (Edit::Equal(old, _), _) => out.extend_from_slice(self.old[old]),
+                (Edit::Delete(old), false) => out.extend_from_slice(self.old[old]),
+                (Edit::Insert(new), true) => out.extend_from_slice(self.new[new]),
+                _ => {}
+            }
+            i += 1;
+        }
+        Ok(out)
+    }
+}
+
+/// The `diff --git` header of the file, which comes before its hunks; a change of mode is
+/// shown like a hunk of its own, after it, and a deletion with all the lines it removes.
+fn write_header(out: &mut impl Write, change: &FileChange, mode: &PatchMode) -> anyhow::Result<()> {
+    let path = change.path;
+    let (a, b) = mode.prefixes;
+    let hash = |content: &[u8]| -> anyhow::Result<Sha> {
+        ObjectStorage::hash_object("blob", content, false, false)
+    };
+    let abbreviated = |sha: &Sha| ObjectStorage::sha_to_hex_string(sha)[..7].to_string();
+    writeln!(out, "diff --git {}{} {}{}", a, path, b, path)?;
+    let old_sha = abbreviated(&hash(change.old.content)?);
+    let Some(new) = &change.new else {
+        writeln!(out, "deleted file mode {:06o}", change.old.mode)?;
+        writeln!(out, "index {}..0000000", old_sha)?;
+        if !change.old.content.is_empty() && !diff::is_binary(change.old.content) {
+            writeln!(out, "--- {}{}\n+++ /dev/null", a, path)?;
+            let line_diff = LineDiff::new(change.old.content, b"");
+            let whole = Hunk {
+                range: 0..line_diff.edits.len(),
+                answer: HunkAnswer::Undecided,
+                split: false,
+            };
+            line_diff.write_hunk(out, &whole)?;
+        }
+        return Ok(());
+    };
+    if change.old.content == new.content {
+        return Ok(());
+    }
+    let new_sha = abbreviated(&hash(new.content)?);
+    write!(out, "index {}..{}", old_sha, new_sha)?;
+    if change.old.mode == new.mode {
+        write!(out, " {:06o}", new.mode)?;
+    }
+    writeln!(out, "\n--- {}{}\n+++ {}{}", a, path, b, path)?;
+    Ok(())
+}
+
+/// The mode and content of the working tree file `path`, as git would store it over an entry
+/// of mode `staged`, or `None` when there is no such file.
+pub fn work_tree_version(path: &str, staged: u32) -> anyhow::Result<Option<(u32, Vec<u8>)>> {
+    let file = ObjectStorage::work_tree().join(path);
+    let Ok(metadata) = fs::symlink_metadata(&file) else {
+        return Ok(None);
+    };
+    let content = index::work_tree_content(&file, &metadata)?;
+    Ok(Some((index::staged_file_mode(&metadata, staged), content)))
+}
+
+/// The lines of an edited hunk with their tags; comments and the `@@` header are dropped, an
+/// empty line is an empty context line, and `\ No newline at end of file` takes the newline
+/// off the line before it.
+fn parse_edited(text: &[u8]) -> Vec<TaggedLine> {
+    let mut lines: Vec<TaggedLine> = vec![];
+    for line in diff::split_lines(text) {
+        match line.first() {
+            Some(b'#') => {}
+            Some(b'@') if line.starts_with(b"@@") => {}
+            Some(b'\\') => {
+                if let Some((_, last)) = lines.last_mut()
+                    && last.ends_with(b"\n")
+                {
+                    last.pop();
+                }
+            }
+            Some(&tag @ (b' ' | b'-' | b'+')) => lines.push((tag, line[1..].to_vec())),
+            Some(b'\n') => lines.push((b' ', b"\n".to_vec())),
+            _ => {}
+        }
+    }
+    lines
+}
+
+/// Opens `path` in the editor git would use: `GIT_EDITOR`, `core.editor`, `VISUAL`, `EDITOR`
+/// or `vi`, run by the shell like git runs it.
+fn run_editor(path: &std::path::Path) -> anyhow::Result<()> {
+    let editor = match env::var("GIT_EDITOR") {
+        Ok(editor) => editor,
+        Err(_) => match Config::get()?.value("core", "editor") {
+            Some(editor) => editor,
+            None => env::var("VISUAL")
+                .or_else(|_| env::var("EDITOR"))
+                .unwrap_or_else(|_| "vi".to_string()),
+        },
+    };
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg(format!("{} \"$@\"", editor))
+        .arg(&editor)
+        .arg(path);
+    let status = trace2::status(&mut command, "editor", None)
+        .map_err(|err| anyhow!("unable to start editor '{}': {}", editor, err))?;
+    if !status.success() {
+        Err(anyhow!("there was a problem with the editor '{}'", editor))?
+    }
+    Ok(())
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
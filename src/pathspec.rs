@@ -0,0 +1,347 @@
+use crate::object_storage::ObjectStorage;
+use anyhow::anyhow;
+use std::env;
+use std::path::{Component, Path};
+
+/// A single git pathspec, with its magic parsed and the path made relative to the top of the
+/// working tree.
+pub struct Pathspec {
+    pattern: String,
+    glob: bool,
+    literal: bool,
+    icase: bool,
+    exclude: bool,
+}
+
+/// The pathspecs given to a command. A path is selected when it matches at least one include
+/// spec (or there are none) and no exclude spec.
+pub struct PathspecSet {
+    specs: Vec<Pathspec>,
+}
+
+impl Pathspec {
+    /// Parses `spec`, resolving it against `prefix` (the current directory relative to the top
+    /// of the working tree) unless it carries the `top` magic.
+    pub fn parse(spec: &str, prefix: &str) -> anyhow::Result<Self> {
+        let mut top = false;
+        let mut glob = false;
+        let mut literal = false;
+        let mut icase = false;
+        let mut exclude = false;
+        let mut rest = spec;
+        if let Some(long_form) = spec.strip_prefix(":(") {
+            let (magic, path) = long_form.split_once(')').ok_or(anyhow!(
+                "missing ')' at the end of pathspec magic in '{}'",
+                spec
+            ))?;
+            for word in magic.split(',').map(str::trim).filter(|w| !w.is_empty()) {
+                match word {
+                    "top" => top = true,
+                    "glob" => glob = true,
+                    "literal" => literal = true,
+                    "icase" => icase = true,
+                    "exclude" => exclude = true,
+                    _ => Err(anyhow!("invalid pathspec magic '{}' in '{}'", word, spec))?,
+                }
+            }
+            rest = path;
+        } else if let Some(short_form) = spec.strip_prefix(':') {
+            rest = "";
+            for (i, c) in short_form.char_indices() {
+                match c {
+                    '/' => top = true,
+                    '!' | '^' => exclude = true,
+                    ':' => {
+                        rest = &short_form[i + 1..];
+                        break;
+                    }
+                    _ => {
+                        rest = &short_form[i..];
+                        break;
+                    }
+                }
+            }
+        }
+        if glob && literal {
+            Err(anyhow!(
+                "'literal' and 'glob' pathspec magic are incompatible"
+            ))?
+        }
+        let joined = if top || prefix.is_empty() {
+            rest.to_string()
+        } else {
+            format!("{}/{}", prefix, rest)
+        };
+        let mut pattern =
+            normalize(&joined).ok_or(anyhow!("'{}' is outside the working tree", spec))?;
+        if rest.ends_with('/') && !pattern.is_empty() {
+            pattern.push('/');
+        }
+        if icase {
+            pattern = pattern.to_lowercase();
+        }
+        Ok(Self {
+            pattern,
+            glob,
+            literal,
+            icase,
+            exclude,
+        })
+    }
+
+    /// Whether `path` (relative to the top of the working tree) is selected by this spec. A spec
+    /// naming a directory matches everything below it; a trailing slash restricts it to
+    /// directories.
+    pub fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.pattern.is_empty() {
+            return true;
+        }
+        let lowered;
+        let path = if self.icase {
+            lowered = path.to_lowercase();
+            lowered.as_str()
+        } else {
+            path
+        };
+        let (pattern, dir_only) = match self.pattern.strip_suffix('/') {
+            Some(pattern) => (pattern, true),
+            None => (self.pattern.as_str(), false),
+        };
+        if path == pattern {
+            return is_dir || !dir_only;
+        }
+        if path
+            .strip_prefix(pattern)
+            .is_some_and(|rest| rest.starts_with('/'))
+        {
+            return true;
+        }
+        !self.literal && has_wildcards(pattern) && wildmatch(pattern, path, self.glob)
+    }
+
+    /// Whether something below the directory `dir` could match, so tree walks can skip
+    /// subtrees that cannot contain a match.
+    pub fn could_match_within(&self, dir: &str) -> bool {
+        if self.matches(dir, true) {
+            return true;
+        }
+        let pattern = self.pattern.trim_end_matches('/');
+        let fixed = if self.literal {
+            pattern
+        } else {
+            &pattern[..pattern.find(['*', '?', '[', '\\']).unwrap_or(pattern.len())]
+        };
+        let dir = format!("{}/", dir);
+        let (fixed, dir) = if self.icase {
+            (fixed.to_lowercase(), dir.to_lowercase())
+        } else {
+            (fixed.to_string(), dir)
+        };
+        fixed.starts_with(&dir) || (fixed.len() < pattern.len() && dir.starts_with(&fixed))
+    }
+}
+
+impl PathspecSet {
+    /// Parses command-line pathspecs relative to the current directory within the worktree.
+    pub fn from_args(args: &[String]) -> anyhow::Result<Self> {
+        let prefix = current_prefix()?;
+        let specs = args
+            .iter()
+            .map(|arg| Pathspec::parse(arg, &prefix))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Self { specs })
+    }
+
+    pub fn matches(&self, path: &str, is_dir: bool) -> bool {
+        let mut includes = self.specs.iter().filter(|spec| !spec.exclude).peekable();
+        let included = includes.peek().is_none() || includes.any(|spec| spec.matches(path, is_dir));
+        included
+            && !self
+                .specs
+                .iter()
+                .filter(|spec| spec.exclude)
+                .any(|spec| spec.matches(path, is_dir))
+    }
+
+    pub fn could_match_within(&self, dir: &str) -> bool {
+        let mut includes = self.specs.iter().filter(|spec| !spec.exclude).peekable();
+        includes.peek().is_none() || includes.any(|spec| spec.could_match_within(dir))
+    }
+
+    /// The path named by a single spec without wildcards, for messages about that path.
+    pub fn literal_path(&self) -> Option<&str> {
+        match self.specs.as_slice() {
+            [spec] if !spec.exclude && (spec.literal || !has_wildcards(&spec.pattern)) => {
+                Some(spec.pattern.trim_end_matches('/'))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The current directory relative to the top of the working tree, `/`-separated.
+pub(crate) fn current_prefix() -> anyhow::Result<String> {
+    let work_tree = ObjectStorage::work_tree().canonicalize()?;
+    let cwd = env::current_dir()?.canonicalize()?;
+    Ok(cwd
+        .strip_prefix(&work_tree)
+        .map(|relative| {
+            relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().to_string())
+                .collect::<Vec<_>>()
+                .join("/")
+        })
+        .unwrap_or_default())
+}
+
+/// Collapses `.` and `..` components; `None` if the path climbs above the top.
+pub(crate) fn normalize(path: &str) -> Option<String> {
+    let mut parts: Vec<String> = vec![];
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_string_lossy().to_string()),
+            Component::ParentDir => {
+                parts.pop()?;
+            }
+            Component::CurDir | Component::RootDir | Component::Prefix(_) => (),
+        }
+    }
+    Some(parts.join("/"))
+}
+
+fn has_wildcards(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '[', '\\'])
+}
+
+/// Matches `text` against a shell wildcard `pattern`. With `pathname` set (the `glob` magic), `*`
+/// and `?` do not match `/` and `**` between slashes matches any number of directories;
+/// otherwise wildcards match across slashes like git's default pathspec matching.
+pub fn wildmatch(pattern: &str, text: &str, pathname: bool) -> bool {
+    wildmatch_bytes(pattern.as_bytes(), text.as_bytes(), pathname)
+}
+
+fn wildmatch_bytes(pattern: &[u8], text: &[u8], pathname: bool) -> bool {
+    let mut p = 0;
+    let mut t = 0;
+    while p < pattern.len() {
+        match pattern[p] {
+            b'*' => {
+                let double = pathname && pattern.get(p + 1) == Some(&b'*');
+                if double {
+                    let at_start = p == 0 || pattern[p - 1] == b'/';
+                    let mut end = p + 2;
+                    if at_start && pattern.get(end) == Some(&b'/') {
+                        // "**/" matches zero or more leading directories.
+                        end += 1;
+                        let rest = &pattern[end..];
+                        let mut i = t;
+                        loop {
+                            if wildmatch_bytes(rest, &text[i..], pathname) {
+                                return true;
+                            }
+                            match text[i..].iter().position(|&b| b == b'/') {
+                                Some(slash) => i += slash + 1,
+                                None => return false,
+                            }
+                        }
+                    }
+                    // A trailing or embedded "**" matches anything, including slashes.
+                    return (t..=text.len())
+                        .any(|i| wildmatch_bytes(&pattern[end..], &text[i..], pathname));
+                }
+                let rest = &pattern[p + 1..];
+                let mut i = t;
+                loop {
+                    if wildmatch_bytes(rest, &text[i..], pathname) {
+                        return true;
+                    }
+                    if i == text.len() || (pathname && text[i] == b'/') {
+                        return false;
+                    }
+                    i += 1;
+                }
+            }
+            b'?' => {
+                if t == text.len() || (pathname && text[t] == b'/') {
+                    return false;
+                }
+                p += 1;
+                t += 1;
+            }
+            b'[' => {
+                let Some(&c) = text.get(t) else {
+                    return false;
+                };
+                match match_class(&pattern[p + 1..], c) {
+                    Some((matched, consumed)) => {
+                        if !matched || (pathname && c == b'/') {
+                            return false;
+                        }
+                        p += 1 + consumed;
+                        t += 1;
+                    }
+                    // An unterminated class is matched literally.
+                    None => {
+                        if c != b'[' {
+                            return false;
+                        }
+                        p += 1;
+                        t += 1;
+                    }
+                }
+            }
+            b'\\' if p + 1 < pattern.len() => {
+                if text.get(t) != Some(&pattern[p + 1]) {
+                    return false;
+                }
+                p += 2;
+                t += 1;
+            }
+            c => {
+                if text.get(t) != Some(&c) {
+                    return false;
+                }
+                p += 1;
+                t += 1;
+            }
+        }
+    }
+    t == text.len()
+}
+
+/// Matches `c` against the bracket expression starting after `[`; returns whether it matched
+/// and how many pattern bytes the expression used (including the closing `]`).
+fn match_class(class: &[u8], c: u8) -> Option<(bool, usize)> {
+    let mut i = 0;
+    let negated = matches!(class.first(), Some(b'!' | b'^'));
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    while i < class.len() {
+        let mut start = class[i];
+        if start == b']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        first = false;
+        if start == b'\\' && i + 1 < class.len() {
+            i += 1;
+            start = class[i];
+        }
+        if class.get(i + 1) == Some(&b'-') && class.get(i + 2).is_some_and(|&end| end != b']') {
+            let end = class[i + 2];
+            if start <= c && c <= end {
+                matched = true;
+            }
+            i += 3;
+        } else {
+            if start == c {
+                matched = true;
+            }
+            i += 1;
+        }
+    }
+    None
+}
@@ -0,0 +1,157 @@
+//! git's pretty formats for commits, as `$Format:...$` in files with the `export-subst`
+//! attribute uses them: `%H` for the commit id, `%an` for the author name, `%s` for the
+//! subject and so on. Placeholders it does not know are left as they are, like git does.
+
+use crate::object_storage::{Commit, ObjectStorage, Sha, Signature};
+use crate::refs;
+use crate::revision;
+
+/// Expands the placeholders of `format` for the commit `sha`.
+pub fn format_commit(format: &str, sha: &Sha, commit: &Commit) -> anyhow::Result<String> {
+    let mut out = String::new();
+    let mut rest = format;
+    while let Some(start) = rest.find('%') {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        match expand(after, sha, commit)? {
+            Some((value, used)) => {
+                out.push_str(&value);
+                rest = &after[used..];
+            }
+            None => {
+                out.push('%');
+                rest = after;
+            }
+        }
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// The value of the placeholder `after` starts with, and how many bytes it takes up.
+fn expand(after: &str, sha: &Sha, commit: &Commit) -> anyhow::Result<Option<(String, usize)>> {
+    let hex = |sha: &Sha| ObjectStorage::sha_to_hex_string(sha);
+    let short = |sha: &Sha| revision::abbreviate(sha, 7);
+    let value = match after.get(..1) {
+        Some("%") => "%".to_string(),
+        Some("n") => "\n".to_string(),
+        Some("H") => hex(sha),
+        Some("h") => short(sha)?,
+        Some("T") => hex(&commit.tree),
+        Some("t") => short(&commit.tree)?,
+        Some("P") => commit.parents.iter().map(hex).collect::<Vec<_>>().join(" "),
+        Some("p") => commit
+            .parents
+            .iter()
+            .map(short)
+            .collect::<anyhow::Result<Vec<_>>>()?
+            .join(" "),
+        Some("s") => subject(&commit.message),
+        Some("b") => body(&commit.message),
+        Some("B") => commit.message.clone(),
+        Some("D") => decorations(sha)?.join(", "),
+        Some("d") => {
+            let decorations = decorations(sha)?;
+            if decorations.is_empty() {
+                String::new()
+            } else {
+                format!(" ({})", decorations.join(", "))
+            }
+        }
+        Some("x") => {
+            let Some(byte) = after
+                .get(1..3)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            else {
+                return Ok(None);
+            };
+            return Ok(Some(((byte as char).to_string(), 3)));
+        }
+        Some("a") => match signature_field(&commit.author, after.get(1..2)) {
+            Some(value) => return Ok(Some((value, 2))),
+            None => return Ok(None),
+        },
+        Some("c") => match signature_field(&commit.committer, after.get(1..2)) {
+            Some(value) => return Ok(Some((value, 2))),
+            None => return Ok(None),
+        },
+        _ => return Ok(None),
+    };
+    Ok(Some((value, 1)))
+}
+
+/// `%an`, `%ae`, `%ad` and the other author and committer placeholders, by their second
+/// letter.
+fn signature_field(signature: &Signature, field: Option<&str>) -> Option<String> {
+    let value = match field? {
+        "n" => signature.name.clone(),
+        "e" => signature.email.clone(),
+        "d" => signature.format_date(),
+        "D" => signature.format_rfc2822_date(),
+        "t" => signature.time.to_string(),
+        "i" => signature.format_iso_date(),
+        "I" => signature.format_iso_strict_date(),
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// The first paragraph of the message, on one line.
+fn subject(message: &str) -> String {
+    message
+        .lines()
+        .skip_while(|line| line.trim().is_empty())
+        .take_while(|line| !line.trim().is_empty())
+        .map(str::trim_end)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The message after its first paragraph.
+fn body(message: &str) -> String {
+    message
+        .lines()
+        .skip_while(|line| line.trim().is_empty())
+        .skip_while(|line| !line.trim().is_empty())
+        .skip_while(|line| line.trim().is_empty())
+        .map(|line| format!("{}\n", line))
+        .collect()
+}
+
+/// The refs that point to the commit, as `git log --decorate` shows them: `HEAD -> main`
+/// for the branch checked out, `tag: v1.0` for tags and short names for other branches.
+/// Later names come first, as in git.
+fn decorations(sha: &Sha) -> anyhow::Result<Vec<String>> {
+    let (head_ref, head) = refs::resolve_ref("HEAD")?;
+    let current = (head_ref != "HEAD").then_some(head_ref);
+    let mut names: Vec<String> = vec![];
+    for (name, target) in refs::list_refs()?.iter().rev() {
+        if Some(name) == current.as_ref()
+            || ObjectStorage::peel_tag(target).ok().as_ref() != Some(sha)
+        {
+            continue;
+        }
+        let shown = if let Some(tag) = name.strip_prefix("refs/tags/") {
+            format!("tag: {}", tag)
+        } else if let Some(short) = ["refs/heads/", "refs/remotes/"]
+            .iter()
+            .find_map(|prefix| name.strip_prefix(prefix))
+        {
+            short.to_string()
+        } else {
+            name.clone()
+        };
+        names.push(shown);
+    }
+    if head.as_ref() == Some(sha) {
+        let head = match current
+            .as_deref()
+            .map(|name| name.trim_start_matches("refs/heads/"))
+        {
+            Some(branch) => format!("HEAD -> {}", branch),
+            None => "HEAD".to_string(),
+        };
+        names.insert(0, head);
+    }
+    Ok(names)
+}
@@ -0,0 +1,284 @@
+//! The `codecrafters-git` command line: the options before the command name, aliases from
+//! config and autocorrection of mistyped command names, around [`commands::run`].
+
+use crate::background;
+use crate::cli;
+use crate::commands;
+use crate::config::Config;
+use crate::object_storage::ObjectStorage;
+use crate::repository::{CommandExit, Repository};
+use crate::trace2;
+use anyhow::anyhow;
+use std::io::{self, IsTerminal};
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+/// Exit code git uses for fatal errors.
+const FATAL_EXIT_CODE: i32 = 128;
+
+/// Runs the command line `args`, program name first, as the `codecrafters-git` command does
+/// and returns the code it exits with. Errors are printed to stderr as `fatal: ...`. The
+/// process's working directory and environment are left alone, so `-C`, `--git-dir` and
+/// `--work-tree`, which the command applies itself, are not accepted. Background jobs such as
+/// automatic gc run the current executable with `gc --auto`, so this is for the
+/// `codecrafters-git` command; programs embedding the library use
+/// [`Repository::run_command`], which runs them in the foreground.
+pub fn run_program(args: Vec<String>) -> i32 {
+    trace2::start(&args);
+    background::allow_detach();
+    let code = match run(args) {
+        Ok(()) => 0,
+        Err(err) => match err.downcast_ref::<CommandExit>() {
+            Some(exit) => exit.code,
+            None => {
+                let message = format!("{:#}", err);
+                eprintln!("fatal: {}", message);
+                trace2::error(&message);
+                FATAL_EXIT_CODE
+            }
+        },
+    };
+    trace2::report_exit(code);
+    code
+}
+
+fn run(args: Vec<String>) -> anyhow::Result<()> {
+    let (args, dry_run) = parse_global_options(args)?;
+    // `init` and `clone` set up a new repository rather than using the enclosing one. For
+    // other names the repository is found first, as aliases may be defined in its config.
+    let repository = match args.get(1) {
+        Some(name) if cli::creates_repository(name) => None,
+        _ => Repository::discover()?,
+    };
+    match &repository {
+        Some(repository) => {
+            trace2::def_repo(repository.work_tree());
+            repository.enter(|| run_in(args, dry_run, Some(repository)))
+        }
+        None => run_in(args, dry_run, None),
+    }
+}
+
+/// Runs the command line with global options taken out, in `repository` if there is one.
+fn run_in(args: Vec<String>, dry_run: bool, repository: Option<&Repository>) -> anyhow::Result<()> {
+    let mut args = expand_alias(args)?;
+    if args.len() < 2 {
+        eprint!("{}", cli::general_usage());
+        Err(CommandExit { code: 1 })?
+    }
+    if !cli::is_builtin(&args[1]) {
+        args[1] = autocorrect(&args[1])?;
+    }
+    trace2::cmd_name(&args[1]);
+    if dry_run {
+        if !cli::supports_dry_run(&args[1]) {
+            Err(anyhow!("'{}' does not support --dry-run", args[1]))?
+        }
+        if let Some(repository) = repository {
+            repository.set_dry_run(true);
+        }
+    }
+    // Elsewhere among the arguments `-h` may be an option's value; the parser tells.
+    if let [_, _, arg] = args.as_slice()
+        && (arg == "-h" || arg == "--help")
+    {
+        return commands::run("help", &args[1..2]);
+    }
+    if cli::needs_repository(&args[1]) {
+        if repository.is_none() {
+            Err(Repository::not_found())?
+        }
+        ObjectStorage::ensure_safe_directory()?;
+    }
+    commands::run(&args[1], &args[2..])
+}
+
+/// What `help.autocorrect` says to do about a mistyped command.
+enum Autocorrect {
+    /// Only suggest similar commands (`0`, the default, or false).
+    Suggest,
+    /// Say nothing but that the command does not exist (`never`).
+    Never,
+    /// Run the one similar command after this many tenths of a second (a positive number),
+    /// or right away (`immediate`, a negative number or true).
+    Run(u64),
+    /// Ask whether to run the one similar command (`prompt`).
+    Prompt,
+}
+
+impl Autocorrect {
+    fn configured() -> anyhow::Result<Self> {
+        let config = Config::get()?;
+        let Some(value) = config.value("help", "autocorrect") else {
+            return Ok(Autocorrect::Suggest);
+        };
+        Ok(match value.as_str() {
+            "never" => Autocorrect::Never,
+            "immediate" => Autocorrect::Run(0),
+            "prompt" => Autocorrect::Prompt,
+            _ => match value.parse::<i64>() {
+                Ok(0) => Autocorrect::Suggest,
+                Ok(delay) => Autocorrect::Run(delay.max(0) as u64),
+                Err(_) => match config.bool("help", "autocorrect") {
+                    Some(true) => Autocorrect::Run(0),
+                    _ => Autocorrect::Suggest,
+                },
+            },
+        })
+    }
+}
+
+/// Reports that `name` is not a command and suggests the similar ones, or, as
+/// `help.autocorrect` asks, returns the only similar command to run instead.
+fn autocorrect(name: &str) -> anyhow::Result<String> {
+    let program = cli::program_name();
+    let autocorrect = Autocorrect::configured()?;
+    let similar = match autocorrect {
+        Autocorrect::Never => vec![],
+        _ => cli::similar_commands(name),
+    };
+    if let [command] = similar.as_slice() {
+        let warn = || {
+            eprintln!(
+                "WARNING: You called a {} command named '{}', which does not exist.",
+                program, name
+            )
+        };
+        match autocorrect {
+            Autocorrect::Run(0) => {
+                warn();
+                eprintln!(
+                    "Continuing under the assumption that you meant '{}'.",
+                    command
+                );
+                return Ok(command.to_string());
+            }
+            Autocorrect::Run(delay) => {
+                warn();
+                eprintln!(
+                    "Continuing in {:.1} seconds, assuming that you meant '{}'.",
+                    delay as f64 / 10.0,
+                    command
+                );
+                thread::sleep(Duration::from_millis(delay * 100));
+                return Ok(command.to_string());
+            }
+            Autocorrect::Prompt if io::stdin().is_terminal() && io::stderr().is_terminal() => {
+                warn();
+                eprint!("Run '{}' instead [y/N]? ", command);
+                let mut answer = String::new();
+                io::stdin().read_line(&mut answer)?;
+                if matches!(answer.trim(), "y" | "Y" | "yes") {
+                    return Ok(command.to_string());
+                }
+                Err(CommandExit { code: 1 })?
+            }
+            _ => (),
+        }
+    }
+    eprintln!(
+        "{}: '{}' is not a command. See '{} help'.",
+        program, name, program
+    );
+    if !similar.is_empty() {
+        match similar.len() {
+            1 => eprintln!("\nThe most similar command is"),
+            _ => eprintln!("\nThe most similar commands are"),
+        }
+        for command in &similar {
+            eprintln!("\t{}", command);
+        }
+    }
+    Err(CommandExit { code: 1 })?
+}
+
+/// Consumes `--dry-run` before the subcommand and tells whether it was given. The options
+/// that change the working directory or the repository, `-C`, `--git-dir` and `--work-tree`,
+/// change the process's state, so the `codecrafters-git` command applies them before calling
+/// [`run_program`]; here they are refused.
+fn parse_global_options(args: Vec<String>) -> anyhow::Result<(Vec<String>, bool)> {
+    let mut remaining = args.into_iter();
+    let mut result: Vec<String> = remaining.next().into_iter().collect();
+    let mut dry_run = false;
+    for arg in remaining.by_ref() {
+        if arg == "--dry-run" {
+            dry_run = true;
+            continue;
+        }
+        let option = arg
+            .split_once('=')
+            .map_or(arg.as_str(), |(option, _)| option);
+        if matches!(option, "-C" | "--git-dir" | "--work-tree") {
+            Err(anyhow!(
+                "option {} is only supported by the command line; open the repository with Repository::open instead",
+                option
+            ))?
+        }
+        result.push(arg);
+        break;
+    }
+    result.extend(remaining);
+    Ok((result, dry_run))
+}
+
+/// Replaces a non-builtin subcommand with its `alias.<name>` expansion from config.
+/// Aliases starting with `!` are run through the shell with the remaining arguments.
+fn expand_alias(mut args: Vec<String>) -> anyhow::Result<Vec<String>> {
+    let mut expanded_names: Vec<String> = vec![];
+    while args.len() > 1 && !cli::is_builtin(&args[1]) {
+        let Some(value) = Config::get()?.value("alias", &args[1]) else {
+            break;
+        };
+        if expanded_names.contains(&args[1]) {
+            Err(anyhow!(
+                "alias loop detected: expansion of '{}' does not terminate",
+                args[1]
+            ))?
+        }
+        expanded_names.push(args[1].clone());
+        if let Some(command) = value.strip_prefix('!') {
+            trace2::alias(&args[1], std::slice::from_ref(&value));
+            let mut shell = process::Command::new("sh");
+            shell
+                .arg("-c")
+                .arg(format!("{} \"$@\"", command))
+                .arg(command)
+                .args(&args[2..]);
+            let status = trace2::status(&mut shell, "shell_alias", None)?;
+            Err(CommandExit {
+                code: status.code().unwrap_or(1),
+            })?
+        }
+        let expansion = split_alias(&value)?;
+        trace2::alias(&args[1], &expansion);
+        let mut expanded = vec![args[0].clone()];
+        expanded.extend(expansion);
+        expanded.extend(args.drain(2..));
+        args = expanded;
+    }
+    Ok(args)
+}
+
+fn split_alias(value: &str) -> anyhow::Result<Vec<String>> {
+    let mut words: Vec<String> = vec![];
+    let mut word: Option<String> = None;
+    let mut quote: Option<char> = None;
+    for c in value.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.get_or_insert_default().push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert_default();
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_default().push(c),
+        }
+    }
+    if quote.is_some() {
+        Err(anyhow!("unclosed quote in alias '{}'", value))?
+    }
+    words.extend(word);
+    Ok(words)
+}
@@ -0,0 +1,210 @@
+//! Callbacks for programs built on the library, such as GUIs, that want to show what a long
+//! operation is doing without parsing stderr.
+//!
+//! A [`ProgressReporter`] hears how far each [`Phase`] of fetching, packing and checking out
+//! has got, and an [`EventSink`] hears about ref updates and merge conflicts. They are
+//! installed on a repository, with [`Repository::set_progress_reporter`] and
+//! [`Repository::set_event_sink`], and hear about what is done to it. Without a reporter, progress is printed to stderr as the
+//! `codecrafters-git` command shows it.
+//!
+//! ```
+//! use codecrafters_git::object_storage::Oid;
+//! use codecrafters_git::{EventSink, Repository};
+//! use std::sync::{Arc, Mutex};
+//!
+//! #[derive(Clone, Default)]
+//! struct RefLog(Arc<Mutex<Vec<String>>>);
+//!
+//! impl EventSink for RefLog {
+//!     fn ref_updated(&self, name: &str, _old: Option<Oid>, new: Option<Oid>) {
+//!         let new = new.map_or("deleted".to_string(), |oid| oid.to_string());
+//!         self.0.lock().unwrap().push(format!("{} {}", name, new));
+//!     }
+//! }
+//!
+//! # let dir = std::env::temp_dir().join(format!("events-{}", std::process::id()));
+//! let repo = Repository::init(&dir)?;
+//! let log = RefLog::default();
+//! repo.set_event_sink(log.clone());
+//! let blob = repo.write_blob(b"hello\n")?;
+//! repo.run_command("update-ref", &["refs/tags/hello".to_string(), blob.to_string()])?;
+//! assert_eq!(
+//!     *log.0.lock().unwrap(),
+//!     ["refs/tags/hello ce013625030ba8dba906f756967f9e9ca394464a"]
+//! );
+//! # std::fs::remove_dir_all(&dir)?;
+//! # anyhow::Ok(())
+//! ```
+//!
+//! [`Repository::set_progress_reporter`]: crate::Repository::set_progress_reporter
+//! [`Repository::set_event_sink`]: crate::Repository::set_event_sink
+
+use crate::context;
+use crate::object_storage::Oid;
+use std::io::{self, IsTerminal, Write};
+use std::sync::{Arc, Mutex};
+
+/// A step of a long operation, named like git's progress lines.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// Downloading a pack from a remote; only bytes are counted.
+    ReceivingPack,
+    /// Reading the objects of a received or built pack to index it.
+    IndexingObjects,
+    /// Computing the id of each object in a pack being indexed, which means applying the
+    /// deltas of deltified ones.
+    ResolvingDeltas,
+    /// Deltifying and compressing objects into a new pack.
+    WritingObjects,
+    /// Writing files into the working tree.
+    UpdatingFiles,
+}
+
+impl Phase {
+    /// The title git shows for the phase, e.g. `Resolving deltas`.
+    pub fn title(self) -> &'static str {
+        match self {
+            Phase::ReceivingPack => "Receiving pack",
+            Phase::IndexingObjects => "Indexing objects",
+            Phase::ResolvingDeltas => "Resolving deltas",
+            Phase::WritingObjects => "Writing objects",
+            Phase::UpdatingFiles => "Updating files",
+        }
+    }
+}
+
+/// How far a phase has got.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progress {
+    pub phase: Phase,
+    /// Objects or files done so far; 0 for [`Phase::ReceivingPack`].
+    pub done: u64,
+    /// How many there are in all, when that is known.
+    pub total: Option<u64>,
+    /// Bytes received or written so far, for the phases that count them.
+    pub bytes: Option<u64>,
+}
+
+/// Receives progress; every method does nothing unless it is implemented.
+pub trait ProgressReporter: Send + Sync {
+    /// Called whenever a phase gets further, possibly once per object, so implementations
+    /// that redraw should throttle themselves.
+    fn progress(&self, _progress: &Progress) {}
+
+    /// Called once a phase is complete.
+    fn finish(&self, _phase: Phase) {}
+
+    /// A line of progress or other text the remote sent, with its `\n` or `\r`.
+    fn remote_message(&self, _message: &str) {}
+}
+
+/// Receives things that happened to the repository; every method does nothing unless it is
+/// implemented.
+pub trait EventSink: Send + Sync {
+    /// A ref was created (`old` is `None`), moved, or deleted (`new` is `None`).
+    fn ref_updated(&self, _name: &str, _old: Option<Oid>, _new: Option<Oid>) {}
+
+    /// A file merge left `conflicts` conflicting hunks in `path`.
+    fn conflict(&self, _path: &str, _conflicts: usize) {}
+}
+
+static STDERR_REPORTER: StderrReporter = StderrReporter {
+    last_line: Mutex::new(None),
+};
+
+pub(crate) fn set_reporter(reporter: Arc<dyn ProgressReporter>) {
+    *context::current().reporter.write().unwrap() = Some(reporter);
+}
+
+pub(crate) fn set_event_sink(sink: Arc<dyn EventSink>) {
+    *context::current().event_sink.write().unwrap() = Some(sink);
+}
+
+/// Calls `report` with the installed reporter, or with the one printing to stderr.
+pub(crate) fn report(report: impl FnOnce(&dyn ProgressReporter)) {
+    let reporter = context::current().reporter.read().unwrap().clone();
+    match reporter.as_deref() {
+        Some(reporter) => report(reporter),
+        None => report(&STDERR_REPORTER),
+    }
+}
+
+/// Calls `event` with the installed event sink, if there is one.
+pub(crate) fn notify(event: impl FnOnce(&dyn EventSink)) {
+    let sink = context::current().event_sink.read().unwrap().clone();
+    if let Some(sink) = sink.as_deref() {
+        event(sink);
+    }
+}
+
+/// What the command line shows: `Updating files` always, as before, the other phases only
+/// when stderr is a terminal, as in git, and remote messages after `remote: `. A line is
+/// redrawn only when its text changes.
+struct StderrReporter {
+    /// The phase being shown and its last line.
+    last_line: Mutex<Option<(Phase, String)>>,
+}
+
+impl StderrReporter {
+    fn shows(phase: Phase) -> bool {
+        phase == Phase::UpdatingFiles || io::stderr().is_terminal()
+    }
+}
+
+impl ProgressReporter for StderrReporter {
+    fn progress(&self, progress: &Progress) {
+        if !Self::shows(progress.phase) {
+            return;
+        }
+        let mut line = format!("{}: ", progress.phase.title());
+        match progress.total {
+            Some(total) => {
+                let percent = (progress.done * 100).checked_div(total).unwrap_or(100);
+                line.push_str(&format!("{:3}% ({}/{})", percent, progress.done, total));
+            }
+            None if progress.bytes.is_none() => line.push_str(&progress.done.to_string()),
+            None => (),
+        }
+        if let Some(bytes) = progress.bytes {
+            if progress.total.is_some() {
+                line.push_str(", ");
+            }
+            line.push_str(&human_bytes(bytes));
+        }
+        let mut last_line = self.last_line.lock().unwrap();
+        if last_line.as_ref() != Some(&(progress.phase, line.clone())) {
+            eprint!("\r{}", line);
+            *last_line = Some((progress.phase, line));
+        }
+    }
+
+    fn finish(&self, phase: Phase) {
+        let mut last_line = self.last_line.lock().unwrap();
+        if last_line.as_ref().is_some_and(|(shown, _)| *shown == phase) {
+            eprintln!(", done.");
+            *last_line = None;
+        }
+    }
+
+    fn remote_message(&self, message: &str) {
+        let mut stderr = io::stderr();
+        // Progress goes to a terminal; a failed write has nowhere to be reported.
+        let _ = stderr.write_all(b"remote: ");
+        let _ = stderr.write_all(message.as_bytes());
+    }
+}
+
+/// A byte count the way git's progress shows it, e.g. `1.50 MiB`.
+fn human_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 3] = ["KiB", "MiB", "GiB"];
+    if bytes < 1024 {
+        return format!("{} bytes", bytes);
+    }
+    let mut unit = 0;
+    let mut scaled = bytes as f64 / 1024.0;
+    while scaled >= 1024.0 && unit < UNITS.len() - 1 {
+        scaled /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.2} {}", scaled, UNITS[unit])
+}
@@ -0,0 +1,84 @@
+use crate::config::Config;
+use crate::context;
+use crate::object_storage::pack;
+use crate::object_storage::{GitObject, ObjectStorage, Sha};
+use anyhow::anyhow;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// What walkers do when an object they reach is not in the repository, from
+/// `core.missingAction`.
+#[derive(Clone, Copy, PartialEq)]
+pub enum MissingAction {
+    /// Stop with an error.
+    Error,
+    /// Skip the object if a promisor remote promised it; the default.
+    AllowPromisor,
+    /// Skip any missing object.
+    AllowAny,
+}
+
+pub fn missing_action() -> anyhow::Result<MissingAction> {
+    match Config::get()?.value("core", "missingAction").as_deref() {
+        None | Some("allow-promisor") => Ok(MissingAction::AllowPromisor),
+        Some("error") => Ok(MissingAction::Error),
+        Some("allow-any") => Ok(MissingAction::AllowAny),
+        Some(other) => Err(anyhow!(
+            "invalid core.missingAction '{}': expected error, allow-promisor or allow-any",
+            other
+        )),
+    }
+}
+
+/// Whether a walker may pass over `sha`, because it is not in the repository and
+/// `core.missingAction` allows that. Objects that are there are never skipped.
+pub fn may_skip(sha: &Sha) -> anyhow::Result<bool> {
+    if ObjectStorage::contains(sha)? {
+        return Ok(false);
+    }
+    Ok(match missing_action()? {
+        MissingAction::Error => false,
+        MissingAction::AllowPromisor => is_promised(sha)?,
+        MissingAction::AllowAny => true,
+    })
+}
+
+/// Whether a promisor remote has `sha`: it is in a pack fetched from one, marked by a
+/// `.promisor` file next to the pack, or referred to by an object in such a pack. A partial
+/// clone leaves such objects out, to be fetched when they are needed.
+pub fn is_promised(sha: &Sha) -> anyhow::Result<bool> {
+    Ok(promised_objects()?.contains(sha))
+}
+
+fn promised_objects() -> anyhow::Result<Arc<HashSet<Sha>>> {
+    let context = context::current();
+    let mut cached = context.promised.lock().unwrap();
+    if let Some(promised) = cached.as_ref() {
+        return Ok(promised.clone());
+    }
+    let mut promised = HashSet::new();
+    for pack in pack::loaded_packs()?
+        .iter()
+        .filter(|pack| pack.is_promisor())
+    {
+        for sha in pack.shas() {
+            promised.insert(*sha);
+            let Some(offset) = pack.find_offset(sha) else {
+                continue;
+            };
+            let (object_type, content) = pack.read_at(offset)?;
+            match GitObject::from_parts(object_type, &content)? {
+                GitObject::Commit(commit) => {
+                    promised.insert(commit.tree);
+                    promised.extend(commit.parents);
+                }
+                GitObject::Tree(tree) => promised.extend(tree.entries.iter().map(|e| e.hash)),
+                GitObject::Tag(tag) => _ = promised.insert(tag.object),
+                GitObject::Blob(_) => {}
+            }
+        }
+    }
+    let promised = Arc::new(promised);
+    *cached = Some(promised.clone());
+    Ok(promised)
+}
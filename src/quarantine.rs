@@ -0,0 +1,96 @@
+//! Quarantined object directories, like git's `objects/incoming-XXXXXX`: a pack received from a
+//! remote is written into one and only moved into the object storage when its refs are about
+//! to be updated. Concurrent fetches from several remotes thus never read each other's packs
+//! halfway, and whatever a failed or interrupted fetch received is removed with its quarantine.
+
+use crate::compat_map;
+use crate::object_storage::{ObjectStorage, pack};
+use crate::temp_file;
+use anyhow::anyhow;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// How many names are tried before giving up on creating a quarantine.
+const MAX_ATTEMPTS: usize = 100;
+
+/// A directory next to the object storage's that is removed when this is dropped, unless its
+/// objects were moved into the storage with [`Quarantine::migrate`].
+pub struct Quarantine {
+    dir: PathBuf,
+}
+
+impl Quarantine {
+    pub fn new() -> anyhow::Result<Self> {
+        let objects = ObjectStorage::git_dir().join("objects");
+        let mut attempts = 0;
+        loop {
+            let dir = objects.join(format!("incoming-{}", temp_file::random_part()));
+            match fs::create_dir(&dir) {
+                Ok(()) => return Ok(Self { dir }),
+                Err(err) if err.kind() == ErrorKind::AlreadyExists && attempts < MAX_ATTEMPTS => {
+                    attempts += 1;
+                }
+                Err(err) => Err(anyhow!(
+                    "unable to create temporary object directory: {}",
+                    err
+                ))?,
+            }
+        }
+    }
+
+    /// Indexes the packfile `data` and writes it into the quarantine, returning the path of
+    /// the pack so files can be put next to it.
+    pub fn write_pack(&self, data: &[u8]) -> anyhow::Result<PathBuf> {
+        pack::write_pack_to(data, &self.dir.join("pack"))
+    }
+
+    /// Moves the packs into `objects/pack`, each index after its pack and the files next to
+    /// it, since the index is what makes a pack visible.
+    pub fn migrate(self) -> anyhow::Result<()> {
+        let source = self.dir.join("pack");
+        if !source.is_dir() {
+            return Ok(());
+        }
+        let destination = ObjectStorage::git_dir().join("objects").join("pack");
+        fs::create_dir_all(&destination)?;
+        let mut paths: Vec<PathBuf> = fs::read_dir(&source)?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<anyhow::Result<_>>()?;
+        paths.sort_by_key(|path| (is_index(path), path.clone()));
+        let mut packs = vec![];
+        for path in paths {
+            let Some(name) = path.file_name() else {
+                continue;
+            };
+            fs::rename(&path, destination.join(name)).map_err(|err| {
+                anyhow!(
+                    "unable to move {} into the object storage: {}",
+                    path.display(),
+                    err
+                )
+            })?;
+            if path
+                .extension()
+                .is_some_and(|extension| extension == "pack")
+            {
+                packs.push(destination.join(name));
+            }
+        }
+        pack::reset_cache();
+        for pack in packs {
+            compat_map::record_pack(&pack)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Quarantine {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn is_index(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "idx")
+}
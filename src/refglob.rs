@@ -0,0 +1,243 @@
+//! Ref patterns, in the forms git has for them. Glob patterns such as
+//! `refs/heads/feature/*` pick refs by name for `log --glob`, `--branches=<pattern>`, the
+//! `--glob` of the ref-listing commands and `--exclude`: they are matched like `fnmatch`
+//! without special slashes, so `*` matches across `/`, and a pattern without wildcards names
+//! the hierarchy under it. Refspec sides such as `refs/heads/*:refs/remotes/origin/*` have a
+//! single `*` standing for the same part of the name on both sides. The patterns `show-ref`
+//! takes name the last components of a ref, as `main` names `refs/heads/main`.
+//!
+//! ```
+//! use codecrafters_git::refglob;
+//!
+//! let pattern = refglob::full_pattern("feature", Some("refs/heads/"));
+//! assert_eq!(pattern, "refs/heads/feature/*");
+//! assert!(refglob::matches(&pattern, "refs/heads/feature/a/b"));
+//! assert!(!refglob::matches(&pattern, "refs/heads/featured"));
+//! assert!(refglob::matches(&refglob::full_pattern("tags/v1.?", None), "refs/tags/v1.2"));
+//!
+//! let matched = refglob::match_star("refs/heads/*", "refs/heads/main");
+//! assert_eq!(matched, Some("main"));
+//! assert_eq!(refglob::replace_star("refs/remotes/origin/*", "main"), "refs/remotes/origin/main");
+//! assert_eq!(refglob::match_star("refs/*/head", "refs/stable/head"), Some("stable"));
+//! assert_eq!(refglob::match_star("refs/heads/*", "refs/tags/v1"), None);
+//!
+//! assert!(refglob::matches_tail("origin/main", "refs/remotes/origin/main"));
+//! assert!(!refglob::matches_tail("main", "refs/heads/domain"));
+//! ```
+
+use crate::object_storage::Sha;
+use crate::pathspec::wildmatch;
+use crate::refs;
+
+/// Whether `pattern` has any of the characters that make it a glob.
+fn has_glob_specials(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '[', '\\'])
+}
+
+/// The full pattern for a glob given to `--glob` (`prefix` `None`), where `refs/` is implied,
+/// or to an option such as `--branches=` whose refs are under `prefix`. A pattern without
+/// wildcards gets `/*` so that `--glob=heads/feature` means every ref under it.
+pub fn full_pattern(pattern: &str, prefix: Option<&str>) -> String {
+    let mut full = match prefix {
+        Some(prefix) => prefix.to_string(),
+        None if !pattern.starts_with("refs/") => "refs/".to_string(),
+        None => String::new(),
+    };
+    full.push_str(pattern);
+    if !has_glob_specials(pattern) {
+        if !full.ends_with('/') {
+            full.push('/');
+        }
+        full.push('*');
+    }
+    full
+}
+
+/// Whether the ref `name` matches the glob `pattern`.
+pub fn matches(pattern: &str, name: &str) -> bool {
+    wildmatch(pattern, name, false)
+}
+
+/// The refs the glob `pattern` matches, as [`full_pattern`] makes it from a pattern and
+/// `prefix`. The names are without `prefix`, as `--exclude` patterns are matched against
+/// them.
+pub fn matching_refs(pattern: &str, prefix: Option<&str>) -> anyhow::Result<Vec<(String, Sha)>> {
+    let pattern = full_pattern(pattern, prefix);
+    Ok(refs::list_refs()?
+        .into_iter()
+        .filter(|(name, _)| matches(&pattern, name))
+        .map(|(name, sha)| {
+            let name = match prefix.and_then(|prefix| name.strip_prefix(prefix)) {
+                Some(rest) => rest.to_string(),
+                None => name,
+            };
+            (name, sha)
+        })
+        .collect())
+}
+
+/// The refs a ref-listing command such as `show-ref` shows: those under `prefix` that one of
+/// the `--glob` patterns picks as [`matching_refs`] does, or all of them without any, less
+/// those `excludes` leaves out. The names are without `prefix`, and sorted.
+pub fn listed_refs(
+    prefix: Option<&str>,
+    globs: &[&str],
+    excludes: &Excludes,
+) -> anyhow::Result<Vec<(String, Sha)>> {
+    let globs = if globs.is_empty() { &["*"][..] } else { globs };
+    let mut listed = vec![];
+    for pattern in globs {
+        listed.extend(
+            matching_refs(pattern, prefix)?
+                .into_iter()
+                .filter(|(name, _)| !excludes.excludes(name)),
+        );
+    }
+    listed.sort();
+    listed.dedup();
+    Ok(listed)
+}
+
+/// The `--exclude` patterns in force, which leave out refs from the next option that
+/// gathers them.
+#[derive(Default)]
+pub struct Excludes(Vec<String>);
+
+impl Excludes {
+    /// Excludes for all of a command's `--exclude` patterns at once, for commands where they
+    /// apply to every ref listed.
+    pub fn from_patterns(patterns: &[&str]) -> Self {
+        Self(patterns.iter().map(|pattern| pattern.to_string()).collect())
+    }
+
+    pub fn add(&mut self, pattern: &str) {
+        self.0.push(pattern.to_string());
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+
+    /// Whether the ref `name`, as the option gathering it names it, is left out.
+    pub fn excludes(&self, name: &str) -> bool {
+        self.0.iter().any(|pattern| matches(pattern, name))
+    }
+}
+
+/// The part of `name` the `*` of the refspec side `pattern` stands for, if it matches: `main`
+/// for `refs/heads/main` and `refs/heads/*`. A pattern without `*` matches only itself, with
+/// nothing for the star.
+pub fn match_star<'a>(pattern: &str, name: &'a str) -> Option<&'a str> {
+    let Some((before, after)) = pattern.split_once('*') else {
+        return (pattern == name).then_some("");
+    };
+    name.strip_prefix(before)?.strip_suffix(after)
+}
+
+/// The refspec side `pattern` with its `*`, if it has one, replaced by `matched`.
+pub fn replace_star(pattern: &str, matched: &str) -> String {
+    pattern.replacen('*', matched, 1)
+}
+
+/// Whether the `show-ref` pattern `pattern` is the whole of `name` or its last path
+/// components, as `main` matches `refs/heads/main` and `refs/remotes/origin/main` but not
+/// `refs/heads/domain`.
+pub fn matches_tail(pattern: &str, name: &str) -> bool {
+    match name.strip_suffix(pattern) {
+        Some("") => true,
+        Some(rest) => rest.ends_with('/'),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_pattern_implies_refs_and_the_hierarchy() {
+        assert_eq!(full_pattern("heads/*", None), "refs/heads/*");
+        assert_eq!(full_pattern("refs/tags/v[0-9]*", None), "refs/tags/v[0-9]*");
+        assert_eq!(full_pattern("refs/heads", None), "refs/heads/*");
+        assert_eq!(full_pattern("refs/heads/", None), "refs/heads/*");
+        assert_eq!(full_pattern("", Some("refs/heads/")), "refs/heads/*");
+        assert_eq!(full_pattern("*", Some("refs/remotes/")), "refs/remotes/*");
+        assert_eq!(
+            full_pattern("origin", Some("refs/remotes/")),
+            "refs/remotes/origin/*"
+        );
+        // An escaped character makes a glob, so nothing is added to it.
+        assert_eq!(full_pattern("v\\*", Some("refs/tags/")), "refs/tags/v\\*");
+    }
+
+    #[test]
+    fn glob_stars_match_across_slashes() {
+        assert!(matches("refs/heads/*", "refs/heads/feature/a"));
+        assert!(matches("refs/*/main", "refs/remotes/origin/main"));
+        assert!(matches("refs/tags/v1.[0-2]", "refs/tags/v1.2"));
+        assert!(!matches("refs/tags/v1.[0-2]", "refs/tags/v1.3"));
+        assert!(!matches("refs/heads/*", "refs/heads"));
+    }
+
+    #[test]
+    fn match_star_edge_cases() {
+        assert_eq!(match_star("refs/heads/*", "refs/heads/"), Some(""));
+        assert_eq!(match_star("refs/heads/*", "refs/heads/a/b"), Some("a/b"));
+        assert_eq!(match_star("*", "refs/tags/v1"), Some("refs/tags/v1"));
+        assert_eq!(match_star("refs/heads/main", "refs/heads/main"), Some(""));
+        assert_eq!(match_star("refs/heads/main", "refs/heads/main2"), None);
+        // The prefix and suffix must not overlap in a name shorter than both.
+        assert_eq!(match_star("refs/a*a/x", "refs/a/x"), None);
+        // Only the first `*` is the star; a second one is literal.
+        assert_eq!(match_star("refs/*/x*", "refs/a/x*"), Some("a"));
+        assert_eq!(match_star("refs/*/x*", "refs/a/xy"), None);
+    }
+
+    #[test]
+    fn replace_star_edge_cases() {
+        assert_eq!(
+            replace_star("refs/remotes/origin/*", "a/b"),
+            "refs/remotes/origin/a/b"
+        );
+        assert_eq!(
+            replace_star("refs/heads/main", "ignored"),
+            "refs/heads/main"
+        );
+        assert_eq!(replace_star("refs/*/x*", "a"), "refs/a/x*");
+        assert_eq!(replace_star("refs/heads/*", ""), "refs/heads/");
+    }
+
+    #[test]
+    fn excludes_edge_cases() {
+        let mut excludes = Excludes::default();
+        assert!(!excludes.excludes("main"));
+        assert!(!excludes.excludes(""));
+
+        excludes.add("feature/*");
+        excludes.add("fix");
+        assert!(excludes.excludes("feature/a"));
+        assert!(excludes.excludes("feature/a/b"));
+        assert!(!excludes.excludes("feature"));
+        // Unlike a --glob pattern, an exclude without wildcards is matched as it is.
+        assert!(excludes.excludes("fix"));
+        assert!(!excludes.excludes("fix/a"));
+
+        excludes.clear();
+        assert!(!excludes.excludes("feature/a"));
+
+        let excludes = Excludes::from_patterns(&["refs/tags/*", ""]);
+        assert!(excludes.excludes("refs/tags/v1"));
+        assert!(!excludes.excludes("refs/heads/main"));
+        assert!(excludes.excludes(""));
+    }
+
+    #[test]
+    fn matches_tail_edge_cases() {
+        assert!(matches_tail("main", "main"));
+        assert!(matches_tail("refs/heads/main", "refs/heads/main"));
+        assert!(matches_tail("heads/main", "refs/heads/main"));
+        assert!(!matches_tail("eads/main", "refs/heads/main"));
+        assert!(!matches_tail("main", "refs/heads/main2"));
+        assert!(!matches_tail("refs/heads/main/x", "refs/heads/main"));
+    }
+}
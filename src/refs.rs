@@ -0,0 +1,499 @@
+use crate::dry_run;
+use crate::object_storage::{ObjectStorage, Oid, Sha};
+use crate::progress;
+use crate::shared_repository;
+use anyhow::anyhow;
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// How many symbolic refs are followed before giving up, as in git.
+const MAX_SYMREF_DEPTH: usize = 5;
+
+/// The raw content of a ref.
+pub enum RefValue {
+    Direct(Sha),
+    /// `ref: <target>`, e.g. `HEAD` pointing at `refs/heads/main`.
+    Symbolic(String),
+}
+
+/// Reads the ref `name` without following it: the loose file wins over `packed-refs`.
+pub fn read_ref(name: &str) -> anyhow::Result<Option<RefValue>> {
+    let path = ref_path(name);
+    if path.is_file() {
+        let content = fs::read_to_string(&path)?;
+        let content = content.trim_end();
+        return match content.strip_prefix("ref:") {
+            Some(target) => Ok(Some(RefValue::Symbolic(target.trim().to_string()))),
+            None => Ok(Some(RefValue::Direct(
+                ObjectStorage::hex_string_to_sha(content)
+                    .map_err(|_| anyhow!("invalid ref {}: '{}'", name, content))?,
+            ))),
+        };
+    }
+    Ok(packed_refs()?
+        .into_iter()
+        .find(|(packed, _)| packed == name)
+        .map(|(_, sha)| RefValue::Direct(sha)))
+}
+
+/// Follows symbolic refs from `name` and returns the name of the ref that finally holds an
+/// object id (which may not exist yet, like the branch of a new repository) and that id.
+pub fn resolve_ref(name: &str) -> anyhow::Result<(String, Option<Sha>)> {
+    let mut name = name.to_string();
+    for _ in 0..MAX_SYMREF_DEPTH {
+        match read_ref(&name)? {
+            Some(RefValue::Symbolic(target)) => name = target,
+            Some(RefValue::Direct(sha)) => return Ok((name, Some(sha))),
+            None => return Ok((name, None)),
+        }
+    }
+    Err(anyhow!("symbolic ref loop at {}", name))
+}
+
+/// Resolves a full or abbreviated ref name using git's lookup order (`<name>`, `refs/<name>`,
+/// `refs/tags/<name>`, `refs/heads/<name>`, `refs/remotes/<name>`,
+/// `refs/remotes/<name>/HEAD`) and returns the full name and its object id.
+pub fn dwim_ref(name: &str) -> anyhow::Result<Option<(String, Sha)>> {
+    let candidates = [
+        name.to_string(),
+        format!("refs/{}", name),
+        format!("refs/tags/{}", name),
+        format!("refs/heads/{}", name),
+        format!("refs/remotes/{}", name),
+        format!("refs/remotes/{}/HEAD", name),
+    ];
+    for candidate in candidates {
+        // Outside refs/, only pseudo-refs like HEAD or ORIG_HEAD are looked up, so names like
+        // "config" never pick up other files in the git directory.
+        let pseudo_ref = candidate
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c == '_');
+        if check_ref_format(&candidate).is_err() || !(candidate.starts_with("refs/") || pseudo_ref)
+        {
+            continue;
+        }
+        if let (full_name, Some(sha)) = resolve_ref(&candidate)? {
+            return Ok(Some((full_name, sha)));
+        }
+    }
+    Ok(None)
+}
+
+/// Points `name` at `sha`, going through symbolic refs (updating `HEAD` moves the current
+/// branch). With `expected_old`, the update only happens if the ref currently has that value;
+/// all-zero means the ref must not exist yet.
+pub fn update_ref(name: &str, sha: &Sha, expected_old: Option<&Sha>) -> anyhow::Result<()> {
+    let (target, _) = resolve_ref(name)?;
+    update_ref_no_deref(&target, sha, expected_old)
+}
+
+/// Like [`update_ref`], but overwrites `name` itself even if it is a symbolic ref, e.g. to
+/// detach `HEAD`.
+pub fn update_ref_no_deref(
+    name: &str,
+    sha: &Sha,
+    expected_old: Option<&Sha>,
+) -> anyhow::Result<()> {
+    check_ref_format(name)?;
+    let lock = RefLock::acquire(name)?;
+    verify_old_value(name, expected_old)?;
+    let old = match read_ref(name)? {
+        Some(RefValue::Direct(old)) => Some(Oid(old)),
+        _ => None,
+    };
+    lock.commit(format!("{}\n", ObjectStorage::sha_to_hex_string(sha)))?;
+    progress::notify(|sink| sink.ref_updated(name, old, Some(Oid(*sha))));
+    Ok(())
+}
+
+/// Makes `name` a symbolic ref pointing at `target`.
+pub fn write_symbolic_ref(name: &str, target: &str) -> anyhow::Result<()> {
+    if !target.starts_with("refs/") {
+        Err(anyhow!("refusing to point {} outside of refs/", name))?
+    }
+    check_ref_format(target)?;
+    RefLock::acquire(name)?.commit(format!("ref: {}\n", target))
+}
+
+/// Deletes `name` (the ref a symbolic ref points to, not the symbolic ref itself), from both
+/// the loose refs and `packed-refs`.
+pub fn delete_ref(name: &str, expected_old: Option<&Sha>) -> anyhow::Result<()> {
+    let (target, sha) = resolve_ref(name)?;
+    if sha.is_none() {
+        Err(anyhow!("cannot delete {}: ref does not exist", name))?
+    }
+    let lock = RefLock::acquire(&target)?;
+    verify_old_value(&target, expected_old)?;
+    let packed = packed_refs()?;
+    if packed.iter().any(|(packed_name, _)| *packed_name == target) {
+        let packed_lock = RefLock::acquire("packed-refs")?;
+        let remaining = packed
+            .iter()
+            .filter(|(packed_name, _)| *packed_name != target)
+            .map(|(packed_name, sha)| (packed_name, sha));
+        packed_lock.commit(packed_refs_content(remaining))?;
+    }
+    let path = ref_path(&target);
+    if path.is_file() {
+        fs::remove_file(path)?;
+    }
+    drop(lock);
+    progress::notify(|sink| sink.ref_updated(&target, sha.map(Oid), None));
+    Ok(())
+}
+
+/// The refs outside `refs/` that commands changing history leave in the git directory, in
+/// the same files as git, so that either can pick up where the other stopped.
+#[derive(Clone, Copy, PartialEq)]
+pub enum PseudoRef {
+    /// Where `HEAD` was before a merge moved it, to go back with `ORIG_HEAD`.
+    Orig,
+    /// The commits a merge that stopped for conflicts is merging, one per line.
+    Merge,
+    /// The commit a cherry-pick that stopped for conflicts is picking.
+    CherryPick,
+    /// The commit a revert that stopped for conflicts is reverting.
+    Revert,
+}
+
+impl PseudoRef {
+    pub fn name(self) -> &'static str {
+        match self {
+            PseudoRef::Orig => "ORIG_HEAD",
+            PseudoRef::Merge => "MERGE_HEAD",
+            PseudoRef::CherryPick => "CHERRY_PICK_HEAD",
+            PseudoRef::Revert => "REVERT_HEAD",
+        }
+    }
+}
+
+/// The commits in the pseudo-ref; none when it does not exist.
+pub fn read_pseudo_ref(pseudo_ref: PseudoRef) -> anyhow::Result<Vec<Sha>> {
+    let content = match fs::read_to_string(ref_path(pseudo_ref.name())) {
+        Ok(content) => content,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => Err(err)?,
+    };
+    content
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            // FETCH_HEAD-style lines may carry a description after the id.
+            let hex = line.split_whitespace().next().unwrap_or_default();
+            ObjectStorage::hex_string_to_sha(hex)
+                .map_err(|_| anyhow!("invalid {}: '{}'", pseudo_ref.name(), line))
+        })
+        .collect()
+}
+
+pub fn write_pseudo_ref(pseudo_ref: PseudoRef, shas: &[Sha]) -> anyhow::Result<()> {
+    let content: String = shas
+        .iter()
+        .map(|sha| format!("{}\n", ObjectStorage::sha_to_hex_string(sha)))
+        .collect();
+    RefLock::acquire(pseudo_ref.name())?.commit(content)
+}
+
+pub fn delete_pseudo_ref(pseudo_ref: PseudoRef) -> anyhow::Result<()> {
+    match fs::remove_file(ref_path(pseudo_ref.name())) {
+        Err(err) if err.kind() != ErrorKind::NotFound => Err(err)?,
+        _ => Ok(()),
+    }
+}
+
+/// What an unfinished merge, cherry-pick or revert left besides its pseudo-ref: the message
+/// prepared for the commit concluding it, the merge's options, and the tree with conflict
+/// markers git records.
+const OPERATION_FILES: [&str; 3] = ["MERGE_MSG", "MERGE_MODE", "AUTO_MERGE"];
+
+/// Forgets the merge, cherry-pick or revert in progress, once the commit concluding it is
+/// made or it is given up.
+pub fn clear_operation_state() -> anyhow::Result<()> {
+    for pseudo_ref in [PseudoRef::Merge, PseudoRef::CherryPick, PseudoRef::Revert] {
+        delete_pseudo_ref(pseudo_ref)?;
+    }
+    for file in OPERATION_FILES {
+        match fs::remove_file(ObjectStorage::git_dir().join(file)) {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err)?,
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+/// Moves every loose ref into `packed-refs` and deletes the loose files, like
+/// `git pack-refs --all --prune`; symbolic refs stay loose. A loose ref is only deleted under
+/// its lock and while it still has the packed value, so a concurrent update is never lost.
+/// Returns how many refs were packed.
+pub fn pack_refs() -> anyhow::Result<usize> {
+    let packed_lock = RefLock::acquire("packed-refs")?;
+    let mut loose: Vec<(String, Sha)> = vec![];
+    collect_loose_direct_refs(&ObjectStorage::git_dir().join("refs"), "refs", &mut loose)?;
+    if loose.is_empty() {
+        return Ok(0);
+    }
+    let mut refs: BTreeMap<String, Sha> = packed_refs()?.into_iter().collect();
+    refs.extend(loose.iter().cloned());
+    packed_lock.commit(packed_refs_content(refs.iter()))?;
+    for (name, sha) in &loose {
+        // A ref locked by someone else is being updated; it stays loose and wins over the
+        // packed value.
+        let Ok(lock) = RefLock::acquire(name) else {
+            continue;
+        };
+        let path = ref_path(name);
+        if matches!(read_ref(name)?, Some(RefValue::Direct(current)) if current == *sha) {
+            dry_run::remove_file(&path)?;
+        }
+        drop(lock);
+        let mut dir = path.parent();
+        while let Some(parent) = dir {
+            if parent.ends_with("refs/heads")
+                || parent.ends_with("refs/tags")
+                || parent.ends_with("refs")
+                || fs::remove_dir(parent).is_err()
+            {
+                break;
+            }
+            dir = parent.parent();
+        }
+    }
+    Ok(loose.len())
+}
+
+/// The `packed-refs` file for `refs`, which must be sorted by name. Every annotated tag is
+/// followed by the object it peels to, so the file can claim to be fully peeled.
+fn packed_refs_content<'a>(refs: impl Iterator<Item = (&'a String, &'a Sha)>) -> String {
+    let mut content = String::from("# pack-refs with: peeled fully-peeled sorted \n");
+    for (name, sha) in refs {
+        content.push_str(&format!(
+            "{} {}\n",
+            ObjectStorage::sha_to_hex_string(sha),
+            name
+        ));
+        if let Ok(peeled) = ObjectStorage::peel_tag(sha)
+            && peeled != *sha
+        {
+            content.push_str(&format!("^{}\n", ObjectStorage::sha_to_hex_string(&peeled)));
+        }
+    }
+    content
+}
+
+/// Every ref under `refs/`, loose and packed (loose entries win), sorted by name.
+pub fn list_refs() -> anyhow::Result<Vec<(String, Sha)>> {
+    let mut refs: Vec<(String, Sha)> = vec![];
+    collect_loose_refs(&ObjectStorage::git_dir().join("refs"), "refs", &mut refs)?;
+    for (name, sha) in packed_refs()? {
+        if !refs.iter().any(|(loose, _)| *loose == name) {
+            refs.push((name, sha));
+        }
+    }
+    refs.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(refs)
+}
+
+/// What the refs whose names start with `prefix` point to: `refs/heads/` for `--branches`,
+/// `refs/tags/` for `--tags`.
+pub fn ref_tips(prefix: &str) -> anyhow::Result<Vec<Sha>> {
+    Ok(list_refs()?
+        .into_iter()
+        .filter(|(name, _)| name.starts_with(prefix))
+        .map(|(_, sha)| sha)
+        .collect())
+}
+
+/// What every ref and `HEAD` point to, as `--all` starts from and everything reachable is
+/// kept from.
+pub fn all_tips() -> anyhow::Result<Vec<Sha>> {
+    let mut tips: Vec<Sha> = resolve_ref("HEAD")?.1.into_iter().collect();
+    tips.extend(ref_tips("refs/")?);
+    Ok(tips)
+}
+
+/// The branch `HEAD` points to, e.g. `refs/heads/main`, or `None` when it is detached.
+pub fn current_branch() -> anyhow::Result<Option<String>> {
+    match read_ref("HEAD")? {
+        Some(RefValue::Symbolic(target)) => Ok(Some(target)),
+        _ => Ok(None),
+    }
+}
+
+/// Checks `name` against git's ref naming rules (`git check-ref-format`).
+pub fn check_ref_format(name: &str) -> anyhow::Result<()> {
+    let invalid = || Err(anyhow!("'{}' is not a valid ref name", name));
+    if name.is_empty()
+        || name == "@"
+        || name.ends_with('/')
+        || name.ends_with('.')
+        || name.contains("..")
+        || name.contains("@{")
+        || name.contains("//")
+        || name.starts_with('/')
+        || name
+            .chars()
+            .any(|c| c.is_ascii_control() || " ~^:?*[\\".contains(c))
+    {
+        return invalid();
+    }
+    if name
+        .split('/')
+        .any(|component| component.starts_with('.') || component.ends_with(".lock"))
+    {
+        return invalid();
+    }
+    Ok(())
+}
+
+fn ref_path(name: &str) -> PathBuf {
+    ObjectStorage::git_dir().join(name)
+}
+
+fn verify_old_value(name: &str, expected_old: Option<&Sha>) -> anyhow::Result<()> {
+    let Some(expected) = expected_old else {
+        return Ok(());
+    };
+    let current = match read_ref(name)? {
+        Some(RefValue::Direct(sha)) => Some(sha),
+        _ => None,
+    };
+    let matches = match current {
+        Some(current) => current == *expected,
+        None => expected.iter().all(|&b| b == 0),
+    };
+    if matches {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "cannot lock ref '{}': is at {} but expected {}",
+            name,
+            current
+                .map(|sha| ObjectStorage::sha_to_hex_string(&sha))
+                .unwrap_or_else(|| "nothing".to_string()),
+            ObjectStorage::sha_to_hex_string(expected)
+        ))
+    }
+}
+
+/// Parses `packed-refs`, skipping the header and peeled (`^`) lines.
+fn packed_refs() -> anyhow::Result<Vec<(String, Sha)>> {
+    let path = ObjectStorage::git_dir().join("packed-refs");
+    if !path.exists() {
+        return Ok(vec![]);
+    }
+    let mut refs = vec![];
+    for line in fs::read_to_string(path)?.lines() {
+        if line.starts_with('#') || line.starts_with('^') || line.is_empty() {
+            continue;
+        }
+        let (sha, name) = line
+            .split_once(' ')
+            .ok_or(anyhow!("invalid line in packed-refs: '{}'", line))?;
+        refs.push((name.to_string(), ObjectStorage::hex_string_to_sha(sha)?));
+    }
+    Ok(refs)
+}
+
+/// The loose refs below `dir` that hold an object id rather than pointing to another ref.
+fn collect_loose_direct_refs(
+    dir: &Path,
+    prefix: &str,
+    refs: &mut Vec<(String, Sha)>,
+) -> anyhow::Result<()> {
+    if !dir.exists() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir)?.flatten() {
+        let name = format!("{}/{}", prefix, entry.file_name().to_string_lossy());
+        if entry.file_type()?.is_dir() {
+            collect_loose_direct_refs(&entry.path(), &name, refs)?;
+        } else if !name.ends_with(".lock")
+            && let Some(RefValue::Direct(sha)) = read_ref(&name)?
+        {
+            refs.push((name, sha));
+        }
+    }
+    Ok(())
+}
+
+fn collect_loose_refs(
+    dir: &Path,
+    prefix: &str,
+    refs: &mut Vec<(String, Sha)>,
+) -> anyhow::Result<()> {
+    if !dir.exists() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(dir)?.flatten() {
+        let name = format!("{}/{}", prefix, entry.file_name().to_string_lossy());
+        if entry.file_type()?.is_dir() {
+            collect_loose_refs(&entry.path(), &name, refs)?;
+        } else if !name.ends_with(".lock")
+            && let (_, Some(sha)) = resolve_ref(&name)?
+        {
+            refs.push((name, sha));
+        }
+    }
+    Ok(())
+}
+
+/// An exclusively created `<ref>.lock` file. Committing writes the new content to the lock and
+/// renames it over the ref; dropping an uncommitted lock removes it.
+struct RefLock {
+    path: PathBuf,
+    lock_path: PathBuf,
+    file: Option<File>,
+}
+
+impl RefLock {
+    fn acquire(name: &str) -> anyhow::Result<Self> {
+        let path = ref_path(name);
+        if let Some(parent) = path.parent() {
+            shared_repository::create_dir_all(parent)?;
+        }
+        let mut lock_name = path.file_name().unwrap_or_default().to_os_string();
+        lock_name.push(".lock");
+        let lock_path = path.with_file_name(lock_name);
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&lock_path)
+            .map_err(|err| match err.kind() {
+                ErrorKind::AlreadyExists => anyhow!(
+                    "Unable to create '{}': File exists.\n\nAnother git process seems to be \
+                     running in this repository, or a previous one crashed; if so, remove the \
+                     file manually.",
+                    lock_path.display()
+                ),
+                _ => anyhow!("Unable to create '{}': {}", lock_path.display(), err),
+            })?;
+        Ok(Self {
+            path,
+            lock_path,
+            file: Some(file),
+        })
+    }
+
+    /// Replaces the ref with `content`; in a dry run the lock is just released.
+    fn commit(mut self, content: String) -> anyhow::Result<()> {
+        if dry_run::intercept(|| format!("update {}", dry_run::display(&self.path))) {
+            return Ok(());
+        }
+        let mut file = self.file.take().ok_or(anyhow!("lock already released"))?;
+        file.write_all(content.as_bytes())?;
+        drop(file);
+        shared_repository::adjust(&self.lock_path)?;
+        fs::rename(&self.lock_path, &self.path)?;
+        Ok(())
+    }
+}
+
+impl Drop for RefLock {
+    fn drop(&mut self) {
+        if self.file.is_some() {
+            let _ = fs::remove_file(&self.lock_path);
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use crate::cancel;
+use crate::config::Config;
+use crate::dry_run;
+use crate::index::Index;
+use crate::object_storage::pack;
+use crate::object_storage::{GitObject, ObjectStorage, Sha};
+use crate::oid_set::OidSet;
+use crate::promisor;
+use crate::refs;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+/// git's defaults for `pack.window` and `pack.depth`.
+const DEFAULT_WINDOW: usize = 10;
+const DEFAULT_DEPTH: usize = 50;
+
+/// The files of a pack, removed with it; the index goes first, so that a pack is never found
+/// without its data.
+const PACK_EXTENSIONS: [&str; 5] = ["idx", "pack", "rev", "bitmap", "promisor"];
+
+pub struct RepackOptions {
+    /// Pack everything reachable and everything in existing packs into one pack, rather than
+    /// only the loose objects.
+    pub all: bool,
+    /// Remove the packs and loose objects made redundant by the new pack.
+    pub delete: bool,
+    pub quiet: bool,
+    pub window: usize,
+    pub depth: usize,
+}
+
+impl RepackOptions {
+    /// Options with the window and depth from `pack.window` and `pack.depth`.
+    pub fn new(all: bool, delete: bool, quiet: bool) -> anyhow::Result<Self> {
+        let option = |key, default| -> anyhow::Result<usize> {
+            Ok(Config::get()?
+                .int("pack", key)?
+                .map_or(default, |value| value.max(0) as usize))
+        };
+        Ok(Self {
+            all,
+            delete,
+            quiet,
+            window: option("window", DEFAULT_WINDOW)?,
+            depth: option("depth", DEFAULT_DEPTH)?,
+        })
+    }
+}
+
+/// Writes the loose objects, or with `all` every object worth keeping, into a new pack under
+/// `objects/pack`, like `git repack`. Packs with a `.keep` file, and packs fetched from a
+/// promisor remote, are left as they are, and their objects are not packed again. Unreachable
+/// loose objects stay loose.
+pub fn repack(options: &RepackOptions) -> anyhow::Result<()> {
+    let objects = if options.all {
+        all_objects()?
+    } else {
+        let mut objects = vec![];
+        for (sha, _) in ObjectStorage::loose_objects()? {
+            if !pack::contains(&sha)? {
+                objects.push((sha, String::new()));
+            }
+        }
+        objects
+    };
+    if objects.is_empty() {
+        if !options.quiet {
+            println!("Nothing new to pack.");
+        }
+    } else {
+        let (data, deltas) = pack::build_pack(&objects, options.window, options.depth)?;
+        let new_pack = pack::path_of(&data);
+        let dry_run = dry_run::intercept(|| {
+            format!(
+                "write {} with {} objects",
+                dry_run::display(&new_pack),
+                objects.len()
+            )
+        });
+        if !dry_run {
+            pack::write_pack(&data)?;
+        }
+        if !options.quiet {
+            eprintln!("Total {} (delta {})", objects.len(), deltas);
+        }
+        if options.all && options.delete {
+            remove_redundant_packs(&new_pack)?;
+        }
+    }
+    if options.delete {
+        let packed: HashSet<Sha> = objects.iter().map(|(sha, _)| *sha).collect();
+        prune_packed(&packed)?;
+    }
+    Ok(())
+}
+
+/// Every object worth keeping: the reachable ones, then the other objects of the packs that
+/// will be replaced. Objects in kept and promisor packs are left out.
+fn all_objects() -> anyhow::Result<Vec<(Sha, String)>> {
+    let packs = pack::loaded_packs()?;
+    let kept: Vec<_> = packs.iter().filter(|pack| is_left_alone(pack)).collect();
+    let is_kept = |sha: &Sha| kept.iter().any(|pack| pack.find_offset(sha).is_some());
+
+    let mut objects = reachable_objects(is_kept)?;
+    let mut seen = OidSet::new()?;
+    for (sha, _) in &objects {
+        seen.insert(*sha);
+    }
+    for pack in packs.iter().filter(|pack| !is_left_alone(pack)) {
+        for sha in pack.shas() {
+            if seen.insert(*sha) && !is_kept(sha) {
+                objects.push((*sha, String::new()));
+            }
+        }
+    }
+    Ok(objects)
+}
+
+/// Every object reachable from the refs, `HEAD` and the index, with the path each was first
+/// reached by. Objects `skip` picks are left out along with what only they lead to, and so
+/// are the missing objects a promisor remote promised.
+pub fn reachable_objects(skip: impl Fn(&Sha) -> bool) -> anyhow::Result<Vec<(Sha, String)>> {
+    let mut seen = OidSet::new()?;
+    let mut objects: Vec<(Sha, String)> = vec![];
+    let mut pending: Vec<(Sha, String)> = refs::all_tips()?
+        .into_iter()
+        .map(|sha| (sha, String::new()))
+        .collect();
+    if Index::path().exists() {
+        for entry in Index::read()?.entries {
+            pending.push((entry.sha, entry.path));
+        }
+    }
+    while let Some((sha, name)) = pending.pop() {
+        if !seen.insert(sha) || skip(&sha) || promisor::may_skip(&sha)? {
+            continue;
+        }
+        cancel::check()?;
+        match GitObject::from_sha(&sha)? {
+            GitObject::Commit(commit) => {
+                pending.extend(commit.parents.iter().map(|parent| (*parent, String::new())));
+                pending.push((commit.tree, String::new()));
+            }
+            GitObject::Tree(tree) => {
+                for entry in tree.entries {
+                    let path = if name.is_empty() {
+                        entry.name
+                    } else {
+                        format!("{}/{}", name, entry.name)
+                    };
+                    pending.push((entry.hash, path));
+                }
+            }
+            GitObject::Tag(tag) => pending.push((tag.object, String::new())),
+            GitObject::Blob(_) => {}
+        }
+        objects.push((sha, name));
+    }
+    Ok(objects)
+}
+
+/// Removes the packs other than `new_pack` that are not left alone: all their objects that
+/// are worth keeping are in the new pack.
+fn remove_redundant_packs(new_pack: &Path) -> anyhow::Result<()> {
+    let packs = pack::loaded_packs()?;
+    for pack in packs.iter() {
+        if pack.pack_path() == new_pack || is_left_alone(pack) {
+            continue;
+        }
+        for extension in PACK_EXTENSIONS {
+            let path = pack.pack_path().with_extension(extension);
+            if path.exists() {
+                dry_run::remove_file(&path)?;
+            }
+        }
+    }
+    pack::reset_cache();
+    Ok(())
+}
+
+/// Packs with a `.keep` file, and promisor packs: objects from a promisor remote have to stay
+/// in a pack marked as such, or they would no longer count as promised.
+fn is_left_alone(pack: &pack::Pack) -> bool {
+    pack.is_kept() || pack.is_promisor()
+}
+
+/// Removes the loose objects that are also in a pack, or among the `packed` ones just written
+/// to one, and the directories left empty, like `git prune-packed`.
+fn prune_packed(packed: &HashSet<Sha>) -> anyhow::Result<()> {
+    for (sha, path) in ObjectStorage::loose_objects()? {
+        if packed.contains(&sha) || pack::contains(&sha)? {
+            dry_run::remove_file(&path)?;
+            if let Some(parent) = path.parent() {
+                // Fails, as it should, while the directory still has objects in it.
+                let _ = fs::remove_dir(parent);
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,362 @@
+use crate::cancel::{self, CancellationToken};
+use crate::cli;
+use crate::commands;
+use crate::commit_walk;
+use crate::context::{self, Context};
+use crate::dry_run;
+use crate::merge;
+use crate::merge_file::Labels;
+use crate::object_storage::{Blob, Commit, GitObject, ObjectStorage, Oid, Tag, Tree};
+use crate::progress::{self, EventSink, ProgressReporter};
+use crate::refs;
+use crate::revision;
+use crate::tree_walk::TreeWalk;
+use crate::usage::{self, UsageRecorder};
+use anyhow::anyhow;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{self, Path, PathBuf};
+use std::sync::Arc;
+
+/// Where a repository and its working tree are, and the entry point of the library API for
+/// reading and writing its objects.
+///
+/// Each `Repository` keeps its own paths, what it has read of the repository's config and
+/// packs, and its settings, such as the progress reporter; its methods work on it alone, so a
+/// process can have several repositories open at once.
+pub struct Repository {
+    context: Arc<Context>,
+}
+
+impl Repository {
+    /// Finds the repository the way git does. `GIT_DIR` (set by `--git-dir`) names it
+    /// directly, with the current directory as the working tree; otherwise the current
+    /// directory and then its parents are searched for a `.git` directory, a `.git` file
+    /// pointing elsewhere (`gitdir: <path>`), or a bare repository. `GIT_WORK_TREE` (set by
+    /// `--work-tree`) overrides the working tree. Returns `None` outside any repository.
+    pub fn discover() -> anyhow::Result<Option<Self>> {
+        let work_tree_override = env::var_os("GIT_WORK_TREE")
+            .map(|work_tree| path::absolute(PathBuf::from(work_tree)))
+            .transpose()?;
+        let cwd = env::current_dir()?;
+        if let Some(git_dir) = env::var_os("GIT_DIR") {
+            let git_dir = path::absolute(PathBuf::from(git_dir))?;
+            if !is_git_dir(&git_dir) {
+                Err(anyhow!("not a git repository: '{}'", git_dir.display()))?
+            }
+            return Ok(Some(Self::new(git_dir, work_tree_override.or(Some(cwd)))));
+        }
+        Ok(Self::search(&cwd)?
+            .map(|(git_dir, work_tree)| Self::new(git_dir, work_tree_override.or(work_tree))))
+    }
+
+    /// Opens the repository that `path` is in, searching upwards like [`discover`] but
+    /// without looking at the environment.
+    ///
+    /// [`discover`]: Repository::discover
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let (git_dir, work_tree) =
+            Self::search(&path::absolute(path.as_ref())?)?.ok_or_else(Self::not_found)?;
+        Ok(Self::new(git_dir, work_tree))
+    }
+
+    /// Creates an empty repository with its working tree at `path`, creating the directory if
+    /// needed.
+    pub fn init(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let work_tree = path::absolute(path.as_ref())?;
+        fs::create_dir_all(&work_tree)?;
+        let repository = Self::new(work_tree.join(".git"), Some(work_tree));
+        repository.enter(|| ObjectStorage::init_cwd(None))?;
+        Ok(repository)
+    }
+
+    fn new(git_dir: PathBuf, work_tree: Option<PathBuf>) -> Self {
+        Self {
+            context: Context::new(git_dir, work_tree),
+        }
+    }
+
+    /// The git directory and the working tree of the repository `start` is in.
+    fn search(start: &Path) -> anyhow::Result<Option<(PathBuf, Option<PathBuf>)>> {
+        for dir in start.ancestors() {
+            let dot_git = dir.join(".git");
+            let git_dir = if dot_git.is_file() {
+                Some(read_gitdir_file(&dot_git)?)
+            } else {
+                is_git_dir(&dot_git).then_some(dot_git)
+            };
+            if let Some(git_dir) = git_dir {
+                return Ok(Some((git_dir, Some(dir.to_path_buf()))));
+            }
+            if is_git_dir(dir) {
+                return Ok(Some((dir.to_path_buf(), None)));
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn git_dir(&self) -> &Path {
+        &self.context.git_dir
+    }
+
+    /// `None` for a bare repository, or when running inside the repository directory.
+    pub fn work_tree(&self) -> Option<&Path> {
+        self.context.work_tree.as_deref()
+    }
+
+    /// The error for commands that need a repository when [`Repository::discover`] found none.
+    pub fn not_found() -> anyhow::Error {
+        anyhow!("not a git repository (or any of the parent directories): .git")
+    }
+
+    /// Runs `work` on this repository: the object storage, the refs and the index it uses
+    /// are this repository's.
+    pub(crate) fn enter<T>(&self, work: impl FnOnce() -> T) -> T {
+        context::enter(self.context.clone(), work)
+    }
+
+    pub fn find_object(&self, oid: Oid) -> anyhow::Result<GitObject> {
+        self.enter(|| GitObject::from_sha(oid.as_bytes()))
+    }
+
+    pub fn find_blob(&self, oid: Oid) -> anyhow::Result<Blob> {
+        self.enter(|| GitObject::read_blob(oid.as_bytes()))
+    }
+
+    pub fn find_tree(&self, oid: Oid) -> anyhow::Result<Tree> {
+        self.enter(|| GitObject::read_tree(oid.as_bytes()))
+    }
+
+    pub fn find_commit(&self, oid: Oid) -> anyhow::Result<Commit> {
+        self.enter(|| GitObject::read_commit(oid.as_bytes()))
+    }
+
+    pub fn find_tag(&self, oid: Oid) -> anyhow::Result<Tag> {
+        self.enter(|| GitObject::read_tag(oid.as_bytes()))
+    }
+
+    pub fn write_blob(&self, content: &[u8]) -> anyhow::Result<Oid> {
+        self.enter(|| Ok(ObjectStorage::hash_object("blob", content, true, true)?.into()))
+    }
+
+    /// Writes `tree` as it is; git expects the entries to be sorted by name.
+    pub fn write_tree(&self, tree: &Tree) -> anyhow::Result<Oid> {
+        self.enter(|| Ok(tree.write_to_object_storage()?.into()))
+    }
+
+    pub fn write_commit(&self, commit: &Commit) -> anyhow::Result<Oid> {
+        self.enter(|| Ok(commit.write_to_object_storage()?.into()))
+    }
+
+    pub fn write_tag(&self, tag: &Tag) -> anyhow::Result<Oid> {
+        self.enter(|| Ok(tag.write_to_object_storage()?.into()))
+    }
+
+    /// Sends the progress of fetching, packing and checking out to `reporter` instead of
+    /// printing it to stderr.
+    pub fn set_progress_reporter(&self, reporter: impl ProgressReporter + 'static) {
+        self.enter(|| progress::set_reporter(Arc::new(reporter)));
+    }
+
+    /// Tells `sink` about ref updates and merge conflicts from now on.
+    pub fn set_event_sink(&self, sink: impl EventSink + 'static) {
+        self.enter(|| progress::set_event_sink(Arc::new(sink)));
+    }
+
+    /// Tells `recorder` how long each command takes and how many objects it reads and writes
+    /// from now on.
+    pub fn set_usage_recorder(&self, recorder: impl UsageRecorder + 'static) {
+        self.enter(|| usage::set_recorder(Arc::new(recorder)));
+    }
+
+    /// Makes long operations check `token` and stop, rolling back, once it is cancelled.
+    pub fn set_cancellation_token(&self, token: CancellationToken) {
+        self.enter(|| cancel::set_token(token));
+    }
+
+    /// With `dry_run`, commands that delete or replace repository data, such as `gc`, only
+    /// print what they would change, one `Would ...` line each. [`run_command`] refuses the
+    /// commands that have no dry run, as the command line refuses them with `--dry-run`.
+    ///
+    /// [`run_command`]: Repository::run_command
+    pub fn set_dry_run(&self, dry_run: bool) {
+        self.enter(|| dry_run::set_active(dry_run));
+    }
+
+    /// Runs the command `name` with `args` as the command line runs `codecrafters-git <name>
+    /// <args>...`, printing to stdout and stderr. Where the command line would exit with a
+    /// non-zero code, as on a usage error or for `diff --exit-code` finding changes, this fails
+    /// with a [`CommandExit`]; other failures are errors as the command line prints them.
+    /// Work the command line leaves to a background process, such as the automatic gc after a
+    /// commit, is done before this returns.
+    pub fn run_command(&self, name: &str, args: &[String]) -> anyhow::Result<()> {
+        self.enter(|| {
+            if dry_run::is_active() && !cli::supports_dry_run(name) {
+                Err(anyhow!("'{}' does not support --dry-run", name))?
+            }
+            commands::run(name, args)
+        })
+    }
+
+    /// The commit `HEAD` points to, or `None` before the first commit.
+    pub fn head(&self) -> anyhow::Result<Option<Oid>> {
+        self.enter(|| Ok(refs::resolve_ref("HEAD")?.1.map(Oid)))
+    }
+
+    /// Every ref under `refs/` with the object it points to, sorted by name.
+    pub fn references(&self) -> anyhow::Result<Vec<(String, Oid)>> {
+        self.enter(|| {
+            Ok(refs::list_refs()?
+                .into_iter()
+                .map(|(name, sha)| (name, Oid(sha)))
+                .collect())
+        })
+    }
+
+    /// The object a revision such as `main`, `v1.0` or an abbreviated id names.
+    pub fn resolve(&self, revision: &str) -> anyhow::Result<Oid> {
+        self.enter(|| Ok(revision::resolve(revision)?.into()))
+    }
+
+    /// Whether the commit `ancestor` is reachable from `descendant`, so that updating a ref
+    /// from one to the other is a fast-forward; a commit is its own ancestor. Generation
+    /// numbers from the commit-graph file, when git wrote one, keep the walk from going
+    /// further back than `ancestor` could be.
+    pub fn is_ancestor(&self, ancestor: Oid, descendant: Oid) -> anyhow::Result<bool> {
+        self.enter(|| commit_walk::is_ancestor(ancestor.as_bytes(), descendant.as_bytes()))
+    }
+
+    /// Merges the commits `ours` and `theirs` name without touching the working tree or the
+    /// index, so that it works in a bare repository too: the merged files and the text merges
+    /// of conflicting ones are written as objects, and the result is their tree. Conflict
+    /// markers are labelled with the two names. Nothing is committed and no ref moves.
+    pub fn merge(&self, ours: &str, theirs: &str) -> anyhow::Result<MergeOutcome> {
+        self.enter(|| self.merge_in_context(ours, theirs))
+    }
+
+    fn merge_in_context(&self, ours: &str, theirs: &str) -> anyhow::Result<MergeOutcome> {
+        let labels = Labels {
+            ours,
+            base: "merged common ancestors",
+            theirs,
+        };
+        let our_commit = ObjectStorage::peel_to_commit(&revision::resolve(ours)?)?;
+        let their_commit = ObjectStorage::peel_to_commit(&revision::resolve(theirs)?)?;
+        let merged = merge::merge_commits(&our_commit, &their_commit, &labels)?;
+        Ok(MergeOutcome {
+            tree: Oid(merged.tree()?),
+            conflicts: merged
+                .conflicts
+                .into_iter()
+                .map(|conflict| (conflict.path, conflict.kind))
+                .collect(),
+        })
+    }
+
+    /// The commits reachable from `start`, newest committer date first, like `git log`.
+    pub fn commits(&self, start: Oid) -> Commits {
+        Commits {
+            context: self.context.clone(),
+            pending: BinaryHeap::new(),
+            loaded: HashMap::new(),
+            seen: HashSet::new(),
+            start: Some(start),
+        }
+    }
+
+    /// The entries of the tree `root` and of every tree in it.
+    pub fn tree_walk<'a>(&self, root: Oid) -> TreeWalk<'a> {
+        self.enter(|| TreeWalk::new(root))
+    }
+}
+
+/// The result of [`Repository::merge`].
+pub struct MergeOutcome {
+    /// The merged tree, with conflict markers in files whose changes conflict.
+    pub tree: Oid,
+    /// The paths that conflict, sorted, each with the kind of conflict as in git's
+    /// `CONFLICT (<kind>)` lines: `content`, `add/add`, `modify/delete`, `file/directory` or
+    /// `file location`. The merge is clean when there are none.
+    pub conflicts: Vec<(String, &'static str)>,
+}
+
+/// The error of [`Repository::run_command`] for a command that ends with a non-zero exit
+/// code, after printing what it had to say about it.
+#[derive(Debug)]
+pub struct CommandExit {
+    pub code: i32,
+}
+
+impl fmt::Display for CommandExit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "command exited with code {}", self.code)
+    }
+}
+
+impl std::error::Error for CommandExit {}
+
+/// The iterator returned by [`Repository::commits`].
+pub struct Commits {
+    context: Arc<Context>,
+    /// By committer time, then id, so that the newest commit comes out first.
+    pending: BinaryHeap<(i64, Oid)>,
+    loaded: HashMap<Oid, Commit>,
+    seen: HashSet<Oid>,
+    start: Option<Oid>,
+}
+
+impl Commits {
+    fn queue(&mut self, oid: Oid) -> anyhow::Result<()> {
+        if self.seen.insert(oid) {
+            let commit = context::enter(self.context.clone(), || {
+                GitObject::read_commit(oid.as_bytes())
+            })?;
+            self.pending.push((commit.committer.time, oid));
+            self.loaded.insert(oid, commit);
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for Commits {
+    type Item = anyhow::Result<(Oid, Commit)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(start) = self.start.take()
+            && let Err(err) = self.queue(start)
+        {
+            return Some(Err(err));
+        }
+        let (_, oid) = self.pending.pop()?;
+        let commit = self.loaded.remove(&oid)?;
+        for parent in commit.parent_ids() {
+            if let Err(err) = self.queue(parent) {
+                return Some(Err(err));
+            }
+        }
+        Some(Ok((oid, commit)))
+    }
+}
+
+/// Whether `dir` looks like a repository: it has `HEAD`, `objects/` and `refs/`.
+fn is_git_dir(dir: &Path) -> bool {
+    dir.join("HEAD").is_file() && dir.join("objects").is_dir() && dir.join("refs").is_dir()
+}
+
+/// Follows a `.git` file, as used by linked worktrees and submodules, to the repository it
+/// names; a relative path is relative to the directory holding the file.
+fn read_gitdir_file(file: &Path) -> anyhow::Result<PathBuf> {
+    let content = fs::read_to_string(file)
+        .map_err(|err| anyhow!("cannot read '{}': {}", file.display(), err))?;
+    let target = content
+        .strip_prefix("gitdir: ")
+        .map(str::trim_end)
+        .ok_or(anyhow!("invalid gitfile format: {}", file.display()))?;
+    let git_dir = file.parent().unwrap_or(Path::new("")).join(target);
+    if !is_git_dir(&git_dir) {
+        Err(anyhow!("not a git repository: {}", git_dir.display()))?
+    }
+    Ok(git_dir)
+}
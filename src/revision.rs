@@ -0,0 +1,230 @@
+use crate::compat_map;
+use crate::index::Index;
+use crate::object_storage::{GitObject, ObjectStorage, Sha};
+use crate::pathspec;
+use crate::refs;
+use anyhow::anyhow;
+
+/// The shortest abbreviated object id that is looked up, as in git.
+const MIN_ABBREV: usize = 4;
+
+/// Turns a command-line revision into an object id. It starts with a full or abbreviated
+/// object id or a ref name (`@` alone is `HEAD`), followed by any number of `~<n>` (the
+/// `<n>`th first-parent ancestor), `^<n>` (the `<n>`th parent, `^0` the commit itself) and
+/// `^{<type>}` (the object peeled to a `commit`, `tree`, `blob` or `tag`; `^{}` peels tags).
+/// A missing `<n>` is 1, so `main^^` and `main~2` are the same commit.
+///
+/// `<revision>:<path>` is the blob or tree at `path` in the tree of the revision, and
+/// `:<path>` or `:<stage>:<path>` the blob staged at `path`, at stage 0 unless another is
+/// given. The path is from the top of the working tree unless it starts with `./` or `../`,
+/// which make it relative to the current directory.
+pub fn resolve(revision: &str) -> anyhow::Result<Sha> {
+    match revision.split_once(':') {
+        Some(("", path)) => resolve_index_path(path),
+        Some((tree_ish, path)) => resolve_tree_path(tree_ish, path),
+        None => resolve_revision(revision),
+    }
+}
+
+fn resolve_tree_path(tree_ish: &str, path: &str) -> anyhow::Result<Sha> {
+    let sha =
+        resolve_revision(tree_ish).map_err(|_| anyhow!("invalid object name '{}'.", tree_ish))?;
+    let path = top_level_path(path)?;
+    let missing = || {
+        let location = match on_disk(&path) {
+            true => "exists on disk, but not",
+            false => "does not exist",
+        };
+        anyhow!("path '{}' {} in '{}'", path, location, tree_ish)
+    };
+    let mut sha = ObjectStorage::peel_to_tree(&sha)?;
+    for name in path.split('/').filter(|name| !name.is_empty()) {
+        let GitObject::Tree(tree) = GitObject::from_sha(&sha)? else {
+            Err(missing())?
+        };
+        let entry = tree.entries.iter().find(|entry| entry.name == name);
+        sha = entry.ok_or_else(missing)?.hash;
+    }
+    Ok(sha)
+}
+
+fn resolve_index_path(path: &str) -> anyhow::Result<Sha> {
+    let (stage, path) = match path.split_once(':') {
+        Some((stage @ ("0" | "1" | "2" | "3"), path)) => (stage.parse()?, path),
+        _ => (0, path),
+    };
+    let path = top_level_path(path)?;
+    let index = Index::read()?;
+    let mut entries = index.entries.iter().filter(|entry| entry.path == path);
+    let first = entries.clone().next();
+    if let Some(entry) = entries.find(|entry| entry.stage == stage) {
+        return Ok(entry.sha);
+    }
+    let message = match (first, on_disk(&path)) {
+        (Some(_), _) => format!(
+            "path '{}' is in the index, but not at stage {}",
+            path, stage
+        ),
+        (None, true) => format!("path '{}' exists on disk, but not in the index", path),
+        (None, false) => format!(
+            "path '{}' does not exist (neither on disk nor in the index)",
+            path
+        ),
+    };
+    Err(anyhow!(message))
+}
+
+/// `path` from the top of the working tree, resolving a leading `./` or `../` against the
+/// current directory.
+fn top_level_path(path: &str) -> anyhow::Result<String> {
+    if path != "." && path != ".." && !path.starts_with("./") && !path.starts_with("../") {
+        return Ok(path.to_string());
+    }
+    let relative = format!("{}/{}", pathspec::current_prefix()?, path);
+    pathspec::normalize(&relative).ok_or(anyhow!("'{}' is outside the repository", path))
+}
+
+fn on_disk(path: &str) -> bool {
+    ObjectStorage::has_work_tree()
+        && ObjectStorage::work_tree()
+            .join(path)
+            .symlink_metadata()
+            .is_ok()
+}
+
+/// Resolves a revision without a `:<path>`.
+fn resolve_revision(revision: &str) -> anyhow::Result<Sha> {
+    let unknown = || {
+        anyhow!(
+            "ambiguous argument '{}': unknown revision or path not in the working tree",
+            revision
+        )
+    };
+    let name_end = revision.find(['~', '^']).unwrap_or(revision.len());
+    let (name, mut suffix) = revision.split_at(name_end);
+    let mut sha = resolve_name(name)?.ok_or_else(unknown)?;
+    while !suffix.is_empty() {
+        if let Some(rest) = suffix.strip_prefix("^{") {
+            let (object_type, rest) = rest.split_once('}').ok_or_else(unknown)?;
+            sha = peel(revision, &sha, object_type)?;
+            suffix = rest;
+            continue;
+        }
+        let (first_parent_only, rest) = match (suffix.strip_prefix('~'), suffix.strip_prefix('^')) {
+            (Some(rest), _) => (true, rest),
+            (_, Some(rest)) => (false, rest),
+            _ => return Err(unknown()),
+        };
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        let count: usize = match digits {
+            0 => 1,
+            _ => rest[..digits].parse().map_err(|_| unknown())?,
+        };
+        suffix = &rest[digits..];
+        sha = ObjectStorage::peel_to_commit(&sha)?;
+        sha = if first_parent_only {
+            nth_ancestor(sha, count)?
+        } else if count == 0 {
+            Some(sha)
+        } else {
+            parents(&sha)?.get(count - 1).copied()
+        }
+        .ok_or_else(unknown)?;
+    }
+    Ok(sha)
+}
+
+/// The object a name without suffixes stands for: a full object id, a ref, or a unique
+/// abbreviated object id, in that order.
+fn resolve_name(name: &str) -> anyhow::Result<Option<Sha>> {
+    let name = if name == "@" { "HEAD" } else { name };
+    if name.len() == 40
+        && let Ok(sha) = ObjectStorage::hex_string_to_sha(name)
+    {
+        return Ok(Some(sha));
+    }
+    if name.len() == 64
+        && let Ok(compat) = compat_map::from_hex(name)
+        && compat_map::enabled()?
+    {
+        return compat_map::storage_id(&compat);
+    }
+    if let Some((_, sha)) = refs::dwim_ref(name)? {
+        return Ok(Some(sha));
+    }
+    if name.len() < MIN_ABBREV || !name.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Ok(None);
+    }
+    match ObjectStorage::find_objects_by_prefix(name)?.as_slice() {
+        [] => Ok(None),
+        [sha] => Ok(Some(*sha)),
+        _ => Err(anyhow!("short object ID {} is ambiguous", name)),
+    }
+}
+
+fn parents(sha: &Sha) -> anyhow::Result<Vec<Sha>> {
+    match GitObject::from_sha(sha)? {
+        GitObject::Commit(commit) => Ok(commit.parents),
+        _ => Err(anyhow!(
+            "{} is not a commit",
+            ObjectStorage::sha_to_hex_string(sha)
+        )),
+    }
+}
+
+fn nth_ancestor(mut sha: Sha, count: usize) -> anyhow::Result<Option<Sha>> {
+    for _ in 0..count {
+        match parents(&sha)?.first() {
+            Some(parent) => sha = *parent,
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(sha))
+}
+
+/// `<revision>^{<object_type>}`: tags are peeled until an object of that type is reached.
+fn peel(revision: &str, sha: &Sha, object_type: &str) -> anyhow::Result<Sha> {
+    let mismatch = |found: &str| {
+        anyhow!(
+            "{}: expected {} type, but the object dereferences to {} type",
+            revision,
+            object_type,
+            found
+        )
+    };
+    let type_of =
+        |sha: &Sha| -> anyhow::Result<String> { Ok(ObjectStorage::open_object(sha)?.object_type) };
+    match object_type {
+        "" => ObjectStorage::peel_tag(sha),
+        "object" => type_of(sha).map(|_| *sha),
+        "tag" => match type_of(sha)?.as_str() {
+            "tag" => Ok(*sha),
+            found => Err(mismatch(found)),
+        },
+        "commit" | "tree" | "blob" => {
+            let peeled = ObjectStorage::peel_tag(sha)?;
+            match (object_type, type_of(&peeled)?.as_str()) {
+                (wanted, found) if wanted == found => Ok(peeled),
+                ("tree", "commit") => ObjectStorage::peel_to_tree(&peeled),
+                (_, found) => Err(mismatch(found)),
+            }
+        }
+        _ => Err(anyhow!(
+            "invalid object type '{}' in {}",
+            object_type,
+            revision
+        )),
+    }
+}
+
+/// The shortest prefix of `sha`, at least `min_length` characters, that names no other
+/// object.
+pub fn abbreviate(sha: &Sha, min_length: usize) -> anyhow::Result<String> {
+    let hex = ObjectStorage::sha_to_hex_string(sha);
+    for length in min_length.max(MIN_ABBREV)..hex.len() {
+        if ObjectStorage::find_objects_by_prefix(&hex[..length])?.len() <= 1 {
+            return Ok(hex[..length].to_string());
+        }
+    }
+    Ok(hex)
+}
@@ -0,0 +1,168 @@
+use crate::config::Config;
+use crate::object_storage::{Commit, GitObject, ObjectStorage, Sha};
+use crate::promisor;
+use crate::refs;
+use anyhow::anyhow;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+/// The first line of the index file, so that the format can change later.
+const HEADER: &str = "# commit-index v1";
+
+/// What the index stores about a commit: enough to walk history in committer-date order and
+/// to rule out commits for `log --author` and `--grep` without reading them.
+pub struct IndexedCommit {
+    pub time: i64,
+    pub parents: Vec<Sha>,
+    /// `Name <email>`, the part of the author line `--author` matches against.
+    pub author: String,
+    /// The distinct words of the message, lowercased.
+    pub words: Vec<String>,
+}
+
+/// The commit search index in `.git/info/commit-index`, one line per commit:
+/// `<id> <committer time> <parents>\t<author>\t<words>`, where the parents are separated by
+/// commas (or `-` for none) and the words by spaces. Lines are only ever appended; the
+/// parents of every indexed commit are indexed too (unless a promisor remote has them), so a
+/// walk that reaches an indexed commit can stay in the index.
+pub struct SearchIndex {
+    commits: HashMap<Sha, IndexedCommit>,
+}
+
+impl SearchIndex {
+    pub fn path() -> PathBuf {
+        ObjectStorage::git_dir().join("info").join("commit-index")
+    }
+
+    /// Reads the index, or `None` when the repository has none.
+    pub fn load() -> anyhow::Result<Option<Self>> {
+        let content = match fs::read_to_string(Self::path()) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => Err(err)?,
+        };
+        let mut commits = HashMap::new();
+        for line in content.lines().filter(|line| *line != HEADER) {
+            let (sha, commit) =
+                parse_line(line).ok_or(anyhow!("corrupt commit index line: {}", line))?;
+            commits.insert(sha, commit);
+        }
+        Ok(Some(Self { commits }))
+    }
+
+    pub fn get(&self, sha: &Sha) -> Option<&IndexedCommit> {
+        self.commits.get(sha)
+    }
+}
+
+/// Whether commands that add commits keep the index up to date: `log.searchIndex` is set.
+pub fn enabled() -> anyhow::Result<bool> {
+    Ok(Config::get()?.bool("log", "searchIndex").unwrap_or(false))
+}
+
+/// Adds the commits reachable from the refs and `HEAD` that the index does not have yet,
+/// creating it with the whole history the first time. Commands call this after they created
+/// or fetched commits; it does nothing unless the index is enabled.
+pub fn update() -> anyhow::Result<()> {
+    if !enabled()? {
+        return Ok(());
+    }
+    let path = SearchIndex::path();
+    let indexed: HashSet<Sha> = match fs::read_to_string(&path) {
+        Ok(content) => content
+            .lines()
+            .filter_map(|line| ObjectStorage::hex_string_to_sha(line.get(..40)?).ok())
+            .collect(),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => HashSet::new(),
+        Err(err) => Err(err)?,
+    };
+    let mut pending: Vec<Sha> = refs::all_tips()?;
+    let mut seen: HashSet<Sha> = HashSet::new();
+    let mut lines = String::new();
+    while let Some(sha) = pending.pop() {
+        if indexed.contains(&sha) || !seen.insert(sha) || promisor::may_skip(&sha)? {
+            continue;
+        }
+        let commit = match GitObject::from_sha(&sha)? {
+            GitObject::Commit(commit) => commit,
+            // Refs may point at tags, or at trees and blobs.
+            GitObject::Tag(tag) => {
+                pending.push(tag.object);
+                continue;
+            }
+            _ => continue,
+        };
+        lines.push_str(&format_line(&sha, &commit));
+        pending.extend(commit.parents);
+    }
+    if lines.is_empty() {
+        return Ok(());
+    }
+    fs::create_dir_all(path.parent().unwrap())?;
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    if indexed.is_empty() && file.metadata()?.len() == 0 {
+        writeln!(file, "{}", HEADER)?;
+    }
+    file.write_all(lines.as_bytes())?;
+    Ok(())
+}
+
+/// The words `--grep` looks up: runs of letters and digits, lowercased.
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+fn format_line(sha: &Sha, commit: &Commit) -> String {
+    let parents: Vec<String> = commit
+        .parents
+        .iter()
+        .map(ObjectStorage::sha_to_hex_string)
+        .collect();
+    let mut words: Vec<String> = words(&commit.message).collect();
+    words.sort();
+    words.dedup();
+    // Tabs and newlines separate the fields, so they cannot be part of the author.
+    let author =
+        format!("{} <{}>", commit.author.name, commit.author.email).replace(['\t', '\n'], " ");
+    format!(
+        "{} {} {}\t{}\t{}\n",
+        ObjectStorage::sha_to_hex_string(sha),
+        commit.committer.time,
+        if parents.is_empty() {
+            "-".to_string()
+        } else {
+            parents.join(",")
+        },
+        author,
+        words.join(" ")
+    )
+}
+
+fn parse_line(line: &str) -> Option<(Sha, IndexedCommit)> {
+    let mut fields = line.splitn(3, '\t');
+    let mut header = fields.next()?.split(' ');
+    let sha = ObjectStorage::hex_string_to_sha(header.next()?).ok()?;
+    let time = header.next()?.parse().ok()?;
+    let parents = match header.next()? {
+        "-" => vec![],
+        parents => parents
+            .split(',')
+            .map(|parent| ObjectStorage::hex_string_to_sha(parent).ok())
+            .collect::<Option<_>>()?,
+    };
+    let author = fields.next()?.to_string();
+    let words = fields.next()?.split(' ').map(str::to_string).collect();
+    Some((
+        sha,
+        IndexedCommit {
+            time,
+            parents,
+            author,
+            words,
+        },
+    ))
+}
@@ -0,0 +1,198 @@
+use crate::config::{self, Config};
+use crate::object_storage::{GitObject, ObjectStorage, Sha};
+use crate::refs::PseudoRef;
+use crate::revision;
+use anyhow::anyhow;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+/// What a line of the todo list does with its commit.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Action {
+    Pick,
+    Revert,
+}
+
+impl Action {
+    /// The instruction as the todo list spells it.
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Pick => "pick",
+            Action::Revert => "revert",
+        }
+    }
+
+    /// The command that carries out the instruction.
+    pub fn command(self) -> &'static str {
+        match self {
+            Action::Pick => "cherry-pick",
+            Action::Revert => "revert",
+        }
+    }
+
+    /// The pseudo-ref naming the commit whose instruction stopped for conflicts.
+    pub fn pseudo_ref(self) -> PseudoRef {
+        match self {
+            Action::Pick => PseudoRef::CherryPick,
+            Action::Revert => PseudoRef::Revert,
+        }
+    }
+}
+
+/// One line of the todo list.
+pub struct Step {
+    pub action: Action,
+    pub commit: Sha,
+}
+
+/// The options the sequence was started with, which `--continue` and `--skip` keep using.
+#[derive(Default)]
+pub struct Options {
+    /// `-x`: a cherry-picked commit's message says which commit it was picked from.
+    pub record_origin: bool,
+    /// `-m <parent-number>`: the parent of a merge the changes are taken relative to.
+    pub mainline: Option<usize>,
+}
+
+/// A cherry-pick or revert of several commits in progress, as git keeps it in
+/// `.git/sequencer`: `todo` lists what is left to do, starting with the step that stopped;
+/// `opts` holds the options in config format; `head` is where `--abort` goes back to; and
+/// `abort-safety` is the commit the sequence last made, so `--abort` can tell that `HEAD` was
+/// moved since. Options of git's this does not support are ignored.
+pub struct Sequence {
+    pub todo: Vec<Step>,
+    pub options: Options,
+    pub head: Sha,
+}
+
+impl Sequence {
+    fn dir() -> PathBuf {
+        ObjectStorage::git_dir().join("sequencer")
+    }
+
+    pub fn in_progress() -> bool {
+        Self::dir().is_dir()
+    }
+
+    /// The sequence in progress, if there is one.
+    pub fn load() -> anyhow::Result<Option<Self>> {
+        let dir = Self::dir();
+        if !dir.is_dir() {
+            return Ok(None);
+        }
+        let head = fs::read_to_string(dir.join("head"))
+            .map_err(|err| anyhow!("could not read '{}': {}", dir.join("head").display(), err))?;
+        let head = ObjectStorage::hex_string_to_sha(head.trim())
+            .map_err(|_| anyhow!("invalid contents: '{}'", dir.join("head").display()))?;
+        let opts = dir.join("opts");
+        let mut options = Options::default();
+        if opts.is_file() {
+            let opts_config = Config::read_file(&opts)?;
+            let value = |key| opts_config.value("options", key);
+            options.record_origin = value("record-origin").as_deref() == Some("true");
+            options.mainline = match value("mainline") {
+                Some(mainline) => Some(
+                    mainline
+                        .parse()
+                        .map_err(|_| anyhow!("invalid mainline in '{}'", opts.display()))?,
+                ),
+                None => None,
+            };
+        }
+        let todo = fs::read_to_string(dir.join("todo"))
+            .map_err(|err| anyhow!("could not read '{}': {}", dir.join("todo").display(), err))?;
+        Ok(Some(Self {
+            todo: parse_todo(&todo)?,
+            options,
+            head,
+        }))
+    }
+
+    /// Starts keeping the sequence, with `abort-safety` at its `head`.
+    pub fn save(&self) -> anyhow::Result<()> {
+        let dir = Self::dir();
+        if dir.exists() {
+            Err(anyhow!("a cherry-pick or revert is already in progress"))?
+        }
+        fs::create_dir_all(&dir)?;
+        let head = format!("{}\n", ObjectStorage::sha_to_hex_string(&self.head));
+        fs::write(dir.join("head"), &head)?;
+        fs::write(dir.join("abort-safety"), &head)?;
+        let opts = dir.join("opts");
+        if self.options.record_origin {
+            config::set_value(&opts, "options", "record-origin", Some("true"))?;
+        }
+        if let Some(mainline) = self.options.mainline {
+            let mainline = mainline.to_string();
+            config::set_value(&opts, "options", "mainline", Some(&mainline))?;
+        }
+        Self::save_todo(&self.todo)
+    }
+
+    /// Writes what is left of the todo list, as `<instruction> <abbreviated id> <subject>`.
+    pub fn save_todo(steps: &[Step]) -> anyhow::Result<()> {
+        let mut todo = String::new();
+        for step in steps {
+            let commit = GitObject::read_commit(&step.commit)?;
+            todo.push_str(&format!(
+                "{} {} {}\n",
+                step.action.name(),
+                revision::abbreviate(&step.commit, 7)?,
+                commit.message.lines().next().unwrap_or_default()
+            ));
+        }
+        fs::write(Self::dir().join("todo"), todo)?;
+        Ok(())
+    }
+
+    /// The commit the sequence last made, or `head` before it made one.
+    pub fn abort_safety() -> anyhow::Result<Option<Sha>> {
+        match fs::read_to_string(Self::dir().join("abort-safety")) {
+            Ok(content) => Ok(ObjectStorage::hex_string_to_sha(content.trim()).ok()),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err)?,
+        }
+    }
+
+    pub fn record_abort_safety(sha: &Sha) -> anyhow::Result<()> {
+        let content = format!("{}\n", ObjectStorage::sha_to_hex_string(sha));
+        fs::write(Self::dir().join("abort-safety"), content)?;
+        Ok(())
+    }
+
+    /// Forgets the sequence.
+    pub fn remove() -> anyhow::Result<()> {
+        match fs::remove_dir_all(Self::dir()) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err)?,
+        }
+    }
+}
+
+/// Parses a todo list; blank lines and `#` comments are skipped, and instructions may be
+/// abbreviated to their first letter, as git allows.
+fn parse_todo(todo: &str) -> anyhow::Result<Vec<Step>> {
+    let mut steps = vec![];
+    for (number, line) in todo.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = || anyhow!("invalid line {}: {}", number + 1, line);
+        let mut fields = line.split_whitespace();
+        let action = match fields.next() {
+            Some("pick" | "p") => Action::Pick,
+            Some("revert") => Action::Revert,
+            _ => Err(invalid())?,
+        };
+        let commit = fields
+            .next()
+            .and_then(|id| revision::resolve(id).ok())
+            .and_then(|sha| ObjectStorage::peel_to_commit(&sha).ok())
+            .ok_or_else(invalid)?;
+        steps.push(Step { action, commit });
+    }
+    Ok(steps)
+}
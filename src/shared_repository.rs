@@ -0,0 +1,149 @@
+//! `core.sharedRepository`, for a repository several users write to: the files and
+//! directories this tool creates in it get the group (or everybody) permission bits the
+//! setting asks for, whatever the writer's umask, and directories are setgid so that new
+//! files belong to the directory's group. With the default, `umask`, nothing is changed.
+
+use crate::config::Config;
+use crate::context;
+use anyhow::anyhow;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// git's `PERM_GROUP` and `PERM_EVERYBODY`: the bits added for `group` and `all`.
+const PERM_GROUP: u32 = 0o660;
+const PERM_EVERYBODY: u32 = 0o664;
+
+/// The setgid bit, which makes files created in a directory take its group.
+const DIR_SET_GID: u32 = 0o2000;
+
+/// How `core.sharedRepository` asks for files to be shared.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Sharing {
+    /// Permissions are left to the umask.
+    Umask,
+    /// These bits are added to what the umask gives.
+    Add(u32),
+    /// The permission bits are replaced by these, given in octal.
+    Exact(u32),
+}
+
+impl Sharing {
+    /// Parses a value of `core.sharedRepository` or `init --shared`: `umask` (or false),
+    /// `group` (or true), `all` (`world`, `everybody`), or an octal mode such as `0640`.
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        let sharing = match value.to_lowercase().as_str() {
+            "umask" | "false" | "no" | "off" | "0" => Self::Umask,
+            "group" | "true" | "yes" | "on" | "1" | "" => Self::Add(PERM_GROUP),
+            "all" | "world" | "everybody" | "2" => Self::Add(PERM_EVERYBODY),
+            octal => {
+                let mode = u32::from_str_radix(octal, 8)
+                    .ok()
+                    .filter(|mode| *mode <= 0o777)
+                    .ok_or_else(|| {
+                        anyhow!(
+                            "bad boolean config value '{}' for 'core.sharedrepository'",
+                            value
+                        )
+                    })?;
+                if mode & 0o600 != 0o600 {
+                    Err(anyhow!(
+                        "problem with core.sharedRepository filemode value (0{:03o}).\nThe \
+                         owner of files must always have read and write permissions.",
+                        mode
+                    ))?
+                }
+                Self::Exact(mode)
+            }
+        };
+        Ok(sharing)
+    }
+
+    /// The value git writes to the config for this setting.
+    pub fn config_value(&self) -> String {
+        match *self {
+            Self::Umask => "0".to_string(),
+            Self::Add(PERM_GROUP) => "1".to_string(),
+            Self::Add(_) => "2".to_string(),
+            Self::Exact(mode) => format!("0{:o}", mode),
+        }
+    }
+
+    /// The mode for a file or directory with the mode `mode`: read-only files stay read-only,
+    /// and wherever the owner may execute, so may those who may read.
+    fn apply(&self, mode: u32, is_dir: bool) -> u32 {
+        let mut tweak = match *self {
+            Self::Umask => return mode,
+            Self::Add(bits) | Self::Exact(bits) => bits,
+        };
+        if mode & 0o200 == 0 {
+            tweak &= !0o222;
+        }
+        if mode & 0o100 != 0 {
+            tweak |= (tweak & 0o444) >> 2;
+        }
+        let mut mode = match *self {
+            Self::Exact(_) => (mode & !0o777) | tweak,
+            _ => mode | tweak,
+        };
+        if is_dir {
+            mode |= (mode & 0o444) >> 2 | DIR_SET_GID;
+        }
+        mode
+    }
+}
+
+/// The repository's `core.sharedRepository`, read on first use.
+pub fn sharing() -> anyhow::Result<Sharing> {
+    let context = context::current();
+    if let Some(sharing) = context.sharing.get() {
+        return Ok(*sharing);
+    }
+    let sharing = match Config::get()?.value("core", "sharedRepository") {
+        Some(value) => Sharing::parse(&value)?,
+        None => Sharing::Umask,
+    };
+    Ok(*context.sharing.get_or_init(|| sharing))
+}
+
+/// Gives the file or directory at `path` the permissions `core.sharedRepository` asks for.
+pub fn adjust(path: &Path) -> anyhow::Result<()> {
+    let sharing = sharing()?;
+    if sharing == Sharing::Umask {
+        return Ok(());
+    }
+    set_mode(path, sharing)
+        .map_err(|err| anyhow!("could not set permissions of '{}': {}", path.display(), err))
+}
+
+/// Creates the directory `path` and any missing parents, sharing each one created.
+pub fn create_dir_all(path: &Path) -> anyhow::Result<()> {
+    if path.is_dir() {
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        create_dir_all(parent)?;
+    }
+    match fs::create_dir(path) {
+        Ok(()) => adjust(path),
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => Ok(()),
+        Err(err) => Err(err)?,
+    }
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, sharing: Sharing) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let metadata = fs::metadata(path)?;
+    let mode = metadata.permissions().mode() & 0o7777;
+    let shared = sharing.apply(mode, metadata.is_dir());
+    if shared != mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(shared))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _sharing: Sharing) -> io::Result<()> {
+    Ok(())
+}
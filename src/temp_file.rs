@@ -0,0 +1,118 @@
+use crate::object_storage::{ObjectStorage, Sha};
+use anyhow::anyhow;
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Characters for the random part of a temporary file name, as in `mkstemp`.
+const NAME_CHARACTERS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+
+/// How many names are tried before giving up on creating a temporary file.
+const MAX_ATTEMPTS: usize = 100;
+
+static NAME_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// A file with a unique name for handing content to another program, such as a signature to
+/// gpg or the versions of a file to a merge driver or an external diff tool. The file is
+/// removed when this is dropped, unless it is kept.
+pub struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    /// Creates `<prefix>XXXXXX<suffix>` in `dir`, readable only by the user, with `content`.
+    /// The suffix keeps a file's extension for tools that go by it.
+    pub fn create(
+        dir: &Path,
+        prefix: &str,
+        suffix: &str,
+        content: &mut impl Read,
+    ) -> anyhow::Result<Self> {
+        let mut attempts = 0;
+        let (path, mut file) = loop {
+            let path = dir.join(format!("{}{}{}", prefix, random_part(), suffix));
+            match open_new(&path) {
+                Ok(file) => break (path, file),
+                Err(err) if err.kind() == ErrorKind::AlreadyExists && attempts < MAX_ATTEMPTS => {
+                    attempts += 1;
+                }
+                Err(err) => Err(anyhow!("unable to create temporary file: {}", err))?,
+            }
+        };
+        let temp_file = Self { path };
+        io::copy(content, &mut file)
+            .map_err(|err| anyhow!("unable to write temporary file: {}", err))?;
+        Ok(temp_file)
+    }
+
+    /// Writes the content of the blob `sha` to a new `<prefix>XXXXXX<suffix>` in `dir`.
+    pub fn from_blob(sha: &Sha, dir: &Path, prefix: &str, suffix: &str) -> anyhow::Result<Self> {
+        let invalid = || {
+            anyhow!(
+                "unable to read blob object {}",
+                ObjectStorage::sha_to_hex_string(sha)
+            )
+        };
+        let mut blob = ObjectStorage::open_object(sha).map_err(|_| invalid())?;
+        if blob.object_type != "blob" {
+            Err(invalid())?
+        }
+        Self::create(dir, prefix, suffix, &mut blob)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Leaves the file in place for the caller, returning its path.
+    pub fn keep(self) -> PathBuf {
+        let path = self.path.clone();
+        std::mem::forget(self);
+        path
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(unix)]
+fn open_new(path: &Path) -> io::Result<fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+fn open_new(path: &Path) -> io::Result<fs::File> {
+    OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+/// Six characters that differ between processes and between calls: a name taken already is
+/// simply followed by another one.
+pub fn random_part() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_nanos() as u64);
+    let counter = NAME_COUNTER.fetch_add(1, Ordering::Relaxed);
+    // xorshift would stay at zero, hence the set low bit.
+    let mut seed =
+        (nanos ^ ((process::id() as u64) << 32) ^ counter.wrapping_mul(0x9e37_79b9_7f4a_7c15)) | 1;
+    (0..6)
+        .map(|_| {
+            // xorshift, enough to spread the seed over the name.
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            NAME_CHARACTERS[(seed % NAME_CHARACTERS.len() as u64) as usize] as char
+        })
+        .collect()
+}
@@ -0,0 +1,461 @@
+//! git's trace2 event format: with `GIT_TRACE2_EVENT` set, the command writes a JSON line per
+//! event to the target it names, as git does. These events are the command starting and
+//! exiting, its name and aliases, regions of work and data points within them, and the
+//! child processes it runs. Tools that already collect git's trace2 telemetry can then
+//! read this tool's events alongside git's.
+//!
+//! Like git, the target is `1` or `2` for stderr, or an absolute path. Events are appended to
+//! a file, and a directory gets a file per process, named by the session id. `0`, `false` or
+//! an empty value turns tracing off. `GIT_TRACE2_EVENT_BRIEF` leaves out the source file
+//! and line of each event. Child processes see `GIT_TRACE2_PARENT_SID`, so that their
+//! session ids nest under their parent's, as git's do.
+//!
+//! Nothing is written unless the program calls [`start`], as the `codecrafters-git` command
+//! does, so the library stays quiet. Commands end with a non-zero code by failing with a
+//! [`CommandExit`](crate::CommandExit), which the program reports through [`report_exit`]
+//! before the process exits, and which a command run from the library returns.
+
+use crate::object_storage::{self, ObjectStorage};
+use std::cell::RefCell;
+use std::env;
+use std::fmt::Write as _;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::panic::Location;
+use std::path::Path;
+use std::process::{self, Command, ExitStatus};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// The version of the event format, as git reports it.
+const EVENT_FORMAT_VERSION: &str = "3";
+
+struct Trace {
+    out: Mutex<Box<dyn Write + Send>>,
+    sid: String,
+    /// The command names down to this one, once [`cmd_name`] knows it.
+    hierarchy: Mutex<Option<String>>,
+    brief: bool,
+    started: Instant,
+}
+
+static TRACE: OnceLock<Option<Trace>> = OnceLock::new();
+static CHILD_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// When each region this thread is in was entered, innermost last.
+    static REGIONS: RefCell<Vec<Instant>> = const { RefCell::new(vec![]) };
+}
+
+/// Opens the target `GIT_TRACE2_EVENT` names and writes the `version` and `start` events for
+/// the command line `argv`.
+#[track_caller]
+pub fn start(argv: &[String]) {
+    let started = Instant::now();
+    let Some(target) = env::var_os("GIT_TRACE2_EVENT") else {
+        TRACE.get_or_init(|| None);
+        return;
+    };
+    let sid = match env::var("GIT_TRACE2_PARENT_SID") {
+        Ok(parent) if !parent.is_empty() => format!("{}/{}", parent, session_id()),
+        _ => session_id(),
+    };
+    let out = match open_target(Path::new(&target), &sid) {
+        Ok(out) => out,
+        Err(err) => {
+            eprintln!(
+                "warning: trace2: could not open '{}' for 'GIT_TRACE2_EVENT' tracing: {}",
+                target.to_string_lossy(),
+                err
+            );
+            None
+        }
+    };
+    let Some(out) = out else {
+        TRACE.get_or_init(|| None);
+        return;
+    };
+    let brief = env::var("GIT_TRACE2_EVENT_BRIEF")
+        .is_ok_and(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes" | "on"));
+    TRACE.get_or_init(|| {
+        Some(Trace {
+            out: Mutex::new(out),
+            sid,
+            hierarchy: Mutex::new(None),
+            brief,
+            started,
+        })
+    });
+    event("version", |fields| {
+        fields.string("evt", EVENT_FORMAT_VERSION);
+        fields.string("exe", env!("CARGO_PKG_VERSION"));
+    });
+    event("start", |fields| {
+        fields.seconds("t_abs", elapsed());
+        fields.strings("argv", argv);
+    });
+}
+
+/// The target for the value of `GIT_TRACE2_EVENT`, or `None` when tracing is off.
+fn open_target(target: &Path, sid: &str) -> io::Result<Option<Box<dyn Write + Send>>> {
+    let value = target.to_string_lossy();
+    match value.to_lowercase().as_str() {
+        "" | "0" | "false" | "no" | "off" => return Ok(None),
+        "1" | "2" | "true" | "yes" | "on" => return Ok(Some(Box::new(io::stderr()))),
+        _ => {}
+    }
+    if !target.is_absolute() {
+        eprintln!(
+            "warning: trace2: unknown value for 'GIT_TRACE2_EVENT': '{}'",
+            value
+        );
+        return Ok(None);
+    }
+    let path = if target.is_dir() {
+        // A nested session id has its parents' in front; the file is named by the last.
+        target.join(sid.rsplit('/').next().unwrap_or(sid))
+    } else {
+        target.to_path_buf()
+    };
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    Ok(Some(Box::new(file)))
+}
+
+/// A session id as git makes them: when the process started, a hash of the host name and the
+/// process id.
+fn session_id() -> String {
+    let host = ObjectStorage::hash_content(host_name().as_bytes());
+    let host: String = host[..4]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!(
+        "{}-H{}-P{:08x}",
+        timestamp(SystemTime::now(), true),
+        host,
+        process::id()
+    )
+}
+
+#[cfg(unix)]
+fn host_name() -> String {
+    let mut name = [0u8; 256];
+    // SAFETY: the buffer is as long as the length passed.
+    if unsafe { libc::gethostname(name.as_mut_ptr().cast(), name.len()) } != 0 {
+        return "localhost".to_string();
+    }
+    let length = name
+        .iter()
+        .position(|&byte| byte == 0)
+        .unwrap_or(name.len());
+    String::from_utf8_lossy(&name[..length]).into_owned()
+}
+
+#[cfg(not(unix))]
+fn host_name() -> String {
+    env::var("COMPUTERNAME").unwrap_or_else(|_| "localhost".to_string())
+}
+
+/// `time` in UTC with microseconds, as `2024-01-31T12:34:56.123456Z`, or without separators
+/// for session ids.
+fn timestamp(time: SystemTime, compact: bool) -> String {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since.as_secs() as i64;
+    let (year, month, day) = object_storage::civil_from_days(seconds.div_euclid(86400));
+    let of_day = seconds.rem_euclid(86400);
+    let (hour, minute, second) = (of_day / 3600, of_day / 60 % 60, of_day % 60);
+    let micros = since.subsec_micros();
+    if compact {
+        format!(
+            "{:04}{:02}{:02}T{:02}{:02}{:02}.{:06}Z",
+            year, month, day, hour, minute, second, micros
+        )
+    } else {
+        format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+            year, month, day, hour, minute, second, micros
+        )
+    }
+}
+
+fn trace() -> Option<&'static Trace> {
+    TRACE.get()?.as_ref()
+}
+
+fn elapsed() -> f64 {
+    trace().map_or(0.0, |trace| trace.started.elapsed().as_secs_f64())
+}
+
+/// Reports the command's name once aliases are resolved. Its hierarchy is the names of the
+/// commands of this tool above it, which children learn from `GIT_TRACE2_PARENT_NAME`.
+#[track_caller]
+pub fn cmd_name(name: &str) {
+    let Some(trace) = trace() else {
+        return;
+    };
+    let hierarchy = match env::var("GIT_TRACE2_PARENT_NAME") {
+        Ok(parent) if !parent.is_empty() => format!("{}/{}", parent, name),
+        _ => name.to_string(),
+    };
+    *trace.hierarchy.lock().unwrap() = Some(hierarchy.clone());
+    event("cmd_name", |fields| {
+        fields.string("name", name);
+        fields.string("hierarchy", &hierarchy);
+    });
+}
+
+/// Reports that the alias `name` expanded to `expansion`.
+#[track_caller]
+pub fn alias(name: &str, expansion: &[String]) {
+    event("alias", |fields| {
+        fields.string("alias", name);
+        fields.strings("argv", expansion);
+    });
+}
+
+/// Reports the repository the command works in.
+#[track_caller]
+pub(crate) fn def_repo(work_tree: Option<&Path>) {
+    event("def_repo", |fields| {
+        fields.number("repo", 1);
+        if let Some(work_tree) = work_tree {
+            let work_tree = std::path::absolute(work_tree).unwrap_or(work_tree.to_path_buf());
+            fields.string("worktree", &work_tree.to_string_lossy());
+        }
+    });
+}
+
+/// Reports the error the command is about to exit with.
+#[track_caller]
+pub fn error(message: &str) {
+    event("error", |fields| {
+        fields.string("msg", message);
+        fields.string("fmt", "%s");
+    });
+}
+
+/// Reports the exit code and the objects read and written, for a process about to exit.
+#[track_caller]
+pub fn report_exit(code: i32) {
+    if trace().is_some() {
+        let (read, written) = crate::usage::object_counts();
+        data("objects", "read", read);
+        data("objects", "written", written);
+        for name in ["exit", "atexit"] {
+            event(name, |fields| {
+                fields.seconds("t_abs", elapsed());
+                fields.number("code", code as i64);
+            });
+        }
+    }
+}
+
+/// Runs `work` as the region `label` of `category`, reporting when it is entered and left;
+/// `message` says more about what it works on.
+#[track_caller]
+pub(crate) fn region<T>(
+    category: &str,
+    label: &str,
+    message: Option<&str>,
+    work: impl FnOnce() -> T,
+) -> T {
+    if trace().is_none() {
+        return work();
+    }
+    let location = Location::caller();
+    let nesting = REGIONS.with_borrow_mut(|regions| {
+        regions.push(Instant::now());
+        regions.len()
+    });
+    let describe = |fields: &mut Fields| {
+        fields.number("nesting", nesting as i64);
+        fields.string("category", category);
+        fields.string("label", label);
+        if let Some(message) = message {
+            fields.string("msg", message);
+        }
+    };
+    write_event("region_enter", location, describe);
+    let result = work();
+    let entered = REGIONS.with_borrow_mut(|regions| regions.pop());
+    write_event("region_leave", location, |fields| {
+        fields.seconds(
+            "t_rel",
+            entered.map_or(0.0, |at| at.elapsed().as_secs_f64()),
+        );
+        describe(fields);
+    });
+    result
+}
+
+/// Reports `value` for `key` of `category`, within the region the thread is in.
+#[track_caller]
+pub(crate) fn data(category: &str, key: &str, value: impl ToString) {
+    if trace().is_none() {
+        return;
+    }
+    let (nesting, entered) =
+        REGIONS.with_borrow(|regions| (regions.len(), regions.last().copied()));
+    event("data", |fields| {
+        let t_abs = elapsed();
+        fields.seconds("t_abs", t_abs);
+        fields.seconds(
+            "t_rel",
+            entered.map_or(t_abs, |at| at.elapsed().as_secs_f64()),
+        );
+        fields.number("nesting", nesting as i64 + 1);
+        fields.string("category", category);
+        fields.string("key", key);
+        fields.string("value", &value.to_string());
+    });
+}
+
+/// Passes this session's id and command hierarchy to the child process `command` in
+/// `GIT_TRACE2_PARENT_SID` and `GIT_TRACE2_PARENT_NAME`, so that its events nest under this
+/// command's. Nothing changes while tracing is off.
+pub fn child_env(command: &mut Command) {
+    let Some(trace) = trace() else {
+        return;
+    };
+    command.env("GIT_TRACE2_PARENT_SID", &trace.sid);
+    if let Some(hierarchy) = &*trace.hierarchy.lock().unwrap() {
+        command.env("GIT_TRACE2_PARENT_NAME", hierarchy);
+    }
+}
+
+/// Runs `command` and waits for it like [`Command::status`], reporting it as a child of
+/// the class `class`, such as `hook` or `editor`; hooks are named by `hook_name`.
+#[track_caller]
+pub fn status(
+    command: &mut Command,
+    class: &str,
+    hook_name: Option<&str>,
+) -> io::Result<ExitStatus> {
+    if trace().is_none() {
+        return command.status();
+    }
+    child_env(command);
+    let location = Location::caller();
+    let child_id = CHILD_ID.fetch_add(1, Ordering::Relaxed);
+    let program = command.get_program().to_string_lossy().into_owned();
+    let mut argv = vec![program.clone()];
+    argv.extend(
+        command
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned()),
+    );
+    write_event("child_start", location, |fields| {
+        fields.number("child_id", child_id as i64);
+        fields.string("child_class", class);
+        fields.boolean("use_shell", program == "sh");
+        if let Some(hook_name) = hook_name {
+            fields.string("hook_name", hook_name);
+        }
+        fields.strings("argv", &argv);
+    });
+    let started = Instant::now();
+    let (pid, result) = match command.spawn() {
+        Ok(mut child) => (child.id() as i64, child.wait()),
+        Err(err) => (-1, Err(err)),
+    };
+    write_event("child_exit", location, |fields| {
+        fields.number("child_id", child_id as i64);
+        fields.number("pid", pid);
+        let code = result.as_ref().ok().and_then(|status| status.code());
+        fields.number("code", code.map_or(-1, i64::from));
+        fields.seconds("t_rel", started.elapsed().as_secs_f64());
+    });
+    result
+}
+
+#[track_caller]
+fn event(name: &str, add_fields: impl FnOnce(&mut Fields)) {
+    write_event(name, Location::caller(), add_fields);
+}
+
+/// Writes the event `name` with the fields every event has and those `add_fields` adds, as
+/// one line so that processes sharing a file do not interleave.
+fn write_event(name: &str, location: &Location, add_fields: impl FnOnce(&mut Fields)) {
+    let Some(trace) = trace() else {
+        return;
+    };
+    let mut fields = Fields(String::from("{"));
+    fields.string("event", name);
+    fields.string("sid", &trace.sid);
+    fields.string("thread", std::thread::current().name().unwrap_or("thread"));
+    if !trace.brief {
+        fields.string("time", &timestamp(SystemTime::now(), false));
+        fields.string("file", location.file());
+        fields.number("line", location.line() as i64);
+    }
+    add_fields(&mut fields);
+    let mut line = fields.0;
+    line.push_str("}\n");
+    let mut out = trace.out.lock().unwrap();
+    // Tracing must not make the command fail.
+    let _ = out.write_all(line.as_bytes()).and_then(|()| out.flush());
+}
+
+/// The fields of a JSON object being written.
+struct Fields(String);
+
+impl Fields {
+    fn key(&mut self, key: &str) {
+        if self.0.len() > 1 {
+            self.0.push(',');
+        }
+        push_json_string(&mut self.0, key);
+        self.0.push(':');
+    }
+
+    fn string(&mut self, key: &str, value: &str) {
+        self.key(key);
+        push_json_string(&mut self.0, value);
+    }
+
+    fn strings(&mut self, key: &str, values: &[String]) {
+        self.key(key);
+        self.0.push('[');
+        for (i, value) in values.iter().enumerate() {
+            if i > 0 {
+                self.0.push(',');
+            }
+            push_json_string(&mut self.0, value);
+        }
+        self.0.push(']');
+    }
+
+    fn number(&mut self, key: &str, value: i64) {
+        self.key(key);
+        let _ = write!(self.0, "{}", value);
+    }
+
+    fn seconds(&mut self, key: &str, value: f64) {
+        self.key(key);
+        let _ = write!(self.0, "{:.6}", value);
+    }
+
+    fn boolean(&mut self, key: &str, value: bool) {
+        self.key(key);
+        self.0.push_str(if value { "true" } else { "false" });
+    }
+}
+
+fn push_json_string(out: &mut String, value: &str) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
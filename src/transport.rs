@@ -0,0 +1,548 @@
+use crate::cancel;
+use crate::config::Config;
+use crate::object_storage::{ObjectStorage, Sha};
+use crate::progress::{self, Phase, Progress};
+use anyhow::anyhow;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
+
+const USER_AGENT: &str = "git/codecrafters-git";
+const DEFAULT_CONNECT_TIMEOUT: u64 = 30;
+const DEFAULT_LOW_SPEED_TIME: u64 = 120;
+const GZIP_REQUEST_THRESHOLD: usize = 1024;
+const UPLOAD_PACK: &str = "git-upload-pack";
+const RECEIVE_PACK: &str = "git-receive-pack";
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// What a remote advertised in response to ref discovery.
+pub struct RemoteRefs {
+    pub refs: Vec<(String, Sha)>,
+    pub capabilities: Vec<String>,
+    /// The branch `HEAD` points to, from the `symref=HEAD:<ref>` capability.
+    pub head: Option<String>,
+}
+
+/// Asks the smart HTTP server at `url` which refs it has (`GET info/refs`).
+pub fn discover_refs(url: &str) -> anyhow::Result<RemoteRefs> {
+    parse_advertisement(url, &request_advertisement(url, UPLOAD_PACK)?, UPLOAD_PACK)
+}
+
+/// Like [`discover_refs`], but asks the service that accepts pushes, which advertises its
+/// own capabilities.
+pub fn discover_push_refs(url: &str) -> anyhow::Result<RemoteRefs> {
+    parse_advertisement(
+        url,
+        &request_advertisement(url, RECEIVE_PACK)?,
+        RECEIVE_PACK,
+    )
+}
+
+/// Like [`discover_refs`], but answers from the advertisement saved at `cache` as long as it
+/// is younger than `max_age`, which saves a round trip to the server. A fresh advertisement
+/// is saved there for next time. Also returns whether the cached one was used.
+pub fn discover_refs_cached(
+    url: &str,
+    cache: &Path,
+    max_age: Duration,
+) -> anyhow::Result<(RemoteRefs, bool)> {
+    let age = fs::metadata(cache)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok());
+    if age.is_some_and(|age| age < max_age)
+        && let Ok(body) = fs::read(cache)
+        && let Ok(remote) = parse_advertisement(url, &body, UPLOAD_PACK)
+    {
+        return Ok((remote, true));
+    }
+    let body = request_advertisement(url, UPLOAD_PACK)?;
+    let remote = parse_advertisement(url, &body, UPLOAD_PACK)?;
+    if let Some(dir) = cache.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(cache, &body)?;
+    Ok((remote, false))
+}
+
+fn request_advertisement(url: &str, service: &str) -> anyhow::Result<Vec<u8>> {
+    let discovery_url = format!("{}/info/refs?service={}", url, service);
+    let response = http_agent()?
+        .get(&discovery_url)
+        .set("User-Agent", USER_AGENT)
+        .call()
+        .map_err(|err| anyhow!("unable to access '{}': {}", url, err))?;
+    if response.content_type() != format!("application/x-{}-advertisement", service) {
+        Err(anyhow!(
+            "'{}' does not speak the smart HTTP protocol (got {})",
+            url,
+            response.content_type()
+        ))?
+    }
+    read_body(response, false)
+}
+
+fn parse_advertisement(url: &str, body: &[u8], service: &str) -> anyhow::Result<RemoteRefs> {
+    let mut reader = body;
+    let first_line = read_pkt_line(&mut reader)?.unwrap_or_default();
+    if first_line.strip_suffix(b"\n").unwrap_or(&first_line)
+        != format!("# service={}", service).as_bytes()
+    {
+        Err(anyhow!("invalid ref advertisement from '{}'", url))?
+    }
+    read_pkt_line(&mut reader)?;
+
+    let mut refs = vec![];
+    let mut capabilities = vec![];
+    while let Some(line) = read_pkt_line(&mut reader)? {
+        let line = String::from_utf8(line)?;
+        let line = line.trim_end_matches('\n');
+        let line = match line.split_once('\0') {
+            Some((line, advertised)) => {
+                capabilities = advertised.split(' ').map(str::to_string).collect();
+                line
+            }
+            None => line,
+        };
+        let (sha, name) = line
+            .split_once(' ')
+            .ok_or(anyhow!("invalid ref line '{}'", line))?;
+        // An empty repository advertises only its capabilities.
+        if name != "capabilities^{}" {
+            refs.push((name.to_string(), ObjectStorage::hex_string_to_sha(sha)?));
+        }
+    }
+    let head = capabilities
+        .iter()
+        .find_map(|capability| capability.strip_prefix("symref=HEAD:"))
+        .map(str::to_string);
+    Ok(RemoteRefs {
+        refs,
+        capabilities,
+        head,
+    })
+}
+
+/// Requests a pack containing `wants` and everything they reference (`POST git-upload-pack`)
+/// and returns the raw packfile. `haves` are sent in the same request, so the server leaves
+/// out what they already reference. Progress messages from the remote go to the progress
+/// reporter, which prints them to stderr by default.
+pub fn fetch_pack(
+    url: &str,
+    remote: &RemoteRefs,
+    wants: &[Sha],
+    haves: &[Sha],
+) -> anyhow::Result<Vec<u8>> {
+    let supports = |name: &str| remote.capabilities.iter().any(|c| c == name);
+    let side_band = supports("side-band-64k");
+    let mut requested: Vec<&str> = vec![];
+    if side_band {
+        requested.push("side-band-64k");
+    }
+    if supports("ofs-delta") {
+        requested.push("ofs-delta");
+    }
+    let agent = format!("agent={}", USER_AGENT);
+    requested.push(&agent);
+
+    let mut request = vec![];
+    for (i, want) in wants.iter().enumerate() {
+        let mut line = format!("want {}", ObjectStorage::sha_to_hex_string(want));
+        if i == 0 {
+            line.push(' ');
+            line.push_str(&requested.join(" "));
+        }
+        line.push('\n');
+        request.extend(pkt_line(line.as_bytes()));
+    }
+    request.extend_from_slice(b"0000");
+    for have in haves {
+        let line = format!("have {}\n", ObjectStorage::sha_to_hex_string(have));
+        request.extend(pkt_line(line.as_bytes()));
+    }
+    request.extend(pkt_line(b"done\n"));
+
+    let mut post = http_agent()?
+        .post(&format!("{}/git-upload-pack", url))
+        .set("User-Agent", USER_AGENT)
+        .set("Content-Type", "application/x-git-upload-pack-request")
+        .set("Accept", "application/x-git-upload-pack-result");
+    // Like git, compress all but tiny negotiation requests; long want/have lists shrink a lot.
+    // Gzipped responses are decoded by ureq, which advertises Accept-Encoding: gzip.
+    if request.len() > GZIP_REQUEST_THRESHOLD {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(&request)?;
+        request = encoder.finish()?;
+        post = post.set("Content-Encoding", "gzip");
+    }
+    let response = post
+        .send_bytes(&request)
+        .map_err(|err| anyhow!("unable to fetch from '{}': {}", url, err))?;
+    let body = read_body(response, true)?;
+    let mut reader = body.as_slice();
+    // A NAK when none of the haves are common, otherwise an ACK for each common one.
+    let acknowledgement = read_pkt_line(&mut reader)?.unwrap_or_default();
+    if !acknowledgement.starts_with(b"NAK") && !acknowledgement.starts_with(b"ACK") {
+        Err(anyhow!(
+            "unexpected response to fetch: {}",
+            String::from_utf8_lossy(&acknowledgement)
+        ))?
+    }
+    if acknowledgement.starts_with(b"ACK") {
+        loop {
+            let mut next = reader;
+            match read_pkt_line(&mut next)? {
+                Some(line) if line.starts_with(b"ACK") => reader = next,
+                _ => break,
+            }
+        }
+    }
+    if !side_band {
+        return Ok(reader.to_vec());
+    }
+    demultiplex(&mut reader)
+}
+
+/// One ref update sent by [`send_pack`].
+pub struct RefUpdate {
+    pub name: String,
+    /// What the remote had, all zeros when creating the ref.
+    pub old: Sha,
+    /// What it is to have, all zeros when deleting it.
+    pub new: Sha,
+}
+
+/// Asks the remote to update refs (`POST git-receive-pack`), sending `pack` with the objects
+/// they need, and returns for each ref the reason the remote gave for refusing it, or `None`
+/// when it was updated. An empty `pack` is left out, which only works when every update is a
+/// deletion. Messages from the remote's hooks go to the progress reporter.
+pub fn send_pack(
+    url: &str,
+    remote: &RemoteRefs,
+    updates: &[RefUpdate],
+    pack: &[u8],
+) -> anyhow::Result<Vec<(String, Option<String>)>> {
+    let supports = |name: &str| remote.capabilities.iter().any(|c| c == name);
+    let side_band = supports("side-band-64k");
+    let mut requested = vec!["report-status"];
+    if side_band {
+        requested.push("side-band-64k");
+    }
+    let agent = format!("agent={}", USER_AGENT);
+    requested.push(&agent);
+
+    let mut request = vec![];
+    for (i, update) in updates.iter().enumerate() {
+        let mut line = format!(
+            "{} {} {}",
+            ObjectStorage::sha_to_hex_string(&update.old),
+            ObjectStorage::sha_to_hex_string(&update.new),
+            update.name
+        );
+        if i == 0 {
+            line.push('\0');
+            line.push_str(&requested.join(" "));
+        }
+        line.push('\n');
+        request.extend(pkt_line(line.as_bytes()));
+    }
+    request.extend_from_slice(b"0000");
+    request.extend_from_slice(pack);
+
+    let response = http_agent()?
+        .post(&format!("{}/git-receive-pack", url))
+        .set("User-Agent", USER_AGENT)
+        .set("Content-Type", "application/x-git-receive-pack-request")
+        .set("Accept", "application/x-git-receive-pack-result")
+        .send_bytes(&request)
+        .map_err(|err| anyhow!("unable to push to '{}': {}", url, err))?;
+    let body = read_body(response, false)?;
+    let mut reader = body.as_slice();
+    let report = if side_band {
+        demultiplex(&mut reader)?
+    } else {
+        reader.to_vec()
+    };
+    let mut reader = report.as_slice();
+    let unpack = read_pkt_line(&mut reader)?.unwrap_or_default();
+    let unpack = String::from_utf8_lossy(&unpack);
+    match unpack.trim_end().strip_prefix("unpack ") {
+        Some("ok") => (),
+        Some(error) => Err(anyhow!("unpack failed: {}", error))?,
+        None => Err(anyhow!("invalid status from remote: {}", unpack.trim_end()))?,
+    }
+    let mut statuses = vec![];
+    while let Some(line) = read_pkt_line(&mut reader)? {
+        let line = String::from_utf8(line)?;
+        let line = line.trim_end_matches('\n');
+        if let Some(name) = line.strip_prefix("ok ") {
+            statuses.push((name.to_string(), None));
+        } else if let Some(rest) = line.strip_prefix("ng ") {
+            let (name, reason) = rest.split_once(' ').unwrap_or((rest, "failed"));
+            statuses.push((name.to_string(), Some(reason.to_string())));
+        } else {
+            Err(anyhow!("invalid ref status from remote: {}", line))?
+        }
+    }
+    Ok(statuses)
+}
+
+/// Collects the data sent on side-band channel 1 until the flush packet, passing the
+/// progress messages of channel 2 to the progress reporter; channel 3 is a fatal error.
+/// A message line may be split across packets, so the reporter gets whole lines only, each
+/// with its `\r` or `\n`; an unterminated last line is finished with a `\n`.
+fn demultiplex(reader: &mut &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut data = vec![];
+    let mut partial_line = vec![];
+    while let Some(packet) = read_pkt_line(reader)? {
+        match packet.split_first() {
+            // upload-pack sends empty packets as keepalives while it is still counting and
+            // compressing objects.
+            None => (),
+            Some((1, payload)) => data.extend_from_slice(payload),
+            Some((2, message)) => {
+                partial_line.extend_from_slice(message);
+                while let Some(end) = partial_line.iter().position(|&b| b == b'\r' || b == b'\n') {
+                    let line: Vec<u8> = partial_line.drain(..=end).collect();
+                    report_remote_message(&line);
+                }
+            }
+            Some((3, message)) => {
+                finish_remote_message(&mut partial_line);
+                Err(anyhow!(
+                    "remote error: {}",
+                    String::from_utf8_lossy(message).trim_end()
+                ))?
+            }
+            _ => Err(anyhow!("invalid side-band packet"))?,
+        }
+    }
+    finish_remote_message(&mut partial_line);
+    Ok(data)
+}
+
+/// Reports what is left of a message line once the remote stops sending, ending it.
+fn finish_remote_message(partial_line: &mut Vec<u8>) {
+    if !partial_line.is_empty() {
+        partial_line.push(b'\n');
+        report_remote_message(partial_line);
+        partial_line.clear();
+    }
+}
+
+fn report_remote_message(line: &[u8]) {
+    let line = String::from_utf8_lossy(line);
+    progress::report(|reporter| reporter.remote_message(&line));
+}
+
+/// An HTTP agent with the connect timeout from `http.connectTimeout` and the stall timeout
+/// from `http.lowSpeedTime` (or `GIT_HTTP_LOW_SPEED_TIME`), both in seconds: a transfer that
+/// receives nothing for that long is aborted instead of hanging.
+fn http_agent() -> anyhow::Result<ureq::Agent> {
+    let seconds = |value: Option<String>, name: &str, default: u64| match value {
+        Some(value) => value
+            .trim()
+            .parse::<u64>()
+            .map_err(|_| anyhow!("invalid {} '{}': expected seconds", name, value)),
+        None => Ok(default),
+    };
+    let config = Config::get()?;
+    let connect_timeout = seconds(
+        config.value("http", "connectTimeout"),
+        "http.connectTimeout",
+        DEFAULT_CONNECT_TIMEOUT,
+    )?;
+    let low_speed_time = seconds(
+        std::env::var("GIT_HTTP_LOW_SPEED_TIME")
+            .ok()
+            .or(config.value("http", "lowSpeedTime")),
+        "http.lowSpeedTime",
+        DEFAULT_LOW_SPEED_TIME,
+    )?;
+    Ok(ureq::AgentBuilder::new()
+        .timeout_connect(Duration::from_secs(connect_timeout))
+        .timeout_read(Duration::from_secs(low_speed_time))
+        .build())
+}
+
+/// Reads a response body in chunks so an interrupt (see [`InterruptGuard`]) stops the
+/// transfer between chunks. With `report_progress`, the bytes received so far are reported as
+/// [`Phase::ReceivingPack`].
+fn read_body(response: ureq::Response, report_progress: bool) -> anyhow::Result<Vec<u8>> {
+    let mut reader = response.into_reader();
+    let mut body = vec![];
+    let mut chunk = vec![0; 64 * 1024];
+    loop {
+        if interrupted() {
+            Err(anyhow!("interrupted"))?
+        }
+        cancel::check()?;
+        match reader.read(&mut chunk) {
+            Ok(0) => {
+                if report_progress {
+                    progress::report(|reporter| reporter.finish(Phase::ReceivingPack));
+                }
+                return Ok(body);
+            }
+            Ok(n) => {
+                body.extend_from_slice(&chunk[..n]);
+                if report_progress {
+                    let progress = Progress {
+                        phase: Phase::ReceivingPack,
+                        done: 0,
+                        total: None,
+                        bytes: Some(body.len() as u64),
+                    };
+                    progress::report(|reporter| reporter.progress(&progress));
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) if err.kind() == io::ErrorKind::TimedOut => Err(anyhow!(
+                "transfer stalled: no data received within the http.lowSpeedTime limit"
+            ))?,
+            Err(err) => Err(err)?,
+        }
+    }
+}
+
+/// Whether Ctrl-C was pressed while an [`InterruptGuard`] was active.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// While alive, SIGINT only sets a flag (and interrupts blocking reads) instead of killing the
+/// process, so a cancelled network operation can return an error and clean up after itself.
+/// The previous handler is restored on drop.
+pub struct InterruptGuard {
+    #[cfg(unix)]
+    previous: libc::sigaction,
+}
+
+#[cfg(unix)]
+extern "C" fn handle_interrupt(_signal: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+impl InterruptGuard {
+    #[cfg(unix)]
+    pub fn new() -> Self {
+        // SAFETY: the handler only stores to an atomic, which is async-signal-safe, and both
+        // sigaction structs are valid for the duration of the call. SA_RESTART is left out so
+        // blocking reads return EINTR.
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handle_interrupt as *const () as usize;
+            libc::sigemptyset(&mut action.sa_mask);
+            let mut previous: libc::sigaction = std::mem::zeroed();
+            libc::sigaction(libc::SIGINT, &action, &mut previous);
+            Self { previous }
+        }
+    }
+
+    #[cfg(not(unix))]
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+#[cfg(unix)]
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        // SAFETY: restores the handler saved in `new`.
+        unsafe {
+            libc::sigaction(libc::SIGINT, &self.previous, std::ptr::null_mut());
+        }
+    }
+}
+
+/// Frames `data` as a pkt-line: four hex digits of total length, then the payload.
+pub fn pkt_line(data: &[u8]) -> Vec<u8> {
+    let mut line = format!("{:04x}", data.len() + 4).into_bytes();
+    line.extend_from_slice(data);
+    line
+}
+
+/// Reads one pkt-line; `None` marks a flush packet (`0000`).
+pub fn read_pkt_line(reader: &mut impl Read) -> anyhow::Result<Option<Vec<u8>>> {
+    let mut length = [0u8; 4];
+    reader
+        .read_exact(&mut length)
+        .map_err(|_| anyhow!("unexpected end of pkt-line stream"))?;
+    let length = usize::from_str_radix(std::str::from_utf8(&length)?, 16)
+        .map_err(|_| anyhow!("invalid pkt-line length"))?;
+    if length == 0 {
+        return Ok(None);
+    }
+    let mut data = vec![
+        0;
+        length
+            .checked_sub(4)
+            .ok_or(anyhow!("invalid pkt-line length"))?
+    ];
+    reader.read_exact(&mut data)?;
+    Ok(Some(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::{self, Context};
+    use crate::progress::ProgressReporter;
+    use std::path::PathBuf;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Default)]
+    struct Messages(Mutex<Vec<String>>);
+
+    impl ProgressReporter for Messages {
+        fn remote_message(&self, message: &str) {
+            self.0.lock().unwrap().push(message.to_string());
+        }
+    }
+
+    fn demultiplex_messages(packets: &[&[u8]]) -> (Vec<u8>, Vec<String>) {
+        let mut stream = vec![];
+        for packet in packets {
+            stream.extend(pkt_line(packet));
+        }
+        stream.extend(b"0000");
+        let messages = Arc::new(Messages::default());
+        let context = Context::new(PathBuf::from(".git"), None);
+        *context.reporter.write().unwrap() = Some(messages.clone());
+        let data = context::enter(context, || demultiplex(&mut stream.as_slice())).unwrap();
+        let messages = messages.0.lock().unwrap().clone();
+        (data, messages)
+    }
+
+    #[test]
+    fn joins_message_lines_split_across_packets() {
+        let (data, messages) = demultiplex_messages(&[
+            b"\x02Counting obj",
+            b"\x01PACK",
+            b"\x02ects: 1\r",
+            b"\x02Counting objects: 2\rdone.\nTotal",
+            b"\x02 2\n",
+        ]);
+        assert_eq!(data, b"PACK");
+        assert_eq!(
+            messages,
+            [
+                "Counting objects: 1\r",
+                "Counting objects: 2\r",
+                "done.\n",
+                "Total 2\n"
+            ]
+        );
+    }
+
+    #[test]
+    fn ends_an_unterminated_last_message_line() {
+        let (_, messages) = demultiplex_messages(&[b"\x02first\nsecond"]);
+        assert_eq!(messages, ["first\n", "second\n"]);
+    }
+}
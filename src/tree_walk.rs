@@ -0,0 +1,122 @@
+//! A depth-first walk over a tree and the trees in it, in the order git stores the entries:
+//! each subtree comes right before what is in it. Subtrees are only read when the walk gets
+//! to their content, so one that is skipped, by the filter or by the caller, costs nothing.
+
+use crate::context::{self, Context};
+use crate::object_storage::{GitObject, Oid, Sha, TreeEntry, TreeEntryPermission};
+use std::sync::Arc;
+use std::vec;
+
+/// Whether to yield an entry, and walk into it if it is a subtree, given its path.
+type Filter<'a> = Box<dyn FnMut(&str, &TreeEntry) -> bool + 'a>;
+
+/// The entries of a tree and of every tree in it, each with its path from the root, such as
+/// `src/main.rs`, from [`Repository::tree_walk`](crate::Repository::tree_walk).
+///
+/// ```
+/// use codecrafters_git::{Repository, Tree, TreeEntry, TreeEntryPermission};
+///
+/// # let dir = std::env::temp_dir().join(format!("tree-walk-{}", std::process::id()));
+/// let repo = Repository::init(&dir)?;
+/// let blob = *repo.write_blob(b"hello\n")?.as_bytes();
+/// let entry = |name: &str, permission, hash| TreeEntry {
+///     permission,
+///     name: name.to_string(),
+///     hash,
+/// };
+/// let docs = repo.write_tree(&Tree {
+///     entries: vec![entry("guide.md", TreeEntryPermission::RegularFile, blob)],
+/// })?;
+/// let src = repo.write_tree(&Tree {
+///     entries: vec![entry("main.rs", TreeEntryPermission::RegularFile, blob)],
+/// })?;
+/// let root = repo.write_tree(&Tree {
+///     entries: vec![
+///         entry("docs", TreeEntryPermission::Directory, *docs.as_bytes()),
+///         entry("src", TreeEntryPermission::Directory, *src.as_bytes()),
+///     ],
+/// })?;
+///
+/// let paths: Vec<String> = repo
+///     .tree_walk(root)
+///     .with_filter(|path, _| path != "docs")
+///     .map(|entry| entry.map(|(path, _)| path))
+///     .collect::<anyhow::Result<_>>()?;
+/// assert_eq!(paths, ["src", "src/main.rs"]);
+/// # std::fs::remove_dir_all(&dir)?;
+/// # anyhow::Ok(())
+/// ```
+pub struct TreeWalk<'a> {
+    /// The repository the trees are read from.
+    context: Arc<Context>,
+    /// The trees being walked, innermost last: the path of each, with a slash, and the
+    /// entries still to come.
+    pending: Vec<(String, vec::IntoIter<TreeEntry>)>,
+    /// The tree to read before the next entry: the root, then each subtree just yielded.
+    enter: Option<(String, Sha)>,
+    filter: Option<Filter<'a>>,
+}
+
+impl<'a> TreeWalk<'a> {
+    /// Walks the tree `root` of the repository the thread works in.
+    pub(crate) fn new(root: Oid) -> Self {
+        Self {
+            context: context::current(),
+            pending: vec![],
+            enter: Some((String::new(), root.0)),
+            filter: None,
+        }
+    }
+
+    /// Only yields the entries `filter` accepts, given the path and the entry; a subtree it
+    /// rejects is not walked into either.
+    pub fn with_filter(mut self, filter: impl FnMut(&str, &TreeEntry) -> bool + 'a) -> Self {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Does not walk into the subtree just yielded, for a caller that wants to see a subtree
+    /// but not what is in it.
+    pub fn skip_subtree(&mut self) {
+        if !self.pending.is_empty() {
+            self.enter = None;
+        }
+    }
+
+    /// Reads the tree the walk is to enter, if there is one.
+    fn enter(&mut self) -> anyhow::Result<()> {
+        let Some((base, sha)) = self.enter.take() else {
+            return Ok(());
+        };
+        let tree = context::enter(self.context.clone(), || GitObject::read_tree(&sha))?;
+        self.pending.push((base, tree.entries.into_iter()));
+        Ok(())
+    }
+}
+
+impl Iterator for TreeWalk<'_> {
+    type Item = anyhow::Result<(String, TreeEntry)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(err) = self.enter() {
+            return Some(Err(err));
+        }
+        loop {
+            let (base, entries) = self.pending.last_mut()?;
+            let Some(entry) = entries.next() else {
+                self.pending.pop();
+                continue;
+            };
+            let path = format!("{}{}", base, entry.name);
+            if let Some(filter) = &mut self.filter
+                && !filter(&path, &entry)
+            {
+                continue;
+            }
+            if entry.permission == TreeEntryPermission::Directory {
+                self.enter = Some((format!("{}/", path), entry.hash));
+            }
+            return Some(Ok((path, entry)));
+        }
+    }
+}
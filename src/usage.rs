@@ -0,0 +1,108 @@
+//! Usage statistics for programs built on the library that want to watch how the commands
+//! they run perform, for instance to notice a slowdown after upgrading. Nothing is collected
+//! unless an application installs a [`UsageRecorder`], and nothing leaves the process: the
+//! recorder is told about each command and does with it what the application wants.
+//!
+//! Like the progress callbacks, the recorder is installed on a repository, with
+//! [`Repository::set_usage_recorder`]. It hears about every command run on it through
+//! [`Repository::run_command`], successfully or not.
+//!
+//! ```
+//! use codecrafters_git::{CommandUsage, Repository, UsageRecorder};
+//! use std::sync::{Arc, Mutex};
+//!
+//! #[derive(Clone, Default)]
+//! struct Usage(Arc<Mutex<Vec<CommandUsage>>>);
+//!
+//! impl UsageRecorder for Usage {
+//!     fn command_finished(&self, usage: &CommandUsage) {
+//!         self.0.lock().unwrap().push(usage.clone());
+//!     }
+//! }
+//!
+//! # let dir = std::env::temp_dir().join(format!("usage-{}", std::process::id()));
+//! let repo = Repository::init(&dir)?;
+//! let usage = Usage::default();
+//! repo.set_usage_recorder(usage.clone());
+//! let blob = repo.write_blob(b"hello\n")?;
+//! repo.run_command("update-ref", &["refs/tags/hello".to_string(), blob.to_string()])?;
+//! let usage = usage.0.lock().unwrap();
+//! assert_eq!(usage[0].command, "update-ref");
+//! assert!(usage[0].succeeded);
+//! # std::fs::remove_dir_all(&dir)?;
+//! # anyhow::Ok(())
+//! ```
+//!
+//! [`Repository::set_usage_recorder`]: crate::Repository::set_usage_recorder
+//! [`Repository::run_command`]: crate::Repository::run_command
+
+use crate::context;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// What one command did and how long it took.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommandUsage {
+    /// The command's name, e.g. `fetch`.
+    pub command: String,
+    /// The time from the command starting to it returning.
+    pub duration: Duration,
+    /// Whether it returned `Ok`.
+    pub succeeded: bool,
+    /// Objects read from the object storage, loose or packed, counting an object each time
+    /// it is read.
+    pub objects_read: u64,
+    /// Objects added to the object storage: new loose objects and the objects of stored
+    /// packs.
+    pub objects_written: u64,
+}
+
+/// Receives usage statistics; every method does nothing unless it is implemented.
+pub trait UsageRecorder: Send + Sync {
+    /// Called when a command returns. Commands running at the same time on other threads
+    /// share the object counts, so each sees the objects of the others too.
+    fn command_finished(&self, _usage: &CommandUsage) {}
+}
+
+static OBJECTS_READ: AtomicU64 = AtomicU64::new(0);
+static OBJECTS_WRITTEN: AtomicU64 = AtomicU64::new(0);
+
+pub(crate) fn set_recorder(recorder: Arc<dyn UsageRecorder>) {
+    *context::current().usage_recorder.write().unwrap() = Some(recorder);
+}
+
+pub(crate) fn object_read() {
+    OBJECTS_READ.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn objects_written(count: u64) {
+    OBJECTS_WRITTEN.fetch_add(count, Ordering::Relaxed);
+}
+
+/// The objects read from and written to the object storage so far.
+pub(crate) fn object_counts() -> (u64, u64) {
+    (
+        OBJECTS_READ.load(Ordering::Relaxed),
+        OBJECTS_WRITTEN.load(Ordering::Relaxed),
+    )
+}
+
+/// Runs the command `name` through `run`, telling the installed recorder about it.
+pub(crate) fn record(name: &str, run: impl FnOnce() -> anyhow::Result<()>) -> anyhow::Result<()> {
+    let Some(recorder) = context::current().usage_recorder.read().unwrap().clone() else {
+        return run();
+    };
+    let started = Instant::now();
+    let read = OBJECTS_READ.load(Ordering::Relaxed);
+    let written = OBJECTS_WRITTEN.load(Ordering::Relaxed);
+    let result = run();
+    recorder.command_finished(&CommandUsage {
+        command: name.to_string(),
+        duration: started.elapsed(),
+        succeeded: result.is_ok(),
+        objects_read: OBJECTS_READ.load(Ordering::Relaxed) - read,
+        objects_written: OBJECTS_WRITTEN.load(Ordering::Relaxed) - written,
+    });
+    result
+}
@@ -0,0 +1,59 @@
+use crate::ignore::IgnoreRules;
+use crate::pathspec::PathspecSet;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+
+/// Whether a directory entry is skipped when scanning the working tree: the repository itself.
+pub fn is_excluded(name: &OsStr) -> bool {
+    name == ".git"
+}
+
+/// Collects the files below `dir` that `pathspecs` selects, in sorted order, as paths relative
+/// to the top of the working tree. `prefix` is the path of `dir` itself, ending in `/` unless it
+/// is the top. With `ignore` (the rules of the parent directory), ignored files and
+/// directories are left out.
+pub fn collect_files(
+    dir: &Path,
+    prefix: &str,
+    pathspecs: &PathspecSet,
+    ignore: Option<&IgnoreRules>,
+    files: &mut Vec<String>,
+) -> anyhow::Result<()> {
+    let ignore = ignore.map(|rules| rules.enter(dir, prefix)).transpose()?;
+    for entry in sorted_entries(dir)? {
+        let path = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        let is_dir = entry.file_type()?.is_dir();
+        if ignore
+            .as_ref()
+            .is_some_and(|rules| rules.is_ignored(&path, is_dir))
+        {
+            continue;
+        }
+        if is_dir {
+            if pathspecs.could_match_within(&path) {
+                let subdir_prefix = format!("{}/", path);
+                collect_files(
+                    &entry.path(),
+                    &subdir_prefix,
+                    pathspecs,
+                    ignore.as_ref(),
+                    files,
+                )?;
+            }
+        } else if pathspecs.matches(&path, false) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// The entries of `dir` sorted by name, without the excluded ones.
+pub fn sorted_entries(dir: &Path) -> anyhow::Result<Vec<fs::DirEntry>> {
+    let mut entries: Vec<fs::DirEntry> = fs::read_dir(dir)?
+        .flatten()
+        .filter(|entry| !is_excluded(&entry.file_name()))
+        .collect();
+    entries.sort_by_key(|entry| entry.file_name());
+    Ok(entries)
+}
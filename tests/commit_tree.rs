@@ -0,0 +1,22 @@
+//! `commit-tree` records the author and committer as `commit` does.
+
+mod common;
+
+use common::TestRepo;
+
+#[test]
+fn commit_tree_uses_the_configured_identity() {
+    let repo = TestRepo::init("commit-tree");
+    repo.write("file", "content\n");
+    repo.git(&["add", "file"]);
+    let tree = repo.git(&["write-tree"]);
+    let commit = repo.git(&["commit-tree", tree.trim(), "-m", "message"]);
+
+    let content = repo.git(&["cat-file", "-p", commit.trim()]);
+    let lines: Vec<&str> = content.lines().collect();
+    assert_eq!(lines[0], format!("tree {}", tree.trim()));
+    assert!(lines[1].starts_with("author A U Thor <author@example.com> "));
+    assert!(lines[2].starts_with("committer C O Mitter <committer@example.com> "));
+    assert!(!lines[1].ends_with(" 0 +0000"), "{}", lines[1]);
+    assert_eq!(&lines[3..], ["", "message"]);
+}
@@ -0,0 +1,78 @@
+//! Runs the `codecrafters-git` binary in a scratch repository for the integration tests.
+
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// A repository in a fresh temporary directory, removed again when dropped. Commands run
+/// with a fixed identity and without the user's or the system's config or editor.
+pub struct TestRepo {
+    pub dir: PathBuf,
+}
+
+impl TestRepo {
+    pub fn init(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let repo = Self { dir };
+        repo.git(&["init"]);
+        repo
+    }
+
+    /// Runs the command and returns its stdout, failing the test if it fails.
+    pub fn git(&self, args: &[&str]) -> String {
+        let output = self.run(args);
+        assert!(
+            output.status.success(),
+            "{:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    pub fn run(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_codecrafters-git"))
+            .args(args)
+            .current_dir(&self.dir)
+            .env("HOME", &self.dir)
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env_remove("GIT_EDITOR")
+            .env("GIT_AUTHOR_NAME", "A U Thor")
+            .env("GIT_AUTHOR_EMAIL", "author@example.com")
+            .env("GIT_COMMITTER_NAME", "C O Mitter")
+            .env("GIT_COMMITTER_EMAIL", "committer@example.com")
+            .output()
+            .unwrap()
+    }
+
+    pub fn path(&self, path: &str) -> PathBuf {
+        self.dir.join(path)
+    }
+
+    pub fn write(&self, path: &str, content: &str) {
+        fs::write(self.path(path), content).unwrap();
+    }
+
+    pub fn read(&self, path: &str) -> String {
+        fs::read_to_string(self.path(path)).unwrap()
+    }
+
+    /// The content of `path` as staged in the index.
+    pub fn staged(&self, path: &str) -> String {
+        self.git(&["cat-file", "-p", &format!(":{}", path)])
+    }
+
+    pub fn exists(&self, path: &str) -> bool {
+        Path::new(&self.path(path)).exists()
+    }
+}
+
+impl Drop for TestRepo {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
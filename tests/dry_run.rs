@@ -0,0 +1,23 @@
+//! A `Repository` in dry-run mode runs only the commands that have a dry run.
+
+use codecrafters_git::Repository;
+use std::fs;
+
+#[test]
+fn dry_run_refuses_commands_without_one() {
+    let base = std::env::temp_dir().join(format!("dry-run-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&base);
+    let repo = Repository::init(&base).unwrap();
+    fs::write(base.join("file"), "content\n").unwrap();
+    repo.run_command("add", &["file".to_string()]).unwrap();
+
+    repo.set_dry_run(true);
+    let args = ["-m".to_string(), "message".to_string()];
+    let error = repo.run_command("commit", &args).unwrap_err();
+    assert_eq!(error.to_string(), "'commit' does not support --dry-run");
+    assert!(repo.head().unwrap().is_none());
+    assert!(repo.run_command("gc", &[]).is_ok());
+
+    repo.set_dry_run(false);
+    fs::remove_dir_all(&base).unwrap();
+}
@@ -0,0 +1,26 @@
+//! Commands that change the index take `index.lock` before reading it and keep other
+//! processes' locks intact.
+
+mod common;
+
+use common::TestRepo;
+
+#[test]
+fn add_refuses_an_existing_index_lock() {
+    let repo = TestRepo::init("index-lock");
+    repo.write("file", "content\n");
+    repo.write(".git/index.lock", "another process\n");
+
+    let output = repo.run(&["add", "file"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Unable to create '"), "{}", stderr);
+    assert!(stderr.contains("index.lock': File exists."), "{}", stderr);
+    assert_eq!(repo.read(".git/index.lock"), "another process\n");
+    assert!(!repo.exists(".git/index"));
+
+    std::fs::remove_file(repo.path(".git/index.lock")).unwrap();
+    repo.git(&["add", "file"]);
+    assert_eq!(repo.staged("file"), "content\n");
+    assert!(!repo.exists(".git/index.lock"));
+}
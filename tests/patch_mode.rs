@@ -0,0 +1,123 @@
+//! `add -p` and `checkout -p` answered from a `--patch-script`, checked by what ends up in
+//! the index and the working tree.
+
+mod common;
+
+use common::TestRepo;
+
+/// The lines `1` to `20`, with the numbered lines replaced by the words given.
+fn numbers(replaced: &[(usize, &str)]) -> String {
+    (1..=20)
+        .map(|number| {
+            let line = match replaced.iter().find(|(at, _)| *at == number) {
+                Some((_, word)) => word.to_string(),
+                None => number.to_string(),
+            };
+            line + "\n"
+        })
+        .collect()
+}
+
+/// A repository with `file` committed as [`numbers`], then changed on lines 2 and 4, which
+/// make one hunk that can be split, and on line 18, which makes a second hunk.
+fn changed_repo(name: &str) -> TestRepo {
+    let repo = TestRepo::init(name);
+    repo.write("file", &numbers(&[]));
+    repo.git(&["add", "file"]);
+    repo.git(&["commit", "-m", "initial"]);
+    repo.write("file", &changed());
+    repo
+}
+
+fn changed() -> String {
+    numbers(&[(2, "two"), (4, "four"), (18, "eighteen")])
+}
+
+/// Runs `args` with `--patch-script` answering with `answers`, returning stdout.
+fn run_script(repo: &TestRepo, args: &[&str], answers: &[&str]) -> String {
+    let script = repo.path(".git/answers");
+    std::fs::write(&script, answers.join("\n") + "\n").unwrap();
+    let mut args = args.to_vec();
+    args.extend(["--patch-script", script.to_str().unwrap(), "file"]);
+    repo.git(&args)
+}
+
+#[test]
+fn add_patch_stages_the_hunks_taken() {
+    let repo = changed_repo("patch-add-yes-no");
+    let out = run_script(&repo, &["add", "-p"], &["n", "y"]);
+    assert!(
+        out.contains("(1/2) Stage this hunk [y,n,q,a,d,s,e,?]? "),
+        "{}",
+        out
+    );
+    assert!(
+        out.contains("(2/2) Stage this hunk [y,n,q,a,d,e,?]? "),
+        "{}",
+        out
+    );
+    assert_eq!(repo.staged("file"), numbers(&[(18, "eighteen")]));
+    assert_eq!(repo.read("file"), changed());
+}
+
+#[test]
+fn add_patch_splits_a_hunk() {
+    let repo = changed_repo("patch-add-split");
+    let out = run_script(&repo, &["add", "-p"], &["s", "y", "n", "y"]);
+    assert!(out.contains("Split into 2 hunks."), "{}", out);
+    assert!(out.contains("(2/3) Stage this hunk"), "{}", out);
+    assert_eq!(
+        repo.staged("file"),
+        numbers(&[(2, "two"), (18, "eighteen")])
+    );
+    assert_eq!(repo.read("file"), changed());
+}
+
+#[test]
+fn add_patch_quits_leaving_the_rest_unstaged() {
+    let repo = changed_repo("patch-add-quit");
+    repo.write("other", "other\n");
+    repo.git(&["add", "other"]);
+    repo.write("other", "changed\n");
+    let script = repo.path(".git/answers");
+    std::fs::write(&script, "y\nq\ny\n").unwrap();
+    repo.git(&["add", "-p", "--patch-script", script.to_str().unwrap()]);
+    assert_eq!(repo.staged("file"), numbers(&[(2, "two"), (4, "four")]));
+    assert_eq!(repo.staged("other"), "other\n");
+    assert_eq!(repo.read("other"), "changed\n");
+}
+
+#[test]
+fn add_patch_stages_an_edited_hunk() {
+    let repo = changed_repo("patch-add-edit");
+    repo.git(&["config", "core.editor", "sed -i s/^+eighteen$/+EIGHTEEN/"]);
+    run_script(&repo, &["add", "-p"], &["n", "e"]);
+    assert_eq!(repo.staged("file"), numbers(&[(18, "EIGHTEEN")]));
+    assert_eq!(repo.read("file"), changed());
+    assert!(!repo.exists(".git/addp-hunk-edit.diff"));
+}
+
+#[test]
+fn add_patch_without_answers_stages_nothing() {
+    let repo = changed_repo("patch-add-none");
+    run_script(&repo, &["add", "-p"], &[]);
+    assert_eq!(repo.staged("file"), numbers(&[]));
+    assert!(!repo.exists(".git/index.lock"));
+}
+
+#[test]
+fn checkout_patch_discards_the_hunks_taken() {
+    let repo = changed_repo("patch-checkout");
+    let out = run_script(&repo, &["checkout", "-p"], &["y", "n"]);
+    assert!(out.contains("Discard this hunk from worktree"), "{}", out);
+    assert_eq!(repo.read("file"), numbers(&[(18, "eighteen")]));
+    assert_eq!(repo.staged("file"), numbers(&[]));
+}
+
+#[test]
+fn checkout_patch_splits_and_quits() {
+    let repo = changed_repo("patch-checkout-split");
+    run_script(&repo, &["checkout", "-p"], &["s", "n", "y", "q"]);
+    assert_eq!(repo.read("file"), numbers(&[(2, "two"), (18, "eighteen")]));
+    assert_eq!(repo.staged("file"), numbers(&[]));
+}
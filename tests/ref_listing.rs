@@ -0,0 +1,56 @@
+//! `branch`, `tag` and `show-ref` listing refs with `--glob` and `--exclude`.
+
+mod common;
+
+use common::TestRepo;
+
+#[test]
+fn listing_commands_take_glob_and_exclude() {
+    let repo = TestRepo::init("ref-listing");
+    repo.write("file", "content\n");
+    repo.git(&["add", "file"]);
+    repo.git(&["commit", "-m", "initial"]);
+    for branch in ["feature/a", "feature/b", "fix"] {
+        repo.git(&["branch", branch]);
+    }
+    for tag in ["v1.0", "v1.1", "v2.0", "rc/1"] {
+        repo.git(&["tag", tag]);
+    }
+
+    assert_eq!(
+        repo.git(&["branch", "--glob=feature", "--exclude=*/b"]),
+        "  feature/a\n"
+    );
+    assert_eq!(
+        repo.git(&["branch", "--glob=f*", "--glob=feature/*", "--exclude=fix"]),
+        "  feature/a\n  feature/b\n"
+    );
+    assert_eq!(repo.git(&["tag", "-l", "v1*"]), "v1.0\nv1.1\n");
+    assert_eq!(repo.git(&["tag", "--glob=rc"]), "rc/1\n");
+    assert_eq!(repo.git(&["tag", "--exclude=v1.*"]), "rc/1\nv2.0\n");
+    assert_eq!(repo.git(&["tag", "-l", "--exclude=v1.*", "v*"]), "v2.0\n");
+
+    let names = |args: &[&str]| -> Vec<String> {
+        repo.git(args)
+            .lines()
+            .map(|line| line.split_once(' ').unwrap().1.to_string())
+            .collect()
+    };
+    assert_eq!(
+        names(&[
+            "show-ref",
+            "--glob=heads/feature",
+            "--exclude=refs/heads/feature/a"
+        ]),
+        ["refs/heads/feature/b"]
+    );
+    assert_eq!(
+        names(&["show-ref", "--tags", "--glob=refs/*/v1.?"]),
+        ["refs/tags/v1.0", "refs/tags/v1.1"]
+    );
+    assert_eq!(
+        names(&["show-ref", "fix", "a"]),
+        ["refs/heads/feature/a", "refs/heads/fix"]
+    );
+    assert!(!repo.run(&["show-ref", "--exclude=*"]).status.success());
+}
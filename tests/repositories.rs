@@ -0,0 +1,60 @@
+//! Several repositories open in one process, each read and written through its own
+//! `Repository`.
+
+use codecrafters_git::{Repository, Tree, TreeEntry, TreeEntryPermission};
+use std::fs;
+use std::thread;
+
+#[test]
+fn repositories_are_independent() {
+    let base = std::env::temp_dir().join(format!("repositories-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&base);
+    let first = Repository::init(base.join("first")).unwrap();
+    let second = Repository::init(base.join("second")).unwrap();
+    assert_eq!(first.git_dir(), base.join("first").join(".git"));
+    assert_eq!(second.work_tree(), Some(base.join("second").as_path()));
+
+    let blob = first.write_blob(b"only in the first\n").unwrap();
+    assert_eq!(
+        first.find_blob(blob).unwrap().content(),
+        b"only in the first\n"
+    );
+    assert!(second.find_blob(blob).is_err());
+
+    let tree = second
+        .write_tree(&Tree {
+            entries: vec![TreeEntry {
+                permission: TreeEntryPermission::RegularFile,
+                name: "file".to_string(),
+                hash: *second.write_blob(b"second\n").unwrap().as_bytes(),
+            }],
+        })
+        .unwrap();
+    let paths: Vec<String> = second
+        .tree_walk(tree)
+        .map(|entry| entry.unwrap().0)
+        .collect();
+    assert_eq!(paths, ["file"]);
+    assert!(first.tree_walk(tree).next().unwrap().is_err());
+
+    // Commands run on the repository they are given, from any thread.
+    fs::write(base.join("second").join("file"), "second\n").unwrap();
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            let args = ["file".to_string()];
+            second.run_command("add", &args).unwrap();
+        });
+    });
+    assert!(first.run_command("write-tree", &[]).is_ok());
+    assert!(!base.join("first").join(".git").join("index").exists());
+    assert!(base.join("second").join(".git").join("index").exists());
+
+    // Another `Repository` for the same directory sees the same objects.
+    let reopened = Repository::open(base.join("first")).unwrap();
+    assert_eq!(
+        reopened.find_blob(blob).unwrap().content(),
+        b"only in the first\n"
+    );
+
+    fs::remove_dir_all(&base).unwrap();
+}
@@ -0,0 +1,39 @@
+//! A symlink checked out as a plain file, with `core.symlinks` false, stays a symlink.
+
+#![cfg(unix)]
+
+mod common;
+
+use common::TestRepo;
+use std::fs;
+use std::os::unix::fs::symlink;
+
+#[test]
+fn symlink_written_as_plain_file_round_trips() {
+    let repo = TestRepo::init("symlinks-round-trip");
+    repo.write("target", "content\n");
+    symlink("target", repo.path("link")).unwrap();
+    repo.git(&["add", "."]);
+    repo.git(&["commit", "-m", "link"]);
+
+    repo.git(&["config", "core.symlinks", "false"]);
+    fs::remove_file(repo.path("link")).unwrap();
+    repo.git(&["checkout", "--", "link"]);
+    assert!(fs::symlink_metadata(repo.path("link")).unwrap().is_file());
+    assert_eq!(repo.read("link"), "target");
+
+    assert_eq!(repo.git(&["status", "--porcelain"]), "");
+    assert_eq!(repo.git(&["diff", "--name-status"]), "");
+    repo.git(&["add", "link"]);
+    assert_eq!(repo.git(&["diff", "--cached", "--name-status"]), "");
+    assert!(repo.git(&["ls-tree", "HEAD"]).contains("120000 blob"));
+
+    // A changed target is still committed as a symlink.
+    repo.write("link", "elsewhere");
+    assert_eq!(repo.git(&["status", "--porcelain"]), " M link\n");
+    repo.git(&["add", "link"]);
+    repo.git(&["commit", "-m", "retarget"]);
+    let tree = repo.git(&["ls-tree", "HEAD", "link"]);
+    assert!(tree.starts_with("120000 blob"), "{}", tree);
+    assert_eq!(repo.git(&["cat-file", "-p", "HEAD:link"]), "elsewhere");
+}